@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches};
+
+use crate::cli::shared::validate;
+
+pub const INPUT: &str = "input";
+pub const REFERENCE: &str = "reference";
+pub const SAVETO: &str = "saveto";
+
+pub fn all<'a>() -> Vec<Arg<'a>> {
+    vec![
+        Arg::new(INPUT)
+            .short('i')
+            .long(INPUT)
+            .required(true)
+            .takes_value(true)
+            .multiple_values(true)
+            .validator(validate::path)
+            .long_help(
+                "REAT outputs to merge: ROI or site tables, or EI tables (--ei). \
+                All files must be of the same kind and have identical columns. \
+                Files ending with .gz are decompressed on the fly",
+            ),
+        Arg::new(REFERENCE).short('r').long(REFERENCE).takes_value(true).validator(validate::path).long_help(
+            "Indexed fasta file (or its .fai index) defining the contig order of the merged ROI/site table. \
+            Required to merge ROI/site tables, only the index is used",
+        ),
+        Arg::new(SAVETO)
+            .short('o')
+            .long(SAVETO)
+            .takes_value(true)
+            .validator(validate::writable)
+            .default_value("/dev/stdout")
+            .long_help(
+                "Path to the merged table. By default, the results are printed to stdout. \
+                Paths ending with .gz or .bgz are gzip- or bgzip-compressed on the fly",
+            ),
+    ]
+}
+
+pub struct MergeArgs {
+    pub inputs: Vec<PathBuf>,
+    pub reference: Option<PathBuf>,
+    pub saveto: PathBuf,
+}
+
+impl MergeArgs {
+    pub fn new(args: &ArgMatches) -> Self {
+        Self {
+            inputs: args.values_of(INPUT).unwrap().map(PathBuf::from).collect(),
+            reference: args.value_of(REFERENCE).map(PathBuf::from),
+            saveto: args.value_of(SAVETO).map(PathBuf::from).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use csv::StringRecord;
+use flate2::bufread::MultiGzDecoder;
+use itertools::Itertools;
+
+use crate::cli::merge::args::MergeArgs;
+use crate::core::hooks::stats::{EditingIndexRecord, ROIEditingIndex};
+use crate::core::io::utils::{self, FinishWrite};
+use crate::core::io::{fasta, table};
+use crate::error::Error;
+
+const WRITE_ERROR: &str = "Failed to write the merged table";
+
+// Kind of the REAT output, detected by its header
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Kind {
+    EditingIndex,
+    // Columns used to sort rows: contig, start/pos and optional end
+    Table { contig: usize, start: usize, end: Option<usize> },
+}
+
+#[derive(Clone)]
+struct Table {
+    path: PathBuf,
+    // Version from the schema line, if any
+    schema: Option<u32>,
+    header: StringRecord,
+    rows: Vec<StringRecord>,
+}
+
+impl Table {
+    // 1-based line of the row in the file, the schema line is not seen by the CSV reader
+    fn line(&self, row: &StringRecord) -> usize {
+        row.position().map_or(0, |x| x.line() as usize) + self.schema.is_some() as usize
+    }
+}
+
+fn schema<T: BufRead>(reader: &mut T) -> io::Result<Option<u32>> {
+    if !reader.fill_buf()?.starts_with(table::SCHEMA_PREFIX.as_bytes()) {
+        return Ok(None);
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match table::schema_version(&line) {
+        Some(version) => Ok(Some(version)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid schema line \"{}\"", line.trim_end()))),
+    }
+}
+
+fn _parse<T: BufRead>(reader: T) -> csv::Result<(StringRecord, Vec<StringRecord>)> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let header = reader.headers()?.clone();
+    let rows = reader.records().collect::<csv::Result<Vec<_>>>()?;
+    Ok((header, rows))
+}
+
+fn _read<T: BufRead>(mut reader: T) -> (Option<u32>, csv::Result<(StringRecord, Vec<StringRecord>)>) {
+    match schema(&mut reader) {
+        Ok(schema) => (schema, _parse(reader)),
+        Err(err) => (None, Err(err.into())),
+    }
+}
+
+fn read(path: &Path) -> Result<Table, Error> {
+    let (schema, parsed) = utils::read_compressed!(path, _read)?;
+    let (header, rows) = parsed.map_err(|x| {
+        let line = x.position().map_or(1, |x| x.line() as usize + schema.is_some() as usize);
+        Error::parse(path, line, x.to_string())
+    })?;
+    if header.is_empty() {
+        return Err(Error::Input(format!("{} is empty, nothing to merge", path.display())));
+    }
+    Ok(Table { path: path.to_owned(), schema, header, rows })
+}
+
+fn kind(table: &Table) -> Result<Kind, Error> {
+    let header = &table.header;
+    if header.iter().join(",") == ROIEditingIndex::header() {
+        return Ok(Kind::EditingIndex);
+    }
+    if header.iter().any(|x| x == "ROI-file") {
+        return Err(Error::Input(format!(
+            "{} looks like an EI table without raw counts (created by an older REAT version?), it can't be merged",
+            table.path.display()
+        )));
+    }
+
+    let column = |name: &str| header.iter().position(|x| x == name);
+    match (column("contig"), column("start").or_else(|| column("pos"))) {
+        (Some(contig), Some(start)) => Ok(Kind::Table { contig, start, end: column("end") }),
+        _ => Err(Error::Input(format!(
+            "{} is neither an EI table nor a ROI/site table with contig & start/pos columns",
+            table.path.display()
+        ))),
+    }
+}
+
+// All inputs must have exactly the same columns, otherwise they were created by different modes or options
+fn same_columns(first: &Table, other: &Table) -> Result<(), Error> {
+    if let (Some(a), Some(b)) = (first.schema, other.schema) {
+        if a != b {
+            return Err(Error::Input(format!(
+                "{} and {} were created by different REAT versions (schema {} and {}), they can't be merged",
+                first.path.display(),
+                other.path.display(),
+                a,
+                b
+            )));
+        }
+    }
+    if first.header == other.header {
+        return Ok(());
+    }
+    let missing = |a: &StringRecord, b: &StringRecord| a.iter().filter(|x| !b.iter().any(|y| y == *x)).join(",");
+    let (absent, extra) = (missing(&first.header, &other.header), missing(&other.header, &first.header));
+    let details = if absent.is_empty() && extra.is_empty() {
+        "columns are in a different order".to_owned()
+    } else {
+        format!("missing columns: [{}], extra columns: [{}]", absent, extra)
+    };
+    Err(Error::Input(format!(
+        "{} and {} have different columns ({}), only outputs of the same kind & options can be merged",
+        first.path.display(),
+        other.path.display(),
+        details
+    )))
+}
+
+// Contigs in the order of the FASTA index, either the index itself or the indexed FASTA can be provided
+fn order(reference: &Path) -> Result<HashMap<String, usize>, Error> {
+    let fasta = match reference.extension() {
+        Some(ext) if ext == OsStr::new("fai") => reference.with_extension(""),
+        _ => reference.to_owned(),
+    };
+    Ok(fasta::contigs(&fasta)?.into_iter().enumerate().map(|(ind, x)| (x, ind)).collect())
+}
+
+// Rows are concatenated and sorted by the contig order & coordinates, the order of identical loci is preserved
+fn merge_tables(
+    tables: Vec<Table>,
+    kind: Kind,
+    order: &HashMap<String, usize>,
+) -> Result<(StringRecord, Vec<StringRecord>), Error> {
+    let (contig, start, end) = match kind {
+        Kind::Table { contig, start, end } => (contig, start, end),
+        Kind::EditingIndex => unreachable!(),
+    };
+
+    let mut keyed = Vec::with_capacity(tables.iter().map(|x| x.rows.len()).sum());
+    let header = tables[0].header.clone();
+    for mut table in tables {
+        for row in std::mem::take(&mut table.rows) {
+            let line = table.line(&row);
+            let rank = *order.get(&row[contig]).ok_or_else(|| {
+                Error::parse(&table.path, line, format!("contig {} is missing in the reference index", &row[contig]))
+            })?;
+            let coordinate = |column: usize| -> Result<u64, Error> {
+                row[column].parse().map_err(|_| {
+                    Error::parse(&table.path, line, format!("invalid {} value \"{}\"", &header[column], &row[column]))
+                })
+            };
+            let key = (rank, coordinate(start)?, end.map(coordinate).transpose()?);
+            keyed.push((key, row));
+        }
+    }
+    keyed.sort_by_key(|x| x.0);
+    Ok((header, keyed.into_iter().map(|x| x.1).collect()))
+}
+
+// Rows of the same experiment, ROIs & contigs subset are merged by summing their raw counts.
+// Merged rows are reported in the order of their first occurrence
+fn merge_editing_indexes(tables: Vec<Table>) -> Result<Vec<EditingIndexRecord>, Error> {
+    let mut merged: Vec<EditingIndexRecord> = Vec::new();
+    for table in tables {
+        for row in &table.rows {
+            let record = EditingIndexRecord::parse(&table.header, row)
+                .map_err(|x| Error::parse(&table.path, table.line(row), x))?;
+            match merged.iter_mut().find(|x| x.key() == record.key()) {
+                Some(existing) => existing.merge(record),
+                None => merged.push(record),
+            }
+        }
+    }
+    Ok(merged)
+}
+
+fn merge<W: Write>(tables: Vec<Table>, reference: Option<&Path>, writer: &mut csv::Writer<W>) -> Result<(), Error> {
+    let kind = kind(&tables[0])?;
+    for table in &tables[1..] {
+        same_columns(&tables[0], table)?;
+    }
+
+    let failed = |x: csv::Error| Error::output(WRITE_ERROR, x);
+    match kind {
+        Kind::EditingIndex => {
+            let merged = merge_editing_indexes(tables)?;
+            for record in &merged {
+                record.to_csv(writer).map_err(failed)?;
+            }
+            Ok(())
+        }
+        Kind::Table { .. } => {
+            let reference = reference.ok_or_else(|| {
+                Error::Input("ROI/site tables are sorted by the contig order => --reference is required".into())
+            })?;
+            // Merged tables keep the schema line of the inputs
+            if let Some(version) = tables[0].schema {
+                writeln!(writer.get_mut(), "{}{}", table::SCHEMA_PREFIX, version).map_err(|x| failed(x.into()))?;
+            }
+            let (header, rows) = merge_tables(tables, kind, &order(reference)?)?;
+            writer.write_record(&header).map_err(failed)?;
+            for row in &rows {
+                writer.write_record(row).map_err(failed)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let args = MergeArgs::new(args);
+    let tables = args.inputs.iter().map(|x| read(x)).collect::<Result<Vec<_>, _>>()?;
+
+    let mut writer = csv::Writer::from_writer(utils::write_compressed(&args.saveto, false)?);
+    merge(tables, args.reference.as_deref(), &mut writer)?;
+    let failed = |x: csv::Error| Error::output(WRITE_ERROR, x);
+    writer.flush().map_err(|x| failed(x.into()))?;
+    writer.get_mut().finish().map_err(|x| failed(x.into()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn table(path: &str, content: &str) -> Table {
+        let (schema, parsed) = _read(content.as_bytes());
+        let (header, rows) = parsed.unwrap();
+        Table { path: path.into(), schema, header, rows }
+    }
+
+    fn merged(tables: Vec<Table>, reference: Option<&Path>) -> Result<String, Error> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        merge(tables, reference, &mut writer)?;
+        Ok(String::from_utf8(writer.into_inner().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn tables() {
+        let dir = TempDir::new().unwrap();
+        let fai = dir.path().join("genome.fa.fai");
+        fs::write(&fai, "chr2\t100\t6\t60\t61\nchr1\t200\t200\t60\t61\n").unwrap();
+
+        let first = table("first.csv", "contig,start,end,name\nchr1,10,20,a\nchr2,50,60,b\n");
+        let second = table("second.csv", "contig,start,end,name\nchr2,5,60,c\nchr1,10,15,d\nchr1,9,15,e\n");
+        let expected = "contig,start,end,name\nchr2,5,60,c\nchr2,50,60,b\nchr1,9,15,e\nchr1,10,15,d\nchr1,10,20,a\n";
+        // Either the index itself or the indexed FASTA
+        for reference in [fai.clone(), dir.path().join("genome.fa")] {
+            let tables = vec![first.clone(), second.clone()];
+            assert_eq!(merged(tables, Some(&reference)).unwrap(), expected);
+        }
+
+        // Sites are sorted by positions
+        let sites = vec![table("a.csv", "contig,pos,A\nchr1,3,1\n"), table("b.csv", "contig,pos,A\nchr2,7,2\n")];
+        assert_eq!(merged(sites, Some(&fai)).unwrap(), "contig,pos,A\nchr2,7,2\nchr1,3,1\n");
+
+        let unknown = vec![first, table("third.csv", "contig,start,end,name\nchrM,1,2,x\n")];
+        let err = merged(unknown, Some(&fai)).unwrap_err();
+        assert_eq!(err.to_string(), "third.csv:2: contig chrM is missing in the reference index");
+
+        let err = merged(vec![second], None).unwrap_err();
+        assert!(err.to_string().contains("--reference is required"));
+    }
+
+    #[test]
+    fn columns() {
+        let first = table("first.csv", "contig,start,end,name\nchr1,10,20,a\n");
+        let err = merged(vec![first.clone(), table("second.csv", "contig,start,end,A->G\n")], None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "first.csv and second.csv have different columns (missing columns: [name], extra columns: [A->G]), \
+            only outputs of the same kind & options can be merged"
+        );
+        let err = merged(vec![first, table("second.csv", "contig,start,name,end\n")], None).unwrap_err();
+        assert!(err.to_string().contains("columns are in a different order"));
+
+        let err = merged(vec![table("x.csv", "name,value\na,1\n")], None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "x.csv is neither an EI table nor a ROI/site table with contig & start/pos columns"
+        );
+        let old = "experiment,ROI-file,contigs,#unstranded,A->A\nexp,rois.bed,all,0,1.0\n";
+        assert!(merged(vec![table("ei.csv", old)], None).unwrap_err().to_string().contains("older REAT version"));
+    }
+
+    #[test]
+    fn schema() {
+        let dir = TempDir::new().unwrap();
+        let fai = dir.path().join("genome.fa.fai");
+        fs::write(&fai, "chr1\t200\t6\t60\t61\n").unwrap();
+
+        let first = table("first.csv", "# reat-schema: 3\ncontig,pos,A\nchr1,5,1\n");
+        let second = table("second.csv", "# reat-schema: 3\ncontig,pos,A\nchr1,3,1\n");
+        assert_eq!(first.schema, Some(3));
+        let expected = "# reat-schema: 3\ncontig,pos,A\nchr1,3,1\nchr1,5,1\n";
+        assert_eq!(merged(vec![first.clone(), second], Some(&fai)).unwrap(), expected);
+
+        // Line numbers account for the schema line
+        let unknown = table("second.csv", "# reat-schema: 3\ncontig,pos,A\nchr1,3,1\nchr2,1,0\n");
+        let err = merged(vec![first.clone(), unknown], Some(&fai)).unwrap_err();
+        assert_eq!(err.to_string(), "second.csv:4: contig chr2 is missing in the reference index");
+
+        let other = table("other.csv", "# reat-schema: 4\ncontig,pos,A\nchr1,3,1\n");
+        let err = merged(vec![first, other], Some(&fai)).unwrap_err();
+        assert!(err.to_string().contains("different REAT versions (schema 3 and 4)"), "{}", err);
+
+        let (_, parsed) = _read("# reat-schema: x\ncontig,pos\n".as_bytes());
+        assert!(parsed.unwrap_err().to_string().contains("invalid schema line \"# reat-schema: x\""));
+    }
+
+    #[test]
+    fn editing_indexes() {
+        let header = ROIEditingIndex::header();
+        let row = |name: &str, ag: f64| {
+            let mut record = EditingIndexRecord {
+                experiment: name.into(),
+                roifiles: "rois.bed".into(),
+                contigs: "all".into(),
+                unstranded_roi: 1,
+                covered_roi: 2,
+                counts: Some(Default::default()),
+            };
+            let counts = record.counts.as_mut().unwrap();
+            counts.A.A = 10f64 - ag;
+            counts.A.G = ag;
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            record.to_csv(&mut writer).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        let first = table("first.csv", &format!("{}\n{}{}", header, row("exp1", 1f64), row("exp2", 2f64)));
+        let second = table("second.csv", &format!("{}\n{}", header, row("exp1", 3f64)));
+
+        let written = merged(vec![first, second], None).unwrap();
+        let mut reader = csv::Reader::from_reader(written.as_bytes());
+        let columns = reader.headers().unwrap().clone();
+        assert_eq!(columns.iter().join(","), header);
+        let records: Vec<EditingIndexRecord> =
+            reader.records().map(|x| EditingIndexRecord::parse(&columns, &x.unwrap()).unwrap()).collect();
+
+        assert_eq!(records.iter().map(|x| x.experiment.as_str()).collect_vec(), ["exp1", "exp2"]);
+        assert_eq!((records[0].unstranded_roi, records[0].covered_roi), (2, 4));
+        let counts = records[0].counts.unwrap();
+        assert_eq!((counts.A.A, counts.A.G), (16f64, 4f64));
+        // Ratios are recomputed from the summed counts
+        let ag = header.split(',').position(|x| x == "A->G").unwrap();
+        assert_eq!(written.lines().nth(1).unwrap().split(',').nth(ag), Some("0.2"));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod merge;
 pub mod rois;
+pub mod schema;
+pub mod selftest;
 pub mod shared;
 pub mod sites;
@@ -1,16 +1,25 @@
-use std::fs::File;
+use std::collections::HashSet;
+use std::path::PathBuf;
 
 use clap::Arg;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
 
 use crate::cli::shared;
+use crate::cli::shared::args::core::{CHECKPOINT, SAVETO, SPLIT_BY_STRAND};
+use crate::cli::shared::format::OutputFormat;
 use crate::cli::shared::validate;
+use crate::core::dna::Multimap;
+use crate::core::dna::ReqNucleotide;
+use crate::core::hooks::filters::{ByExpression, Records};
+use crate::core::io::utils::OutputWriter;
 use crate::core::mismatches::prefilters;
 use crate::core::mismatches::prefilters::retain::RetainROIFromList;
-use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::roi::{ROIColumn, ROIMismatchesVec};
+use crate::core::rpileup::ncounter::cnt::MinROIOverlap;
 use crate::core::stranding::predict::REATStrandingEngine;
 use crate::core::workload::ROIWorkload;
+use crate::error::Error;
 
 use super::parse;
 
@@ -18,18 +27,108 @@ pub mod stats {
     use super::*;
 
     pub const EDITING_INDEX: &str = "ei";
+    pub const EDITING_INDEX_PER_SAMPLE: &str = "ei-per-sample";
+    pub const EDITING_INDEX_CONTIGS: &str = "ei-contigs";
+    pub const CONVERSION: &str = "stat";
+    pub const CONVERSION_OUTPUT: &str = "stat-out";
+    pub const SPECTRUM: &str = "spectrum";
+    pub const EI_BY_STRAND: &str = "ei-by-strand";
+    pub const PER_CONTIG: &str = "per-contig-stats";
+    pub const STATS_ONLY: &str = "stats-only";
 
     pub const SECTION_NAME: &str = "Stats";
 
     pub fn args<'a>() -> Vec<Arg<'a>> {
-        let args = vec![Arg::new(EDITING_INDEX)
-            .long(EDITING_INDEX)
-            .takes_value(true)
-            .validator(validate::writable)
-            .long_help(
-                "File for saving Editing Indexes (EI). \
-                If the file already exists, EI for the current experiments will be appended to it",
-            )];
+        let args = vec![
+            Arg::new(EDITING_INDEX)
+                .long(EDITING_INDEX)
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .validator(|x| validate::editing_index(x).map(|_| ()))
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "File for saving Editing Indexes (EI). \
+                    If the file already exists, EI for the current experiments will be appended to it. \
+                    Paths ending with .gz are gzip-compressed on the fly. \
+                    Use LABEL:REGEX:PATH to calculate EI only for ROIs with names matching the REGEX \
+                    (e.g. Alu:^Alu:alu-ei.csv). The option can be repeated to calculate several EIs in one run, \
+                    ROIs matching several patterns contribute to each of them",
+                ),
+            Arg::new(EDITING_INDEX_PER_SAMPLE)
+                .long(EDITING_INDEX_PER_SAMPLE)
+                .takes_value(false)
+                .requires(EDITING_INDEX)
+                .long_help(
+                    "Calculate EI for each input BAM file separately instead of pooling them together. \
+                    The EI file will contain one row per BAM file, file stems are used as experiment names. \
+                    The #unstranded and #covered columns count only ROIs covered by the given BAM file",
+                ),
+            Arg::new(EDITING_INDEX_CONTIGS)
+                .long(EDITING_INDEX_CONTIGS)
+                .takes_value(true)
+                .requires(EDITING_INDEX)
+                .long_help(
+                    "Calculate EI only for ROIs located on the given contigs (e.g. autosomes). \
+                    Either a comma-separated list of contigs or a file with one contig per line. \
+                    The subset is reported in the 'contigs' column of the EI file",
+                ),
+            Arg::new(CONVERSION)
+                .long(CONVERSION)
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .validator(|x| match x {
+                    SPECTRUM | EI_BY_STRAND => Ok(()),
+                    _ => validate::conversion(x).map(|_| ()),
+                })
+                .requires(CONVERSION_OUTPUT)
+                .long_help(
+                    "Nucleotide conversions to summarize across all ROIs, e.g. t2c for SLAM-seq. \
+                    For each conversion, both strand-aware (transcription strand, stranded ROIs only) \
+                    and strand-agnostic (conversion + its complement, all ROIs) rates are reported. \
+                    Use \"spectrum\" to report all 12 mismatch types normalized by the reference nucleotide \
+                    coverage (transcription strand for stranded ROIs) and \"ei-by-strand\" to report the pooled \
+                    A->G / T->C editing separately for forward, reverse and unstranded ROIs",
+                ),
+            Arg::new(CONVERSION_OUTPUT)
+                .long(CONVERSION_OUTPUT)
+                .takes_value(true)
+                .validator(validate::writable)
+                .requires(CONVERSION)
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "File for saving conversion rates and the mismatch spectrum requested via --stat. \
+                    Each stat is written as a separate header + row pair. \
+                    If the file already exists, the results will be appended to it",
+                ),
+            Arg::new(PER_CONTIG)
+                .long(PER_CONTIG)
+                .takes_value(true)
+                .validator(validate::writable)
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "TSV file for saving editing indexes calculated for each contig separately, \
+                    e.g. to quickly spot misbehaving chrM/chrY. Columns are the same as in the EI file, \
+                    one row per contig plus the TOTAL row over all ROIs. \
+                    Contigs without covered ROIs are reported with empty values",
+                ),
+            Arg::new(STATS_ONLY)
+                .long(STATS_ONLY)
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    SAVETO,
+                    SPLIT_BY_STRAND,
+                    CHECKPOINT,
+                    output_filtering::BED,
+                    output_filtering::ROI_PROFILE,
+                    output_filtering::COLUMNS,
+                ])
+                .long_help(
+                    "Calculate only the requested stats (--ei, --stat, --per-contig-stats) without reporting ROIs. \
+                    ROIs are dropped right after the stats are collected and output filters are not applied, \
+                    which saves both time and memory. Stats are identical to the ones from a regular run",
+                ),
+        ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
 }
@@ -38,25 +137,130 @@ pub mod special {
     use super::*;
 
     pub const ROI: &str = "rois";
+    pub const TILE: &str = "tile";
+    pub const MASK: &str = "mask";
+    pub const GROUP_BY_NAME: &str = "group-rois-by-name";
+    pub const CLIP_ROIS: &str = "clip-rois";
+    pub const MIN_ROI_OVERLAP: &str = "min-roi-overlap";
+    pub const ROI_FLANK: &str = "roi-flank";
 
     pub const SECTION_NAME: &str = "Special information";
 
     pub fn args<'a>() -> Vec<Arg<'a>> {
-        let args = vec![Arg::new(ROI).long(ROI).required(true).takes_value(true).validator(validate::path).long_help(
-            "Path to a BED file with regions of interest(ROIS) \
-            with at least 4 first BED columns(chr, start, end, name)",
-        )];
+        let args = vec![
+            Arg::new(ROI)
+                .long(ROI)
+                .required_unless_present(TILE)
+                .conflicts_with(TILE)
+                .takes_value(true)
+                .validator(validate::path)
+                .long_help(
+                    "Path to a BED file with regions of interest(ROIS) \
+                    with at least 4 first BED columns(chr, start, end, name)",
+                ),
+            Arg::new(TILE).long(TILE).takes_value(true).validator(|x| validate::tile(x).map(|_| ())).long_help(
+                "Use fixed-size genomic windows as unstranded ROIs instead of a BED file, given as SIZE[:STEP] \
+                (e.g. 1000 or 1000:500 for sliding windows). Windows tile every reference contig present in the BAM \
+                files, the last window of each contig is truncated. ROIs are named as contig:start-end, \
+                include/exclude lists and --mask are applied as for BED ROIs",
+            ),
+            Arg::new(MASK).long(MASK).takes_value(true).validator(validate::path).long_help(
+                "Path to a BED file with subregions to mask inside each ROI (e.g. SNP-dense regions or repeats). \
+                Masked positions are not counted, but ROIs are reported with their original coordinates. \
+                ROIs that are completely masked have zero coverage and are reported only if --force'd",
+            ),
+            Arg::new(GROUP_BY_NAME).long(GROUP_BY_NAME).takes_value(false).conflicts_with(TILE).long_help(
+                "Report ROIs with the same name & strand as a single row, e.g. exons of a gene listed on separate \
+                BED lines. Parts are merged before the output filtering and stats: counts are summed, \
+                start/end span all parts. Same-named ROIs on different contigs are reported separately",
+            ),
+            Arg::new(CLIP_ROIS).long(CLIP_ROIS).takes_value(false).conflicts_with(TILE).long_help(
+                "Truncate ROIs extending past the end of their contigs (according to the BAM headers) instead of \
+                rejecting the BED file. ROIs starting past the contig end are dropped",
+            ),
+            Arg::new(MIN_ROI_OVERLAP)
+                .long(MIN_ROI_OVERLAP)
+                .takes_value(true)
+                .validator(|x| validate::roi_overlap(x).map(|_| ()))
+                .long_help(
+                    "Count a read for a ROI only if its aligned bases overlap the (masked) ROI by at least X bases \
+                    (integer, e.g. 20) or by at least X fraction of the read aligned length (e.g. 0.5, 1.0 requires \
+                    the whole read inside the ROI). Reads are ignored for ROIs they overlap less, \
+                    but still counted for other overlapping ROIs. By default, any overlap is enough",
+                ),
+            Arg::new(ROI_FLANK)
+                .long(ROI_FLANK)
+                .takes_value(true)
+                .validator(validate::numeric(0u64, u64::MAX))
+                .conflicts_with(MIN_ROI_OVERLAP)
+                .long_help(
+                    "Count N bases on both sides of each (masked) ROI and report their editing separately in the \
+                    flank_coverage, flank_A->G and flank_A->G-freq columns, e.g. to compare the core & flank editing. \
+                    Flanks are clipped at the contig bounds and may overlap other ROIs. \
+                    Reported coordinates and all other columns refer to the original ROI",
+                ),
+        ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
 }
 
+pub mod stranding {
+    use super::*;
+
+    pub const EDITING: &str = "stranding-editing-roi";
+    pub const TRUST_ROI_STRAND: &str = "trust-roi-strand";
+    pub const IGNORE_ROI_STRAND: &str = "ignore-roi-strand";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(EDITING).long(EDITING).takes_value(true).validator(validate::editing_thresholds).long_help(
+                "A->I editing thresholds for the ROI strand prediction as min mismatches:min freq, e.g. 20:0.05. \
+                ROIs accumulate mismatches over many positions and usually require higher thresholds than sites. \
+                Overrides --str-min-mismatches/--str-min-freq for A->I editing, \
+                but not the thresholds given in --stranding-algo.",
+            ),
+            Arg::new(TRUST_ROI_STRAND)
+                .long(TRUST_ROI_STRAND)
+                .takes_value(false)
+                .conflicts_with(IGNORE_ROI_STRAND)
+                .long_help(
+                    "Report ROIs with a known strand in the BED file (+/-) on that strand without the strand \
+                    prediction (default). Only relevant for unstranded libraries, \
+                    ROIs with the \".\" strand are still predicted.",
+                ),
+            Arg::new(IGNORE_ROI_STRAND).long(IGNORE_ROI_STRAND).takes_value(false).long_help(
+                "Ignore the BED strand column and predict the strand of all ROIs in unstranded libraries, \
+                i.e. the behavior of older versions.",
+            ),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(shared::args::stranding::SECTION_NAME))).collect()
+    }
+}
+
 pub mod output_filtering {
     use super::*;
 
     pub const MIN_MISMATCHES: &str = "out-min-mismatches";
     pub const MIN_FREQ: &str = "out-min-freq";
+    pub const MIN_FREQ_CI: &str = "out-min-freq-ci";
     pub const MIN_COVERAGE: &str = "out-min-cov";
+    pub const CI: &str = "ci";
+    pub const EDITING_ONLY: &str = "prefilter-editing-only";
     pub const FORCE_LIST: &str = "force";
+    pub const BED: &str = "bed";
+    pub const ROI_PROFILE: &str = "roi-profile";
+    pub const NO_BACKGROUND: &str = "no-background";
+    pub const NO_ALN_STATS: &str = "no-aln-stats";
+    pub const WITH_ALT_FRAGMENTS: &str = "with-alt-fragments";
+    pub const REPLICATE_SUPPORT: &str = "replicate-support";
+    pub const COLUMNS: &str = "columns";
+    pub const FORMAT: &str = "format";
+    pub const FILTER_EXPR: &str = "filter-expr";
+    pub const SOFT_FILTERS: &str = "soft-filters";
+    pub const NO_OUTPUT_FILTERS: &str = "no-output-filters";
+    pub const EMIT_ZERO_COVERAGE: &str = "emit-zero-coverage";
+    pub const ALSO_LOCI: &str = "also-loci";
+    pub const LOCI_WITHIN_ROIS: &str = "loci-within-rois";
 
     pub const SECTION_NAME: &str = "Output hooks";
 
@@ -87,9 +291,145 @@ pub mod output_filtering {
                 .long_help(
                     "Output only ROI having total mismatches frequency ≥ threshold (freq = ∑ mismatches / coverage)",
                 ),
+            Arg::new(MIN_FREQ_CI).long(MIN_FREQ_CI).takes_value(false).requires(CI).long_help(
+                "Apply --out-min-freq to the lower bound of the mismatches frequency confidence interval \
+                (Wilson score, --ci level) instead of the raw frequency. \
+                Sparsely covered ROIs need more evidence to pass the filter, e.g. 1 mismatch out of 3 bases \
+                has a 33% frequency, but the lower bound of its 95% interval is only 6%.",
+            ),
+            Arg::new(EDITING_ONLY).long(EDITING_ONLY).takes_value(false).long_help(
+                "Count only canonical A-to-I editing in the mismatches filter: A->G for the forward transcription strand, \
+                T->C for the reverse one, and the max of the two for ROIs with unknown strand. \
+                Other mismatches are still reported but do not let ROIs pass the filter.",
+            ),
+            Arg::new(REPLICATE_SUPPORT)
+                .long(REPLICATE_SUPPORT)
+                .takes_value(true)
+                .validator(|x| validate::replicate_support(x).map(|_| ()))
+                .long_help(
+                    "Output only ROIs with at least X mismatches in at least Y input files, given as X:Y. \
+                    Useful to discard single-library artifacts when technical replicates are processed together. \
+                    Reported counts are still pooled across all files.",
+                ),
+            Arg::new(FILTER_EXPR)
+                .long(FILTER_EXPR)
+                .takes_value(true)
+                .validator(validate::filter_expr(Records::ROIs))
+                .long_help(
+                    "Output only ROIs matching the given expression, e.g. \"A->G >= 3 AND C->T <= 1 AND coverage >= 20\". \
+                    Fields: coverage, mismatches (total), freq (mismatches / coverage), strand (transcription strand, \
+                    compared with \"+\", \"-\" or \".\") and X->Y mismatches relative to the reference strand. \
+                    Operators: <, <=, >, >=, ==, != (or ≤, ≥, ≠), AND, OR, NOT (or &&, ||, !) and parentheses. \
+                    Applied after other output filters, forced ROIs are always reported.",
+                ),
+            Arg::new(SOFT_FILTERS).long(SOFT_FILTERS).takes_value(false).conflicts_with(stats::STATS_ONLY).long_help(
+                "Report ROIs failing the output filters (--out-min-*, --replicate-support and --filter-expr) \
+                instead of dropping them. The added \"filter\" column holds PASS or semicolon-separated names \
+                of the failed filters (VCF-style). Forced ROIs always pass.",
+            ),
+            Arg::new(NO_OUTPUT_FILTERS)
+                .long(NO_OUTPUT_FILTERS)
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    MIN_MISMATCHES,
+                    MIN_FREQ,
+                    MIN_FREQ_CI,
+                    MIN_COVERAGE,
+                    EDITING_ONLY,
+                    REPLICATE_SUPPORT,
+                    FILTER_EXPR,
+                    SOFT_FILTERS,
+                    stats::STATS_ONLY,
+                ])
+                .long_help(
+                    "Disable all output filters and report every counted ROI with nonzero coverage. \
+                    Forced ROIs (--force) are reported regardless of their coverage.",
+                ),
+            Arg::new(EMIT_ZERO_COVERAGE)
+                .long(EMIT_ZERO_COVERAGE)
+                .takes_value(false)
+                .requires(NO_OUTPUT_FILTERS)
+                .conflicts_with(special::GROUP_BY_NAME)
+                .long_help(
+                    "Additionally report ROIs without coverage as a single row with zeroed counts \
+                    (on the BED strand, unless --ignore-roi-strand), e.g. to get a row for each ROI in the BED file. \
+                    All genome bins are processed in this case, including bins without reads.",
+                ),
             Arg::new(FORCE_LIST).long(FORCE_LIST).takes_value(true).validator(validate::path).long_help(
                 "Force the output of ROIs located in a given BED file (even if they do not pass other filters).",
             ),
+            Arg::new(BED)
+                .long(BED)
+                .takes_value(true)
+                .validator(validate::writable)
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "Additionally save reported ROIs to the given BED6 file (e.g. for IGV/UCSC tracks). \
+                    The score column is min(1000, round(1000 * A->G frequency)) relative to the transcription strand. \
+                    ROIs without coverage are not reported.",
+                ),
+            Arg::new(ROI_PROFILE)
+                .long(ROI_PROFILE)
+                .takes_value(true)
+                .validator(validate::writable)
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "Save per-position counts for reported ROIs to the given CSV file. \
+                    Only positions with at least one mismatch are reported, \
+                    nucleotides are given relative to the transcription strand.",
+                ),
+            Arg::new(ALSO_LOCI)
+                .long(ALSO_LOCI)
+                .takes_value(true)
+                .validator(validate::writable)
+                .conflicts_with(CHECKPOINT)
+                .long_help(
+                    "Additionally save the per-site table to the given path, as reported by the site subcommand \
+                    with the same core options and default site options. Reads are fetched & counted only once \
+                    for both tables, each one has its own stranding, filters and output. By default, sites are \
+                    reported for the whole genome (excluding --exclude'd regions).",
+                ),
+            Arg::new(LOCI_WITHIN_ROIS).long(LOCI_WITHIN_ROIS).takes_value(false).requires(ALSO_LOCI).long_help(
+                "Report --also-loci sites only within the ROI windows instead of the whole genome, \
+                i.e. reads outside of ROIs are never fetched.",
+            ),
+            Arg::new(CI).long(CI).takes_value(true).validator(|x| validate::confidence_level(x).map(|_| ())).long_help(
+                "Report the ci_low/ci_high columns with the Wilson score interval of the A->G frequency \
+                (relative to the transcription strand) at the given confidence level, e.g. 0.95.",
+            ),
+            Arg::new(NO_BACKGROUND).long(NO_BACKGROUND).takes_value(false).long_help(
+                "Don't report A->G frequency, background mismatches frequency (all mismatches except A->G/T->C) \
+                and A->G enrichment over the background. Useful to keep the column layout of older versions.",
+            ),
+            Arg::new(NO_ALN_STATS).long(NO_ALN_STATS).takes_value(false).long_help(
+                "Don't report fractions of covering reads with soft-clipped bases and insertions/deletions \
+                inside each ROI. Skips the extra CIGAR inspection and keeps the column layout of older versions.",
+            ),
+            Arg::new(WITH_ALT_FRAGMENTS).long(WITH_ALT_FRAGMENTS).takes_value(false).long_help(
+                "Add the \"alt_fragments\" column: max number of distinct fragments (read names, mates are the same \
+                fragment) with the mismatching nucleotides at a single ROI position. Tells independent support from \
+                a single duplicated fragment. Positions where the predicted reference differs from the assembly \
+                nucleotide are ignored; saturates at 256.",
+            ),
+            Arg::new(COLUMNS)
+                .long(COLUMNS)
+                .takes_value(true)
+                .validator(|x| validate::columns::<ROIColumn>(x).map(|_| ()))
+                .long_help(
+                    "Comma-separated list of columns to report, in the given order (e.g. contig,start,end,name,A->G). \
+                    Columns disabled by other options (e.g. --no-background) can't be selected. \
+                    By default, all enabled columns are reported.",
+                ),
+            Arg::new(FORMAT)
+                .long(FORMAT)
+                .takes_value(true)
+                .possible_values(&["tsv", "jsonl", "parquet"])
+                .default_value("tsv")
+                .long_help(
+                    "Output format of the main table, all formats have the same columns & ROIs order. \
+                    \"jsonl\" emits one JSON object per line, \"parquet\" is a typed Parquet table \
+                    (requires REAT built with the parquet feature, doesn't support checkpoints).",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -100,6 +440,7 @@ pub fn all<'a>() -> Vec<Arg<'a>> {
         .into_iter()
         .chain(stats::args())
         .chain(special::args())
+        .chain(stranding::args())
         .chain(output_filtering::args())
         .collect()
 }
@@ -107,40 +448,173 @@ pub fn all<'a>() -> Vec<Arg<'a>> {
 pub struct ROIArgs {
     pub workload: Vec<ROIWorkload>,
     pub maxwsize: usize,
+    // ROI bases removed by the exclude list or the mask
+    pub masked: u64,
+    pub group_by_name: bool,
+    pub min_overlap: Option<MinROIOverlap>,
+    // Skip the strand prediction for ROIs with a known (BED) strand
+    pub trust_strand: bool,
     pub prefilter: prefilters::ByMismatches,
-    pub ei: Option<(String, csv::Writer<File>)>,
+    pub replicates: Option<prefilters::ByReplicates>,
+    pub filter_expr: Option<ByExpression>,
+    pub ei: Option<(String, parse::EditingIndexes)>,
+    pub ei_per_sample: bool,
+    pub ei_contigs: Option<(String, HashSet<String>)>,
+    pub conversions: Option<(String, Vec<(ReqNucleotide, ReqNucleotide)>, bool, bool, csv::Writer<OutputWriter>)>,
+    pub per_contig: Option<(String, csv::Writer<OutputWriter>)>,
+    // Report only stats, ROIs are discarded once stats are collected
+    pub stats_only: bool,
     pub stranding: REATStrandingEngine<ROIMismatchesVec>,
     pub retain: Option<RetainROIFromList>,
+    pub bed: Option<csv::Writer<OutputWriter>>,
+    pub profile: Option<csv::Writer<OutputWriter>>,
+    pub columns: Vec<ROIColumn>,
+    pub format: OutputFormat,
+    pub alnstats: bool,
+    // Track fragments for the alt_fragments column
+    pub alt_fragments: bool,
+    // Z score of the requested A->G frequency CI
+    pub ci: Option<f32>,
+    // Mark ROIs failing the output filters instead of dropping them
+    pub soft_filters: bool,
+    // Only ROIs without coverage are dropped, or reported once with zeroed counts
+    pub no_output_filters: bool,
+    pub zero_coverage: bool,
+    // Per-site table written along with the ROIs & whether it's limited to the ROI windows
+    pub also_loci: Option<(PathBuf, bool)>,
 }
 
 impl ROIArgs {
-    pub fn new(core: &shared::args::CoreArgs, args: &ArgMatches, factory: &impl Fn() -> ProgressBar) -> Self {
-        let prefilter = shared::parse::outfilter(
-            factory(),
-            output_filtering::MIN_MISMATCHES,
-            output_filtering::MIN_FREQ,
-            output_filtering::MIN_COVERAGE,
-            args,
-        );
-        let ei = parse::editing_index(factory(), args);
-
-        let mut stranding = REATStrandingEngine::new();
-        let mut workload: Option<Vec<ROIWorkload>> = Default::default();
-        let mut maxsize: Option<usize> = Default::default();
-        let mut retain: Option<RetainROIFromList> = Default::default();
+    pub fn new(
+        core: &shared::args::CoreArgs,
+        args: &ArgMatches,
+        factory: &impl Fn() -> ProgressBar,
+    ) -> Result<Self, Error> {
+        if core.stream.is_some() {
+            panic!(
+                "ROIs are fetched from indexed inputs in arbitrary order, --{} is supported only in the site mode",
+                shared::args::core::STREAM
+            );
+        }
+        let ci = parse::ci(factory(), args);
+        let no_output_filters = args.is_present(output_filtering::NO_OUTPUT_FILTERS);
+        let mut prefilter = if no_output_filters {
+            shared::parse::no_outfilter(factory())
+        } else {
+            shared::parse::outfilter(
+                factory(),
+                output_filtering::MIN_MISMATCHES,
+                output_filtering::MIN_FREQ,
+                output_filtering::MIN_COVERAGE,
+                output_filtering::EDITING_ONLY,
+                // ROI counts are always converted back to natural units
+                1,
+                args,
+            )
+        };
+        if args.is_present(output_filtering::MIN_FREQ_CI) {
+            prefilter = prefilter.with_minfreq_ci(ci.unwrap());
+        }
+        let replicates =
+            shared::parse::replicates(factory(), output_filtering::REPLICATE_SUPPORT, 1, &core.bamfiles, args);
+        let filter_expr = shared::parse::filter_expr(factory(), output_filtering::FILTER_EXPR, Records::ROIs, 1, args);
+        let ei = parse::editing_index(factory(), args)?;
+        let ei_per_sample = args.is_present(stats::EDITING_INDEX_PER_SAMPLE);
+        let ei_contigs = parse::ei_contigs(factory(), args)?;
+        let conversions = parse::conversions(factory(), args)?;
+        let per_contig = parse::per_contig(factory(), args)?;
+        let stats_only = args.is_present(stats::STATS_ONLY);
+        if stats_only && ei.is_none() && conversions.is_none() && per_contig.is_none() {
+            panic!(
+                "--{} requires at least one stat output: --{}, --{} or --{}",
+                stats::STATS_ONLY,
+                stats::EDITING_INDEX,
+                stats::CONVERSION_OUTPUT,
+                stats::PER_CONTIG
+            );
+        }
+        let bed = parse::bed(factory(), args)?;
+        let profile = parse::profile(factory(), args)?;
+        if profile.is_some() && core.multimap != Multimap::Full {
+            panic!("Per-position ROI profiles require integer counts, use --multimap full with --roi-profile");
+        }
+        let background = !args.is_present(output_filtering::NO_BACKGROUND);
+        let alnstats = !args.is_present(output_filtering::NO_ALN_STATS);
+        let alt_fragments = args.is_present(output_filtering::WITH_ALT_FRAGMENTS);
+        // The "corrected" column is reported along with the reference source
+        let mut layout = ROIColumn::layout(background, core.refsource, alnstats, core.strand_depth);
+        if core.annotate.is_some() {
+            layout.extend(ROIColumn::annotation());
+        }
+        if ci.is_some() {
+            layout.extend(ROIColumn::ci());
+        }
+        if alt_fragments {
+            layout.extend(ROIColumn::fragments());
+        }
+        if args.is_present(special::ROI_FLANK) {
+            layout.extend(ROIColumn::flanks());
+        }
+        let soft_filters = args.is_present(output_filtering::SOFT_FILTERS);
+        if soft_filters {
+            layout.extend(ROIColumn::filters());
+        }
+        if core.tag_output {
+            layout.insert(0, ROIColumn::Sample);
+        }
+        let mut columns = shared::parse::columns(factory(), args, output_filtering::COLUMNS, layout);
+        // Explicitly selected columns may place the run name anywhere
+        if core.tag_output && !columns.contains(&ROIColumn::Sample) {
+            columns.insert(0, ROIColumn::Sample);
+        }
+        let format = shared::parse::format(factory(), args, output_filtering::FORMAT);
+
+        let mut stranding: Option<Result<REATStrandingEngine<_>, Error>> = Default::default();
+        let mut work: Option<Result<(Vec<ROIWorkload>, usize, u64), Error>> = Default::default();
+        let mut retain: Option<Result<Option<RetainROIFromList>, Error>> = Default::default();
 
         let (pbarw, pbars, pbarr) = (factory(), factory(), factory());
-        let excluded = core.excluded.clone();
+        let (included, excluded) = (core.included.clone(), core.excluded.clone());
         rayon::scope(|s| {
             s.spawn(|_| {
-                let (w, m) = parse::work(pbarw, args, excluded);
-                workload = Some(w);
-                maxsize = Some(m)
+                let (reference, refnames) = (core.reference.as_path(), &core.refnames);
+                work = Some(parse::work(pbarw, args, &core.contiglens, reference, refnames, included, excluded));
             });
-            s.spawn(|_| stranding = shared::parse::strandpred(pbars, args));
-            s.spawn(|_| retain = parse::retain(pbarr, args));
+            s.spawn(|_| stranding = Some(shared::parse::strandpred(pbars, args, stranding::EDITING, 1)));
+            s.spawn(|_| retain = Some(parse::retain(pbarr, args)));
         });
+        let (workload, maxwsize, masked) = work.unwrap()?;
+        let (stranding, retain) = (stranding.unwrap()?, retain.unwrap()?);
 
-        Self { workload: workload.unwrap(), maxwsize: maxsize.unwrap(), prefilter, ei, stranding, retain }
+        Ok(Self {
+            workload,
+            maxwsize,
+            masked,
+            group_by_name: args.is_present(special::GROUP_BY_NAME),
+            min_overlap: parse::min_overlap(factory(), args),
+            trust_strand: !args.is_present(stranding::IGNORE_ROI_STRAND),
+            prefilter,
+            replicates,
+            filter_expr,
+            ei,
+            ei_per_sample,
+            ei_contigs,
+            conversions,
+            per_contig,
+            stats_only,
+            stranding,
+            retain,
+            bed,
+            profile,
+            columns,
+            format,
+            alnstats,
+            alt_fragments,
+            ci,
+            soft_filters,
+            no_output_filters,
+            zero_coverage: args.is_present(output_filtering::EMIT_ZERO_COVERAGE),
+            also_loci: parse::also_loci(factory(), args),
+        })
     }
 }
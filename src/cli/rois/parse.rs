@@ -1,85 +1,402 @@
-use std::fs;
-use std::fs::File;
-
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-
 use std::str::FromStr;
 
+use bio_types::genome::AbstractInterval;
 use clap::ArgMatches;
+use flate2::bufread::MultiGzDecoder;
 use indicatif::ProgressBar;
+use itertools::Itertools;
+use rayon::prelude::*;
+use regex::Regex;
 
-use crate::cli::rois::args::output_filtering::FORCE_LIST;
+use crate::cli::rois::args::output_filtering::{ALSO_LOCI, BED, CI, FORCE_LIST, LOCI_WITHIN_ROIS, ROI_PROFILE};
+use crate::cli::rois::args::special::MIN_ROI_OVERLAP;
 use crate::cli::shared;
+use crate::core::dna::ReqNucleotide;
+use crate::core::hooks::stats::ROIEditingIndex;
 use crate::core::io;
 use crate::core::io::bed;
 use crate::core::io::bed::BedRecord;
+use crate::core::io::contigs::{ContigLengths, ContigNames};
+use crate::core::io::fasta;
+use crate::core::io::utils::OutputWriter;
 use crate::core::mismatches::prefilters::retain::RetainROIFromList;
+use crate::core::rpileup::ncounter::cnt::MinROIOverlap;
+use crate::core::statistics;
+use crate::core::workload;
 use crate::core::workload::ROIWorkload;
+use crate::error::Error;
 
 use super::args;
 
-pub fn work(pbar: ProgressBar, matches: &ArgMatches, exclude: Option<Vec<BedRecord>>) -> (Vec<ROIWorkload>, usize) {
-    let roi: &Path = matches.value_of(args::special::ROI).unwrap().as_ref();
+pub fn work(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    contiglens: &ContigLengths,
+    reference: &Path,
+    refnames: &ContigNames,
+    include: Option<Vec<BedRecord>>,
+    exclude: Option<Vec<BedRecord>>,
+) -> Result<(Vec<ROIWorkload>, usize, u64), Error> {
     let binsize = matches.value_of(shared::args::core::BINSIZE).unwrap().parse().unwrap();
-    pbar.set_message(format!("Parsing BED regions of interest from {}...", roi.display()));
-
-    let roi = io::bed::parse(roi);
-    let workload = ROIWorkload::from_bed(roi, binsize, exclude);
-    let maxlen = workload.iter().max_by_key(|x| x.len()).map(|x| x.len()).unwrap_or(0);
+    let bamcontigs = &contiglens.names();
+    let (roi, mut warnings) = match matches.value_of(args::special::TILE) {
+        None => {
+            let roi: &Path = matches.value_of(args::special::ROI).unwrap().as_ref();
+            pbar.set_message(format!("Parsing BED regions of interest from {}...", roi.display()));
+            shared::parse::bed_contigs(matches, "ROI list", bamcontigs, io::bed::parse(roi)?)?
+        }
+        Some(tile) => {
+            let (size, step) = shared::validate::tile(tile).unwrap();
+            pbar.set_message(format!("Tiling reference contigs with {}bp windows every {}bp...", size, step));
+            // Contigs absent in the BAM files have no reads => they are not tiled
+            let inverse = refnames.inverse();
+            let contigs = fasta::lengths(reference)?
+                .into_iter()
+                .map(|(contig, length)| (inverse.rename(&contig).to_owned(), length))
+                .filter(|(contig, _)| bamcontigs.contains(contig))
+                .sorted()
+                .collect();
+            (workload::roi::tiles(contigs, size, step), String::new())
+        }
+    };
+    // ROIs past the contig end are a sign of a wrong assembly or off-by-one coordinates
+    let past = contiglens.past_end(&roi).collect_vec();
+    if let Some((first, length)) = past.first() {
+        if !matches.is_present(args::special::CLIP_ROIS) {
+            return Err(Error::Input(format!(
+                "{} ROIs extend past the end of their contigs (e.g. {} {}:{}-{}, contig length {}), \
+                wrong assembly? Use --{} to truncate them at the contig ends",
+                past.len(),
+                first.name,
+                first.contig(),
+                first.interval.range().start,
+                first.interval.range().end,
+                length,
+                args::special::CLIP_ROIS
+            )));
+        }
+        warnings += &format!(" WARNING: {} ROIs extending past the contig ends were clipped.", past.len());
+    }
+    let roi = if past.is_empty() { roi } else { contiglens.clip(roi) };
+    let mask = match matches.value_of(args::special::MASK) {
+        None => None,
+        Some(x) => {
+            let (mask, warning) =
+                shared::parse::bed_contigs(matches, "mask", bamcontigs, io::bed::parse(Path::new(x))?)?;
+            warnings += &warning;
+            Some(mask)
+        }
+    };
+    let mut workload = ROIWorkload::from_bed(roi, binsize, include, exclude, mask);
+    if matches.is_present(args::special::GROUP_BY_NAME) {
+        let (grouped, shared) = ROIWorkload::group_by_name(workload);
+        workload = grouped;
+        if !shared.is_empty() {
+            warnings += &format!(
+                " WARNING: {} ROI names are used on several contigs (e.g. {}), \
+                such ROIs are grouped for each contig separately.",
+                shared.len(),
+                shared[0]
+            );
+        }
+    }
+    if let Some(flank) = matches.value_of(args::special::ROI_FLANK) {
+        workload = ROIWorkload::with_flanks(workload, flank.parse().unwrap(), contiglens);
+    }
+    if workload.is_empty() {
+        pbar.finish_with_message(format!(
+            "No work to do: no regions of interest left after the include/exclude filtering.{}",
+            warnings
+        ));
+        return Ok((workload, 0, 0));
+    }
+    let maxlen = workload.par_iter().map(|x| x.len()).max().unwrap_or(0);
+    // ROI bases removed by the exclude list or the mask
+    let masked = workload.par_iter().flat_map_iter(|x| x.rois()).map(|x| x.masked()).sum::<u64>();
     pbar.finish_with_message(format!(
-        "Will summarize {} ROI editing for regions with max bin size {}",
+        "Will summarize {} ROI editing for regions with max bin size {} ({} bases masked).{}",
         workload.len(),
-        maxlen
+        maxlen,
+        masked,
+        warnings
     ));
-    (workload, maxlen)
+    Ok((workload, maxlen, masked))
 }
 
-pub fn editing_index(pbar: ProgressBar, matches: &ArgMatches) -> Option<(String, csv::Writer<File>)> {
-    pbar.set_message("Parsing EI output path...");
-    match matches.value_of(args::stats::EDITING_INDEX) {
+// Output of each requested EI together with the optional label & ROI name pattern
+pub type EditingIndexes = Vec<(Option<(String, Regex)>, csv::Writer<OutputWriter>)>;
+
+pub fn editing_index(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<(String, EditingIndexes)>, Error> {
+    pbar.set_message("Parsing EI output paths...");
+    match matches.values_of(args::stats::EDITING_INDEX) {
         None => {
             pbar.finish_with_message("Editing index won't be calculated");
-            None
+            Ok(None)
         }
-        Some(ei) => {
-            let ei = PathBuf::from_str(ei).unwrap();
+        Some(values) => {
+            let entries: Vec<(Option<(String, Regex)>, PathBuf)> =
+                values.map(|x| shared::validate::editing_index(x).unwrap()).collect();
+            if !entries.iter().map(|x| x.0.as_ref().map(|x| &x.0)).all_unique() {
+                return Err(Error::Input(
+                    "EI labels must be unique, and only a single EI can be calculated for all ROIs".into(),
+                ));
+            }
+            if !entries.iter().map(|x| &x.1).all_unique() {
+                return Err(Error::Input("Each EI must be saved to a separate file".into()));
+            }
 
-            let mut builder = csv::WriterBuilder::new();
-            let mut options = fs::OpenOptions::new();
+            let mut saveto = Vec::with_capacity(entries.len());
+            for (names, ei) in entries {
+                // Append to the existing file without repeating the header
+                let append = match ei_header(&ei)? {
+                    None => false,
+                    Some(header) if header == ROIEditingIndex::header() => true,
+                    Some(_) => {
+                        return Err(Error::Input(format!(
+                            "EI file {} has different columns (created by an older REAT version?), \
+                            can't append to it. Expected header: {}",
+                            ei.display(),
+                            ROIEditingIndex::header()
+                        )))
+                    }
+                };
+                let stream = io::utils::write_compressed(&ei, append)?;
+                let writer = csv::WriterBuilder::new().has_headers(!append).from_writer(stream);
+                saveto.push((names, writer));
+            }
 
-            if ei.exists() {
-                builder.has_headers(false);
-                options.append(true);
+            let kind = if matches.is_present(args::stats::EDITING_INDEX_PER_SAMPLE) {
+                "Per-sample editing"
             } else {
-                options.create(true);
+                "Editing"
             };
+            pbar.finish_with_message(format!(
+                "{} index(es) will be calculated for {} ROI subset(s)",
+                kind,
+                saveto.len()
+            ));
+
+            Ok(Some((source(matches), saveto)))
+        }
+    }
+}
 
-            let stream = options.write(true).open(&ei).expect("Failed to open EI index file for writing.");
+// Requested conversions and whether the mismatch spectrum & the per-strand EI are requested as well
+#[allow(clippy::type_complexity)]
+pub fn conversions(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+) -> Result<Option<(String, Vec<(ReqNucleotide, ReqNucleotide)>, bool, bool, csv::Writer<OutputWriter>)>, Error> {
+    pbar.set_message("Parsing requested conversion rates...");
+    match matches.values_of(args::stats::CONVERSION) {
+        None => {
+            pbar.finish_with_message("Conversion rates won't be calculated");
+            Ok(None)
+        }
+        Some(stats) => {
+            let (spectrum, conversions): (Vec<&str>, Vec<&str>) = stats.partition(|x| *x == args::stats::SPECTRUM);
+            let spectrum = !spectrum.is_empty();
+            let (bystrand, conversions): (Vec<&str>, Vec<&str>) =
+                conversions.into_iter().partition(|x| *x == args::stats::EI_BY_STRAND);
+            let bystrand = !bystrand.is_empty();
+            let conversions: Vec<(ReqNucleotide, ReqNucleotide)> =
+                conversions.into_iter().map(|x| shared::validate::conversion(x).unwrap()).unique().collect();
+            let saveto = PathBuf::from_str(matches.value_of(args::stats::CONVERSION_OUTPUT).unwrap()).unwrap();
 
-            let writer = builder.from_writer(stream);
+            // Each stat has its own header row => always append & never write csv headers automatically
+            let stream = io::utils::write_compressed(&saveto, saveto.exists())?;
+            let writer = csv::WriterBuilder::new().has_headers(false).from_writer(stream);
 
-            pbar.finish_with_message(format!("Editing index will be saved to {}", ei.display()));
+            pbar.finish_with_message(format!(
+                "{} conversion rate(s){}{} will be saved to {}",
+                conversions.len(),
+                if spectrum { ", the mismatch spectrum" } else { "" },
+                if bystrand { ", per-strand EI" } else { "" },
+                saveto.display()
+            ));
 
-            let roi = matches.value_of(args::special::ROI).unwrap();
+            Ok(Some((source(matches), conversions, spectrum, bystrand, writer)))
+        }
+    }
+}
 
-            Some((roi.into(), writer))
+pub fn per_contig(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+) -> Result<Option<(String, csv::Writer<OutputWriter>)>, Error> {
+    pbar.set_message("Parsing per-contig stats output path...");
+    match matches.value_of(args::stats::PER_CONTIG) {
+        None => {
+            pbar.finish_with_message("Per-contig editing indexes won't be calculated");
+            Ok(None)
+        }
+        Some(path) => {
+            let stream = io::utils::write_compressed(Path::new(path), false)?;
+            let writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(stream);
+            pbar.finish_with_message(format!("Per-contig editing indexes will be saved to {}", path));
+            Ok(Some((source(matches), writer)))
         }
     }
 }
 
-pub fn retain(pbar: ProgressBar, matches: &ArgMatches) -> Option<RetainROIFromList> {
+// ROIs origin reported in the stats: the BED file or the tiling parameters
+fn source(matches: &ArgMatches) -> String {
+    match matches.value_of(args::special::TILE) {
+        None => matches.value_of(args::special::ROI).unwrap().to_owned(),
+        Some(tile) => format!("tile:{}", tile),
+    }
+}
+
+fn _first_line<T: BufRead>(mut reader: T) -> std::io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line)
+}
+
+// First line of the existing non-empty EI file, compressed files are decompressed the same way they are written
+fn ei_header(ei: &Path) -> Result<Option<String>, Error> {
+    if !ei.exists() {
+        return Ok(None);
+    }
+    let header = io::utils::read_compressed!(ei, _first_line)?.map_err(|x| Error::io(ei, x))?;
+    let header = header.trim_end();
+    if header.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(header.to_owned()))
+    }
+}
+
+pub fn ei_contigs(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<(String, HashSet<String>)>, Error> {
+    pbar.set_message("Parsing EI contigs...");
+    match matches.value_of(args::stats::EDITING_INDEX_CONTIGS) {
+        None => {
+            pbar.finish_with_message("Editing index will be calculated using all contigs");
+            Ok(None)
+        }
+        Some(subset) => {
+            // Either a file with one contig per line or a comma-separated list
+            let path = Path::new(subset);
+            let contigs: HashSet<String> = if path.is_file() {
+                fs::read_to_string(path)
+                    .map_err(|x| Error::io(path, x))?
+                    .lines()
+                    .map(|x| x.trim().to_owned())
+                    .filter(|x| !x.is_empty())
+                    .collect()
+            } else {
+                subset.split(',').map(|x| x.trim().to_owned()).filter(|x| !x.is_empty()).collect()
+            };
+            if contigs.is_empty() {
+                return Err(Error::Input("EI contigs subset must not be empty".into()));
+            }
+            pbar.finish_with_message(format!("Editing index will be calculated using {} contigs", contigs.len()));
+            Ok(Some((subset.into(), contigs)))
+        }
+    }
+}
+
+pub fn retain(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<RetainROIFromList>, Error> {
     pbar.set_message("Parsing the \"force\" BED file...");
 
-    let bedrecords = matches.value_of(FORCE_LIST).map(|x| bed::parse(Path::new(x)));
+    let bedrecords = matches.value_of(FORCE_LIST).map(|x| bed::parse(Path::new(x))).transpose()?;
 
     match bedrecords {
         None => {
             pbar.finish_with_message("Forced output is disabled");
-            None
+            Ok(None)
         }
         Some(bed) => {
             pbar.finish_with_message(format!("Output thresholds are disabled for {} ROIs(force list)", bed.len()));
-            Some(RetainROIFromList::new(bed))
+            Ok(Some(RetainROIFromList::new(bed)))
+        }
+    }
+}
+
+pub fn bed(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<csv::Writer<OutputWriter>>, Error> {
+    pbar.set_message("Parsing BED output path...");
+    match matches.value_of(BED) {
+        None => {
+            pbar.finish_with_message("BED output is disabled");
+            Ok(None)
+        }
+        Some(bed) => {
+            let file = io::utils::write_compressed(Path::new(bed), false)?;
+            let writer = csv::WriterBuilder::new().delimiter(b'\t').has_headers(false).from_writer(file);
+            pbar.finish_with_message(format!("ROIs will be additionally saved to {}", bed));
+            Ok(Some(writer))
+        }
+    }
+}
+
+pub fn profile(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<csv::Writer<OutputWriter>>, Error> {
+    pbar.set_message("Parsing ROI profile output path...");
+    match matches.value_of(ROI_PROFILE) {
+        None => {
+            pbar.finish_with_message("ROI profile output is disabled");
+            Ok(None)
+        }
+        Some(profile) => {
+            let file = io::utils::write_compressed(Path::new(profile), false)?;
+            let writer = csv::WriterBuilder::new().from_writer(file);
+            pbar.finish_with_message(format!("Per-position ROI profiles will be saved to {}", profile));
+            Ok(Some(writer))
+        }
+    }
+}
+
+pub fn also_loci(pbar: ProgressBar, matches: &ArgMatches) -> Option<(PathBuf, bool)> {
+    pbar.set_message("Parsing the per-site output path...");
+    match matches.value_of(ALSO_LOCI) {
+        None => {
+            pbar.finish_with_message("Per-site output is disabled");
+            None
+        }
+        Some(path) => {
+            let within = matches.is_present(LOCI_WITHIN_ROIS);
+            let territory = if within { "within the ROI windows" } else { "for the whole genome" };
+            pbar.finish_with_message(format!("Sites {} will be additionally saved to {}", territory, path));
+            Some((PathBuf::from(path), within))
+        }
+    }
+}
+
+// Z score of the requested confidence level
+pub fn ci(pbar: ProgressBar, matches: &ArgMatches) -> Option<f32> {
+    pbar.set_message("Parsing the confidence level...");
+    match matches.value_of(CI) {
+        None => {
+            pbar.finish_with_message("Confidence intervals won't be reported");
+            None
+        }
+        Some(level) => {
+            let level = shared::validate::confidence_level(level).unwrap();
+            pbar.finish_with_message(format!(
+                "{}% confidence intervals of the A->G frequency will be reported",
+                level * 100f32
+            ));
+            Some(statistics::zscore(level))
+        }
+    }
+}
+
+pub fn min_overlap(pbar: ProgressBar, matches: &ArgMatches) -> Option<MinROIOverlap> {
+    pbar.set_message("Parsing the min ROI overlap...");
+    let overlap = matches.value_of(MIN_ROI_OVERLAP).map(|x| shared::validate::roi_overlap(x).unwrap());
+    match overlap {
+        None => pbar.finish_with_message("Reads will be counted for all overlapping ROIs"),
+        Some(MinROIOverlap::Bases(x)) => {
+            pbar.finish_with_message(format!("Reads will be counted only for ROIs they overlap by ≥ {} bases", x))
         }
+        Some(MinROIOverlap::Fraction(x)) => pbar.finish_with_message(format!(
+            "Reads will be counted only for ROIs covering ≥ {}% of their aligned bases",
+            x * 100f32
+        )),
     }
+    overlap
 }
@@ -1,70 +1,251 @@
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
+use bio_types::genome::{AbstractInterval, Interval};
 use clap::ArgMatches;
 use indicatif::ProgressBar;
+use itertools::Itertools;
 
 use crate::cli::rois::args::ROIArgs;
 use crate::cli::shared;
 use crate::cli::shared::args::CoreArgs;
-use crate::cli::shared::stranding::Stranding;
-use crate::core::hooks::engine::REATHooksEngine;
+use crate::cli::shared::checkpoint::Checkpoint;
+use crate::cli::shared::format::OutputFormat;
+use crate::cli::shared::runinfo::Skipped;
+use crate::cli::shared::RunMeta;
+use crate::cli::sites::Loci;
 use crate::core::hooks::filters;
-use crate::core::hooks::stats::{EditingStatType, ROIEditingIndex};
-use crate::core::mismatches::roi::{ROIMismatchesBuilder, ROIMismatchesVec};
-use crate::core::rpileup::hts::HTSPileupEngine;
-use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, ROINucCounter, StrandedNucCounter};
-use crate::core::runner::REATRunner;
+use crate::core::hooks::stats::{
+    EditingStatType, ROIContigEditingIndex, ROIConversionRate, ROIEditingIndex, ROIMismatchSpectrum,
+    StrandSplitEditingIndex,
+};
+use crate::core::io::fasta::{BasicFastaReader, FastaReader};
+use crate::core::io::table;
+use crate::core::io::utils::FinishWrite;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::pipeline::{stream_paired, stream_rois, ROIRunConfig};
+use crate::core::refpred::RefNPolicy;
+use crate::core::rpileup::ncounter::cnt::ExcludedRegions;
+use crate::core::workload::ROIWorkload;
+use crate::error::Error;
 
-pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> ProgressBar) {
-    let args = ROIArgs::new(&core, args, &factory);
+const BED_WRITE_ERROR: &str = "Failed to write ROIs to the output BED file";
+const PROFILE_WRITE_ERROR: &str = "Failed to write ROI profiles";
+
+pub fn run(matches: &ArgMatches, core: CoreArgs, factory: impl Fn() -> ProgressBar) -> Result<(), Error> {
+    let mut meta = RunMeta::new("roi", matches, &super::args(), &core.bamfiles)?;
+    let args = ROIArgs::new(&core, matches, &factory)?;
+    let bins = args.workload.len();
+    let contigs: Vec<String> = args.workload.iter().map(|x| x.contig().to_owned()).unique().collect();
+    // Sites are counted in the same pass over the reads, optionally only within the ROI windows
+    let loci = match &args.also_loci {
+        None => None,
+        Some((saveto, within)) => {
+            let regions = within.then(|| windows(&args.workload));
+            Some(Loci::new(matches, saveto, regions, &factory)?)
+        }
+    };
+
+    let mut config = ROIRunConfig::new(core.bamfiles, args.workload, args.maxwsize, core.refnucpred, core.readfilter);
+    config.indexes = core.indexes;
+    config.reference = Some(core.reference.clone());
+    config.skip_refn = core.refn == RefNPolicy::Skip;
+    config.trim5 = core.trim5;
+    config.trim3 = core.trim3;
+    config.overlaps_dedup = core.overlaps_dedup;
+    config.umi = core.umi;
+    config.hts_threads = core.hts_threads;
+    config.prefetch = core.prefetch;
+    config.strand_depth = core.strand_depth;
+    config.multimap = core.multimap;
+    config.max_coverage = core.max_coverage;
+    if let Some(max) = core.max_read_contribution {
+        let reader = BasicFastaReader::new(core.reference.clone())?.with_contig_names(core.refnames.clone());
+        config.max_read_contribution = Some((max, Box::new(reader) as Box<dyn FastaReader>));
+    }
+    config.design = core.stranding.design();
+    config.stranding = args.stranding;
+    config.retain = args.retain;
+    config.group_by_name = args.group_by_name;
+    config.min_roi_overlap = args.min_overlap;
+    // Excluded regions are subtracted from ROIs, but their flanks & covering reads must be masked as well
+    config.excluded = core.excluded.map(|x| Arc::new(ExcludedRegions::new(x)));
+    config.trust_roi_strand = args.trust_strand;
+    config.contigs = core.contigs;
+    config.progress = core.progress.reporter(factory);
+    config.deterministic = core.deterministic;
 
-    let mut hooks: REATHooksEngine<ROIMismatchesVec> = REATHooksEngine::new();
     let mut statsto = HashMap::new();
-    let builder = match args.ei {
-        None => {
-            // Always with prefilter since there are no site-level stats right now
-            ROIMismatchesBuilder::new(args.maxwsize, core.refnucpred, args.retain, Some(args.prefilter))
+    if let Some((ei, saveto)) = args.ei {
+        let samples = config.bamfiles.iter().map(|x| shared::parse::sample_name(x)).collect::<Result<Vec<_>, _>>()?;
+        for (names, writer) in saveto {
+            let mut stat = if args.ei_per_sample {
+                ROIEditingIndex::per_sample(core.name.clone(), ei.clone(), samples.clone())
+            } else {
+                ROIEditingIndex::new(core.name.clone(), ei.clone())
+            };
+            if let Some((subset, contigs)) = args.ei_contigs.clone() {
+                stat = stat.with_contigs(subset, contigs);
+            }
+            if let Some((label, pattern)) = names {
+                stat = stat.with_names(label, pattern);
+            }
+            statsto.insert(EditingStatType::ROIEditingIndex(stat.label().map(|x| x.to_owned())), writer);
+            config.hooks.add_stat(Box::new(stat));
         }
-        Some((ei, writer)) => {
-            statsto.insert(EditingStatType::ROIEditingIndex, writer);
-            // Disable prefilter and use a hook instead
-            hooks.add_stat(Box::new(ROIEditingIndex::new(core.name, ei)));
-            let filter: filters::ByMismatches = args.prefilter.into();
-            hooks.add_filter(Box::new(filter));
-            // Builder without prefiltering
-            ROIMismatchesBuilder::new(args.maxwsize, core.refnucpred, args.retain, None)
+    }
+    if let Some((roifiles, conversions, spectrum, bystrand, writer)) = args.conversions {
+        // The mismatch spectrum & per-strand EI are written to the same output
+        statsto.insert(EditingStatType::ROIConversionRate, writer);
+        for (from, to) in conversions {
+            let stat = ROIConversionRate::new(core.name.clone(), roifiles.clone(), from, to);
+            config.hooks.add_stat(Box::new(stat));
         }
-    };
+        if spectrum {
+            config.hooks.add_stat(Box::new(ROIMismatchSpectrum::new(core.name.clone(), roifiles.clone())));
+        }
+        if bystrand {
+            config.hooks.add_stat(Box::new(StrandSplitEditingIndex::new(core.name.clone(), roifiles)));
+        }
+    }
 
-    // Initialize basic counter
-    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3);
-    let counter = ROINucCounter::new(counter);
-
-    let mut strander = args.stranding;
-    match core.stranding {
-        Stranding::Unstranded => {
-            // Compose strander + pileuper
-            let pileuper = HTSPileupEngine::new(core.bamfiles, counter);
-            // Launch the processing
-            let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, statsto).unwrap()
+    if let Some((roifiles, writer)) = args.per_contig {
+        statsto.insert(EditingStatType::ROIContigEditingIndex, writer);
+        let stat = ROIContigEditingIndex::new(core.name.clone(), roifiles, contigs);
+        config.hooks.add_stat(Box::new(stat));
+    }
+
+    if args.soft_filters {
+        config.hooks.soft_filters();
+    }
+    if args.stats_only {
+        // Output filters are irrelevant, ROIs are dropped right after the stats
+        config.stats_only = true;
+    } else if args.no_output_filters {
+        // ROIs without coverage don't contribute to the stats => the prefilter is never a hook
+        if args.zero_coverage {
+            config.zero_coverage = true;
+        } else {
+            config.prefilter = Some(args.prefilter);
         }
-        Stranding::Stranded(x) => {
-            // Remove all stranding algorithm -> they are not required
-            strander.clear();
-            // Compose strander + pileuper
-            let deductor = crate::core::stranding::deduce::DeduceStrandByDesign::new(x);
-            let pileuper = HTSPileupEngine::new(core.bamfiles, StrandedNucCounter::new(counter, deductor));
-
-            // Launch the processing
-            let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, statsto).unwrap()
+    } else if statsto.is_empty() && !args.soft_filters {
+        // Always with prefilter since there are no site-level stats right now
+        config.prefilter = Some(args.prefilter);
+        config.replicates = args.replicates;
+    } else {
+        // Stats must see all ROIs & soft filters only mark them => disable prefilter and use a hook instead
+        let filter: filters::ByMismatches = args.prefilter.into();
+        config.hooks.add_filter(Box::new(filter));
+        if let Some(replicates) = args.replicates {
+            let filter: filters::ByReplicates = replicates.into();
+            config.hooks.add_filter(Box::new(filter));
+            // Per-file counts are required by the filter
+            config.per_source = true;
+        }
+    }
+    // Applied after the built-in output filters, irrelevant for the stats-only runs
+    if let (false, Some(filter)) = (args.stats_only, args.filter_expr) {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    // Track counts for each input file to calculate per-sample EI
+    config.per_source |= args.ei_per_sample;
+
+    config.profile = args.profile.is_some();
+    // Alignment stats, per-strand depth & fragments are reported only in the main table
+    config.alnstats = args.alnstats && !args.stats_only;
+    config.strand_depth &= !args.stats_only;
+    if args.alt_fragments && !args.stats_only {
+        let reader = BasicFastaReader::new(core.reference.clone())?.with_contig_names(core.refnames.clone());
+        config.alt_fragments = Some(Box::new(reader) as Box<dyn FastaReader>);
+    }
+
+    // BED & profile outputs (if any) are written along with the main table to keep the same ordering
+    let (mut bed, mut profile) = (args.bed, args.profile);
+    let (columns, orient, annotation, ci) = (args.columns, core.orient, core.annotate, args.ci);
+    let sample = core.tag_output.then(|| core.name.clone());
+    let mut saveto = core.saveto;
+    if let Some(dir) = core.checkpoint {
+        let checkpoint = Checkpoint::new(dir, meta.fingerprint())?;
+        config.finished = checkpoint.finished();
+        saveto = saveto.checkpointed(checkpoint);
+    }
+    if !args.stats_only {
+        let format = args.format.table(ROIMismatchesVec::schema(&columns));
+        saveto = saveto.with_format(format).with_header(ROIMismatchesVec::header(&columns));
+        if core.schema_line && args.format == OutputFormat::Tsv {
+            saveto = saveto.with_comment(&table::schema_line())?;
+        }
+    }
+    let (contiglens, mut past) = (&core.contiglens, 0);
+    let write = |contig: &str, items: Vec<ROIMismatchesVec>| {
+        past += shared::past_end(&items, contiglens);
+        saveto.write(items, |items, writer| {
+            ROIMismatchesVec::ugly_in_contig_sort_and_to_csv_and_bed(
+                items,
+                writer,
+                bed.as_mut(),
+                profile.as_mut(),
+                &columns,
+                orient,
+                annotation.as_ref(),
+                ci,
+                sample.as_deref(),
+            )
+        })?;
+        saveto.commit(contig)
+    };
+    let summary = match loci {
+        None => stream_rois(config, write)?,
+        Some((mut loci, sites)) => {
+            let (summary, sites) = stream_paired(config, sites, write, |contig, items| loci.write(contig, items))?;
+            loci.finish(sites)?;
+            summary
         }
     };
+    shared::finalize(summary.stats, &mut saveto, statsto)?;
+    shared::report_past_end(past, &factory);
+    shared::report_tags(&core.tags, &factory);
+    core.filtered.dumped()?;
+    shared::report_filtered(&core.filtered, &factory);
+
+    if let Some(mut bed) = bed {
+        bed.flush().and_then(|_| bed.get_mut().finish()).map_err(|x| Error::output(BED_WRITE_ERROR, x))?;
+    }
+    if let Some(mut profile) = profile {
+        profile.flush().and_then(|_| profile.get_mut().finish()).map_err(|x| Error::output(PROFILE_WRITE_ERROR, x))?;
+    }
+
+    if let Some(runinfo) = core.runinfo {
+        let skipped = Skipped { excluded: 0, masked: args.masked, refn: summary.refn_skipped };
+        meta.onfinish(bins, summary.items, summary.reads, skipped, core.started.elapsed());
+        meta.refbases = core.refbases.load(Ordering::Relaxed);
+        meta.save(&runinfo)?;
+    }
+    // Outputs are finalized anyway => empty tables are left for inspection
+    shared::check_empty(&summary.contigs, core.aligned.as_ref(), core.stale_index, core.allow_empty, &factory)?;
 
     // debug_assert!(stats.is_empty());
 
     // resformat::rois(&mut core.saveto, rois);
+    Ok(())
+}
+
+// Merged ROI windows, i.e. each site is reported once
+fn windows(workload: &[ROIWorkload]) -> Vec<Interval> {
+    workload
+        .iter()
+        .map(|x| (x.contig(), x.range()))
+        .sorted_by(|x, y| x.0.cmp(y.0).then(x.1.start.cmp(&y.1.start)))
+        .coalesce(|x, y| {
+            if x.0 == y.0 && y.1.start <= x.1.end {
+                Ok((x.0, x.1.start..x.1.end.max(y.1.end)))
+            } else {
+                Err((x, y))
+            }
+        })
+        .map(|(contig, range)| Interval::new(contig.to_owned(), range))
+        .collect()
 }
 
 // fn process<
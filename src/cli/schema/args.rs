@@ -0,0 +1,41 @@
+use clap::{Arg, ArgMatches};
+
+pub const MODE: &str = "mode";
+pub const FORMAT: &str = "format";
+
+pub fn all<'a>() -> Vec<Arg<'a>> {
+    vec![
+        Arg::new(MODE)
+            .long(MODE)
+            .required(true)
+            .takes_value(true)
+            .possible_values(&["rois", "loci"])
+            .long_help("Output table to describe: the ROI table (reat roi) or the site table (reat site)"),
+        Arg::new(FORMAT).long(FORMAT).takes_value(true).possible_values(&["json"]).default_value("json").long_help(
+            "Format of the description. \"json\" is a single object with the schema version and the ordered list \
+            of columns, each with its name, type (str, uint or float) and description",
+        ),
+    ]
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Mode {
+    ROIs,
+    Loci,
+}
+
+pub struct SchemaArgs {
+    pub mode: Mode,
+}
+
+impl SchemaArgs {
+    pub fn new(args: &ArgMatches) -> Self {
+        // All values are validated by clap
+        let mode = match args.value_of(MODE).unwrap() {
+            "rois" => Mode::ROIs,
+            "loci" => Mode::Loci,
+            mode => panic!("Unknown mode: {}", mode),
+        };
+        Self { mode }
+    }
+}
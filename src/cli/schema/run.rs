@@ -0,0 +1,64 @@
+use std::io;
+use std::io::Write;
+
+use clap::ArgMatches;
+use serde::Serialize;
+
+use crate::cli::schema::args::{Mode, SchemaArgs};
+use crate::core::dna::Multimap;
+use crate::core::io::table::{ColumnInfo, SCHEMA_VERSION};
+use crate::core::mismatches::roi::{ROIColumn, ROIMismatchesVec};
+use crate::core::mismatches::site::{SiteColumn, SiteMismatchesVec};
+use crate::error::Error;
+
+const WRITE_ERROR: &str = "Failed to print the schema";
+
+#[derive(Serialize)]
+struct Schema {
+    version: u32,
+    mode: &'static str,
+    columns: Vec<ColumnInfo>,
+}
+
+// Columns of the main table written with the default options, described by the same table the serializers use
+fn schema(mode: Mode) -> Schema {
+    match mode {
+        Mode::ROIs => {
+            Schema { version: SCHEMA_VERSION, mode: "rois", columns: ROIMismatchesVec::describe(&ROIColumn::all()) }
+        }
+        Mode::Loci => {
+            let columns = SiteMismatchesVec::describe(&SiteColumn::all(), Multimap::Full, false, None, false);
+            Schema { version: SCHEMA_VERSION, mode: "loci", columns }
+        }
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let args = SchemaArgs::new(args);
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &schema(args.mode))
+        .map_err(|x| Error::output(WRITE_ERROR, io::Error::from(x)))?;
+    writeln!(stdout).map_err(|x| Error::output(WRITE_ERROR, x))
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    #[test]
+    fn schema() {
+        for (mode, header) in [
+            (Mode::ROIs, ROIMismatchesVec::header(&ROIColumn::all())),
+            (Mode::Loci, SiteMismatchesVec::header(&SiteColumn::all(), None, false)),
+        ] {
+            let json = serde_json::to_value(super::schema(mode)).unwrap();
+            assert_eq!(json["version"], SCHEMA_VERSION);
+            let columns = json["columns"].as_array().unwrap();
+            assert_eq!(columns.iter().map(|x| x["name"].as_str().unwrap()).collect_vec(), header);
+            assert!(columns.iter().all(|x| ["str", "uint", "float"].contains(&x["type"].as_str().unwrap())));
+        }
+    }
+}
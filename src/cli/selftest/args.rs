@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches};
+
+use crate::cli::shared::validate;
+use crate::core::mismatches::prefilters;
+use crate::core::testkit::Simulation;
+
+pub mod simulation {
+    use super::*;
+
+    pub const SEED: &str = "seed";
+    pub const CONTIGS: &str = "contigs";
+    pub const CONTIG_LENGTH: &str = "contig-length";
+    pub const READ_LENGTH: &str = "read-length";
+    pub const COVERAGE: &str = "coverage";
+    pub const ERROR_RATE: &str = "error-rate";
+    pub const SPIKES: &str = "spikes";
+    pub const WORKDIR: &str = "workdir";
+
+    pub const SECTION_NAME: &str = "Simulation";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(SEED)
+                .long(SEED)
+                .takes_value(true)
+                .validator(validate::numeric(0u64, u64::MAX))
+                .default_value("42")
+                .long_help("Seed of the random generator, the same seed always produces the same dataset"),
+            Arg::new(CONTIGS)
+                .long(CONTIGS)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, 1000))
+                .default_value("3")
+                .long_help("Number of random contigs in the synthetic genome"),
+            Arg::new(CONTIG_LENGTH)
+                .long(CONTIG_LENGTH)
+                .takes_value(true)
+                .validator(validate::numeric(1000u64, 1_000_000_000))
+                .default_value("100000")
+                .long_help("Length of each synthetic contig"),
+            Arg::new(READ_LENGTH)
+                .long(READ_LENGTH)
+                .takes_value(true)
+                .validator(validate::numeric(10u32, 10_000))
+                .default_value("100")
+                .long_help("Length of simulated single-end reads"),
+            Arg::new(COVERAGE)
+                .long(COVERAGE)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, 100_000))
+                .default_value("50")
+                .long_help("Mean coverage of each position by reads from both strands"),
+            Arg::new(ERROR_RATE)
+                .long(ERROR_RATE)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .default_value("0.001")
+                .long_help("Probability to replace each sequenced base by a random nucleotide"),
+            Arg::new(SPIKES)
+                .long(SPIKES)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, 1_000_000))
+                .default_value("50")
+                .long_help(
+                    "A->I edits spiked per contig at random A (forward strand) or T (reverse strand) positions. \
+                    Editing frequencies cycle through 0.1, 0.25, 0.5, 0.75 and 0.9",
+                ),
+            Arg::new(WORKDIR).long(WORKDIR).takes_value(true).validator(validate::writable).long_help(
+                "Directory to keep the synthetic genome & reads (genome.fa, reads.bam), \
+                e.g. to inspect them or to benchmark the roi/site subcommands. \
+                By default, a temporary directory is used and removed at the end",
+            ),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
+pub mod checks {
+    use super::*;
+
+    pub const THREADS: &str = "threads";
+    pub const TILE: &str = "tile";
+    pub const MIN_MISMATCHES: &str = "min-mismatches";
+    pub const MIN_FREQ: &str = "min-freq";
+    pub const TOLERANCE: &str = "tolerance";
+    pub const MAX_FP_RATE: &str = "max-fp-rate";
+
+    pub const SECTION_NAME: &str = "Checks";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(THREADS)
+                .short('t')
+                .long(THREADS)
+                .takes_value(true)
+                .validator(validate::numeric(1, usize::MAX))
+                .default_value("1")
+                .long_help("Maximum number of threads to spawn at once"),
+            Arg::new(TILE)
+                .long(TILE)
+                .takes_value(true)
+                .validator(validate::numeric(1u64, u64::MAX))
+                .default_value("1000")
+                .long_help("Size of the genomic windows used as ROIs"),
+            Arg::new(MIN_MISMATCHES)
+                .long(MIN_MISMATCHES)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, u32::MAX))
+                .default_value("3")
+                .long_help(
+                    "Report sites with at least X mismatches. \
+                    Only spikes with enough edited reads in the simulated data are expected to be recovered",
+                ),
+            Arg::new(MIN_FREQ)
+                .long(MIN_FREQ)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .default_value("0.05")
+                .long_help("Report sites with the mismatches frequency ≥ threshold"),
+            Arg::new(TOLERANCE)
+                .long(TOLERANCE)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .default_value("0.01")
+                .long_help(
+                    "Max absolute difference between the reported A->I frequency and the fraction of edited bases \
+                    in the simulated reads, both for spiked sites and ROIs",
+                ),
+            Arg::new(MAX_FP_RATE)
+                .long(MAX_FP_RATE)
+                .takes_value(true)
+                .validator(validate::numeric(0f64, 1f64))
+                .default_value("0.0001")
+                .long_help("Max fraction of positions without spikes (both strands) reported as edited sites"),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
+pub fn all<'a>() -> Vec<Arg<'a>> {
+    simulation::args().into_iter().chain(checks::args()).collect()
+}
+
+pub struct SelfTestArgs {
+    pub simulation: Simulation,
+    pub workdir: Option<PathBuf>,
+    pub threads: usize,
+    pub tile: u64,
+    pub prefilter: prefilters::ByMismatches,
+    pub tolerance: f32,
+    pub max_fp_rate: f64,
+}
+
+impl SelfTestArgs {
+    pub fn new(args: &ArgMatches) -> Self {
+        // All values are validated by clap
+        let value = |key| args.value_of(key).unwrap();
+        let (contigs, length): (u32, u64) =
+            (value(simulation::CONTIGS).parse().unwrap(), value(simulation::CONTIG_LENGTH).parse().unwrap());
+        let contigs = (1..=contigs).map(|x| (format!("chr{}", x), length)).collect();
+
+        let simulation = Simulation::new(value(simulation::SEED).parse().unwrap(), contigs)
+            .with_reads(value(simulation::READ_LENGTH).parse().unwrap(), value(simulation::COVERAGE).parse().unwrap())
+            .with_error_rate(value(simulation::ERROR_RATE).parse().unwrap())
+            .with_spikes(value(simulation::SPIKES).parse().unwrap(), vec![0.1, 0.25, 0.5, 0.75, 0.9]);
+        let prefilter = prefilters::ByMismatches::new(
+            value(checks::MIN_MISMATCHES).parse().unwrap(),
+            value(checks::MIN_FREQ).parse().unwrap(),
+            10,
+            false,
+        );
+        Self {
+            simulation,
+            workdir: args.value_of(simulation::WORKDIR).map(PathBuf::from),
+            threads: value(checks::THREADS).parse().unwrap(),
+            tile: value(checks::TILE).parse().unwrap(),
+            prefilter,
+            tolerance: value(checks::TOLERANCE).parse().unwrap(),
+            max_fp_rate: value(checks::MAX_FP_RATE).parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{env, fs, process};
+
+use bio_types::genome::Interval;
+use clap::ArgMatches;
+use rayon::ThreadPoolBuilder;
+
+use crate::cli::selftest::args::SelfTestArgs;
+use crate::core::io;
+use crate::core::io::fasta::BasicFastaReader;
+use crate::core::pipeline::{run_rois, run_sites, ROIRunConfig, ReadsFilter, SiteRunConfig};
+use crate::core::refpred::{AutoRef, RefEngine};
+use crate::core::rpileup::ncounter::filters::{ByFlags, ByQuality, ByTags, MapQ255, Sequential, Tracked};
+use crate::core::stranding::deduce::StrandSpecificExperimentDesign;
+use crate::core::workload::{self, ROIWorkload, SiteWorkload};
+use crate::error::Error;
+
+const CREATE_THREAD_POOL_ERROR: &str = "Failed to initialize thread pool";
+const BINSIZE: u64 = 64_000;
+
+fn refnucpred(fasta: &Path) -> Result<Box<dyn RefEngine>, Error> {
+    let reader = BasicFastaReader::new(fasta.to_owned())?;
+    Ok(Box::new(AutoRef::new(20, 0.95, true, Box::new(reader))))
+}
+
+fn readfilter() -> ReadsFilter {
+    Tracked::new(Sequential::new(
+        Sequential::new(ByQuality::new(1, MapQ255::Keep, 20), ByFlags::new(0, 2820)),
+        ByTags::new(),
+    ))
+}
+
+fn verdict(passed: bool) -> &'static str {
+    if passed {
+        "PASS"
+    } else {
+        "FAIL"
+    }
+}
+
+fn selftest(args: &SelfTestArgs, workdir: &Path) -> Result<bool, Error> {
+    let started = Instant::now();
+    let data = args.simulation.run();
+    let (fasta, bam) = data.write(workdir)?;
+    let (contigs, reads) = (data.lengths(), data.reads.len() as f64);
+    println!(
+        "Simulated {} contigs ({} bp), {} reads and {} spikes in {:.2}s",
+        contigs.len(),
+        contigs.iter().map(|x| x.1).sum::<u64>(),
+        data.reads.len(),
+        data.spikes.len(),
+        started.elapsed().as_secs_f64()
+    );
+
+    // Sites
+    let started = Instant::now();
+    let intervals = io::hts::contigs(&[&bam])?;
+    let workload = SiteWorkload::from_intervals(intervals, BINSIZE, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let mut config = SiteRunConfig::new(vec![bam.clone()], workload, maxwsize, refnucpred(&fasta)?, readfilter());
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    config.prefilter = Some(args.prefilter);
+    let sites = run_sites(config)?;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let sites = data.check_sites(&sites, &args.prefilter, args.tolerance);
+    let sitesok = sites.passed(args.max_fp_rate);
+    println!(
+        "[{}] site: {}/{} detectable spikes recovered ({} spiked), max deviation {:.4}, \
+        mean deviation from the spiked frequency {:.4}, {} false positives / {} clean positions; \
+        {:.2}s ({:.0} reads/s)",
+        verdict(sitesok),
+        sites.recovered,
+        sites.detectable,
+        sites.spikes,
+        sites.maxdev,
+        sites.sampling,
+        sites.falsepos,
+        sites.clean,
+        elapsed,
+        reads / elapsed
+    );
+
+    // Tiled ROIs
+    let started = Instant::now();
+    let tiles = workload::roi::tiles(contigs, args.tile, args.tile);
+    let workload = ROIWorkload::from_bed(tiles, BINSIZE, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+    let mut config = ROIRunConfig::new(vec![bam], workload, maxwsize, refnucpred(&fasta)?, readfilter());
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    let rois = run_rois(config)?;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let rois = data.check_rois(&rois, args.tolerance);
+    println!(
+        "[{}] roi: {}/{} ROIs recovered ({} edited), max deviation {:.4}; {:.2}s ({:.0} reads/s)",
+        verdict(rois.passed()),
+        rois.recovered,
+        rois.rois,
+        rois.edited,
+        rois.maxdev,
+        elapsed,
+        reads / elapsed
+    );
+    Ok(sitesok && rois.passed())
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let args = SelfTestArgs::new(args);
+    let pool = ThreadPoolBuilder::new().num_threads(args.threads).build().expect(CREATE_THREAD_POOL_ERROR);
+
+    let (workdir, temporary): (PathBuf, bool) = match &args.workdir {
+        Some(workdir) => (workdir.clone(), false),
+        None => (env::temp_dir().join(format!("reat-selftest-{}", process::id())), true),
+    };
+    let result = pool.install(|| selftest(&args, &workdir));
+    if temporary {
+        // Failing to clean up a temporary directory doesn't affect the verdict
+        let _ = fs::remove_dir_all(&workdir);
+    }
+    match result? {
+        true => Ok(()),
+        false => Err(Error::Input("self-test failed".into())),
+    }
+}
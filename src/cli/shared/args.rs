@@ -1,16 +1,26 @@
-use std::fs::File;
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::Instant;
 
 use clap::Arg;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
-use rust_htslib::bam::Record;
 
+use crate::cli::shared::output::SaveTo;
+use crate::cli::shared::progress::ProgressMode;
 use crate::cli::shared::stranding::Stranding;
+use crate::core::annotation::GenomicFeatures;
+use crate::core::dna::Multimap;
 use crate::core::io::bed::BedRecord;
+use crate::core::io::contigs::{ContigLengths, ContigNames};
 use crate::core::io::fasta::BasicFastaReader;
-use crate::core::refpred::RefEngine;
-use crate::core::rpileup::ncounter::filters;
+use crate::core::pipeline::ReadsFilter;
+use crate::core::refpred::{RefEngine, RefNPolicy};
+use crate::core::rpileup::ncounter::filters::{ByTags, FilterStats};
+use crate::core::rpileup::stream::HTSStream;
+use crate::error::Error;
 
 use super::parse;
 use super::validate;
@@ -19,13 +29,36 @@ pub mod core {
     use super::*;
 
     pub const INPUT: &str = "input";
+    pub const INDEX: &str = "index";
     pub const REFERENCE: &str = "reference";
     pub const BINSIZE: &str = "binsize";
     pub const STRANDING: &str = "stranding";
     pub const THREADS: &str = "threads";
+    pub const HTS_THREADS: &str = "hts-threads";
+    pub const PREFETCH: &str = "prefetch";
+    pub const REF_CACHE: &str = "ref-cache";
+    pub const BUILD_INDEX: &str = "build-index";
+    pub const STREAM: &str = "stream";
     pub const SAVETO: &str = "saveto";
+    pub const SPLIT_BY_STRAND: &str = "split-by-strand";
+    pub const RUN_INFO: &str = "run-info";
+    pub const CHECKPOINT: &str = "checkpoint";
+    pub const DETERMINISTIC: &str = "deterministic";
     pub const NAME: &str = "name";
+    pub const INCLUDE_LIST: &str = "include";
     pub const EXCLUDE_LIST: &str = "exclude";
+    pub const KNOWN_SNV: &str = "known-snv";
+    pub const KNOWN_SNV_PASS: &str = "known-snv-pass";
+    pub const NO_SORT: &str = "no-sort";
+    pub const FIX_CONTIG_NAMES: &str = "fix-contig-names";
+    pub const ALLOW_EMPTY: &str = "allow-empty";
+    pub const NO_STRAND_DEPTH: &str = "no-strand-depth";
+    pub const NO_SCHEMA_LINE: &str = "no-schema-line";
+    pub const ORIENT_BY_TRSTRAND: &str = "orient-by-trstrand";
+    pub const TAG_OUTPUT: &str = "tag-output";
+    pub const ANNOTATE: &str = "annotate";
+    pub const PROGRESS: &str = "progress";
+    pub const PROGRESS_EVERY: &str = "progress-every";
 
     pub const SECTION_NAME: &str = "Core";
 
@@ -37,11 +70,23 @@ pub mod core {
                 .required(true)
                 .takes_value(true)
                 .multiple_values(true)
-                .validator(validate::path)
+                .validator(|x| validate::input(x).map(|_| ()))
                 .long_help(
-                    "Path to the input BAM file(s). \
+                    "Path to the input BAM/CRAM file(s). CRAM files are decoded with the --reference assembly. \
                     May contain a space-separated list of files, in which case they are treated as \
-                    technical replicates and pulled together",
+                    technical replicates and pulled together. \
+                    Files can be named as NAME=PATH, names are used only by the site mode --samples",
+                ),
+            Arg::new(INDEX)
+                .long(INDEX)
+                .takes_value(true)
+                .multiple_values(true)
+                .validator(validate::path)
+                .conflicts_with(STREAM)
+                .long_help(
+                    "Path to the index (BAI, CSI or CRAI) of each input file, in the same order as --input. \
+                    By default, indexes are looked up next to the input files: file.bam.bai, file.bai, \
+                    file.bam.csi, file.csi (BAI indexes take precedence).",
                 ),
             Arg::new(REFERENCE)
                 .short('r')
@@ -51,7 +96,8 @@ pub mod core {
                 .validator(validate::path)
                 .long_help(
                     "Indexed fasta file with a reference genome assembly. \
-                    Contig / chromosome names must match the entries in the BAM header(s)",
+                    Contig / chromosome names must match the entries in the BAM header(s). \
+                    Bgzipped fasta files are supported if both .fai and .gzi indices are present",
                 ),
             Arg::new(BINSIZE)
                 .long(BINSIZE)
@@ -68,14 +114,15 @@ pub mod core {
                 .required(true)
                 .takes_value(true)
                 .validator(validate::stranding)
-                .possible_values(&["u", "s", "f", "s/f", "f/s"])
+                .possible_values(&["u", "s", "f", "s/f", "f/s", "fr-secondstrand", "fr-firststrand"])
                 .long_help(
                     "Strand-specificity of the experiment, \
                     i.e. matching between the read strand and the gene strand. Use \"u\" for unstranded experiments; \
                     other available options based on the RSeQC nomenclature(see infer_experiment.py docs): \
                     same:\"s\" (++,--), flip:\"f\" (+-,-+), \
-                    same read1/flip read2:\"s/f\" (1++,1--/2+-,2-+), \
-                    flip read1/same read2:\"f/s\" (1+-,1-+/2++,2--)",
+                    same read1/flip read2:\"s/f\" or \"fr-secondstrand\" (1++,1--/2+-,2-+), \
+                    flip read1/same read2:\"f/s\" or \"fr-firststrand\" (1+-,1-+/2++,2--, e.g. dUTP). \
+                    For paired-end designs, unpaired reads are treated as read1",
                 ),
             Arg::new(NAME).short('n').long(NAME).takes_value(true).default_value("NA").long_help("Name of the run."),
             Arg::new(SAVETO)
@@ -84,7 +131,34 @@ pub mod core {
                 .takes_value(true)
                 .validator(validate::writable)
                 .default_value("/dev/stdout")
-                .long_help("Path to the output tsv file. By default, the results are printed to stdout"),
+                .long_help(
+                    "Path to the output tsv file. By default, the results are printed to stdout. \
+                    Paths ending with .gz or .bgz are gzip- or bgzip-compressed on the fly",
+                ),
+            Arg::new(SPLIT_BY_STRAND).long(SPLIT_BY_STRAND).takes_value(false).long_help(
+                "Save results for each transcription strand to a separate file. \
+                The output path is treated as a prefix, e.g. out.tsv.gz results in out.forward.tsv.gz, \
+                out.reverse.tsv.gz and out.unknown.tsv.gz. Files are created even if there are no results for a strand",
+            ),
+            Arg::new(RUN_INFO).long(RUN_INFO).takes_value(true).validator(validate::writable).long_help(
+                "Path to the output JSON file with the run metadata: resolved parameters, input files, \
+                processed reads, duration and REAT version. By default, it is saved next to the output file \
+                with the \".json\" suffix (if the output is a regular file).",
+            ),
+            Arg::new(CHECKPOINT).long(CHECKPOINT).takes_value(true).conflicts_with(SPLIT_BY_STRAND).long_help(
+                "Directory to save the results of each contig as soon as it is finished. \
+                If the run is interrupted (e.g. OOM or node preemption), rerunning the same command skips \
+                finished contigs and reuses their results; the final output is assembled once all contigs are done. \
+                Resuming with different parameters or input files is refused. Run info counters cover only \
+                contigs processed by the last invocation",
+            ),
+            Arg::new(DETERMINISTIC).long(DETERMINISTIC).takes_value(false).conflicts_with(SPLIT_BY_STRAND).long_help(
+                "Guarantee byte-identical result tables and stats across runs with the same inputs and parameters. \
+                Results are always collected and written by a single writer in the genome order, \
+                and stats are accumulated in the same order regardless of --threads. \
+                This flag forces a single output file; debug builds also verify upfront that results \
+                for a few bins don't depend on the threads scheduling.",
+            ),
             Arg::new(THREADS)
                 .short('t')
                 .long(THREADS)
@@ -92,11 +166,125 @@ pub mod core {
                 .validator(validate::numeric(1, usize::MAX))
                 .default_value("1")
                 .long_help("Maximum number of threads to spawn at once"),
+            Arg::new(HTS_THREADS)
+                .long(HTS_THREADS)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, 1024))
+                .default_value("0")
+                .long_help(
+                    "Number of threads in the htslib pool shared by all BAM readers to decompress BGZF blocks. \
+                    Decompression then runs in parallel independently of the --threads workers. \
+                    0 - no pool, each reader decompresses its data in the calling thread",
+                ),
+            Arg::new(PREFETCH).long(PREFETCH).takes_value(false).long_help(
+                "Decode reads in a helper thread while the previously decoded chunk is being counted. \
+                Each worker gets its own helper, at most one chunk of reads is queued ahead. \
+                Helps when BAM decoding is the bottleneck (e.g. slow storage), results are not affected.",
+            ),
+            Arg::new(REF_CACHE)
+                .long(REF_CACHE)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, 100_000))
+                .default_value("4")
+                .long_help(
+                    "Size of the reference sequence cache in megabases, each thread has its own cache. \
+                    The reference is read in 1Mb blocks that are reused by overlapping and adjacent bins/ROIs. \
+                    0 - no cache, the reference is re-read for each bin. Results are not affected.",
+                ),
+            Arg::new(BUILD_INDEX).long(BUILD_INDEX).takes_value(false).long_help(
+                "Index input files that have no index (same as samtools index) before the processing. \
+                Otherwise, the run is aborted right away if any index is missing.",
+            ),
+            Arg::new(STREAM)
+                .long(STREAM)
+                .takes_value(false)
+                .conflicts_with_all(&[INCLUDE_LIST, PREFETCH, BUILD_INDEX])
+                .long_help(
+                    "Read coordinate-sorted BAM/SAM input(s) sequentially, without an index. \
+                    Use \"-\" as the input path to read from stdin, e.g. samtools view -u ... | reat site -i - --stream. \
+                    Genome bins are processed one by one in the BAM header order, which is also the output order of \
+                    contigs; --hts-threads still speeds up the decompression. Available only in the site mode. \
+                    Random-access options (--include, --region, --error-model) are not supported.",
+                ),
+            Arg::new(INCLUDE_LIST)
+                .long(INCLUDE_LIST)
+                .takes_value(true)
+                .validator(validate::path)
+                .long_help("Path to a BED file with regions to restrict the analysis to"),
             Arg::new(EXCLUDE_LIST)
                 .long(EXCLUDE_LIST)
                 .takes_value(true)
                 .validator(validate::path)
                 .long_help("Path to a BED file with regions to exclude from the analysis"),
+            Arg::new(KNOWN_SNV).long(KNOWN_SNV).takes_value(true).validator(validate::path).long_help(
+                "Path to a VCF/BCF file (optionally bgzipped + indexed) with known SNVs (e.g. dbSNP). \
+                Biallelic SNV loci are excluded from the analysis, multi-allelic records and indels are skipped",
+            ),
+            Arg::new(KNOWN_SNV_PASS)
+                .long(KNOWN_SNV_PASS)
+                .takes_value(false)
+                .requires(KNOWN_SNV)
+                .long_help("Use only known SNVs that passed all filters (FILTER=PASS)"),
+            Arg::new(NO_SORT).long(NO_SORT).takes_value(false).long_help(
+                "Don't sort contigs in the output by their order in the reference FASTA index (.fai), \
+                use the alphabetical order instead. Items within each contig are always sorted by coordinates",
+            ),
+            Arg::new(FIX_CONTIG_NAMES).long(FIX_CONTIG_NAMES).takes_value(false).long_help(
+                "Resolve mismatched contig names between the BAM files, the reference FASTA and BED files \
+                (e.g. \"chr1\" vs \"1\") by adding/stripping the \"chr\" prefix. \
+                Contigs are always reported as named in the BAM files",
+            ),
+            Arg::new(ALLOW_EMPTY).long(ALLOW_EMPTY).takes_value(false).long_help(
+                "Finish with a warning if no reads were counted for any genome bin/ROI. By default, such runs \
+                fail with the exit code 3 and the most likely reason (contigs without reads, stale index, \
+                or genuinely empty inputs), since empty outputs usually mean that reads and regions come from \
+                different assemblies.",
+            ),
+            Arg::new(NO_STRAND_DEPTH).long(NO_STRAND_DEPTH).takes_value(false).long_help(
+                "Don't track the number of forward/reverse strand alignments supporting each position \
+                (fwd_depth, rev_depth and strand_bias columns). Saves memory for runs over huge loci \
+                and keeps the column layout of older versions.",
+            ),
+            Arg::new(NO_SCHEMA_LINE).long(NO_SCHEMA_LINE).takes_value(false).long_help(
+                "Don't write the \"# reat-schema: N\" comment line at the top of the main table. \
+                N is bumped whenever the output columns change, see `reat schema` for the columns of each version. \
+                Use it for strict CSV parsers that don't support comments. Auxiliary tables (EI, stats, coverage \
+                summary, substitution rates) never have this line.",
+            ),
+            Arg::new(ORIENT_BY_TRSTRAND).long(ORIENT_BY_TRSTRAND).takes_value(false).long_help(
+                "Report nucleotides of reverse strand items relative to the transcription strand, \
+                i.e. complement homozygous/mismatches counts of ROIs and refnuc, prednuc & nucleotide counts of sites. \
+                Thus, the A->G column always means A-to-G on the transcribed strand. \
+                By default, all nucleotides are reported relative to the reference (forward) strand.",
+            ),
+            Arg::new(TAG_OUTPUT).long(TAG_OUTPUT).takes_value(false).long_help(
+                "Prepend the \"sample\" column with the run name (--name) to every row of the main table. \
+                Makes tables of different runs safe to concatenate. With --columns, the column is prepended \
+                unless it's listed explicitly.",
+            ),
+            Arg::new(ANNOTATE).long(ANNOTATE).takes_value(true).validator(validate::path).long_help(
+                "Genome annotation in the GTF or GFF3 format (optionally gzipped) to label the reported items. \
+                Adds two columns: \"genes\" - comma-separated names of all overlapping genes (sorted alphabetically) and \
+                \"feature\" - the highest-priority overlapping feature (CDS > UTR > exon > intron > intergenic). \
+                Features are matched regardless of their strand, contig names must match the BAM files.",
+            ),
+            Arg::new(PROGRESS)
+                .long(PROGRESS)
+                .takes_value(true)
+                .possible_values(&["bars", "json", "none"])
+                .default_value("bars")
+                .long_help(
+                    "How to report the processing progress: interactive progress bars (bars), \
+                    single-line JSON records written to stderr (json), or nothing at all (none). \
+                    Each JSON record contains the processing phase, the number of processed/total genome bins, \
+                    the number of counted reads, and the elapsed time in seconds",
+                ),
+            Arg::new(PROGRESS_EVERY)
+                .long(PROGRESS_EVERY)
+                .takes_value(true)
+                .validator(validate::numeric(1u64, u64::MAX))
+                .default_value("100")
+                .long_help("Write a JSON progress record after every N processed genome bins (--progress json)"),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -106,12 +294,21 @@ pub mod reads_filtering {
     use super::*;
 
     pub const MAPQ: &str = "mapq";
-    pub const NO_MAPQ_255: &str = "no-mapq-255";
+    pub const MAPQ_255: &str = "mapq-255";
     pub const INCLUDE_FLAGS: &str = "in-flags";
     pub const EXCLUDE_FLAGS: &str = "ex-flags";
     pub const PHREAD: &str = "phread";
     pub const TRIM5: &str = "trim5";
     pub const TRIM3: &str = "trim3";
+    pub const NO_OVERLAP_DEDUP: &str = "no-overlap-dedup";
+    pub const UMI_TAG: &str = "umi-tag";
+    pub const MULTIMAP: &str = "multimap";
+    pub const MAX_READ_CONTRIBUTION: &str = "max-read-contribution";
+    pub const MAX_COVERAGE: &str = "max-coverage";
+    pub const READ_GROUP: &str = "read-group";
+    pub const CB_WHITELIST: &str = "cb-whitelist";
+    pub const CB_TAG: &str = "cb-tag";
+    pub const DUMP_READS: &str = "dump-reads";
 
     pub const SECTION_NAME: &str = "Reads hooks";
 
@@ -124,16 +321,22 @@ pub mod reads_filtering {
                 .default_value("1")
                 .long_help(
                     "Count only reads with mapq ≥ threshold. \
-                    Note that reads with mapq = 255 are NOT skipped by default\
-                    (mapq 255 means \"not available\" according to the SAM spec)",
+                    Reads with mapq = 255 are handled separately, see --mapq-255",
+                ),
+            Arg::new(MAPQ_255)
+                .long(MAPQ_255)
+                .takes_value(true)
+                .possible_values(&["keep", "drop"])
+                .default_value("keep")
+                .long_help(
+                    "What to do with reads with mapq = 255 (\"not available\" according to the SAM spec) \
+                    regardless of the --mapq threshold. \
+                    Note, some aligners don't fully conform to the SAM specification \
+                    (e.g., STAR with default parameters use mapq=255 for unique alignments)",
                 ),
-            Arg::new(NO_MAPQ_255).long(NO_MAPQ_255).takes_value(true).takes_value(false).long_help(
-                "Skip reads with mapq=255. \
-                Note, some aligners don't fully conform to the SAM specification \
-                (e.g., STAR with default parameters use mapq=255 for unique alignments)",
-            ),
             Arg::new(INCLUDE_FLAGS)
                 .long(INCLUDE_FLAGS)
+                .alias("require-flags")
                 .takes_value(true)
                 .validator(validate::numeric(0u16, 4095u16))
                 .default_value("0")
@@ -144,6 +347,7 @@ pub mod reads_filtering {
                 ),
             Arg::new(EXCLUDE_FLAGS)
                 .long(EXCLUDE_FLAGS)
+                .alias("exclude-flags")
                 .takes_value(true)
                 .validator(validate::numeric(0u16, 4095u16))
                 .default_value("2820")
@@ -155,6 +359,7 @@ pub mod reads_filtering {
                 ),
             Arg::new(PHREAD)
                 .long(PHREAD)
+                .alias("min-phred")
                 .takes_value(true)
                 .validator(validate::numeric(0u8, 255u8))
                 .default_value("20")
@@ -170,7 +375,10 @@ pub mod reads_filtering {
                 .validator(validate::numeric(0u16, 65535u16))
                 .default_value("0")
                 .long_help(
-                    "Trim bases from the 5’ (left) end of each read before processing. \
+                    "Trim bases from the 5’ end of each read before processing, i.e. the rightmost bases of reads \
+                    aligned to the reverse strand. Trimming is done in the read coordinates: soft-clipped and \
+                    inserted bases count towards the trimmed length, introns & deletions don't. \
+                    Trimmed bases contribute neither to the coverage nor to the mismatches. \
                     In particular, one can skip the first ~12 bases with non-random composition due to priming biases, \
                     what is a common anomaly in short-read RNA-seq experiments.",
                 ),
@@ -181,10 +389,81 @@ pub mod reads_filtering {
                 .validator(validate::numeric(0u16, 65535u16))
                 .default_value("0")
                 .long_help(
-                    "Trim bases from the 3’ (right) end of each read before processing. \
+                    "Trim bases from the 3’ end of each read before processing, in the read orientation (see --trim5). \
                     Can be used to hard skip low-quality bases at the end of filters if no trimming was done \
                     before / during the alignment.",
                 ),
+            Arg::new(NO_OVERLAP_DEDUP).long(NO_OVERLAP_DEDUP).takes_value(false).long_help(
+                "Count bases covered by both overlapping mates of a read pair twice. \
+                By default, such bases are counted once, using the mate with the highest base quality",
+            ),
+            Arg::new(UMI_TAG).long(UMI_TAG).takes_value(true).validator(validate::bamtag).long_help(
+                "BAM tag with the UMI sequence (e.g. UB). If specified, reads sharing the same start position, \
+                strand and UMI within a genome bin are counted once, using the read with the highest sum of \
+                base qualities. Reads without the tag are counted as usual",
+            ),
+            Arg::new(MULTIMAP)
+                .long(MULTIMAP)
+                .takes_value(true)
+                .possible_values(&["full", "fractional"])
+                .default_value("full")
+                .long_help(
+                    "How to count reads aligned to several loci (NH tag > 1). \
+                    full - each alignment is counted as a whole read; \
+                    fractional - each alignment contributes 1/NH, reads without the NH tag are counted fully. \
+                    Note, secondary alignments are skipped by default, see --exclude-flags",
+                ),
+            Arg::new(MAX_READ_CONTRIBUTION)
+                .long(MAX_READ_CONTRIBUTION)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, u32::MAX))
+                .long_help(
+                    "Max number of mismatches (relative to the reference assembly) a single read may contribute \
+                    within one genome bin. The rest of the read's bases in the bin are ignored once the limit is hit. \
+                    Useful to damp systematic errors of noisy long reads (e.g. ONT) in low-coverage regions. \
+                    Disabled by default",
+                ),
+            Arg::new(MAX_COVERAGE)
+                .long(MAX_COVERAGE)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, u32::MAX))
+                .long_help(
+                    "Skip reads starting at positions already covered by the given number of counted reads \
+                    (similar to samtools mpileup --max-depth, but for all input files together). \
+                    Bounds the runtime & memory in ultra-deep regions (e.g. mitochondria, rRNA). \
+                    Reads are taken in the BAM order, so results are reproducible. Disabled by default",
+                ),
+            Arg::new(READ_GROUP)
+                .long(READ_GROUP)
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .long_help(
+                    "Comma-separated list of read group IDs (RG tag) to count, e.g. samples multiplexed in a single \
+                    BAM file. Reads without the RG tag are skipped. Disabled by default",
+                ),
+            Arg::new(CB_WHITELIST).long(CB_WHITELIST).takes_value(true).validator(validate::path).long_help(
+                "File with cell barcodes to count, one per line (only the first column is used, e.g. 10x \
+                barcodes.tsv.gz of a cell cluster). Reads without the barcode tag (see --cb-tag) are skipped. \
+                Disabled by default",
+            ),
+            Arg::new(CB_TAG)
+                .long(CB_TAG)
+                .takes_value(true)
+                .requires(CB_WHITELIST)
+                .validator(validate::bamtag)
+                .default_value("CB")
+                .long_help("BAM tag with the cell barcode for the --cb-whitelist"),
+            Arg::new(DUMP_READS)
+                .long(DUMP_READS)
+                .takes_value(true)
+                .conflicts_with(super::core::STREAM)
+                .long_help(
+                    "Debug mode: save reads accepted and rejected by the filters above to PREFIX.accepted.bam and \
+                    PREFIX.rejected.bam (the header of the first input file is used, all inputs must share it). \
+                    Reads are checked and written once per overlapping genome bin, so the files match the reported \
+                    filter counters. Use only with small regions (e.g. --include), writing is serialized between threads",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -197,6 +476,8 @@ pub mod autoref {
     pub const MIN_FREQ: &str = "ref-min-freq";
     pub const HYPEREDITING: &str = "hyperedit";
     pub const VCF: &str = "vcf";
+    pub const NO_REF_SOURCE: &str = "no-ref-source";
+    pub const REF_N_POLICY: &str = "ref-n-policy";
 
     pub const SECTION_NAME: &str = "Autoref";
 
@@ -231,6 +512,55 @@ pub mod autoref {
                 "Turn on the \"hyperediting\" mode, i.e. do not correct(replace) A with G and T with C. \
                     This will ensure that potentially hyper-editable sites are not accidentally lost",
             ),
+            Arg::new(NO_REF_SOURCE).long(NO_REF_SOURCE).takes_value(false).long_help(
+                "Don't report whether the reference nucleotide was corrected (\"ref_source\" column for sites, \
+                \"corrected\" column for ROIs). Useful to keep the column layout of older versions.",
+            ),
+            Arg::new(REF_N_POLICY)
+                .long(REF_N_POLICY)
+                .takes_value(true)
+                .possible_values(&["mismatch", "skip", "autoref"])
+                .default_value("mismatch")
+                .long_help(
+                    "How to handle unknown(N) nucleotides in the reference assembly. \
+                    \"mismatch\": keep the predicted reference as is, i.e. all sequenced nucleotides are mismatches \
+                    for N sites (default). \
+                    \"skip\": never report positions with N in the assembly and count them as masked for ROIs. \
+                    \"autoref\": infer N from the most common sequenced nucleotide using the --ref-min-cov and \
+                    --ref-min-freq thresholds (even if --vcf is used)",
+                ),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
+pub mod refinference {
+    use super::*;
+
+    pub const HET_MIN_FREQ: &str = "ref-het-min-freq";
+    pub const HET_MIN_COUNT: &str = "ref-het-min-count";
+
+    pub const SECTION_NAME: &str = "Reference inference";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(HET_MIN_FREQ)
+                .long(HET_MIN_FREQ)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .long_help(
+                    "Call a heterozygous site if the second most common nucleotide frequency ≥ cutoff. \
+                    Heterozygous sites are not called by default. Ignored when the reference is corrected by a VCF file.",
+                ),
+            Arg::new(HET_MIN_COUNT)
+                .long(HET_MIN_COUNT)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, u32::MAX))
+                .default_value("1")
+                .long_help(
+                    "Minimum count of the second most common nucleotide required to call a heterozygous site. \
+                    Used only together with the heterozygous frequency cutoff.",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -241,15 +571,18 @@ pub mod stranding {
 
     pub const MIN_MISMATCHES: &str = "str-min-mismatches";
     pub const MIN_FREQ: &str = "str-min-freq";
+    pub const EDITING: &str = "str-editing";
+    pub const ALGO: &str = "stranding-algo";
     pub const ANNOTATION: &str = "annotation";
     pub const EXTEND_UTR3: &str = "utr3ext";
+    pub const FEATURE_FLANK: &str = "stranding-feature-flank";
 
     pub const SECTION_NAME: &str = "Stranding";
 
     pub fn args<'a>() -> Vec<Arg<'a>> {
         let args = vec![
             Arg::new(ANNOTATION).long(ANNOTATION).takes_value(true).validator(validate::path).long_help(
-                "Genome annotation in the GFF3 format. \
+                "Genome annotation in the GFF3 or GTF format. \
                     Genomic features (exons and genes) are used only to inference site/ROI strand based on the most \
                     likely direction of transcription (see the GitHub documentation for details). \
                     It is recommended to provide genome annotation for unstranded libraries, \
@@ -264,6 +597,43 @@ pub mod stranding {
                     "Extend 3`UTRs of the annotated genes by the given value and use these pseudo \
                     3`UTRs to predict strands in intergenic regions. A good default is ~3000-6000bp.",
                 ),
+            Arg::new(FEATURE_FLANK)
+                .long(FEATURE_FLANK)
+                .takes_value(true)
+                .requires(ANNOTATION)
+                .validator(validate::numeric(0, u32::MAX))
+                .long_help(
+                    "Sites/ROIs without overlapping genomic features adopt the strand of the nearest gene \
+                    within the given distance (bp) on either side. Equidistant genes on opposite strands \
+                    leave the strand unknown. 0 (default) disables the search.",
+                ),
+            Arg::new(EDITING)
+                .long(EDITING)
+                .takes_value(true)
+                .multiple_values(true)
+                .possible_values(&["a2i", "c2u"])
+                .default_value("a2i")
+                .long_help(
+                    "Editing types used to predict strand, in the order of priority. \
+                    \"a2i\" stands for A->I editing (A->G on the forward / T->C on the reverse strand), \
+                    \"c2u\" stands for C->U editing (C->T on the forward / G->A on the reverse strand). \
+                    Thresholds below are shared between all editing types.",
+                ),
+            Arg::new(ALGO)
+                .long(ALGO)
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .validator(validate::stranding_algo)
+                .long_help(
+                    "Comma-separated list of strand prediction algorithms, in the order of priority, e.g. \
+                    \"features,editing\" or \"editing:8:0.05,features\". Later algorithms handle only items left \
+                    unstranded by the previous ones. Supported algorithms: \"features\" (requires the genome \
+                    annotation), \"editing\"/\"a2i\" and \"c2u\". Editing-based algorithms accept optional \
+                    thresholds as name:min mismatches:min freq, thresholds below are used otherwise. \
+                    Overrides the editing types list. By default, genomic features (if any) go first, \
+                    followed by the editing types.",
+                ),
             Arg::new(MIN_MISMATCHES)
                 .long(MIN_MISMATCHES)
                 .takes_value(true)
@@ -294,43 +664,159 @@ pub fn all<'a>() -> Vec<Arg<'a>> {
         .chain(reads_filtering::args().into_iter())
         .chain(stranding::args().into_iter())
         .chain(autoref::args().into_iter())
+        .chain(refinference::args().into_iter())
         .collect()
 }
 
-type ReadsFilter = filters::Sequential<Record, filters::ByQuality, filters::ByFlags>;
-
 pub struct CoreArgs {
     pub name: String,
     pub threads: usize,
+    pub hts_threads: u32,
+    pub prefetch: bool,
     pub trim5: u16,
     pub trim3: u16,
+    pub overlaps_dedup: bool,
+    pub umi: Option<[u8; 2]>,
+    pub multimap: Multimap,
+    pub max_read_contribution: Option<u32>,
+    pub max_coverage: Option<u32>,
+    pub refsource: bool,
+    pub strand_depth: bool,
+    // Write the schema version comment at the top of the main table
+    pub schema_line: bool,
+    // Complement nucleotides of reverse strand items in the output
+    pub orient: bool,
+    // Prepend the run name to each row of the main table
+    pub tag_output: bool,
+    // Annotation to label reported items, shared by all output writers
+    pub annotate: Option<GenomicFeatures>,
     pub bamfiles: Vec<PathBuf>,
+    // Index of each input file, None for the streamed inputs
+    pub indexes: Option<Vec<PathBuf>>,
+    // Sequential reader of unindexed inputs (--stream)
+    pub stream: Option<HTSStream>,
+    pub bamcontigs: HashSet<String>,
+    // Contigs with aligned reads according to the BAI indexes, None if unknown
+    pub aligned: Option<HashSet<String>>,
+    // Some indexes are older than the indexed files
+    pub stale_index: bool,
+    // Runs without any counted reads are not an error
+    pub allow_empty: bool,
+    // Contig lengths from the BAM headers, the same in the reference assembly
+    pub contiglens: ContigLengths,
+    // Indexed FASTA with the reference assembly
+    pub reference: PathBuf,
+    // BAM contigs => reference contigs
+    pub refnames: ContigNames,
     pub refnucpred: Box<dyn RefEngine>,
+    // Reference bases read from the FASTA file by all threads
+    pub refbases: Arc<AtomicU64>,
+    pub refn: RefNPolicy,
     pub readfilter: ReadsFilter,
+    // Shares counters with the read groups & cell barcodes filter in the readfilter
+    pub tags: ByTags,
+    // Accepted & rejected reads counted by the readfilter
+    pub filtered: Arc<FilterStats>,
     pub stranding: Stranding,
+    pub included: Option<Vec<BedRecord>>,
     pub excluded: Option<Vec<BedRecord>>,
-    pub saveto: csv::Writer<File>,
+    pub saveto: SaveTo,
+    pub runinfo: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub deterministic: bool,
+    // Contigs order for the output, None => alphabetical
+    pub contigs: Option<Vec<String>>,
+    pub progress: ProgressMode,
+    pub started: Instant,
 }
 
 impl CoreArgs {
-    pub fn new(args: &ArgMatches, factory: impl Fn() -> ProgressBar) -> Self {
+    pub fn new(args: &ArgMatches, factory: impl Fn() -> ProgressBar) -> Result<Self, Error> {
+        let started = Instant::now();
         let name = parse::name(factory(), args);
         let threads = parse::threads(factory(), args);
+        let hts_threads = parse::hts_threads(factory(), args);
         let (trim5, trim3) = parse::trimming(factory(), args);
 
+        let multimap = parse::multimap(factory(), args);
+        let tags = parse::tags(factory(), args)?;
+        let bamfiles = parse::bamfiles(factory(), args);
         let reference = parse::reference(factory(), args);
-        let refreader = BasicFastaReader::new(reference);
-        Self {
+        let stream = parse::stream(factory(), args, &bamfiles, &reference)?;
+        let (indexes, stale_index) = match stream {
+            None => {
+                let (indexes, stale) = parse::indexes(factory(), args, &bamfiles, hts_threads)?;
+                (Some(indexes), stale)
+            }
+            Some(_) => (None, false),
+        };
+        // Contig names are validated upfront, BAM names are used everywhere else
+        let contiglens = parse::contig_lengths(&bamfiles, stream.as_ref())?;
+        let bamcontigs = contiglens.names();
+        let readfilter = parse::readfilter(factory(), args, tags.clone(), &bamfiles)?;
+
+        let aligned = parse::aligned_contigs(&bamfiles, indexes.as_deref(), &reference);
+        let refnames = parse::refnames(factory(), args, &contiglens, aligned.as_ref(), &reference)?;
+        parse::cram(factory(), &bamfiles, &refnames)?;
+        let contigs = parse::contigs(factory(), args, &reference, &refnames)?;
+        let refreader = BasicFastaReader::new(reference.clone())?
+            .with_contig_names(refnames.clone())
+            .with_cache(parse::ref_cache(factory(), args));
+        let refbases = refreader.fetched();
+
+        // Known SNVs are excluded along with other regions
+        let excluded =
+            match (parse::excluded(factory(), args, &bamcontigs)?, parse::known_snv(factory(), args, &bamcontigs)) {
+                (Some(mut excluded), Some(snv)) => {
+                    excluded.extend(snv);
+                    Some(excluded)
+                }
+                (excluded, snv) => excluded.or(snv),
+            };
+        Ok(Self {
             name,
             threads,
+            hts_threads,
+            prefetch: args.is_present(core::PREFETCH),
             trim5,
             trim3,
-            bamfiles: parse::bamfiles(factory(), args),
-            refnucpred: parse::refnucpred(factory(), args, Box::new(refreader)),
-            readfilter: parse::readfilter(factory(), args),
+            overlaps_dedup: parse::overlaps_dedup(factory(), args),
+            umi: parse::umi(factory(), args),
+            multimap,
+            max_read_contribution: parse::max_read_contribution(factory(), args),
+            max_coverage: parse::max_coverage(factory(), args),
+            refsource: !args.is_present(autoref::NO_REF_SOURCE),
+            strand_depth: !args.is_present(core::NO_STRAND_DEPTH),
+            schema_line: !args.is_present(core::NO_SCHEMA_LINE),
+            orient: args.is_present(core::ORIENT_BY_TRSTRAND),
+            tag_output: args.is_present(core::TAG_OUTPUT),
+            annotate: parse::annotate(factory(), args)?,
+            bamfiles,
+            indexes,
+            stream,
+            bamcontigs,
+            aligned,
+            stale_index,
+            allow_empty: args.is_present(core::ALLOW_EMPTY),
+            contiglens,
+            reference,
+            refnames,
+            refnucpred: parse::refnucpred(factory(), args, Box::new(refreader), multimap.scale()),
+            refbases,
+            refn: parse::refn(args),
+            filtered: readfilter.stats(),
+            readfilter,
+            tags,
             stranding: parse::stranding(factory(), args),
-            excluded: parse::excluded(factory(), args),
-            saveto: parse::saveto(factory(), args),
-        }
+            included: parse::included(factory(), args, &bamcontigs)?,
+            excluded,
+            saveto: parse::saveto(factory(), args)?,
+            runinfo: parse::runinfo(factory(), args),
+            checkpoint: parse::checkpoint(factory(), args),
+            deterministic: args.is_present(core::DETERMINISTIC),
+            contigs,
+            progress: ProgressMode::new(args),
+            started,
+        })
     }
 }
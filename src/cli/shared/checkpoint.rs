@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::io::utils::{self, OutputWriter};
+use crate::error::Error;
+
+// Dropped only after the contig results are safely stored in the corresponding part file
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct Marker {
+    contig: String,
+    fingerprint: String,
+    // Size of the part file in bytes
+    size: u64,
+}
+
+// Per-contig results of a (possibly interrupted) run.
+// Contigs are numbered in the order of completion, which is the same for the same parameters =>
+// parts from previous runs are simply concatenated with the new ones
+pub struct Checkpoint {
+    dir: PathBuf,
+    fingerprint: String,
+    finished: Vec<String>,
+}
+
+impl Checkpoint {
+    pub fn new(dir: PathBuf, fingerprint: String) -> Result<Self, Error> {
+        fs::create_dir_all(&dir).map_err(|x| Error::io(&dir, x))?;
+
+        let mut finished = Vec::new();
+        while let Some(marker) = Self::marker(&dir, finished.len()) {
+            if marker.fingerprint != fingerprint {
+                return Err(Error::Input(format!(
+                    "Checkpoint {} was created with different parameters or input files, refusing to resume. \
+                    Remove it to start from scratch.",
+                    dir.display()
+                )));
+            }
+            // Partially written / damaged part => this and all the following contigs must be processed again
+            match fs::metadata(part_path(&dir, finished.len())) {
+                Ok(x) if x.len() == marker.size => finished.push(marker.contig),
+                _ => break,
+            }
+        }
+        Self::discard(&dir, finished.len())?;
+        Ok(Self { dir, fingerprint, finished })
+    }
+
+    pub fn finished(&self) -> HashSet<String> {
+        self.finished.iter().cloned().collect()
+    }
+
+    // Writer for the results of the next contig
+    pub fn part(&self) -> Result<OutputWriter, Error> {
+        utils::write_compressed(&part_path(&self.dir, self.finished.len()), false)
+    }
+
+    pub fn commit(&mut self, contig: &str) -> Result<(), Error> {
+        let index = self.finished.len();
+        let part = part_path(&self.dir, index);
+        if !part.exists() {
+            File::create(&part).map_err(|x| Error::io(&part, x))?;
+        }
+        let size = fs::metadata(&part).map_err(|x| Error::io(&part, x))?.len();
+        let marker = Marker { contig: contig.to_owned(), fingerprint: self.fingerprint.clone(), size };
+
+        // Write & rename => markers are never partially written
+        let (tmp, path) = (self.dir.join(format!("{:06}.json.tmp", index)), marker_path(&self.dir, index));
+        let file = File::create(&tmp).map_err(|x| Error::io(&tmp, x))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &marker)
+            .map_err(|x| x.into())
+            .and_then(|_| writer.flush())
+            .and_then(|_| writer.get_ref().sync_all())
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|x: std::io::Error| Error::io(&path, x))?;
+        self.finished.push(marker.contig);
+        Ok(())
+    }
+
+    // Part files of all finished contigs in the order of completion
+    pub fn parts(&self) -> Vec<PathBuf> {
+        (0..self.finished.len()).map(|x| part_path(&self.dir, x)).collect()
+    }
+
+    fn marker(dir: &Path, index: usize) -> Option<Marker> {
+        let file = File::open(marker_path(dir, index)).ok()?;
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    // Remove leftovers of an interrupted run starting from the given contig index
+    fn discard(dir: &Path, from: usize) -> Result<(), Error> {
+        let entries = fs::read_dir(dir).map_err(|x| Error::io(dir, x))?;
+        for path in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+            let index = path.file_name().and_then(|x| x.to_str()).and_then(|x| x.split('.').next()?.parse().ok());
+            if matches!(index, Some(index) if index >= from) {
+                fs::remove_file(&path).map_err(|x| Error::io(&path, x))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn marker_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:06}.json", index))
+}
+
+fn part_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{:06}.part", index))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::core::io::utils::FinishWrite;
+
+    use super::*;
+
+    fn finish(checkpoint: &mut Checkpoint, contig: &str, content: &[u8]) {
+        if !content.is_empty() {
+            let mut part = checkpoint.part().unwrap();
+            part.write_all(content).unwrap();
+            part.finish().unwrap();
+        }
+        checkpoint.commit(contig).unwrap();
+    }
+
+    #[test]
+    fn resume() {
+        let tmp = TempDir::new().unwrap();
+        let mut checkpoint = Checkpoint::new(tmp.path().to_owned(), "abc".into()).unwrap();
+        assert!(checkpoint.finished().is_empty());
+        finish(&mut checkpoint, "1", b"first\n");
+        finish(&mut checkpoint, "MT", b"");
+        finish(&mut checkpoint, "X", b"third\n");
+
+        // Damaged part => the contig must be processed again
+        fs::write(part_path(tmp.path(), 2), b"thi").unwrap();
+        // Partially written part without a marker
+        fs::write(part_path(tmp.path(), 3), b"fourth").unwrap();
+
+        let mut checkpoint = Checkpoint::new(tmp.path().to_owned(), "abc".into()).unwrap();
+        assert_eq!(checkpoint.finished(), HashSet::from(["1".to_owned(), "MT".to_owned()]));
+        assert!(!part_path(tmp.path(), 3).exists());
+
+        finish(&mut checkpoint, "X", b"third\n");
+        let written: Vec<String> = checkpoint.parts().iter().map(|x| fs::read_to_string(x).unwrap()).collect();
+        assert_eq!(written, ["first\n", "", "third\n"]);
+    }
+
+    #[test]
+    fn different_parameters() {
+        let tmp = TempDir::new().unwrap();
+        let mut checkpoint = Checkpoint::new(tmp.path().to_owned(), "abc".into()).unwrap();
+        finish(&mut checkpoint, "1", b"first\n");
+        assert!(Checkpoint::new(tmp.path().to_owned(), "abd".into()).is_err());
+    }
+}
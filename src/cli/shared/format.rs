@@ -0,0 +1,50 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::core::io::table::{Schema, TableFormat};
+
+#[derive(Eq, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Tsv,
+    MPileup,
+    JsonLines,
+    Parquet,
+}
+
+impl OutputFormat {
+    // Schema is required only by the Parquet tables
+    pub fn table(&self, schema: Schema) -> TableFormat {
+        match self {
+            OutputFormat::Tsv => TableFormat::Delimited(b','),
+            OutputFormat::MPileup => TableFormat::Delimited(b'\t'),
+            OutputFormat::JsonLines => TableFormat::JsonLines,
+            OutputFormat::Parquet => TableFormat::Parquet(schema),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(OutputFormat::Tsv),
+            "mpileup" => Ok(OutputFormat::MPileup),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::MPileup => "mpileup",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::Parquet => "parquet",
+        };
+        write!(f, "{}", symbol)
+    }
+}
@@ -1,9 +1,14 @@
-pub use run::run;
+pub use run::{check_empty, finalize, past_end, report_filtered, report_past_end, report_tags};
+pub use runinfo::RunMeta;
 
 pub mod args;
+pub mod checkpoint;
+pub mod format;
+pub mod output;
 pub mod parse;
+pub mod progress;
 mod run;
+pub mod runinfo;
 pub mod stranding;
 pub mod style;
-pub mod thread_cache;
 pub mod validate;
@@ -0,0 +1,318 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bio_types::strand::Strand;
+
+use crate::cli::shared::checkpoint::Checkpoint;
+use crate::core::io::table::{TableFormat, TableWriter};
+use crate::core::io::utils::{self, OutputWriter};
+use crate::core::mismatches::MismatchesVec;
+use crate::core::strandutil::Stranded;
+use crate::error::Error;
+
+pub type Writer = TableWriter<OutputWriter>;
+
+const STRANDS: [Strand; 3] = [Strand::Forward, Strand::Reverse, Strand::Unknown];
+
+const WRITE_ERROR: &str = "Failed to write the results";
+
+// Main output table, optionally split into separate files by the transcription strand
+pub enum SaveTo {
+    Joint(Writer),
+    Stranded {
+        writers: Stranded<Writer>,
+        // Files without any items get only the header (if any)
+        written: Stranded<bool>,
+        header: Option<Vec<String>>,
+    },
+    // Results are stored contig by contig in the checkpoint and concatenated once all contigs are finished
+    Checkpointed {
+        writer: Writer,
+        checkpoint: Checkpoint,
+        // Writer for the currently processed contig (if anything was written)
+        part: Option<Writer>,
+        format: TableFormat,
+        header: Option<Vec<String>>,
+    },
+}
+
+impl SaveTo {
+    pub fn joint(path: &Path) -> Result<Self, Error> {
+        Ok(SaveTo::Joint(TableWriter::new(&TableFormat::Delimited(b','), utils::write_compressed(path, false)?)))
+    }
+
+    // The path is treated as a prefix: out.tsv.gz -> out.forward.tsv.gz, out.reverse.tsv.gz, out.unknown.tsv.gz
+    pub fn stranded(prefix: &Path) -> Result<Self, Error> {
+        let writer = |strand: Strand| -> Result<Writer, Error> {
+            let file = utils::write_compressed(&stranded_path(prefix, strand), false)?;
+            Ok(TableWriter::new(&TableFormat::Delimited(b','), file))
+        };
+        let writers = Stranded {
+            forward: writer(Strand::Forward)?,
+            reverse: writer(Strand::Reverse)?,
+            unknown: writer(Strand::Unknown)?,
+        };
+        Ok(SaveTo::Stranded { writers, written: Stranded::with_fn(|_| false), header: None })
+    }
+
+    pub fn checkpointed(self, checkpoint: Checkpoint) -> Self {
+        match self {
+            SaveTo::Joint(writer) => {
+                let format = TableFormat::Delimited(b',');
+                SaveTo::Checkpointed { writer, checkpoint, part: None, format, header: None }
+            }
+            _ => panic!("Checkpoints are supported only for a single output file"),
+        }
+    }
+
+    pub fn with_header(self, header: Vec<String>) -> Self {
+        match self {
+            SaveTo::Joint(_) => self,
+            SaveTo::Stranded { writers, written, .. } => SaveTo::Stranded { writers, written, header: Some(header) },
+            SaveTo::Checkpointed { writer, checkpoint, part, format, .. } => {
+                SaveTo::Checkpointed { writer, checkpoint, part, format, header: Some(header) }
+            }
+        }
+    }
+
+    // Write a comment line (e.g. the schema version) at the top of all files, must be called before anything else
+    pub fn with_comment(mut self, comment: &str) -> Result<Self, Error> {
+        let write = |writer: &mut Writer| -> io::Result<()> { writeln!(writer.get_mut()?, "{}", comment) };
+        let result = match &mut self {
+            SaveTo::Joint(writer) | SaveTo::Checkpointed { writer, .. } => write(writer),
+            SaveTo::Stranded { writers, .. } => STRANDS.iter().try_for_each(|x| write(&mut writers[*x])),
+        };
+        result.map_err(|x| Error::output(WRITE_ERROR, x))?;
+        Ok(self)
+    }
+
+    // Reopen the same files in a different format
+    pub fn with_format(self, format: TableFormat) -> Self {
+        let rebuild = |writer: Writer| TableWriter::new(&format, writer.into_inner());
+        match self {
+            SaveTo::Joint(writer) => SaveTo::Joint(rebuild(writer)),
+            SaveTo::Stranded { writers, written, header } => {
+                SaveTo::Stranded { writers: writers.into(|x, _| rebuild(x)), written, header }
+            }
+            SaveTo::Checkpointed { writer, checkpoint, part, header, .. } => {
+                // Contigs are stored as separate parts & concatenated at the end
+                if let TableFormat::Parquet(_) = format {
+                    panic!("Parquet output doesn't support checkpoints, use another format or drop the checkpoint");
+                }
+                SaveTo::Checkpointed { writer: rebuild(writer), checkpoint, part, format, header }
+            }
+        }
+    }
+
+    pub fn write<T: MismatchesVec>(
+        &mut self,
+        items: Vec<T>,
+        mut serialize: impl FnMut(Vec<T>, &mut Writer) -> csv::Result<()>,
+    ) -> Result<(), Error> {
+        let failed = |x: csv::Error| Error::output(WRITE_ERROR, x);
+        match self {
+            SaveTo::Joint(writer) => serialize(items, writer).map_err(failed),
+            SaveTo::Stranded { writers, written, .. } => {
+                let mut groups: Stranded<Vec<T>> = Stranded::with_fn(|_| Vec::new());
+                for item in items {
+                    groups[item.trstrand()].push(item);
+                }
+                for strand in STRANDS {
+                    let group = std::mem::take(&mut groups[strand]);
+                    if !group.is_empty() {
+                        serialize(group, &mut writers[strand]).map_err(failed)?;
+                        written[strand] = true;
+                    }
+                }
+                Ok(())
+            }
+            SaveTo::Checkpointed { checkpoint, part, format, .. } => {
+                // Headers are written only once, see finish
+                if part.is_none() {
+                    *part = Some(TableWriter::headless(format, checkpoint.part()?));
+                }
+                serialize(items, part.as_mut().unwrap()).map_err(failed)
+            }
+        }
+    }
+
+    // Must be called once all results for the contig are written
+    pub fn commit(&mut self, contig: &str) -> Result<(), Error> {
+        if let SaveTo::Checkpointed { checkpoint, part, .. } = self {
+            if let Some(mut part) = part.take() {
+                part.finish().map_err(|x: csv::Error| Error::output(WRITE_ERROR, x))?;
+            }
+            checkpoint.commit(contig)?;
+        }
+        Ok(())
+    }
+
+    // Explicitly finalize all files to report any IO errors here instead of silently ignoring them on drop
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.flush().map_err(|x: csv::Error| Error::output(WRITE_ERROR, x))
+    }
+
+    fn flush(&mut self) -> csv::Result<()> {
+        match self {
+            SaveTo::Joint(writer) => writer.finish()?,
+            SaveTo::Stranded { writers, written, header } => {
+                for strand in STRANDS {
+                    let writer = &mut writers[strand];
+                    if let (false, Some(header)) = (written[strand], header.as_ref()) {
+                        writer.write_header(header)?;
+                    }
+                    writer.finish()?;
+                }
+            }
+            SaveTo::Checkpointed { writer, checkpoint, header, .. } => {
+                let parts = checkpoint.parts();
+                // Same as for a single uninterrupted run: the header is written only if there are any results
+                if let Some(header) = header {
+                    let sizes: io::Result<Vec<u64>> = parts.iter().map(|x| x.metadata().map(|m| m.len())).collect();
+                    if sizes?.iter().any(|x| *x > 0) {
+                        writer.write_header(header)?;
+                    }
+                }
+                for part in parts {
+                    io::copy(&mut File::open(part)?, writer.get_mut()?)?;
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn stranded_paths(prefix: &Path) -> Vec<PathBuf> {
+    STRANDS.iter().map(|x| stranded_path(prefix, *x)).collect()
+}
+
+fn stranded_path(prefix: &Path, strand: Strand) -> PathBuf {
+    let filename = prefix
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or_else(|| panic!("Failed to infer the file name for {}.", prefix.display()));
+
+    let (name, compression) = match filename.rsplit_once('.') {
+        Some((name, ext @ ("gz" | "bgz"))) => (name, Some(ext)),
+        _ => (filename, None),
+    };
+    let (name, ext) = match name.rsplit_once('.') {
+        Some((name, ext @ ("tsv" | "csv"))) => (name, ext),
+        _ => (name, "tsv"),
+    };
+    let strand = match strand {
+        Strand::Forward => "forward",
+        Strand::Reverse => "reverse",
+        Strand::Unknown => "unknown",
+    };
+
+    let mut filename = format!("{}.{}.{}", name, strand, ext);
+    if let Some(compression) = compression {
+        filename = format!("{}.{}", filename, compression);
+    }
+    prefix.with_file_name(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::core::io::table::RowWriter;
+    use crate::core::mismatches::site::{SiteDataVec, SiteMismatchesVec};
+
+    use super::*;
+
+    #[test]
+    fn split_by_strand() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("out.csv");
+        let mut saveto = SaveTo::stranded(&prefix).unwrap().with_header(vec!["contig".into(), "pos".into()]);
+
+        let items =
+            ["1", "2", "MT"].map(|contig| SiteMismatchesVec::new(contig.into(), Strand::Forward, SiteDataVec::new()));
+        saveto
+            .write(items.into(), |items, writer| {
+                for item in items {
+                    writer.write_record([item.contig(), "0"])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        saveto.finish().unwrap();
+
+        let written: Vec<String> = stranded_paths(&prefix).iter().map(|x| fs::read_to_string(x).unwrap()).collect();
+        // Empty files still have the header
+        assert_eq!(written, ["1,0\n2,0\nMT,0\n", "contig,pos\n", "contig,pos\n"]);
+    }
+
+    #[test]
+    fn comment() {
+        let tmp = TempDir::new().unwrap();
+        let header = vec!["contig".to_owned(), "pos".to_owned()];
+        let write = |saveto: &mut SaveTo| {
+            let items = vec![SiteMismatchesVec::new("1".into(), Strand::Forward, SiteDataVec::new())];
+            saveto.write(items, |items, writer| writer.write_record([items[0].contig(), "0"])).unwrap();
+            saveto.commit("1").unwrap();
+            saveto.finish().unwrap();
+        };
+
+        // The comment always precedes the header, even if the latter is written at the very end
+        let prefix = tmp.path().join("out.csv");
+        let mut saveto = SaveTo::stranded(&prefix).unwrap().with_header(header.clone()).with_comment("# x").unwrap();
+        write(&mut saveto);
+        let written: Vec<String> = stranded_paths(&prefix).iter().map(|x| fs::read_to_string(x).unwrap()).collect();
+        assert_eq!(written, ["# x\n1,0\n", "# x\ncontig,pos\n", "# x\ncontig,pos\n"]);
+
+        let (output, dir) = (tmp.path().join("joint.csv"), tmp.path().join("checkpoint"));
+        let checkpoint = Checkpoint::new(dir, "abc".into()).unwrap();
+        let mut saveto = SaveTo::joint(&output).unwrap().checkpointed(checkpoint).with_header(header);
+        saveto = saveto.with_comment("# x").unwrap();
+        write(&mut saveto);
+        assert_eq!(fs::read_to_string(&output).unwrap(), "# x\ncontig,pos\n1,0\n");
+    }
+
+    #[test]
+    fn checkpointed() {
+        let tmp = TempDir::new().unwrap();
+        let (output, dir) = (tmp.path().join("out.csv"), tmp.path().join("checkpoint"));
+        let saveto = |checkpoint| {
+            SaveTo::joint(&output).unwrap().checkpointed(checkpoint).with_header(vec!["contig".into(), "pos".into()])
+        };
+        let write = |saveto: &mut SaveTo, contig: &str| {
+            let items = vec![SiteMismatchesVec::new(contig.into(), Strand::Forward, SiteDataVec::new())];
+            saveto.write(items, |items, writer| writer.write_record([items[0].contig(), "0"])).unwrap();
+            saveto.commit(contig).unwrap();
+        };
+
+        // Interrupted run
+        let mut interrupted = saveto(Checkpoint::new(dir.clone(), "abc".into()).unwrap());
+        write(&mut interrupted, "1");
+        interrupted.commit("2").unwrap();
+        drop(interrupted);
+
+        let checkpoint = Checkpoint::new(dir, "abc".into()).unwrap();
+        assert_eq!(checkpoint.finished().len(), 2);
+        let mut resumed = saveto(checkpoint);
+        write(&mut resumed, "MT");
+        resumed.finish().unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "contig,pos\n1,0\nMT,0\n");
+    }
+
+    #[test]
+    fn stranded_path() {
+        for (prefix, strand, expected) in [
+            ("out.tsv.gz", Strand::Forward, "out.forward.tsv.gz"),
+            ("results/out", Strand::Reverse, "results/out.reverse.tsv"),
+            ("out.csv", Strand::Unknown, "out.unknown.csv"),
+            ("my.out.bgz", Strand::Forward, "my.out.forward.tsv.bgz"),
+        ] {
+            assert_eq!(super::stranded_path(Path::new(prefix), strand), PathBuf::from(expected));
+        }
+    }
+}
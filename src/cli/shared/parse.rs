@@ -1,36 +1,56 @@
-use std::fs::File;
+use std::collections::HashSet;
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use bio_types::genome::AbstractInterval;
+use bio_types::strand::Strand;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
 use itertools::Itertools;
-use rust_htslib::bam::Record;
+use rust_htslib::bam::{Read, Record};
 
-use crate::cli::shared::stranding::Stranding;
+use crate::cli::shared::format::OutputFormat;
+use crate::cli::shared::output::{self, SaveTo};
+use crate::cli::shared::stranding::{self, Stranding, StrandingAlgoSpec};
+use crate::core::annotation::GenomicFeatures;
+use crate::core::dna::Multimap;
+use crate::core::hooks::filters::{ByExpression, Records};
+use crate::core::io::contigs::{ContigLengths, ContigNames};
+use crate::core::io::fasta;
 use crate::core::io::fasta::FastaReader;
-use crate::core::io::{bed, vcf};
+use crate::core::io::hts::IndexStatus;
+use crate::core::io::{bed, hts, vcf, whitelist};
 use crate::core::mismatches::{prefilters, MismatchesVec};
-use crate::core::refpred::{AutoRef, RefEngine, VCFCorrectedReference};
+use crate::core::pipeline::ReadsFilter;
+use crate::core::refpred::{AutoRef, RefEngine, RefNPolicy, ResolveUnknownRef, VCFCorrectedReference};
 use crate::core::rpileup::ncounter::filters;
+use crate::core::rpileup::stream::HTSStream;
 use crate::core::stranding::deduce::StrandSpecificExperimentDesign;
-use crate::core::stranding::predict::algo::{StrandByAtoIEditing, StrandByGenomicAnnotation};
+use crate::core::stranding::predict::algo::{StrandByAtoIEditing, StrandByC2UEditing, StrandByGenomicAnnotation};
 use crate::core::stranding::predict::{REATStrandingEngine, StrandingAlgo};
+use crate::error::Error;
 
 use super::args;
 
 pub fn readfilter(
     pbar: ProgressBar,
     matches: &ArgMatches,
-) -> filters::Sequential<Record, filters::ByQuality, filters::ByFlags> {
+    tags: filters::ByTags,
+    bamfiles: &[PathBuf],
+) -> Result<ReadsFilter, Error> {
     pbar.set_message("Parsing filters filter options...");
-    let (mapq, nomapq255, phread) = (
+    let (mapq, phread) = (
         matches.value_of(args::reads_filtering::MAPQ).unwrap().parse().unwrap(),
-        matches.is_present(args::reads_filtering::NO_MAPQ_255),
         matches.value_of(args::reads_filtering::PHREAD).unwrap().parse().unwrap(),
     );
-    let byquality = filters::ByQuality::new(mapq, nomapq255, phread);
+    let mapq255 = match matches.value_of(args::reads_filtering::MAPQ_255).unwrap() {
+        "keep" => filters::MapQ255::Keep,
+        "drop" => filters::MapQ255::Drop,
+        policy => panic!("Unknown mapq=255 policy: {}", policy),
+    };
+    let byquality = filters::ByQuality::new(mapq, mapq255, phread);
 
     let (include, exclude) = (
         matches.value_of(args::reads_filtering::INCLUDE_FLAGS).unwrap().parse().unwrap(),
@@ -45,13 +65,48 @@ pub fn readfilter(
         byquality.mapq(),
         byquality.phread()
     );
-    if nomapq255 {
-        pbar.finish_with_message(msg + "Mapq = 255 is NOT allowed.");
+    let mut msg = if mapq255 == filters::MapQ255::Drop {
+        msg + "Mapq = 255 is NOT allowed."
     } else {
-        pbar.finish_with_message(msg + "Mapq = 255 is allowed.");
+        msg + "Mapq = 255 is allowed."
+    };
+
+    let mut filter =
+        filters::Tracked::new(filters::Sequential::new(filters::Sequential::new(byquality, byflags), tags));
+    if let Some(prefix) = matches.value_of(args::reads_filtering::DUMP_READS) {
+        filter = filter.with_dump(Arc::new(hts::BamDump::new(prefix, bamfiles)?));
+        msg = format!("{} Checked reads will be saved to {}.accepted.bam & {}.rejected.bam", msg, prefix, prefix);
     }
+    pbar.finish_with_message(msg);
+    Ok(filter)
+}
 
-    filters::Sequential::new(byquality, byflags)
+pub fn tags(pbar: ProgressBar, matches: &ArgMatches) -> Result<filters::ByTags, Error> {
+    pbar.set_message("Parsing read groups & cell barcodes filters...");
+    let mut result = filters::ByTags::new();
+    let mut restrictions = Vec::new();
+    if let Some(groups) = matches.values_of(args::reads_filtering::READ_GROUP) {
+        let groups: HashSet<Vec<u8>> = groups.map(|x| x.as_bytes().to_vec()).collect();
+        restrictions.push(format!("{} read group(s)", groups.len()));
+        result = result.with_read_groups(groups);
+    }
+    if let Some(path) = matches.value_of(args::reads_filtering::CB_WHITELIST) {
+        let tag = matches.value_of(args::reads_filtering::CB_TAG).unwrap();
+        let barcodes = whitelist::parse(path)?;
+        if barcodes.is_empty() {
+            return Err(Error::Input(format!("Cell barcodes whitelist {} is empty", path)));
+        }
+        restrictions.push(format!("{} cell barcode(s) in the {} tag", barcodes.len(), tag));
+        let tag = tag.as_bytes();
+        result = result.with_barcodes([tag[0], tag[1]], barcodes);
+    }
+
+    if restrictions.is_empty() {
+        pbar.finish_with_message("Reads are not filtered by read groups or cell barcodes.");
+    } else {
+        pbar.finish_with_message(format!("Counting only reads from {}.", restrictions.join(" and ")));
+    }
+    Ok(result)
 }
 
 pub fn trimming(pbar: ProgressBar, matches: &ArgMatches) -> (u16, u16) {
@@ -69,13 +124,110 @@ pub fn trimming(pbar: ProgressBar, matches: &ArgMatches) -> (u16, u16) {
     (trim5, trim3)
 }
 
-pub fn saveto(pbar: ProgressBar, matches: &ArgMatches) -> csv::Writer<File> {
+pub fn overlaps_dedup(pbar: ProgressBar, matches: &ArgMatches) -> bool {
+    pbar.set_message("Parsing overlapping mates options...");
+    let dedup = !matches.is_present(args::reads_filtering::NO_OVERLAP_DEDUP);
+    if dedup {
+        pbar.finish_with_message("Bases covered by both overlapping mates will be counted once.");
+    } else {
+        pbar.finish_with_message("Bases covered by both overlapping mates will be counted twice.");
+    }
+    dedup
+}
+
+pub fn umi(pbar: ProgressBar, matches: &ArgMatches) -> Option<[u8; 2]> {
+    pbar.set_message("Parsing UMI options...");
+    match matches.value_of(args::reads_filtering::UMI_TAG) {
+        None => {
+            pbar.finish_with_message("UMI deduplication disabled.");
+            None
+        }
+        Some(tag) => {
+            pbar.finish_with_message(format!("Reads will be deduplicated by the UMI stored in the {} tag.", tag));
+            let tag = tag.as_bytes();
+            Some([tag[0], tag[1]])
+        }
+    }
+}
+
+pub fn multimap(pbar: ProgressBar, matches: &ArgMatches) -> Multimap {
+    pbar.set_message("Parsing multi-mapped reads options...");
+    match matches.value_of(args::reads_filtering::MULTIMAP).unwrap() {
+        "full" => {
+            pbar.finish_with_message("Multi-mapped reads will be counted fully.");
+            Multimap::Full
+        }
+        "fractional" => {
+            pbar.finish_with_message("Multi-mapped reads will be weighted by 1/NH.");
+            Multimap::Fractional
+        }
+        x => panic!("Unknown multi-mapped reads policy: {}", x),
+    }
+}
+
+pub fn max_read_contribution(pbar: ProgressBar, matches: &ArgMatches) -> Option<u32> {
+    pbar.set_message("Parsing per-read contribution options...");
+    let max = matches.value_of(args::reads_filtering::MAX_READ_CONTRIBUTION).map(|x| x.parse().unwrap());
+    match max {
+        None => pbar.finish_with_message("Per-read contribution is not limited."),
+        Some(max) => {
+            pbar.finish_with_message(format!("Each read may contribute at most {} mismatches per genome bin.", max))
+        }
+    }
+    max
+}
+
+pub fn max_coverage(pbar: ProgressBar, matches: &ArgMatches) -> Option<u32> {
+    pbar.set_message("Parsing the coverage cap...");
+    let max = matches.value_of(args::reads_filtering::MAX_COVERAGE).map(|x| x.parse().unwrap());
+    match max {
+        None => pbar.finish_with_message("Coverage is not capped."),
+        Some(max) => pbar.finish_with_message(format!("Reads are skipped once the coverage reaches {}.", max)),
+    }
+    max
+}
+
+pub fn saveto(pbar: ProgressBar, matches: &ArgMatches) -> Result<SaveTo, Error> {
     pbar.set_message("Parsing output path...");
     let result = matches.value_of(args::core::SAVETO).unwrap();
-    let file = File::create(result).unwrap();
-    let file = csv::WriterBuilder::new().from_writer(file);
-    pbar.finish_with_message(format!("Result will be saved to {}", result));
-    file
+    if !matches.is_present(args::core::SPLIT_BY_STRAND) {
+        pbar.finish_with_message(format!("Result will be saved to {}", result));
+        return SaveTo::joint(Path::new(result));
+    }
+
+    if result.starts_with("/dev/") {
+        return Err(Error::Input(format!(
+            "Output path must be a regular file prefix to split results by strand, got {}",
+            result
+        )));
+    }
+    let paths = output::stranded_paths(Path::new(result));
+    pbar.finish_with_message(format!(
+        "Results will be split by strand and saved to {}",
+        paths.iter().map(|x| x.display()).join(", ")
+    ));
+    SaveTo::stranded(Path::new(result))
+}
+
+pub fn runinfo(pbar: ProgressBar, matches: &ArgMatches) -> Option<PathBuf> {
+    pbar.set_message("Parsing run info path...");
+    let result = match matches.value_of(args::core::RUN_INFO) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            // Next to the output file, but only if it is a regular file (not stdout, /dev/null, etc)
+            let saveto = matches.value_of(args::core::SAVETO).unwrap();
+            if saveto.starts_with("/dev/") {
+                None
+            } else {
+                Some(PathBuf::from(format!("{}.json", saveto)))
+            }
+        }
+    };
+    match &result {
+        Some(path) => pbar.finish_with_message(format!("Run info will be saved to {}", path.display())),
+        None => pbar.finish_with_message("Run info won't be saved"),
+    }
+    result
 }
 
 pub fn stranding(pbar: ProgressBar, matches: &ArgMatches) -> Stranding {
@@ -98,11 +250,19 @@ pub fn stranding(pbar: ProgressBar, matches: &ArgMatches) -> Stranding {
     stranding
 }
 
-pub fn strandpred<T>(pbar: ProgressBar, matches: &ArgMatches) -> REATStrandingEngine<T>
+// Count thresholds are multiplied by the scale of weighted counts, see Multimap
+// `editing` is the argument with A->I editing thresholds specific for the given mode (ROIs or sites)
+pub fn strandpred<T>(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    editing: &str,
+    scale: u32,
+) -> Result<REATStrandingEngine<T>, Error>
 where
     T: MismatchesVec,
     StrandByGenomicAnnotation: StrandingAlgo<T>,
     StrandByAtoIEditing: StrandingAlgo<T>,
+    StrandByC2UEditing: StrandingAlgo<T>,
 {
     pbar.set_draw_delta(10_000);
     pbar.set_message("Parsing strand prediction parameters...");
@@ -115,33 +275,107 @@ where
             "Strand prediction is disabled -> working with \"{}\" stranded library",
             stranding
         ));
-        return engine;
-    }
-
-    // User message
-    let mut msg = vec![];
-    if let Some(x) = matches.value_of(args::stranding::ANNOTATION) {
-        msg.push("by genomic features [exons, genes, extended utrs]".to_owned());
-        let extend3utr = matches.value_of(args::stranding::EXTEND_UTR3).unwrap_or("0").parse().unwrap();
-        engine.add(Box::new(StrandByGenomicAnnotation::from_gff(x.as_ref(), extend3utr, |_| pbar.inc(1))));
+        return Ok(engine);
     }
 
     let (minmismatches, minfreq) = (
         matches.value_of(args::stranding::MIN_MISMATCHES).unwrap().parse().unwrap(),
         matches.value_of(args::stranding::MIN_FREQ).unwrap().parse().unwrap(),
     );
-    msg.push(format!("by A->I editing[min mismatches={}, min freq={}]", minmismatches, minfreq));
-    engine.add(Box::new(StrandByAtoIEditing::new(minmismatches, minfreq)));
+    let a2i = matches.value_of(editing).map_or((minmismatches, minfreq), |x| stranding::editing_thresholds(x).unwrap());
+    let annotation = matches.value_of(args::stranding::ANNOTATION);
+
+    // Algorithms in the order of priority
+    let algos: Vec<StrandingAlgoSpec> = match matches.values_of(args::stranding::ALGO) {
+        Some(algos) => {
+            let algos: Vec<StrandingAlgoSpec> = algos.map(|x| StrandingAlgoSpec::from_str(x).unwrap()).collect();
+            if algos.contains(&StrandingAlgoSpec::Features) && annotation.is_none() {
+                return Err(Error::Input(format!(
+                    "Stranding by genomic features requires the genome annotation (--{})",
+                    args::stranding::ANNOTATION
+                )));
+            }
+            algos
+        }
+        None => {
+            // Default: genomic features (if any) first, then the editing types
+            let mut algos = vec![];
+            if annotation.is_some() {
+                algos.push(StrandingAlgoSpec::Features);
+            }
+            for editing in matches.values_of(args::stranding::EDITING).unwrap() {
+                match editing {
+                    "a2i" => algos.push(StrandingAlgoSpec::AtoI(None)),
+                    "c2u" => algos.push(StrandingAlgoSpec::CtoU(None)),
+                    _ => panic!("Unknown editing type: {}", editing),
+                }
+            }
+            algos
+        }
+    };
+
+    // User message
+    let mut msg = vec![];
+    for algo in algos {
+        match algo {
+            StrandingAlgoSpec::Features => {
+                let extend3utr = matches.value_of(args::stranding::EXTEND_UTR3).unwrap_or("0").parse().unwrap();
+                let flank = matches.value_of(args::stranding::FEATURE_FLANK).unwrap_or("0").parse().unwrap();
+                let algo =
+                    StrandByGenomicAnnotation::from_gff(annotation.unwrap().as_ref(), extend3utr, |_| pbar.inc(1))?
+                        .with_flank(flank);
+                let features = match flank {
+                    0 => "exons, genes, extended utrs".to_owned(),
+                    _ => format!("exons, genes, extended utrs, nearest genes within {}bp", flank),
+                };
+                if algo.skipped() > 0 {
+                    msg.push(format!(
+                        "by genomic features [{}; WARNING: {} unstranded genes/exons skipped]",
+                        features,
+                        algo.skipped()
+                    ));
+                } else {
+                    msg.push(format!("by genomic features [{}]", features));
+                }
+                engine.add(Box::new(algo));
+            }
+            StrandingAlgoSpec::AtoI(thresholds) => {
+                let (minmismatches, minfreq) = thresholds.unwrap_or(a2i);
+                msg.push(format!("by A->I editing[min mismatches={}, min freq={}]", minmismatches, minfreq));
+                engine.add(Box::new(StrandByAtoIEditing::new(minmismatches * scale, minfreq)));
+            }
+            StrandingAlgoSpec::CtoU(thresholds) => {
+                let (minmismatches, minfreq) = thresholds.unwrap_or((minmismatches, minfreq));
+                msg.push(format!("by C->U editing[min mismatches={}, min freq={}]", minmismatches, minfreq));
+                engine.add(Box::new(StrandByC2UEditing::new(minmismatches * scale, minfreq)));
+            }
+        }
+    }
 
     let msg = format!("Strand prediction (by priority): {}", msg.join(", "));
     pbar.finish_with_message(msg);
-    engine
+    Ok(engine)
 }
 
-pub fn refnucpred(pbar: ProgressBar, matches: &ArgMatches, reader: Box<dyn FastaReader>) -> Box<dyn RefEngine> {
+pub fn refn(matches: &ArgMatches) -> RefNPolicy {
+    match matches.value_of(args::autoref::REF_N_POLICY).unwrap() {
+        "mismatch" => RefNPolicy::Mismatch,
+        "skip" => RefNPolicy::Skip,
+        "autoref" => RefNPolicy::AutoRef,
+        x => panic!("Unknown policy for N's in the reference: {}", x),
+    }
+}
+
+// Count thresholds are multiplied by the scale of weighted counts, see Multimap
+pub fn refnucpred(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    reader: Box<dyn FastaReader>,
+    scale: u32,
+) -> Box<dyn RefEngine> {
     pbar.set_message("Parsing reference prediction parameters...");
 
-    if let Some(file) = matches.value_of(args::autoref::VCF) {
+    let (engine, mut msg): (Box<dyn RefEngine>, String) = if let Some(file) = matches.value_of(args::autoref::VCF) {
         let file = Path::new(file);
         let snv = vcf::parse(file);
 
@@ -149,13 +383,13 @@ pub fn refnucpred(pbar: ProgressBar, matches: &ArgMatches, reader: Box<dyn Fasta
         let homozygotes: usize = snv.homozygous.iter().map(|x| x.len()).sum();
 
         let variants = VCFCorrectedReference::new(snv, reader);
-        pbar.finish_with_message(format!(
+        let msg = format!(
             "Reference will be adjusted by SNPs(heterozygotes: {heterozygotes}, homozygotes: {homozygotes}) from: {}.",
             file.file_name().unwrap().to_str().unwrap()
-        ));
-        Box::new(variants)
+        );
+        (Box::new(variants), msg)
     } else {
-        let (mincoverage, minfreq, hyperedit) = (
+        let (mincoverage, minfreq, hyperedit): (u32, f32, bool) = (
             matches.value_of(args::autoref::MIN_COVERAGE).unwrap().parse().unwrap(),
             matches.value_of(args::autoref::MIN_FREQ).unwrap().parse().unwrap(),
             matches.is_present(args::autoref::HYPEREDITING),
@@ -167,15 +401,45 @@ pub fn refnucpred(pbar: ProgressBar, matches: &ArgMatches, reader: Box<dyn Fasta
         if hyperedit {
             msg += " A->G or T->C corrections was disabled (hyper editing mode)."
         }
-        let result = AutoRef::new(mincoverage, minfreq, hyperedit, reader);
-        pbar.finish_with_message(msg);
-        Box::new(result)
-    }
+        let mut result = AutoRef::new(mincoverage * scale, minfreq, hyperedit, reader);
+        if let Some(hetminfreq) = matches.value_of(args::refinference::HET_MIN_FREQ) {
+            let hetminfreq = hetminfreq.parse().unwrap();
+            let hetmincount: u32 = matches.value_of(args::refinference::HET_MIN_COUNT).unwrap().parse().unwrap();
+            msg += &format!(
+                " Heterozygous sites are called for the second most common nucleotide frequency >= {} and count >= {}.",
+                hetminfreq, hetmincount
+            );
+            result = result.with_heterozygous(hetminfreq, hetmincount * scale);
+        }
+        (Box::new(result), msg)
+    };
+
+    let engine: Box<dyn RefEngine> = match refn(matches) {
+        RefNPolicy::AutoRef => {
+            let (mincoverage, minfreq): (u32, f32) = (
+                matches.value_of(args::autoref::MIN_COVERAGE).unwrap().parse().unwrap(),
+                matches.value_of(args::autoref::MIN_FREQ).unwrap().parse().unwrap(),
+            );
+            msg += " N's in the assembly are inferred from the sequenced nucleotides.";
+            Box::new(ResolveUnknownRef::new(engine, mincoverage * scale, minfreq))
+        }
+        RefNPolicy::Skip => {
+            msg += " Positions with N's in the assembly are skipped.";
+            engine
+        }
+        RefNPolicy::Mismatch => engine,
+    };
+    pbar.finish_with_message(msg);
+    engine
 }
 
 pub fn bamfiles(pbar: ProgressBar, matches: &ArgMatches) -> Vec<PathBuf> {
     pbar.set_message("Parsing paths to the input files...");
-    let result: Vec<PathBuf> = matches.values_of(args::core::INPUT).unwrap().map(|x| x.into()).collect();
+    let result: Vec<PathBuf> =
+        matches.values_of(args::core::INPUT).unwrap().map(|x| super::validate::input(x).unwrap().1).collect();
+    if !matches.is_present(args::core::STREAM) && result.iter().any(|x| x.as_path() == Path::new(hts::STDIN)) {
+        panic!("Reading from stdin ({}) requires --{}", hts::STDIN, args::core::STREAM);
+    }
     if result.len() == 1 {
         pbar.finish_with_message(format!("Input file path: {}", result[0].display()))
     } else {
@@ -185,6 +449,99 @@ pub fn bamfiles(pbar: ProgressBar, matches: &ArgMatches) -> Vec<PathBuf> {
     result
 }
 
+// Sample name derived from the input file name, e.g. /data/liver.bam => liver
+pub fn sample_name(path: &Path) -> Result<String, Error> {
+    path.file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .ok_or_else(|| Error::Input(format!("Failed to derive the sample name from the input path {}", path.display())))
+}
+
+pub fn stream(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamfiles: &[PathBuf],
+    reference: &Path,
+) -> Result<Option<HTSStream>, Error> {
+    pbar.set_message("Parsing the input access mode...");
+    if !matches.is_present(args::core::STREAM) {
+        pbar.finish_with_message("Input files will be accessed through their indexes");
+        return Ok(None);
+    }
+    let stream = HTSStream::open(bamfiles, Some(reference))?;
+    pbar.finish_with_message("Input files will be read sequentially in the coordinate order, indexes are not required");
+    Ok(Some(stream))
+}
+
+// All input files must be indexed before any setup step that fetches reads. Returns the index of each file and
+// whether some indexes are stale
+pub fn indexes(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamfiles: &[PathBuf],
+    threads: u32,
+) -> Result<(Vec<PathBuf>, bool), Error> {
+    pbar.set_message("Checking indexes of the input files...");
+    let explicit: Option<Vec<PathBuf>> = matches.values_of(args::core::INDEX).map(|x| x.map(PathBuf::from).collect());
+    if let Some(explicit) = &explicit {
+        if explicit.len() != bamfiles.len() {
+            return Err(Error::Input(format!(
+                "--{} must list exactly one index per input file, got {} indexes for {} files",
+                args::core::INDEX,
+                explicit.len(),
+                bamfiles.len()
+            )));
+        }
+    }
+    let build = matches.is_present(args::core::BUILD_INDEX);
+    let (mut indexes, mut missing, mut built, mut outdated) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (ind, file) in bamfiles.iter().enumerate() {
+        let index = explicit.as_ref().map(|x| x[ind].as_path());
+        match hts::index_status(file, index) {
+            IndexStatus::Missing if build && index.is_none() => {
+                pbar.set_message(format!("Building index for {}...", file.display()));
+                hts::build_index(file, threads)?;
+                built.push(file.display());
+                match hts::find_index(file) {
+                    Some(index) => indexes.push(index),
+                    None => return Err(Error::hts(file, "the index was built, but it can't be found")),
+                }
+            }
+            IndexStatus::Missing => missing.push(file.display()),
+            // Network filesystems often report wrong modification times => not an error
+            IndexStatus::Outdated(index) => {
+                outdated.push(index.display().to_string());
+                indexes.push(index);
+            }
+            IndexStatus::Ok(index) => indexes.push(index),
+        }
+    }
+    // All files without indexes are reported at once
+    if !missing.is_empty() {
+        return Err(Error::Input(format!(
+            "Index is missing for {}, create it with `samtools index <file>`, pass its path with --{} \
+            or rerun with --{}",
+            missing.iter().join(" "),
+            args::core::INDEX,
+            args::core::BUILD_INDEX
+        )));
+    }
+
+    let mut message = if built.is_empty() {
+        "All input files are indexed".to_owned()
+    } else {
+        format!("Built missing indexes for: {}", built.iter().join(" "))
+    };
+    if !outdated.is_empty() {
+        message = format!(
+            "{} WARNING: indexes are older than the indexed files and might be stale: {}",
+            message,
+            outdated.iter().join(" ")
+        );
+    }
+    pbar.finish_with_message(message);
+    Ok((indexes, !outdated.is_empty()))
+}
+
 pub fn reference(pbar: ProgressBar, matches: &ArgMatches) -> PathBuf {
     pbar.set_message("Parsing path to the reference assembly...");
     let result: PathBuf = matches.value_of(args::core::REFERENCE).unwrap().into();
@@ -192,6 +549,180 @@ pub fn reference(pbar: ProgressBar, matches: &ArgMatches) -> PathBuf {
     result
 }
 
+// Contigs are named as in the BAM files, `refnames` maps them to the reference ones
+pub fn contigs(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    reference: &Path,
+    refnames: &ContigNames,
+) -> Result<Option<Vec<String>>, Error> {
+    if matches.is_present(args::core::NO_SORT) {
+        pbar.finish_with_message("Contigs will be reported in the alphabetical order");
+        return Ok(None);
+    }
+    pbar.set_message("Parsing contigs order from the reference FASTA index...");
+    let inverse = refnames.inverse();
+    let result: Vec<String> = fasta::contigs(reference)?.iter().map(|x| inverse.rename(x).to_owned()).collect();
+    pbar.finish_with_message(format!("Contigs will be reported in the reference order ({} contigs)", result.len()));
+    Ok(Some(result))
+}
+
+// At most 10 contigs are listed in messages
+pub fn listed(contigs: &[String]) -> String {
+    let mut result = contigs.iter().take(10).join(", ");
+    if contigs.len() > 10 {
+        result += &format!(" and {} more", contigs.len() - 10);
+    }
+    result
+}
+
+fn contigs_mismatch(what: &str, missing: &[String], fixable: bool) -> String {
+    let mut msg = format!("{} contig(s) {}: {}.", missing.len(), what, listed(missing));
+    if fixable {
+        msg += &format!(" Use --{} to add/strip the \"chr\" prefix automatically.", args::core::FIX_CONTIG_NAMES);
+    }
+    msg
+}
+
+// Headers of the streamed inputs can't be read twice => they are taken from the opened stream
+pub fn contig_lengths(bamfiles: &[PathBuf], stream: Option<&HTSStream>) -> Result<ContigLengths, Error> {
+    let contigs = match stream {
+        Some(stream) => stream.contigs()?,
+        None => hts::contigs(bamfiles)?,
+    };
+    Ok(ContigLengths::new(contigs.into_iter().map(|x| (x.contig().to_owned(), x.range().end)).collect()))
+}
+
+// Contigs with at least one aligned read according to the indexes, None if unknown (e.g. streamed inputs).
+// Only the first read of each contig is fetched => the whole file is never decoded
+pub fn aligned_contigs(bamfiles: &[PathBuf], indexes: Option<&[PathBuf]>, reference: &Path) -> Option<HashSet<String>> {
+    let (mut aligned, mut record) = (HashSet::new(), Record::new());
+    for (file, index) in bamfiles.iter().zip(indexes?) {
+        let mut reader = hts::open(file, Some(index), Some(reference)).ok()?;
+        let header = reader.header().clone();
+        for tid in 0..header.target_count() {
+            let contig = String::from_utf8_lossy(header.tid2name(tid)).into_owned();
+            if aligned.contains(&contig) {
+                continue;
+            }
+            reader.fetch((contig.as_str(), 0, header.target_len(tid)?)).ok()?;
+            if let Some(Ok(())) = reader.read(&mut record) {
+                aligned.insert(contig);
+            }
+        }
+    }
+    Some(aligned)
+}
+
+// BAM contigs must be present in the reference assembly with the same lengths, returns the renaming of BAM contigs
+// to the reference ones. Contigs without aligned reads (e.g. decoys or unplaced scaffolds) are never fetched and
+// may be missing
+pub fn refnames(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    contiglens: &ContigLengths,
+    aligned: Option<&HashSet<String>>,
+    reference: &Path,
+) -> Result<ContigNames, Error> {
+    pbar.set_message("Matching contig names of the BAM files and the reference assembly...");
+    let fix = matches.is_present(args::core::FIX_CONTIG_NAMES);
+    let lengths = fasta::lengths(reference)?;
+    let fasta: HashSet<String> = lengths.keys().cloned().collect();
+    let bamcontigs = contiglens.names();
+    let (names, missing) = ContigNames::resolve(&fasta, bamcontigs.iter().map(|x| x.as_str()), fix);
+    let mut warning = String::new();
+    if !missing.is_empty() {
+        let fixable =
+            !fix && ContigNames::resolve(&fasta, missing.iter().map(|x| x.as_str()), true).1.len() < missing.len();
+        let what = format!("from the BAM files are missing in the reference assembly {}", reference.display());
+        let empty = aligned.map_or(false, |aligned| missing.iter().all(|x| !aligned.contains(x)));
+        if missing.len() == bamcontigs.len() || !empty {
+            return Err(Error::Input(contigs_mismatch(&what, &missing, fixable)));
+        }
+        warning = format!(
+            " WARNING: {} contig(s) without aligned reads are missing in the reference assembly: {}.",
+            missing.len(),
+            listed(&missing)
+        );
+    }
+    let mismatches = contiglens.mismatches(&lengths, &names);
+    if let Some((contig, length, reflength)) = mismatches.first() {
+        return Err(Error::Input(format!(
+            "{} contig(s) have different lengths in the BAM headers and the reference assembly {} \
+            (e.g. {}: {} != {}), were reads aligned to a different assembly?",
+            mismatches.len(),
+            reference.display(),
+            contig,
+            length,
+            reflength
+        )));
+    }
+    if names.is_empty() {
+        pbar.finish_with_message(format!("Contig names of the BAM files match the reference assembly.{}", warning));
+    } else {
+        pbar.finish_with_message(format!(
+            "{} contig(s) will be fetched from the reference assembly under the alternative names.{}",
+            names.len(),
+            warning
+        ));
+    }
+    Ok(names)
+}
+
+// CRAM inputs are decoded by htslib with the reference assembly => contigs must have the same names in both.
+// BAM & CRAM inputs can be mixed
+pub fn cram(pbar: ProgressBar, bamfiles: &[PathBuf], refnames: &ContigNames) -> Result<(), Error> {
+    pbar.set_message("Checking formats of the input files...");
+    let mut cram = Vec::new();
+    for file in bamfiles {
+        if hts::is_cram_file(file)? {
+            cram.push(file.display());
+        }
+    }
+    if cram.is_empty() {
+        pbar.finish_with_message("There are no CRAM files among the inputs");
+        return Ok(());
+    }
+    if !refnames.is_empty() {
+        return Err(Error::Input(format!(
+            "CRAM files can't be decoded with renamed contigs (--{}), contig names must match the reference \
+            assembly: {}",
+            args::core::FIX_CONTIG_NAMES,
+            cram.iter().join(" ")
+        )));
+    }
+    pbar.finish_with_message(format!(
+        "CRAM files will be decoded with the reference assembly: {}",
+        cram.iter().join(" ")
+    ));
+    Ok(())
+}
+
+// Contigs of a BED file must be present in the BAM files, otherwise they are renamed (--fix-contig-names) or rejected.
+// Returns renamed records and a warning for contigs absent in the BAM files (if any)
+pub fn bed_contigs(
+    matches: &ArgMatches,
+    what: &str,
+    bamcontigs: &HashSet<String>,
+    bed: Vec<bed::BedRecord>,
+) -> Result<(Vec<bed::BedRecord>, String), Error> {
+    let fix = matches.is_present(args::core::FIX_CONTIG_NAMES);
+    let contigs: HashSet<&str> = bed.iter().map(|x| x.contig()).collect();
+    let (names, missing) = ContigNames::resolve(bamcontigs, contigs.iter().copied(), fix);
+    if missing.is_empty() {
+        return Ok((names.rename_bed(bed), String::new()));
+    }
+
+    let fixable =
+        !fix && ContigNames::resolve(bamcontigs, missing.iter().map(|x| x.as_str()), true).1.len() < missing.len();
+    let what = format!("from the {} are missing in the BAM files", what);
+    // Nothing in common => most likely different naming conventions
+    if missing.len() == contigs.len() {
+        return Err(Error::Input(contigs_mismatch(&what, &missing, fixable)));
+    }
+    Ok((names.rename_bed(bed), format!(" WARNING: {}", contigs_mismatch(&what, &missing, fixable))))
+}
+
 pub fn threads(pbar: ProgressBar, matches: &ArgMatches) -> usize {
     pbar.set_message("Parsing number of threads allowed to launch...");
     let result = matches.value_of(args::core::THREADS).and_then(|x| x.parse().ok()).unwrap();
@@ -202,6 +733,38 @@ pub fn threads(pbar: ProgressBar, matches: &ArgMatches) -> usize {
     result
 }
 
+pub fn hts_threads(pbar: ProgressBar, matches: &ArgMatches) -> u32 {
+    pbar.set_message("Parsing number of htslib decompression threads...");
+    let result = matches.value_of(args::core::HTS_THREADS).and_then(|x| x.parse().ok()).unwrap();
+    if result > 0 {
+        pbar.finish_with_message(format!("BAM files will be decompressed by the shared pool with {} threads", result));
+    } else {
+        pbar.finish_with_message("BAM files will be decompressed by the worker threads");
+    }
+    result
+}
+
+pub fn ref_cache(pbar: ProgressBar, matches: &ArgMatches) -> u32 {
+    pbar.set_message("Parsing reference cache size...");
+    let result = matches.value_of(args::core::REF_CACHE).and_then(|x| x.parse().ok()).unwrap();
+    if result > 0 {
+        pbar.finish_with_message(format!("Each thread will cache up to {}Mb of the reference sequence", result));
+    } else {
+        pbar.finish_with_message("Reference sequence will not be cached");
+    }
+    result
+}
+
+pub fn checkpoint(pbar: ProgressBar, matches: &ArgMatches) -> Option<PathBuf> {
+    pbar.set_message("Parsing checkpoint directory...");
+    let result = matches.value_of(args::core::CHECKPOINT).map(PathBuf::from);
+    match &result {
+        Some(dir) => pbar.finish_with_message(format!("Finished contigs will be saved to {}", dir.display())),
+        None => pbar.finish_with_message("Checkpoints are disabled"),
+    }
+    result
+}
+
 pub fn name(pbar: ProgressBar, matches: &ArgMatches) -> String {
     pbar.set_message("Parsing the run title...");
     let result = matches.value_of(args::core::NAME).and_then(|x| x.parse().ok()).unwrap();
@@ -209,39 +772,214 @@ pub fn name(pbar: ProgressBar, matches: &ArgMatches) -> String {
     result
 }
 
+// Count thresholds are multiplied by the scale of weighted counts, see Multimap
 pub fn outfilter(
     pbar: ProgressBar,
     mismatch_key: &str,
     freq_key: &str,
     cov_key: &str,
+    editing_key: &str,
+    scale: u32,
     matches: &ArgMatches,
 ) -> prefilters::ByMismatches {
     pbar.set_message("Parsing filtering options...");
-    let (minmismatches, minfreq, mincov) = (
+    let (minmismatches, minfreq, mincov): (u32, f32, u32) = (
         matches.value_of(mismatch_key).unwrap().parse().unwrap(),
         matches.value_of(freq_key).unwrap().parse().unwrap(),
         matches.value_of(cov_key).unwrap().parse().unwrap(),
     );
-    let result = prefilters::ByMismatches::new(minmismatches, minfreq, mincov);
+    let editing_only = matches.is_present(editing_key);
     pbar.finish_with_message(format!(
-        "Filtering options: min coverage >= {}; mismatches min number >= {}, min frequency >= {}",
-        result.mincov(),
-        result.minmismatches(),
-        result.minfreq()
+        "Filtering options: min coverage >= {}; {} min number >= {}, min frequency >= {}",
+        mincov,
+        if editing_only { "A->G/T->C mismatches" } else { "mismatches" },
+        minmismatches,
+        minfreq
     ));
-    result
+    prefilters::ByMismatches::new(minmismatches * scale, minfreq, mincov * scale, editing_only)
+}
+
+// Output filters are disabled => only records without coverage are dropped
+pub fn no_outfilter(pbar: ProgressBar) -> prefilters::ByMismatches {
+    pbar.finish_with_message("Filtering options: disabled, records without coverage are dropped");
+    prefilters::ByMismatches::new(0, 0f32, 1, false)
+}
+
+// Same as for outfilter, the mismatches threshold is multiplied by the scale of weighted counts
+pub fn replicates(
+    pbar: ProgressBar,
+    key: &str,
+    scale: u32,
+    bamfiles: &[PathBuf],
+    matches: &ArgMatches,
+) -> Option<prefilters::ByReplicates> {
+    pbar.set_message("Parsing replicate support...");
+    match matches.value_of(key) {
+        None => {
+            pbar.finish_with_message("Replicate support is not required");
+            None
+        }
+        Some(x) => {
+            let (minmismatches, minfiles) = super::validate::replicate_support(x).unwrap();
+            if minfiles > bamfiles.len() {
+                panic!(
+                    "Replicate support requires {} files, but only {} BAM files were given",
+                    minfiles,
+                    bamfiles.len()
+                );
+            }
+            pbar.finish_with_message(format!(
+                "Replicate support: mismatches min number >= {} in at least {} of {} files",
+                minmismatches,
+                minfiles,
+                bamfiles.len()
+            ));
+            Some(prefilters::ByReplicates::new(minmismatches * scale, minfiles))
+        }
+    }
+}
+
+// Selected output columns must be a subset of the layout allowed by other options
+pub fn columns<T: FromStr<Err = String> + PartialEq + Display>(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    key: &str,
+    layout: Vec<T>,
+) -> Vec<T> {
+    pbar.set_message("Parsing output columns...");
+    match matches.value_of(key) {
+        None => {
+            pbar.finish_with_message(format!("All {} columns will be reported", layout.len()));
+            layout
+        }
+        Some(x) => {
+            let columns: Vec<T> = super::validate::columns(x).unwrap();
+            if let Some(disabled) = columns.iter().find(|x| !layout.contains(x)) {
+                panic!("Column {} is disabled by other options and can't be reported", disabled);
+            }
+            pbar.finish_with_message(format!("Output columns: {}", columns.iter().map(|x| x.to_string()).join(",")));
+            columns
+        }
+    }
+}
+
+pub fn format(pbar: ProgressBar, matches: &ArgMatches, key: &str) -> OutputFormat {
+    pbar.set_message("Parsing the output format...");
+    let format = OutputFormat::from_str(matches.value_of(key).unwrap()).unwrap();
+    pbar.finish_with_message(format!("Output format: {}", format));
+    format
+}
+
+pub fn included(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamcontigs: &HashSet<String>,
+) -> Result<Option<Vec<bed::BedRecord>>, Error> {
+    pbar.set_message("Parsing included regions...");
+
+    if let Some(path) = matches.value_of(args::core::INCLUDE_LIST) {
+        let (bed, warning) = bed_contigs(matches, "include list", bamcontigs, bed::parse(Path::new(path))?)?;
+        let bases = bed.iter().map(|x| x.interval.range().end - x.interval.range().start).sum::<u64>();
+        pbar.finish_with_message(format!(
+            "Processing restricted to: {} regions({} bases){}",
+            bed.len(),
+            bases,
+            warning
+        ));
+        Ok(Some(bed))
+    } else {
+        pbar.finish_with_message("Processing is not restricted to any regions");
+        Ok(None)
+    }
 }
 
-pub fn excluded(pbar: ProgressBar, matches: &ArgMatches) -> Option<Vec<bed::BedRecord>> {
+pub fn excluded(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamcontigs: &HashSet<String>,
+) -> Result<Option<Vec<bed::BedRecord>>, Error> {
     pbar.set_message("Parsing excluded regions...");
 
     if let Some(path) = matches.value_of(args::core::EXCLUDE_LIST) {
-        let bed = bed::parse(Path::new(path));
+        let (bed, warning) = bed_contigs(matches, "exclude list", bamcontigs, bed::parse(Path::new(path))?)?;
         let bases = bed.iter().map(|x| x.interval.range().end - x.interval.range().start).sum::<u64>();
-        pbar.finish_with_message(format!("Excluded from the processing: {} regions({} bases)", bed.len(), bases));
-        Some(bed)
+        pbar.finish_with_message(format!(
+            "Excluded from the processing: {} regions({} bases){}",
+            bed.len(),
+            bases,
+            warning
+        ));
+        Ok(Some(bed))
     } else {
         pbar.finish_with_message("No regions will be excluded from the processing");
+        Ok(None)
+    }
+}
+
+pub fn known_snv(pbar: ProgressBar, matches: &ArgMatches, bamcontigs: &HashSet<String>) -> Option<Vec<bed::BedRecord>> {
+    pbar.set_message("Parsing known SNVs...");
+
+    if let Some(path) = matches.value_of(args::core::KNOWN_SNV) {
+        let pass = matches.is_present(args::core::KNOWN_SNV_PASS);
+        // Only contigs present in the BAM files are relevant
+        let snv = vcf::known_snv(path, bamcontigs, pass);
+
+        let mut msg = format!("Known SNVs excluded from the processing: {} loci.", snv.loci.len());
+        if snv.skipped > 0 {
+            msg += &format!(" WARNING: {} multi-allelic records and indels were skipped.", snv.skipped);
+        }
+        pbar.finish_with_message(msg);
+
+        let records = snv
+            .loci
+            .into_iter()
+            .map(|interval| bed::BedRecord {
+                name: "known-snv".to_owned(),
+                strand: Strand::Unknown,
+                interval,
+                blocks: vec![],
+            })
+            .collect();
+        Some(records)
+    } else {
+        pbar.finish_with_message("Known SNVs are not excluded");
         None
     }
 }
+
+pub fn annotate(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<GenomicFeatures>, Error> {
+    pbar.set_message("Parsing the annotation to label the results...");
+    match matches.value_of(args::core::ANNOTATE) {
+        None => {
+            pbar.finish_with_message("Results are not annotated");
+            Ok(None)
+        }
+        Some(path) => {
+            let annotation = GenomicFeatures::from_gff(Path::new(path), |_| pbar.inc(1))?;
+            pbar.finish_with_message(format!("Results will be annotated with genomic features from {}", path));
+            Ok(Some(annotation))
+        }
+    }
+}
+
+// Counts of sites are scaled by the multimap scale, ROI counts are always in natural units
+pub fn filter_expr(
+    pbar: ProgressBar,
+    key: &str,
+    records: Records,
+    scale: u32,
+    matches: &ArgMatches,
+) -> Option<ByExpression> {
+    pbar.set_message("Parsing the filter expression...");
+    match matches.value_of(key) {
+        None => {
+            pbar.finish_with_message("Filter expression is not specified");
+            None
+        }
+        Some(expr) => {
+            let filter = ByExpression::new(expr, records).unwrap().with_scale(scale);
+            pbar.finish_with_message(format!("Only records matching \"{}\" will be reported", expr));
+            Some(filter)
+        }
+    }
+}
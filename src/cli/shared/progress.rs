@@ -0,0 +1,243 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use clap::ArgMatches;
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+use crate::cli::shared;
+use crate::core::pipeline::{EventSink, Progress};
+use crate::core::strandutil::Stranded;
+
+use super::args;
+
+const PROGRESS_IO_ERROR: &str = "Failed to report the processing progress.";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ProgressMode {
+    // Interactive progress bars
+    Bars,
+    // JSON lines written to stderr every N processed workload items
+    Json(u64),
+    // Silent run
+    None,
+}
+
+impl ProgressMode {
+    pub fn new(matches: &ArgMatches) -> Self {
+        match matches.value_of(args::core::PROGRESS).unwrap() {
+            "bars" => ProgressMode::Bars,
+            "json" => ProgressMode::Json(matches.value_of(args::core::PROGRESS_EVERY).unwrap().parse().unwrap()),
+            "none" => ProgressMode::None,
+            x => panic!("Unknown progress mode: {}", x),
+        }
+    }
+
+    pub fn bars(&self) -> bool {
+        *self == ProgressMode::Bars
+    }
+
+    pub fn reporter(&self, pbar: impl FnOnce() -> ProgressBar) -> Option<Box<dyn Progress>> {
+        match self {
+            ProgressMode::Bars => Some(Box::new(pbar())),
+            ProgressMode::Json(every) => Some(Box::new(JsonLinesProgress::new(std::io::stderr(), *every))),
+            ProgressMode::None => None,
+        }
+    }
+}
+
+impl Progress for ProgressBar {
+    fn start(&self, total: u64) {
+        self.set_style(shared::style::run::running());
+        self.set_length(total);
+    }
+
+    fn inc(&self, delta: u64, _reads: u32) {
+        ProgressBar::inc(self, delta)
+    }
+
+    fn finish(
+        &self,
+        items: usize,
+        reads: Stranded<u32>,
+        mapq_255_dropped: u32,
+        umi_collapsed: u32,
+        coverage_capped: u32,
+        refn_skipped: u64,
+    ) {
+        self.set_style(shared::style::run::finished());
+        let mut message = format!("Finished with {} items, processed reads: {}", items, reads);
+        if mapq_255_dropped > 0 {
+            message = format!("{}, dropped reads with mapq=255: {}", message, mapq_255_dropped);
+        }
+        if umi_collapsed > 0 {
+            message = format!("{}, collapsed UMI duplicates: {}", message, umi_collapsed);
+        }
+        if coverage_capped > 0 {
+            message = format!("{}, skipped reads due to the coverage cap: {}", message, coverage_capped);
+        }
+        if refn_skipped > 0 {
+            message = format!("{}, skipped positions with unknown(N) reference: {}", message, refn_skipped);
+        }
+        self.finish_with_message(message);
+    }
+
+    fn warn(&self, message: &str) {
+        self.println(format!("Warning: {}", message));
+    }
+}
+
+// Plain progress bar for embedders, batches are not rendered
+impl<Mismatches> EventSink<Mismatches> for ProgressBar {
+    fn on_batch(&self, _contig: &str, _items: &Mismatches) {}
+
+    fn on_progress(&self, done: u64, total: u64) {
+        self.set_length(total);
+        self.set_position(done);
+    }
+
+    fn on_finish(&self, reads: u32) {
+        self.finish_with_message(format!("Finished, processed reads: {}", reads));
+    }
+}
+
+#[derive(Serialize)]
+struct Record {
+    phase: &'static str,
+    items_done: u64,
+    items_total: u64,
+    reads_counted: u64,
+    elapsed: f64,
+}
+
+#[derive(Serialize)]
+struct Warning<'a> {
+    phase: &'static str,
+    message: &'a str,
+    elapsed: f64,
+}
+
+// One JSON record per line, suitable for log files & workflow managers
+pub struct JsonLinesProgress<W: Write + Send> {
+    writer: Mutex<W>,
+    every: u64,
+    started: Instant,
+    total: AtomicU64,
+    done: AtomicU64,
+    reads: AtomicU64,
+}
+
+impl<W: Write + Send> JsonLinesProgress<W> {
+    pub fn new(writer: W, every: u64) -> Self {
+        debug_assert!(every > 0);
+        Self {
+            writer: Mutex::new(writer),
+            every,
+            started: Instant::now(),
+            total: AtomicU64::new(0),
+            done: AtomicU64::new(0),
+            reads: AtomicU64::new(0),
+        }
+    }
+
+    fn report(&self, phase: &'static str, items_done: u64, reads_counted: u64) {
+        self.write(&Record {
+            phase,
+            items_done,
+            items_total: self.total.load(Ordering::Relaxed),
+            reads_counted,
+            elapsed: self.started.elapsed().as_secs_f64(),
+        });
+    }
+
+    fn write(&self, record: &impl Serialize) {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, record)
+            .map_err(|x| x.into())
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush())
+            .unwrap_or_else(|x: std::io::Error| panic!("{} {}", PROGRESS_IO_ERROR, x));
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Progress for JsonLinesProgress<W> {
+    fn start(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+        self.report("running", 0, 0);
+    }
+
+    fn inc(&self, delta: u64, reads: u32) {
+        let reads = self.reads.fetch_add(reads as u64, Ordering::Relaxed) + reads as u64;
+        let done = self.done.fetch_add(delta, Ordering::Relaxed);
+        // Report once per each crossed multiple of `every`
+        if (done + delta) / self.every > done / self.every {
+            self.report("running", done + delta, reads);
+        }
+    }
+
+    fn finish(&self, _items: usize, reads: Stranded<u32>, _mapq255: u32, _umi: u32, _capped: u32, _refn: u64) {
+        let reads = reads.forward as u64 + reads.reverse as u64 + reads.unknown as u64;
+        self.report("finished", self.done.load(Ordering::Relaxed), reads);
+    }
+
+    fn warn(&self, message: &str) {
+        self.write(&Warning { phase: "warning", message, elapsed: self.started.elapsed().as_secs_f64() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let progress = JsonLinesProgress::new(vec![], 2);
+        progress.start(5);
+        for reads in [1, 2, 3, 4, 5] {
+            progress.inc(1, reads);
+        }
+        progress.finish(3, Stranded { forward: 10, reverse: 4, unknown: 1 }, 0, 0, 0, 0);
+
+        let written = String::from_utf8(progress.into_inner()).unwrap();
+        let records: Vec<serde_json::Value> = written.lines().map(|x| serde_json::from_str(x).unwrap()).collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|x| {
+                assert_eq!(x["items_total"], 5);
+                assert!(x["elapsed"].as_f64().unwrap() >= 0f64);
+                (x["phase"].as_str().unwrap(), x["items_done"].as_u64().unwrap(), x["reads_counted"].as_u64().unwrap())
+            })
+            .collect();
+        assert_eq!(summary, vec![("running", 0, 0), ("running", 2, 3), ("running", 4, 10), ("finished", 5, 15)]);
+    }
+
+    #[test]
+    fn json_warnings() {
+        let progress = JsonLinesProgress::new(vec![], 1);
+        progress.warn("contig chrM is absent");
+
+        let written = String::from_utf8(progress.into_inner()).unwrap();
+        let record: serde_json::Value = serde_json::from_str(written.trim_end()).unwrap();
+        assert_eq!(record["phase"], "warning");
+        assert_eq!(record["message"], "contig chrM is absent");
+        assert!(record["elapsed"].as_f64().unwrap() >= 0f64);
+    }
+
+    #[test]
+    fn events() {
+        let pbar = ProgressBar::hidden();
+        let sink: &dyn EventSink<()> = &pbar;
+        sink.on_progress(0, 4);
+        sink.on_batch("chr1", &());
+        sink.on_progress(3, 4);
+        assert_eq!((pbar.position(), pbar.length()), (3, 4));
+        sink.on_finish(10);
+        assert!(pbar.is_finished());
+    }
+}
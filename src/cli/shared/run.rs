@@ -1,110 +1,192 @@
 use std::any::Any;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use indicatif::ProgressBar;
-use itertools::Itertools;
-use rayon::prelude::*;
 
-use crate::cli::shared;
-use crate::cli::shared::thread_cache::ThreadCache;
-use crate::core::hooks::stats::EditingStatType;
-use crate::core::hooks::stats::ROIEditingIndex;
-use crate::core::mismatches::{Batch, MismatchesVec};
-use crate::core::runner::Runner;
-use crate::core::strandutil::Stranded;
+use crate::cli::shared::args::core::ALLOW_EMPTY;
+use crate::cli::shared::output::SaveTo;
+use crate::cli::shared::parse;
+use crate::core::hooks::stats::SubstitutionRates;
+use crate::core::hooks::stats::{EditingStat, EditingStatType};
+use crate::core::hooks::stats::{
+    ROIContigEditingIndex, ROIConversionRate, ROIEditingIndex, ROIMismatchSpectrum, StrandSplitEditingIndex,
+};
+use crate::core::io::contigs::ContigLengths;
+use crate::core::io::utils::{FinishWrite, OutputWriter};
+use crate::core::mismatches::MismatchesVec;
+use crate::core::pipeline::ContigSummary;
+use crate::core::rpileup::ncounter::filters::{ByTags, Criterion, FilterStats};
+use crate::error::Error;
 
-const OUTPUT_IO_ERROR: &str = "Failed to write results to the output TSV file.";
-const STATS_IO_ERROR: &str = "Failed to write statistics to the output TSV file.";
+const STATS_WRITE_ERROR: &str = "Failed to write statistics";
 
-pub fn run<RunnerT, Mismatches, Workload, W: io::Write>(
-    workload: Vec<Workload>,
-    runner: RunnerT,
-    pbar: ProgressBar,
-    saveto: &mut csv::Writer<W>,
-    mut statsto: HashMap<EditingStatType, csv::Writer<W>>,
-) -> csv::Result<()>
-where
-    Mismatches: Send + MismatchesVec,
-    Workload: Sized + Send,
-    RunnerT: for<'runner> Runner<'runner, Mismatches, Workload = Workload> + Clone + Send,
-{
-    // Callbacks to track progress
-    pbar.set_style(shared::style::run::running());
-
-    // let delta = std::cmp::min(rayon::current_num_threads() * 10, workload.len() / 10 + 1);
-    // pbar.set_draw_delta(delta as u64);
-
-    pbar.set_length(workload.len() as u64);
-
-    let ctxstore = ThreadCache::new(move || RefCell::new(runner.clone()));
-    let edits: Vec<Batch<Mismatches>> = workload
-        .into_par_iter()
-        // .into_iter()
-        .filter_map(|w| {
-            let result = ctxstore.get().borrow_mut().run(w);
-            pbar.inc(1);
-            result
-        })
-        .collect();
-
-    // Report the result
-    pbar.set_style(shared::style::run::finished());
-    let reads: Stranded<u32> = edits.iter().map(|x| x.mapped).fold(Default::default(), |a, b| a + b);
-    let items: usize = edits
-        .iter()
-        .map(|x| {
-            x.retained.unknown.len()
-                + x.retained.forward.len()
-                + x.retained.reverse.len()
-                + x.items.unknown.len()
-                + x.items.forward.len()
-                + x.items.reverse.len()
-        })
-        .sum();
-    pbar.finish_with_message(format!("Finished with {} items, processed reads: {}", items, reads));
-
-    // Group stats by type
-    let stats = ctxstore.dissolve().flat_map(|x| x.into_inner().stats());
-    let grouped: HashMap<EditingStatType, Vec<Box<dyn Any>>> = HashMap::new();
-    for _ in stats {
-        // let (typed, any) = stat.into_any();
-        // grouped.entry(typed).or_default().push(any);
-        println!("STATS DISABLED!")
+pub fn finalize<Mismatches: MismatchesVec>(
+    stats: Vec<Box<dyn EditingStat<Mismatches>>>,
+    saveto: &mut SaveTo,
+    mut statsto: HashMap<EditingStatType, csv::Writer<OutputWriter>>,
+) -> Result<(), Error> {
+    // Group stats by type, ordered => stats sharing the same output are always written in the same order
+    let mut grouped: BTreeMap<EditingStatType, Vec<Box<dyn Any>>> = BTreeMap::new();
+    for stat in stats {
+        let (typed, any) = stat.into_any();
+        grouped.entry(typed).or_default().push(any);
     }
 
     // Collapse identical stats & write them into requested serializers
+    let failed = |x: csv::Error| Error::output(STATS_WRITE_ERROR, x);
     for (k, v) in grouped.into_iter() {
-        if let Some(serializer) = statsto.get_mut(&k) {
+        if let Some(serializer) = statsto.get_mut(&k.output()) {
             match k {
-                EditingStatType::ROIEditingIndex => {
-                    serializer.serialize(ROIEditingIndex::collapse(v)).expect(STATS_IO_ERROR)
+                EditingStatType::ROIEditingIndex(_) => {
+                    ROIEditingIndex::collapse(v).to_csv(serializer).map_err(failed)?
+                }
+                EditingStatType::ROIContigEditingIndex => {
+                    ROIContigEditingIndex::collapse(v).to_csv(serializer).map_err(failed)?
+                }
+                EditingStatType::ROIConversionRate => {
+                    for stat in ROIConversionRate::collapse(v) {
+                        stat.to_csv(serializer).map_err(failed)?
+                    }
+                }
+                EditingStatType::ROIMismatchSpectrum => {
+                    ROIMismatchSpectrum::collapse(v).to_csv(serializer).map_err(failed)?
+                }
+                EditingStatType::StrandSplitEditingIndex => {
+                    StrandSplitEditingIndex::collapse(v).to_csv(serializer).map_err(failed)?
+                }
+                EditingStatType::SubstitutionRates => {
+                    SubstitutionRates::collapse(v).to_csv(serializer).map_err(failed)?
                 }
             };
         };
     }
 
-    // Group by contigs
-    let mut percontig = HashMap::with_capacity(120);
-    for batch in edits {
-        for item in [batch.items, batch.retained] {
-            for mm in [item.forward, item.unknown, item.reverse] {
-                if mm.is_empty() {
-                    continue;
-                }
-                if !percontig.contains_key(mm.contig()) {
-                    percontig.insert(mm.contig().to_owned(), vec![]);
-                }
-                percontig.get_mut(mm.contig()).unwrap().push(mm);
-            }
+    // Explicitly finalize all outputs to report any IO errors here instead of silently ignoring them on drop
+    saveto.finish()?;
+    for writer in statsto.values_mut() {
+        writer.flush().map_err(|x| failed(x.into()))?;
+        writer.get_mut().finish().map_err(|x| failed(x.into()))?;
+    }
+    Ok(())
+}
+
+// Reported records must lie within their contigs, otherwise downstream tools (e.g. tabix) reject the output.
+// ROIs are validated upfront => this is a safety net, returns the number of records past the contig ends
+pub fn past_end<T: MismatchesVec>(items: &[T], lengths: &ContigLengths) -> usize {
+    let past = items.iter().map(|x| lengths.get(x.contig()).map_or(0, |length| x.past_end(length))).sum();
+    debug_assert_eq!(past, 0, "Reported records extend past the contig ends");
+    past
+}
+
+pub fn report_past_end(past: usize, pbar: impl FnOnce() -> ProgressBar) {
+    if past > 0 {
+        pbar().finish_with_message(format!(
+            "WARNING: {} reported records extend past the end of their contigs (according to the BAM headers)",
+            past
+        ));
+    }
+}
+
+// Reads matched & rejected by the read groups / cell barcodes filters, nothing is reported if they are disabled
+pub fn report_tags(tags: &ByTags, pbar: impl FnOnce() -> ProgressBar) {
+    let report: Vec<String> = [("read groups", tags.read_groups()), ("cell barcodes", tags.barcodes())]
+        .into_iter()
+        .filter_map(|(what, counts)| {
+            counts.map(|x| format!("{}: {} reads matched, {} rejected", what, x.matched(), x.rejected()))
+        })
+        .collect();
+    if !report.is_empty() {
+        pbar().finish_with_message(format!("Reads filtered by {}", report.join("; ")));
+    }
+}
+
+pub fn report_filtered(stats: &FilterStats, pbar: impl FnOnce() -> ProgressBar) {
+    let criteria: Vec<String> = Criterion::ALL
+        .into_iter()
+        .map(|x| (x.name(), stats.rejected_by(x)))
+        .filter(|(_, rejected)| *rejected > 0)
+        .map(|(name, rejected)| format!("{}: {}", name, rejected))
+        .collect();
+    let mut message = format!("Reads filter: {} reads accepted, {} rejected", stats.accepted(), stats.rejected());
+    if !criteria.is_empty() {
+        message = format!("{} ({})", message, criteria.join(", "));
+    }
+    pbar().finish_with_message(message);
+}
+
+// Most likely reason why no reads were counted for the whole workload
+fn empty_reason(contigs: &[ContigSummary], aligned: Option<&HashSet<String>>, stale_index: bool) -> String {
+    let processed: Vec<String> = contigs.iter().filter(|x| x.workload > 0).map(|x| x.contig.clone()).collect();
+    match aligned {
+        Some(aligned) if processed.iter().all(|x| !aligned.contains(x)) => format!(
+            "processed contigs have no aligned reads according to the BAM indexes ({}), \
+            were reads and regions taken from different assemblies?",
+            parse::listed(&processed)
+        ),
+        _ if stale_index => "BAM indexes are older than the BAM files and might be stale, \
+            re-index them with `samtools index`"
+            .to_owned(),
+        None => "contigs with aligned reads can't be checked without BAI indexes, \
+            make sure that reads and regions come from the same assembly"
+            .to_owned(),
+        Some(_) => {
+            "no reads overlap the processed regions or all of them were rejected by the reads filters".to_owned()
         }
     }
-    // Sort by contig name
-    let percontig = percontig.into_iter().sorted_by(|x, y| x.0.cmp(&y.0));
+}
 
-    for items in percontig {
-        Mismatches::ugly_in_contig_sort_and_to_csv(items.1, saveto).expect(OUTPUT_IO_ERROR);
+// Empty outputs are an error unless explicitly allowed: they are usually caused by mismatched inputs.
+// Items dropped by the output filters are not empty, only items without any counted reads are
+pub fn check_empty(
+    contigs: &[ContigSummary],
+    aligned: Option<&HashSet<String>>,
+    stale_index: bool,
+    allow: bool,
+    pbar: impl FnOnce() -> ProgressBar,
+) -> Result<(), Error> {
+    // Nothing was processed at all, e.g. all contigs were finished by a previous run
+    let workload: usize = contigs.iter().map(|x| x.workload).sum();
+    if workload == 0 || contigs.iter().any(|x| x.empty < x.workload) {
+        return Ok(());
+    }
+    let message = format!(
+        "no reads were counted for any of {} genome bins/ROIs: {}",
+        workload,
+        empty_reason(contigs, aligned, stale_index)
+    );
+    if allow {
+        pbar().finish_with_message(format!("WARNING: {}", message));
+        return Ok(());
+    }
+    Err(Error::Empty(format!("{}. Use --{} to accept empty results", message, ALLOW_EMPTY)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_reasons() {
+        let summary = |contig: &str, workload| ContigSummary {
+            contig: contig.into(),
+            covered: 0,
+            emitted: 0,
+            workload,
+            empty: workload,
+        };
+        let contigs = [summary("1", 2), summary("2", 0)];
+        let aligned: HashSet<String> = ["2".to_owned(), "MT".to_owned()].into_iter().collect();
+
+        // Contigs without workload are not considered, the mismatch takes priority over stale indexes
+        let reason = empty_reason(&contigs, Some(&aligned), true);
+        assert!(
+            reason.starts_with("processed contigs have no aligned reads according to the BAM indexes (1)"),
+            "{}",
+            reason
+        );
+        let aligned: HashSet<String> = ["1".to_owned()].into_iter().collect();
+        assert!(empty_reason(&contigs, Some(&aligned), true).contains("might be stale"));
+        assert!(empty_reason(&contigs, None, false).contains("without BAI indexes"));
+        assert!(empty_reason(&contigs, Some(&aligned), false).contains("rejected by the reads filters"));
     }
-    Ok(())
 }
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches};
+use flate2::Crc;
+use serde::{Deserialize, Serialize};
+
+use crate::core::io::hts;
+use crate::core::strandutil::Stranded;
+use crate::error::Error;
+
+use super::args;
+
+// Arguments that don't affect the results
+const NOT_RESULTS: [&str; 9] = [
+    args::core::SAVETO,
+    args::core::RUN_INFO,
+    args::core::CHECKPOINT,
+    args::core::THREADS,
+    args::core::HTS_THREADS,
+    args::core::PREFETCH,
+    args::core::REF_CACHE,
+    args::core::PROGRESS,
+    args::core::PROGRESS_EVERY,
+];
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InputFile {
+    pub path: String,
+    // Size in bytes
+    pub size: u64,
+}
+
+// Genome positions that were not counted at all
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Skipped {
+    // Bases removed by the exclude list (site mode)
+    pub excluded: u64,
+    // ROI bases removed by the exclude list or the mask (ROI mode)
+    pub masked: u64,
+    // Positions with unknown(N) reference dropped by --ref-n-policy skip
+    pub refn: u64,
+}
+
+// Run provenance: resolved parameters & summary counters
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RunMeta {
+    pub version: String,
+    pub command: String,
+    pub inputs: Vec<InputFile>,
+    pub reference: String,
+    pub stranding: String,
+    // All arguments after resolving defaults; flags have no values
+    pub parameters: BTreeMap<String, Vec<String>>,
+    // Populated once the processing is finished
+    pub bins: usize,
+    pub items: usize,
+    pub mapped: u64,
+    pub skipped: Skipped,
+    // Reference bases read from the FASTA file, re-reads included
+    #[serde(default)]
+    pub refbases: u64,
+    pub seconds: f64,
+}
+
+impl RunMeta {
+    pub fn new(command: &str, matches: &ArgMatches, arguments: &[Arg], bamfiles: &[PathBuf]) -> Result<Self, Error> {
+        let inputs = bamfiles
+            .iter()
+            .map(|x| -> Result<InputFile, Error> {
+                Ok(InputFile {
+                    path: x.display().to_string(),
+                    // Size of the streamed stdin is unknown
+                    size: match x.as_path() == Path::new(hts::STDIN) {
+                        true => 0,
+                        false => fs::metadata(x).map_err(|e| Error::io(x, e))?.len(),
+                    },
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut parameters = BTreeMap::new();
+        for arg in arguments {
+            let id = arg.get_id();
+            if !matches.is_present(id) {
+                continue;
+            }
+            let values = matches.values_of(id).map_or(vec![], |x| x.map(|v| v.to_owned()).collect());
+            parameters.insert(id.to_owned(), values);
+        }
+
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            command: command.to_owned(),
+            inputs,
+            reference: matches.value_of(args::core::REFERENCE).unwrap().to_owned(),
+            stranding: matches.value_of(args::core::STRANDING).unwrap().to_owned(),
+            parameters,
+            bins: 0,
+            items: 0,
+            mapped: 0,
+            skipped: Skipped::default(),
+            refbases: 0,
+            seconds: 0f64,
+        })
+    }
+
+    pub fn onfinish(&mut self, bins: usize, items: usize, reads: Stranded<u32>, skipped: Skipped, elapsed: Duration) {
+        self.bins = bins;
+        self.items = items;
+        self.mapped = reads.forward as u64 + reads.reverse as u64 + reads.unknown as u64;
+        self.skipped = skipped;
+        self.seconds = elapsed.as_secs_f64();
+    }
+
+    // Checksum of everything that affects the results: version, inputs & their sizes, parameters
+    pub fn fingerprint(&self) -> String {
+        let mut meta = Self {
+            bins: 0,
+            items: 0,
+            mapped: 0,
+            skipped: Skipped::default(),
+            refbases: 0,
+            seconds: 0f64,
+            ..self.clone()
+        };
+        meta.parameters.retain(|k, _| !NOT_RESULTS.contains(&k.as_str()));
+
+        let mut crc = Crc::new();
+        crc.update(&serde_json::to_vec(&meta).expect("Failed to serialize run info"));
+        format!("{:08x}", crc.sum())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path).map_err(|x| Error::io(path, x))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|x| x.into())
+            .and_then(|_| writer.flush())
+            .map_err(|x: std::io::Error| Error::io(path, x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Command;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let bam = NamedTempFile::new().unwrap();
+        fs::write(bam.path(), b"1234567").unwrap();
+
+        let arguments = args::all();
+        let path = bam.path().to_str().unwrap();
+        let matches = Command::new("test").args(arguments.clone()).get_matches_from([
+            "test",
+            "-i",
+            path,
+            "-r",
+            path,
+            "-s",
+            "f/s",
+            "--hyperedit",
+        ]);
+
+        let mut meta = RunMeta::new("roi", &matches, &arguments, &[bam.path().to_owned()]).unwrap();
+        let skipped = Skipped { excluded: 10, masked: 0, refn: 2 };
+        meta.onfinish(12, 3, Stranded { forward: 1, reverse: 2, unknown: 3 }, skipped, Duration::from_millis(1500));
+
+        assert_eq!(meta.inputs, vec![InputFile { path: bam.path().display().to_string(), size: 7 }]);
+        assert_eq!((meta.reference.as_str(), meta.stranding.as_str()), (path, "f/s"));
+        assert_eq!((meta.bins, meta.items, meta.mapped, meta.seconds), (12, 3, 6, 1.5));
+        assert_eq!(meta.skipped, skipped);
+        // Defaults & flags are recorded, absent options are not
+        assert_eq!(meta.parameters[args::autoref::MIN_FREQ], vec!["0.95"]);
+        assert!(meta.parameters[args::autoref::HYPEREDITING].is_empty());
+        assert!(!meta.parameters.contains_key(args::autoref::VCF));
+
+        let saveto = NamedTempFile::new().unwrap();
+        meta.save(saveto.path()).unwrap();
+        let restored: RunMeta = serde_json::from_reader(File::open(saveto.path()).unwrap()).unwrap();
+        assert_eq!(restored, meta);
+    }
+
+    #[test]
+    fn fingerprint() {
+        let bam = NamedTempFile::new().unwrap();
+        let path = bam.path().to_str().unwrap();
+        let arguments = args::all();
+        let meta = |extra: &[&str]| {
+            let cmd = ["test", "-i", path, "-r", path, "-s", "f/s"].iter().chain(extra.iter());
+            let matches = Command::new("test").args(arguments.clone()).get_matches_from(cmd);
+            RunMeta::new("roi", &matches, &arguments, &[bam.path().to_owned()]).unwrap()
+        };
+
+        let expected = meta(&[]).fingerprint();
+        assert_eq!(expected.len(), 8);
+        // Threads, prefetching, caching, progress & output paths don't matter
+        assert_eq!(
+            meta(&["-t", "8", "--prefetch", "--ref-cache", "0", "--progress", "none", "-o", "out.tsv"]).fingerprint(),
+            expected
+        );
+        for extra in [&["--mapq", "5"][..], &["--hyperedit"], &["-5", "1"]] {
+            assert_ne!(meta(extra).fingerprint(), expected);
+        }
+        // Input files must be the same
+        fs::write(bam.path(), b"1234567").unwrap();
+        assert_ne!(meta(&[]).fingerprint(), expected);
+    }
+}
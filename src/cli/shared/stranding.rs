@@ -4,12 +4,88 @@ use std::str::FromStr;
 use crate::core::stranding::deduce::StrandSpecificExperimentDesign;
 use crate::core::stranding::deduce::StrandSpecificExperimentDesign::*;
 
+// Strand prediction algorithm with optional thresholds: name[:min mismatches:min freq]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StrandingAlgoSpec {
+    Features,
+    AtoI(Option<(u32, f32)>),
+    CtoU(Option<(u32, f32)>),
+}
+
+impl FromStr for StrandingAlgoSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split(':');
+        let name = split.next().unwrap();
+        let params: Vec<&str> = split.collect();
+
+        let thresholds = match params.as_slice() {
+            [] => None,
+            [minmismatches, minfreq] => {
+                let minmismatches = minmismatches
+                    .parse()
+                    .map_err(|_| format!("Failed to parse min mismatches \"{}\" in \"{}\"", minmismatches, s))?;
+                let minfreq: f32 =
+                    minfreq.parse().map_err(|_| format!("Failed to parse min freq \"{}\" in \"{}\"", minfreq, s))?;
+                if !(0f32..=1f32).contains(&minfreq) {
+                    return Err(format!("Min freq is expected to be inside [0, 1] range in \"{}\"", s));
+                }
+                Some((minmismatches, minfreq))
+            }
+            _ => {
+                return Err(format!(
+                    "Expected either no parameters or exactly two(name:min mismatches:min freq), got \"{}\"",
+                    s
+                ))
+            }
+        };
+
+        match name {
+            "features" if thresholds.is_some() => Err(format!("\"features\" doesn't accept parameters, got \"{}\"", s)),
+            "features" => Ok(StrandingAlgoSpec::Features),
+            "editing" | "a2i" => Ok(StrandingAlgoSpec::AtoI(thresholds)),
+            "c2u" => Ok(StrandingAlgoSpec::CtoU(thresholds)),
+            _ => Err(format!("Unknown stranding algorithm: {}", name)),
+        }
+    }
+}
+
+// Mode-specific A->I editing thresholds: min mismatches:min freq
+pub fn editing_thresholds(s: &str) -> Result<(u32, f32), String> {
+    let (minmismatches, minfreq) =
+        s.split_once(':').ok_or_else(|| format!("Expected min mismatches:min freq, got \"{}\"", s))?;
+    let minmismatches: u32 = minmismatches
+        .parse()
+        .map_err(|_| format!("Failed to parse min mismatches \"{}\" in \"{}\"", minmismatches, s))?;
+    let minfreq: f32 = minfreq.parse().map_err(|_| format!("Failed to parse min freq \"{}\" in \"{}\"", minfreq, s))?;
+    if !(0f32..=1f32).contains(&minfreq) {
+        return Err(format!("Min freq is expected to be inside [0, 1] range in \"{}\"", s));
+    }
+    if minfreq == 0f32 && minmismatches > 0 {
+        return Err(format!(
+            "Zero min freq with nonzero min mismatches is not allowed in \"{}\", use a positive min freq",
+            s
+        ));
+    }
+    Ok((minmismatches, minfreq))
+}
+
 #[derive(Eq, PartialEq)]
 pub enum Stranding {
     Unstranded,
     Stranded(StrandSpecificExperimentDesign),
 }
 
+impl Stranding {
+    pub fn design(&self) -> Option<StrandSpecificExperimentDesign> {
+        match self {
+            Stranding::Unstranded => None,
+            Stranding::Stranded(x) => Some(*x),
+        }
+    }
+}
+
 impl FromStr for Stranding {
     type Err = String;
 
@@ -18,8 +94,8 @@ impl FromStr for Stranding {
             "u" | "unstranded" => Ok(Stranding::Unstranded),
             "s" => Ok(Stranding::Stranded(Same)),
             "f" => Ok(Stranding::Stranded(Flip)),
-            "s/f" => Ok(Stranding::Stranded(Same1Flip2)),
-            "f/s" => Ok(Stranding::Stranded(Flip1Same2)),
+            "s/f" | "fr-secondstrand" => Ok(Stranding::Stranded(Same1Flip2)),
+            "f/s" | "fr-firststrand" => Ok(Stranding::Stranded(Flip1Same2)),
             _ => Err(format!("Unknown strand: {}", s)),
         }
     }
@@ -34,3 +110,52 @@ impl Display for Stranding {
         write!(f, "{}", symbol)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stranding_algo() {
+        for (symbol, expected) in [
+            ("features", StrandingAlgoSpec::Features),
+            ("editing", StrandingAlgoSpec::AtoI(None)),
+            ("a2i", StrandingAlgoSpec::AtoI(None)),
+            ("editing:8:0.05", StrandingAlgoSpec::AtoI(Some((8, 0.05)))),
+            ("c2u:0:1", StrandingAlgoSpec::CtoU(Some((0, 1.0)))),
+        ] {
+            assert_eq!(StrandingAlgoSpec::from_str(symbol), Ok(expected));
+        }
+        for symbol in ["", "annotation", "features:1:0.1", "editing:8", "editing:8:0.05:1", "editing:-1:0.1", "c2u:1:2"]
+        {
+            assert!(StrandingAlgoSpec::from_str(symbol).is_err());
+        }
+    }
+
+    #[test]
+    fn stranding() {
+        for (symbol, expected) in [
+            ("u", Stranding::Unstranded),
+            ("unstranded", Stranding::Unstranded),
+            ("s", Stranding::Stranded(Same)),
+            ("f", Stranding::Stranded(Flip)),
+            ("s/f", Stranding::Stranded(Same1Flip2)),
+            ("fr-secondstrand", Stranding::Stranded(Same1Flip2)),
+            ("f/s", Stranding::Stranded(Flip1Same2)),
+            ("fr-firststrand", Stranding::Stranded(Flip1Same2)),
+        ] {
+            assert!(Stranding::from_str(symbol) == Ok(expected));
+        }
+        assert!(Stranding::from_str("fr-unstranded").is_err());
+    }
+
+    #[test]
+    fn editing_thresholds() {
+        for (symbol, expected) in [("20:0.05", (20, 0.05)), ("3:0.1", (3, 0.1)), ("0:0", (0, 0.0)), ("0:1", (0, 1.0))] {
+            assert_eq!(super::editing_thresholds(symbol), Ok(expected));
+        }
+        for symbol in ["", "3", "3:0.1:1", "-1:0.1", "3:1.5", "a:0.1", "3:0", "8:0.0"] {
+            assert!(super::editing_thresholds(symbol).is_err());
+        }
+    }
+}
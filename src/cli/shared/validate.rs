@@ -1,7 +1,13 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use crate::cli::shared::stranding::Stranding;
+use regex::Regex;
+
+use crate::cli::shared::stranding::{self, Stranding, StrandingAlgoSpec};
+use crate::core::dna::ReqNucleotide;
+use crate::core::hooks::filters::{ByExpression, Records};
+use crate::core::io::hts;
+use crate::core::rpileup::ncounter::cnt::MinROIOverlap;
 
 pub fn path(rawpath: &str) -> Result<(), String> {
     let path = Path::new(&rawpath);
@@ -12,6 +18,20 @@ pub fn path(rawpath: &str) -> Result<(), String> {
     }
 }
 
+// Input BAM file, optionally named as NAME=PATH. "-" stands for the standard input
+pub fn input(input: &str) -> Result<(Option<String>, PathBuf), String> {
+    let inpath = |rawpath: &str| if rawpath == hts::STDIN { Ok(()) } else { path(rawpath) };
+    if Path::new(input).exists() {
+        return Ok((None, PathBuf::from(input)));
+    }
+    match input.split_once('=') {
+        Some((name, rawpath)) if !name.is_empty() => {
+            inpath(rawpath).map(|_| (Some(name.to_owned()), PathBuf::from(rawpath)))
+        }
+        _ => inpath(input).map(|_| (None, PathBuf::from(input))),
+    }
+}
+
 pub fn writable(_rawpath: &str) -> Result<(), String> {
     // TODO: are there any good way to actually check that file is writeable?
     Ok(())
@@ -34,6 +54,21 @@ pub fn stranding(stranding: &str) -> Result<(), String> {
     }
 }
 
+pub fn stranding_algo(algo: &str) -> Result<(), String> {
+    StrandingAlgoSpec::from_str(algo).map(|_| ())
+}
+
+pub fn editing_thresholds(thresholds: &str) -> Result<(), String> {
+    stranding::editing_thresholds(thresholds).map(|_| ())
+}
+
+pub fn bamtag(tag: &str) -> Result<(), String> {
+    match tag.as_bytes() {
+        [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => Ok(()),
+        _ => Err(format!("BAM tag must be 2 characters long, [A-Za-z][A-Za-z0-9], got {}", tag)),
+    }
+}
+
 pub fn numeric<T>(low: T, upper: T) -> impl Fn(&str) -> Result<(), String>
 where
     T: FromStr + std::fmt::Display + std::cmp::PartialOrd + Sized,
@@ -57,10 +92,10 @@ where
 mod test {
     #[test]
     fn stranding() {
-        for symbol in ["u", "s", "f", "s/f", "f/s"] {
+        for symbol in ["u", "s", "f", "s/f", "f/s", "fr-firststrand", "fr-secondstrand"] {
             assert!(super::stranding(symbol).is_ok());
         }
-        for symbol in [".", "r", "uf", "ff", "rr", "+", "-"] {
+        for symbol in [".", "r", "uf", "ff", "rr", "+", "-", "fr-unstranded", "firststrand"] {
             assert!(super::stranding(symbol).is_err())
         }
     }
@@ -78,4 +113,178 @@ mod test {
         assert!(validator("10").is_ok());
         assert!(validator("11").is_err());
     }
+
+    #[test]
+    fn confidence_level() {
+        assert_eq!(super::confidence_level("0.95"), Ok(0.95));
+        assert_eq!(super::confidence_level("0.5"), Ok(0.5));
+        for invalid in ["0", "1", "-0.5", "95", "", "high"] {
+            assert!(super::confidence_level(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn editing_index() {
+        let (names, path) = super::editing_index("Alu:^Alu(Sx|Y):ei/alu.csv").unwrap();
+        let (label, pattern) = names.unwrap();
+        assert_eq!((label.as_str(), pattern.as_str(), path.to_str().unwrap()), ("Alu", "^Alu(Sx|Y)", "ei/alu.csv"));
+
+        assert!(matches!(super::editing_index("ei.csv"), Ok((None, _))));
+        for invalid in [":^Alu:alu.csv", "Alu:^Alu(:alu.csv", "Alu:^Alu:"] {
+            assert!(super::editing_index(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn input() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        assert_eq!(super::input(path), Ok((None, file.path().to_owned())));
+        assert_eq!(
+            super::input(&format!("treated={}", path)),
+            Ok((Some("treated".to_owned()), file.path().to_owned()))
+        );
+        assert_eq!(super::input("-"), Ok((None, super::PathBuf::from("-"))));
+        assert_eq!(super::input("treated=-"), Ok((Some("treated".to_owned()), super::PathBuf::from("-"))));
+        for invalid in [format!("={}", path), "treated=missing.bam".to_owned(), "missing.bam".to_owned()] {
+            assert!(super::input(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn columns() {
+        use crate::core::mismatches::roi::ROIColumn;
+        use crate::core::mismatches::site::SiteColumn;
+
+        let columns: Vec<ROIColumn> = super::columns("name,A->G,contig").unwrap();
+        assert_eq!(columns.iter().map(|x| x.name()).collect::<Vec<_>>(), ["name", "A->G", "contig"]);
+        assert!(super::columns::<SiteColumn>("contig,pos,A").is_ok());
+        for invalid in ["", "contig,", "contig,contig", "contig,Pos", "contig,start"] {
+            assert!(super::columns::<SiteColumn>(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn replicate_support() {
+        assert_eq!(super::replicate_support("2:3"), Ok((2, 3)));
+        assert_eq!(super::replicate_support("0:1"), Ok((0, 1)));
+        for invalid in ["2", "2:0", ":2", "2:", "-1:2", "2:1.5", "a:b"] {
+            assert!(super::replicate_support(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn tile() {
+        assert_eq!(super::tile("1000"), Ok((1000, 1000)));
+        assert_eq!(super::tile("1000:250"), Ok((1000, 250)));
+        for invalid in ["0", "1000:0", "1000:2000", "1000:", ":500", "1kb", "-1000"] {
+            assert!(super::tile(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
+
+// Single nucleotide, e.g. A
+pub fn nucleotide(nuc: &str) -> Result<ReqNucleotide, String> {
+    match nuc.as_bytes() {
+        [nuc] => ReqNucleotide::try_from(*nuc).map_err(|_| ()),
+        _ => Err(()),
+    }
+    .map_err(|_| format!("Nucleotide must be one of A, C, G, T, got {}", nuc))
+}
+
+// Nucleotide conversion in the "t2c" format
+pub fn conversion(conversion: &str) -> Result<(ReqNucleotide, ReqNucleotide), String> {
+    let err = || format!("Conversion must be in the <from>2<to> format (e.g. t2c), got {}", conversion);
+    match conversion.as_bytes() {
+        [from, b'2', to] => {
+            let from = ReqNucleotide::try_from(*from).map_err(|_| err())?;
+            let to = ReqNucleotide::try_from(*to).map_err(|_| err())?;
+            if from == to {
+                return Err(err());
+            }
+            Ok((from, to))
+        }
+        _ => Err(err()),
+    }
+}
+
+// Editing index output, either PATH or LABEL:REGEX:PATH to compute EI only for ROIs with names matching the REGEX
+pub fn editing_index(ei: &str) -> Result<(Option<(String, Regex)>, PathBuf), String> {
+    let (label, rest) = match ei.split_once(':') {
+        Some((label, rest)) if rest.contains(':') => (label, rest),
+        _ => return Ok((None, PathBuf::from(ei))),
+    };
+    let (pattern, path) = rest.rsplit_once(':').unwrap();
+    if label.is_empty() || path.is_empty() {
+        return Err(format!("EI must be either PATH or LABEL:REGEX:PATH with non-empty LABEL and PATH, got {}", ei));
+    }
+    let pattern = Regex::new(pattern).map_err(|x| format!("Invalid ROI name pattern for EI {}: {}", label, x))?;
+    Ok((Some((label.to_owned(), pattern)), PathBuf::from(path)))
+}
+
+// Per-file mismatches support in the "min mismatches:min files" format
+pub fn replicate_support(support: &str) -> Result<(u32, usize), String> {
+    let err =
+        || format!("Replicate support must be in the <min mismatches>:<min files> format (e.g. 2:2), got {}", support);
+    let (mismatches, files) = support.split_once(':').ok_or_else(err)?;
+    let (mismatches, files): (u32, usize) = (mismatches.parse().map_err(|_| err())?, files.parse().map_err(|_| err())?);
+    if files == 0 {
+        return Err(format!("Replicate support requires at least 1 file, got {}", support));
+    }
+    Ok((mismatches, files))
+}
+
+// Tiling windows as SIZE[:STEP], the step defaults to the size (non-overlapping windows)
+pub fn tile(tile: &str) -> Result<(u64, u64), String> {
+    let err = || format!("Tiling must be in the <size>[:<step>] format (e.g. 1000 or 1000:500), got {}", tile);
+    let (size, step) = tile.split_once(':').unwrap_or((tile, tile));
+    let (size, step): (u64, u64) = (size.parse().map_err(|_| err())?, step.parse().map_err(|_| err())?);
+    if size == 0 || step == 0 || step > size {
+        return Err(format!("Tiling requires 0 < step ≤ size, got {}", tile));
+    }
+    Ok((size, step))
+}
+
+// Confidence level strictly inside the (0, 1) range, e.g. 0.95
+pub fn confidence_level(level: &str) -> Result<f32, String> {
+    let parsed: f32 = level.parse().map_err(|_| format!("Failed to parse confidence level {}", level))?;
+    if parsed <= 0f32 || parsed >= 1f32 {
+        return Err(format!("Confidence level is expected to be inside (0, 1) range, got {}", level));
+    }
+    Ok(parsed)
+}
+
+// Min read/ROI overlap: integers are the number of bases, fractions in (0, 1] - share of the read aligned length
+pub fn roi_overlap(overlap: &str) -> Result<MinROIOverlap, String> {
+    if let Ok(bases) = overlap.parse::<u32>() {
+        return match bases {
+            0 => Err(format!("Min ROI overlap must be > 0, got {}", overlap)),
+            _ => Ok(MinROIOverlap::Bases(bases)),
+        };
+    }
+    let fraction: f32 = overlap.parse().map_err(|_| {
+        format!("Min ROI overlap must be either the number of bases or a fraction (e.g. 0.5), got {}", overlap)
+    })?;
+    if !(fraction > 0f32 && fraction <= 1f32) {
+        return Err(format!("Min ROI overlap fraction is expected to be inside (0, 1] range, got {}", overlap));
+    }
+    Ok(MinROIOverlap::Fraction(fraction))
+}
+
+// Filter expression, e.g. "A->G >= 3 AND coverage >= 20". Errors point to the offending token
+pub fn filter_expr(records: Records) -> impl Fn(&str) -> Result<(), String> {
+    move |expr: &str| ByExpression::new(expr, records).map(|_| ())
+}
+
+// Comma-separated list of unique output columns, e.g. contig,start,end,A->G
+pub fn columns<T: FromStr<Err = String> + PartialEq>(columns: &str) -> Result<Vec<T>, String> {
+    let mut result = Vec::new();
+    for name in columns.split(',') {
+        let column = T::from_str(name)?;
+        if result.contains(&column) {
+            return Err(format!("Column {} is listed more than once in {}", name, columns));
+        }
+        result.push(column);
+    }
+    Ok(result)
 }
@@ -1,14 +1,22 @@
+use bio_types::genome::Interval;
 use clap::Arg;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
 
 use crate::cli::shared;
+use crate::cli::shared::format::OutputFormat;
 use crate::cli::shared::validate;
+use crate::core::dna::Multimap;
+use crate::core::hooks::filters::{ByAltFragments, ByBaseQuality, ByExpression, ByRefBase, BySpliceDist, Records};
+use crate::core::io;
+use crate::core::io::utils::OutputWriter;
 use crate::core::mismatches::prefilters;
-use crate::core::mismatches::prefilters::retain::RetainSitesFromIntervals;
-use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::prefilters::retain::RetainSitesFromList;
+use crate::core::mismatches::site::{SiteColumn, SiteMismatchesVec, TopK};
+use crate::core::rpileup::ncounter::cnt::MAX_SPLICE_DIST;
 use crate::core::stranding::predict::REATStrandingEngine;
 use crate::core::workload::SiteWorkload;
+use crate::error::Error;
 
 use super::parse;
 
@@ -18,8 +26,32 @@ pub mod output_filtering {
     pub const MIN_MISMATCHES: &str = "out-min-mismatches";
     pub const MIN_FREQ: &str = "out-min-freq";
     pub const MIN_COVERAGE: &str = "out-min-cov";
+    pub const EDITING_ONLY: &str = "prefilter-editing-only";
     pub const FORCE_LIST: &str = "force";
+    pub const FORCE_REPORT_MISSING: &str = "force-report-missing";
     pub const REGIONS: &str = "region";
+    pub const FORMAT: &str = "format";
+    pub const ROUND_COUNTS: &str = "round-counts";
+    pub const COVERAGE_SUMMARY: &str = "coverage-summary";
+    pub const CONTEXT: &str = "context";
+    pub const REPLICATE_SUPPORT: &str = "replicate-support";
+    pub const SAMPLES: &str = "samples";
+    pub const COLUMNS: &str = "columns";
+    pub const ERROR_MODEL: &str = "error-model";
+    pub const MAX_QVALUE: &str = "max-qvalue";
+    pub const SUBSTITUTION_RATES: &str = "substitution-rates";
+    pub const REF_BASE: &str = "ref-base";
+    pub const WITH_QUALITY: &str = "with-quality";
+    pub const MIN_MEANQ: &str = "out-min-meanq";
+    pub const WITH_ALT_FRAGMENTS: &str = "with-alt-fragments";
+    pub const MIN_ALT_FRAGMENTS: &str = "out-min-alt-fragments";
+    pub const WITH_SPLICE_DIST: &str = "with-splice-dist";
+    pub const MIN_SPLICE_DIST: &str = "out-min-splice-dist";
+    pub const FILTER_EXPR: &str = "filter-expr";
+    pub const SOFT_FILTERS: &str = "soft-filters";
+    pub const NO_OUTPUT_FILTERS: &str = "no-output-filters";
+    pub const TOP_K: &str = "top-k";
+    pub const RANK_BY: &str = "rank-by";
 
     pub const SECTION_NAME: &str = "Output hooks";
 
@@ -51,57 +83,496 @@ pub mod output_filtering {
                 .long_help(
                     "Output only sites with total mismatches frequency ≥ threshold (freq = ∑ mismatches / coverage)",
                 ),
+            Arg::new(EDITING_ONLY).long(EDITING_ONLY).takes_value(false).long_help(
+                "Count only canonical A-to-I editing in the mismatches filter: A->G for the forward transcription strand, \
+                T->C for the reverse one, and the max of the two for sites with unknown strand. \
+                Other mismatches are still reported but do not let sites pass the filter.",
+            ),
+            Arg::new(REPLICATE_SUPPORT)
+                .long(REPLICATE_SUPPORT)
+                .takes_value(true)
+                .validator(|x| validate::replicate_support(x).map(|_| ()))
+                .long_help(
+                    "Output only sites with at least X mismatches in at least Y input files, given as X:Y. \
+                    Useful to discard single-library artifacts when technical replicates are processed together. \
+                    Reported counts are still pooled across all files.",
+                ),
+            Arg::new(REF_BASE)
+                .long(REF_BASE)
+                .takes_value(true)
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .validator(|x| validate::nucleotide(x).map(|_| ()))
+                .long_help(
+                    "Output only sites with the given predicted reference nucleotides (forward strand), \
+                    e.g. A,T for A-to-I editing. Heterozygous sites are reported if either allele is listed. \
+                    Forced sites are always reported, counting, stranding and statistics are not affected.",
+                ),
+            Arg::new(WITH_QUALITY).long(WITH_QUALITY).takes_value(false).long_help(
+                "Add the \"meanq_alt\" and \"minq_alt\" columns: mean and min base quality of the mismatching \
+                nucleotides (all but both alleles for heterozygous sites), empty for sites without mismatches. \
+                Low qualities often point to sequencing artifacts. Doubles the memory used for counting. \
+                Not available for the mpileup output format.",
+            ),
+            Arg::new(MIN_MEANQ)
+                .long(MIN_MEANQ)
+                .takes_value(true)
+                .requires(WITH_QUALITY)
+                .validator(validate::numeric(0f32, 255f32))
+                .long_help(
+                    "Output only sites where the mismatching nucleotides have the mean base quality ≥ threshold \
+                    (the meanq_alt column). Sites without mismatches and forced sites are always reported.",
+                ),
+            Arg::new(WITH_ALT_FRAGMENTS).long(WITH_ALT_FRAGMENTS).takes_value(false).long_help(
+                "Add the \"alt_fragments\" column: number of distinct fragments (read names, mates are the same \
+                fragment) with the mismatching nucleotides. Tells independent support from a single duplicated \
+                fragment. Only bases that differ from the assembly are tracked => empty if the predicted reference \
+                differs from the assembly nucleotide; saturates at 256. Not available for the mpileup output format.",
+            ),
+            Arg::new(MIN_ALT_FRAGMENTS)
+                .long(MIN_ALT_FRAGMENTS)
+                .takes_value(true)
+                .requires(WITH_ALT_FRAGMENTS)
+                .validator(validate::numeric(0u32, u32::MAX))
+                .long_help(
+                    "Output only sites where the mismatching nucleotides come from at least X distinct fragments \
+                    (the alt_fragments column). Sites with an empty alt_fragments and forced sites are always reported.",
+                ),
+            Arg::new(WITH_SPLICE_DIST).long(WITH_SPLICE_DIST).takes_value(false).long_help(
+                "Add the \"splice_dist\" column: distance to the nearest splice junction observed in the spliced \
+                reads themselves (0 for the exonic bases flanking an N gap), capped at 1000. Junctions of all reads \
+                in the contig are considered, no annotation is required. Mismatches near junctions often come from \
+                misaligned read ends. Not available for the mpileup output format.",
+            ),
+            Arg::new(MIN_SPLICE_DIST)
+                .long(MIN_SPLICE_DIST)
+                .takes_value(true)
+                .requires(WITH_SPLICE_DIST)
+                .validator(validate::numeric(0u32, MAX_SPLICE_DIST))
+                .long_help(
+                    "Output only sites at least X bases away from the nearest observed splice junction \
+                    (the splice_dist column). Forced sites are always reported.",
+                ),
+            Arg::new(FILTER_EXPR)
+                .long(FILTER_EXPR)
+                .takes_value(true)
+                .validator(validate::filter_expr(Records::Sites))
+                .long_help(
+                    "Output only sites matching the given expression, e.g. \"G >= 3 AND coverage >= 20\". \
+                    Fields: coverage, mismatches (relative to the predicted reference), freq (mismatches / coverage), \
+                    strand (transcription strand, compared with \"+\", \"-\" or \".\") and A, C, G, T counts. \
+                    Operators: <, <=, >, >=, ==, != (or ≤, ≥, ≠), AND, OR, NOT (or &&, ||, !) and parentheses. \
+                    Applied after other output filters, forced sites are always reported.",
+                ),
+            Arg::new(TOP_K)
+                .long(TOP_K)
+                .takes_value(true)
+                .validator(validate::numeric(1usize, usize::MAX))
+                .long_help(
+                    "Output only the best N sites of each contig according to --rank-by, e.g. to inspect the \
+                    strongest candidates of an exploratory run. Applied after stranding and all other output filters, \
+                    ties at the cutoff are resolved by the position (lower first). Forced sites are always reported \
+                    and don't take the slots. The effective cutoff of each contig is reported at the end of the run.",
+                ),
+            Arg::new(RANK_BY)
+                .long(RANK_BY)
+                .takes_value(true)
+                .requires(TOP_K)
+                .possible_values(&["ag_count", "freq", "score"])
+                .default_value("score")
+                .long_help(
+                    "Ranking key of --top-k, A->G mismatches are taken relative to the transcription strand: \
+                    \"ag_count\" - number of A->G mismatches, \"freq\" - A->G mismatches / coverage, \
+                    \"score\" - lower bound of the 95% Wilson score interval of the A->G frequency \
+                    (penalizes frequencies supported by a few reads). Heterozygous sites are ranked last.",
+                ),
+            Arg::new(SOFT_FILTERS).long(SOFT_FILTERS).takes_value(false).long_help(
+                "Report sites failing the output filters (--ref-base, --out-min-meanq, --out-min-alt-fragments, \
+                --out-min-splice-dist, --filter-expr, --max-qvalue and --top-k) instead of dropping them. \
+                The added \"filter\" column holds PASS or semicolon-separated names of the failed filters (VCF-style). \
+                The mismatches & replicates prefilters (--out-min-*, --replicate-support) are applied during counting \
+                and still drop sites. Not available for the mpileup output format.",
+            ),
+            Arg::new(NO_OUTPUT_FILTERS)
+                .long(NO_OUTPUT_FILTERS)
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    MIN_MISMATCHES,
+                    MIN_FREQ,
+                    MIN_COVERAGE,
+                    EDITING_ONLY,
+                    REPLICATE_SUPPORT,
+                    REF_BASE,
+                    MIN_MEANQ,
+                    MIN_ALT_FRAGMENTS,
+                    MIN_SPLICE_DIST,
+                    FILTER_EXPR,
+                    MAX_QVALUE,
+                    SOFT_FILTERS,
+                ])
+                .long_help(
+                    "Disable all output filters and report every counted site with nonzero coverage. \
+                    Forced sites (--force) are reported regardless of their coverage.",
+                ),
+            Arg::new(SAMPLES).long(SAMPLES).takes_value(false).long_help(
+                "Count each input file separately in a single pass and report coverage (cov_NAME) and mismatches \
+                relative to the predicted reference (mm_NAME) for each of them. Sites are reported if any sample \
+                passes the output filters. Sample names are taken from NAME=PATH inputs or the file names.",
+            ),
             Arg::new(FORCE_LIST).long(FORCE_LIST).takes_value(true).validator(validate::path).long_help(
-                "Force the output of sites located in a given BED file (even if they do not pass other filters).",
+                "Force the output of sites located in a given BED file (even if they do not pass other filters). \
+                Alternatively, a 2-column file with contigs and 0-based positions (same as the output pos column).",
+            ),
+            Arg::new(FORCE_REPORT_MISSING)
+                .long(FORCE_REPORT_MISSING)
+                .takes_value(false)
+                .requires(FORCE_LIST)
+                .long_help(
+                    "Report forced sites with zero counts even if there are no reads in the corresponding genome bin. \
+                    Note that all genome bins are processed in this case, including bins without reads.",
+                ),
+            Arg::new(REGIONS)
+                .long(REGIONS)
+                .takes_value(true)
+                .validator(validate::path)
+                .conflicts_with(shared::args::core::STREAM)
+                .long_help(
+                    "Process only sites overlapping the given BED file. \
+                    Requires indexed inputs, filter the streamed reads upstream instead (e.g. samtools view -L).",
+                ),
+            Arg::new(FORMAT)
+                .long(FORMAT)
+                .takes_value(true)
+                .possible_values(&["tsv", "mpileup", "jsonl", "parquet"])
+                .default_value("tsv")
+                .long_help(
+                    "Output format. \"tsv\" is a flat table with per-site nucleotide counts. \
+                    \"mpileup\" emits samtools mpileup-like columns (contig, 1-based position, reference, depth, bases), \
+                    where bases are reconstructed from the nucleotide counts and are lowercase for reverse strand sites. \
+                    \"jsonl\" and \"parquet\" contain the same columns as \"tsv\", one JSON object per line or a typed \
+                    Parquet table (requires REAT built with the parquet feature, doesn't support checkpoints).",
+                ),
+            Arg::new(ROUND_COUNTS).long(ROUND_COUNTS).takes_value(false).long_help(
+                "Round weighted nucleotide counts to the nearest integer in the output table. \
+                Has an effect only with --multimap fractional, filters are always applied to the exact counts.",
+            ),
+            Arg::new(CONTEXT).long(CONTEXT).takes_value(true).validator(validate::numeric(0u32, 1000u32)).long_help(
+                "Add the \"context\" column with the reference sequence from pos-N to pos+N, e.g. N=1 for \
+                trinucleotides. The sequence is reverse-complemented for the reverse strand sites, \
+                positions outside the contig are reported as N. Not available for the mpileup output format.",
+            ),
+            Arg::new(COLUMNS)
+                .long(COLUMNS)
+                .takes_value(true)
+                .validator(|x| validate::columns::<SiteColumn>(x).map(|_| ()))
+                .long_help(
+                    "Comma-separated list of columns to report, in the given order (e.g. contig,pos,refnuc,A,C,G,T). \
+                    Columns disabled by other options (e.g. --no-ref-source) can't be selected. \
+                    Per-sample and context columns are always reported last. Not available for the mpileup output format.",
+                ),
+            Arg::new(COVERAGE_SUMMARY).long(COVERAGE_SUMMARY).takes_value(true).long_help(
+                "Save per-contig totals to the given TSV file: positions considered after the include/exclude \
+                filtering, positions covered by at least one read and positions reported after all filters. \
+                Useful to verify that different runs covered the same territory.",
             ),
-            Arg::new(REGIONS).long(REGIONS).takes_value(true).validator(validate::path).long_help(
-                "Process only sites overlapping the given BED file.",
+            Arg::new(ERROR_MODEL)
+                .long(ERROR_MODEL)
+                .takes_value(false)
+                .conflicts_with_all(&[shared::args::core::CHECKPOINT, shared::args::core::STREAM])
+                .long_help(
+                    "Process the input twice. The first pass learns the background substitution rates (sequencing & \
+                    alignment errors) from all covered positions with homozygous references, skipping positions \
+                    that look like variants (any mismatch frequency > 0.3). The second pass reports sites as usual \
+                    and adds the \"pvalue\" (binomial test against the learned rates, Bonferroni-corrected for \
+                    the 3 possible substitutions) and the \"qvalue\" (Benjamini-Hochberg) columns. \
+                    Forced sites and sites with heterozygous references are not tested. \
+                    Results are kept in memory until the end of the run => checkpoints are not supported. \
+                    Streamed inputs (--stream) can't be read twice.",
+                ),
+            Arg::new(MAX_QVALUE)
+                .long(MAX_QVALUE)
+                .takes_value(true)
+                .requires(ERROR_MODEL)
+                .validator(validate::numeric(0f64, 1f64))
+                .long_help("Output only sites with q-value ≤ threshold. Untested sites are always reported."),
+            Arg::new(SUBSTITUTION_RATES).long(SUBSTITUTION_RATES).takes_value(true).requires(ERROR_MODEL).long_help(
+                "Save the background substitution counts & rates learned by the error model to the given TSV file.",
             ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
 }
 
+pub mod stranding {
+    use super::*;
+
+    pub const EDITING: &str = "stranding-editing-site";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args =
+            vec![Arg::new(EDITING).long(EDITING).takes_value(true).validator(validate::editing_thresholds).long_help(
+                "A->I editing thresholds for the site strand prediction as min mismatches:min freq, e.g. 3:0.1. \
+                Overrides --str-min-mismatches/--str-min-freq for A->I editing, \
+                but not the thresholds given in --stranding-algo.",
+            )];
+        args.into_iter().map(|x| x.help_heading(Some(shared::args::stranding::SECTION_NAME))).collect()
+    }
+}
+
+pub mod workload {
+    use super::*;
+
+    pub const BINPAD: &str = "binpad";
+    pub const ADAPTIVE_BINS: &str = "adaptive-bins";
+
+    pub const SECTION_NAME: &str = "Workload";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(BINPAD)
+                .long(BINPAD)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, 1_000_000u32))
+                .default_value("0")
+                .long_help(
+                    "Extend each genome bin by X base pairs on both sides while counting reads. \
+                    Results are reported only for the core bin => reads spanning bin boundaries \
+                    are handled the same way regardless of the bin size",
+                ),
+            Arg::new(ADAPTIVE_BINS)
+                .long(ADAPTIVE_BINS)
+                .takes_value(false)
+                .conflicts_with(shared::args::core::STREAM)
+                .long_help(
+                    "Balance genome bins by the read density estimated from the BAI index of the first input file \
+                    (no reads are decoded). Dense regions (e.g. rRNA, chrM) are split into bins down to 1/16 of \
+                    --binsize, sparse ones are merged into bins up to 16x --binsize, each bin holding roughly the same \
+                    number of reads. Only the scheduling changes: results are the same as with fixed bins \
+                    as long as reads spanning bin boundaries are handled consistently (see --binpad)",
+                ),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
 pub fn all<'a>() -> Vec<Arg<'a>> {
-    shared::args::all().into_iter().chain(output_filtering::args()).collect()
+    shared::args::all()
+        .into_iter()
+        .chain(workload::args())
+        .chain(stranding::args())
+        .chain(output_filtering::args())
+        .collect()
 }
 
 pub struct SiteArgs {
     pub workload: Vec<SiteWorkload>,
     pub maxwsize: usize,
+    // Bases removed by the exclude list
+    pub excluded: u64,
     pub prefilter: prefilters::ByMismatches,
+    pub replicates: Option<prefilters::ByReplicates>,
+    pub ref_base: Option<ByRefBase>,
+    // Collect base qualities for the meanq_alt / minq_alt columns
+    pub qualities: bool,
+    pub min_meanq: Option<ByBaseQuality>,
+    // Track fragments for the alt_fragments column
+    pub alt_fragments: bool,
+    pub min_alt_fragments: Option<ByAltFragments>,
+    // Collect splice junctions for the splice_dist column
+    pub splice_dist: bool,
+    pub min_splice_dist: Option<BySpliceDist>,
+    pub filter_expr: Option<ByExpression>,
+    // Names of input files reported separately
+    pub samples: Option<Vec<String>>,
     pub stranding: REATStrandingEngine<SiteMismatchesVec>,
-    pub retain: Option<RetainSitesFromIntervals>,
+    pub retain: Option<RetainSitesFromList>,
+    pub report_missing: bool,
+    pub format: OutputFormat,
+    pub round_counts: bool,
+    pub columns: Vec<SiteColumn>,
+    // Flank size for the reference context column
+    pub context: Option<u64>,
+    pub coverage_summary: Option<csv::Writer<OutputWriter>>,
+    pub error_model: Option<ErrorModel>,
+    // Mark sites failing the output filters instead of dropping them
+    pub soft_filters: bool,
+    // Report only the best sites of each contig
+    pub top_k: Option<TopK>,
+}
+
+pub struct ErrorModel {
+    pub max_qvalue: Option<f64>,
+    // Learned substitution rates
+    pub saveto: Option<csv::Writer<OutputWriter>>,
 }
 
 impl SiteArgs {
-    pub fn new(core: &mut shared::args::CoreArgs, args: &ArgMatches, factory: &impl Fn() -> ProgressBar) -> Self {
-        let filter = shared::parse::outfilter(
-            factory(),
-            output_filtering::MIN_MISMATCHES,
-            output_filtering::MIN_FREQ,
-            output_filtering::MIN_COVERAGE,
-            args,
-        );
-
-        let mut stranding = REATStrandingEngine::new();
-        let mut workload: Option<Vec<SiteWorkload>> = Default::default();
-        let mut maxsize: Option<usize> = Default::default();
-        let mut retain: Option<RetainSitesFromIntervals> = Default::default();
+    pub fn new(
+        core: &mut shared::args::CoreArgs,
+        args: &ArgMatches,
+        factory: &impl Fn() -> ProgressBar,
+    ) -> Result<Self, Error> {
+        Self::with_regions(core, args, factory, None)
+    }
+
+    // Sites are reported only within the given regions, e.g. ROI windows of the joint run
+    pub fn with_regions(
+        core: &mut shared::args::CoreArgs,
+        args: &ArgMatches,
+        factory: &impl Fn() -> ProgressBar,
+        regions: Option<Vec<Interval>>,
+    ) -> Result<Self, Error> {
+        let format = shared::parse::format(factory(), args, output_filtering::FORMAT);
+        if format == OutputFormat::MPileup && core.multimap != Multimap::Full {
+            return Err(Error::Input(
+                "mpileup output requires integer counts, use --multimap full or the tsv format".into(),
+            ));
+        }
+        if format == OutputFormat::MPileup && core.orient {
+            return Err(Error::Input(
+                "mpileup output is always relative to the reference strand, drop --orient-by-trstrand".into(),
+            ));
+        }
+        if format == OutputFormat::MPileup && core.annotate.is_some() {
+            return Err(Error::Input("mpileup output can't be annotated, drop --annotate".into()));
+        }
+        let samples = parse::samples(factory(), args)?;
+        if samples.is_some() && format == OutputFormat::MPileup {
+            return Err(Error::Input("Per-sample counts are not reported in the mpileup output format".into()));
+        }
+        let scale = core.multimap.scale();
+        let filter = if args.is_present(output_filtering::NO_OUTPUT_FILTERS) {
+            shared::parse::no_outfilter(factory())
+        } else {
+            shared::parse::outfilter(
+                factory(),
+                output_filtering::MIN_MISMATCHES,
+                output_filtering::MIN_FREQ,
+                output_filtering::MIN_COVERAGE,
+                output_filtering::EDITING_ONLY,
+                scale,
+                args,
+            )
+        };
+        let replicates =
+            shared::parse::replicates(factory(), output_filtering::REPLICATE_SUPPORT, scale, &core.bamfiles, args);
+        let ref_base = parse::ref_base(factory(), args);
+        let qualities = args.is_present(output_filtering::WITH_QUALITY);
+        if qualities && format == OutputFormat::MPileup {
+            return Err(Error::Input("Base qualities are not reported in the mpileup output format".into()));
+        }
+        let min_meanq = parse::min_meanq(factory(), args);
+        let alt_fragments = args.is_present(output_filtering::WITH_ALT_FRAGMENTS);
+        if alt_fragments && format == OutputFormat::MPileup {
+            return Err(Error::Input(
+                "Fragments supporting mismatches are not reported in the mpileup output format".into(),
+            ));
+        }
+        let min_alt_fragments = parse::min_alt_fragments(factory(), args);
+        let splice_dist = args.is_present(output_filtering::WITH_SPLICE_DIST);
+        if splice_dist && format == OutputFormat::MPileup {
+            return Err(Error::Input(
+                "Distances to splice junctions are not reported in the mpileup output format".into(),
+            ));
+        }
+        let min_splice_dist = parse::min_splice_dist(factory(), args);
+        let filter_expr =
+            shared::parse::filter_expr(factory(), output_filtering::FILTER_EXPR, Records::Sites, scale, args);
+        let soft_filters = args.is_present(output_filtering::SOFT_FILTERS);
+        if soft_filters && format == OutputFormat::MPileup {
+            return Err(Error::Input("Failed filters are not reported in the mpileup output format".into()));
+        }
+
+        let mut stranding: Option<Result<REATStrandingEngine<_>, Error>> = Default::default();
+        let mut work: Option<Result<(Vec<SiteWorkload>, usize, u64), Error>> = Default::default();
+        let mut retain: Option<Result<Option<RetainSitesFromList>, Error>> = Default::default();
 
+        // Headers of the streamed inputs are available only through the opened stream
+        let genome = core.stream.as_ref().map_or_else(|| io::hts::contigs(&core.bamfiles), |x| x.contigs())?;
         let (pbarw, pbars, pbarf) = (factory(), factory(), factory());
         rayon::scope(|s| {
             s.spawn(|_| {
-                let (w, m) = parse::work(pbarw, &core.bamfiles, core.excluded.take(), args);
-                workload = Some(w);
-                maxsize = Some(m)
+                let (included, excluded) = (core.included.take(), core.excluded.take());
+                let indexed = (core.bamfiles[0].as_path(), core.indexes.as_ref().map(|x| x[0].as_path()));
+                work = Some(parse::work(pbarw, genome, included, excluded, indexed, regions, args));
             });
             s.spawn(|_| {
-                stranding = shared::parse::strandpred(pbars, args);
+                stranding = Some(shared::parse::strandpred(pbars, args, stranding::EDITING, scale));
             });
-            s.spawn(|_| retain = parse::retain(pbarf, args));
+            s.spawn(|_| retain = Some(parse::retain(pbarf, args)));
         });
+        let (workload, maxwsize, excluded) = work.unwrap()?;
+        let (stranding, retain) = (stranding.unwrap()?, retain.unwrap()?);
 
-        Self { workload: workload.unwrap(), maxwsize: maxsize.unwrap(), prefilter: filter, stranding, retain }
+        let report_missing = args.is_present(output_filtering::FORCE_REPORT_MISSING);
+        let round_counts = args.is_present(output_filtering::ROUND_COUNTS);
+        let context = parse::context(factory(), args);
+        if context.is_some() && format == OutputFormat::MPileup {
+            return Err(Error::Input("Reference context is not reported in the mpileup output format".into()));
+        }
+        let error_model = parse::error_model(factory(), args)?;
+        let mut layout = SiteColumn::layout(core.refsource, core.strand_depth);
+        if core.annotate.is_some() {
+            layout.extend(SiteColumn::annotation());
+        }
+        if error_model.is_some() {
+            layout.extend(SiteColumn::significance());
+        }
+        if qualities {
+            layout.extend(SiteColumn::quality());
+        }
+        if alt_fragments {
+            layout.extend(SiteColumn::fragments());
+        }
+        if splice_dist {
+            layout.extend(SiteColumn::splicing());
+        }
+        if soft_filters {
+            layout.extend(SiteColumn::filters());
+        }
+        if core.tag_output {
+            layout.insert(0, SiteColumn::Sample);
+        }
+        let mut columns = shared::parse::columns(factory(), args, output_filtering::COLUMNS, layout);
+        if args.is_present(output_filtering::COLUMNS) && format == OutputFormat::MPileup {
+            return Err(Error::Input("Output columns can't be selected for the mpileup output format".into()));
+        }
+        if core.tag_output {
+            if format == OutputFormat::MPileup {
+                return Err(Error::Input("Rows can't be tagged with the run name in the mpileup output format".into()));
+            }
+            // Explicitly selected columns may place the run name anywhere
+            if !columns.contains(&SiteColumn::Sample) {
+                columns.insert(0, SiteColumn::Sample);
+            }
+        }
+        let coverage_summary = parse::coverage_summary(factory(), args)?;
+        Ok(Self {
+            workload,
+            maxwsize,
+            excluded,
+            prefilter: filter,
+            replicates,
+            ref_base,
+            qualities,
+            min_meanq,
+            alt_fragments,
+            min_alt_fragments,
+            splice_dist,
+            min_splice_dist,
+            filter_expr,
+            samples,
+            stranding,
+            retain,
+            report_missing,
+            format,
+            round_counts,
+            columns,
+            context,
+            coverage_summary,
+            error_model,
+            soft_filters,
+            top_k: parse::top_k(factory(), args, scale),
+        })
     }
 }
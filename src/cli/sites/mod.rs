@@ -1,5 +1,5 @@
 pub use args::all as args;
-pub use run::run;
+pub use run::{run, Loci};
 
 mod args;
 mod parse;
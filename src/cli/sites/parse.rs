@@ -4,61 +4,290 @@ use std::path::Path;
 use bio_types::genome::{AbstractInterval, Interval};
 use clap::ArgMatches;
 use indicatif::ProgressBar;
+use itertools::Itertools;
 
 use crate::cli::shared;
-use crate::cli::sites::args::output_filtering::{FORCE_LIST, REGIONS};
+use crate::cli::sites::args::output_filtering::{
+    CONTEXT, COVERAGE_SUMMARY, ERROR_MODEL, FORCE_LIST, FORCE_REPORT_MISSING, MAX_QVALUE, MIN_ALT_FRAGMENTS, MIN_MEANQ,
+    MIN_SPLICE_DIST, RANK_BY, REF_BASE, REGIONS, SAMPLES, SUBSTITUTION_RATES, TOP_K,
+};
+use crate::cli::sites::args::workload::{ADAPTIVE_BINS, BINPAD};
+use crate::cli::sites::args::ErrorModel;
+use crate::core::hooks::filters::{ByAltFragments, ByBaseQuality, ByRefBase, BySpliceDist};
 use crate::core::io;
 use crate::core::io::bed;
 use crate::core::io::bed::BedRecord;
-use crate::core::mismatches::prefilters::retain::RetainSitesFromIntervals;
-use crate::core::workload::SiteWorkload;
+use crate::core::io::utils::OutputWriter;
+use crate::core::mismatches::prefilters::retain::RetainSitesFromList;
+use crate::core::mismatches::site::{RankBy, TopK};
+use crate::core::workload::{ReadsDensity, SiteWorkload};
+use crate::error::Error;
 
 pub fn work(
     pbar: ProgressBar,
-    bamfiles: &[impl AsRef<Path>],
+    genome: Vec<Interval>,
+    include: Option<Vec<BedRecord>>,
     exclude: Option<Vec<BedRecord>>,
+    // First input file & its index, the read density is estimated from them
+    indexed: (&Path, Option<&Path>),
+    // Regions given by the caller take precedence over the --regions BED file
+    regions: Option<Vec<Interval>>,
     matches: &ArgMatches,
-) -> (Vec<SiteWorkload>, usize) {
+) -> Result<(Vec<SiteWorkload>, usize, u64), Error> {
     let binsize = matches.value_of(shared::args::core::BINSIZE).unwrap().parse().unwrap();
+    let binpad: u64 = matches.value_of(BINPAD).unwrap().parse().unwrap();
 
-    let workload = if let Some(path) = matches.value_of(REGIONS) {
-        let bed: Vec<Interval> = bed::parse(Path::new(path)).into_iter().map(|x| x.interval).collect();
+    let intervals = if let Some(regions) = regions {
+        let bases = regions.iter().map(|x| x.range().end - x.range().start).sum::<u64>();
+        pbar.set_message(format!("Will process: {} regions ({} bases)", regions.len(), bases));
+        regions
+    } else if let Some(path) = matches.value_of(REGIONS) {
+        let bed: Vec<Interval> = bed::parse(Path::new(path))?.into_iter().map(|x| x.interval).collect();
 
         let bases = bed.iter().map(|x| x.range().end - x.range().start).sum::<u64>();
         pbar.set_message(format!("Will process: {} regions ({} bases)", bed.len(), bases));
-        SiteWorkload::from_intervals(bed, binsize, exclude)
+        bed
     } else {
         pbar.set_message(format!("Splitting the genome into {}bp bins...", binsize));
+        genome
+    };
 
-        let contigs = io::hts::contigs(bamfiles);
-        SiteWorkload::from_intervals(contigs, binsize, exclude)
+    // Excluded bases = difference between the workloads with and without the exclude list
+    let unexcluded = exclude.as_ref().map(|_| {
+        bases(&SiteWorkload::from_intervals(intervals.clone(), binsize, include.clone(), None::<Vec<BedRecord>>))
+    });
+    // Adaptive bins require the BAI linear index, fixed bins are used otherwise
+    let mut warning = String::new();
+    let mut density = None;
+    if matches.is_present(ADAPTIVE_BINS) {
+        let (file, index) = indexed;
+        pbar.set_message(format!("Estimating the read density from the index of {}...", file.display()));
+        density = io::hts::indexed_density(file, index)?.map(|x| ReadsDensity::from_index(io::hts::BAI_WINDOW, x));
+        if density.is_none() {
+            warning =
+                format!(" WARNING: BAI index of {} is not available, adaptive bins are disabled.", file.display());
+        }
+    }
+    // Adaptive bins: (max bin size, estimated reads per bin)
+    let (workload, adaptive) = match density {
+        None => (SiteWorkload::from_intervals(intervals, binsize, include, exclude), None),
+        Some(density) => {
+            let workload = SiteWorkload::adaptive(intervals, binsize, include, exclude, &density);
+            let maxbin = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap_or(0);
+            let mut reads = workload
+                .iter()
+                .map(|x| x.include().iter().map(|r| density.estimate(x.contig(), r)).sum::<f64>())
+                .collect_vec();
+            reads.sort_by(|a, b| a.total_cmp(b));
+            (workload, Some((maxbin, reads)))
+        }
     };
+    let excluded = unexcluded.map_or(0, |x| x - bases(&workload));
 
-    debug_assert!(!workload.is_empty());
+    let workload: Vec<SiteWorkload> = workload.into_iter().map(|x| x.with_padding(binpad)).collect();
+
+    if workload.is_empty() {
+        pbar.finish_with_message(format!(
+            "No work to do: no genome regions left after the include/exclude filtering.{}",
+            warning
+        ));
+        return Ok((workload, 0, excluded));
+    }
 
     let maxsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap();
+    let (maxbin, reads) = match adaptive {
+        None => (binsize, String::new()),
+        Some((maxbin, reads)) => (
+            maxbin,
+            format!(
+                ", min/median/max estimated reads per bin: {:.0}/{:.0}/{:.0}",
+                reads[0],
+                reads[reads.len() / 2],
+                reads[reads.len() - 1]
+            ),
+        ),
+    };
     pbar.finish_with_message(format!(
-        "Will summarize editing for {} genome bins with max bin size {}",
+        "Will summarize editing for {} genome bins with max bin size {} ({} bases excluded{}){}",
         workload.len(),
-        binsize
+        maxbin,
+        excluded,
+        reads,
+        warning
     ));
-    (workload, maxsize.try_into().unwrap())
+    Ok((workload, maxsize.try_into().unwrap(), excluded))
 }
 
-pub fn retain(pbar: ProgressBar, matches: &ArgMatches) -> Option<RetainSitesFromIntervals> {
-    pbar.set_message("Parsing the \"force\" BED file...");
+// Total number of bases reported for the workload
+pub fn bases(workload: &[SiteWorkload]) -> u64 {
+    workload.iter().flat_map(|x| x.include()).map(|x| x.end - x.start).sum()
+}
+
+pub fn retain(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<RetainSitesFromList>, Error> {
+    pbar.set_message("Parsing the \"force\" loci...");
 
-    let bedrecords = matches.value_of(FORCE_LIST).map(|x| bed::parse(Path::new(x)));
+    let loci = matches.value_of(FORCE_LIST).map(|x| bed::loci(Path::new(x))).transpose()?;
 
-    match bedrecords {
+    match loci {
         None => {
             pbar.finish_with_message("Forced output is disabled");
-            None
+            Ok(None)
+        }
+        Some(loci) => {
+            let retain = RetainSitesFromList::new(loci);
+            if matches.is_present(FORCE_REPORT_MISSING) {
+                pbar.finish_with_message(format!(
+                    "Output thresholds are disabled for {} sites(force list), sites without reads will be reported",
+                    retain.loci()
+                ));
+            } else {
+                pbar.finish_with_message(format!(
+                    "Output thresholds are disabled for {} sites(force list)",
+                    retain.loci()
+                ));
+            }
+            Ok(Some(retain))
+        }
+    }
+}
+
+pub fn coverage_summary(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<csv::Writer<OutputWriter>>, Error> {
+    pbar.set_message("Parsing coverage summary output path...");
+    match matches.value_of(COVERAGE_SUMMARY) {
+        None => {
+            pbar.finish_with_message("Coverage summary is disabled");
+            Ok(None)
+        }
+        Some(path) => {
+            let file = io::utils::write_compressed(Path::new(path), false)?;
+            let writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+            pbar.finish_with_message(format!("Per-contig coverage summary will be saved to {}", path));
+            Ok(Some(writer))
+        }
+    }
+}
+
+pub fn error_model(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<ErrorModel>, Error> {
+    pbar.set_message("Parsing error model options...");
+    if !matches.is_present(ERROR_MODEL) {
+        pbar.finish_with_message("Error model is disabled");
+        return Ok(None);
+    }
+    let max_qvalue = matches.value_of(MAX_QVALUE).map(|x| x.parse().unwrap());
+    let saveto = matches
+        .value_of(SUBSTITUTION_RATES)
+        .map(|path| io::utils::write_compressed(Path::new(path), false))
+        .transpose()?
+        .map(|file| csv::WriterBuilder::new().delimiter(b'\t').from_writer(file));
+    match max_qvalue {
+        None => pbar.finish_with_message("Two-pass error model: sites will be reported with p-values & q-values"),
+        Some(q) => {
+            pbar.finish_with_message(format!("Two-pass error model: sites with q-value > {} will be skipped", q))
+        }
+    }
+    Ok(Some(ErrorModel { max_qvalue, saveto }))
+}
+
+pub fn ref_base(pbar: ProgressBar, matches: &ArgMatches) -> Option<ByRefBase> {
+    pbar.set_message("Parsing allowed reference nucleotides...");
+    let nucleotides = match matches.values_of(REF_BASE) {
+        None => {
+            pbar.finish_with_message("Sites will be reported for all reference nucleotides");
+            return None;
         }
-        Some(bed) => {
-            let loci = bed.iter().map(|x| x.range().end - x.range().start).sum::<u64>();
-            pbar.finish_with_message(format!("Output thresholds are disabled for {} sites(force list)", loci));
-            Some(RetainSitesFromIntervals::new(bed))
+        Some(x) => x.collect_vec(),
+    };
+    let allowed = nucleotides.iter().map(|x| shared::validate::nucleotide(x).unwrap()).collect_vec();
+    pbar.finish_with_message(format!(
+        "Only sites with the following reference nucleotides will be reported: {}",
+        nucleotides.join(",")
+    ));
+    Some(ByRefBase::new(&allowed))
+}
+
+pub fn min_meanq(pbar: ProgressBar, matches: &ArgMatches) -> Option<ByBaseQuality> {
+    pbar.set_message("Parsing min base quality of mismatches...");
+    let minmeanq: Option<f32> = matches.value_of(MIN_MEANQ).map(|x| x.parse().unwrap());
+    match minmeanq {
+        None => pbar.finish_with_message("Sites won't be filtered by base qualities"),
+        Some(q) => {
+            pbar.finish_with_message(format!("Sites with mean base quality of mismatches < {} will be skipped", q))
         }
     }
+    minmeanq.map(ByBaseQuality::new)
+}
+
+pub fn min_alt_fragments(pbar: ProgressBar, matches: &ArgMatches) -> Option<ByAltFragments> {
+    pbar.set_message("Parsing min fragments supporting mismatches...");
+    let minfragments: Option<u32> = matches.value_of(MIN_ALT_FRAGMENTS).map(|x| x.parse().unwrap());
+    match minfragments {
+        None => pbar.finish_with_message("Sites won't be filtered by fragments supporting mismatches"),
+        Some(x) => pbar.finish_with_message(format!("Sites with mismatches from < {} fragments will be skipped", x)),
+    }
+    minfragments.map(ByAltFragments::new)
+}
+
+pub fn min_splice_dist(pbar: ProgressBar, matches: &ArgMatches) -> Option<BySpliceDist> {
+    pbar.set_message("Parsing min distance to splice junctions...");
+    let mindist: Option<u32> = matches.value_of(MIN_SPLICE_DIST).map(|x| x.parse().unwrap());
+    match mindist {
+        None => pbar.finish_with_message("Sites won't be filtered by the distance to splice junctions"),
+        Some(x) => pbar.finish_with_message(format!("Sites closer than {} bp to splice junctions will be skipped", x)),
+    }
+    mindist.map(BySpliceDist::new)
+}
+
+// Counts are in 1 / scale units, see Multimap
+pub fn top_k(pbar: ProgressBar, matches: &ArgMatches, scale: u32) -> Option<TopK> {
+    pbar.set_message("Parsing top-K options...");
+    let k = match matches.value_of(TOP_K) {
+        None => {
+            pbar.finish_with_message("All sites passing the output filters will be reported");
+            return None;
+        }
+        Some(x) => x.parse().unwrap(),
+    };
+    let by = match matches.value_of(RANK_BY).unwrap() {
+        "ag_count" => RankBy::AGCount,
+        "freq" => RankBy::Freq,
+        "score" => RankBy::Score,
+        x => panic!("Unknown ranking key: {}", x),
+    };
+    pbar.finish_with_message(format!("Only the best {} sites of each contig by {} will be reported", k, by.name()));
+    Some(TopK::new(k, by, scale))
+}
+
+pub fn context(pbar: ProgressBar, matches: &ArgMatches) -> Option<u64> {
+    pbar.set_message("Parsing reference context size...");
+    let flank = matches.value_of(CONTEXT).map(|x| x.parse().unwrap());
+    match flank {
+        None => pbar.finish_with_message("Reference context won't be reported"),
+        Some(flank) => pbar.finish_with_message(format!("Reference context: ±{} bases around each site", flank)),
+    }
+    flank
+}
+
+// Sample names are either given explicitly (NAME=PATH) or derived from the file names
+pub fn samples(pbar: ProgressBar, matches: &ArgMatches) -> Result<Option<Vec<String>>, Error> {
+    pbar.set_message("Parsing sample names...");
+    if !matches.is_present(SAMPLES) {
+        pbar.finish_with_message("Counts will be pooled across all input files");
+        return Ok(None);
+    }
+    let samples: Vec<String> = matches
+        .values_of(shared::args::core::INPUT)
+        .unwrap()
+        .map(|x| match shared::validate::input(x).map_err(Error::Input)? {
+            (Some(name), _) => Ok(name),
+            (None, path) => shared::parse::sample_name(&path),
+        })
+        .collect::<Result<_, _>>()?;
+    if !samples.iter().all_unique() {
+        return Err(Error::Input(format!(
+            "Sample names must be unique, use NAME=PATH to name input files explicitly: {}",
+            samples.join(" ")
+        )));
+    }
+    pbar.finish_with_message(format!("Per-sample counts will be reported for: {}", samples.join(" ")));
+    Ok(Some(samples))
 }
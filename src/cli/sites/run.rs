@@ -1,52 +1,393 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::Ordering;
 
-use clap::ArgMatches;
+use bio_types::genome::{AbstractInterval, Interval};
+use clap::{ArgMatches, Command, ValueSource};
 use indicatif::ProgressBar;
 
 use crate::cli::shared;
+use crate::cli::shared::args::core::{BUILD_INDEX, CHECKPOINT, RUN_INFO, SAVETO};
+use crate::cli::shared::args::reads_filtering::DUMP_READS;
 use crate::cli::shared::args::CoreArgs;
-use crate::cli::shared::stranding::Stranding;
+use crate::cli::shared::checkpoint::Checkpoint;
+use crate::cli::shared::format::OutputFormat;
+use crate::cli::shared::output::{self, SaveTo};
+use crate::cli::shared::runinfo::Skipped;
+use crate::cli::shared::RunMeta;
 use crate::cli::sites::args::SiteArgs;
-use crate::core::hooks::engine::REATHooksEngine;
-use crate::core::mismatches::site::{SiteMismatchesBuilder, SiteMismatchesVec};
-use crate::core::rpileup::hts::HTSPileupEngine;
-use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, IntervalNucCounter, StrandedNucCounter};
-use crate::core::runner::REATRunner;
-
-pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> ProgressBar) {
-    let args = SiteArgs::new(&mut core, args, &factory);
-
-    // Strander & Hooks don't require any further processing
-    let mut strander = args.stranding;
-    let hooks: REATHooksEngine<SiteMismatchesVec> = REATHooksEngine::new();
-
-    // Mismatchs builder. Always with prefilter since there are no site-level stats right now
-    let builder = SiteMismatchesBuilder::new(args.maxwsize, core.refnucpred, args.retain, Some(args.prefilter));
-
-    // Initialize basic counter
-    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3);
-    let counter = IntervalNucCounter::new(counter);
-
-    match core.stranding {
-        Stranding::Unstranded => {
-            // Compose strander + pileuper
-            let pileuper = HTSPileupEngine::new(core.bamfiles, counter);
-            // Launch the processing
-            let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, HashMap::new()).unwrap();
+use crate::core::hooks::filters::ByErrorRates;
+use crate::core::hooks::stats::SubstitutionRates;
+use crate::core::io::fasta;
+use crate::core::io::fasta::{BasicFastaReader, FastaReader};
+use crate::core::io::table;
+use crate::core::io::table::TableFormat;
+use crate::core::io::utils::FinishWrite;
+use crate::core::mismatches::site::{SampleColumns, SiteContext, SiteMismatchesVec, TopK};
+use crate::core::mismatches::MismatchesVec;
+use crate::core::pipeline::{stream_sites, ContigSummary, SiteRunConfig, Summary};
+use crate::core::refpred::RefNPolicy;
+use crate::core::statistics;
+use crate::core::stranding::predict::REATStrandingEngine;
+use crate::error::Error;
+
+const COVERAGE_SUMMARY_WRITE_ERROR: &str = "Failed to write the coverage summary";
+const SUBSTITUTION_RATES_WRITE_ERROR: &str = "Failed to write the substitution rates";
+
+type Serializer = Box<dyn FnMut(Vec<SiteMismatchesVec>, &mut output::Writer) -> csv::Result<()>>;
+
+pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> ProgressBar) -> Result<(), Error> {
+    let mut meta = RunMeta::new("site", args, &super::args(), &core.bamfiles)?;
+    let mut args = SiteArgs::new(&mut core, args, &factory)?;
+    let bins = args.workload.len();
+    let mut considered: HashMap<String, u64> = HashMap::new();
+    for w in &args.workload {
+        *considered.entry(w.contig().to_owned()).or_default() +=
+            w.include().iter().map(|x| x.end - x.start).sum::<u64>();
+    }
+
+    // Two-pass error model: the first pass only learns the background substitution rates
+    let background = args.error_model.as_ref().map(|_| -> Result<SubstitutionRates, Error> {
+        let mut config = counting(&core, &args)?;
+        config.prefilter = Some(args.prefilter);
+        config.contigs = core.contigs.clone();
+        config.progress = core.progress.reporter(&factory);
+        config.substitutions = true;
+        config.hooks.add_stat(Box::new(SubstitutionRates::new()));
+        let summary = stream_sites(config, |_, _| Ok(()))?;
+        Ok(SubstitutionRates::collapse(summary.stats.into_iter().map(|x| x.into_any().1).collect()))
+    });
+    let background = background.transpose()?;
+
+    // Forced sites don't compete for the top-K slots
+    let forced = args.top_k.as_ref().and_then(|_| args.retain.clone());
+    let (format, header, mut serialize) = serializer(&core, &args)?;
+    let mut config = configure(&core, &mut args, background.as_ref())?;
+    config.contigs = core.contigs;
+    config.progress = core.progress.reporter(&factory);
+    config.deterministic = core.deterministic;
+    config.stream = core.stream.take();
+
+    let mut saveto = core.saveto;
+    if let Some(dir) = core.checkpoint {
+        let checkpoint = Checkpoint::new(dir, meta.fingerprint())?;
+        config.finished = checkpoint.finished();
+        saveto = saveto.checkpointed(checkpoint);
+    }
+    saveto = saveto.with_format(format);
+    if let Some(header) = header {
+        saveto = saveto.with_header(header);
+    }
+
+    // Only CSV tables get the schema line: mpileup mimics samtools, JSON lines & Parquet are self-describing
+    if core.schema_line && args.format == OutputFormat::Tsv {
+        saveto = saveto.with_comment(&table::schema_line())?;
+    }
+
+    let (contiglens, mut past) = (&core.contiglens, 0);
+    // Contigs with sites dropped by the top-K selection: reported sites & the score cutoff
+    let mut ranked: Vec<(String, usize, f64)> = Vec::new();
+    let mut write = |contig: &str, mut items: Vec<SiteMismatchesVec>| {
+        if let Some(topk) = &args.top_k {
+            if let Some(cutoff) = topk.select(contig, &mut items, forced.as_ref(), args.soft_filters) {
+                ranked.push((contig.to_owned(), items.iter().map(|x| x.len()).sum(), cutoff));
+            }
         }
-        Stranding::Stranded(x) => {
-            // Remove all stranding algorithm -> they are not required
-            strander.clear();
-            // Compose strander + pileuper
-            let deductor = crate::core::stranding::deduce::DeduceStrandByDesign::new(x);
-            let pileuper = HTSPileupEngine::new(core.bamfiles, StrandedNucCounter::new(counter, deductor));
-
-            // Launch the processing
-            let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, HashMap::new()).unwrap();
+        past += shared::past_end(&items, contiglens);
+        saveto.write(items, |items, writer| serialize(items, writer))?;
+        saveto.commit(contig)
+    };
+    let mut summary = match &args.error_model {
+        None => stream_sites(config, write)?,
+        Some(model) => {
+            // q-values depend on all tested sites => results are written only once the run is over
+            let mut results = Vec::new();
+            let mut summary = stream_sites(config, |contig, items| {
+                results.push((contig.to_owned(), items));
+                Ok(())
+            })?;
+            summary.items = significance(&mut results, model.max_qvalue, args.soft_filters);
+            for (contig, items) in results {
+                if let Some(x) = summary.contigs.iter_mut().find(|x| x.contig == contig) {
+                    x.emitted = items.iter().map(|x| x.len()).sum();
+                }
+                write(&contig, items)?;
+            }
+            summary
+        }
+    };
+    if let Some(topk) = &args.top_k {
+        // Soft filters keep all sites
+        if !args.soft_filters && !ranked.is_empty() {
+            for (contig, emitted, _) in &ranked {
+                if let Some(x) = summary.contigs.iter_mut().find(|x| &x.contig == contig) {
+                    x.emitted = *emitted;
+                }
+            }
+            summary.items = summary.contigs.iter().map(|x| x.emitted).sum();
         }
+        report_top_k(topk, &ranked, &factory);
+    }
+    shared::finalize(summary.stats, &mut saveto, HashMap::new())?;
+    if let Some(mut writer) = args.error_model.and_then(|x| x.saveto) {
+        let failed = |x: csv::Error| Error::output(SUBSTITUTION_RATES_WRITE_ERROR, x);
+        background.unwrap().to_csv(&mut writer).map_err(failed)?;
+        writer.flush().and_then(|_| writer.get_mut().finish()).map_err(|x| failed(x.into()))?;
+    }
+    shared::report_past_end(past, &factory);
+    shared::report_tags(&core.tags, &factory);
+    core.filtered.dumped()?;
+    shared::report_filtered(&core.filtered, &factory);
+
+    if let Some(mut writer) = args.coverage_summary {
+        let failed = |x: csv::Error| Error::output(COVERAGE_SUMMARY_WRITE_ERROR, x);
+        coverage_summary(&considered, &summary.contigs, &mut writer).map_err(failed)?;
+        writer.flush().and_then(|_| writer.get_mut().finish()).map_err(|x| failed(x.into()))?;
+    }
+
+    if let Some(runinfo) = core.runinfo {
+        let skipped = Skipped { excluded: args.excluded, masked: 0, refn: summary.refn_skipped };
+        meta.onfinish(bins, summary.items, summary.reads, skipped, core.started.elapsed());
+        meta.refbases = core.refbases.load(Ordering::Relaxed);
+        meta.save(&runinfo)?;
+    }
+    // Outputs are finalized anyway => empty tables are left for inspection
+    shared::check_empty(&summary.contigs, core.aligned.as_ref(), core.stale_index, core.allow_empty, &factory)?;
+    Ok(())
+}
+
+// Read counting options shared by both passes of the error model
+fn counting(core: &CoreArgs, args: &SiteArgs) -> Result<SiteRunConfig, Error> {
+    let refnucpred = dyn_clone::clone_box(&*core.refnucpred);
+    let mut config = SiteRunConfig::new(
+        core.bamfiles.clone(),
+        args.workload.clone(),
+        args.maxwsize,
+        refnucpred,
+        core.readfilter.clone(),
+    );
+    config.indexes = core.indexes.clone();
+    config.reference = Some(core.reference.clone());
+    config.skip_refn = core.refn == RefNPolicy::Skip;
+    config.trim5 = core.trim5;
+    config.trim3 = core.trim3;
+    config.overlaps_dedup = core.overlaps_dedup;
+    config.umi = core.umi;
+    config.hts_threads = core.hts_threads;
+    config.prefetch = core.prefetch;
+    // Depth isn't a part of the mpileup output
+    config.strand_depth = core.strand_depth && args.format != OutputFormat::MPileup;
+    config.multimap = core.multimap;
+    config.max_coverage = core.max_coverage;
+    if let Some(max) = core.max_read_contribution {
+        let reader = BasicFastaReader::new(core.reference.clone())?.with_contig_names(core.refnames.clone());
+        config.max_read_contribution = Some((max, Box::new(reader) as Box<dyn FastaReader>));
+    }
+    config.design = core.stranding.design();
+    Ok(config)
+}
+
+// Options & output hooks of the main table, first pass options are set by the caller
+fn configure(
+    core: &CoreArgs,
+    args: &mut SiteArgs,
+    background: Option<&SubstitutionRates>,
+) -> Result<SiteRunConfig, Error> {
+    // Hooks don't require any further processing.
+    // Mismatches builder is always with prefilter since there are no site-level stats right now
+    let mut config = counting(core, args)?;
+    config.stranding = mem::replace(&mut args.stranding, REATStrandingEngine::new());
+    config.prefilter = Some(args.prefilter);
+    config.replicates = args.replicates;
+    config.per_source = args.samples.is_some();
+    config.retain = args.retain.take();
+    config.empty_bins = args.report_missing;
+    // Base qualities are reported only => not collected in the first pass of the error model
+    config.base_qualities = args.qualities;
+    config.splice_dist = args.splice_dist;
+    if args.alt_fragments {
+        let reader = BasicFastaReader::new(core.reference.clone())?.with_contig_names(core.refnames.clone());
+        config.alt_fragments = Some(Box::new(reader) as Box<dyn FastaReader>);
+    }
+    if args.soft_filters {
+        config.hooks.soft_filters();
+    }
+    if let Some(background) = background {
+        config.hooks.add_filter(Box::new(ByErrorRates::new(*background.counts(), core.multimap.scale())));
+    }
+    if let Some(filter) = args.ref_base.take() {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    if let Some(filter) = args.min_meanq.take() {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    if let Some(filter) = args.min_alt_fragments.take() {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    if let Some(filter) = args.min_splice_dist.take() {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    if let Some(filter) = args.filter_expr.take() {
+        config.hooks.add_filter(Box::new(filter));
+    }
+    Ok(config)
+}
+
+// Format, header (if any) & serialization of the main table.
+// Same sorting for all formats, only serialization differs
+fn serializer(core: &CoreArgs, args: &SiteArgs) -> Result<(TableFormat, Option<Vec<String>>, Serializer), Error> {
+    if args.format == OutputFormat::MPileup {
+        return Ok((args.format.table(vec![]), None, Box::new(SiteMismatchesVec::ugly_in_contig_sort_and_to_mpileup)));
+    }
+    let (columns, multimap, round, orient) = (args.columns.clone(), core.multimap, args.round_counts, core.orient);
+    let annotation = core.annotate.clone();
+    let sample = core.tag_output.then(|| core.name.clone());
+    // Sites are sorted within each contig => the reference is read sequentially
+    let mut context = match args.context {
+        None => None,
+        Some(flank) => {
+            let reader = BasicFastaReader::new(core.reference.clone())?.with_contig_names(core.refnames.clone());
+            let inverse = core.refnames.inverse();
+            let lengths =
+                fasta::lengths(&core.reference)?.into_iter().map(|(k, v)| (inverse.rename(&k).to_owned(), v)).collect();
+            Some(SiteContext::new(flank, Box::new(reader), lengths))
+        }
+    };
+    let samples = args.samples.as_deref().map(SampleColumns::new);
+    let header = SiteMismatchesVec::header(&columns, samples.as_ref(), context.is_some());
+    let schema = SiteMismatchesVec::schema(&columns, multimap, round, samples.as_ref(), context.is_some());
+    let serialize = move |items, writer: &mut _| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+            items,
+            writer,
+            &columns,
+            multimap,
+            round,
+            samples.as_ref(),
+            context.as_mut(),
+            orient,
+            annotation.as_ref(),
+            sample.as_deref(),
+        )
     };
+    Ok((args.format.table(schema), Some(header), Box::new(serialize)))
+}
+
+// Per-site table written along with the ROIs (roi --also-loci). Core options given for the ROIs are forwarded,
+// site options keep their defaults. Run info, checkpoints & reads reports are left to the ROIs run
+pub struct Loci {
+    saveto: SaveTo,
+    serialize: Serializer,
+}
+
+impl Loci {
+    // Options forwarded to the site run as is, the rest belong to the ROIs run only
+    const SKIPPED: [&'static str; 5] = [SAVETO, RUN_INFO, CHECKPOINT, BUILD_INDEX, DUMP_READS];
+
+    pub fn new(
+        matches: &ArgMatches,
+        saveto: &Path,
+        regions: Option<Vec<Interval>>,
+        factory: impl Fn() -> ProgressBar,
+    ) -> Result<(Self, SiteRunConfig), Error> {
+        let mut argv: Vec<OsString> = vec!["site".into(), format!("--{}", SAVETO).into(), saveto.into()];
+        for arg in shared::args::all() {
+            let (id, long) = (arg.get_id(), arg.get_long().unwrap());
+            if Self::SKIPPED.contains(&id) || matches.value_source(id) != Some(ValueSource::CommandLine) {
+                continue;
+            }
+            argv.push(format!("--{}", long).into());
+            if arg.is_takes_value_set() {
+                argv.extend(matches.values_of_os(id).into_iter().flatten().map(|x| x.to_owned()));
+            }
+        }
+        let matches = Command::new("site").args(super::args()).try_get_matches_from(argv).unwrap_or_else(|x| x.exit());
+
+        let mut core = CoreArgs::new(&matches, &factory)?;
+        let mut args = SiteArgs::with_regions(&mut core, &matches, &factory, regions)?;
+        let (format, header, serialize) = serializer(&core, &args)?;
+        let mut config = configure(&core, &mut args, None)?;
+        config.contigs = core.contigs;
+
+        let mut saveto = core.saveto.with_format(format);
+        if let Some(header) = header {
+            saveto = saveto.with_header(header);
+        }
+        if core.schema_line && args.format == OutputFormat::Tsv {
+            saveto = saveto.with_comment(&table::schema_line())?;
+        }
+        Ok((Self { saveto, serialize }, config))
+    }
+
+    pub fn write(&mut self, contig: &str, items: Vec<SiteMismatchesVec>) -> Result<(), Error> {
+        let serialize = &mut self.serialize;
+        self.saveto.write(items, |items, writer| serialize(items, writer))?;
+        self.saveto.commit(contig)
+    }
+
+    pub fn finish(mut self, summary: Summary<SiteMismatchesVec>) -> Result<(), Error> {
+        shared::finalize(summary.stats, &mut self.saveto, HashMap::new())
+    }
+}
+
+// Benjamini-Hochberg q-values for all tested sites, sites above the threshold are dropped (or marked with soft filters)
+// => returns the kept number
+fn significance(results: &mut [(String, Vec<SiteMismatchesVec>)], max_qvalue: Option<f64>, soft: bool) -> usize {
+    let pvalues: Vec<f64> =
+        results.iter().flat_map(|(_, items)| items).flat_map(|x| &x.data.pvalue).flatten().copied().collect();
+    let mut qvalues = statistics::benjamini_hochberg(&pvalues).into_iter();
+
+    let mut kept = 0;
+    for x in results.iter_mut().flat_map(|(_, items)| items) {
+        let data = &mut x.data;
+        for (qvalue, pvalue) in data.qvalue.iter_mut().zip(&data.pvalue) {
+            *qvalue = pvalue.map(|_| qvalues.next().unwrap());
+        }
+        match max_qvalue {
+            Some(max) if soft => {
+                let verdicts: Vec<bool> = data.qvalue.iter().map(|x| x.map_or(true, |q| q <= max)).collect();
+                x.mark("max-qvalue", &verdicts);
+            }
+            Some(max) => data.retain(|x| x.qvalue.map_or(true, |q| q <= max)),
+            None => {}
+        }
+        kept += x.len();
+    }
+    kept
+}
+
+// Effective score cutoff of each contig, contigs with at most K sites are not mentioned
+fn report_top_k(topk: &TopK, ranked: &[(String, usize, f64)], pbar: impl Fn() -> ProgressBar) {
+    if ranked.is_empty() {
+        pbar().finish_with_message(format!("Top-{}: all contigs have at most {} ranked sites", topk.k(), topk.k()));
+        return;
+    }
+    for (contig, _, cutoff) in ranked {
+        pbar().finish_with_message(format!(
+            "Top-{} cutoff for {}: {} >= {:.4}",
+            topk.k(),
+            contig,
+            topk.by().name(),
+            cutoff
+        ));
+    }
+}
+
+// Positions considered after the include/exclude filtering, covered by reads & reported for each processed contig
+fn coverage_summary(
+    considered: &HashMap<String, u64>,
+    contigs: &[ContigSummary],
+    writer: &mut csv::Writer<impl Write>,
+) -> csv::Result<()> {
+    writer.write_record(["contig", "considered", "covered", "emitted"])?;
+    for x in contigs {
+        let total = considered.get(&x.contig).copied().unwrap_or(0);
+        writer.write_record([x.contig.clone(), total.to_string(), x.covered.to_string(), x.emitted.to_string()])?;
+    }
+    Ok(())
 }
 
 // #[cfg(test)]
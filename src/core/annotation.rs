@@ -0,0 +1,174 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
+
+use bio::data_structures::annot_map::AnnotMap;
+use bio_types::annot::contig::Contig;
+use bio_types::genome::Position;
+use bio_types::strand::Strand;
+use flate2::read::MultiGzDecoder;
+use itertools::Itertools;
+
+use crate::core::io;
+use crate::core::io::gff::Record;
+use crate::error::Error;
+
+// Type of the annotated region, variants are ordered by priority
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum FeatureType {
+    Intergenic,
+    Intron,
+    Exon,
+    Utr,
+    Cds,
+}
+
+impl FeatureType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Intergenic => "intergenic",
+            Self::Intron => "intron",
+            Self::Exon => "exon",
+            Self::Utr => "UTR",
+            Self::Cds => "CDS",
+        }
+    }
+
+    // Annotated features only, introns & intergenic regions are inferred from genes
+    fn from_gff(feature: &str) -> Option<Self> {
+        match feature {
+            "exon" | "Exon" => Some(Self::Exon),
+            "UTR" | "five_prime_UTR" | "three_prime_UTR" | "5UTR" | "3UTR" => Some(Self::Utr),
+            "CDS" => Some(Self::Cds),
+            _ => None,
+        }
+    }
+}
+
+// Genes & the highest-priority feature overlapping a given region
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Overlap {
+    // Comma-separated gene names, sorted alphabetically
+    pub genes: String,
+    pub feature: FeatureType,
+}
+
+// Genome annotation used to label reported items, features are matched regardless of their strand
+#[derive(Clone)]
+pub struct GenomicFeatures {
+    // Gene names, if any
+    genes: AnnotMap<String, Option<String>>,
+    // Exons, UTRs & CDS
+    features: AnnotMap<String, FeatureType>,
+}
+
+impl GenomicFeatures {
+    pub fn from_gff(path: &Path, hook: impl Fn(usize)) -> Result<Self, Error> {
+        io::utils::read_compressed!(path, Self::parse_gff, path, hook)?
+    }
+
+    fn parse_gff<T: BufRead>(mut reader: T, path: &Path, hook: impl Fn(usize)) -> Result<Self, Error> {
+        let mut genes: AnnotMap<String, Option<String>> = AnnotMap::new();
+        let mut features: AnnotMap<String, FeatureType> = AnnotMap::new();
+
+        let (mut parsedcnt, mut lineno) = (0, 0);
+        let mut buf = String::new();
+        while reader.read_line(&mut buf).map_err(|x| Error::io(path, x))? != 0 {
+            lineno += 1;
+            if let Some(record) = Record::parse(&buf, path, lineno)? {
+                let contig = Contig::new(
+                    record.contig.to_owned(),
+                    record.start as isize,
+                    (record.end - record.start) as usize,
+                    Strand::Unknown,
+                );
+                if matches!(record.feature, "gene" | "Gene") {
+                    genes.insert_at(record.gene_name().map(|x| x.to_owned()), &contig);
+                    parsedcnt += 1;
+                    hook(parsedcnt);
+                } else if let Some(feature) = FeatureType::from_gff(record.feature) {
+                    features.insert_at(feature, &contig);
+                    parsedcnt += 1;
+                    hook(parsedcnt);
+                }
+            }
+            buf.clear();
+        }
+        Ok(Self { genes, features })
+    }
+
+    pub fn overlap(&self, contig: &str, range: Range<Position>) -> Overlap {
+        let (start, end) = (range.start, range.end);
+        let key = Contig::new(contig.to_owned(), start as isize, (end - start) as usize, Strand::Unknown);
+
+        let genes = self.genes.find(&key).collect_vec();
+        let feature = match self.features.find(&key).map(|x| *x.data()).max() {
+            Some(feature) => feature,
+            None if !genes.is_empty() => FeatureType::Intron,
+            None => FeatureType::Intergenic,
+        };
+        let genes = genes.into_iter().filter_map(|x| x.data().as_deref()).sorted().dedup().join(",");
+        Overlap { genes, feature }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap() {
+        let gff3 = "##gff-version 3\n\
+        1\t.\tgene\t101\t1000\t.\t+\t.\tID=gene:1;Name=B\n\
+        1\t.\texon\t101\t200\t.\t+\t.\tParent=transcript:1\n\
+        1\t.\tfive_prime_UTR\t101\t150\t.\t+\t.\tParent=transcript:1\n\
+        1\t.\tCDS\t151\t200\t.\t+\t.\tParent=transcript:1\n\
+        1\t.\texon\t901\t1000\t.\t+\t.\tParent=transcript:1\n\
+        1\t.\tgene\t951\t2000\t.\t-\t.\tID=gene:2;Name=A\n\
+        1\t.\tgene\t991\t1100\t.\t.\t.\tID=gene:3;Name=B\n\
+        1\t.\tgene\t3001\t4000\t.\t+\t.\n";
+        let dummy =
+            GenomicFeatures::parse_gff(BufReader::new(gff3.as_bytes()), Path::new("genes.gff3"), |_| {}).unwrap();
+
+        for (range, genes, feature) in [
+            (120..121, "B", FeatureType::Utr),
+            (140..160, "B", FeatureType::Cds),
+            (300..301, "B", FeatureType::Intron),
+            (900..901, "B", FeatureType::Exon),
+            // Sorted & deduplicated names of all overlapping genes, regardless of their strand
+            (990..991, "A,B", FeatureType::Exon),
+            (1500..1600, "A", FeatureType::Intron),
+            (2000..3000, "", FeatureType::Intergenic),
+            // Genes without names
+            (3500..3501, "", FeatureType::Intron),
+        ] {
+            let expected = Overlap { genes: genes.to_owned(), feature };
+            assert_eq!(dummy.overlap("1", range), expected);
+        }
+        assert_eq!(dummy.overlap("2", 120..121), Overlap { genes: "".to_owned(), feature: FeatureType::Intergenic });
+    }
+
+    #[test]
+    fn gtf() {
+        let gtf = "chr1\tHAVANA\tgene\t11\t100\t.\t+\t.\tgene_id \"ENSG1\"; gene_name \"DDX11L1\";\n\
+        chr1\tHAVANA\tUTR\t11\t20\t.\t+\t.\tgene_id \"ENSG1\"; gene_name \"DDX11L1\";\n\
+        chr1\tHAVANA\tgene\t51\t200\t.\t-\t.\tgene_id \"ENSG2\";\n";
+        let dummy = GenomicFeatures::parse_gff(BufReader::new(gtf.as_bytes()), Path::new("genes.gtf"), |_| {}).unwrap();
+        assert_eq!(
+            dummy.overlap("chr1", 10..60),
+            Overlap { genes: "DDX11L1,ENSG2".to_owned(), feature: FeatureType::Utr }
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let gtf = "chr1\tHAVANA\tgene\t11\t100\t.\t+\t.\tgene_id \"ENSG1\";\nchr1\tHAVANA\tgene\t51\n";
+        let err = GenomicFeatures::parse_gff(BufReader::new(gtf.as_bytes()), Path::new("genes.gtf"), |_| {});
+        assert_eq!(
+            err.err().unwrap().to_string(),
+            "genes.gtf:2: expected at least 7 tab-separated GFF/GTF columns, got 4"
+        );
+    }
+}
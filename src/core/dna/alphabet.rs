@@ -20,6 +20,16 @@ impl Nucleotide {
             Nucleotide::Unknown => "N",
         }
     }
+
+    pub fn complementary(&self) -> Self {
+        match self {
+            Nucleotide::A => Nucleotide::T,
+            Nucleotide::C => Nucleotide::G,
+            Nucleotide::G => Nucleotide::C,
+            Nucleotide::T => Nucleotide::A,
+            Nucleotide::Unknown => Nucleotide::Unknown,
+        }
+    }
 }
 
 impl Display for Nucleotide {
@@ -57,7 +67,7 @@ impl From<ReqNucleotide> for Nucleotide {
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 #[allow(non_snake_case)]
 pub enum ReqNucleotide {
     A,
@@ -66,6 +76,17 @@ pub enum ReqNucleotide {
     T,
 }
 
+impl ReqNucleotide {
+    pub fn complementary(&self) -> Self {
+        match self {
+            ReqNucleotide::A => ReqNucleotide::T,
+            ReqNucleotide::C => ReqNucleotide::G,
+            ReqNucleotide::G => ReqNucleotide::C,
+            ReqNucleotide::T => ReqNucleotide::A,
+        }
+    }
+}
+
 impl TryFrom<Nucleotide> for ReqNucleotide {
     type Error = ();
 
@@ -0,0 +1,63 @@
+use derive_more::{Add, AddAssign};
+use funty::Numeric;
+
+// Per-position depth, a single locus can't realistically overflow u32
+pub type StrandDepth = InnerStrandDepth<u32>;
+// Depth summed over all positions of a ROI, e.g. ultra-deep amplicons easily exceed u32
+pub type WideStrandDepth = InnerStrandDepth<u64>;
+
+// Number of forward / reverse alignments supporting a locus, regardless of the transcription strand
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Add, AddAssign)]
+pub struct InnerStrandDepth<T: Numeric> {
+    pub forward: T,
+    pub reverse: T,
+}
+
+impl<T: Numeric> InnerStrandDepth<T> {
+    pub fn new(forward: T, reverse: T) -> Self {
+        Self { forward, reverse }
+    }
+
+    // |forward - reverse| / (forward + reverse): 0 - balanced support, 1 - all alignments are from a single strand
+    pub fn bias(&self) -> f32 {
+        let (forward, reverse) = (self.forward.as_f64(), self.reverse.as_f64());
+        let total = forward + reverse;
+        if total == 0f64 {
+            return f32::NAN;
+        }
+        ((forward - reverse).abs() / total) as f32
+    }
+}
+
+impl From<StrandDepth> for WideStrandDepth {
+    fn from(x: StrandDepth) -> Self {
+        Self { forward: x.forward as u64, reverse: x.reverse as u64 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bias() {
+        assert!(StrandDepth::default().bias().is_nan());
+        assert_eq!(StrandDepth::new(5, 5).bias(), 0f32);
+        assert_eq!(StrandDepth::new(0, 3).bias(), 1f32);
+        assert_eq!(StrandDepth::new(6, 2).bias(), 0.5);
+        assert_eq!(StrandDepth::new(1, 2) + StrandDepth::new(3, 0), StrandDepth::new(4, 2));
+        assert_eq!(WideStrandDepth::new(6, 2).bias(), 0.5);
+    }
+
+    #[test]
+    fn wide() {
+        // Near-max per-position depths don't wrap around when summed over a ROI
+        let near_max = StrandDepth::new(u32::MAX - 1, u32::MAX);
+        let mut total = WideStrandDepth::default();
+        for _ in 0..2 {
+            total += near_max.into();
+        }
+        assert_eq!(total, WideStrandDepth::new(2 * (u32::MAX as u64 - 1), 2 * u32::MAX as u64));
+        assert!(total.bias() < 1e-9);
+    }
+}
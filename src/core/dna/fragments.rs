@@ -0,0 +1,83 @@
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::refpred::PredNucleotide;
+
+// Max fragments tracked at a single locus, counts saturate for deeper loci
+pub const MAX_FRAGMENTS: usize = 256;
+
+// Fragments (hashed read names) that sequenced a nucleotide other than the assembly one at a locus.
+// Only mismatches relative to the assembly are tracked to bound the memory, all bases for unknown(N) assembly.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct AltFragments {
+    fragments: Vec<(ReqNucleotide, u64)>,
+}
+
+impl AltFragments {
+    #[inline]
+    pub fn add(&mut self, nuc: ReqNucleotide, fragment: u64) {
+        if self.fragments.len() < MAX_FRAGMENTS && !self.fragments.contains(&(nuc, fragment)) {
+            self.fragments.push((nuc, fragment));
+        }
+    }
+
+    #[inline]
+    pub fn remove(&mut self, nuc: ReqNucleotide, fragment: u64) {
+        if let Some(ind) = self.fragments.iter().position(|x| *x == (nuc, fragment)) {
+            self.fragments.swap_remove(ind);
+        }
+    }
+
+    // Distinct fragments with nucleotides other than the predicted alleles. None if the assembly nucleotide isn't
+    // one of the alleles: its bases were not tracked => the number of fragments is unknown
+    pub fn alt(&self, assembly: Nucleotide, reference: &PredNucleotide) -> Option<u32> {
+        let alleles = match *reference {
+            PredNucleotide::Homozygous(nuc) => [nuc, nuc],
+            PredNucleotide::Heterozygous((n1, n2)) => [n1, n2],
+        };
+        if assembly != Nucleotide::Unknown && !alleles.contains(&assembly) {
+            return None;
+        }
+        let mut distinct: Vec<u64> =
+            self.fragments.iter().filter(|(nuc, _)| !alleles.contains(&(*nuc).into())).map(|x| x.1).collect();
+        distinct.sort_unstable();
+        distinct.dedup();
+        Some(distinct.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt() {
+        let mut dummy = AltFragments::default();
+        for (nuc, fragment) in
+            [(ReqNucleotide::G, 1), (ReqNucleotide::G, 2), (ReqNucleotide::G, 1), (ReqNucleotide::T, 2)]
+        {
+            dummy.add(nuc, fragment);
+        }
+        let (a, g) = (PredNucleotide::Homozygous(Nucleotide::A), PredNucleotide::Homozygous(Nucleotide::G));
+        // Duplicated fragments & fragments with several mismatching nucleotides are counted once
+        assert_eq!(dummy.alt(Nucleotide::A, &a), Some(2));
+        // Both alleles are excluded
+        let heterozygous = PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G));
+        assert_eq!(dummy.alt(Nucleotide::A, &heterozygous), Some(1));
+        // Assembly bases are not tracked
+        assert_eq!(dummy.alt(Nucleotide::A, &g), None);
+        assert_eq!(dummy.alt(Nucleotide::Unknown, &g), Some(1));
+
+        dummy.remove(ReqNucleotide::T, 2);
+        dummy.remove(ReqNucleotide::C, 1);
+        assert_eq!(dummy.alt(Nucleotide::A, &heterozygous), Some(0));
+        assert_eq!(dummy.alt(Nucleotide::A, &a), Some(2));
+    }
+
+    #[test]
+    fn saturated() {
+        let mut dummy = AltFragments::default();
+        for fragment in 0..2 * MAX_FRAGMENTS as u64 {
+            dummy.add(ReqNucleotide::G, fragment);
+        }
+        assert_eq!(dummy.alt(Nucleotide::A, &PredNucleotide::Homozygous(Nucleotide::A)), Some(MAX_FRAGMENTS as u32));
+    }
+}
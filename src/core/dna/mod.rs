@@ -1,5 +1,15 @@
 pub use alphabet::{Nucleotide, ReqNucleotide};
+pub use depth::{StrandDepth, WideStrandDepth};
+pub use fragments::AltFragments;
+pub use multimap::Multimap;
 pub use ncounts::{FracNucCounts, NucCounts};
+pub use quality::BaseQualities;
+pub use substitutions::SubstitutionCounts;
 
 mod alphabet;
+mod depth;
+pub mod fragments;
+mod multimap;
 pub mod ncounts;
+mod quality;
+pub mod substitutions;
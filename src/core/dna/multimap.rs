@@ -0,0 +1,58 @@
+// Contribution of reads aligned to several loci (NH tag > 1) to the nucleotide counts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Multimap {
+    // Each alignment is counted as a whole read
+    Full,
+    // Each alignment is weighted by 1 / NH.
+    // Weights are stored as fixed-point integers => counts are in 1 / SCALE units and count thresholds
+    // (e.g. min coverage) must be multiplied by the scale as well
+    Fractional,
+}
+
+impl Multimap {
+    // Divisible by 1..=8 => exact weights for the most common NH values, ~5M reads per position before overflow
+    pub const SCALE: u32 = 840;
+
+    #[inline]
+    pub fn scale(&self) -> u32 {
+        match self {
+            Multimap::Full => 1,
+            Multimap::Fractional => Self::SCALE,
+        }
+    }
+
+    // Weight of a single alignment given the total number of alignments for the read
+    #[inline]
+    pub fn weight(&self, hits: u32) -> u32 {
+        match self {
+            Multimap::Full => 1,
+            // Rounded to the nearest integer, reads without the NH tag are counted fully
+            Multimap::Fractional => ((Self::SCALE + hits / 2) / hits.max(1)).max(1),
+        }
+    }
+
+    // Counts in natural units, i.e. the (weighted) number of reads
+    #[inline]
+    pub fn natural(&self, count: u32) -> f32 {
+        count as f32 / self.scale() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight() {
+        for hits in [0, 1, 2, 8, 1000] {
+            assert_eq!(Multimap::Full.weight(hits), 1);
+        }
+        let fractional = Multimap::Fractional;
+        for (hits, expected) in [(0, 1f32), (1, 1f32), (2, 0.5), (3, 1f32 / 3f32), (8, 0.125)] {
+            assert_eq!(fractional.natural(fractional.weight(hits)), expected);
+        }
+        // Inexact but never zero
+        assert!((fractional.natural(fractional.weight(9)) - 1f32 / 9f32).abs() < 1e-3);
+        assert_eq!(fractional.weight(10_000), 1);
+    }
+}
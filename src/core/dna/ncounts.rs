@@ -6,8 +6,11 @@ use derive_more::{Add, AddAssign, Mul};
 use crate::core::dna::{Nucleotide, ReqNucleotide};
 use funty::Numeric;
 
+// Per-position counts, a single locus can't realistically overflow u32
 pub type NucCounts = InnerNucCounts<u32>;
-pub type FracNucCounts = InnerNucCounts<f32>;
+// Weighted counts aggregated over ROIs & whole experiments. f32 silently stops accumulating past 2^24,
+// f64 is exact for integers up to 2^53
+pub type FracNucCounts = InnerNucCounts<f64>;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Add, AddAssign, Mul, Default)]
 #[allow(non_snake_case)]
@@ -136,13 +139,13 @@ impl<T: Numeric> IndexMut<ReqNucleotide> for InnerNucCounts<T> {
 
 impl From<&'_ NucCounts> for FracNucCounts {
     fn from(nc: &'_ NucCounts) -> Self {
-        Self { A: nc.A as f32, C: nc.C as f32, G: nc.G as f32, T: nc.T as f32 }
+        Self { A: nc.A as f64, C: nc.C as f64, G: nc.G as f64, T: nc.T as f64 }
     }
 }
 
 impl From<NucCounts> for FracNucCounts {
     fn from(nc: NucCounts) -> Self {
-        Self { A: nc.A as f32, C: nc.C as f32, G: nc.G as f32, T: nc.T as f32 }
+        Self { A: nc.A as f64, C: nc.C as f64, G: nc.G as f64, T: nc.T as f64 }
     }
 }
 
@@ -226,4 +229,24 @@ mod tests {
         a += b;
         assert_eq!(a, result);
     }
+
+    #[test]
+    fn complementary() {
+        let counts = InnerNucCounts { A: 1, C: 2, G: 3, T: 4 };
+        assert_eq!(counts.complementary(), InnerNucCounts { A: 4, C: 3, G: 2, T: 1 });
+        assert_eq!(counts.complementary().complementary(), counts);
+    }
+
+    #[test]
+    fn no_wraparound() {
+        // u32 counts would wrap around, f32 ones would lose the increments
+        let near_max = NucCounts::new(u32::MAX, u32::MAX - 1, 1, 0);
+        let mut total = FracNucCounts::from(near_max);
+        total += near_max.into();
+        assert_eq!(total, FracNucCounts::new(2f64 * u32::MAX as f64, 2f64 * (u32::MAX - 1) as f64, 2f64, 0f64));
+        assert_eq!(total.coverage(), 4f64 * u32::MAX as f64);
+
+        total += NucCounts::C(1).into();
+        assert_eq!(total.C - 2f64 * (u32::MAX - 1) as f64, 1f64);
+    }
 }
@@ -0,0 +1,96 @@
+use crate::core::dna::ncounts::InnerNucCounts;
+use crate::core::dna::substitutions::NUCLEOTIDES;
+use crate::core::dna::{NucCounts, ReqNucleotide};
+use crate::core::refpred::PredNucleotide;
+
+// Base qualities of the nucleotides sequenced at a locus. Sums are weighted just like the counts => mean = sum / count.
+// Fractional weights are in 1 / Multimap::SCALE units => sums would overflow u32 for deep loci
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BaseQualities {
+    pub sum: InnerNucCounts<u64>,
+    // u8::MAX for nucleotides that were never sequenced
+    pub min: InnerNucCounts<u8>,
+}
+
+impl Default for BaseQualities {
+    fn default() -> Self {
+        Self { sum: InnerNucCounts::zeros(), min: InnerNucCounts::new(u8::MAX, u8::MAX, u8::MAX, u8::MAX) }
+    }
+}
+
+impl BaseQualities {
+    #[inline]
+    pub fn add(&mut self, nuc: ReqNucleotide, qual: u8, weight: u32) {
+        self.sum[nuc] += qual as u64 * weight as u64;
+        self.min[nuc] = self.min[nuc].min(qual);
+    }
+
+    // The min can't be rolled back => it still accounts for the removed base
+    #[inline]
+    pub fn remove(&mut self, nuc: ReqNucleotide, qual: u8, weight: u32) {
+        self.sum[nuc] -= qual as u64 * weight as u64;
+    }
+
+    // Mean & min quality of the mismatching nucleotides, i.e. all but both alleles for heterozygous references.
+    // None if there are no mismatches
+    pub fn alt(&self, sequenced: &NucCounts, reference: &PredNucleotide) -> (Option<f32>, Option<u8>) {
+        let alleles = match *reference {
+            PredNucleotide::Homozygous(nuc) => [nuc, nuc],
+            PredNucleotide::Heterozygous((n1, n2)) => [n1, n2],
+        };
+        let (mut count, mut sum, mut min) = (0u64, 0u64, u8::MAX);
+        for nuc in NUCLEOTIDES {
+            if sequenced[nuc] == 0 || alleles.contains(&nuc.into()) {
+                continue;
+            }
+            count += sequenced[nuc] as u64;
+            sum += self.sum[nuc];
+            min = min.min(self.min[nuc]);
+        }
+        match count {
+            0 => (None, None),
+            _ => (Some(sum as f32 / count as f32), Some(min)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::Nucleotide;
+
+    use super::*;
+
+    #[test]
+    fn alt() {
+        let mut dummy = BaseQualities::default();
+        for (nuc, qual) in
+            [(ReqNucleotide::A, 40), (ReqNucleotide::G, 10), (ReqNucleotide::G, 30), (ReqNucleotide::T, 5)]
+        {
+            dummy.add(nuc, qual, 1);
+        }
+        let sequenced = NucCounts::new(1, 0, 2, 1);
+
+        assert_eq!(dummy.alt(&sequenced, &PredNucleotide::Homozygous(Nucleotide::A)), (Some(15f32), Some(5)));
+        assert_eq!(dummy.alt(&sequenced, &PredNucleotide::Homozygous(Nucleotide::G)), (Some(22.5), Some(5)));
+        // Both alleles are excluded
+        let heterozygous = PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::T));
+        assert_eq!(dummy.alt(&sequenced, &heterozygous), (Some(20f32), Some(10)));
+        // All nucleotides are mismatches for unknown references
+        assert_eq!(dummy.alt(&sequenced, &PredNucleotide::Homozygous(Nucleotide::Unknown)), (Some(21.25), Some(5)));
+        // No mismatches
+        assert_eq!(dummy.alt(&NucCounts::A(1), &PredNucleotide::Homozygous(Nucleotide::A)), (None, None));
+    }
+
+    #[test]
+    fn weighted() {
+        let mut dummy = BaseQualities::default();
+        dummy.add(ReqNucleotide::C, 30, 840);
+        dummy.add(ReqNucleotide::C, 20, 420);
+        dummy.remove(ReqNucleotide::C, 20, 420);
+        assert_eq!(dummy.sum.C, 30 * 840);
+        // The min is kept after removal
+        assert_eq!(dummy.min.C, 20);
+        let sequenced = NucCounts::C(840);
+        assert_eq!(dummy.alt(&sequenced, &PredNucleotide::Homozygous(Nucleotide::A)), (Some(30f32), Some(20)));
+    }
+}
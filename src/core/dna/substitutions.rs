@@ -0,0 +1,118 @@
+use std::ops::AddAssign;
+
+use crate::core::dna::{NucCounts, ReqNucleotide};
+use crate::core::refpred::PredNucleotide;
+
+// Positions with any mismatch above this frequency look like variants (SNPs, editing) rather than errors
+pub const VARIANT_FREQ: f32 = 0.3;
+
+pub const NUCLEOTIDES: [ReqNucleotide; 4] = [ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T];
+
+// Sequenced nucleotides (columns) for each reference nucleotide (rows) at non-variable positions,
+// i.e. the background substitution (sequencing & alignment errors) profile of the library
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SubstitutionCounts {
+    counts: [[u64; 4]; 4],
+}
+
+impl SubstitutionCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Heterozygous & unknown reference positions are skipped as well as the variable ones
+    pub fn add(&mut self, reference: PredNucleotide, sequenced: &NucCounts) {
+        let reference = match reference {
+            PredNucleotide::Homozygous(nuc) => match ReqNucleotide::try_from(nuc) {
+                Ok(nuc) => nuc,
+                Err(_) => return,
+            },
+            PredNucleotide::Heterozygous(_) => return,
+        };
+        let coverage = sequenced.coverage();
+        if coverage == 0 {
+            return;
+        }
+        let maxfreq = VARIANT_FREQ * coverage as f32;
+        if NUCLEOTIDES.iter().any(|x| *x != reference && sequenced[*x] as f32 > maxfreq) {
+            return;
+        }
+        for nuc in NUCLEOTIDES {
+            self.counts[reference as usize][nuc as usize] += sequenced[nuc] as u64;
+        }
+    }
+
+    pub fn count(&self, reference: ReqNucleotide, sequenced: ReqNucleotide) -> u64 {
+        self.counts[reference as usize][sequenced as usize]
+    }
+
+    // Fraction of the reference nucleotide sequenced as the given one. A single pseudo count keeps rates positive,
+    // references without any coverage get the rate of 1 => nothing is significant for them
+    pub fn rate(&self, reference: ReqNucleotide, sequenced: ReqNucleotide) -> f64 {
+        let total: u64 = self.counts[reference as usize].iter().sum();
+        (self.count(reference, sequenced) + 1) as f64 / (total + 1) as f64
+    }
+}
+
+impl AddAssign for SubstitutionCounts {
+    fn add_assign(&mut self, rhs: Self) {
+        for (row, other) in self.counts.iter_mut().zip(rhs.counts) {
+            for (x, y) in row.iter_mut().zip(other) {
+                *x += y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::Nucleotide;
+
+    use super::*;
+
+    #[test]
+    fn add() {
+        let mut dummy = SubstitutionCounts::new();
+        // Errors are counted
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(97, 1, 2, 0));
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(10, 0, 0, 0));
+        // Variable, heterozygous & unknown positions are not
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(6, 0, 4, 0));
+        dummy.add(PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G)), &NucCounts::new(90, 0, 10, 0));
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::Unknown), &NucCounts::new(1, 1, 1, 1));
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::T), &NucCounts::zeros());
+
+        let (a, c, g, t) = (ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T);
+        assert_eq!([dummy.count(a, a), dummy.count(a, c), dummy.count(a, g), dummy.count(a, t)], [107, 1, 2, 0]);
+        for reference in [c, g, t] {
+            assert!(NUCLEOTIDES.iter().all(|x| dummy.count(reference, *x) == 0));
+        }
+
+        // Exactly at the threshold => still an error
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::C), &NucCounts::new(0, 7, 0, 3));
+        assert_eq!(dummy.count(c, t), 3);
+    }
+
+    #[test]
+    fn rate() {
+        let mut dummy = SubstitutionCounts::new();
+        dummy.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(996, 0, 3, 0));
+        assert!((dummy.rate(ReqNucleotide::A, ReqNucleotide::G) - 0.004).abs() < 1e-12);
+        assert!((dummy.rate(ReqNucleotide::A, ReqNucleotide::C) - 0.001).abs() < 1e-12);
+        assert_eq!(dummy.rate(ReqNucleotide::T, ReqNucleotide::C), 1f64);
+    }
+
+    #[test]
+    fn merge() {
+        let (mut first, mut second) = (SubstitutionCounts::new(), SubstitutionCounts::new());
+        first.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(10, 1, 0, 0));
+        second.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(20, 0, 1, 0));
+        second.add(PredNucleotide::Homozygous(Nucleotide::G), &NucCounts::new(1, 0, 30, 0));
+        first += second;
+        assert_eq!(first.count(ReqNucleotide::A, ReqNucleotide::A), 30);
+        assert_eq!(first.count(ReqNucleotide::A, ReqNucleotide::C), 1);
+        assert_eq!(first.count(ReqNucleotide::A, ReqNucleotide::G), 1);
+        assert_eq!(first.count(ReqNucleotide::G, ReqNucleotide::A), 1);
+        assert_eq!(first.count(ReqNucleotide::G, ReqNucleotide::G), 30);
+    }
+}
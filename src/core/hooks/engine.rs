@@ -1,17 +1,26 @@
+use bio_types::strand::Strand;
+
 use crate::core::hooks::filters::Filter;
 use crate::core::hooks::stats::EditingStat;
 use crate::core::hooks::{Hook, HooksEngine};
 use crate::core::mismatches::{Batch, MismatchesVec};
+use crate::core::strandutil::Stranded;
 
 #[derive(Default)]
 pub struct REATHooksEngine<T> {
     stats: Vec<Box<dyn EditingStat<T>>>,
     filters: Vec<Box<dyn Filter<T>>>,
+    // Mark records failing the filters instead of dropping them
+    soft: bool,
 }
 
 impl<T> REATHooksEngine<T> {
     pub fn new() -> Self {
-        Self { stats: vec![], filters: vec![] }
+        Self { stats: vec![], filters: vec![], soft: false }
+    }
+
+    pub fn soft_filters(&mut self) {
+        self.soft = true;
     }
 
     pub fn add_stat(&mut self, stat: Box<dyn EditingStat<T>>) {
@@ -28,17 +37,35 @@ impl<T> Clone for REATHooksEngine<T> {
         Self {
             stats: self.stats.iter().map(|x| dyn_clone::clone_box(x.as_ref())).collect(),
             filters: self.filters.iter().map(|x| dyn_clone::clone_box(x.as_ref())).collect(),
+            soft: self.soft,
         }
     }
 }
 
+// Returns false if the filter can't be applied softly
+fn mark<T: MismatchesVec>(filter: &dyn Filter<T>, items: &mut Stranded<T>) -> bool {
+    for (x, strand) in [
+        (&mut items.forward, Strand::Forward),
+        (&mut items.reverse, Strand::Reverse),
+        (&mut items.unknown, Strand::Unknown),
+    ] {
+        match filter.classify(x, strand) {
+            Some(verdicts) => x.mark(filter.name(), &verdicts),
+            None => return false,
+        }
+    }
+    true
+}
+
 impl<T: MismatchesVec> Hook<T> for REATHooksEngine<T> {
     fn on_finish(&mut self, mismatches: &mut Batch<T>) {
         for s in &mut self.stats {
             s.on_finish(mismatches);
         }
         for f in &mut self.filters {
-            f.on_finish(mismatches);
+            if !(self.soft && mark(f.as_ref(), &mut mismatches.items)) {
+                f.on_finish(mismatches);
+            }
         }
     }
 }
@@ -48,3 +75,72 @@ impl<T: MismatchesVec> HooksEngine<T> for REATHooksEngine<T> {
         self.stats
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{Multimap, Nucleotide, ReqNucleotide};
+    use crate::core::hooks::filters::ByRefBase;
+    use crate::core::mismatches::site::{SiteColumn, SiteData, SiteDataVec, SiteMismatchesVec};
+    use crate::core::refpred::PredNucleotide;
+
+    use super::*;
+
+    fn batch() -> Batch<SiteMismatchesVec> {
+        let empty = || Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: empty(),
+            retained: empty(),
+        };
+        for (pos, nuc) in [(0, Nucleotide::A), (1, Nucleotide::G), (2, Nucleotide::C)] {
+            let prednuc = PredNucleotide::Homozygous(nuc);
+            batch.items.forward.data.push(SiteData { pos, prednuc, ..Default::default() });
+        }
+        batch
+    }
+
+    #[test]
+    fn soft_filters() {
+        let mut engine = REATHooksEngine::new();
+        engine.add_filter(Box::new(ByRefBase::new(&[ReqNucleotide::A, ReqNucleotide::G])));
+        engine.add_filter(Box::new(ByRefBase::new(&[ReqNucleotide::A])));
+
+        // Hard filters drop records
+        let mut hard = batch();
+        engine.clone().on_finish(&mut hard);
+        assert_eq!(hard.items.forward.data.pos, vec![0]);
+
+        // Soft filters keep all records and label the failing ones
+        let mut soft = batch();
+        engine.soft_filters();
+        engine.on_finish(&mut soft);
+        assert_eq!(soft.items.forward.data.pos, vec![0, 1, 2]);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        let columns = [SiteColumn::Pos, SiteColumn::Filter];
+        let items = vec![soft.items.forward];
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+            items,
+            &mut writer,
+            &columns,
+            Multimap::Full,
+            false,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(written, "pos,filter\n0,PASS\n1,ref-base\n2,ref-base;ref-base\n");
+    }
+}
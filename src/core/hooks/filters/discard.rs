@@ -0,0 +1,22 @@
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Drop all ROIs once they were seen by the stats (stats run before filters), i.e. nothing is kept in memory
+// until the end of the contig when only the stats are reported
+#[derive(Clone, Default)]
+pub struct Discard;
+
+impl Hook<ROIMismatchesVec> for Discard {
+    fn on_finish(&mut self, mm: &mut Batch<ROIMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.clear());
+        mm.retained.apply_mut(|x, _| x.data.clear());
+    }
+}
+
+impl Filter<ROIMismatchesVec> for Discard {
+    fn name(&self) -> &'static str {
+        "discard"
+    }
+}
@@ -0,0 +1,162 @@
+use crate::core::dna::substitutions::NUCLEOTIDES;
+use crate::core::dna::{NucCounts, ReqNucleotide, SubstitutionCounts};
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+use crate::core::refpred::PredNucleotide;
+use crate::core::statistics;
+
+// Binomial test of each site against the background substitution rates learned by a previous pass.
+// Only p-values are assigned here, q-values require all sites and must be computed once the run is over.
+// Retained sites as well as heterozygous / unknown references are not tested
+#[derive(Clone)]
+pub struct ByErrorRates {
+    background: SubstitutionCounts,
+    // Counts are in 1 / scale units (e.g. fractional multimappers)
+    scale: f64,
+}
+
+impl ByErrorRates {
+    pub fn new(background: SubstitutionCounts, scale: u32) -> Self {
+        Self { background, scale: scale as f64 }
+    }
+
+    // The smallest p-value among all possible substitutions, Bonferroni-corrected for their number
+    pub fn pvalue(&self, reference: PredNucleotide, sequenced: &NucCounts) -> Option<f64> {
+        let reference = match reference {
+            PredNucleotide::Homozygous(nuc) => ReqNucleotide::try_from(nuc).ok()?,
+            PredNucleotide::Heterozygous(_) => return None,
+        };
+        let trials = sequenced.coverage() as f64 / self.scale;
+        let minimum = NUCLEOTIDES
+            .iter()
+            .filter(|x| **x != reference)
+            .map(|x| {
+                let successes = sequenced[*x] as f64 / self.scale;
+                statistics::binomial_sf(successes, trials, self.background.rate(reference, *x))
+            })
+            .fold(1f64, f64::min);
+        Some((minimum * (NUCLEOTIDES.len() - 1) as f64).min(1f64))
+    }
+}
+
+impl Hook<SiteMismatchesVec> for ByErrorRates {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, _| {
+            // Reference & counts are always in the forward strand orientation, as well as the background rates
+            let data = &mut x.data;
+            for (pvalue, (prednuc, sequenced)) in data.pvalue.iter_mut().zip(data.prednuc.iter().zip(&data.sequenced)) {
+                *pvalue = self.pvalue(*prednuc, sequenced);
+            }
+        });
+    }
+}
+
+impl Filter<SiteMismatchesVec> for ByErrorRates {
+    fn name(&self) -> &'static str {
+        "error-rates"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::strand::Strand;
+
+    use crate::core::dna::Nucleotide;
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    fn background() -> SubstitutionCounts {
+        // ~0.1% error rate for each substitution
+        let mut counts = SubstitutionCounts::new();
+        for nuc in [Nucleotide::A, Nucleotide::C, Nucleotide::G, Nucleotide::T] {
+            let mut sequenced = NucCounts::new(1, 1, 1, 1);
+            sequenced[ReqNucleotide::try_from(nuc).unwrap()] = 997;
+            for _ in 0..1000 {
+                counts.add(PredNucleotide::Homozygous(nuc), &sequenced);
+            }
+        }
+        counts
+    }
+
+    fn site(pos: u64, prednuc: PredNucleotide, sequenced: NucCounts) -> SiteData {
+        SiteData { pos, prednuc, sequenced, ..Default::default() }
+    }
+
+    #[test]
+    fn pvalue() {
+        let dummy = ByErrorRates::new(background(), 1);
+        let a = PredNucleotide::Homozygous(Nucleotide::A);
+        // No mismatches
+        assert_eq!(dummy.pvalue(a, &NucCounts::new(100, 0, 0, 0)), Some(1f64));
+        // Background-level mismatches
+        assert!(dummy.pvalue(a, &NucCounts::new(99, 0, 1, 0)).unwrap() > 0.1);
+        // Editing
+        assert!(dummy.pvalue(a, &NucCounts::new(90, 0, 10, 0)).unwrap() < 1e-10);
+        // Not tested
+        assert_eq!(dummy.pvalue(PredNucleotide::Homozygous(Nucleotide::Unknown), &NucCounts::new(9, 0, 1, 0)), None);
+        let heterozygous = PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G));
+        assert_eq!(dummy.pvalue(heterozygous, &NucCounts::new(50, 0, 50, 0)), None);
+
+        // Scaled counts give the same results
+        let scaled = ByErrorRates::new(background(), 10);
+        assert_eq!(scaled.pvalue(a, &NucCounts::new(900, 0, 100, 0)), dummy.pvalue(a, &NucCounts::new(90, 0, 10, 0)));
+    }
+
+    #[test]
+    fn spiked_in() {
+        // Background sites with sporadic errors & A->G edits at various levels, 100x coverage
+        let (a, t) = (PredNucleotide::Homozygous(Nucleotide::A), PredNucleotide::Homozygous(Nucleotide::T));
+        let (mut forward, mut reverse) = (SiteDataVec::new(), SiteDataVec::new());
+        for pos in 0..300 {
+            let errors = (pos % 10 == 0) as u32;
+            forward.push(site(pos, a, NucCounts::new(100 - errors, 0, errors, 0)));
+            reverse.push(site(pos, t, NucCounts::new(0, errors, 0, 100 - errors)));
+        }
+        for (pos, edited) in (300..330).zip(5..35) {
+            forward.push(site(pos, a, NucCounts::new(100 - edited, 0, edited, 0)));
+            reverse.push(site(pos, t, NucCounts::new(0, edited, 0, 100 - edited)));
+        }
+
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new())),
+            retained: Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new())),
+        };
+        batch.items.forward = SiteMismatchesVec::new("1".into(), Strand::Forward, forward);
+        batch.items.reverse = SiteMismatchesVec::new("1".into(), Strand::Reverse, reverse);
+        batch.retained.forward.data.push(site(0, a, NucCounts::new(90, 0, 10, 0)));
+        ByErrorRates::new(background(), 1).on_finish(&mut batch);
+
+        // Retained sites are not tested
+        assert_eq!(batch.retained.forward.data.pvalue, vec![None]);
+
+        let pvalues: Vec<f64> = [&batch.items.forward, &batch.items.reverse]
+            .into_iter()
+            .flat_map(|x| x.data.pvalue.iter().map(|p| p.unwrap()))
+            .collect();
+        let qvalues = statistics::benjamini_hochberg(&pvalues);
+        let edited: Vec<bool> = [&batch.items.forward, &batch.items.reverse]
+            .into_iter()
+            .flat_map(|x| x.data.pos.iter().map(|pos| *pos >= 300))
+            .collect();
+        for (q, edited) in qvalues.into_iter().zip(edited) {
+            if edited {
+                assert!(q < 1e-3, "{}", q);
+            } else {
+                assert!(q > 0.1, "{}", q);
+            }
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use std::fmt::{Display, Formatter};
+
+use bio_types::strand::Strand;
+
+use crate::core::dna::ReqNucleotide;
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::{ROIDataRef, ROIMismatchesVec};
+use crate::core::mismatches::site::{SiteDataRef, SiteMismatchesVec};
+use crate::core::mismatches::Batch;
+use crate::core::refpred::PredNucleotide;
+
+const NUCLEOTIDES: [ReqNucleotide; 4] = [ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T];
+
+// Records the expression is compiled for, some fields are available only for ROIs or sites
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Records {
+    ROIs,
+    Sites,
+}
+
+// Report only records matching a boolean expression over their fields, e.g. "A->G >= 3 AND coverage >= 20".
+// The expression is compiled once, retained records are never dropped
+#[derive(Clone, Debug)]
+pub struct ByExpression {
+    expr: Expr,
+    // Site counts are in 1 / scale units, fields are always in natural units
+    scale: f64,
+}
+
+impl ByExpression {
+    pub fn new(expr: &str, records: Records) -> Result<Self, String> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens: &tokens, cursor: 0, records, end: expr.chars().count() + 1 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {} at position {}", token.kind, token.pos));
+        }
+        Ok(Self { expr, scale: 1f64 })
+    }
+
+    // Multimap scale of the site counts
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale as f64;
+        self
+    }
+
+    #[inline]
+    pub fn is_roi_ok(&self, roi: &ROIDataRef, trstrand: Strand) -> bool {
+        let mismatches = roi.mismatches;
+        self.expr.eval(&|field| match field {
+            Field::Coverage => Value::Number(*roi.coverage as f64),
+            Field::Mismatches => Value::Number(mismatches.mismatches()),
+            Field::Freq => Value::Number(freq(mismatches.mismatches(), mismatches.coverage())),
+            Field::Strand => Value::Strand(trstrand),
+            Field::Mismatch(reference, sequenced) => Value::Number(mismatches[reference][sequenced]),
+            Field::Count(_) => unreachable!(),
+        })
+    }
+
+    #[inline]
+    pub fn is_site_ok(&self, site: &SiteDataRef, trstrand: Strand) -> bool {
+        let sequenced = site.sequenced;
+        let mismatches = match site.prednuc {
+            PredNucleotide::Homozygous(nuc) => sequenced.mismatches(*nuc),
+            // Same as for the built-in filters: either allele is enough
+            PredNucleotide::Heterozygous((n1, n2)) => sequenced.mismatches(*n1).max(sequenced.mismatches(*n2)),
+        };
+        let scaled = |x: u32| x as f64 / self.scale;
+        self.expr.eval(&|field| match field {
+            Field::Coverage => Value::Number(scaled(sequenced.coverage())),
+            Field::Mismatches => Value::Number(scaled(mismatches)),
+            Field::Freq => Value::Number(freq(mismatches as f64, sequenced.coverage() as f64)),
+            Field::Strand => Value::Strand(trstrand),
+            Field::Count(nuc) => Value::Number(scaled(sequenced[nuc])),
+            Field::Mismatch(..) => unreachable!(),
+        })
+    }
+}
+
+impl Hook<ROIMismatchesVec> for ByExpression {
+    fn on_finish(&mut self, mm: &mut Batch<ROIMismatchesVec>) {
+        mm.items.apply_mut(|x, strand| x.data.retain(|x| self.is_roi_ok(&x, strand)));
+    }
+}
+
+impl Filter<ROIMismatchesVec> for ByExpression {
+    fn name(&self) -> &'static str {
+        "expression"
+    }
+
+    fn classify(&self, items: &ROIMismatchesVec, strand: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_roi_ok(&x, strand)).collect())
+    }
+}
+
+impl Hook<SiteMismatchesVec> for ByExpression {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, strand| x.data.retain(|x| self.is_site_ok(&x, strand)));
+    }
+}
+
+impl Filter<SiteMismatchesVec> for ByExpression {
+    fn name(&self) -> &'static str {
+        "expression"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, strand: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_site_ok(&x, strand)).collect())
+    }
+}
+
+#[inline]
+fn freq(mismatches: f64, coverage: f64) -> f64 {
+    if coverage <= 0f64 {
+        return f64::NAN;
+    }
+    mismatches / coverage
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Field {
+    Coverage,
+    Mismatches,
+    // Mismatches / coverage, NaN for uncovered records
+    Freq,
+    // Transcription strand
+    Strand,
+    // ROIs: reference => sequenced nucleotide
+    Mismatch(ReqNucleotide, ReqNucleotide),
+    // Sites: number of sequenced nucleotides
+    Count(ReqNucleotide),
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        let field = match name {
+            "coverage" => Self::Coverage,
+            "mismatches" => Self::Mismatches,
+            "freq" => Self::Freq,
+            "strand" => Self::Strand,
+            _ => {
+                let nuc = |x: &str| NUCLEOTIDES.into_iter().find(|n| n.symbol() == x);
+                match name.split_once("->") {
+                    Some((reference, sequenced)) => Self::Mismatch(nuc(reference)?, nuc(sequenced)?),
+                    None => Self::Count(nuc(name)?),
+                }
+            }
+        };
+        Some(field)
+    }
+
+    fn records(&self) -> Option<Records> {
+        match self {
+            Self::Mismatch(..) => Some(Records::ROIs),
+            Self::Count(_) => Some(Records::Sites),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Value {
+    Number(f64),
+    Strand(Strand),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Operand {
+    Field(Field),
+    Literal(Value),
+}
+
+impl Operand {
+    fn is_strand(&self) -> bool {
+        matches!(self, Operand::Field(Field::Strand) | Operand::Literal(Value::Strand(_)))
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Operand, Cmp, Operand),
+}
+
+impl Expr {
+    fn eval(&self, fields: &impl Fn(Field) -> Value) -> bool {
+        match self {
+            Expr::Or(left, right) => left.eval(fields) || right.eval(fields),
+            Expr::And(left, right) => left.eval(fields) && right.eval(fields),
+            Expr::Not(inner) => !inner.eval(fields),
+            Expr::Cmp(left, cmp, right) => {
+                let value = |x: &Operand| match x {
+                    Operand::Field(field) => fields(*field),
+                    Operand::Literal(value) => *value,
+                };
+                match (value(left), value(right)) {
+                    // NaN frequencies don't pass any comparison except !=
+                    (Value::Number(left), Value::Number(right)) => match cmp {
+                        Cmp::Lt => left < right,
+                        Cmp::Le => left <= right,
+                        Cmp::Gt => left > right,
+                        Cmp::Ge => left >= right,
+                        Cmp::Eq => left == right,
+                        Cmp::Ne => left != right,
+                    },
+                    (Value::Strand(left), Value::Strand(right)) => match cmp {
+                        Cmp::Eq => left == right,
+                        Cmp::Ne => left != right,
+                        _ => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum TokenKind {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Cmp(Cmp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Ident(x) => write!(f, "identifier \"{}\"", x),
+            TokenKind::Number(x) => write!(f, "number {}", x),
+            TokenKind::Str(x) => write!(f, "string \"{}\"", x),
+            TokenKind::Cmp(_) => write!(f, "comparison operator"),
+            TokenKind::And => write!(f, "AND"),
+            TokenKind::Or => write!(f, "OR"),
+            TokenKind::Not => write!(f, "NOT"),
+            TokenKind::LParen => write!(f, "'('"),
+            TokenKind::RParen => write!(f, "')'"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Token {
+    kind: TokenKind,
+    // 1-based position of the first character
+    pos: usize,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut ind = 0;
+    while ind < chars.len() {
+        let (ch, next) = (chars[ind], chars.get(ind + 1).copied());
+        let start = ind;
+        let kind = match (ch, next) {
+            _ if ch.is_whitespace() => {
+                ind += 1;
+                continue;
+            }
+            ('(', _) => TokenKind::LParen,
+            (')', _) => TokenKind::RParen,
+            ('<', Some('=')) | ('>', Some('=')) | ('=', Some('=')) | ('!', Some('=')) => {
+                ind += 1;
+                TokenKind::Cmp(match ch {
+                    '<' => Cmp::Le,
+                    '>' => Cmp::Ge,
+                    '=' => Cmp::Eq,
+                    _ => Cmp::Ne,
+                })
+            }
+            ('<', _) => TokenKind::Cmp(Cmp::Lt),
+            ('>', _) => TokenKind::Cmp(Cmp::Gt),
+            ('≤', _) => TokenKind::Cmp(Cmp::Le),
+            ('≥', _) => TokenKind::Cmp(Cmp::Ge),
+            ('≠', _) => TokenKind::Cmp(Cmp::Ne),
+            ('!', _) => TokenKind::Not,
+            ('&', Some('&')) | ('|', Some('|')) => {
+                ind += 1;
+                if ch == '&' {
+                    TokenKind::And
+                } else {
+                    TokenKind::Or
+                }
+            }
+            ('"', _) | ('\'', _) => {
+                let end = chars[ind + 1..]
+                    .iter()
+                    .position(|x| *x == ch)
+                    .ok_or_else(|| format!("Unterminated string at position {}", start + 1))?;
+                ind += end + 1;
+                TokenKind::Str(chars[start + 1..ind].iter().collect())
+            }
+            _ if ch.is_ascii_digit() || (ch == '.' && next.map_or(false, |x| x.is_ascii_digit())) => {
+                while ind + 1 < chars.len() && (chars[ind + 1].is_ascii_digit() || chars[ind + 1] == '.') {
+                    ind += 1;
+                }
+                let number: String = chars[start..=ind].iter().collect();
+                let number =
+                    number.parse().map_err(|_| format!("Invalid number {} at position {}", number, start + 1))?;
+                TokenKind::Number(number)
+            }
+            _ if ch.is_alphabetic() || ch == '_' => {
+                while ind + 1 < chars.len() && (chars[ind + 1].is_alphanumeric() || chars[ind + 1] == '_') {
+                    ind += 1;
+                }
+                let mut word: String = chars[start..=ind].iter().collect();
+                // Mismatches as in the table header, e.g. A->G or A→G
+                let arrow = match (chars.get(ind + 1), chars.get(ind + 2)) {
+                    (Some('-'), Some('>')) => 2,
+                    (Some('→'), _) => 1,
+                    _ => 0,
+                };
+                if arrow > 0 && chars.get(ind + arrow + 1).map_or(false, |x| x.is_alphabetic()) {
+                    ind += arrow + 1;
+                    word = format!("{}->{}", word, chars[ind]);
+                }
+                match word.to_ascii_uppercase().as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => TokenKind::Ident(word),
+                }
+            }
+            _ => return Err(format!("Unexpected character '{}' at position {}", ch, start + 1)),
+        };
+        tokens.push(Token { kind, pos: start + 1 });
+        ind += 1;
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, from the lowest precedence: OR < AND < NOT < comparisons & parentheses
+struct Parser<'a> {
+    tokens: &'a [Token],
+    cursor: usize,
+    records: Records,
+    // Position reported for the unexpected end of the expression
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.cursor)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<&'a Token, String> {
+        let token = self
+            .tokens
+            .get(self.cursor)
+            .ok_or_else(|| format!("Expected {} at position {}, got the end of the expression", expected, self.end))?;
+        self.cursor += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek().map_or(false, |x| x.kind == TokenKind::Or) {
+            self.cursor += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.peek().map_or(false, |x| x.kind == TokenKind::And) {
+            self.cursor += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        match self.peek().map(|x| &x.kind) {
+            Some(TokenKind::Not) => {
+                self.cursor += 1;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            }
+            Some(TokenKind::LParen) => {
+                let pos = self.next("'('")?.pos;
+                let expr = self.or()?;
+                match self.peek() {
+                    Some(Token { kind: TokenKind::RParen, .. }) => {
+                        self.cursor += 1;
+                        Ok(expr)
+                    }
+                    Some(token) => Err(format!("Expected ')' at position {}, got {}", token.pos, token.kind)),
+                    None => Err(format!("Unmatched '(' at position {}", pos)),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let (left, lpos) = self.operand()?;
+        let token = self.next("a comparison operator")?;
+        let (cmp, cmppos) = match token.kind {
+            TokenKind::Cmp(cmp) => (cmp, token.pos),
+            _ => return Err(format!("Expected a comparison operator at position {}, got {}", token.pos, token.kind)),
+        };
+        let (right, rpos) = self.operand()?;
+
+        if left.is_strand() != right.is_strand() {
+            let pos = if left.is_strand() { rpos } else { lpos };
+            return Err(format!("Strand can be compared only with \"+\", \"-\" or \".\" at position {}", pos));
+        }
+        if left.is_strand() && !matches!(cmp, Cmp::Eq | Cmp::Ne) {
+            return Err(format!("Strand can be compared only with == or != at position {}", cmppos));
+        }
+        Ok(Expr::Cmp(left, cmp, right))
+    }
+
+    fn operand(&mut self) -> Result<(Operand, usize), String> {
+        let records = self.records;
+        let token = self.next("a field, a number or a strand")?;
+        let operand = match &token.kind {
+            TokenKind::Number(x) => Operand::Literal(Value::Number(*x)),
+            TokenKind::Str(x) => match x.as_str() {
+                "+" => Operand::Literal(Value::Strand(Strand::Forward)),
+                "-" => Operand::Literal(Value::Strand(Strand::Reverse)),
+                "." => Operand::Literal(Value::Strand(Strand::Unknown)),
+                _ => {
+                    return Err(format!(
+                        "Unknown strand \"{}\" at position {}, expected \"+\", \"-\" or \".\"",
+                        x, token.pos
+                    ))
+                }
+            },
+            TokenKind::Ident(x) => {
+                let field =
+                    Field::from_name(x).ok_or_else(|| format!("Unknown field \"{}\" at position {}", x, token.pos))?;
+                match field.records() {
+                    Some(available) if available != records => {
+                        let available = if available == Records::ROIs { "ROIs" } else { "sites" };
+                        return Err(format!(
+                            "Field \"{}\" at position {} is available only for {}",
+                            x, token.pos, available
+                        ));
+                    }
+                    _ => Operand::Field(field),
+                }
+            }
+            kind => {
+                return Err(format!("Expected a field, a number or a strand at position {}, got {}", token.pos, kind))
+            }
+        };
+        Ok((operand, token.pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{FracNucCounts, NucCounts, Nucleotide};
+    use crate::core::mismatches::roi::{ROIData, ROIDataRecord, ROIDataVec, ROINucCounts};
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::mismatches::MismatchesVec;
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    fn batch<T: MismatchesVec>(empty: impl Fn(Strand) -> T) -> Batch<T> {
+        Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: Stranded::with_fn(&empty),
+            retained: Stranded::with_fn(&empty),
+        }
+    }
+
+    fn roi(coverage: u32, a2g: f64, c2t: f64) -> ROIData {
+        let mut mismatches = ROINucCounts::zeros();
+        mismatches.A = FracNucCounts::new(20f64, 0f64, a2g, 0f64);
+        mismatches.C = FracNucCounts::new(0f64, 20f64, 0f64, c2t);
+        ROIData {
+            roi: ROIDataRecord {
+                premasked: 0..10,
+                postmasked: 0..10,
+                subintervals: vec![0..10],
+                name: "roi".into(),
+                strand: Strand::Unknown,
+                length: 10,
+            },
+            coverage,
+            softclipped: 0,
+            indels: 0,
+            depth: Default::default(),
+            alt_fragments: 0,
+            homozygous: NucCounts::zeros(),
+            heterozygous: 0,
+            corrected: 0,
+            refnmasked: 0,
+            mismatches,
+            persample: vec![],
+            flank: ROINucCounts::zeros(),
+            profile: vec![],
+        }
+    }
+
+    fn roi_ok(expr: &str, roi: &ROIData, trstrand: Strand) -> bool {
+        let mut batch = batch(|strand| ROIMismatchesVec::new("1".into(), strand, ROIDataVec::new()));
+        batch.items[trstrand].data.push(roi.clone());
+        batch.retained[trstrand].data.push(roi.clone());
+
+        ByExpression::new(expr, Records::ROIs).unwrap().on_finish(&mut batch);
+        // Retained ROIs are never dropped
+        assert_eq!(batch.retained[trstrand].data.len(), 1);
+        batch.items[trstrand].data.len() == 1
+    }
+
+    fn site_ok(mut filter: ByExpression, site: SiteData) -> bool {
+        let mut batch = batch(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        batch.items.forward.data.push(site);
+        filter.on_finish(&mut batch);
+        batch.items.forward.data.len() == 1
+    }
+
+    #[test]
+    fn precedence() {
+        let x = roi(25, 3f64, 2f64);
+        // AND binds tighter than OR => true || (false && false)
+        assert!(roi_ok("A->G >= 3 OR C->T <= 1 AND coverage >= 30", &x, Strand::Forward));
+        assert!(!roi_ok("(A->G >= 3 OR C->T <= 1) AND coverage >= 30", &x, Strand::Forward));
+        // NOT binds tighter than AND => (!false) && false
+        assert!(!roi_ok("NOT A->G >= 4 AND coverage >= 30", &x, Strand::Forward));
+        assert!(roi_ok("NOT (A->G >= 4 AND coverage >= 30)", &x, Strand::Forward));
+        assert!(roi_ok("!(C->T > 2) && coverage == 25 || freq > 1", &x, Strand::Forward));
+
+        // Unicode operators & arrows
+        assert!(!roi_ok("A→G ≥ 3 AND C→T ≤ 1 AND coverage ≥ 20", &x, Strand::Forward));
+        assert!(roi_ok("A→G ≥ 3 AND C→T ≤ 1 AND coverage ≥ 20", &roi(25, 3f64, 1f64), Strand::Forward));
+    }
+
+    #[test]
+    fn fields() {
+        let x = roi(25, 3f64, 2f64);
+        assert!(roi_ok("mismatches == 5 and freq > 0.11 and freq < 0.12", &x, Strand::Forward));
+        assert!(roi_ok("strand == \"-\" and '+' != strand", &x, Strand::Reverse));
+        assert!(!roi_ok("strand == '.'", &x, Strand::Forward));
+
+        // NaN frequencies fail all comparisons except !=
+        let mut uncovered = roi(0, 0f64, 0f64);
+        uncovered.mismatches = ROINucCounts::zeros();
+        assert!(!roi_ok("freq >= 0 or freq < 0", &uncovered, Strand::Forward));
+        assert!(roi_ok("freq != 0", &uncovered, Strand::Forward));
+
+        // Site counts are scaled back to natural units
+        let site = SiteData {
+            prednuc: PredNucleotide::Homozygous(Nucleotide::A),
+            sequenced: NucCounts::new(14, 0, 6, 0),
+            ..Default::default()
+        };
+        let filter = ByExpression::new("G == 3 and coverage == 10 and mismatches == 3", Records::Sites).unwrap();
+        assert!(site_ok(filter.clone().with_scale(2), site.clone()));
+        assert!(!site_ok(filter, site.clone()));
+        let filter = ByExpression::new("freq == 0.3 and A >= 14 and strand == '+'", Records::Sites).unwrap();
+        assert!(site_ok(filter, site.clone()));
+
+        // Either allele of heterozygous sites
+        let site = SiteData { prednuc: PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G)), ..site };
+        assert!(site_ok(ByExpression::new("mismatches == 14", Records::Sites).unwrap(), site));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |expr, records| ByExpression::new(expr, records).unwrap_err();
+        // Fields are checked against the records
+        assert_eq!(
+            error("coverage > 10 AND G >= 3", Records::ROIs),
+            "Field \"G\" at position 19 is available only for sites"
+        );
+        assert_eq!(error("A->G >= 3", Records::Sites), "Field \"A->G\" at position 1 is available only for ROIs");
+        assert!(ByExpression::new("G >= 3", Records::Sites).is_ok());
+        assert!(ByExpression::new("A->G >= 3", Records::ROIs).is_ok());
+
+        assert_eq!(error("covrage > 10", Records::ROIs), "Unknown field \"covrage\" at position 1");
+        assert_eq!(error("coverage 10", Records::ROIs), "Expected a comparison operator at position 10, got number 10");
+        assert_eq!(
+            error("coverage >", Records::ROIs),
+            "Expected a field, a number or a strand at position 11, got the end of the expression"
+        );
+        assert_eq!(error("(coverage > 1", Records::ROIs), "Unmatched '(' at position 1");
+        assert_eq!(error("coverage > 1)", Records::ROIs), "Unexpected ')' at position 13");
+        assert_eq!(error("coverage > 1 # 2", Records::ROIs), "Unexpected character '#' at position 14");
+        assert_eq!(error("strand > '+'", Records::ROIs), "Strand can be compared only with == or != at position 8");
+        assert_eq!(
+            error("strand == 1", Records::ROIs),
+            "Strand can be compared only with \"+\", \"-\" or \".\" at position 11"
+        );
+        assert_eq!(
+            error("strand == 'x'", Records::ROIs),
+            "Unknown strand \"x\" at position 11, expected \"+\", \"-\" or \".\""
+        );
+        assert_eq!(error("strand == 'x", Records::ROIs), "Unterminated string at position 11");
+    }
+}
@@ -0,0 +1,79 @@
+use bio_types::strand::Strand;
+
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Report only sites where the mismatching nucleotides come from at least the given number of distinct fragments.
+// Requires fragments to be tracked, sites with unknown number of fragments & retained sites are never dropped
+#[derive(Clone)]
+pub struct ByAltFragments {
+    minfragments: u32,
+}
+
+impl ByAltFragments {
+    pub fn new(minfragments: u32) -> Self {
+        Self { minfragments }
+    }
+
+    #[inline]
+    pub fn is_ok(&self, fragments: Option<u32>) -> bool {
+        fragments.map_or(true, |x| x >= self.minfragments)
+    }
+}
+
+impl Hook<SiteMismatchesVec> for ByAltFragments {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.retain(|x| self.is_ok(*x.alt_fragments)));
+    }
+}
+
+impl Filter<SiteMismatchesVec> for ByAltFragments {
+    fn name(&self) -> &'static str {
+        "alt-fragments"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, _: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_ok(*x.alt_fragments)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    #[test]
+    fn on_finish() {
+        let site = |pos, alt_fragments| SiteData { pos, alt_fragments, ..Default::default() };
+        let empty = || Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: empty(),
+            retained: empty(),
+        };
+        for (pos, fragments) in [(0, Some(3)), (1, Some(1)), (2, Some(2)), (3, None)] {
+            batch.items.forward.data.push(site(pos, fragments));
+        }
+        batch.items.reverse.data.push(site(4, Some(0)));
+        batch.retained.forward.data.push(site(5, Some(1)));
+
+        ByAltFragments::new(2).on_finish(&mut batch);
+        // Sites with unknown number of fragments are kept
+        assert_eq!(batch.items.forward.data.pos, vec![0, 2, 3]);
+        assert!(batch.items.reverse.data.pos.is_empty());
+        // Forced sites are never dropped
+        assert_eq!(batch.retained.forward.data.pos, vec![5]);
+    }
+}
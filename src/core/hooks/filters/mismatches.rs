@@ -1,9 +1,10 @@
+use bio_types::strand::Strand;
+
 use crate::core::hooks::filters::Filter;
 use crate::core::hooks::Hook;
 use crate::core::mismatches::roi::ROIMismatchesVec;
 use crate::core::mismatches::site::SiteMismatchesVec;
 use crate::core::mismatches::{prefilters, Batch};
-use crate::core::refpred::PredNucleotide;
 
 #[derive(Clone)]
 pub struct ByMismatches {
@@ -18,24 +19,40 @@ impl From<prefilters::ByMismatches> for ByMismatches {
 
 impl Hook<ROIMismatchesVec> for ByMismatches {
     fn on_finish(&mut self, mm: &mut Batch<ROIMismatchesVec>) {
-        mm.items.apply_mut(|x, _| x.data.retain(|x| self.inner.enough_mismatches_per_roi(x.mismatches)));
+        mm.items.apply_mut(|x, strand| x.data.retain(|x| self.inner.enough_mismatches_per_roi(x.mismatches, strand)));
     }
 }
 
-impl Filter<ROIMismatchesVec> for ByMismatches {}
+impl Filter<ROIMismatchesVec> for ByMismatches {
+    fn name(&self) -> &'static str {
+        "mismatches"
+    }
+
+    fn classify(&self, items: &ROIMismatchesVec, strand: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.inner.enough_mismatches_per_roi(x.mismatches, strand)).collect())
+    }
+}
 
 impl Hook<SiteMismatchesVec> for ByMismatches {
     fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
-        mm.items.apply_mut(|x, _| {
-            x.data.retain(|x| match x.prednuc {
-                PredNucleotide::Homozygous(nuc) => self.inner.enough_mismatches_per_site(*nuc, x.sequenced),
-                PredNucleotide::Heterozygous((n1, n2)) => {
-                    self.inner.enough_mismatches_per_site(*n1, x.sequenced)
-                        || self.inner.enough_mismatches_per_site(*n2, x.sequenced)
-                }
-            })
+        mm.items.apply_mut(|x, strand| {
+            x.data.retain(|x| self.inner.enough_mismatches_per_data(*x.prednuc, x.sequenced, x.persample, strand))
         });
     }
 }
 
-impl Filter<SiteMismatchesVec> for ByMismatches {}
+impl Filter<SiteMismatchesVec> for ByMismatches {
+    fn name(&self) -> &'static str {
+        "mismatches"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, strand: Strand) -> Option<Vec<bool>> {
+        Some(
+            items
+                .data
+                .iter()
+                .map(|x| self.inner.enough_mismatches_per_data(*x.prednuc, x.sequenced, x.persample, strand))
+                .collect(),
+        )
+    }
+}
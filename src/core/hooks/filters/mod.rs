@@ -1,9 +1,36 @@
+pub use discard::Discard;
+pub use error_rates::ByErrorRates;
+pub use expression::{ByExpression, Records};
+pub use fragments::ByAltFragments;
 pub use mismatches::ByMismatches;
+pub use quality::ByBaseQuality;
+pub use refbase::ByRefBase;
+pub use replicates::ByReplicates;
+pub use splicing::BySpliceDist;
+
+use bio_types::strand::Strand;
 
 use crate::core::mismatches::MismatchesVec;
 
 use super::Hook;
 
+mod discard;
+mod error_rates;
+mod expression;
+mod fragments;
 mod mismatches;
+mod quality;
+mod refbase;
+mod replicates;
+mod splicing;
+
+pub trait Filter<T: MismatchesVec>: Hook<T> {
+    // Reported for records failing the filter in the soft mode
+    fn name(&self) -> &'static str;
 
-pub trait Filter<T: MismatchesVec>: Hook<T> {}
+    // Verdicts for each record (true = passed) to mark records instead of dropping them.
+    // None => the filter doesn't judge individual records (e.g. annotates them) and is always applied as is
+    fn classify(&self, _items: &T, _strand: Strand) -> Option<Vec<bool>> {
+        None
+    }
+}
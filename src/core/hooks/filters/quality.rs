@@ -0,0 +1,88 @@
+use bio_types::strand::Strand;
+
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Report only sites where the mismatching nucleotides have the mean base quality ≥ threshold.
+// Requires base qualities to be collected, sites without mismatches & retained sites are never dropped
+#[derive(Clone)]
+pub struct ByBaseQuality {
+    minmeanq: f32,
+}
+
+impl ByBaseQuality {
+    pub fn new(minmeanq: f32) -> Self {
+        Self { minmeanq }
+    }
+
+    #[inline]
+    pub fn is_ok(&self, meanq: Option<f32>) -> bool {
+        meanq.map_or(true, |x| x >= self.minmeanq)
+    }
+}
+
+impl Hook<SiteMismatchesVec> for ByBaseQuality {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.retain(|x| self.is_ok(x.qualities.alt(x.sequenced, x.prednuc).0)));
+    }
+}
+
+impl Filter<SiteMismatchesVec> for ByBaseQuality {
+    fn name(&self) -> &'static str {
+        "meanq"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, _: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_ok(x.qualities.alt(x.sequenced, x.prednuc).0)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{BaseQualities, NucCounts, Nucleotide, ReqNucleotide};
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::refpred::PredNucleotide;
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    #[test]
+    fn on_finish() {
+        let site = |pos, sequenced, qual| {
+            let mut qualities = BaseQualities::default();
+            qualities.add(ReqNucleotide::A, 40, 1);
+            qualities.add(ReqNucleotide::G, qual, 1);
+            let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+            SiteData { pos, prednuc, sequenced, qualities, ..Default::default() }
+        };
+        let empty = || Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: empty(),
+            retained: empty(),
+        };
+        let (mismatch, matched) = (NucCounts::new(1, 0, 1, 0), NucCounts::new(1, 0, 0, 0));
+        for (pos, sequenced, qual) in [(0, mismatch, 30), (1, mismatch, 19), (2, mismatch, 20), (3, matched, 0)] {
+            batch.items.forward.data.push(site(pos, sequenced, qual));
+        }
+        batch.items.reverse.data.push(site(4, mismatch, 2));
+        batch.retained.forward.data.push(site(5, mismatch, 2));
+
+        ByBaseQuality::new(20f32).on_finish(&mut batch);
+        // Sites without mismatches are kept
+        assert_eq!(batch.items.forward.data.pos, vec![0, 2, 3]);
+        assert!(batch.items.reverse.data.pos.is_empty());
+        // Forced sites are never dropped
+        assert_eq!(batch.retained.forward.data.pos, vec![5]);
+    }
+}
@@ -0,0 +1,109 @@
+use bio_types::strand::Strand;
+
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+use crate::core::refpred::PredNucleotide;
+
+// Report only sites with the given predicted reference nucleotides (forward strand), e.g. A & T for A-to-I editing.
+// Heterozygous sites pass if either allele is allowed, retained sites are never dropped
+#[derive(Clone)]
+pub struct ByRefBase {
+    allowed: Vec<Nucleotide>,
+}
+
+impl ByRefBase {
+    pub fn new(allowed: &[ReqNucleotide]) -> Self {
+        Self { allowed: allowed.iter().map(|x| (*x).into()).collect() }
+    }
+
+    #[inline]
+    pub fn is_ok(&self, reference: &PredNucleotide) -> bool {
+        match reference {
+            PredNucleotide::Homozygous(nuc) => self.allowed.contains(nuc),
+            PredNucleotide::Heterozygous((n1, n2)) => self.allowed.contains(n1) || self.allowed.contains(n2),
+        }
+    }
+}
+
+impl Hook<SiteMismatchesVec> for ByRefBase {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.retain(|x| self.is_ok(x.prednuc)));
+    }
+}
+
+impl Filter<SiteMismatchesVec> for ByRefBase {
+    fn name(&self) -> &'static str {
+        "ref-base"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, _: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_ok(x.prednuc)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    #[test]
+    fn is_ok() {
+        use Nucleotide::{Unknown as N, A, C, G, T};
+
+        let filter = ByRefBase::new(&[ReqNucleotide::A]);
+        for (reference, expected) in [
+            (PredNucleotide::Homozygous(A), true),
+            (PredNucleotide::Homozygous(G), false),
+            (PredNucleotide::Homozygous(N), false),
+            // Either allele is enough
+            (PredNucleotide::Heterozygous((A, G)), true),
+            (PredNucleotide::Heterozygous((G, A)), true),
+            (PredNucleotide::Heterozygous((C, T)), false),
+        ] {
+            assert_eq!(filter.is_ok(&reference), expected, "{:?}", reference);
+        }
+
+        let filter = ByRefBase::new(&[ReqNucleotide::A, ReqNucleotide::T]);
+        assert!(filter.is_ok(&PredNucleotide::Homozygous(T)));
+        assert!(!filter.is_ok(&PredNucleotide::Homozygous(C)));
+    }
+
+    #[test]
+    fn on_finish() {
+        let site = |pos, prednuc| SiteData { pos, prednuc, ..Default::default() };
+        let empty = || Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: empty(),
+            retained: empty(),
+        };
+        for (pos, prednuc) in [
+            (0, PredNucleotide::Homozygous(Nucleotide::A)),
+            (1, PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G))),
+            (2, PredNucleotide::Homozygous(Nucleotide::G)),
+        ] {
+            batch.items.forward.data.push(site(pos, prednuc));
+        }
+        batch.items.reverse.data.push(site(3, PredNucleotide::Homozygous(Nucleotide::T)));
+        batch.retained.forward.data.push(site(4, PredNucleotide::Homozygous(Nucleotide::C)));
+
+        ByRefBase::new(&[ReqNucleotide::A]).on_finish(&mut batch);
+        assert_eq!(batch.items.forward.data.pos, vec![0, 1]);
+        assert!(batch.items.reverse.data.pos.is_empty());
+        // Forced sites are never dropped
+        assert_eq!(batch.retained.forward.data.pos, vec![4]);
+    }
+}
@@ -0,0 +1,34 @@
+use bio_types::strand::Strand;
+
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::{prefilters, Batch};
+
+// Per-file counts are available only for ROIs => there is no site-level counterpart
+#[derive(Clone)]
+pub struct ByReplicates {
+    inner: prefilters::ByReplicates,
+}
+
+impl From<prefilters::ByReplicates> for ByReplicates {
+    fn from(br: prefilters::ByReplicates) -> Self {
+        Self { inner: br }
+    }
+}
+
+impl Hook<ROIMismatchesVec> for ByReplicates {
+    fn on_finish(&mut self, mm: &mut Batch<ROIMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.retain(|x| self.inner.enough_support_per_roi(x.persample)));
+    }
+}
+
+impl Filter<ROIMismatchesVec> for ByReplicates {
+    fn name(&self) -> &'static str {
+        "replicates"
+    }
+
+    fn classify(&self, items: &ROIMismatchesVec, _: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.inner.enough_support_per_roi(x.persample)).collect())
+    }
+}
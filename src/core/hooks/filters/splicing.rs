@@ -0,0 +1,82 @@
+use bio_types::strand::Strand;
+
+use crate::core::hooks::filters::Filter;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Report only sites at least the given distance away from the nearest observed splice junction, e.g. to drop
+// mismatches caused by misaligned read ends. Requires junctions to be collected, unannotated & retained sites are kept
+#[derive(Clone)]
+pub struct BySpliceDist {
+    mindist: u32,
+}
+
+impl BySpliceDist {
+    pub fn new(mindist: u32) -> Self {
+        Self { mindist }
+    }
+
+    #[inline]
+    pub fn is_ok(&self, dist: Option<u32>) -> bool {
+        dist.map_or(true, |x| x >= self.mindist)
+    }
+}
+
+impl Hook<SiteMismatchesVec> for BySpliceDist {
+    fn on_finish(&mut self, mm: &mut Batch<SiteMismatchesVec>) {
+        mm.items.apply_mut(|x, _| x.data.retain(|x| self.is_ok(*x.splice_dist)));
+    }
+}
+
+impl Filter<SiteMismatchesVec> for BySpliceDist {
+    fn name(&self) -> &'static str {
+        "splice-dist"
+    }
+
+    fn classify(&self, items: &SiteMismatchesVec, _: Strand) -> Option<Vec<bool>> {
+        Some(items.data.iter().map(|x| self.is_ok(*x.splice_dist)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    #[test]
+    fn on_finish() {
+        let site = |pos, splice_dist| SiteData { pos, splice_dist, ..Default::default() };
+        let empty = || Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+        let mut batch = Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: empty(),
+            retained: empty(),
+        };
+        for (pos, dist) in [(0, Some(0)), (1, Some(3)), (2, Some(10)), (3, None)] {
+            batch.items.forward.data.push(site(pos, dist));
+        }
+        batch.items.reverse.data.push(site(4, Some(4)));
+        batch.retained.forward.data.push(site(5, Some(0)));
+
+        let filter = BySpliceDist::new(4);
+        assert_eq!(filter.classify(&batch.items.forward, Strand::Forward), Some(vec![false, false, true, true]));
+
+        BySpliceDist::new(4).on_finish(&mut batch);
+        // Sites without the annotation are kept
+        assert_eq!(batch.items.forward.data.pos, vec![2, 3]);
+        assert_eq!(batch.items.reverse.data.pos, vec![4]);
+        // Forced sites are never dropped
+        assert_eq!(batch.retained.forward.data.pos, vec![5]);
+    }
+}
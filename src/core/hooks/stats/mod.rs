@@ -1,15 +1,44 @@
 use std::any::Any;
 
-pub use roi_editing_index::ROIEditingIndex;
+pub use roi_contig_editing_index::ROIContigEditingIndex;
+pub use roi_conversion_rate::ROIConversionRate;
+pub use roi_editing_index::{EditingIndexRecord, ROIEditingIndex};
+pub use roi_mismatch_spectrum::ROIMismatchSpectrum;
+pub use roi_strand_editing_index::StrandSplitEditingIndex;
+pub use substitution_rates::SubstitutionRates;
 
 use crate::core::hooks::Hook;
 use crate::core::mismatches::MismatchesVec;
 
+mod roi_contig_editing_index;
+mod roi_conversion_rate;
 mod roi_editing_index;
+mod roi_mismatch_spectrum;
+mod roi_strand_editing_index;
+mod substitution_rates;
 
-#[derive(Hash, PartialEq, Eq)]
+// Stats are written in the declaration order
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum EditingStatType {
-    ROIEditingIndex,
+    // Optional label of the ROI subset, each subset is reported separately
+    ROIEditingIndex(Option<String>),
+    ROIContigEditingIndex,
+    ROIConversionRate,
+    ROIMismatchSpectrum,
+    StrandSplitEditingIndex,
+    SubstitutionRates,
+}
+
+impl EditingStatType {
+    // Stats requested via --stat share the same output
+    pub fn output(&self) -> Self {
+        match self {
+            EditingStatType::ROIMismatchSpectrum | EditingStatType::StrandSplitEditingIndex => {
+                EditingStatType::ROIConversionRate
+            }
+            x => x.clone(),
+        }
+    }
 }
 
 pub trait EditingStat<T: MismatchesVec>: Hook<T> + Any {
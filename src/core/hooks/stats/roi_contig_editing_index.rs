@@ -0,0 +1,198 @@
+use std::any::Any;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::core::hooks::stats::{EditingStat, EditingStatType, ROIEditingIndex};
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Label of the row with EI pooled across all contigs
+pub const TOTAL: &str = "TOTAL";
+
+// Pooled EI for each contig separately (e.g. to spot misbehaving chrM / chrY), plus the total over all contigs
+#[derive(Clone)]
+pub struct ROIContigEditingIndex {
+    percontig: HashMap<String, ROIEditingIndex>,
+    // Contigs in the workload order, they are reported even without covered ROIs
+    contigs: Vec<String>,
+    expname: String,
+    roifiles: String,
+}
+
+impl ROIContigEditingIndex {
+    pub fn new(expname: String, roifiles: String, contigs: Vec<String>) -> Self {
+        let percontig =
+            contigs.iter().map(|x| (x.clone(), ROIEditingIndex::new(expname.clone(), roifiles.clone()))).collect();
+        Self { percontig, contigs, expname, roifiles }
+    }
+
+    // Union of contigs, EIs of the same contig are added up
+    pub fn collapse(items: Vec<Box<dyn Any>>) -> Self {
+        items
+            .into_iter()
+            .map(|x| *x.downcast::<Self>().unwrap())
+            .reduce(|mut a, b| {
+                debug_assert_eq!(a.contigs, b.contigs);
+                debug_assert_eq!(a.expname, b.expname);
+                debug_assert_eq!(a.roifiles, b.roifiles);
+                for (contig, ei) in b.percontig {
+                    match a.percontig.entry(contig) {
+                        Entry::Occupied(mut x) => x.get_mut().merge(ei),
+                        Entry::Vacant(x) => {
+                            x.insert(ei);
+                        }
+                    }
+                }
+                a
+            })
+            .unwrap()
+    }
+
+    // Workload contigs go first, contigs outside of it (if any) are sorted by name
+    fn ordered(&self) -> Vec<&String> {
+        let mut ordered: Vec<&String> = self.contigs.iter().collect();
+        let mut other: Vec<&String> = self.percontig.keys().filter(|x| !self.contigs.contains(x)).collect();
+        other.sort();
+        ordered.extend(other);
+        ordered
+    }
+
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        let mut total = ROIEditingIndex::new(self.expname.clone(), self.roifiles.clone());
+        for contig in self.ordered() {
+            let ei = &self.percontig[contig];
+            ei.to_csv_row(writer, contig)?;
+            total.merge(ei.clone());
+        }
+        total.to_csv_row(writer, TOTAL)
+    }
+}
+
+impl Hook<ROIMismatchesVec> for ROIContigEditingIndex {
+    fn on_finish(&mut self, mismatches: &mut Batch<ROIMismatchesVec>) {
+        let (expname, roifiles) = (&self.expname, &self.roifiles);
+        self.percontig
+            .entry(mismatches.contig.clone())
+            .or_insert_with(|| ROIEditingIndex::new(expname.clone(), roifiles.clone()))
+            .on_finish(mismatches);
+    }
+}
+
+impl EditingStat<ROIMismatchesVec> for ROIContigEditingIndex {
+    fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
+        (EditingStatType::ROIContigEditingIndex, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::strand::Strand;
+
+    use crate::core::dna::{NucCounts, WideStrandDepth};
+    use crate::core::mismatches::roi::{ROIData, ROIDataRecord, ROIDataVec, ROINucCounts};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    fn batch(contig: &str, ag: &[f64]) -> Batch<ROIMismatchesVec> {
+        let mut data = ROIDataVec::new();
+        for ag in ag {
+            let mut mismatches = ROINucCounts::zeros();
+            mismatches.A.A = 10f64;
+            mismatches.A.G = *ag;
+            data.push(ROIData {
+                roi: ROIDataRecord {
+                    premasked: 0..10,
+                    postmasked: 0..10,
+                    subintervals: vec![0..10],
+                    name: "roi".into(),
+                    strand: Strand::Forward,
+                    length: 10,
+                },
+                coverage: 1,
+                softclipped: 0,
+                indels: 0,
+                depth: WideStrandDepth::default(),
+                alt_fragments: 0,
+                homozygous: NucCounts::zeros(),
+                heterozygous: 0,
+                corrected: 0,
+                refnmasked: 0,
+                mismatches,
+                persample: vec![],
+                flank: ROINucCounts::zeros(),
+                profile: vec![],
+            });
+        }
+        let empty = |strand| ROIMismatchesVec::new(contig.into(), strand, ROIDataVec::new());
+        let mut batch = Batch {
+            contig: contig.into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: Stranded::with_fn(empty),
+            retained: Stranded::with_fn(empty),
+        };
+        batch.items.forward = ROIMismatchesVec::new(contig.into(), Strand::Forward, data);
+        batch
+    }
+
+    fn rows(written: &[u8]) -> Vec<Vec<String>> {
+        let written = String::from_utf8(written.to_vec()).unwrap();
+        written.lines().map(|x| x.split('\t').map(|x| x.to_owned()).collect()).collect()
+    }
+
+    #[test]
+    fn per_contig() {
+        let contigs = vec!["chr1".to_owned(), "chrM".to_owned(), "chrY".to_owned()];
+        let new = || ROIContigEditingIndex::new("exp".into(), "rois.bed".into(), contigs.clone());
+        let mut global = ROIEditingIndex::new("exp".into(), "rois.bed".into());
+
+        // Two threads, chr1 is split between them, chrY is not covered at all, chrX is outside of the workload
+        let mut threads = [new(), new()];
+        for (thread, contig, ag) in
+            [(0, "chr1", &[1f64, 2f64][..]), (1, "chr1", &[3f64]), (1, "chrM", &[10f64]), (0, "chrX", &[0f64])]
+        {
+            let mut batch = batch(contig, ag);
+            threads[thread].on_finish(&mut batch);
+            global.on_finish(&mut batch);
+        }
+
+        let items: Vec<Box<dyn Any>> = threads.into_iter().map(|x| Box::new(x) as Box<dyn Any>).collect();
+        let collapsed = ROIContigEditingIndex::collapse(items);
+        let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+        collapsed.to_csv(&mut writer).unwrap();
+        let rows = rows(&writer.into_inner().unwrap());
+
+        let header = &rows[0];
+        let column = |row: &[String], name: &str| row[header.iter().position(|x| x == name).unwrap()].clone();
+        let contigs: Vec<String> = rows[1..].iter().map(|x| column(x, "contigs")).collect();
+        assert_eq!(contigs, ["chr1", "chrM", "chrY", "chrX", TOTAL]);
+
+        let (chr1, chrm, chry) = (&rows[1], &rows[2], &rows[3]);
+        assert_eq!((column(chr1, "#covered"), column(chr1, "A->G(sum)")), ("3".into(), "6.0".into()));
+        assert_eq!((column(chrm, "#covered"), column(chrm, "A->G")), ("1".into(), "0.5".into()));
+        // Empty values for contigs without covered ROIs
+        assert_eq!(column(chry, "#covered"), "0");
+        for name in ["A->G", "T->C", "A->G(sum)", "T(coverage)"] {
+            assert_eq!(column(chry, name), "", "{}", name);
+        }
+
+        // The total matches the global EI
+        let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+        global.to_csv(&mut writer).unwrap();
+        let expected = rows(&writer.into_inner().unwrap());
+        assert_eq!(expected[0], *header);
+        let total = rows.last().unwrap();
+        for (ind, name) in header.iter().enumerate().filter(|(_, x)| *x != "contigs") {
+            assert_eq!(total[ind], expected[1][ind], "{}", name);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::any::Any;
+use std::io::Write;
+
+use bio_types::strand::Strand;
+
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::hooks::stats::EditingStat;
+use crate::core::hooks::stats::EditingStatType;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::{ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::{Batch, MismatchesVec};
+
+// Pooled from->to conversion rate (e.g. T->C for SLAM-seq)
+#[derive(Clone)]
+pub struct ROIConversionRate {
+    from: ReqNucleotide,
+    to: ReqNucleotide,
+    // (conversions, coverage) on the transcription strand, stranded ROIs only
+    stranded: (f64, f64),
+    // (conversions, coverage) for from->to and the complementary pair on the reference strand, all ROIs
+    agnostic: (f64, f64),
+    unstranded_roi: usize,
+    expname: String,
+    roifiles: String,
+}
+
+impl ROIConversionRate {
+    pub fn new(expname: String, roifiles: String, from: ReqNucleotide, to: ReqNucleotide) -> Self {
+        Self { from, to, stranded: (0f64, 0f64), agnostic: (0f64, 0f64), unstranded_roi: 0, expname, roifiles }
+    }
+
+    pub fn conversion(&self) -> String {
+        format!("{}->{}", Nucleotide::from(self.from), Nucleotide::from(self.to))
+    }
+
+    fn add(&mut self, counts: &ROINucCounts, strand: Strand) {
+        let (cfrom, cto) = (self.from.complementary(), self.to.complementary());
+        self.agnostic.0 += counts[self.from][self.to] + counts[cfrom][cto];
+        self.agnostic.1 += counts[self.from].coverage() + counts[cfrom].coverage();
+
+        let counts = match strand {
+            Strand::Forward => *counts,
+            Strand::Reverse => counts.complementary(),
+            Strand::Unknown => {
+                self.unstranded_roi += 1;
+                return;
+            }
+        };
+        self.stranded.0 += counts[self.from][self.to];
+        self.stranded.1 += counts[self.from].coverage();
+    }
+
+    fn process(&mut self, x: &ROIMismatchesVec, strand: Strand) {
+        for counts in x.data.mismatches.iter() {
+            self.add(counts, strand);
+        }
+    }
+
+    // Merge thread-local stats, conversions are reported in the order of their first appearance
+    pub fn collapse(items: Vec<Box<dyn Any>>) -> Vec<Self> {
+        let mut result: Vec<Self> = Vec::new();
+        for item in items.into_iter().map(|x| *x.downcast::<Self>().unwrap()) {
+            match result.iter_mut().find(|x| x.from == item.from && x.to == item.to) {
+                None => result.push(item),
+                Some(x) => {
+                    debug_assert_eq!(x.roifiles, item.roifiles);
+                    debug_assert_eq!(x.expname, item.expname);
+                    x.stranded = (x.stranded.0 + item.stranded.0, x.stranded.1 + item.stranded.1);
+                    x.agnostic = (x.agnostic.0 + item.agnostic.0, x.agnostic.1 + item.agnostic.1);
+                    x.unstranded_roi += item.unstranded_roi;
+                }
+            }
+        }
+        result
+    }
+
+    // Each stat is preceded by its own header => several stats can share the same writer
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        let conversion = self.conversion();
+        writer.write_record([
+            "experiment",
+            "ROI-file",
+            "#unstranded",
+            format!("{}(stranded)", conversion).as_str(),
+            format!("{}(agnostic)", conversion).as_str(),
+        ])?;
+        writer.write_record([
+            self.expname.clone(),
+            self.roifiles.clone(),
+            self.unstranded_roi.to_string(),
+            ((self.stranded.0 / self.stranded.1) as f32).to_string(),
+            ((self.agnostic.0 / self.agnostic.1) as f32).to_string(),
+        ])
+    }
+}
+
+impl Hook<ROIMismatchesVec> for ROIConversionRate {
+    fn on_finish(&mut self, mismatches: &mut Batch<ROIMismatchesVec>) {
+        for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            self.process(&mismatches.retained[strand], strand);
+            self.process(&mismatches.items[strand], strand);
+        }
+    }
+}
+
+impl EditingStat<ROIMismatchesVec> for ROIConversionRate {
+    fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
+        (EditingStatType::ROIConversionRate, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion_rate() {
+        let mut counts = ROINucCounts::zeros();
+        counts.T.T = 6f64;
+        counts.T.C = 2f64;
+        counts.A.A = 3f64;
+        counts.A.G = 1f64;
+
+        let t2c = || ROIConversionRate::new("exp".into(), "rois.bed".into(), ReqNucleotide::T, ReqNucleotide::C);
+        let mut first = t2c();
+        first.add(&counts, Strand::Forward);
+        first.add(&counts, Strand::Unknown);
+        let mut second = t2c();
+        second.add(&counts, Strand::Reverse);
+        let mut a2g = ROIConversionRate::new("exp".into(), "rois.bed".into(), ReqNucleotide::A, ReqNucleotide::G);
+        a2g.add(&counts, Strand::Forward);
+
+        let items: Vec<Box<dyn Any>> = vec![Box::new(first), Box::new(a2g), Box::new(second)];
+        let collapsed = ROIConversionRate::collapse(items);
+        assert_eq!(collapsed.len(), 2);
+        assert_eq!(collapsed[0].conversion(), "T->C");
+        assert_eq!(collapsed[1].conversion(), "A->G");
+
+        let t2c = &collapsed[0];
+        assert_eq!(t2c.unstranded_roi, 1);
+        // Forward: T->C = 2 / 8, reverse: A->G = 1 / 4
+        assert_eq!(t2c.stranded, (3f64, 12f64));
+        // Each ROI: (T->C + A->G) / (T + A)
+        assert_eq!(t2c.agnostic, (9f64, 36f64));
+
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        for x in &collapsed {
+            x.to_csv(&mut writer).unwrap();
+        }
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            written,
+            "experiment,ROI-file,#unstranded,T->C(stranded),T->C(agnostic)\n\
+             exp,rois.bed,1,0.25,0.25\n\
+             experiment,ROI-file,#unstranded,A->G(stranded),A->G(agnostic)\n\
+             exp,rois.bed,0,0.25,0.25\n"
+        );
+    }
+}
@@ -1,44 +1,122 @@
 use std::any::Any;
+use std::collections::HashSet;
+use std::io::Write;
 
 use bio_types::strand::Strand;
+use regex::Regex;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
+use crate::core::dna::ReqNucleotide;
 use crate::core::hooks::stats::EditingStat;
 use crate::core::hooks::stats::EditingStatType;
 use crate::core::hooks::Hook;
 use crate::core::mismatches::roi::{ROIMismatchesVec, ROINucCounts};
-use crate::core::mismatches::Batch;
+use crate::core::mismatches::{Batch, MismatchesVec};
+
+// Counts of a single input file (sample), ROIs are counted only if the sample covered them
+#[derive(Clone, Copy)]
+struct SampleCounts {
+    counts: ROINucCounts,
+    unstranded_roi: usize,
+    covered_roi: usize,
+}
+
+impl SampleCounts {
+    fn zeros() -> Self {
+        Self { counts: ROINucCounts::zeros(), unstranded_roi: 0, covered_roi: 0 }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.counts += other.counts;
+        self.unstranded_roi += other.unstranded_roi;
+        self.covered_roi += other.covered_roi;
+    }
+}
 
 #[derive(Clone)]
 pub struct ROIEditingIndex {
     accumulator: ROINucCounts,
+    persample: Vec<SampleCounts>,
     unstranded_roi: usize,
+    // Stranded ROIs with nonzero coverage
+    covered_roi: usize,
     expname: String,
+    samples: Vec<String>,
     roifiles: String,
+    subset: String,
+    contigs: Option<HashSet<String>>,
+    // Label & pattern to select ROIs by their names
+    names: Option<(String, Regex)>,
 }
 
 impl ROIEditingIndex {
     pub fn new(expname: String, roifiles: String) -> Self {
-        Self { accumulator: ROINucCounts::zeros(), unstranded_roi: 0, expname, roifiles }
+        Self {
+            accumulator: ROINucCounts::zeros(),
+            persample: vec![],
+            unstranded_roi: 0,
+            covered_roi: 0,
+            expname,
+            samples: vec![],
+            roifiles,
+            subset: "all".into(),
+            contigs: None,
+            names: None,
+        }
     }
 
-    fn process(&mut self, x: &ROIMismatchesVec, strand: Strand) {
-        let iter = x.data.mismatches.iter();
+    // Report EI for each input file(sample) separately instead of the pooled one
+    pub fn per_sample(expname: String, roifiles: String, samples: Vec<String>) -> Self {
+        Self { samples, ..Self::new(expname, roifiles) }
+    }
+
+    // Accumulate counts only for ROIs located on the given contigs (e.g. autosomes)
+    pub fn with_contigs(self, subset: String, contigs: HashSet<String>) -> Self {
+        Self { subset, contigs: Some(contigs), ..self }
+    }
+
+    // Accumulate counts only for ROIs with names matching the pattern (e.g. ^Alu)
+    pub fn with_names(self, label: String, pattern: Regex) -> Self {
+        Self { names: Some((label, pattern)), ..self }
+    }
 
-        match strand {
-            Strand::Forward => {
-                for x in iter {
-                    self.accumulator += *x;
+    pub fn label(&self) -> Option<&str> {
+        self.names.as_ref().map(|x| x.0.as_str())
+    }
+
+    fn process(&mut self, x: &ROIMismatchesVec, strand: Strand) {
+        if self.contigs.as_ref().map_or(false, |contigs| !contigs.contains(x.contig())) {
+            return;
+        }
+        if strand == Strand::Unknown {
+            self.unstranded_roi += 1;
+            let samples = x.data.persample.iter().map(|x| x.len()).max().unwrap_or(0);
+            for sample in 0..samples {
+                let covered = x.data.persample.iter().any(|x| x.get(sample).map_or(false, |x| x.coverage() > 0f64));
+                if covered {
+                    sample_counts(&mut self.persample, sample).unstranded_roi += 1;
                 }
             }
-            Strand::Reverse => {
-                for x in iter {
-                    self.accumulator += x.complementary();
-                }
+            return;
+        }
+
+        let selected: Vec<usize> = match &self.names {
+            None => (0..x.data.len()).collect(),
+            Some((_, pattern)) => (0..x.data.len()).filter(|ind| pattern.is_match(&x.data.roi.name[*ind])).collect(),
+        };
+        for ind in selected {
+            let (counts, persample) = (&x.data.mismatches[ind], &x.data.persample[ind]);
+            if counts.coverage() > 0f64 {
+                self.covered_roi += 1;
             }
-            Strand::Unknown => {
-                self.unstranded_roi += 1;
+            self.accumulator += if strand == Strand::Forward { *counts } else { counts.complementary() };
+            for (sample, x) in persample.iter().enumerate() {
+                let accumulator = sample_counts(&mut self.persample, sample);
+                accumulator.counts += if strand == Strand::Forward { *x } else { x.complementary() };
+                if x.coverage() > 0f64 {
+                    accumulator.covered_roi += 1;
+                }
             }
         }
     }
@@ -48,40 +126,237 @@ impl ROIEditingIndex {
             .into_iter()
             .map(|x| x.downcast::<Self>().unwrap())
             .reduce(|mut a, b| {
-                a.accumulator += b.accumulator;
-                a.unstranded_roi += b.unstranded_roi;
                 debug_assert_eq!(a.roifiles, b.roifiles);
                 debug_assert_eq!(a.expname, b.expname);
+                debug_assert_eq!(a.samples, b.samples);
+                debug_assert_eq!(a.subset, b.subset);
+                debug_assert_eq!(a.label(), b.label());
+                a.merge(*b);
                 a
             })
             .unwrap()
     }
+
+    // Add up counts of another EI, settings of the current one are kept
+    pub(super) fn merge(&mut self, other: Self) {
+        self.accumulator += other.accumulator;
+        self.unstranded_roi += other.unstranded_roi;
+        self.covered_roi += other.covered_roi;
+        for (sample, x) in other.persample.iter().enumerate() {
+            sample_counts(&mut self.persample, sample).add(x);
+        }
+    }
+
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        if self.samples.is_empty() {
+            return writer.serialize(EditingIndexRow {
+                experiment: &self.expname,
+                roifiles: &self.roifiles,
+                contigs: &self.subset,
+                unstranded_roi: self.unstranded_roi,
+                covered_roi: self.covered_roi,
+                counts: Some(&self.accumulator),
+            });
+        }
+
+        debug_assert!(self.persample.len() <= self.samples.len());
+        let zeros = SampleCounts::zeros();
+        for (ind, sample) in self.samples.iter().enumerate() {
+            let counts = self.persample.get(ind).unwrap_or(&zeros);
+            writer.serialize(EditingIndexRow {
+                experiment: sample,
+                roifiles: &self.roifiles,
+                contigs: &self.subset,
+                unstranded_roi: counts.unstranded_roi,
+                covered_roi: counts.covered_roi,
+                counts: Some(&counts.counts),
+            })?;
+        }
+        Ok(())
+    }
+
+    // Single pooled row with a custom contigs label, EI values are left empty if no ROIs were covered
+    pub(super) fn to_csv_row<W: Write>(&self, writer: &mut csv::Writer<W>, contigs: &str) -> csv::Result<()> {
+        writer.serialize(EditingIndexRow {
+            experiment: &self.expname,
+            roifiles: &self.roifiles,
+            contigs,
+            unstranded_roi: self.unstranded_roi,
+            covered_roi: self.covered_roi,
+            counts: (self.covered_roi > 0).then_some(&self.accumulator),
+        })
+    }
+
+    // CSV header of the EI table, used to check existing files before appending to them
+    pub fn header() -> String {
+        let zeros = ROINucCounts::zeros();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(EditingIndexRow {
+                experiment: "",
+                roifiles: "",
+                contigs: "",
+                unstranded_roi: 0,
+                covered_roi: 0,
+                counts: Some(&zeros),
+            })
+            .unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        written.lines().next().unwrap().to_owned()
+    }
 }
 
-impl Serialize for ROIEditingIndex {
+// Samples are added lazily, the number of input files is not known in advance
+fn sample_counts(persample: &mut Vec<SampleCounts>, sample: usize) -> &mut SampleCounts {
+    if sample >= persample.len() {
+        persample.resize(sample + 1, SampleCounts::zeros());
+    }
+    &mut persample[sample]
+}
+
+// Raw counts reported after the main EI components, in the order of the frequency columns
+const RAW_COUNTS: [(&str, ReqNucleotide, ReqNucleotide); 14] = [
+    ("A->A(sum)", ReqNucleotide::A, ReqNucleotide::A),
+    ("T->T(sum)", ReqNucleotide::T, ReqNucleotide::T),
+    ("G->G(sum)", ReqNucleotide::G, ReqNucleotide::G),
+    ("C->C(sum)", ReqNucleotide::C, ReqNucleotide::C),
+    ("A->T(sum)", ReqNucleotide::A, ReqNucleotide::T),
+    ("T->A(sum)", ReqNucleotide::T, ReqNucleotide::A),
+    ("A->C(sum)", ReqNucleotide::A, ReqNucleotide::C),
+    ("T->G(sum)", ReqNucleotide::T, ReqNucleotide::G),
+    ("G->C(sum)", ReqNucleotide::G, ReqNucleotide::C),
+    ("C->G(sum)", ReqNucleotide::C, ReqNucleotide::G),
+    ("G->A(sum)", ReqNucleotide::G, ReqNucleotide::A),
+    ("C->T(sum)", ReqNucleotide::C, ReqNucleotide::T),
+    ("G->T(sum)", ReqNucleotide::G, ReqNucleotide::T),
+    ("C->A(sum)", ReqNucleotide::C, ReqNucleotide::A),
+];
+
+// Row of an existing EI table, e.g. to merge results of several runs
+#[derive(Clone, PartialEq, Debug)]
+pub struct EditingIndexRecord {
+    pub experiment: String,
+    pub roifiles: String,
+    pub contigs: String,
+    pub unstranded_roi: usize,
+    pub covered_roi: usize,
+    pub counts: Option<ROINucCounts>,
+}
+
+impl EditingIndexRecord {
+    // Values are located by the column names, ratios are ignored and recomputed from the raw counts
+    pub fn parse(header: &csv::StringRecord, row: &csv::StringRecord) -> Result<Self, String> {
+        let value = |column: &str| -> Result<&str, String> {
+            header
+                .iter()
+                .position(|x| x == column)
+                .and_then(|ind| row.get(ind))
+                .ok_or_else(|| format!("missing {} column", column))
+        };
+        let number = |column: &str| -> Result<usize, String> {
+            let raw = value(column)?;
+            raw.parse().map_err(|_| format!("invalid {} value \"{}\"", column, raw))
+        };
+
+        let mut counts = ROINucCounts::zeros();
+        let mut missing = 0;
+        let sums =
+            [("A->G(sum)", ReqNucleotide::A, ReqNucleotide::G), ("T->C(sum)", ReqNucleotide::T, ReqNucleotide::C)];
+        for (column, from, to) in sums.into_iter().chain(RAW_COUNTS) {
+            match value(column)? {
+                "" => missing += 1,
+                raw => counts[from][to] = raw.parse().map_err(|_| format!("invalid {} value \"{}\"", column, raw))?,
+            }
+        }
+        // Counts are either all reported or all empty (no covered ROIs)
+        let counts = match missing {
+            0 => Some(counts),
+            16 => None,
+            _ => return Err("raw counts are partially missing".into()),
+        };
+        Ok(Self {
+            experiment: value("experiment")?.to_owned(),
+            roifiles: value("ROI-file")?.to_owned(),
+            contigs: value("contigs")?.to_owned(),
+            unstranded_roi: number("#unstranded")?,
+            covered_roi: number("#covered")?,
+            counts,
+        })
+    }
+
+    // Rows with the same key describe the same experiment & ROIs and can be merged
+    pub fn key(&self) -> (&str, &str, &str) {
+        (&self.experiment, &self.roifiles, &self.contigs)
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.unstranded_roi += other.unstranded_roi;
+        self.covered_roi += other.covered_roi;
+        self.counts = match (self.counts, other.counts) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        writer.serialize(EditingIndexRow {
+            experiment: &self.experiment,
+            roifiles: &self.roifiles,
+            contigs: &self.contigs,
+            unstranded_roi: self.unstranded_roi,
+            covered_roi: self.covered_roi,
+            counts: self.counts.as_ref(),
+        })
+    }
+}
+
+struct EditingIndexRow<'a> {
+    experiment: &'a str,
+    roifiles: &'a str,
+    contigs: &'a str,
+    unstranded_roi: usize,
+    covered_roi: usize,
+    // Empty values are reported if missing
+    counts: Option<&'a ROINucCounts>,
+}
+
+impl Serialize for EditingIndexRow<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let res = &self.accumulator;
+        use ReqNucleotide::{A, C, G, T};
 
-        let mut state = serializer.serialize_struct("ROIEditingIndex", 19)?;
-        state.serialize_field("experiment", &self.expname)?;
+        let freq =
+            |from: ReqNucleotide, to: ReqNucleotide| self.counts.map(|x| (x[from][to] / x[from].coverage()) as f32);
+        let mut state = serializer.serialize_struct("ROIEditingIndex", 39)?;
+        state.serialize_field("experiment", &self.experiment)?;
         state.serialize_field("ROI-file", &self.roifiles)?;
+        state.serialize_field("contigs", &self.contigs)?;
         state.serialize_field("#unstranded", &self.unstranded_roi)?;
-        state.serialize_field("A->A", &(res.A.A as f32 / res.A.coverage() as f32))?;
-        state.serialize_field("T->T", &(res.T.T as f32 / res.T.coverage() as f32))?;
-        state.serialize_field("G->G", &(res.G.G as f32 / res.G.coverage() as f32))?;
-        state.serialize_field("C->C", &(res.C.C as f32 / res.C.coverage() as f32))?;
-        state.serialize_field("A->T", &(res.A.T as f32 / res.A.coverage() as f32))?;
-        state.serialize_field("T->A", &(res.T.A as f32 / res.T.coverage() as f32))?;
-        state.serialize_field("A->G", &(res.A.G as f32 / res.A.coverage() as f32))?;
-        state.serialize_field("T->C", &(res.T.C as f32 / res.T.coverage() as f32))?;
-        state.serialize_field("A->C", &(res.A.C as f32 / res.A.coverage() as f32))?;
-        state.serialize_field("T->G", &(res.T.G as f32 / res.T.coverage() as f32))?;
-        state.serialize_field("G->C", &(res.G.C as f32 / res.G.coverage() as f32))?;
-        state.serialize_field("C->G", &(res.C.G as f32 / res.C.coverage() as f32))?;
-        state.serialize_field("G->A", &(res.G.A as f32 / res.G.coverage() as f32))?;
-        state.serialize_field("C->T", &(res.C.T as f32 / res.C.coverage() as f32))?;
-        state.serialize_field("G->T", &(res.G.T as f32 / res.G.coverage() as f32))?;
-        state.serialize_field("C->A", &(res.C.A as f32 / res.C.coverage() as f32))?;
+        state.serialize_field("A->A", &freq(A, A))?;
+        state.serialize_field("T->T", &freq(T, T))?;
+        state.serialize_field("G->G", &freq(G, G))?;
+        state.serialize_field("C->C", &freq(C, C))?;
+        state.serialize_field("A->T", &freq(A, T))?;
+        state.serialize_field("T->A", &freq(T, A))?;
+        state.serialize_field("A->G", &freq(A, G))?;
+        state.serialize_field("T->C", &freq(T, C))?;
+        state.serialize_field("A->C", &freq(A, C))?;
+        state.serialize_field("T->G", &freq(T, G))?;
+        state.serialize_field("G->C", &freq(G, C))?;
+        state.serialize_field("C->G", &freq(C, G))?;
+        state.serialize_field("G->A", &freq(G, A))?;
+        state.serialize_field("C->T", &freq(C, T))?;
+        state.serialize_field("G->T", &freq(G, T))?;
+        state.serialize_field("C->A", &freq(C, A))?;
+        // Raw numerators & denominators for the main EI components
+        state.serialize_field("#covered", &self.covered_roi)?;
+        state.serialize_field("A->G(sum)", &self.counts.map(|x| x.A.G))?;
+        state.serialize_field("A(coverage)", &self.counts.map(|x| x.A.coverage()))?;
+        state.serialize_field("T->C(sum)", &self.counts.map(|x| x.T.C))?;
+        state.serialize_field("T(coverage)", &self.counts.map(|x| x.T.coverage()))?;
+        // Remaining raw counts, required to merge EI tables from several runs
+        for (name, from, to) in RAW_COUNTS {
+            state.serialize_field(name, &self.counts.map(|x| x[from][to]))?;
+        }
         state.end()
     }
 }
@@ -97,7 +372,207 @@ impl Hook<ROIMismatchesVec> for ROIEditingIndex {
 
 impl EditingStat<ROIMismatchesVec> for ROIEditingIndex {
     fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
-        (EditingStatType::ROIEditingIndex, self)
+        (EditingStatType::ROIEditingIndex(self.label().map(|x| x.to_owned())), self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{NucCounts, WideStrandDepth};
+    use crate::core::mismatches::roi::{ROIData, ROIDataRecord, ROIDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    fn roi(name: &str, ag: f64) -> ROIData {
+        let mut mismatches = ROINucCounts::zeros();
+        mismatches.A.A = 10f64;
+        mismatches.A.G = ag;
+        ROIData {
+            roi: ROIDataRecord {
+                premasked: 0..10,
+                postmasked: 0..10,
+                subintervals: vec![0..10],
+                name: name.into(),
+                strand: Strand::Forward,
+                length: 10,
+            },
+            coverage: 1,
+            softclipped: 0,
+            indels: 0,
+            depth: WideStrandDepth::default(),
+            alt_fragments: 0,
+            homozygous: NucCounts::zeros(),
+            heterozygous: 0,
+            corrected: 0,
+            refnmasked: 0,
+            mismatches,
+            persample: vec![],
+            flank: ROINucCounts::zeros(),
+            profile: vec![],
+        }
+    }
+
+    fn batch() -> Batch<ROIMismatchesVec> {
+        Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items: Stranded::with_fn(|strand| ROIMismatchesVec::new("1".into(), strand, ROIDataVec::new())),
+            retained: Stranded::with_fn(|strand| ROIMismatchesVec::new("1".into(), strand, ROIDataVec::new())),
+        }
+    }
+
+    #[test]
+    fn names() {
+        let mut data = ROIDataVec::new();
+        for (name, ag) in [("AluSx", 1f64), ("L1PA2", 2f64), ("AluYa5", 3f64)] {
+            data.push(roi(name, ag));
+        }
+        let mut batch = batch();
+        batch.items.forward = ROIMismatchesVec::new("1".into(), Strand::Forward, data);
+
+        let mut all = ROIEditingIndex::new("exp".into(), "rois.bed".into());
+        let mut alu =
+            ROIEditingIndex::new("exp".into(), "rois.bed".into()).with_names("Alu".into(), Regex::new("^Alu").unwrap());
+        all.on_finish(&mut batch);
+        alu.on_finish(&mut batch);
+
+        assert_eq!((all.label(), all.covered_roi, all.accumulator.A.G), (None, 3, 6f64));
+        assert_eq!((alu.label(), alu.covered_roi, alu.accumulator.A.G), (Some("Alu"), 2, 4f64));
+        assert_eq!(Box::new(alu).into_any().0, EditingStatType::ROIEditingIndex(Some("Alu".into())));
+    }
+
+    #[test]
+    fn per_sample() {
+        // The second sample covers only the first stranded ROI and none of the unstranded ones
+        let mut covered = ROINucCounts::zeros();
+        covered.A.A = 3f64;
+        covered.A.G = 1f64;
+        let persample = |first: bool, second: bool| {
+            [first, second].map(|x| if x { covered } else { ROINucCounts::zeros() }).to_vec()
+        };
+        let mut batch = batch();
+        for (strand, samples) in [
+            (Strand::Forward, vec![persample(true, true), persample(true, false)]),
+            (Strand::Unknown, vec![persample(true, false)]),
+        ] {
+            let mut data = ROIDataVec::new();
+            for persample in samples {
+                data.push(ROIData { persample, ..roi("AluSx", 1f64) });
+            }
+            batch.items[strand] = ROIMismatchesVec::new("1".into(), strand, data);
+        }
+
+        let mut ei = ROIEditingIndex::per_sample("exp".into(), "rois.bed".into(), vec!["a".into(), "b".into()]);
+        ei.on_finish(&mut batch);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        ei.to_csv(&mut writer).unwrap();
+        let written = writer.into_inner().unwrap();
+
+        let mut reader = csv::Reader::from_reader(written.as_slice());
+        let header = reader.headers().unwrap().clone();
+        let rows: Vec<(String, usize, usize, f64)> = reader
+            .records()
+            .map(|x| {
+                let record = EditingIndexRecord::parse(&header, &x.unwrap()).unwrap();
+                (record.experiment, record.unstranded_roi, record.covered_roi, record.counts.unwrap().A.G)
+            })
+            .collect();
+        assert_eq!(rows, [("a".to_owned(), 1, 2, 2f64), ("b".to_owned(), 0, 1, 1f64)]);
+    }
+
+    #[test]
+    fn raw_counts() {
+        let mut counts = ROINucCounts::zeros();
+        counts.A.A = 6f64;
+        counts.A.G = 2f64;
+        counts.T.T = 3f64;
+        counts.T.C = 1f64;
+
+        let mut first = ROIEditingIndex::new("exp".into(), "rois.bed".into());
+        first.accumulator = counts;
+        first.covered_roi = 2;
+        let mut second = first.clone();
+        second.accumulator = counts.complementary();
+        second.unstranded_roi = 1;
+
+        let items: Vec<Box<dyn Any>> = vec![Box::new(first), Box::new(second)];
+        let collapsed = ROIEditingIndex::collapse(items);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        collapsed.to_csv(&mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(lines.next().unwrap(), ROIEditingIndex::header());
+
+        let header = ROIEditingIndex::header();
+        let row: Vec<(&str, &str)> = header.split(',').zip(lines.next().unwrap().split(',')).skip(3).collect();
+        let expected = [("#unstranded", "1"), ("A->A", "0.75"), ("A->G", "0.25"), ("T->C", "0.25")];
+        for x in expected {
+            assert!(row.contains(&x), "{:?}", x);
+        }
+        let covered = row.iter().position(|x| x.0 == "#covered").unwrap();
+        assert_eq!(
+            row[covered..covered + 5],
+            [
+                ("#covered", "4"),
+                ("A->G(sum)", "3.0"),
+                ("A(coverage)", "12.0"),
+                ("T->C(sum)", "3.0"),
+                ("T(coverage)", "12.0")
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_records() {
+        let mut counts = ROINucCounts::zeros();
+        counts.A.A = 6f64;
+        counts.A.G = 2f64;
+        counts.C.T = 1f64;
+
+        let mut ei = ROIEditingIndex::new("exp".into(), "rois.bed".into());
+        ei.accumulator = counts;
+        ei.covered_roi = 2;
+        ei.unstranded_roi = 1;
+        let mut writer = csv::Writer::from_writer(vec![]);
+        ei.to_csv(&mut writer).unwrap();
+        let empty = ROIEditingIndex::new("exp".into(), "rois.bed".into());
+        empty.to_csv_row(&mut writer, "empty").unwrap();
+        let written = writer.into_inner().unwrap();
+
+        let mut reader = csv::Reader::from_reader(written.as_slice());
+        let header = reader.headers().unwrap().clone();
+        let records: Vec<EditingIndexRecord> =
+            reader.records().map(|x| EditingIndexRecord::parse(&header, &x.unwrap()).unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].key(), ("exp", "rois.bed", "all"));
+        assert_eq!((records[0].unstranded_roi, records[0].covered_roi, records[0].counts), (1, 2, Some(counts)));
+        assert_eq!((records[1].contigs.as_str(), records[1].counts), ("empty", None));
+
+        // Merged counts => the same row as for the EI with summed counts
+        let mut merged = records[0].clone();
+        merged.merge(records[0].clone());
+        merged.merge(records[1].clone());
+        let mut writer = csv::Writer::from_writer(vec![]);
+        merged.to_csv(&mut writer).unwrap();
+
+        ei.accumulator += counts;
+        ei.covered_roi *= 2;
+        ei.unstranded_roi *= 2;
+        let mut expected = csv::Writer::from_writer(vec![]);
+        ei.to_csv(&mut expected).unwrap();
+        assert_eq!(writer.into_inner().unwrap(), expected.into_inner().unwrap());
+
+        let row = csv::StringRecord::from(vec!["exp", "rois.bed"]);
+        assert_eq!(EditingIndexRecord::parse(&header, &row).unwrap_err(), "missing contigs column");
     }
 }
 
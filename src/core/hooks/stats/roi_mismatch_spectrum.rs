@@ -0,0 +1,222 @@
+use std::any::Any;
+use std::io::Write;
+
+use bio_types::strand::Strand;
+
+use crate::core::dna::substitutions::NUCLEOTIDES;
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::hooks::stats::EditingStat;
+use crate::core::hooks::stats::EditingStatType;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::{ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::Batch;
+
+// All 12 mismatch types pooled across ROIs, i.e. whether A->G dominates the spectrum.
+// Stranded ROIs are in the transcript orientation, unstranded ones are taken as is
+#[derive(Clone)]
+pub struct ROIMismatchSpectrum {
+    accumulator: ROINucCounts,
+    unstranded_roi: usize,
+    expname: String,
+    roifiles: String,
+}
+
+impl ROIMismatchSpectrum {
+    pub fn new(expname: String, roifiles: String) -> Self {
+        Self { accumulator: ROINucCounts::zeros(), unstranded_roi: 0, expname, roifiles }
+    }
+
+    fn process(&mut self, x: &ROIMismatchesVec, strand: Strand) {
+        for counts in x.data.mismatches.iter() {
+            match strand {
+                Strand::Forward => self.accumulator += *counts,
+                Strand::Reverse => self.accumulator += counts.complementary(),
+                Strand::Unknown => {
+                    self.accumulator += *counts;
+                    self.unstranded_roi += 1;
+                }
+            }
+        }
+    }
+
+    // Mismatches normalized by the coverage of the reference nucleotide
+    pub fn spectrum(&self) -> Vec<(ReqNucleotide, ReqNucleotide, f32)> {
+        let mut spectrum = Vec::with_capacity(12);
+        for from in NUCLEOTIDES {
+            let coverage = self.accumulator[from].coverage();
+            for to in NUCLEOTIDES.into_iter().filter(|x| *x != from) {
+                spectrum.push((from, to, (self.accumulator[from][to] / coverage) as f32));
+            }
+        }
+        spectrum
+    }
+
+    pub fn collapse(items: Vec<Box<dyn Any>>) -> Self {
+        items
+            .into_iter()
+            .map(|x| *x.downcast::<Self>().unwrap())
+            .reduce(|mut a, b| {
+                debug_assert_eq!(a.expname, b.expname);
+                debug_assert_eq!(a.roifiles, b.roifiles);
+                a.accumulator = a.accumulator + b.accumulator;
+                a.unstranded_roi += b.unstranded_roi;
+                a
+            })
+            .unwrap()
+    }
+
+    // Header + row pair, the output is shared with conversion rates
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        let spectrum = self.spectrum();
+
+        let mut header = vec!["experiment".to_owned(), "ROI-file".to_owned(), "#unstranded".to_owned()];
+        let mut row = vec![self.expname.clone(), self.roifiles.clone(), self.unstranded_roi.to_string()];
+        for (from, to, freq) in spectrum {
+            header.push(format!("{}->{}", Nucleotide::from(from), Nucleotide::from(to)));
+            row.push(freq.to_string());
+        }
+        writer.write_record(header)?;
+        writer.write_record(row)
+    }
+}
+
+impl Hook<ROIMismatchesVec> for ROIMismatchSpectrum {
+    fn on_finish(&mut self, mismatches: &mut Batch<ROIMismatchesVec>) {
+        for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            self.process(&mismatches.retained[strand], strand);
+            self.process(&mismatches.items[strand], strand);
+        }
+    }
+}
+
+impl EditingStat<ROIMismatchesVec> for ROIMismatchSpectrum {
+    fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
+        (EditingStatType::ROIMismatchSpectrum, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{NucCounts, WideStrandDepth};
+    use crate::core::mismatches::roi::{ROIData, ROIDataRecord, ROIDataVec};
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    fn rois(contig: &str, strand: Strand, mismatches: &[ROINucCounts]) -> ROIMismatchesVec {
+        let mut data = ROIDataVec::new();
+        for mismatches in mismatches {
+            data.push(ROIData {
+                roi: ROIDataRecord {
+                    premasked: 0..10,
+                    postmasked: 0..10,
+                    subintervals: vec![0..10],
+                    name: "roi".into(),
+                    strand,
+                    length: 10,
+                },
+                coverage: 1,
+                softclipped: 0,
+                indels: 0,
+                depth: WideStrandDepth::default(),
+                alt_fragments: 0,
+                homozygous: NucCounts::zeros(),
+                heterozygous: 0,
+                corrected: 0,
+                refnmasked: 0,
+                mismatches: *mismatches,
+                persample: vec![],
+                flank: ROINucCounts::zeros(),
+                profile: vec![],
+            });
+        }
+        ROIMismatchesVec::new(contig.into(), strand, data)
+    }
+
+    #[test]
+    fn spectrum() {
+        let mut forward = ROINucCounts::zeros();
+        forward.A.A = 16f64;
+        forward.A.G = 4f64;
+        forward.C.C = 9f64;
+        forward.C.T = 1f64;
+        // Reverse: T->C is A->G & G->A is C->T on the transcript
+        let mut reverse = ROINucCounts::zeros();
+        reverse.T.T = 8f64;
+        reverse.T.C = 2f64;
+        reverse.G.G = 9f64;
+        reverse.G.A = 1f64;
+        reverse.A.C = 1f64;
+        reverse.A.A = 3f64;
+        // Unknown: taken as is
+        let mut unknown = ROINucCounts::zeros();
+        unknown.G.G = 15f64;
+        unknown.G.T = 5f64;
+        unknown.A.A = 1f64;
+
+        let empty = |strand| ROIMismatchesVec::new("chr1".into(), strand, ROIDataVec::new());
+        let batch = |items: Stranded<ROIMismatchesVec>| Batch {
+            contig: "chr1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items,
+            retained: Stranded::with_fn(empty),
+        };
+
+        // Two threads
+        let mut first = ROIMismatchSpectrum::new("exp".into(), "rois.bed".into());
+        let mut items = Stranded::with_fn(empty);
+        items.forward = rois("chr1", Strand::Forward, &[forward]);
+        items.unknown = rois("chr1", Strand::Unknown, &[unknown]);
+        first.on_finish(&mut batch(items));
+
+        let mut second = ROIMismatchSpectrum::new("exp".into(), "rois.bed".into());
+        let mut items = Stranded::with_fn(empty);
+        items.reverse = rois("chr1", Strand::Reverse, &[reverse]);
+        second.on_finish(&mut batch(items));
+
+        let items: Vec<Box<dyn Any>> = vec![Box::new(first), Box::new(second)];
+        let collapsed = ROIMismatchSpectrum::collapse(items);
+        assert_eq!(collapsed.unstranded_roi, 1);
+
+        // A: 16 + 8 + 1 (A) | 4 + 2 (G) => A->G = 6 / 31
+        // C: 9 + 9 (C) | 1 + 1 (T) => C->T = 2 / 20
+        // G: 15 (G) | 5 (T) => G->T = 5 / 20
+        // T: 3 (T) | 1 (G) => T->G = 1 / 4
+        let ag = (6f64 / 31f64) as f32;
+        let (a, c, g, t) = (ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T);
+        let expected = [
+            (a, c, 0f32),
+            (a, g, ag),
+            (a, t, 0f32),
+            (c, a, 0f32),
+            (c, g, 0f32),
+            (c, t, 0.1f32),
+            (g, a, 0f32),
+            (g, c, 0f32),
+            (g, t, 0.25f32),
+            (t, a, 0f32),
+            (t, c, 0f32),
+            (t, g, 0.25f32),
+        ];
+        assert_eq!(collapsed.spectrum(), expected);
+
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        collapsed.to_csv(&mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            written,
+            format!(
+                "experiment,ROI-file,#unstranded,A->C,A->G,A->T,C->A,C->G,C->T,G->A,G->C,G->T,T->A,T->C,T->G\n\
+                 exp,rois.bed,1,0,{},0,0,0,0.1,0,0,0.25,0,0,0.25\n",
+                ag
+            )
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::any::Any;
+use std::io::Write;
+
+use bio_types::strand::Strand;
+
+use crate::core::hooks::stats::EditingStat;
+use crate::core::hooks::stats::EditingStatType;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::roi::{ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::Batch;
+use crate::core::strandutil::Stranded;
+
+const STRANDS: [Strand; 3] = [Strand::Forward, Strand::Reverse, Strand::Unknown];
+
+// Pooled EI for each transcription strand separately, e.g. to compare the sense & antisense editing signal.
+// Stranded ROIs are in the transcript orientation, unstranded ones are taken as is
+#[derive(Clone)]
+pub struct StrandSplitEditingIndex {
+    accumulator: Stranded<ROINucCounts>,
+    // ROIs with nonzero coverage
+    covered_roi: Stranded<usize>,
+    expname: String,
+    roifiles: String,
+}
+
+impl StrandSplitEditingIndex {
+    pub fn new(expname: String, roifiles: String) -> Self {
+        Self {
+            accumulator: Stranded::with_fn(|_| ROINucCounts::zeros()),
+            covered_roi: Stranded::default(),
+            expname,
+            roifiles,
+        }
+    }
+
+    fn process(&mut self, x: &ROIMismatchesVec, strand: Strand) {
+        for counts in x.data.mismatches.iter() {
+            if counts.coverage() > 0f64 {
+                self.covered_roi[strand] += 1;
+            }
+            self.accumulator[strand] += match strand {
+                Strand::Reverse => counts.complementary(),
+                _ => *counts,
+            };
+        }
+    }
+
+    // Components of each strand are merged independently
+    pub fn collapse(items: Vec<Box<dyn Any>>) -> Self {
+        items
+            .into_iter()
+            .map(|x| *x.downcast::<Self>().unwrap())
+            .reduce(|mut a, b| {
+                debug_assert_eq!(a.expname, b.expname);
+                debug_assert_eq!(a.roifiles, b.roifiles);
+                a.accumulator += b.accumulator;
+                a.covered_roi += b.covered_roi;
+                a
+            })
+            .unwrap()
+    }
+
+    // Header + one row per strand, the output is shared with conversion rates
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        writer.write_record([
+            "experiment",
+            "ROI-file",
+            "trstrand",
+            "#covered",
+            "A->G",
+            "T->C",
+            "A->G(sum)",
+            "A(coverage)",
+            "T->C(sum)",
+            "T(coverage)",
+        ])?;
+        for strand in STRANDS {
+            let counts = &self.accumulator[strand];
+            writer.write_record([
+                self.expname.clone(),
+                self.roifiles.clone(),
+                strand.strand_symbol().to_owned(),
+                self.covered_roi[strand].to_string(),
+                ((counts.A.G / counts.A.coverage()) as f32).to_string(),
+                ((counts.T.C / counts.T.coverage()) as f32).to_string(),
+                counts.A.G.to_string(),
+                counts.A.coverage().to_string(),
+                counts.T.C.to_string(),
+                counts.T.coverage().to_string(),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook<ROIMismatchesVec> for StrandSplitEditingIndex {
+    fn on_finish(&mut self, mismatches: &mut Batch<ROIMismatchesVec>) {
+        for strand in STRANDS {
+            self.process(&mismatches.retained[strand], strand);
+            self.process(&mismatches.items[strand], strand);
+        }
+    }
+}
+
+impl EditingStat<ROIMismatchesVec> for StrandSplitEditingIndex {
+    fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
+        (EditingStatType::StrandSplitEditingIndex, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{NucCounts, WideStrandDepth};
+    use crate::core::mismatches::roi::{ROIData, ROIDataRecord, ROIDataVec};
+
+    use super::*;
+
+    fn roi(counts: ROINucCounts) -> ROIData {
+        ROIData {
+            roi: ROIDataRecord {
+                premasked: 0..10,
+                postmasked: 0..10,
+                subintervals: vec![0..10],
+                name: "roi".into(),
+                strand: Strand::Unknown,
+                length: 10,
+            },
+            coverage: 1,
+            softclipped: 0,
+            indels: 0,
+            depth: WideStrandDepth::default(),
+            alt_fragments: 0,
+            homozygous: NucCounts::zeros(),
+            heterozygous: 0,
+            corrected: 0,
+            refnmasked: 0,
+            mismatches: counts,
+            persample: vec![],
+            flank: ROINucCounts::zeros(),
+            profile: vec![],
+        }
+    }
+
+    fn batch(forward: &[ROINucCounts], reverse: &[ROINucCounts], unknown: &[ROINucCounts]) -> Batch<ROIMismatchesVec> {
+        let items = Stranded { forward, reverse, unknown }.into(|counts, strand| {
+            let mut data = ROIDataVec::new();
+            for x in counts {
+                data.push(roi(*x));
+            }
+            ROIMismatchesVec::new("1".into(), strand, data)
+        });
+        Batch {
+            contig: "1".into(),
+            mapped: Default::default(),
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            items,
+            retained: Stranded::with_fn(|strand| ROIMismatchesVec::new("1".into(), strand, ROIDataVec::new())),
+        }
+    }
+
+    #[test]
+    fn per_strand() {
+        let counts = |aa, ag, tt, tc| {
+            let mut counts = ROINucCounts::zeros();
+            counts.A.A = aa;
+            counts.A.G = ag;
+            counts.T.T = tt;
+            counts.T.C = tc;
+            counts
+        };
+        let (first, second, uncovered) =
+            (counts(6f64, 2f64, 9f64, 1f64), counts(3f64, 1f64, 0f64, 0f64), counts(0f64, 0f64, 0f64, 0f64));
+
+        // Two threads, each strand has its own counts
+        let mut threads = [
+            StrandSplitEditingIndex::new("exp".into(), "rois.bed".into()),
+            StrandSplitEditingIndex::new("exp".into(), "rois.bed".into()),
+        ];
+        threads[0].on_finish(&mut batch(&[first], &[second], &[uncovered]));
+        threads[1].on_finish(&mut batch(&[second], &[], &[first, second]));
+
+        let items: Vec<Box<dyn Any>> = threads.into_iter().map(|x| Box::new(x) as Box<dyn Any>).collect();
+        let collapsed = StrandSplitEditingIndex::collapse(items);
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        collapsed.to_csv(&mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        // Reverse strand ROIs are complemented => their reference strand A->G is reported as T->C
+        assert_eq!(
+            written,
+            "experiment,ROI-file,trstrand,#covered,A->G,T->C,A->G(sum),A(coverage),T->C(sum),T(coverage)\n\
+             exp,rois.bed,+,2,0.25,0.1,3,12,1,10\n\
+             exp,rois.bed,-,1,NaN,0.25,0,0,1,4\n\
+             exp,rois.bed,.,2,0.25,0.1,3,12,1,10\n"
+        );
+    }
+}
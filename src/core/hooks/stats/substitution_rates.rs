@@ -0,0 +1,92 @@
+use std::any::Any;
+use std::io::Write;
+
+use crate::core::dna::substitutions::NUCLEOTIDES;
+use crate::core::dna::{Nucleotide, SubstitutionCounts};
+use crate::core::hooks::stats::EditingStat;
+use crate::core::hooks::stats::EditingStatType;
+use crate::core::hooks::Hook;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::Batch;
+
+// Background substitution profile of the library, counts are collected by the mismatches builder for each batch
+#[derive(Clone, Default)]
+pub struct SubstitutionRates {
+    counts: SubstitutionCounts,
+}
+
+impl SubstitutionRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counts(&self) -> &SubstitutionCounts {
+        &self.counts
+    }
+
+    // Merge thread-local stats
+    pub fn collapse(items: Vec<Box<dyn Any>>) -> Self {
+        let mut result = Self::new();
+        for item in items.into_iter().map(|x| *x.downcast::<Self>().unwrap()) {
+            result.counts += item.counts;
+        }
+        result
+    }
+
+    pub fn to_csv<W: Write>(&self, writer: &mut csv::Writer<W>) -> csv::Result<()> {
+        writer.write_record(["reference", "sequenced", "count", "rate"])?;
+        for reference in NUCLEOTIDES {
+            for sequenced in NUCLEOTIDES {
+                writer.write_record([
+                    Nucleotide::from(reference).symbol().to_string(),
+                    Nucleotide::from(sequenced).symbol().to_string(),
+                    self.counts.count(reference, sequenced).to_string(),
+                    self.counts.rate(reference, sequenced).to_string(),
+                ])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hook<SiteMismatchesVec> for SubstitutionRates {
+    fn on_finish(&mut self, mismatches: &mut Batch<SiteMismatchesVec>) {
+        self.counts += mismatches.substitutions;
+    }
+}
+
+impl EditingStat<SiteMismatchesVec> for SubstitutionRates {
+    fn into_any(self: Box<Self>) -> (EditingStatType, Box<dyn Any>) {
+        (EditingStatType::SubstitutionRates, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::NucCounts;
+    use crate::core::refpred::PredNucleotide;
+
+    use super::*;
+
+    #[test]
+    fn collapse() {
+        let (mut first, mut second) = (SubstitutionRates::new(), SubstitutionRates::new());
+        first.counts.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(9, 0, 1, 0));
+        second.counts.add(PredNucleotide::Homozygous(Nucleotide::A), &NucCounts::new(8, 0, 1, 0));
+        second.counts.add(PredNucleotide::Homozygous(Nucleotide::T), &NucCounts::new(0, 1, 0, 8));
+
+        let items: Vec<Box<dyn Any>> = vec![Box::new(first), Box::new(second)];
+        let collapsed = SubstitutionRates::collapse(items);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        collapsed.to_csv(&mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 17);
+        assert_eq!(lines[0], "reference,sequenced,count,rate");
+        assert_eq!(lines[1], "A,A,17,0.9");
+        assert_eq!(lines[3], "A,G,2,0.15");
+        assert_eq!(lines[14], "T,C,1,0.2");
+        assert_eq!(lines[5], "C,A,0,1");
+    }
+}
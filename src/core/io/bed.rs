@@ -1,6 +1,6 @@
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::ops::Range;
 use std::path::Path;
 
@@ -8,6 +8,9 @@ use bio_types::genome::{AbstractInterval, Interval, Position};
 use bio_types::strand::{Same, Strand};
 use derive_getters::Dissolve;
 use flate2::bufread::MultiGzDecoder;
+use rayon::prelude::*;
+
+use crate::error::Error;
 
 use super::utils;
 
@@ -16,11 +19,16 @@ pub struct BedRecord {
     pub name: String,
     pub strand: Strand,
     pub interval: Interval,
+    // Blocks (e.g. exons) of BED12 records in absolute coordinates, empty for other records
+    pub blocks: Vec<Range<Position>>,
 }
 
 impl PartialEq for BedRecord {
     fn eq(&self, other: &Self) -> bool {
-        self.strand.same(&other.strand) && self.name == other.name && self.interval == other.interval
+        self.strand.same(&other.strand)
+            && self.name == other.name
+            && self.interval == other.interval
+            && self.blocks == other.blocks
     }
 }
 
@@ -34,38 +42,109 @@ impl AbstractInterval for BedRecord {
     }
 }
 
-fn _parse<T: BufRead>(mut reader: T) -> Vec<BedRecord> {
-    let mut records = Vec::new();
-
-    let mut buf = String::new();
-    while reader.read_line(&mut buf).expect("Failed to read BED file") != 0 {
-        let line = buf.trim_end();
-        if line.is_empty() {
-            buf.clear();
-            continue;
-        }
-        let split: Vec<&str> = line.split('\t').take(6).collect();
-        assert!(split.len() >= 3);
+// blockCount, blockSizes, blockStarts columns of a BED12 record, starts are relative to the record start
+fn parse_blocks(start: Position, end: Position, columns: &[&str]) -> Result<Vec<Range<Position>>, String> {
+    let count: usize = columns[0].parse().map_err(|_| format!("invalid BED12 block count \"{}\"", columns[0]))?;
+    let numbers = |x: &str| -> Result<Vec<Position>, String> {
+        x.split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.parse().map_err(|_| format!("invalid BED12 block size or start \"{}\"", x)))
+            .collect()
+    };
+    let (sizes, starts) = (numbers(columns[1])?, numbers(columns[2])?);
+    if count == 0 || sizes.len() != count || starts.len() != count {
+        return Err(format!("expected {} BED12 block sizes & starts, got {} & {}", count, sizes.len(), starts.len()));
+    }
 
-        let start = split[1].parse().expect("Failed to filters string start");
-        let end = split[2].parse().expect("Failed to filters string start");
-        assert!(end > start, "{}", line);
-        let interval = Interval::new(split[0].to_owned(), Range { start, end });
+    let mut blocks: Vec<Range<Position>> =
+        starts.into_iter().zip(sizes).map(|(bstart, bsize)| start + bstart..start + bstart + bsize).collect();
+    blocks.sort_by_key(|x| x.start);
+    if !(blocks.iter().all(|x| x.end > x.start && x.end <= end) && blocks.windows(2).all(|x| x[0].end <= x[1].start)) {
+        return Err("BED12 blocks must be non-empty, non-overlapping and located inside the record".into());
+    }
+    Ok(blocks)
+}
 
-        let name = split.get(3).unwrap_or(&"").to_string();
-        let strand = split.get(5).map_or(Strand::Unknown, |x| {
-            Strand::from_char(&x.chars().next().unwrap()).expect("Failed to parse strand")
-        });
+fn parse_line(line: &str) -> Result<Option<BedRecord>, String> {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let split: Vec<&str> = line.split('\t').take(12).collect();
+    if split.len() < 3 {
+        return Err(format!("expected at least 3 tab-separated columns, got {}", split.len()));
+    }
 
-        records.push(BedRecord { name, strand, interval });
-        buf.clear();
+    let start: Position = split[1].parse().map_err(|_| format!("invalid start coordinate \"{}\"", split[1]))?;
+    let end: Position = split[2].parse().map_err(|_| format!("invalid end coordinate \"{}\"", split[2]))?;
+    if end <= start {
+        return Err(format!("end coordinate {} must be greater than the start {}", end, start));
     }
-    records
+    let interval = Interval::new(split[0].to_owned(), Range { start, end });
+
+    let name = split.get(3).unwrap_or(&"").to_string();
+    let strand = match split.get(5) {
+        None => Strand::Unknown,
+        Some(x) => x
+            .chars()
+            .next()
+            .and_then(|x| Strand::from_char(&x).ok())
+            .ok_or_else(|| format!("invalid strand \"{}\"", x))?,
+    };
+
+    // BED12 and other records might be mixed in the same file
+    let blocks = if split.len() == 12 { parse_blocks(start, end, &split[9..])? } else { vec![] };
+
+    Ok(Some(BedRecord { name, strand, interval, blocks }))
 }
 
-pub fn parse(bed: impl AsRef<Path>) -> Vec<BedRecord> {
+// Lines are parsed in parallel, the first malformed line (if any) is reported
+fn parse_lines<T: Send>(
+    content: &str,
+    path: &Path,
+    parser: impl Fn(&str) -> Result<Option<T>, String> + Sync,
+) -> Result<Vec<T>, Error> {
+    let lines: Vec<&str> = content.lines().collect();
+    let parsed: Vec<Result<Option<T>, Error>> = lines
+        .into_par_iter()
+        .enumerate()
+        .map(|(ind, line)| parser(line).map_err(|x| Error::parse(path, ind + 1, x)))
+        .collect();
+    parsed.into_iter().filter_map(Result::transpose).collect()
+}
+
+fn _parse<T: BufRead>(mut reader: T, path: &Path) -> Result<Vec<BedRecord>, Error> {
+    // Read everything first and then parse lines in parallel, the order of records is preserved
+    let mut content = String::new();
+    reader.read_to_string(&mut content).map_err(|x| Error::io(path, x))?;
+    parse_lines(&content, path, parse_line)
+}
+
+pub fn parse(bed: impl AsRef<Path>) -> Result<Vec<BedRecord>, Error> {
     let bed = bed.as_ref();
-    utils::read_compressed!(bed, _parse)
+    utils::read_compressed!(bed, _parse, bed)?
+}
+
+// Either a 2-column line (contig, 0-based position) or a regular BED record
+fn parse_locus(line: &str) -> Result<Option<Interval>, String> {
+    let trimmed = line.trim_end();
+    let split: Vec<&str> = trimmed.split('\t').take(3).collect();
+    if split.len() == 2 {
+        let pos: Position = split[1].parse().map_err(|_| format!("invalid locus position \"{}\"", split[1]))?;
+        return Ok(Some(Interval::new(split[0].to_owned(), pos..pos + 1)));
+    }
+    parse_line(line).map(|x| x.map(|x| x.interval))
+}
+
+fn _loci<T: BufRead>(mut reader: T, path: &Path) -> Result<Vec<Interval>, Error> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content).map_err(|x| Error::io(path, x))?;
+    parse_lines(&content, path, parse_locus)
+}
+
+pub fn loci(path: impl AsRef<Path>) -> Result<Vec<Interval>, Error> {
+    let path = path.as_ref();
+    utils::read_compressed!(path, _loci, path)?
 }
 
 #[cfg(test)]
@@ -77,13 +156,13 @@ mod tests {
     use super::*;
 
     fn br(chr: &str, range: Range<Position>, name: &str, strand: Strand) -> BedRecord {
-        BedRecord { interval: Interval::new(chr.to_string(), range), name: name.to_string(), strand }
+        BedRecord { interval: Interval::new(chr.to_string(), range), name: name.to_string(), strand, blocks: vec![] }
     }
 
     #[test]
     fn empty() {
         let bed = "";
-        assert!(_parse(BufReader::new(bed.as_bytes())).is_empty());
+        assert!(_parse(BufReader::new(bed.as_bytes()), Path::new("test.bed")).unwrap().is_empty());
     }
 
     #[test]
@@ -100,7 +179,7 @@ mod tests {
             br("chr1", 70..80, "", Strand::Forward),
         ];
 
-        assert_eq!(records, _parse(BufReader::new(bed.as_bytes())));
+        assert_eq!(records, _parse(BufReader::new(bed.as_bytes()), Path::new("test.bed")).unwrap());
     }
 
     #[test]
@@ -122,6 +201,64 @@ mod tests {
             br("chr4", 700..1800, "\"1\"", Strand::Reverse),
         ];
 
-        assert_eq!(records, _parse(BufReader::new(bed.as_bytes())));
+        assert_eq!(records, _parse(BufReader::new(bed.as_bytes()), Path::new("test.bed")).unwrap());
+    }
+
+    #[test]
+    fn bed12() {
+        let bed = "\
+        chr1\t100\t200\ttranscript\t0\t-\t100\t200\t0\t3\t10,20,5,\t0,40,95,\n\
+        chr1\t10\t20\tregion\t.\t+\n\
+        chr2\t0\t50\tunordered\t0\t+\t0\t50\t0\t2\t10,10\t40,0\n";
+        let mut transcript = br("chr1", 100..200, "transcript", Strand::Reverse);
+        transcript.blocks = vec![100..110, 140..160, 195..200];
+        let mut unordered = br("chr2", 0..50, "unordered", Strand::Forward);
+        unordered.blocks = vec![0..10, 40..50];
+        let records = vec![transcript, br("chr1", 10..20, "region", Strand::Forward), unordered];
+
+        assert_eq!(records, _parse(BufReader::new(bed.as_bytes()), Path::new("test.bed")).unwrap());
+    }
+
+    #[test]
+    fn overlapping_blocks() {
+        assert!(parse_line("chr1\t0\t100\tname\t0\t+\t0\t100\t0\t2\t10,10\t0,5").is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let bed = "\
+        chr1\t10\t20\tReg1\t.\t+\n\
+        chr1\t1e3\t2000\tReg2\t.\t+\n\
+        chr1\t30\t20\tReg3\t.\t+\n";
+        let err = _parse(BufReader::new(bed.as_bytes()), Path::new("regions.bed")).unwrap_err();
+        // The first malformed line is reported
+        assert_eq!(err.to_string(), "regions.bed:2: invalid start coordinate \"1e3\"");
+
+        for (line, expected) in [
+            ("chr1\t10", "expected at least 3 tab-separated columns, got 2"),
+            ("chr1\t10\t-5", "invalid end coordinate \"-5\""),
+            ("chr1\t30\t20", "end coordinate 20 must be greater than the start 30"),
+            ("chr1\t10\t20\tname\t0\t*", "invalid strand \"*\""),
+        ] {
+            assert_eq!(parse_line(line).unwrap_err(), expected, "{}", line);
+        }
+
+        let err = _loci(BufReader::new("chr1\t10\nchr1\tx\n".as_bytes()), Path::new("loci.tsv")).unwrap_err();
+        assert_eq!(err.to_string(), "loci.tsv:2: invalid locus position \"x\"");
+    }
+
+    #[test]
+    fn loci() {
+        let loci = "\
+        chr1\t10\n\
+        \n\
+        chr2\t5\t8\tname\t0\t+\n\
+        MT\t0\n";
+        let expected = vec![
+            Interval::new("chr1".into(), 10..11),
+            Interval::new("chr2".into(), 5..8),
+            Interval::new("MT".into(), 0..1),
+        ];
+        assert_eq!(expected, _loci(BufReader::new(loci.as_bytes()), Path::new("loci.bed")).unwrap());
     }
 }
@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+
+use bio_types::genome::{AbstractInterval, Interval};
+use itertools::Itertools;
+
+use super::bed::BedRecord;
+
+// The same contig in the alternative naming convention: chr1 <-> 1
+fn alternative(contig: &str) -> String {
+    match contig.strip_prefix("chr") {
+        Some(stripped) => stripped.to_owned(),
+        None => format!("chr{}", contig),
+    }
+}
+
+// Renaming of contigs between naming conventions (e.g. "1" -> "chr1"), contigs without a rule are kept as is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContigNames {
+    renamed: HashMap<String, String>,
+}
+
+impl ContigNames {
+    // Match contigs to the target names. If `fix` is set, contigs missing in the target are renamed by
+    // adding/stripping the "chr" prefix when possible. Returns the renaming & contigs missing in the target (sorted)
+    pub fn resolve<'a>(
+        target: &HashSet<String>,
+        contigs: impl IntoIterator<Item = &'a str>,
+        fix: bool,
+    ) -> (Self, Vec<String>) {
+        let mut renamed = HashMap::new();
+        let mut missing = HashSet::new();
+        for contig in contigs {
+            if target.contains(contig) || renamed.contains_key(contig) {
+                continue;
+            }
+            let alt = alternative(contig);
+            if fix && target.contains(&alt) {
+                renamed.insert(contig.to_owned(), alt);
+            } else {
+                missing.insert(contig.to_owned());
+            }
+        }
+        let mut missing: Vec<String> = missing.into_iter().collect();
+        missing.sort();
+        (Self { renamed }, missing)
+    }
+
+    #[inline]
+    pub fn rename<'a>(&'a self, contig: &'a str) -> &'a str {
+        self.renamed.get(contig).map_or(contig, |x| x.as_str())
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self { renamed: self.renamed.iter().map(|(from, to)| (to.clone(), from.clone())).collect() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.renamed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.renamed.is_empty()
+    }
+
+    pub fn rename_bed(&self, records: Vec<BedRecord>) -> Vec<BedRecord> {
+        if self.is_empty() {
+            return records;
+        }
+        records
+            .into_iter()
+            .map(|mut x| {
+                if let Some(contig) = self.renamed.get(x.contig()) {
+                    x.interval = Interval::new(contig.clone(), x.interval.range());
+                }
+                x
+            })
+            .collect()
+    }
+}
+
+// Contig lengths from the BAM headers, used to validate coordinates of ROIs and reported records
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContigLengths {
+    lengths: HashMap<String, u64>,
+}
+
+impl ContigLengths {
+    pub fn new(lengths: HashMap<String, u64>) -> Self {
+        Self { lengths }
+    }
+
+    #[inline]
+    pub fn get(&self, contig: &str) -> Option<u64> {
+        self.lengths.get(contig).copied()
+    }
+
+    pub fn names(&self) -> HashSet<String> {
+        self.lengths.keys().cloned().collect()
+    }
+
+    // Contigs with different lengths in the given source (e.g. the reference assembly), contigs are renamed to
+    // the source names first and missing ones are ignored. Returns (contig, length, source length) sorted by name
+    pub fn mismatches(&self, source: &HashMap<String, u64>, names: &ContigNames) -> Vec<(String, u64, u64)> {
+        let mut result = self
+            .lengths
+            .iter()
+            .filter_map(|(contig, length)| match source.get(names.rename(contig)) {
+                Some(other) if other != length => Some((contig.clone(), *length, *other)),
+                _ => None,
+            })
+            .collect_vec();
+        result.sort();
+        result
+    }
+
+    // Records extending past the end of their contig together with the contig length, unknown contigs are ignored
+    pub fn past_end<'a>(&'a self, records: &'a [BedRecord]) -> impl Iterator<Item = (&'a BedRecord, u64)> + 'a {
+        records.iter().filter_map(|x| match self.get(x.contig()) {
+            Some(length) if x.interval.range().end > length => Some((x, length)),
+            _ => None,
+        })
+    }
+
+    // Truncate records (and their BED12 blocks) to the contig ends, records left empty are dropped
+    pub fn clip(&self, records: Vec<BedRecord>) -> Vec<BedRecord> {
+        records
+            .into_iter()
+            .filter_map(|mut x| {
+                let length = match self.get(x.contig()) {
+                    Some(length) if x.interval.range().end > length => length,
+                    _ => return Some(x),
+                };
+                let start = x.interval.range().start;
+                if start >= length {
+                    return None;
+                }
+                if !x.blocks.is_empty() {
+                    x.blocks.retain(|b| b.start < length);
+                    if x.blocks.is_empty() {
+                        return None;
+                    }
+                    for block in &mut x.blocks {
+                        block.end = block.end.min(length);
+                    }
+                }
+                *x.interval.range_mut() = start..length;
+                Some(x)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use bio_types::genome::Position;
+    use bio_types::strand::Strand;
+
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let target: HashSet<String> =
+            ["chr1", "chr2", "chrM", "KI270728.1"].into_iter().map(|x| x.to_owned()).collect();
+        let contigs = ["1", "chr2", "2", "KI270728.1", "MT", "1", "chrX"];
+
+        let (names, missing) = ContigNames::resolve(&target, contigs, false);
+        assert!(names.is_empty());
+        assert_eq!(missing, ["1", "2", "MT", "chrX"]);
+
+        let (names, missing) = ContigNames::resolve(&target, contigs, true);
+        assert_eq!(names.len(), 2);
+        assert_eq!(missing, ["MT", "chrX"]);
+        for (contig, expected) in
+            [("1", "chr1"), ("2", "chr2"), ("chr2", "chr2"), ("MT", "MT"), ("KI270728.1", "KI270728.1")]
+        {
+            assert_eq!(names.rename(contig), expected);
+        }
+
+        let inverse = names.inverse();
+        for (contig, expected) in [("chr1", "1"), ("chr2", "2"), ("chrM", "chrM")] {
+            assert_eq!(inverse.rename(contig), expected);
+        }
+
+        // Stripping the prefix
+        let target = HashSet::from(["1".to_owned()]);
+        let (names, missing) = ContigNames::resolve(&target, ["chr1"], true);
+        assert!(missing.is_empty());
+        assert_eq!(names.rename("chr1"), "1");
+    }
+
+    #[test]
+    fn rename_bed() {
+        let record = |contig: &str| BedRecord {
+            name: "ROI".to_owned(),
+            strand: Strand::Forward,
+            interval: Interval::new(contig.to_owned(), 10..20),
+            blocks: vec![12..14],
+        };
+        let target = HashSet::from(["chr1".to_owned()]);
+        let (names, _) = ContigNames::resolve(&target, ["1", "2"], true);
+        assert_eq!(names.rename_bed(vec![record("1"), record("2")]), vec![record("chr1"), record("2")]);
+    }
+
+    #[test]
+    fn lengths() {
+        let lengths = ContigLengths::new(HashMap::from([("1".to_owned(), 100), ("2".to_owned(), 50)]));
+        assert_eq!(lengths.get("1"), Some(100));
+        assert_eq!(lengths.get("3"), None);
+
+        let reference = HashMap::from([("chr1".to_owned(), 100), ("chr2".to_owned(), 60), ("chr3".to_owned(), 10)]);
+        let target = reference.keys().cloned().collect();
+        let (names, _) = ContigNames::resolve(&target, ["1", "2"], true);
+        assert_eq!(lengths.mismatches(&reference, &names), [("2".to_owned(), 50, 60)]);
+        assert!(lengths.mismatches(&reference, &ContigNames::default()).is_empty());
+    }
+
+    #[test]
+    fn clip() {
+        let record = |contig: &str, range: Range<Position>, blocks: Vec<Range<Position>>| BedRecord {
+            name: "ROI".to_owned(),
+            strand: Strand::Forward,
+            interval: Interval::new(contig.to_owned(), range),
+            blocks,
+        };
+        let lengths = ContigLengths::new(HashMap::from([("1".to_owned(), 100)]));
+        let records = vec![
+            record("1", 10..100, vec![]),
+            record("1", 90..1100, vec![]),
+            record("1", 100..200, vec![]),
+            record("1", 50..150, vec![50..60, 90..110, 120..150]),
+            record("1", 50..150, vec![120..150]),
+            record("2", 10..1000, vec![]),
+        ];
+        let past = lengths.past_end(&records).map(|(x, length)| (x.interval.range(), length)).collect_vec();
+        assert_eq!(past, [(90..1100, 100), (100..200, 100), (50..150, 100), (50..150, 100)]);
+
+        let expected = vec![
+            record("1", 10..100, vec![]),
+            record("1", 90..100, vec![]),
+            record("1", 50..100, vec![50..60, 90..100]),
+            record("2", 10..1000, vec![]),
+        ];
+        assert_eq!(lengths.clip(records), expected);
+    }
+}
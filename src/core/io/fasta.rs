@@ -1,5 +1,10 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use bio_types::genome::Position;
 use dyn_clone::DynClone;
@@ -8,9 +13,13 @@ use mockall::mock;
 use rust_htslib::faidx;
 
 use crate::core::dna::Nucleotide;
+use crate::error::Error;
+
+use super::contigs::ContigNames;
 
 pub trait FastaReader: Send + DynClone {
-    fn fetch(&mut self, contig: &str, range: Range<Position>);
+    // Errors are reported for unreadable files and regions absent in the FASTA
+    fn fetch(&mut self, contig: &str, range: Range<Position>) -> Result<(), Error>;
     fn result(&self) -> &[Nucleotide];
 }
 dyn_clone::clone_trait_object!(FastaReader);
@@ -23,15 +32,27 @@ mock! {
     }
 
     impl FastaReader for FastaReader {
-        fn fetch(&mut self, contig: &str, range: Range<Position>);
+        fn fetch(&mut self, contig: &str, range: Range<Position>) -> Result<(), Error>;
         fn result(&self) -> &[Nucleotide];
     }
 }
 
+// Reference is cached in blocks of 1Mb
+const CACHE_BLOCK: u64 = 1_000_000;
+
 pub struct BasicFastaReader {
-    faidx: faidx::Reader,
+    // Opened on the first fetch => clones (i.e. threads) report failures to reopen the file as regular errors
+    faidx: Option<faidx::Reader>,
     cache: Vec<Nucleotide>,
     path: PathBuf,
+    // Requested contigs => contigs in the FASTA file
+    names: ContigNames,
+    // Contigs in the FASTA file => their lengths
+    lengths: Arc<HashMap<String, u64>>,
+    // Recently used reference blocks, each clone (i.e. each thread) has its own cache
+    blocks: Option<BlockCache>,
+    // Bases read from the FASTA file by the reader and all its clones
+    fetched: Arc<AtomicU64>,
 }
 
 unsafe impl Send for BasicFastaReader {}
@@ -39,33 +60,66 @@ unsafe impl Send for BasicFastaReader {}
 unsafe impl Sync for BasicFastaReader {}
 
 impl BasicFastaReader {
-    pub fn new(path: PathBuf) -> Self {
-        Self {
-            faidx: faidx::Reader::from_path(&path).expect("Failed to open reference fasta file"),
+    // Truncated files are rejected upfront => sequences of all indexed contigs can be fetched later
+    pub fn new(path: PathBuf) -> Result<Self, Error> {
+        validate(&path)?;
+        Ok(Self {
+            faidx: Some(Self::open(&path)?),
             cache: Vec::new(),
+            lengths: Arc::new(lengths(&path)?),
             path,
-        }
+            names: ContigNames::default(),
+            blocks: None,
+            fetched: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn open(path: &Path) -> Result<faidx::Reader, Error> {
+        faidx::Reader::from_path(path).map_err(|x| Error::reference(path, x.to_string()))
+    }
+
+    pub fn with_contig_names(mut self, names: ContigNames) -> Self {
+        self.names = names;
+        self
+    }
+
+    // Cache up to the given number of megabases, 0 - no caching
+    pub fn with_cache(self, megabases: u32) -> Self {
+        self.with_blocks(CACHE_BLOCK, megabases as usize)
+    }
+
+    fn with_blocks(mut self, size: u64, capacity: usize) -> Self {
+        assert!(size > 0, "Cache block size must be > 0");
+        self.blocks = match capacity {
+            0 => None,
+            _ => Some(BlockCache { size, capacity, lru: VecDeque::with_capacity(capacity) }),
+        };
+        self
+    }
+
+    pub fn fetched(&self) -> Arc<AtomicU64> {
+        self.fetched.clone()
     }
 }
 
 impl FastaReader for BasicFastaReader {
-    fn fetch(&mut self, contig: &str, range: Range<Position>) {
+    fn fetch(&mut self, contig: &str, range: Range<Position>) -> Result<(), Error> {
         self.cache.clear();
 
-        let iter = self
-            .faidx
-            .fetch_seq(contig, range.start as usize, range.end as usize)
-            .unwrap_or_else(|_| panic!("Failed to fetch sequence for region {}:{}-{}", contig, range.start, range.end))
-            .iter()
-            .map(|x| Nucleotide::from(*x));
-        self.cache.extend(iter);
-
-        let expected = (range.end - range.start) as usize;
-        if self.cache.len() != expected {
-            assert!(self.cache.len() > expected);
-            self.cache.truncate(expected);
+        let contig = self.names.rename(contig);
+        let faidx = match &mut self.faidx {
+            Some(faidx) => faidx,
+            None => self.faidx.insert(Self::open(&self.path)?),
+        };
+        match &mut self.blocks {
+            None => read(faidx, &self.path, contig, range, &self.fetched, &mut self.cache),
+            Some(blocks) => {
+                let length = *self.lengths.get(contig).ok_or_else(|| {
+                    Error::reference(&self.path, format!("contig {} is missing in the FASTA index", contig))
+                })?;
+                blocks.fetch(faidx, &self.path, contig, length, range, &self.fetched, &mut self.cache)
+            }
         }
-        debug_assert_eq!(self.cache.len(), expected);
     }
 
     fn result(&self) -> &[Nucleotide] {
@@ -75,6 +129,357 @@ impl FastaReader for BasicFastaReader {
 
 impl Clone for BasicFastaReader {
     fn clone(&self) -> Self {
-        Self::new(self.path.clone())
+        Self {
+            faidx: None,
+            cache: Vec::new(),
+            path: self.path.clone(),
+            names: self.names.clone(),
+            lengths: self.lengths.clone(),
+            blocks: self.blocks.as_ref().map(|x| BlockCache {
+                size: x.size,
+                capacity: x.capacity,
+                lru: VecDeque::with_capacity(x.capacity),
+            }),
+            fetched: self.fetched.clone(),
+        }
+    }
+}
+
+// Appends the reference sequence for the given range to the buffer
+fn read(
+    faidx: &faidx::Reader,
+    path: &Path,
+    contig: &str,
+    range: Range<Position>,
+    fetched: &AtomicU64,
+    buffer: &mut Vec<Nucleotide>,
+) -> Result<(), Error> {
+    let region = || format!("{}:{}-{}", contig, range.start, range.end);
+    let offset = buffer.len();
+    let iter = faidx
+        .fetch_seq(contig, range.start as usize, range.end as usize)
+        .map_err(|x| Error::reference(path, format!("failed to fetch sequence for region {}: {}", region(), x)))?
+        .iter()
+        .map(|x| Nucleotide::from(*x));
+    buffer.extend(iter);
+
+    let expected = (range.end - range.start) as usize;
+    if buffer.len() - offset < expected {
+        let message = format!("region {} ends past the contig end, truncated FASTA?", region());
+        return Err(Error::reference(path, message));
+    }
+    buffer.truncate(offset + expected);
+    fetched.fetch_add(expected as u64, Ordering::Relaxed);
+    Ok(())
+}
+
+struct Block {
+    contig: String,
+    index: u64,
+    seq: Vec<Nucleotide>,
+}
+
+// LRU cache of fixed-size reference blocks, the most recently used block is the first one
+struct BlockCache {
+    size: u64,
+    capacity: usize,
+    lru: VecDeque<Block>,
+}
+
+impl BlockCache {
+    #[allow(clippy::too_many_arguments)]
+    fn fetch(
+        &mut self,
+        faidx: &faidx::Reader,
+        path: &Path,
+        contig: &str,
+        length: u64,
+        range: Range<Position>,
+        fetched: &AtomicU64,
+        buffer: &mut Vec<Nucleotide>,
+    ) -> Result<(), Error> {
+        if range.start >= range.end {
+            return Ok(());
+        }
+        if range.end > length {
+            let message = format!("region {}:{}-{} ends past the contig end", contig, range.start, range.end);
+            return Err(Error::reference(path, message));
+        }
+        for index in range.start / self.size..=(range.end - 1) / self.size {
+            let start = index * self.size;
+            let (from, to) = (range.start.max(start) - start, range.end.min(start + self.size) - start);
+            let block = self.block(faidx, path, contig, length, index, fetched)?;
+            buffer.extend_from_slice(&block[from as usize..to as usize]);
+        }
+        Ok(())
+    }
+
+    fn block(
+        &mut self,
+        faidx: &faidx::Reader,
+        path: &Path,
+        contig: &str,
+        length: u64,
+        index: u64,
+        fetched: &AtomicU64,
+    ) -> Result<&[Nucleotide], Error> {
+        match self.lru.iter().position(|x| x.index == index && x.contig == contig) {
+            Some(ind) => {
+                let block = self.lru.remove(ind).unwrap();
+                self.lru.push_front(block);
+            }
+            None => {
+                // Reuse the memory of the evicted block
+                let mut block = if self.lru.len() >= self.capacity {
+                    self.lru.pop_back().unwrap()
+                } else {
+                    Block { contig: String::new(), index, seq: Vec::new() }
+                };
+                block.contig.clear();
+                block.contig.push_str(contig);
+                block.index = index;
+                block.seq.clear();
+
+                let start = index * self.size;
+                read(faidx, path, contig, start..length.min(start + self.size), fetched, &mut block.seq)?;
+                self.lru.push_front(block);
+            }
+        }
+        Ok(&self.lru[0].seq)
+    }
+}
+
+struct Indexed {
+    contig: String,
+    length: u64,
+    // Byte offset right after the last base of the contig
+    end: u64,
+}
+
+// Contigs in the order of the FASTA index (.fai)
+fn index(fasta: &Path) -> Result<Vec<Indexed>, Error> {
+    let mut fai = fasta.as_os_str().to_owned();
+    fai.push(".fai");
+    let fai = PathBuf::from(fai);
+
+    let file = File::open(&fai).map_err(|x| Error::io(&fai, x))?;
+    let mut result = Vec::new();
+    for (ind, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|x| Error::io(&fai, x))?;
+        if line.is_empty() {
+            continue;
+        }
+        // NAME, LENGTH, OFFSET, LINEBASES, LINEWIDTH
+        let split: Vec<&str> = line.split('\t').collect();
+        let column = |col: usize, what: &str| -> Result<u64, Error> {
+            split
+                .get(col)
+                .and_then(|x| x.parse().ok())
+                .ok_or_else(|| Error::parse(&fai, ind + 1, format!("invalid contig {}", what)))
+        };
+        let (length, offset, linebases, linewidth) =
+            (column(1, "length")?, column(2, "offset")?, column(3, "line bases")?, column(4, "line width")?);
+        if linebases == 0 {
+            return Err(Error::parse(&fai, ind + 1, "contig line bases must be > 0"));
+        }
+        let end = match length {
+            0 => offset,
+            _ => offset + ((length - 1) / linebases) * linewidth + (length - 1) % linebases + 1,
+        };
+        result.push(Indexed { contig: split[0].to_owned(), length, end });
+    }
+    Ok(result)
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Compression {
+    Plain,
+    // Blocked gzip, random access requires the .gzi index
+    Bgzf,
+    // Regular gzip, can't be indexed
+    Gzip,
+}
+
+// Detected by the magic bytes: gzip header with the BGZF "BC" extra subfield
+fn compression(fasta: &Path) -> Result<Compression, Error> {
+    let mut header = Vec::with_capacity(14);
+    File::open(fasta).and_then(|x| x.take(14).read_to_end(&mut header)).map_err(|x| Error::io(fasta, x))?;
+    Ok(match header.as_slice() {
+        [0x1f, 0x8b, _, flags, .., b'B', b'C'] if flags & 0x04 != 0 => Compression::Bgzf,
+        [0x1f, 0x8b, ..] => Compression::Gzip,
+        _ => Compression::Plain,
+    })
+}
+
+// The FASTA file must contain all indexed sequences. Compressed files can't be checked this way,
+// but bgzipped ones must have the .gzi index to be fetched by htslib
+fn validate(fasta: &Path) -> Result<(), Error> {
+    let index = index(fasta)?;
+    match compression(fasta)? {
+        Compression::Plain => {}
+        Compression::Bgzf => {
+            let mut gzi = fasta.as_os_str().to_owned();
+            gzi.push(".gzi");
+            let gzi = PathBuf::from(gzi);
+            if gzi.is_file() {
+                return Ok(());
+            }
+            let message = format!(
+                "bgzipped FASTA requires the {} index, create it with `samtools faidx {}`",
+                gzi.display(),
+                fasta.display()
+            );
+            return Err(Error::reference(fasta, message));
+        }
+        Compression::Gzip => {
+            let message = "gzip-compressed FASTA can't be indexed, decompress it or recompress with `bgzip`";
+            return Err(Error::reference(fasta, message));
+        }
+    }
+    let size = fasta.metadata().map_err(|x| Error::io(fasta, x))?.len();
+    match index.into_iter().find(|x| x.end > size) {
+        Some(x) => Err(Error::reference(
+            fasta,
+            format!("contig {} ends past the end of the file ({} > {} bytes), truncated FASTA?", x.contig, x.end, size),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Contig names in the order of the FASTA index (.fai)
+pub fn contigs(fasta: &Path) -> Result<Vec<String>, Error> {
+    Ok(index(fasta)?.into_iter().map(|x| x.contig).collect())
+}
+
+pub fn lengths(fasta: &Path) -> Result<HashMap<String, u64>, Error> {
+    Ok(index(fasta)?.into_iter().map(|x| (x.contig, x.length)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use rust_htslib::bgzf;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn contigs() {
+        let dir = TempDir::new().unwrap();
+        let fasta = dir.path().join("genome.fa");
+        fs::write(dir.path().join("genome.fa.fai"), "chr2\t10\t6\t60\t61\nchr1\t20\t23\t60\t61\nchrM\t5\t50\t60\t61\n")
+            .unwrap();
+        assert_eq!(super::contigs(&fasta).unwrap(), vec!["chr2", "chr1", "chrM"]);
+        let expected = HashMap::from([("chr2".to_owned(), 10), ("chr1".to_owned(), 20), ("chrM".to_owned(), 5)]);
+        assert_eq!(super::lengths(&fasta).unwrap(), expected);
+    }
+
+    #[test]
+    fn truncated() {
+        let dir = TempDir::new().unwrap();
+        let fasta = dir.path().join("genome.fa");
+        fs::write(&fasta, ">chr1\nACGTACGTAC\nACGTA\n>chr2\nACG").unwrap();
+        fs::write(dir.path().join("genome.fa.fai"), "chr1\t15\t6\t10\t11\nchr2\t10\t29\t10\t11\n").unwrap();
+
+        let err = BasicFastaReader::new(fasta.clone()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("{}: contig chr2 ends past the end of the file (39 > 32 bytes), truncated FASTA?", fasta.display())
+        );
+
+        fs::write(dir.path().join("genome.fa.fai"), "chr1\t15\t6\t10\t11\nchr2\t3\t29\t10\t11\n").unwrap();
+        assert!(validate(&fasta).is_ok());
+    }
+
+    #[test]
+    fn compressed() {
+        let dir = TempDir::new().unwrap();
+        let fasta = dir.path().join("genome.fa.gz");
+        let mut writer = bgzf::Writer::from_path(&fasta).unwrap();
+        writer.write_all(b">chr1\nACGTACGTAC\n").unwrap();
+        drop(writer);
+        fs::write(dir.path().join("genome.fa.gz.fai"), "chr1\t10\t6\t10\t11\n").unwrap();
+        assert_eq!(compression(&fasta).unwrap(), Compression::Bgzf);
+
+        let err = BasicFastaReader::new(fasta.clone()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}: bgzipped FASTA requires the {}.gzi index, create it with `samtools faidx {}`",
+                fasta.display(),
+                fasta.display(),
+                fasta.display()
+            )
+        );
+
+        // Regular gzip header without the BGZF extra field
+        fs::write(&fasta, [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff]).unwrap();
+        assert_eq!(compression(&fasta).unwrap(), Compression::Gzip);
+        assert!(validate(&fasta).err().unwrap().to_string().contains("recompress with `bgzip`"));
+    }
+
+    #[test]
+    fn cache() {
+        let dir = TempDir::new().unwrap();
+        let fasta = dir.path().join("genome.fa");
+        fs::write(&fasta, ">chr1\nACGTACGTAC\nGGCCA\n>chr2\nTTGCA\n").unwrap();
+        fs::write(dir.path().join("genome.fa.fai"), "chr1\t15\t6\t10\t11\nchr2\t5\t29\t10\t11\n").unwrap();
+
+        let mut plain = BasicFastaReader::new(fasta.clone()).unwrap();
+        let mut cached = BasicFastaReader::new(fasta).unwrap().with_blocks(4, 2);
+        let regions = [
+            ("chr1", 0..15),
+            ("chr1", 2..7),
+            ("chr1", 5..6),
+            ("chr1", 6..6),
+            ("chr2", 0..5),
+            ("chr1", 11..15),
+            ("chr1", 3..10),
+            ("chr2", 1..3),
+        ];
+        for (contig, range) in regions {
+            plain.fetch(contig, range.clone()).unwrap();
+            cached.fetch(contig, range.clone()).unwrap();
+            assert_eq!(cached.result(), plain.result(), "{}:{:?}", contig, range);
+            assert_eq!(cached.result().len(), (range.end - range.start) as usize);
+        }
+        assert_eq!(plain.fetched().load(Ordering::Relaxed), 15 + 5 + 1 + 5 + 4 + 7 + 2);
+        // Blocks read from the file (the cache holds 2 blocks): chr1 [0, 4, 8, 12]; chr1 [0, 4]; chr1:5-6 is cached;
+        // chr2 [0, 4]; chr1 [8, 12]; chr1 [0, 4, 8]; chr2 [0]
+        assert_eq!(cached.fetched().load(Ordering::Relaxed), 15 + 8 + 5 + 7 + 12 + 4);
+
+        // Clones share the counter but not the cached blocks
+        let mut clone = cached.clone();
+        clone.fetch("chr1", 4..6).unwrap();
+        assert_eq!(clone.result(), &[Nucleotide::A, Nucleotide::C]);
+        assert_eq!(cached.fetched().load(Ordering::Relaxed), 15 + 8 + 5 + 7 + 12 + 4 + 4);
+    }
+
+    #[test]
+    fn fetch_errors() {
+        let dir = TempDir::new().unwrap();
+        let fasta = dir.path().join("genome.fa");
+        fs::write(&fasta, ">chr1\nACGTACGTAC\nGGCCA\n").unwrap();
+        fs::write(dir.path().join("genome.fa.fai"), "chr1\t15\t6\t10\t11\n").unwrap();
+
+        for mut reader in [
+            BasicFastaReader::new(fasta.clone()).unwrap(),
+            BasicFastaReader::new(fasta.clone()).unwrap().with_blocks(4, 2),
+        ] {
+            let err = reader.fetch("chr1", 10..20).err().unwrap();
+            assert!(err.to_string().starts_with(&format!("{}: ", fasta.display())), "{}", err);
+            assert!(reader.fetch("chr2", 0..1).is_err());
+            // Errors are recoverable
+            reader.fetch("chr1", 0..2).unwrap();
+            assert_eq!(reader.result(), &[Nucleotide::A, Nucleotide::C]);
+        }
+
+        // Clones reopen the file lazily => failures are reported by the fetch
+        let reader = BasicFastaReader::new(fasta.clone()).unwrap();
+        fs::remove_file(&fasta).unwrap();
+        let mut clone = reader.clone();
+        assert!(clone.fetch("chr1", 0..2).is_err());
     }
 }
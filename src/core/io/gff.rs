@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use bio_types::genome::Position;
+
+use crate::error::Error;
+
+// Single GFF3/GTF record, only fields used by REAT. Coordinates are converted to 0-based half-open intervals
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Record<'a> {
+    pub contig: &'a str,
+    pub feature: &'a str,
+    pub start: Position,
+    pub end: Position,
+    // "+", "-" or anything else for unstranded features
+    pub strand: &'a str,
+    attributes: &'a str,
+}
+
+impl<'a> Record<'a> {
+    // None for comments & empty lines, malformed records are reported with the file name & the 1-based line number
+    pub fn parse(line: &'a str, path: &Path, lineno: usize) -> Result<Option<Self>, Error> {
+        let line = line.trim_end_matches(&['\n', '\r']);
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let split: Vec<&str> = line.splitn(9, '\t').collect();
+        if split.len() < 7 {
+            let message = format!("expected at least 7 tab-separated GFF/GTF columns, got {}", split.len());
+            return Err(Error::parse(path, lineno, message));
+        }
+
+        let coordinate = |x: &str| -> Result<Position, Error> {
+            match x.parse() {
+                Ok(x) if x > 0 => Ok(x),
+                _ => Err(Error::parse(path, lineno, format!("invalid GFF/GTF coordinate \"{}\"", x))),
+            }
+        };
+        Ok(Some(Self {
+            contig: split[0],
+            feature: split[2],
+            start: coordinate(split[3])? - 1,
+            end: coordinate(split[4])?,
+            strand: split[6],
+            attributes: split.get(8).copied().unwrap_or(""),
+        }))
+    }
+
+    // GFF3: key=value;key=value, GTF: key "value"; key "value";
+    pub fn attribute(&self, key: &str) -> Option<&'a str> {
+        self.attributes.split(';').find_map(|field| {
+            let field = field.trim();
+            let (name, value) = field.split_once('=').or_else(|| field.split_once(' '))?;
+            (name.trim() == key).then(|| value.trim().trim_matches('"'))
+        })
+    }
+
+    // Gene name (GTF, Ensembl GFF3) / Name (GFF3) / gene_id (GTF) / ID (GFF3), whichever comes first
+    pub fn gene_name(&self) -> Option<&'a str> {
+        ["gene_name", "Name", "gene_id", "ID"].into_iter().find_map(|key| self.attribute(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> Option<Record> {
+        Record::parse(line, Path::new("genes.gff3"), 1).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert!(record("##gff-version 3\n").is_none());
+        assert!(record("\n").is_none());
+
+        let gff3 = record("1\thavana\tgene\t11869\t14409\t.\t+\t.\tID=gene:ENSG1;Name=DDX11L1;biotype=lnc\n");
+        let gff3 = gff3.unwrap();
+        assert_eq!((gff3.contig, gff3.feature, gff3.start, gff3.end, gff3.strand), ("1", "gene", 11868, 14409, "+"));
+        assert_eq!(gff3.gene_name(), Some("DDX11L1"));
+        assert_eq!(gff3.attribute("biotype"), Some("lnc"));
+        assert_eq!(gff3.attribute("gene_name"), None);
+
+        let gtf = "chr1\tHAVANA\texon\t12010\t12057\t.\t-\t.\tgene_id \"ENSG1\"; gene_name \"DDX11L1\"; level 2;";
+        let gtf = record(gtf).unwrap();
+        assert_eq!((gtf.contig, gtf.feature, gtf.start, gtf.end, gtf.strand), ("chr1", "exon", 12009, 12057, "-"));
+        assert_eq!(gtf.gene_name(), Some("DDX11L1"));
+        assert_eq!(gtf.attribute("gene_id"), Some("ENSG1"));
+        assert_eq!(gtf.attribute("level"), Some("2"));
+
+        // No attributes at all
+        let bare = record("chr1\t.\tgene\t1\t10\t.\t.").unwrap();
+        assert_eq!((bare.start, bare.end, bare.strand, bare.gene_name()), (0, 10, ".", None));
+    }
+
+    #[test]
+    fn malformed() {
+        let path = Path::new("genes.gtf");
+        for (line, message) in [
+            ("chr1	.	gene	1	10", "genes.gtf:3: expected at least 7 tab-separated GFF/GTF columns, got 5"),
+            ("chr1	.	gene	1e3	10	.	+", "genes.gtf:3: invalid GFF/GTF coordinate \"1e3\""),
+            ("chr1	.	gene	0	10	.	+", "genes.gtf:3: invalid GFF/GTF coordinate \"0\""),
+        ] {
+            assert_eq!(Record::parse(line, path, 3).unwrap_err().to_string(), message);
+        }
+    }
+}
@@ -1,44 +1,415 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
 
 use bio_types::genome::Interval;
 use itertools::{zip, Itertools};
+use rust_htslib::bam;
+use rust_htslib::bam::index;
+use rust_htslib::bam::HeaderView;
 pub use rust_htslib::bam::IndexedReader;
-use rust_htslib::bam::Read;
+use rust_htslib::bam::{Read, Record};
+use rust_htslib::htslib;
 
-pub fn contigs(hts: &[impl AsRef<Path>]) -> Vec<Interval> {
-    let mut contigs = HashMap::new();
+use crate::core::rpileup::ncounter::filters::ReadsDump;
+use crate::error::Error;
+
+// Path of the standard input, it can be read only sequentially
+pub const STDIN: &str = "-";
+
+// Index => explicit path to the index, otherwise it's looked up by htslib next to the file.
+// Reference => assembly to decode CRAM files, it's ignored for other formats
+pub fn open(file: &Path, index: Option<&Path>, reference: Option<&Path>) -> Result<IndexedReader, Error> {
+    let mut reader = match index {
+        None => IndexedReader::from_path(file).map_err(|x| {
+            Error::hts(file, format!("{} (BAM file is not indexed or there is no permission to read it?)", x))
+        }),
+        Some(index) => IndexedReader::from_path_and_index(file, index).map_err(|x| {
+            Error::hts(file, format!("{} (failed to open the file with the index {})", x, index.display()))
+        }),
+    }?;
+    if is_cram(&reader) {
+        let reference = cram_reference(file, reference)?;
+        reader.set_reference(reference).map_err(|x| reference_error(file, reference, x))?;
+    }
+    Ok(reader)
+}
+
+// CRAM records are encoded relative to the reference assembly => it's required to decode them
+pub fn is_cram(reader: &impl Read) -> bool {
+    // SAFETY: htsfile() of an opened reader is a valid htsFile owned by the reader, the borrow keeps it alive.
+    // The format is detected once by htslib when the file is opened and is only read here
+    unsafe { (*reader.htsfile()).format.format == htslib::htsExactFormat_cram }
+}
+
+// Same for files that are not opened yet, the standard input is never peeked
+pub fn is_cram_file(file: &Path) -> Result<bool, Error> {
+    if file == Path::new(STDIN) {
+        return Ok(false);
+    }
+    let reader = bam::Reader::from_path(file).map_err(|x| Error::hts(file, x.to_string()))?;
+    Ok(is_cram(&reader))
+}
+
+pub fn cram_reference<'a>(file: &Path, reference: Option<&'a Path>) -> Result<&'a Path, Error> {
+    reference.ok_or_else(|| Error::hts(file, "CRAM files can be decoded only with the reference assembly"))
+}
+
+pub fn reference_error(file: &Path, reference: &Path, err: impl Display) -> Error {
+    Error::hts(file, format!("failed to use {} to decode the CRAM file: {}", reference.display(), err))
+}
 
+// Only headers are required => files are opened without indexes
+pub fn contigs(hts: &[impl AsRef<Path>]) -> Result<Vec<Interval>, Error> {
     let readers = hts
         .iter()
         .map(|file| {
             let file = file.as_ref();
-            IndexedReader::from_path(file).unwrap_or_else(|_| {
-                panic!(
-                    "Failed to open file {}\n\
-                        Possible reasons: BAM file was not indexed (samtools index); you don't have read permissions",
-                    file.display()
-                )
-            })
+            bam::Reader::from_path(file)
+                .map_err(|x| Error::hts(file, format!("{} (there is no permission to read the file?)", x)))
         })
-        .collect_vec();
+        .collect::<Result<Vec<_>, _>>()?;
     let headers = readers.iter().map(|x| x.header()).collect_vec();
+    header_contigs(zip(hts.iter().map(|x| x.as_ref()), headers))
+}
 
-    for (file, header) in zip(hts, headers) {
+// Contigs of all headers, the same contig must have the same length everywhere
+pub fn header_contigs<'a>(
+    headers: impl IntoIterator<Item = (&'a Path, &'a HeaderView)>,
+) -> Result<Vec<Interval>, Error> {
+    let mut contigs = HashMap::new();
+    for (file, header) in headers {
         for tid in 0..header.target_count() {
             let name = String::from_utf8_lossy(header.tid2name(tid));
-            let length = header
-                .target_len(tid)
-                .unwrap_or_else(|| panic!("Failed to parse header for {}", file.as_ref().display()));
+            let length = header.target_len(tid).ok_or_else(|| Error::hts(file, "failed to parse the header"))?;
 
             let stored = contigs.entry(name.clone()).or_insert(length);
-            assert_eq!(
-                *stored, length,
-                "BAM headers must contain equivalent contigs, {} two lengths {} != {}",
-                name, length, stored
-            );
+            if *stored != length {
+                return Err(Error::hts(
+                    file,
+                    format!(
+                        "BAM headers must contain equivalent contigs, {} has two lengths {} != {}",
+                        name, length, stored
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(contigs.into_iter().map(|(name, length)| Interval::new(name.into(), 0..length)).collect())
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum IndexStatus {
+    Missing,
+    // Index is older than the alignment file
+    Outdated(PathBuf),
+    Ok(PathBuf),
+}
+
+// Index files that htslib looks up next to the alignment file: file.bam.bai, file.bai, etc
+fn index_candidates(file: &Path) -> Vec<PathBuf> {
+    let mut result = Vec::with_capacity(6);
+    for ext in ["bai", "csi", "crai"] {
+        let mut appended = OsString::from(file.as_os_str());
+        appended.push(".");
+        appended.push(ext);
+        result.push(PathBuf::from(appended));
+        result.push(file.with_extension(ext));
+    }
+    result
+}
+
+// BAI indexes take precedence over CSI ones, same as in htslib
+pub fn find_index(file: &Path) -> Option<PathBuf> {
+    index_candidates(file).into_iter().find(|x| x.is_file())
+}
+
+// Index => explicit path to the index, otherwise it's looked up next to the file
+pub fn index_status(file: &Path, index: Option<&Path>) -> IndexStatus {
+    let index = match index.map(Path::to_path_buf).or_else(|| find_index(file)) {
+        Some(index) if index.is_file() => index,
+        _ => return IndexStatus::Missing,
+    };
+    let modified = |path: &Path| path.metadata().and_then(|x| x.modified()).ok();
+    match (modified(file), modified(&index)) {
+        (Some(file), Some(idx)) if idx < file => IndexStatus::Outdated(index),
+        _ => IndexStatus::Ok(index),
+    }
+}
+
+// Same as samtools index: BAI for BAM files, CRAI for CRAM files
+pub fn build_index(file: &Path, threads: u32) -> Result<(), Error> {
+    index::build(file, None, index::Type::Bai, threads)
+        .map_err(|err| Error::hts(file, format!("failed to build the index: {}", err)))
+}
+
+// Window size of the BAI linear index
+pub const BAI_WINDOW: u64 = 1 << 14;
+// Pseudo-bin holding the contig offsets & the number of mapped / unmapped reads
+const BAI_PSEUDO_BIN: u32 = 37450;
+
+// Reads in each BAI_WINDOW of a contig estimated from the index, without decoding any reads
+#[derive(Clone, PartialEq, Debug)]
+pub struct IndexedDensity {
+    pub contig: String,
+    pub mapped: u64,
+    // Compressed bytes of reads starting in each window
+    pub bytes: Vec<u64>,
+}
+
+// None if the index is missing or is not a BAI (CSI & CRAI indexes don't have the linear index)
+pub fn indexed_density(file: &Path, index: Option<&Path>) -> Result<Option<Vec<IndexedDensity>>, Error> {
+    let index = match index_status(file, index) {
+        IndexStatus::Ok(x) | IndexStatus::Outdated(x) => x,
+        IndexStatus::Missing => return Ok(None),
+    };
+    let data = std::fs::read(&index).map_err(|x| Error::io(&index, x))?;
+    let linear = match parse_bai(&data) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let reader = bam::Reader::from_path(file).map_err(|x| Error::hts(file, x.to_string()))?;
+    let header = reader.header();
+    if linear.len() != header.target_count() as usize {
+        return Ok(None);
+    }
+    let density = linear
+        .into_iter()
+        .enumerate()
+        .map(|(tid, (mapped, bytes))| IndexedDensity {
+            contig: String::from_utf8_lossy(header.tid2name(tid as u32)).into_owned(),
+            mapped,
+            bytes,
+        })
+        .collect();
+    Ok(Some(density))
+}
+
+// Little-endian fields of binary index files
+struct BinaryReader<'a>(&'a [u8]);
+
+impl BinaryReader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
         }
+        let (head, tail) = self.0.split_at(N);
+        self.0 = tail;
+        head.try_into().ok()
     }
 
-    contigs.into_iter().map(|(name, length)| Interval::new(name.into(), 0..length)).collect()
+    fn i32(&mut self) -> Option<i32> {
+        self.take().map(i32::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+}
+
+// (mapped reads, compressed bytes in each window) for each contig, None for malformed files
+fn parse_bai(data: &[u8]) -> Option<Vec<(u64, Vec<u64>)>> {
+    let mut data = BinaryReader(data);
+    if data.take::<4>()? != *b"BAI\x01" {
+        return None;
+    }
+    let contigs = data.i32()?;
+    let mut result = Vec::with_capacity(contigs.max(0) as usize);
+    for _ in 0..contigs {
+        let (mut mapped, mut end) = (0, None);
+        for _ in 0..data.i32()? {
+            let (bin, chunks) = (data.u32()?, data.i32()?);
+            if bin == BAI_PSEUDO_BIN && chunks == 2 {
+                let (_, refend) = (data.u64()?, data.u64()?);
+                (mapped, end) = (data.u64()?, Some(refend));
+                data.u64()?;
+            } else {
+                for _ in 0..chunks {
+                    data.u64()?;
+                    data.u64()?;
+                }
+            }
+        }
+        let windows = data.i32()?;
+        // Compressed offsets of the first read in each window. Empty windows are either zero or repeat the previous
+        // offset => offsets are made monotonic
+        let offsets = (0..windows).map(|_| data.u64().map(|x| x >> 16)).collect::<Option<Vec<u64>>>()?;
+        let mut previous = offsets.iter().find(|x| **x > 0).copied().unwrap_or(0);
+        let mut starts = Vec::with_capacity(offsets.len() + 1);
+        for offset in offsets.into_iter().chain(end.map(|x| x >> 16)) {
+            previous = previous.max(offset);
+            starts.push(previous);
+        }
+        let bytes = starts.windows(2).map(|x| x[1] - x[0]).collect();
+        result.push((mapped, bytes));
+    }
+    Some(result)
+}
+
+// Reads checked by the reads filter, saved to <prefix>.accepted.bam & <prefix>.rejected.bam for debugging.
+// All inputs must have the same header => records are written as is. bam::Writer can't be shared between threads
+// (it keeps the header in Rc) => copies of records are sent to a dedicated thread that owns both writers
+pub struct BamDump {
+    // Accepted & rejected reads
+    paths: [PathBuf; 2],
+    // None once the dump is finished
+    sender: Mutex<Option<SyncSender<(Record, bool)>>>,
+    writer: Mutex<Option<JoinHandle<Result<(), Error>>>>,
+}
+
+// Max number of records waiting to be written, workers are blocked once it's reached
+const DUMP_QUEUE_SIZE: usize = 4096;
+
+impl BamDump {
+    pub fn new(prefix: &str, hts: &[impl AsRef<Path>]) -> Result<Self, Error> {
+        let readers = hts
+            .iter()
+            .map(|x| bam::Reader::from_path(x.as_ref()).map_err(|err| Error::hts(x, err.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let targets = |header: &HeaderView| {
+            (0..header.target_count()).map(|tid| (header.tid2name(tid).to_vec(), header.target_len(tid))).collect_vec()
+        };
+        let template = readers.first().expect("No input files to dump reads from").header();
+        for (file, reader) in zip(hts, &readers) {
+            if targets(reader.header()) != targets(template) {
+                return Err(Error::hts(
+                    file,
+                    "reads can be dumped only for input files with identical headers (same contigs in the same order)",
+                ));
+            }
+        }
+
+        let header = bam::Header::from_template(template);
+        let paths = ["accepted", "rejected"].map(|what| PathBuf::from(format!("{}.{}.bam", prefix, what)));
+        let (sender, receiver) = mpsc::sync_channel::<(Record, bool)>(DUMP_QUEUE_SIZE);
+        let (opened, isopened) = mpsc::channel();
+        let writer = {
+            let paths = paths.clone();
+            thread::spawn(move || {
+                let open = |path: &PathBuf| {
+                    bam::Writer::from_path(path, &header, bam::Format::Bam)
+                        .map_err(|err| Error::hts(path, err.to_string()))
+                };
+                let mut writers = match open(&paths[0]).and_then(|accepted| Ok([accepted, open(&paths[1])?])) {
+                    Ok(writers) => {
+                        opened.send(Ok(())).unwrap();
+                        writers
+                    }
+                    Err(err) => {
+                        opened.send(Err(err)).unwrap();
+                        return Ok(());
+                    }
+                };
+                // Closed channel => all reads are checked
+                for (record, accepted) in receiver {
+                    let ind = if accepted { 0 } else { 1 };
+                    writers[ind].write(&record).map_err(|err| Error::hts(&paths[ind], err.to_string()))?;
+                }
+                Ok(())
+            })
+        };
+        isopened.recv().expect("Reads dump writer failed to start")?;
+        Ok(Self { paths, sender: Mutex::new(Some(sender)), writer: Mutex::new(Some(writer)) })
+    }
+}
+
+impl ReadsDump<Record> for BamDump {
+    fn dump(&self, record: &Record, accepted: bool) -> Result<(), Error> {
+        let sent = match self.sender.lock().unwrap().as_ref() {
+            Some(sender) => sender.send((record.clone(), accepted)).is_ok(),
+            None => false,
+        };
+        if sent {
+            return Ok(());
+        }
+        // The writer has stopped => report its failure
+        self.finish()?;
+        let path = &self.paths[if accepted { 0 } else { 1 }];
+        Err(Error::hts(path, "reads can't be dumped after the dump is finished"))
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        // The writer saves the remaining records & exits once the channel is closed
+        *self.sender.lock().unwrap() = None;
+        match self.writer.lock().unwrap().take() {
+            Some(writer) => writer.join().expect("Reads dump writer panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Interrupted runs keep the reads dumped so far, failures are reported only by finish
+impl Drop for BamDump {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bai() {
+        let mut data = b"BAI\x01".to_vec();
+        let mut push = |x: &[u8]| data.extend_from_slice(x);
+        // Single contig with a regular bin, the pseudo-bin & 4 windows, the first one is empty
+        push(&1i32.to_le_bytes());
+        push(&2i32.to_le_bytes());
+        push(&4681u32.to_le_bytes());
+        push(&1i32.to_le_bytes());
+        push(&(100u64 << 16).to_le_bytes());
+        push(&(900u64 << 16).to_le_bytes());
+        push(&BAI_PSEUDO_BIN.to_le_bytes());
+        push(&2i32.to_le_bytes());
+        push(&(100u64 << 16).to_le_bytes());
+        push(&((900u64 << 16) | 15).to_le_bytes());
+        push(&42u64.to_le_bytes());
+        push(&3u64.to_le_bytes());
+        push(&4i32.to_le_bytes());
+        for offset in [0u64, 100, 100, 700] {
+            push(&((offset << 16) | 7).to_le_bytes());
+        }
+        assert_eq!(super::parse_bai(&data), Some(vec![(42, vec![0, 0, 600, 200])]));
+
+        // Truncated & foreign files
+        assert_eq!(super::parse_bai(&data[..data.len() - 1]), None);
+        assert_eq!(super::parse_bai(b"CSI\x01"), None);
+    }
+
+    #[test]
+    fn index_status() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("reads.bam");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(super::index_status(&file, None), IndexStatus::Missing);
+
+        // Unrelated files are ignored
+        std::fs::write(dir.path().join("reads.bam.tbi"), b"").unwrap();
+        assert_eq!(super::index_status(&file, None), IndexStatus::Missing);
+
+        // Preferred candidates shadow the rest
+        for index in ["reads.bam.csi", "reads.bai", "reads.bam.bai"] {
+            let index = dir.path().join(index);
+            std::fs::write(&index, b"").unwrap();
+            assert_eq!(super::index_status(&file, None), IndexStatus::Ok(index));
+        }
+
+        // Explicit indexes are never looked up
+        let explicit = dir.path().join("indexes").join("reads.bam.csi");
+        assert_eq!(super::index_status(&file, Some(&explicit)), IndexStatus::Missing);
+        std::fs::create_dir(explicit.parent().unwrap()).unwrap();
+        std::fs::write(&explicit, b"").unwrap();
+        assert_eq!(super::index_status(&file, Some(&explicit)), IndexStatus::Ok(explicit));
+    }
 }
@@ -1,5 +1,11 @@
 pub mod bed;
+pub mod contigs;
 pub mod fasta;
+pub mod gff;
 pub mod hts;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod table;
 pub mod utils;
 pub mod vcf;
+pub mod whitelist;
@@ -0,0 +1,614 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float32Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde::ser::{Impossible, SerializeStruct};
+use serde::{ser, Serialize, Serializer};
+
+use super::table::{ColumnType, Schema};
+use super::utils::FinishWrite;
+
+// Rows are buffered and written as a single row group
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+// Parquet table with a fixed schema. Rows are serialized field by field into typed column buffers.
+pub struct ParquetWriter<W: FinishWrite> {
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    rows: usize,
+    writer: Option<ArrowWriter<Shared<W>>>,
+    // The file must be finalized after the Parquet footer is written, see finish
+    file: Shared<W>,
+}
+
+impl<W: FinishWrite> ParquetWriter<W> {
+    pub fn new(file: W, schema: &Schema) -> Self {
+        let fields = schema
+            .iter()
+            .map(|(name, ctype)| {
+                let dtype = match ctype {
+                    ColumnType::Str => DataType::Utf8,
+                    ColumnType::UInt => DataType::UInt64,
+                    ColumnType::Float => DataType::Float32,
+                };
+                Field::new(name, dtype, false)
+            })
+            .collect();
+        let columns = schema.iter().map(|(name, ctype)| Column::new(name.clone(), *ctype)).collect();
+
+        let arrow = Arc::new(ArrowSchema::new(fields));
+        let file = Shared(Rc::new(RefCell::new(file)));
+        let writer = ArrowWriter::try_new(file.clone(), arrow.clone(), None)
+            .unwrap_or_else(|x| panic!("Failed to initialize the Parquet writer: {}", x));
+        Self { schema: arrow, columns, rows: 0, writer: Some(writer), file }
+    }
+
+    pub fn serialize<S: Serialize>(&mut self, row: S) -> io::Result<()> {
+        row.serialize(RowSerializer { columns: &mut self.columns, next: 0 }).map_err(invalid)?;
+        self.rows += 1;
+        if self.rows >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(invalid)?;
+        }
+        self.file.0.borrow_mut().finish()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let writer = self.writer.as_mut().expect("Parquet table is already finished");
+        let arrays = self.columns.iter_mut().map(|x| x.take()).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays).map_err(invalid)?;
+        writer.write(&batch).map_err(invalid)?;
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+fn invalid<E: Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+// The same file is owned by the Arrow writer & the table itself
+struct Shared<W>(Rc<RefCell<W>>);
+
+impl<W> Clone for Shared<W> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<W: Write> Write for Shared<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+enum Values {
+    Str(Vec<String>),
+    UInt(Vec<u64>),
+    Float(Vec<f32>),
+}
+
+struct Column {
+    name: String,
+    values: Values,
+}
+
+impl Column {
+    fn new(name: String, ctype: ColumnType) -> Self {
+        let values = match ctype {
+            ColumnType::Str => Values::Str(Vec::new()),
+            ColumnType::UInt => Values::UInt(Vec::new()),
+            ColumnType::Float => Values::Float(Vec::new()),
+        };
+        Self { name, values }
+    }
+
+    // Integers are accepted by float columns, e.g. rounded weighted counts. Missing floats are stored as NaN
+    fn push(&mut self, cell: Cell) -> Result<(), Error> {
+        match (&mut self.values, cell) {
+            (Values::Str(x), Cell::Str(v)) => x.push(v),
+            (Values::UInt(x), Cell::UInt(v)) => x.push(v),
+            (Values::Float(x), Cell::Float(v)) => x.push(v),
+            (Values::Float(x), Cell::UInt(v)) => x.push(v as f32),
+            (Values::Float(x), Cell::Missing) => x.push(f32::NAN),
+            (_, cell) => return Err(Error(format!("Unexpected value {:?} for the column {}", cell, self.name))),
+        }
+        Ok(())
+    }
+
+    fn take(&mut self) -> ArrayRef {
+        match &mut self.values {
+            Values::Str(x) => Arc::new(StringArray::from(std::mem::take(x))),
+            Values::UInt(x) => Arc::new(UInt64Array::from(std::mem::take(x))),
+            Values::Float(x) => Arc::new(Float32Array::from(std::mem::take(x))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, Error> {
+    Err(Error(format!("Parquet tables support only flat rows, got {}", what)))
+}
+
+// Rows are structs, each field is appended to the next column
+struct RowSerializer<'a> {
+    columns: &'a mut [Column],
+    next: usize,
+}
+
+impl<'a> SerializeStruct for RowSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        let column =
+            self.columns.get_mut(self.next).ok_or_else(|| Error(format!("Column {} is missing in the schema", key)))?;
+        debug_assert_eq!(column.name, key);
+        column.push(value.serialize(CellSerializer)?)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        match self.next == self.columns.len() {
+            true => Ok(()),
+            false => Err(Error(format!("Expected {} columns, got {}", self.columns.len(), self.next))),
+        }
+    }
+}
+
+impl<'a> Serializer for RowSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Error> {
+        Ok(self)
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<(), Error> {
+        unsupported("bool")
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_char(self, _: char) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_str(self, _: &str) -> Result<(), Error> {
+        unsupported("a scalar")
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Error> {
+        unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        unsupported("an option")
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<(), Error> {
+        unsupported("an option")
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        unsupported("a unit")
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> {
+        unsupported("a unit")
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str) -> Result<(), Error> {
+        unsupported("an enum")
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Error> {
+        unsupported("an enum")
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        unsupported("a sequence")
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+        unsupported("a tuple")
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        unsupported("a tuple")
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        unsupported("an enum")
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        unsupported("a map")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        unsupported("an enum")
+    }
+}
+
+#[derive(Debug)]
+enum Cell {
+    Str(String),
+    UInt(u64),
+    Float(f32),
+    Missing,
+}
+
+// Single field of the row
+struct CellSerializer;
+
+impl Serializer for CellSerializer {
+    type Ok = Cell;
+    type Error = Error;
+    type SerializeSeq = Impossible<Cell, Error>;
+    type SerializeTuple = Impossible<Cell, Error>;
+    type SerializeTupleStruct = Impossible<Cell, Error>;
+    type SerializeTupleVariant = Impossible<Cell, Error>;
+    type SerializeMap = Impossible<Cell, Error>;
+    type SerializeStruct = Impossible<Cell, Error>;
+    type SerializeStructVariant = Impossible<Cell, Error>;
+
+    fn serialize_bool(self, _: bool) -> Result<Cell, Error> {
+        unsupported("bool")
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Cell, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Cell, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Cell, Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Cell, Error> {
+        u64::try_from(v).map(Cell::UInt).or_else(|_| unsupported("a negative integer"))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Cell, Error> {
+        Ok(Cell::UInt(v as u64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Cell, Error> {
+        Ok(Cell::UInt(v as u64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Cell, Error> {
+        Ok(Cell::UInt(v as u64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Cell, Error> {
+        Ok(Cell::UInt(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Cell, Error> {
+        Ok(Cell::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Cell, Error> {
+        Ok(Cell::Float(v as f32))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Cell, Error> {
+        Ok(Cell::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Cell, Error> {
+        Ok(Cell::Str(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<Cell, Error> {
+        unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<Cell, Error> {
+        Ok(Cell::Missing)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Cell, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Cell, Error> {
+        unsupported("a unit")
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Cell, Error> {
+        unsupported("a unit")
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Cell, Error> {
+        Ok(Cell::Str(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, value: &T) -> Result<Cell, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Cell, Error> {
+        unsupported("an enum")
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        unsupported("a sequence")
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+        unsupported("a tuple")
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        unsupported("a tuple")
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        unsupported("an enum")
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        unsupported("a map")
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Error> {
+        unsupported("a nested struct")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        unsupported("an enum")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::iter::zip;
+
+    use arrow::util::display::array_value_to_string;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde::ser::SerializeStruct;
+    use tempfile::TempDir;
+
+    use crate::core::io::table::{RowWriter, TableFormat, TableWriter};
+
+    use super::*;
+
+    struct Row(&'static str, u32, f32);
+
+    impl Serialize for Row {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut state = serializer.serialize_struct("Row", 3)?;
+            state.serialize_field("contig", self.0)?;
+            state.serialize_field("pos", &self.1)?;
+            state.serialize_field("freq", &self.2)?;
+            state.end()
+        }
+    }
+
+    fn schema() -> Schema {
+        vec![("contig".into(), ColumnType::Str), ("pos".into(), ColumnType::UInt), ("freq".into(), ColumnType::Float)]
+    }
+
+    // Rows as strings, column names are prepended
+    fn read(path: &std::path::Path) -> Vec<Vec<String>> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            if rows.is_empty() {
+                rows.push(batch.schema().fields().iter().map(|x| x.name().clone()).collect());
+            }
+            for row in 0..batch.num_rows() {
+                rows.push(batch.columns().iter().map(|x| array_value_to_string(x, row).unwrap()).collect());
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn roundtrip() {
+        let tmp = TempDir::new().unwrap();
+        let (parquet, tsv) = (tmp.path().join("table.parquet"), tmp.path().join("table.tsv"));
+
+        let mut writers = [
+            TableWriter::new(&TableFormat::Parquet(schema()), BufWriter::new(File::create(&parquet).unwrap())),
+            TableWriter::new(&TableFormat::Delimited(b'\t'), BufWriter::new(File::create(&tsv).unwrap())),
+        ];
+        // Several row groups
+        for pos in 0..(ROW_GROUP_SIZE as u32 + 10) {
+            let contig = if pos % 2 == 0 { "chr1" } else { "MT" };
+            for writer in &mut writers {
+                writer.serialize(Row(contig, pos, (pos % 16) as f32 / 8.0)).unwrap();
+            }
+        }
+        for writer in &mut writers {
+            writer.finish().unwrap();
+        }
+
+        let rows = read(&parquet);
+        let expected: Vec<Vec<String>> =
+            fs::read_to_string(&tsv).unwrap().lines().map(|x| x.split('\t').map(|x| x.to_owned()).collect()).collect();
+        assert_eq!(rows.len(), ROW_GROUP_SIZE + 11);
+        assert_eq!(rows.len(), expected.len());
+        assert_eq!(rows[0], expected[0]);
+        for (row, expected) in zip(&rows[1..], &expected[1..]) {
+            assert_eq!(row[..2], expected[..2]);
+            // Floats are formatted differently, e.g. 1 vs 1.0
+            assert_eq!(row[2].parse::<f32>().unwrap(), expected[2].parse::<f32>().unwrap());
+        }
+    }
+
+    #[test]
+    fn schema_mismatch() {
+        let tmp = TempDir::new().unwrap();
+        let file = BufWriter::new(File::create(tmp.path().join("table.parquet")).unwrap());
+
+        // Wrong type
+        let mut schema = schema();
+        schema[1].1 = ColumnType::Str;
+        let mut writer = ParquetWriter::new(file, &schema);
+        assert!(writer.serialize(Row("chr1", 1, 0.5)).is_err());
+
+        // Not a struct
+        assert!(writer.serialize(1u32).is_err());
+    }
+
+    #[test]
+    fn missing() {
+        struct Sparse(Option<u32>, Option<f32>);
+
+        impl Serialize for Sparse {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut state = serializer.serialize_struct("Sparse", 2)?;
+                state.serialize_field("pos", &self.0)?;
+                state.serialize_field("freq", &self.1)?;
+                state.end()
+            }
+        }
+
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("table.parquet");
+        let schema = vec![("pos".into(), ColumnType::UInt), ("freq".into(), ColumnType::Float)];
+        let mut writer = ParquetWriter::new(BufWriter::new(File::create(&path).unwrap()), &schema);
+        writer.serialize(Sparse(Some(1), None)).unwrap();
+        writer.serialize(Sparse(Some(2), Some(0.5))).unwrap();
+        // Only floats can be missing
+        assert!(writer.serialize(Sparse(None, Some(0.5))).is_err());
+        writer.finish().unwrap();
+
+        let rows = read(&path);
+        assert_eq!(rows[1..], [vec!["1".to_owned(), "NaN".to_owned()], vec!["2".to_owned(), "0.5".to_owned()]]);
+    }
+}
@@ -0,0 +1,254 @@
+use std::io;
+use std::io::Write;
+
+use serde::Serialize;
+
+#[cfg(feature = "parquet")]
+use super::parquet::ParquetWriter;
+use super::utils::FinishWrite;
+
+// Version of the result tables layout, must be bumped whenever columns are added, removed, renamed or reordered
+pub const SCHEMA_VERSION: u32 = 1;
+
+// First line of the delimited result tables, unless disabled by the user. Only the main ROI/site tables described by
+// `reat schema` have it, auxiliary tables (EI, conversion rates, coverage summary, etc) never start with it
+pub const SCHEMA_PREFIX: &str = "# reat-schema: ";
+
+pub fn schema_line() -> String {
+    format!("{}{}", SCHEMA_PREFIX, SCHEMA_VERSION)
+}
+
+// Schema version from the first line of a result table, if any
+pub fn schema_version(line: &str) -> Option<u32> {
+    line.trim_end().strip_prefix(SCHEMA_PREFIX).and_then(|x| x.parse().ok())
+}
+
+// Value type of a result table column, required to build a fixed schema for typed outputs (e.g. Parquet)
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Str,
+    UInt,
+    Float,
+}
+
+// Column names & types in the serialization order, i.e. the same as in the header
+pub type Schema = Vec<(String, ColumnType)>;
+
+// Same as a schema entry, but with a human-readable description, e.g. for the `reat schema` dumps
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ctype: ColumnType,
+    pub description: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TableFormat {
+    Delimited(u8),
+    // One JSON object per row, keys are column names
+    JsonLines,
+    Parquet(Schema),
+}
+
+// Row-by-row sink for result tables. Rows are written in the given order => sorting is up to the caller.
+pub trait RowWriter {
+    fn serialize<S: Serialize>(&mut self, row: S) -> csv::Result<()>;
+
+    // Untyped row (e.g. mpileup records), supported only by delimited tables
+    fn write_record<I, T>(&mut self, record: I) -> csv::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>;
+}
+
+impl<W: Write> RowWriter for csv::Writer<W> {
+    fn serialize<S: Serialize>(&mut self, row: S) -> csv::Result<()> {
+        csv::Writer::serialize(self, row)
+    }
+
+    fn write_record<I, T>(&mut self, record: I) -> csv::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        csv::Writer::write_record(self, record)
+    }
+}
+
+// Result table in one of the supported formats
+pub enum TableWriter<W: FinishWrite> {
+    Delimited(csv::Writer<W>),
+    JsonLines(W),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetWriter<W>),
+}
+
+impl<W: FinishWrite> TableWriter<W> {
+    // Delimited tables get the header from the first serialized row
+    pub fn new(format: &TableFormat, writer: W) -> Self {
+        Self::build(format, writer, true)
+    }
+
+    // Table without the header, e.g. a part to be appended to another table
+    pub fn headless(format: &TableFormat, writer: W) -> Self {
+        Self::build(format, writer, false)
+    }
+
+    fn build(format: &TableFormat, writer: W, headers: bool) -> Self {
+        match format {
+            TableFormat::Delimited(delimiter) => Self::Delimited(
+                csv::WriterBuilder::new().delimiter(*delimiter).has_headers(headers).from_writer(writer),
+            ),
+            TableFormat::JsonLines => Self::JsonLines(writer),
+            #[cfg(feature = "parquet")]
+            TableFormat::Parquet(schema) => Self::Parquet(ParquetWriter::new(writer, schema)),
+            #[cfg(not(feature = "parquet"))]
+            TableFormat::Parquet(_) => {
+                panic!("Parquet output is not supported by this build, reinstall REAT with --features parquet")
+            }
+        }
+    }
+
+    // Self-describing formats (JSON lines, Parquet) have no header
+    pub fn write_header(&mut self, header: &[String]) -> csv::Result<()> {
+        match self {
+            Self::Delimited(writer) => writer.write_record(header),
+            _ => Ok(()),
+        }
+    }
+
+    // Raw access to the underlying file, e.g. to append other parts of the table. Buffered rows are flushed first
+    pub fn get_mut(&mut self) -> io::Result<&mut W> {
+        match self {
+            Self::Delimited(writer) => {
+                writer.flush()?;
+                Ok(writer.get_mut())
+            }
+            Self::JsonLines(writer) => Ok(writer),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => panic!("Parquet tables can't be concatenated"),
+        }
+    }
+
+    // Underlying file, must be called before anything is written
+    pub fn into_inner(self) -> W {
+        match self {
+            Self::Delimited(writer) => {
+                writer.into_inner().unwrap_or_else(|x| panic!("Failed to reopen the output file: {}", x.error()))
+            }
+            Self::JsonLines(writer) => writer,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => panic!("Parquet tables can't be reopened"),
+        }
+    }
+
+    // Write all buffered rows & finalize the file (e.g. Parquet footer), must be called once all rows are written
+    pub fn finish(&mut self) -> csv::Result<()> {
+        match self {
+            Self::Delimited(writer) => {
+                writer.flush()?;
+                writer.get_mut().finish()?;
+            }
+            Self::JsonLines(writer) => writer.finish()?,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+impl<W: FinishWrite> RowWriter for TableWriter<W> {
+    fn serialize<S: Serialize>(&mut self, row: S) -> csv::Result<()> {
+        match self {
+            Self::Delimited(writer) => writer.serialize(row),
+            Self::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, &row).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            Self::Parquet(writer) => Ok(writer.serialize(row)?),
+        }
+    }
+
+    fn write_record<I, T>(&mut self, record: I) -> csv::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        match self {
+            Self::Delimited(writer) => writer.write_record(record),
+            _ => panic!("Untyped records are supported only by delimited tables"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::BufWriter;
+
+    use serde::ser::SerializeStruct;
+    use serde::Serializer;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    struct Row(&'static str, u32, f32);
+
+    impl Serialize for Row {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut state = serializer.serialize_struct("Row", 3)?;
+            state.serialize_field("contig", self.0)?;
+            state.serialize_field("pos", &self.1)?;
+            state.serialize_field("freq", &self.2)?;
+            state.end()
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let tmp = TempDir::new().unwrap();
+        for (format, expected) in [
+            (TableFormat::Delimited(b','), "contig,pos,freq\nchr1,10,0.5\nchr2,1,0.0\n"),
+            (TableFormat::Delimited(b'\t'), "contig\tpos\tfreq\nchr1\t10\t0.5\nchr2\t1\t0.0\n"),
+            (
+                TableFormat::JsonLines,
+                "{\"contig\":\"chr1\",\"pos\":10,\"freq\":0.5}\n{\"contig\":\"chr2\",\"pos\":1,\"freq\":0.0}\n",
+            ),
+        ] {
+            let path = tmp.path().join("table");
+            let mut writer = TableWriter::new(&format, BufWriter::new(fs::File::create(&path).unwrap()));
+            writer.serialize(Row("chr1", 10, 0.5)).unwrap();
+            writer.serialize(Row("chr2", 1, 0.0)).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn schema_version() {
+        assert_eq!(schema_line(), format!("# reat-schema: {}", SCHEMA_VERSION));
+        assert_eq!(super::schema_version(&format!("{}\n", schema_line())), Some(SCHEMA_VERSION));
+        assert_eq!(super::schema_version("# reat-schema: 12"), Some(12));
+        for line in ["contig,start,end", "# reat-schema: x", "#reat-schema: 1", ""] {
+            assert_eq!(super::schema_version(line), None, "{}", line);
+        }
+        assert_eq!(serde_json::to_string(&ColumnType::UInt).unwrap(), "\"uint\"");
+    }
+
+    #[test]
+    fn header() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("table");
+        let header = ["contig".to_owned(), "pos".to_owned()];
+        for (format, expected) in [(TableFormat::Delimited(b','), "contig,pos\n"), (TableFormat::JsonLines, "")] {
+            let mut writer = TableWriter::headless(&format, BufWriter::new(fs::File::create(&path).unwrap()));
+            writer.write_header(&header).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        }
+    }
+}
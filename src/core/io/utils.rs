@@ -1,3 +1,16 @@
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rust_htslib::bgzf;
+
+use crate::error::Error;
+
+// Evaluates to Err if the file can't be opened, otherwise to Ok with the result of the function
 macro_rules! read_compressed {
     ($file: ident, $function: expr $(, $param: expr )* ) => {{
         let filename = $file
@@ -5,17 +18,155 @@ macro_rules! read_compressed {
             .and_then(OsStr::to_str)
             .unwrap_or_else(|| panic!("Failed to infer extension for the file {}.", $file.display()));
 
-        let reader = File::open($file).unwrap_or_else(|x| panic!("Failed to open {}: {}.", $file.display(), x));
-        let reader = BufReader::new(reader);
-
-        match filename.split('.').last() {
-            Some("gz") => {
-                let reader = BufReader::new(MultiGzDecoder::new(reader));
-                $function(reader $(, $param)*)
+        match File::open($file) {
+            Err(x) => Err($crate::error::Error::io($file, x)),
+            Ok(reader) => {
+                let reader = BufReader::new(reader);
+                match filename.split('.').last() {
+                    // bgzip files are valid multi-member gzip files
+                    Some("gz" | "bgz") => {
+                        let reader = BufReader::new(MultiGzDecoder::new(reader));
+                        Ok($function(reader $(, $param)*))
+                    }
+                    Some(_) | None => Ok($function(reader $(, $param)*)),
+                }
             }
-            Some(_) | None => $function(reader $(, $param)*),
         }
     }};
 }
 
 pub(crate) use read_compressed;
+
+// Writer that must be explicitly finalized (e.g. gzip footer) once all data is written
+pub trait FinishWrite: Write {
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl FinishWrite for BufWriter<File> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl FinishWrite for GzEncoder<BufWriter<File>> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+// bgzip-compressed file. htslib writes the EOF marker only when the file is closed, i.e. when the writer is dropped.
+// rust_htslib ignores the result of the close => finish flushes all blocks to report failures and closes the file
+pub struct BgzfWriter {
+    path: PathBuf,
+    // None once the file is closed
+    inner: Option<bgzf::Writer>,
+}
+
+impl BgzfWriter {
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let inner = bgzf::Writer::from_path(path).map_err(|x| Error::hts(path, x.to_string()))?;
+        Ok(Self { path: path.to_owned(), inner: Some(inner) })
+    }
+
+    fn inner(&mut self) -> io::Result<&mut bgzf::Writer> {
+        let path = &self.path;
+        self.inner.as_mut().ok_or_else(|| io::Error::other(format!("{} is already closed", path.display())))
+    }
+}
+
+impl Write for BgzfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner()?.flush()
+    }
+}
+
+impl FinishWrite for BgzfWriter {
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut inner) = self.inner.take() {
+            inner.flush()?;
+            // Dropped => closed & the EOF marker is written
+        }
+        Ok(())
+    }
+}
+
+impl<T: FinishWrite + ?Sized> FinishWrite for Box<T> {
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+pub type OutputWriter = Box<dyn FinishWrite>;
+
+pub fn write_compressed(path: &Path, append: bool) -> Result<OutputWriter, Error> {
+    let filename = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or_else(|| panic!("Failed to infer extension for the file {}.", path.display()));
+
+    match filename.split('.').last() {
+        Some("bgz") if append => Err(Error::Input(format!(
+            "Appending to the bgzip-compressed files is not supported: {}, use .gz instead.",
+            path.display()
+        ))),
+        Some("bgz") => Ok(Box::new(BgzfWriter::from_path(path)?)),
+        ext => {
+            let writer = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)
+                .map_err(|x| Error::io(path, x))?;
+            let writer = BufWriter::new(writer);
+            match ext {
+                Some("gz") => Ok(Box::new(GzEncoder::new(writer, Compression::default()))),
+                Some(_) | None => Ok(Box::new(writer)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Empty BGZF block terminating all bgzipped files
+    const BGZF_EOF: [u8; 28] = [
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00, 0x1b, 0x00,
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn bgzf_finish() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("table.tsv.bgz");
+        let mut writer = write_compressed(&path, false).unwrap();
+        writer.write_all(b"contig\tpos\nchr1\t10\n").unwrap();
+        writer.finish().unwrap();
+
+        // The file is complete before the writer is dropped
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.ends_with(&BGZF_EOF));
+        let mut content = String::new();
+        MultiGzDecoder::new(bytes.as_slice()).read_to_string(&mut content).unwrap();
+        assert_eq!(content, "contig\tpos\nchr1\t10\n");
+
+        // Finished writers are closed
+        assert!(writer.write_all(b"chr2\t5\n").is_err());
+        writer.finish().unwrap();
+        drop(writer);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+    }
+}
@@ -1,11 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
-use bio_types::genome::Position;
+use bio_types::genome::{Interval, Position};
 use itertools::Itertools;
 use rust_htslib::bcf::header::Id;
 use rust_htslib::bcf::record::GenotypeAllele;
-use rust_htslib::bcf::{Read, Reader};
+use rust_htslib::bcf::{IndexedReader, Read, Reader};
 
 use crate::core::dna::ReqNucleotide;
 
@@ -82,3 +82,107 @@ pub fn parse(vcf: impl AsRef<Path>) -> SimplisticSNV {
     }
     SimplisticSNV { rid2ref, ref2rid, homozygous, heterozygous }
 }
+
+pub struct KnownSNV {
+    // Single-base intervals for all known biallelic SNVs
+    pub loci: Vec<Interval>,
+    // Skipped multi-allelic records and indels
+    pub skipped: usize,
+}
+
+fn _known_snv(reader: &mut impl Read, contigs: &HashSet<String>, pass: bool, result: &mut KnownSNV) {
+    // rid -> contig name if the contig is relevant
+    let mut rid2ref: HashMap<u32, Option<String>> = HashMap::new();
+
+    let mut record = reader.empty_record();
+    while let Some(Ok(())) = reader.read(&mut record) {
+        let rid = record.rid().expect("Failed to parse VCF contig");
+        let contig = rid2ref.entry(rid).or_insert_with(|| {
+            let name = reader.header().rid2name(rid).expect("Failed to parse VCF contig names");
+            let name = String::from_utf8_lossy(name).into_owned();
+            contigs.contains(&name).then(|| name)
+        });
+        let contig = match contig {
+            None => continue,
+            Some(contig) => contig,
+        };
+        if pass && !record.has_filter(&Id(0)) {
+            continue;
+        }
+
+        // Biallelic SNVs only
+        let alleles = record.alleles();
+        if alleles.len() != 2 || alleles.iter().any(|x| x.len() != 1) {
+            result.skipped += 1;
+            continue;
+        }
+        let pos = record.pos() as Position;
+        result.loci.push(Interval::new(contig.clone(), pos..pos + 1));
+    }
+}
+
+// Known SNVs located on the given contigs. Indexed files are fetched contig by contig.
+pub fn known_snv(vcf: impl AsRef<Path>, contigs: &HashSet<String>, pass: bool) -> KnownSNV {
+    let vcf = vcf.as_ref();
+    let mut result = KnownSNV { loci: vec![], skipped: 0 };
+    match IndexedReader::from_path(vcf) {
+        Ok(mut reader) => {
+            for contig in contigs.iter().sorted() {
+                let rid = match reader.header().name2rid(contig.as_bytes()) {
+                    Ok(rid) => rid,
+                    Err(_) => continue,
+                };
+                reader.fetch(rid, 0, None).expect("Failed to fetch VCF records");
+                _known_snv(&mut reader, contigs, pass, &mut result);
+            }
+        }
+        Err(_) => {
+            let mut reader = Reader::from_path(vcf).expect("Error opening file.");
+            _known_snv(&mut reader, contigs, pass, &mut result);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn known_snv() {
+        let mut vcf = Builder::new().suffix(".vcf").tempfile().unwrap();
+        vcf.write_all(
+            b"##fileformat=VCFv4.2\n\
+            ##FILTER=<ID=PASS,Description=\"All filters passed\">\n\
+            ##FILTER=<ID=q10,Description=\"Quality below 10\">\n\
+            ##contig=<ID=1>\n\
+            ##contig=<ID=2>\n\
+            ##contig=<ID=3>\n\
+            #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t10\t.\tA\tG\t.\tPASS\t.\n\
+            1\t20\t.\tA\tG,T\t.\tPASS\t.\n\
+            1\t30\t.\tAC\tA\t.\tPASS\t.\n\
+            1\t40\t.\tC\tT\t.\tq10\t.\n\
+            2\t5\t.\tG\tC\t.\tPASS\t.\n\
+            3\t1\t.\tT\tC\t.\tPASS\t.\n",
+        )
+        .unwrap();
+        vcf.flush().unwrap();
+
+        let contigs = HashSet::from(["1".to_owned(), "2".to_owned()]);
+        let result = super::known_snv(vcf.path(), &contigs, false);
+        assert_eq!(
+            result.loci,
+            [Interval::new("1".into(), 9..10), Interval::new("1".into(), 39..40), Interval::new("2".into(), 4..5)]
+        );
+        assert_eq!(result.skipped, 2);
+
+        let result = super::known_snv(vcf.path(), &contigs, true);
+        assert_eq!(result.loci, [Interval::new("1".into(), 9..10), Interval::new("2".into(), 4..5)]);
+        assert_eq!(result.skipped, 2);
+    }
+}
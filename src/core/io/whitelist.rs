@@ -0,0 +1,41 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+use crate::error::Error;
+
+use super::utils;
+
+// The first column of each line, e.g. 10x barcodes.tsv(.gz). Empty lines & comments (#) are skipped
+fn _parse<T: BufRead>(reader: T) -> io::Result<HashSet<Vec<u8>>> {
+    let mut result = HashSet::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(x) = line.split_whitespace().next().filter(|x| !x.starts_with('#')) {
+            result.insert(x.as_bytes().to_vec());
+        }
+    }
+    Ok(result)
+}
+
+pub fn parse(path: impl AsRef<Path>) -> Result<HashSet<Vec<u8>>, Error> {
+    let path = path.as_ref();
+    utils::read_compressed!(path, _parse)?.map_err(|x| Error::io(path, x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let content = "# barcodes\nAAACCTG-1\tcluster1\n\nAAACGGG-1\n  \nAAACCTG-1 cluster2\n";
+        let expected: HashSet<Vec<u8>> = [b"AAACCTG-1".to_vec(), b"AAACGGG-1".to_vec()].into_iter().collect();
+        assert_eq!(_parse(content.as_bytes()).unwrap(), expected);
+        assert!(_parse("".as_bytes()).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 use bio_types::strand::Strand;
-use std::io::Write;
 
+use crate::core::dna::SubstitutionCounts;
+use crate::core::io::table::RowWriter;
+use crate::core::rpileup::ncounter::cnt::SpliceJunctions;
 use crate::core::strandutil::Stranded;
+use crate::error::Error;
 
 pub mod prefilters;
 pub mod roi;
@@ -15,21 +18,93 @@ pub trait MismatchesVec: Sized {
 
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool;
+    // Number of records extending past the end of a contig with the given length
+    fn past_end(&self, length: u64) -> usize;
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut csv::Writer<F>) -> csv::Result<()>;
+    fn ugly_in_contig_sort_and_to_csv<R: RowWriter>(items: Vec<Self>, writer: &mut R) -> csv::Result<()>;
+
+    // Label records that failed the given soft filter (false verdicts) instead of dropping them
+    fn mark(&mut self, filter: &'static str, verdicts: &[bool]);
+
+    // Distance to the nearest splice junction of the contig for each record, no-op for records without such field
+    fn annotate_splicing(&mut self, _junctions: &SpliceJunctions) {}
+}
+
+// Names of the failed soft filters for each record, in the order the filters were applied
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct FilterLabels {
+    failed: Vec<Vec<&'static str>>,
+}
+
+impl FilterLabels {
+    pub fn new(records: usize) -> Self {
+        Self { failed: vec![Vec::new(); records] }
+    }
+
+    pub fn mark(&mut self, filter: &'static str, verdicts: &[bool]) {
+        debug_assert_eq!(self.failed.len(), verdicts.len());
+        for (failed, passed) in self.failed.iter_mut().zip(verdicts) {
+            if !passed {
+                failed.push(filter);
+            }
+        }
+    }
+
+    pub fn get(&self, record: usize) -> &[&'static str] {
+        &self.failed[record]
+    }
+
+    // VCF-style label: PASS or semicolon-separated names of the failed filters
+    pub fn label(failed: Option<&[&'static str]>) -> String {
+        match failed {
+            Some(failed) if !failed.is_empty() => failed.join(";"),
+            _ => "PASS".to_owned(),
+        }
+    }
 }
 
 pub trait Builder<'a> {
     type Out: MismatchesVec;
     type SourceCounts;
-    fn build(&mut self, nc: Self::SourceCounts) -> Batch<Self::Out>;
+    // Errors are reported if the reference can't be predicted, e.g. the assembly is unreadable
+    fn build(&mut self, nc: Self::SourceCounts) -> Result<Batch<Self::Out>, Error>;
 }
 
 pub struct Batch<T: MismatchesVec> {
     pub contig: String,
     pub mapped: Stranded<u32>,
+    // Reads rejected only due to the mapq = 255 policy
+    pub mapq_255_dropped: u32,
+    // Reads collapsed as UMI duplicates
+    pub umi_collapsed: u32,
+    // Reads skipped due to the coverage cap
+    pub coverage_capped: u32,
+    // Positions skipped due to the unknown(N) reference policy
+    pub refn_skipped: u64,
+    // Positions covered by at least one read (site mode only)
+    pub covered: u64,
+    // Background substitutions at non-variable positions (site mode only, if requested)
+    pub substitutions: SubstitutionCounts,
+    // Splice junctions observed in the window (site mode only, if requested)
+    pub junctions: Option<SpliceJunctions>,
     // Must be retained & printed no matter what
     pub retained: Stranded<T>,
     // Other mismatches
     pub items: Stranded<T>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_labels() {
+        let mut labels = FilterLabels::new(3);
+        labels.mark("mismatches", &[true, false, false]);
+        labels.mark("expression", &[true, true, false]);
+        let label = |record| FilterLabels::label(Some(labels.get(record)));
+        assert_eq!([label(0), label(1), label(2)], ["PASS", "mismatches", "mismatches;expression"]);
+        // Records without labels (e.g. forced ones) always pass
+        assert_eq!(FilterLabels::label(None), "PASS");
+    }
+}
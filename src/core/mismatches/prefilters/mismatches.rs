@@ -1,44 +1,124 @@
+use bio_types::strand::Strand;
+
 use crate::core::dna::{NucCounts, Nucleotide};
 use crate::core::mismatches::roi::{ROIData, ROINucCounts};
 use crate::core::mismatches::site::SiteData;
 use crate::core::refpred::PredNucleotide;
+use crate::core::statistics;
 
 use super::MismatchesPreFilter;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct ByMismatches {
     minfreq: f32,
+    // Count only A->G (forward strand) and T->C (reverse strand) mismatches, see editing_only()
+    editing_only: bool,
+    // Compare the lower bound of the ROI mismatches frequency CI(Wilson score, given z score) with the minfreq
+    minfreq_ci: Option<f32>,
     // Precasted values to save on convertions
-    minmismatches_f32: f32,
-    mincov_f32: f32,
+    minmismatches_f64: f64,
+    mincov_f64: f64,
     minmismatches_u32: u32,
     mincov_u32: u32,
 }
 
 impl ByMismatches {
-    pub fn new(minmismatches: u32, minfreq: f32, mincov: u32) -> Self {
+    pub fn new(minmismatches: u32, minfreq: f32, mincov: u32, editing_only: bool) -> Self {
         Self {
             minfreq,
-            minmismatches_f32: minmismatches as f32,
-            mincov_f32: mincov as f32,
+            editing_only,
+            minfreq_ci: None,
+            minmismatches_f64: minmismatches as f64,
+            mincov_f64: mincov as f64,
             minmismatches_u32: minmismatches,
             mincov_u32: mincov,
         }
     }
 
+    // Only ROIs are affected, sites are always filtered by the raw frequency
+    pub fn with_minfreq_ci(mut self, z: f32) -> Self {
+        self.minfreq_ci = Some(z);
+        self
+    }
+
+    #[inline]
+    fn roi_freq(&self, mismatches: f64, coverage: f64) -> f32 {
+        match self.minfreq_ci {
+            Some(z) => statistics::wilson(mismatches, coverage, z).0,
+            None => (mismatches / coverage) as f32,
+        }
+    }
+
+    // Editing-only mode: the A->G/T->C pair is chosen by the transcription strand, the max of both for unknown strand
+    #[inline]
+    fn roi_mismatches(&self, x: &ROINucCounts, trstrand: Strand) -> f64 {
+        if !self.editing_only {
+            return x.mismatches();
+        }
+        match trstrand {
+            Strand::Forward => x.A.G,
+            Strand::Reverse => x.T.C,
+            Strand::Unknown => x.A.G.max(x.T.C),
+        }
+    }
+
+    #[inline]
+    fn site_mismatches(&self, reference: Nucleotide, sequenced: &NucCounts, trstrand: Strand) -> u32 {
+        if !self.editing_only {
+            return sequenced.mismatches(reference);
+        }
+        match (reference, trstrand) {
+            (Nucleotide::A, Strand::Forward | Strand::Unknown) => sequenced.G,
+            (Nucleotide::T, Strand::Reverse | Strand::Unknown) => sequenced.C,
+            _ => 0,
+        }
+    }
+
     #[inline]
-    pub fn enough_mismatches_per_roi(&self, x: &ROINucCounts) -> bool {
-        let (cov, mismatch) = (x.coverage(), x.mismatches());
-        cov >= self.mincov_f32 && mismatch >= self.minmismatches_f32 && mismatch / cov >= self.minfreq
+    pub fn enough_mismatches_per_roi(&self, x: &ROINucCounts, trstrand: Strand) -> bool {
+        let (cov, mismatch) = (x.coverage(), self.roi_mismatches(x, trstrand));
+        cov >= self.mincov_f64 && mismatch >= self.minmismatches_f64 && self.roi_freq(mismatch, cov) >= self.minfreq
     }
 
     #[inline]
-    pub fn enough_mismatches_per_site(&self, reference: Nucleotide, sequenced: &NucCounts) -> bool {
+    pub fn enough_mismatches_per_site(&self, reference: Nucleotide, sequenced: &NucCounts, trstrand: Strand) -> bool {
         let cov = sequenced.coverage();
-        let mismatch = sequenced.mismatches(reference);
+        let mismatch = self.site_mismatches(reference, sequenced, trstrand);
         cov >= self.mincov_u32 && mismatch >= self.minmismatches_u32 && mismatch as f32 / cov as f32 >= self.minfreq
     }
 
+    #[inline]
+    pub fn enough_mismatches_per_prednuc(
+        &self,
+        prednuc: PredNucleotide,
+        sequenced: &NucCounts,
+        trstrand: Strand,
+    ) -> bool {
+        match prednuc {
+            PredNucleotide::Homozygous(nuc) => self.enough_mismatches_per_site(nuc, sequenced, trstrand),
+            PredNucleotide::Heterozygous((n1, n2)) => {
+                self.enough_mismatches_per_site(n1, sequenced, trstrand)
+                    || self.enough_mismatches_per_site(n2, sequenced, trstrand)
+            }
+        }
+    }
+
+    // Sites with per-sample counts pass the filter if any of the samples passes it
+    #[inline]
+    pub fn enough_mismatches_per_data(
+        &self,
+        prednuc: PredNucleotide,
+        sequenced: &NucCounts,
+        persample: &[NucCounts],
+        trstrand: Strand,
+    ) -> bool {
+        if persample.is_empty() {
+            self.enough_mismatches_per_prednuc(prednuc, sequenced, trstrand)
+        } else {
+            persample.iter().any(|x| self.enough_mismatches_per_prednuc(prednuc, x, trstrand))
+        }
+    }
+
     #[inline]
     pub fn mincov(&self) -> u32 {
         self.mincov_u32
@@ -53,25 +133,29 @@ impl ByMismatches {
     pub fn minmismatches(&self) -> u32 {
         self.minmismatches_u32
     }
+
+    #[inline]
+    pub fn editing_only(&self) -> bool {
+        self.editing_only
+    }
+
+    #[inline]
+    pub fn minfreq_ci(&self) -> Option<f32> {
+        self.minfreq_ci
+    }
 }
 
 impl MismatchesPreFilter<ROIData> for ByMismatches {
     #[inline]
-    fn is_ok(&self, preview: &ROIData) -> bool {
-        self.enough_mismatches_per_roi(&preview.mismatches)
+    fn is_ok(&self, preview: &ROIData, trstrand: Strand) -> bool {
+        self.enough_mismatches_per_roi(&preview.mismatches, trstrand)
     }
 }
 
 impl MismatchesPreFilter<SiteData> for ByMismatches {
     #[inline]
-    fn is_ok(&self, preview: &SiteData) -> bool {
-        match preview.prednuc {
-            PredNucleotide::Homozygous(nuc) => self.enough_mismatches_per_site(nuc, &preview.sequenced),
-            PredNucleotide::Heterozygous((n1, n2)) => {
-                self.enough_mismatches_per_site(n1, &preview.sequenced)
-                    || self.enough_mismatches_per_site(n2, &preview.sequenced)
-            }
-        }
+    fn is_ok(&self, preview: &SiteData, trstrand: Strand) -> bool {
+        self.enough_mismatches_per_data(preview.prednuc, &preview.sequenced, &preview.persample, trstrand)
     }
 }
 
@@ -86,17 +170,17 @@ mod tests {
     fn ok_roi() {
         let mut dummy: ROINucCounts = Default::default();
 
-        dummy.A.C = 1_f32;
-        dummy.A.A = 4_f32;
+        dummy.A.C = 1_f64;
+        dummy.A.A = 4_f64;
 
-        dummy.C.G = 1_f32;
-        dummy.C.C = 2_f32;
+        dummy.C.G = 1_f64;
+        dummy.C.C = 2_f64;
 
-        dummy.G.T = 1_f32;
-        dummy.G.G = 5_f32;
+        dummy.G.T = 1_f64;
+        dummy.G.G = 5_f64;
 
-        dummy.T.A = 10_f32;
-        dummy.T.T = 3_f32;
+        dummy.T.A = 10_f64;
+        dummy.T.T = 3_f64;
         // dummy coverage = 27, mismatches = 13, freq = 0.48148
 
         for (expected, minmismatches, minfreq, mincov) in [
@@ -110,11 +194,57 @@ mod tests {
             (false, 13, 0.48f32, 30),
             (false, 1, 0.48f32, 42),
         ] {
-            let filter = ByMismatches::new(minmismatches, minfreq, mincov);
-            assert_eq!(filter.enough_mismatches_per_roi(&dummy), expected, "{} {} {}", minmismatches, minfreq, mincov);
+            let filter = ByMismatches::new(minmismatches, minfreq, mincov, false);
+            for trstrand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+                assert_eq!(
+                    filter.enough_mismatches_per_roi(&dummy, trstrand),
+                    expected,
+                    "{} {} {}",
+                    minmismatches,
+                    minfreq,
+                    mincov
+                );
+            }
         }
     }
 
+    #[test]
+    fn ok_roi_ci() {
+        // 1/3 = 33% raw frequency, but the 95% Wilson interval is [0.061, 0.792]
+        let mut sparse = ROINucCounts::zeros();
+        sparse.A.A = 2_f64;
+        sparse.A.G = 1_f64;
+        // 100/300 = 33%, [0.283, 0.387]
+        let mut dense = ROINucCounts::zeros();
+        dense.A.A = 200_f64;
+        dense.A.G = 100_f64;
+
+        let raw = ByMismatches::new(1, 0.25, 0, false);
+        let ci = raw.with_minfreq_ci(1.959964);
+        assert_eq!(ci.minfreq_ci(), Some(1.959964));
+        for trstrand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            assert!(raw.enough_mismatches_per_roi(&sparse, trstrand));
+            assert!(!ci.enough_mismatches_per_roi(&sparse, trstrand));
+            assert!(raw.enough_mismatches_per_roi(&dense, trstrand));
+            assert!(ci.enough_mismatches_per_roi(&dense, trstrand));
+        }
+        assert!(!ci.enough_mismatches_per_roi(&ROINucCounts::zeros(), Strand::Unknown));
+    }
+
+    #[test]
+    fn ok_persample() {
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let sequenced = NucCounts { A: 10, C: 0, G: 4, T: 0 };
+        let persample = [NucCounts { A: 5, C: 0, G: 1, T: 0 }, NucCounts { A: 5, C: 0, G: 3, T: 0 }];
+
+        let filter = ByMismatches::new(3, 0.1, 5, false);
+        let strand = Strand::Unknown;
+        assert!(filter.enough_mismatches_per_data(prednuc, &sequenced, &[], strand));
+        assert!(filter.enough_mismatches_per_data(prednuc, &sequenced, &persample, strand));
+        // Pooled counts are ignored when per-sample counts are available
+        assert!(!filter.enough_mismatches_per_data(prednuc, &sequenced, &persample[..1], strand));
+    }
+
     #[test]
     fn ok_site() {
         let mut reference = Nucleotide::A;
@@ -129,8 +259,8 @@ mod tests {
             (true, 9, 0.85f32, 10),
             (false, 9, 0.85f32, 11),
         ] {
-            let filter = ByMismatches::new(minmismatches, minfreq, mincov);
-            assert_eq!(filter.enough_mismatches_per_site(reference, &sequenced), expected);
+            let filter = ByMismatches::new(minmismatches, minfreq, mincov, false);
+            assert_eq!(filter.enough_mismatches_per_site(reference, &sequenced, Strand::Unknown), expected);
         }
 
         reference = Nucleotide::Unknown;
@@ -143,8 +273,54 @@ mod tests {
             (true, 10, 1f32, 10),
             (false, 10, 1f32, 11),
         ] {
-            let filter = ByMismatches::new(minmismatches, minfreq, mincov);
-            assert_eq!(filter.enough_mismatches_per_site(reference, &sequenced), expected);
+            let filter = ByMismatches::new(minmismatches, minfreq, mincov, false);
+            assert_eq!(filter.enough_mismatches_per_site(reference, &sequenced, Strand::Unknown), expected);
+        }
+    }
+
+    #[test]
+    fn editing_only_roi() {
+        // C->T noise without A->G mismatches
+        let mut noise = ROINucCounts::zeros();
+        noise.A.A = 100_f64;
+        noise.C.C = 50_f64;
+        noise.C.T = 50_f64;
+
+        let default = ByMismatches::new(10, 0.01, 20, false);
+        let editing = ByMismatches::new(10, 0.01, 20, true);
+        for trstrand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            assert!(default.enough_mismatches_per_roi(&noise, trstrand));
+            assert!(!editing.enough_mismatches_per_roi(&noise, trstrand));
+        }
+
+        // A->G is sense editing for the forward strand, T->C - for the reverse one
+        let mut edited = noise;
+        edited.A.G = 20_f64;
+        edited.T.T = 100_f64;
+        edited.T.C = 5_f64;
+        for (trstrand, expected) in [(Strand::Forward, true), (Strand::Reverse, false), (Strand::Unknown, true)] {
+            assert_eq!(editing.enough_mismatches_per_roi(&edited, trstrand), expected);
+        }
+    }
+
+    #[test]
+    fn editing_only_site() {
+        let editing = ByMismatches::new(3, 0.01, 5, true);
+        let sequenced = NucCounts { A: 10, C: 4, G: 2, T: 0 };
+        assert!(ByMismatches::new(3, 0.01, 5, false).enough_mismatches_per_site(
+            Nucleotide::A,
+            &sequenced,
+            Strand::Forward
+        ));
+        assert!(!editing.enough_mismatches_per_site(Nucleotide::A, &sequenced, Strand::Forward));
+
+        let sequenced = NucCounts { A: 10, C: 0, G: 4, T: 0 };
+        for (trstrand, expected) in [(Strand::Forward, true), (Strand::Reverse, false), (Strand::Unknown, true)] {
+            assert_eq!(editing.enough_mismatches_per_site(Nucleotide::A, &sequenced, trstrand), expected);
+        }
+        let sequenced = NucCounts { A: 0, C: 4, G: 0, T: 10 };
+        for (trstrand, expected) in [(Strand::Forward, false), (Strand::Reverse, true), (Strand::Unknown, true)] {
+            assert_eq!(editing.enough_mismatches_per_site(Nucleotide::T, &sequenced, trstrand), expected);
         }
     }
 }
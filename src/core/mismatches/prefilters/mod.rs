@@ -1,8 +1,13 @@
+use bio_types::strand::Strand;
+
 pub use mismatches::ByMismatches;
+pub use replicates::ByReplicates;
 
 mod mismatches;
+mod replicates;
 pub mod retain;
 
 pub trait MismatchesPreFilter<T> {
-    fn is_ok(&self, preview: &T) -> bool;
+    // Transcription strand of the preview is required by the strand-aware filters
+    fn is_ok(&self, preview: &T, trstrand: Strand) -> bool;
 }
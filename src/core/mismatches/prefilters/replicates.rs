@@ -0,0 +1,132 @@
+use bio_types::strand::Strand;
+
+use crate::core::dna::NucCounts;
+use crate::core::mismatches::roi::{ROIData, ROINucCounts};
+use crate::core::refpred::PredNucleotide;
+
+use super::MismatchesPreFilter;
+
+// Require at least `minmismatches` mismatches in at least `minfiles` input files (technical replicates)
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ByReplicates {
+    minmismatches_f64: f64,
+    minmismatches_u32: u32,
+    minfiles: usize,
+}
+
+impl ByReplicates {
+    pub fn new(minmismatches: u32, minfiles: usize) -> Self {
+        Self { minmismatches_f64: minmismatches as f64, minmismatches_u32: minmismatches, minfiles }
+    }
+
+    #[inline]
+    pub fn enough_support_per_site<'a>(
+        &self,
+        prednuc: PredNucleotide,
+        persample: impl Iterator<Item = &'a NucCounts>,
+    ) -> bool {
+        let supported = |x: &NucCounts| match prednuc {
+            PredNucleotide::Homozygous(nuc) => x.mismatches(nuc) >= self.minmismatches_u32,
+            PredNucleotide::Heterozygous((n1, n2)) => {
+                x.mismatches(n1) >= self.minmismatches_u32 || x.mismatches(n2) >= self.minmismatches_u32
+            }
+        };
+        persample.filter(|x| supported(x)).take(self.minfiles).count() >= self.minfiles
+    }
+
+    #[inline]
+    pub fn enough_support_per_roi(&self, persample: &[ROINucCounts]) -> bool {
+        persample.iter().filter(|x| x.mismatches() >= self.minmismatches_f64).take(self.minfiles).count()
+            >= self.minfiles
+    }
+
+    #[inline]
+    pub fn minmismatches(&self) -> u32 {
+        self.minmismatches_u32
+    }
+
+    #[inline]
+    pub fn minfiles(&self) -> usize {
+        self.minfiles
+    }
+}
+
+impl MismatchesPreFilter<ROIData> for ByReplicates {
+    #[inline]
+    fn is_ok(&self, preview: &ROIData, _: Strand) -> bool {
+        self.enough_support_per_roi(&preview.persample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::dna::{Nucleotide, WideStrandDepth};
+    use crate::core::mismatches::roi::ROIDataRecord;
+
+    use super::*;
+
+    #[test]
+    fn ok_site() {
+        let persample = [
+            NucCounts { A: 10, C: 0, G: 3, T: 0 },
+            NucCounts { A: 10, C: 1, G: 1, T: 0 },
+            NucCounts { A: 0, C: 0, G: 5, T: 0 },
+        ];
+        let homozygous = PredNucleotide::Homozygous(Nucleotide::A);
+        for (expected, minmismatches, minfiles) in
+            [(true, 2, 3), (true, 3, 2), (false, 3, 3), (true, 5, 1), (false, 6, 1), (true, 0, 3), (false, 0, 4)]
+        {
+            let filter = ByReplicates::new(minmismatches, minfiles);
+            assert_eq!(filter.enough_support_per_site(homozygous, persample.iter()), expected);
+        }
+
+        // Mismatches relative to any of the alleles
+        let heterozygous = PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G));
+        for (expected, minmismatches, minfiles) in [(true, 2, 3), (true, 10, 2), (false, 12, 1), (false, 6, 3)] {
+            let filter = ByReplicates::new(minmismatches, minfiles);
+            assert_eq!(filter.enough_support_per_site(heterozygous, persample.iter()), expected);
+        }
+    }
+
+    #[test]
+    fn ok_roi() {
+        let persample = [0f64, 2f64, 5f64]
+            .into_iter()
+            .map(|mismatches| {
+                let mut cnts = ROINucCounts::zeros();
+                cnts.A.A = 10f64;
+                cnts.A.G = mismatches;
+                cnts
+            })
+            .collect();
+        // Pooled counts are irrelevant here
+        let roi = ROIData {
+            roi: ROIDataRecord {
+                premasked: 0..10,
+                postmasked: 0..10,
+                subintervals: vec![0..10],
+                name: "ROI".into(),
+                strand: Strand::Forward,
+                length: 10,
+            },
+            coverage: 0,
+            softclipped: 0,
+            indels: 0,
+            depth: WideStrandDepth::default(),
+            alt_fragments: 0,
+            homozygous: NucCounts::zeros(),
+            heterozygous: 0,
+            corrected: 0,
+            refnmasked: 0,
+            mismatches: ROINucCounts::zeros(),
+            persample,
+            flank: ROINucCounts::zeros(),
+            profile: vec![],
+        };
+
+        for (expected, minmismatches, minfiles) in [(true, 2, 2), (false, 3, 2), (true, 5, 1), (false, 1, 3)] {
+            let filter = ByReplicates::new(minmismatches, minfiles);
+            assert_eq!(filter.is_ok(&roi, Strand::Forward), expected, "{} {}", minmismatches, minfiles);
+        }
+    }
+}
@@ -4,7 +4,7 @@ use bio_types::genome::Position;
 use bio_types::strand::Strand;
 
 pub use rois::RetainROIFromList;
-pub use sites::RetainSitesFromIntervals;
+pub use sites::{RetainSitesFromIntervals, RetainSitesFromList};
 
 mod rois;
 mod sites;
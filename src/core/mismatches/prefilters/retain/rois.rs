@@ -89,7 +89,7 @@ impl RetainROIFromList {
     pub fn new(rois: Vec<BedRecord>) -> Self {
         let mut hash = HashSet::new();
         for r in rois.into_iter() {
-            let (name, strand, roi) = r.dissolve();
+            let (name, strand, roi, _) = r.dissolve();
             hash.insert((roi.contig().into(), roi.range(), strand.strand_symbol().into(), name));
         }
         Self { hash }
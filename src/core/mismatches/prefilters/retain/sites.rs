@@ -46,3 +46,73 @@ impl SitesRetainer for RetainSitesFromIntervals {
         }
     }
 }
+
+// Sorted loci for each contig, lookups are binary searches
+#[derive(Clone)]
+pub struct RetainSitesFromList {
+    index: HashMap<String, Vec<Position>>,
+}
+
+impl RetainSitesFromList {
+    pub fn new(include: Vec<impl AbstractInterval>) -> Self {
+        let mut index: HashMap<String, Vec<Position>> = HashMap::new();
+        for record in include {
+            index.entry(record.contig().into()).or_default().extend(record.range());
+        }
+        for loci in index.values_mut() {
+            loci.sort_unstable();
+            loci.dedup();
+        }
+        Self { index }
+    }
+
+    pub fn loci(&self) -> usize {
+        self.index.values().map(|x| x.len()).sum()
+    }
+}
+
+impl SitesRetainer for RetainSitesFromList {
+    #[inline]
+    fn retained(&self, contig: &str, range: Range<Position>) -> Vec<Range<Position>> {
+        match self.index.get(contig) {
+            None => vec![],
+            Some(loci) => {
+                let first = loci.partition_point(|x| *x < range.start);
+                // Merge adjacent loci into continuous ranges
+                let mut results: Vec<Range<Position>> = Vec::new();
+                for &pos in loci[first..].iter().take_while(|x| **x < range.end) {
+                    match results.last_mut() {
+                        Some(last) if last.end == pos => last.end += 1,
+                        _ => results.push(pos..pos + 1),
+                    }
+                }
+                results
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use super::*;
+
+    #[test]
+    fn retain_from_list() {
+        let loci = vec![
+            Interval::new("1".into(), 10..11),
+            Interval::new("1".into(), 3..6),
+            Interval::new("1".into(), 5..7),
+            Interval::new("2".into(), 0..1),
+        ];
+        let dummy = RetainSitesFromList::new(loci);
+        assert_eq!(dummy.loci(), 6);
+
+        assert_eq!(dummy.retained("1", 0..100), vec![3..7, 10..11]);
+        assert_eq!(dummy.retained("1", 4..10), vec![4..7]);
+        assert_eq!(dummy.retained("1", 7..10), vec![]);
+        assert_eq!(dummy.retained("2", 0..1), vec![0..1]);
+        assert_eq!(dummy.retained("3", 0..100), vec![]);
+    }
+}
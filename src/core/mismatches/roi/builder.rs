@@ -1,17 +1,35 @@
 use std::iter::zip;
+use std::ops::Range;
 
 use bio_types::genome::{AbstractInterval, Position};
 use bio_types::strand::Strand;
 
-use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::dna::{AltFragments, Multimap, NucCounts, Nucleotide, StrandDepth, WideStrandDepth};
 use crate::core::mismatches::prefilters::retain::ROIRetainer;
-use crate::core::mismatches::prefilters::MismatchesPreFilter;
-use crate::core::mismatches::roi::{ROIData, ROIDataVec, ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::prefilters::{ByReplicates, MismatchesPreFilter};
+use crate::core::mismatches::roi::{
+    group_by_name, ROIData, ROIDataRecord, ROIDataVec, ROIMismatchesVec, ROINucCounts, ROIProfileSite,
+};
 use crate::core::mismatches::{Batch, Builder};
-use crate::core::refpred::{PredNucleotide, RefEngine, RefEngineResult};
-use crate::core::rpileup::ncounter::NucCounterResult;
+use crate::core::refpred::{PredNucleotide, RefEngine, RefEngineResult, RefSource};
+use crate::core::rpileup::ncounter::{AlnStats, InnerNucCounts, NucCounterResult};
 use crate::core::strandutil::Stranded;
 use crate::core::workload::ROI;
+use crate::error::Error;
+
+// Counts aggregated over the ROI subintervals, shared by ROIs with the same geometry
+#[derive(Clone)]
+struct ROISummary {
+    homozygous: NucCounts,
+    mismatches: ROINucCounts,
+    heterozygous: u64,
+    persample: Vec<ROINucCounts>,
+    flank: ROINucCounts,
+    corrected: u64,
+    refnmasked: u64,
+    depth: WideStrandDepth,
+    alt_fragments: u32,
+}
 
 #[derive(Clone)]
 pub struct ROIMismatchesBuilder<RR: ROIRetainer, MP: MismatchesPreFilter<ROIData>> {
@@ -19,6 +37,19 @@ pub struct ROIMismatchesBuilder<RR: ROIRetainer, MP: MismatchesPreFilter<ROIData
     refpred: Box<dyn RefEngine>,
     retainer: Option<RR>,
     prefilter: Option<MP>,
+    // Per-file support required for non-retained ROIs (counts for each source must be available)
+    replicates: Option<ByReplicates>,
+    profile: bool,
+    // Merge ROIs with the same name & strand before the prefiltering
+    group_by_name: bool,
+    // Skip positions with unknown(N) assembly nucleotides & report them as masked
+    skip_refn: bool,
+    // Unstranded counts of ROIs with a known (BED) strand are reported on that strand => they skip the prediction
+    trusted_strand: bool,
+    // ROIs without coverage on all strands are reported once regardless of the prefilters,
+    // zero-coverage records of partially covered ROIs are dropped
+    zero_coverage: bool,
+    multimap: Multimap,
 }
 
 impl<'a, RR, MP> ROIMismatchesBuilder<RR, MP>
@@ -27,47 +58,296 @@ where
     MP: MismatchesPreFilter<ROIData>,
 {
     pub fn new(maxsize: usize, refpred: Box<dyn RefEngine>, retainer: Option<RR>, prefilter: Option<MP>) -> Self {
-        Self { buffer: Vec::with_capacity(maxsize), refpred, retainer, prefilter }
+        Self {
+            buffer: Vec::with_capacity(maxsize),
+            refpred,
+            retainer,
+            prefilter,
+            replicates: None,
+            profile: false,
+            group_by_name: false,
+            skip_refn: false,
+            trusted_strand: false,
+            zero_coverage: false,
+            multimap: Multimap::Full,
+        }
+    }
+
+    // Keep per-position counts for ROIs that pass the prefilter
+    pub fn with_profile(mut self) -> Self {
+        self.profile = true;
+        self
+    }
+
+    // Parts of the same ROI (e.g. exons listed on separate BED lines) must be counted in the same workload
+    pub fn with_grouping(mut self) -> Self {
+        self.group_by_name = true;
+        self
+    }
+
+    pub fn with_refn_skipped(mut self) -> Self {
+        self.skip_refn = true;
+        self
+    }
+
+    pub fn with_trusted_strand(mut self) -> Self {
+        self.trusted_strand = true;
+        self
+    }
+
+    pub fn with_zero_coverage(mut self) -> Self {
+        self.zero_coverage = true;
+        self
+    }
+
+    pub fn with_replicates(mut self, replicates: ByReplicates) -> Self {
+        self.replicates = Some(replicates);
+        self
+    }
+
+    // Convert weighted counts back to natural units, per-position profiles are not converted
+    pub fn with_multimap(mut self, multimap: Multimap) -> Self {
+        self.multimap = multimap;
+        self
+    }
+
+    #[inline]
+    fn skipped(&self, assembly: Nucleotide) -> bool {
+        self.skip_refn && assembly == Nucleotide::Unknown
     }
 
     #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
+        trstrand: Strand,
         cntstart: Position,
         cnts: &'a [NucCounts],
         refpred: &RefEngineResult<'_>,
         roi: &'a ROI,
         coverage: u32,
+        alnstats: AlnStats,
+        depth: Option<&[StrandDepth]>,
+        fragments: Option<&[AltFragments]>,
+        samples: &[&'a [NucCounts]],
         retain: &mut ROIDataVec,
         other: &mut ROIDataVec,
     ) {
-        // Get mismatches
-        let (prednuc, mismatches, heterozygous) = self.summarize(roi, cntstart, refpred.predicted, cnts);
-        let record = ROIData { roi: roi.into(), coverage, homozygous: prednuc, heterozygous, mismatches };
-        if self.retainer.as_ref().map_or(false, |x| x.retained(roi.contig(), &roi.range(), roi.strand(), roi.name())) {
+        let summary = self.summary(roi, cntstart, cnts, refpred, depth, fragments, samples);
+        self.push(trstrand, summary, cntstart, cnts, refpred, roi, coverage, alnstats, retain, other);
+    }
+
+    // Aggregate per-position counts over the ROI subintervals
+    #[allow(clippy::too_many_arguments)]
+    fn summary(
+        &self,
+        roi: &'a ROI,
+        cntstart: Position,
+        cnts: &'a [NucCounts],
+        refpred: &RefEngineResult<'_>,
+        depth: Option<&[StrandDepth]>,
+        fragments: Option<&[AltFragments]>,
+        samples: &[&'a [NucCounts]],
+    ) -> ROISummary {
+        let (homozygous, mismatches, heterozygous) = self.summarize(roi, cntstart, refpred, cnts);
+        ROISummary {
+            homozygous,
+            mismatches,
+            heterozygous,
+            persample: samples.iter().map(|x| self.summarize(roi, cntstart, refpred, x).1).collect(),
+            flank: self.summarize_ranges(&roi.flanks(), cntstart, refpred, cnts).1,
+            corrected: self.corrected(roi, cntstart, refpred),
+            refnmasked: if self.skip_refn { self.refnmasked(roi, cntstart, refpred.reference) } else { 0 },
+            depth: depth.map_or(WideStrandDepth::default(), |x| self.depth(roi, cntstart, x)),
+            alt_fragments: fragments.map_or(0, |x| self.alt_fragments(roi, cntstart, refpred, x)),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &self,
+        trstrand: Strand,
+        summary: ROISummary,
+        cntstart: Position,
+        cnts: &'a [NucCounts],
+        refpred: &RefEngineResult<'_>,
+        roi: &'a ROI,
+        coverage: u32,
+        alnstats: AlnStats,
+        retain: &mut ROIDataVec,
+        other: &mut ROIDataVec,
+    ) -> bool {
+        // Subintervals are copied only for ROIs that survived the prefilter, most ROIs are usually dropped
+        let mut record = self.record(summary, ROIDataRecord::bare(roi), coverage, alnstats);
+        if let Some(saveto) = self.route(trstrand, roi.contig(), &record, retain, other) {
+            record.roi.subintervals = roi.subintervals().into();
+            // Profile is collected only for ROIs that survived the prefilter
+            if self.profile {
+                record.profile = self.profile(roi, cntstart, refpred, cnts);
+            }
+            saveto.push(record);
+            return true;
+        }
+        false
+    }
+
+    fn record(&self, summary: ROISummary, roi: ROIDataRecord, coverage: u32, alnstats: AlnStats) -> ROIData {
+        ROIData {
+            roi,
+            coverage,
+            softclipped: alnstats.softclipped,
+            indels: alnstats.indels,
+            depth: summary.depth,
+            alt_fragments: summary.alt_fragments,
+            homozygous: summary.homozygous,
+            heterozygous: summary.heterozygous,
+            corrected: summary.corrected,
+            refnmasked: summary.refnmasked,
+            mismatches: summary.mismatches,
+            persample: summary.persample,
+            flank: summary.flank,
+            profile: vec![],
+        }
+    }
+
+    // Where to save the record: retained ROIs, other ROIs that pass the prefilters, or nowhere
+    fn route<'b>(
+        &self,
+        trstrand: Strand,
+        contig: &str,
+        record: &ROIData,
+        retain: &'b mut ROIDataVec,
+        other: &'b mut ROIDataVec,
+    ) -> Option<&'b mut ROIDataVec> {
+        let roi = &record.roi;
+        let retained =
+            self.retainer.as_ref().map_or(false, |x| x.retained(contig, &roi.postmasked, roi.strand, &roi.name));
+        if retained {
             // Must be retained
-            retain.push(record);
-        } else if self.prefilter.as_ref().map_or(true, |x| x.is_ok(&record)) {
+            Some(retain)
+        } else if self.zero_coverage && record.mismatches.coverage() == 0f64 {
+            // Reported once per ROI, see uncovered()
+            None
+        } else if self.prefilter.as_ref().map_or(true, |x| x.is_ok(record, trstrand))
+            && self.replicates.as_ref().map_or(true, |x| x.is_ok(record, trstrand))
+        {
             // Must be other
-            other.push(record);
+            Some(other)
+        } else {
+            None
         }
     }
 
+    fn corrected(&self, roi: &'a ROI, cntstart: Position, refpred: &RefEngineResult<'_>) -> u64 {
+        let mut corrected = 0;
+        for sub in roi.subintervals() {
+            let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
+            for (assembly, predicted) in zip(&refpred.reference[idx.clone()], &refpred.predicted[idx]) {
+                if !self.skipped(*assembly) && RefSource::new(*assembly, *predicted) == RefSource::Corrected {
+                    corrected += 1;
+                }
+            }
+        }
+        corrected
+    }
+
+    fn depth(&self, roi: &'a ROI, cntstart: Position, depth: &[StrandDepth]) -> WideStrandDepth {
+        let mut total = WideStrandDepth::default();
+        for sub in roi.subintervals() {
+            let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
+            for x in &depth[idx] {
+                total += (*x).into();
+            }
+        }
+        total
+    }
+
+    // Max per-position value, positions with unknown number of fragments are ignored
+    fn alt_fragments(
+        &self,
+        roi: &'a ROI,
+        cntstart: Position,
+        refpred: &RefEngineResult<'_>,
+        fragments: &[AltFragments],
+    ) -> u32 {
+        let mut max = 0;
+        for sub in roi.subintervals() {
+            for idx in (sub.start - cntstart) as usize..(sub.end - cntstart) as usize {
+                let (assembly, predicted) = (refpred.reference[idx], &refpred.predicted[idx]);
+                if self.skipped(assembly) {
+                    continue;
+                }
+                if let Some(alt) = fragments[idx].alt(assembly, predicted) {
+                    max = max.max(alt);
+                }
+            }
+        }
+        max
+    }
+
+    fn refnmasked(&self, roi: &'a ROI, cntstart: Position, reference: &[Nucleotide]) -> u64 {
+        let mut refnmasked = 0;
+        for sub in roi.subintervals() {
+            let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
+            refnmasked += reference[idx].iter().filter(|x| **x == Nucleotide::Unknown).count() as u64;
+        }
+        refnmasked
+    }
+
+    fn profile(
+        &self,
+        roi: &'a ROI,
+        cntstart: Position,
+        refpred: &RefEngineResult<'_>,
+        cnts: &'a [NucCounts],
+    ) -> Vec<ROIProfileSite> {
+        let mut profile = Vec::new();
+        for sub in roi.subintervals() {
+            let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
+            let refnuc = zip(&refpred.reference[idx.clone()], &refpred.predicted[idx.clone()]);
+            for (pos, ((assembly, nuc), seq)) in zip(sub.clone(), zip(refnuc, &cnts[idx])) {
+                if self.skipped(*assembly) {
+                    continue;
+                }
+                // Same as for summaries: skip unknown and heterozygous positions
+                if let PredNucleotide::Homozygous(nuc) = nuc {
+                    if *nuc != Nucleotide::Unknown && seq.mismatches(*nuc) > 0 {
+                        profile.push(ROIProfileSite { pos, refnuc: *nuc, sequenced: *seq });
+                    }
+                }
+            }
+        }
+        profile
+    }
+
     fn summarize(
         &self,
         roi: &'a ROI,
         cntstart: Position,
-        prednuc: &'a [PredNucleotide],
+        refpred: &RefEngineResult<'_>,
         cnts: &'a [NucCounts],
     ) -> (NucCounts, ROINucCounts, u64) {
         debug_assert!(roi.range().start >= cntstart && roi.range().end <= (cntstart + cnts.len() as u64));
+        self.summarize_ranges(roi.subintervals(), cntstart, refpred, cnts)
+    }
+
+    fn summarize_ranges(
+        &self,
+        ranges: &[Range<Position>],
+        cntstart: Position,
+        refpred: &RefEngineResult<'_>,
+        cnts: &'a [NucCounts],
+    ) -> (NucCounts, ROINucCounts, u64) {
         let mut mismatches = ROINucCounts::zeros();
         let mut nuccnts = NucCounts::zeros();
         let mut heterozygous = 0;
 
-        for sub in roi.subintervals() {
+        for sub in ranges {
             let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
-            for (nuc, seq) in zip(&prednuc[idx.clone()], &cnts[idx]) {
+            let refnuc = zip(&refpred.reference[idx.clone()], &refpred.predicted[idx.clone()]);
+            for ((assembly, nuc), seq) in zip(refnuc, &cnts[idx]) {
+                if self.skipped(*assembly) {
+                    continue;
+                }
                 match nuc {
                     PredNucleotide::Homozygous(nuc) => match nuc {
                         Nucleotide::A => {
@@ -121,14 +401,52 @@ where
             }
         }
 
+        if self.multimap != Multimap::Full {
+            mismatches = mismatches.scaled(1f64 / self.multimap.scale() as f64);
+        }
         (nuccnts, mismatches, heterozygous)
     }
 
+    #[inline]
+    fn trstrand(&self, strand: Strand, roi: &ROI) -> Strand {
+        match (strand, self.trusted_strand) {
+            (Strand::Unknown, true) => roi.strand(),
+            _ => strand,
+        }
+    }
+
+    // Zeroed record for ROIs without coverage on all strands, reported on the trusted (BED) or unknown strand
+    fn uncovered(
+        &self,
+        item: &InnerNucCounts<'a, &'a ROI>,
+        summaries: &Stranded<Option<ROISummary>>,
+    ) -> Option<ROIData> {
+        let mut first = None;
+        for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            if let (Some(_), Some(x)) = (item.cnts[strand], &summaries[strand]) {
+                if x.mismatches.coverage() > 0f64 {
+                    return None;
+                }
+                first.get_or_insert((strand, x));
+            }
+        }
+        let (strand, summary) = first?;
+        let roi = ROIDataRecord::bare(item.data);
+        let mut record = self.record(summary.clone(), roi, item.coverage[strand], item.alnstats[strand]);
+        record.roi.subintervals = item.data.subintervals().into();
+        Some(record)
+    }
+
+    // Number of records for each transcription strand if all ROIs are reported
     #[inline]
     fn size_hint(&self, nc: &NucCounterResult<'a, &'a ROI>) -> Stranded<usize> {
         let mut size: Stranded<usize> = Stranded::default();
-        for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
-            size[strand] = nc.cnts.iter().map(|item| item.cnts[strand].is_some() as usize).sum()
+        for item in &nc.cnts {
+            for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+                if item.cnts[strand].is_some() {
+                    size[self.trstrand(strand, item.data)] += 1;
+                }
+            }
         }
         size
     }
@@ -142,43 +460,566 @@ where
     type Out = ROIMismatchesVec;
     type SourceCounts = NucCounterResult<'a, &'a ROI>;
 
-    fn build(&mut self, nc: Self::SourceCounts) -> Batch<Self::Out> {
+    fn build(&mut self, nc: Self::SourceCounts) -> Result<Batch<Self::Out>, Error> {
         let contig = nc.contig.to_owned();
 
-        // Pre-allocate results
-        let _hint = self.size_hint(&nc);
+        // Pre-allocate results: all ROIs are reported without prefilters, otherwise most of them are usually dropped
+        // and the reserved memory would be held until the whole contig is written
+        let hint = match (&self.prefilter, &self.replicates, self.group_by_name) {
+            (None, None, false) => self.size_hint(&nc),
+            _ => Stranded::default(),
+        };
         let mut items = Stranded::with_fn(|strnd| {
-            ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::new())
-            // ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::with_capacity(hint[strnd]))
-        });
-        let mut retained = Stranded::with_fn(|strnd| {
-            ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::new())
-            // ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::with_capacity(hint[strnd] / 10))
+            ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::with_capacity(hint[strnd]))
         });
+        let mut retained = Stranded::with_fn(|strnd| ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::new()));
 
+        let mut refn_skipped = 0;
+        // Overlapping ROIs are projected from the same counting window. Consecutive ROIs with the same range
+        // (e.g. duplicated or isoform-level records) also share the predicted reference,
+        // and the summaries are reused if subintervals are the same as well
+        let mut previous: Option<(Range<Position>, &'a ROI)> = None;
+        let mut summaries: Stranded<Option<ROISummary>> = Stranded::default();
+        // Parts of grouped ROIs, they are filtered only after the merge
+        let mut parts: Stranded<Vec<ROIData>> = Stranded::default();
         for item in nc.cnts.into_iter() {
             // debug_assert!(item.coverage.forward + item.coverage.reverse + item.coverage.unknown > 0);
+            let samerange = previous.as_ref().map_or(false, |(range, _)| *range == item.range);
+            let samerois =
+                samerange && previous.as_ref().map_or(false, |(_, x)| x.subintervals() == item.data.subintervals());
 
             // Predict the reference
-            let counts = item.seqnuc(&mut self.buffer).unwrap_or(&self.buffer);
-            self.refpred.run(&contig, item.range.clone(), counts);
+            if !samerange {
+                let counts = item.seqnuc(&mut self.buffer).unwrap_or(&self.buffer);
+                self.refpred.run(&contig, item.range.clone(), counts)?;
+            }
+            if !samerois {
+                summaries = Stranded::default();
+            }
             let refpred = self.refpred.results();
+            if self.skip_refn {
+                refn_skipped += self.refnmasked(item.data, item.range.start, refpred.reference);
+            }
 
             // Process the counts
+            let mut reported = false;
             for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
                 if let Some(cnts) = item.cnts[strand] {
-                    self.process(
+                    let trstrand = self.trstrand(strand, item.data);
+                    let summary = summaries[strand].get_or_insert_with(|| {
+                        self.summary(
+                            item.data,
+                            item.range.start,
+                            cnts,
+                            &refpred,
+                            item.depth[strand],
+                            item.fragments[strand],
+                            &item.samples[strand],
+                        )
+                    });
+                    if self.group_by_name {
+                        let (coverage, alnstats) = (item.coverage[strand], item.alnstats[strand]);
+                        let mut record = self.record(summary.clone(), item.data.into(), coverage, alnstats);
+                        if self.profile {
+                            record.profile = self.profile(item.data, item.range.start, &refpred, cnts);
+                        }
+                        parts[trstrand].push(record);
+                        continue;
+                    }
+                    reported |= self.push(
+                        trstrand,
+                        summary.clone(),
                         item.range.start,
                         cnts,
                         &refpred,
                         item.data,
                         item.coverage[strand],
-                        &mut retained[strand].data,
-                        &mut items[strand].data,
+                        item.alnstats[strand],
+                        &mut retained[trstrand].data,
+                        &mut items[trstrand].data,
                     );
                 }
             }
+            if self.zero_coverage && !reported && !self.group_by_name {
+                if let Some(record) = self.uncovered(&item, &summaries) {
+                    items[self.trstrand(Strand::Unknown, item.data)].data.push(record);
+                }
+            }
+            previous = Some((item.range, item.data));
+        }
+        // Merged before the prefiltering => filters & hooks (e.g. EI) see whole ROIs only
+        for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
+            for record in group_by_name(std::mem::take(&mut parts[strand])) {
+                if let Some(saveto) =
+                    self.route(strand, &contig, &record, &mut retained[strand].data, &mut items[strand].data)
+                {
+                    saveto.push(record);
+                }
+            }
+        }
+        Ok(Batch {
+            contig,
+            mapped: nc.mapped,
+            mapq_255_dropped: nc.mapq_255_dropped,
+            umi_collapsed: nc.umi_collapsed,
+            coverage_capped: nc.coverage_capped,
+            refn_skipped,
+            // Overlapping ROIs would be counted several times
+            covered: 0,
+            substitutions: Default::default(),
+            junctions: None,
+            retained,
+            items,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+    use bio_types::strand::ReqStrand;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    use crate::core::dna::ReqNucleotide;
+    use crate::core::io::bed::BedRecord;
+    use crate::core::io::fasta::MockFastaReader;
+    use crate::core::mismatches::prefilters::retain::RetainROIFromList;
+    use crate::core::mismatches::prefilters::ByMismatches;
+    use crate::core::read::MockRead;
+    use crate::core::refpred::AutoRef;
+    use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, ExcludedRegions, ROINucCounter};
+    use crate::core::rpileup::ncounter::filters::{ByQuality, MapQ255};
+    use crate::core::rpileup::ReadsCollider;
+    use crate::core::stranding::predict::algo::StrandByAtoIEditing;
+    use crate::core::stranding::predict::{REATStrandingEngine, StrandingEngine};
+    use crate::core::testkit;
+    use crate::core::workload::ROIWorkload;
+
+    use super::*;
+
+    #[test]
+    fn refn_policy() {
+        use Nucleotide::{Unknown as N, A, C, G, T};
+
+        // Second half of the ROI is N in the assembly, two of them are corrected by the reference predictor
+        let roi = ROI::new("chr1".into(), 10..18, vec![10..18], "roi".into(), Strand::Forward);
+        let reference = [A, C, G, T, N, N, N, N];
+        let predicted = [A, C, G, T, G, G, N, N].map(PredNucleotide::Homozygous);
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        let cnts = [NucCounts::G(2); 8];
+
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(8, refengine, None, None);
+        for (builder, homozygous, matches, refnmasked) in [
+            (builder.clone(), NucCounts::new(1, 1, 3, 1), 6f64, 0),
+            (builder.with_refn_skipped(), NucCounts::new(1, 1, 1, 1), 2f64, 4),
+        ] {
+            let (mut retained, mut other) = (ROIDataVec::new(), ROIDataVec::new());
+            builder.process(
+                Strand::Forward,
+                10,
+                &cnts,
+                &refpred,
+                &roi,
+                2,
+                Default::default(),
+                None,
+                None,
+                &[],
+                &mut retained,
+                &mut other,
+            );
+            assert!(retained.is_empty());
+            assert_eq!(other.len(), 1);
+
+            assert_eq!(other.homozygous[0], homozygous);
+            assert_eq!(other.refnmasked[0], refnmasked);
+            let mismatches = &other.mismatches[0];
+            assert_eq!((mismatches.A.G, mismatches.C.G, mismatches.G.G, mismatches.T.G), (2f64, 2f64, matches, 2f64));
+        }
+    }
+    #[test]
+    fn replicates() {
+        use Nucleotide::{A, C};
+
+        let roi = ROI::new("chr1".into(), 0..4, vec![0..4], "roi".into(), Strand::Forward);
+        let reference = [A, A, C, C];
+        let predicted = reference.map(PredNucleotide::Homozygous);
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        // Pooled: 4 A->G mismatches, all of them come from the first file
+        let first = [NucCounts::G(2), NucCounts::G(2), NucCounts::C(2), NucCounts::C(2)];
+        let second = [NucCounts::A(2), NucCounts::A(2), NucCounts::C(2), NucCounts::C(2)];
+        let cnts = [NucCounts::new(2, 0, 2, 0), NucCounts::new(2, 0, 2, 0), NucCounts::C(4), NucCounts::C(4)];
+        let samples: [&[NucCounts]; 2] = [&first, &second];
+
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(4, refengine, None, None);
+        for (replicates, passed) in [(ByReplicates::new(4, 1), true), (ByReplicates::new(1, 2), false)] {
+            let builder = builder.clone().with_replicates(replicates);
+            let (mut retained, mut other) = (ROIDataVec::new(), ROIDataVec::new());
+            builder.process(
+                Strand::Forward,
+                0,
+                &cnts,
+                &refpred,
+                &roi,
+                4,
+                Default::default(),
+                None,
+                None,
+                &samples,
+                &mut retained,
+                &mut other,
+            );
+            assert!(retained.is_empty());
+            assert_eq!(other.len(), passed as usize);
+            if passed {
+                // Pooled counts are reported as is
+                assert_eq!(other.mismatches[0].A.G, 4f64);
+            }
         }
-        Batch { contig, mapped: nc.mapped, retained, items }
+    }
+    #[test]
+    fn bed12() {
+        use Nucleotide::A;
+
+        // Transcript with two exons, 0..3 and 7..10, the intron is full of mismatches
+        let transcript = BedRecord {
+            name: "transcript".into(),
+            strand: Strand::Forward,
+            interval: Interval::new("chr1".into(), 0..10),
+            blocks: vec![0..3, 7..10],
+        };
+        let workload = ROIWorkload::from_bed(vec![transcript], 100, None, None, None);
+        let roi = &workload[0].rois()[0];
+        assert_eq!((roi.premasked(), roi.subintervals()), (0..10, &[0..3, 7..10][..]));
+
+        let reference = [A; 10];
+        let predicted = reference.map(PredNucleotide::Homozygous);
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        let mut cnts = [NucCounts::G(1); 10];
+        cnts[3..7].fill(NucCounts::G(5));
+
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(10, refengine, None, None);
+        let (mut retained, mut other) = (ROIDataVec::new(), ROIDataVec::new());
+        builder.process(
+            Strand::Forward,
+            0,
+            &cnts,
+            &refpred,
+            roi,
+            1,
+            Default::default(),
+            None,
+            None,
+            &[],
+            &mut retained,
+            &mut other,
+        );
+        assert_eq!(other.len(), 1);
+
+        let record = other.iter().next().unwrap();
+        assert_eq!(*record.homozygous, NucCounts::A(6));
+        assert_eq!((record.mismatches.A.G, record.mismatches.mismatches()), (6f64, 6f64));
+        // Introns are not masked
+        assert_eq!(record.roi.nucmasked(), 0);
+    }
+
+    #[test]
+    fn excluded() {
+        use std::sync::Arc;
+
+        let read = |pos: i64, seq: &'static str| {
+            let mut read = MockRead::new();
+            read.expect_mapq().return_const(60);
+            read.expect_flags().return_const(0u16);
+            read.expect_contig().return_const("chr1".to_owned());
+            read.expect_pos().return_const(pos);
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().returning(move || CigarString(vec![Cigar::Match(seq.len() as u32)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read.expect_base_qual().return_const(30);
+            read
+        };
+        // Only the second half of the ROI is edited, and it's excluded
+        let reads = [read(0, "AAAAAGGGGG"), read(0, "AAAAAGGGGG"), read(5, "GGGGG")];
+        let excluded = Arc::new(ExcludedRegions::new(vec![Interval::new("chr1".into(), 5..10)]));
+
+        // No exclusion, excluded only during counting, excluded from the workload as well (same as the CLI)
+        for (subintervals, masked, coverage, a2g, nucmasked) in [
+            (vec![0..10], None, 3, 15f64, 0),
+            (vec![0..10], Some(excluded.clone()), 2, 0f64, 0),
+            (vec![0..5], Some(excluded.clone()), 2, 0f64, 5),
+        ] {
+            let end = subintervals.last().unwrap().end;
+            let roi = ROI::new("chr1".into(), 0..10, subintervals, "roi".into(), Strand::Unknown);
+
+            let mut base = BaseNucCounter::new(10, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+            if let Some(masked) = masked {
+                base = base.with_excluded(masked);
+            }
+            let mut counter = ROINucCounter::new(base);
+            counter.reset(ROIWorkload::new(Interval::new("chr1".into(), 0..end), vec![roi])).unwrap();
+            for read in &reads {
+                counter.collide(read);
+            }
+            counter.finalize();
+
+            let mut reader = MockFastaReader::new();
+            reader.expect_fetch().returning(|_, _| Ok(()));
+            reader.expect_result().return_const(vec![Nucleotide::A; end as usize]);
+            let refengine = Box::new(AutoRef::new(1000, 1f32, false, Box::new(reader)));
+            let mut builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(10, refengine, None, None);
+            let items = builder.build(counter.result()).unwrap().items;
+
+            let record = items.unknown.data.iter().next().unwrap();
+            assert_eq!(*record.coverage, coverage);
+            assert_eq!((record.mismatches.A.A, record.mismatches.A.G), (10f64, a2g));
+            assert_eq!(record.roi.nucmasked(), nucmasked);
+        }
+    }
+
+    #[test]
+    fn flanks() {
+        use Nucleotide::A;
+
+        // Strong editing in the upstream flank, none in the ROI itself
+        let roi = ROI::new("chr1".into(), 10..14, vec![10..14], "roi".into(), Strand::Forward).with_flanks(3, 100);
+        assert_eq!(roi.flanked(), 7..17);
+        let reference = [A; 10];
+        let predicted = reference.map(PredNucleotide::Homozygous);
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        let mut cnts = [NucCounts::A(10); 10];
+        cnts[1] = NucCounts::new(2, 0, 8, 0);
+
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(10, refengine, None, None);
+        let (mut retained, mut other) = (ROIDataVec::new(), ROIDataVec::new());
+        builder.process(
+            Strand::Forward,
+            7,
+            &cnts,
+            &refpred,
+            &roi,
+            10,
+            Default::default(),
+            None,
+            None,
+            &[],
+            &mut retained,
+            &mut other,
+        );
+        assert_eq!(other.len(), 1);
+
+        let record = other.iter().next().unwrap();
+        assert_eq!((record.roi.premasked.clone(), record.roi.postmasked.clone()), (10..14, 10..14));
+        assert_eq!(*record.homozygous, NucCounts::A(4));
+        assert_eq!((record.mismatches.A.A, record.mismatches.mismatches()), (40f64, 0f64));
+        assert_eq!((record.flank.A.A, record.flank.A.G, record.flank.coverage()), (52f64, 8f64, 60f64));
+        assert_eq!(record.flank_a2g_freq(Strand::Forward), 8f32 / 60f32);
+    }
+
+    #[test]
+    fn trusted_strand() {
+        // Reverse strand ROI with apparent A->G editing => the editing predictor calls it forward
+        let rois = [
+            ROI::new("chr1".into(), 0..4, vec![0..4], "reverse".into(), Strand::Reverse),
+            ROI::new("chr1".into(), 0..4, vec![0..4], "unstranded".into(), Strand::Unknown),
+        ];
+        let cnts = [NucCounts::new(5, 0, 5, 0); 4];
+        let mut stranding = REATStrandingEngine::new();
+        stranding.add(Box::new(StrandByAtoIEditing::new(8, 0.05)));
+
+        for (trusted, forward, reverse) in
+            [(true, vec!["unstranded"], vec!["reverse"]), (false, vec!["reverse", "unstranded"], vec![])]
+        {
+            let mut reader = MockFastaReader::new();
+            reader.expect_fetch().returning(|_, _| Ok(()));
+            reader.expect_result().return_const(vec![Nucleotide::A; 4]);
+            let refengine = Box::new(AutoRef::new(1000, 1f32, false, Box::new(reader)));
+            let mut builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(4, refengine, None, None);
+            if trusted {
+                builder = builder.with_trusted_strand();
+            }
+
+            let counts = |roi| InnerNucCounts {
+                data: roi,
+                range: 0..4,
+                cnts: Stranded::unknown(Some(&cnts[..])),
+                samples: Stranded::unknown(vec![]),
+                coverage: Stranded::unknown(10),
+                alnstats: Default::default(),
+                depth: Stranded::unknown(None),
+                qualities: Stranded::unknown(None),
+                fragments: Stranded::unknown(None),
+            };
+            let nc = NucCounterResult {
+                contig: "chr1",
+                mapped: Stranded::unknown(10),
+                mapq_255_dropped: 0,
+                umi_collapsed: 0,
+                coverage_capped: 0,
+                junctions: None,
+                cnts: rois.iter().map(counts).collect(),
+            };
+            let items = stranding.strand("chr1", builder.build(nc).unwrap().items);
+            // Unstranded ROIs are always predicted
+            assert!(items.unknown.is_empty());
+            let names = |x: &ROIMismatchesVec| x.data.roi.name.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            assert_eq!(names(&items.forward), forward);
+            assert_eq!(names(&items.reverse), reverse);
+        }
+    }
+
+    #[test]
+    fn zero_coverage() {
+        let rois = [
+            ROI::new("chr1".into(), 0..4, vec![0..4], "covered".into(), Strand::Forward),
+            ROI::new("chr1".into(), 0..4, vec![0..4], "uncovered".into(), Strand::Reverse),
+        ];
+        let (covered, uncovered) = ([NucCounts::A(10); 4], [NucCounts::default(); 4]);
+
+        for (zero_coverage, forward, reverse) in [
+            (false, vec!["covered", "uncovered"], vec!["covered", "uncovered"]),
+            // Zero-coverage records are dropped, but uncovered ROIs are reported once on their BED strand
+            (true, vec!["covered"], vec!["uncovered"]),
+        ] {
+            let mut reader = MockFastaReader::new();
+            reader.expect_fetch().returning(|_, _| Ok(()));
+            reader.expect_result().return_const(vec![Nucleotide::A; 4]);
+            let refengine = Box::new(AutoRef::new(1000, 1f32, false, Box::new(reader)));
+            let mut builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(4, refengine, None, None)
+                .with_trusted_strand();
+            if zero_coverage {
+                builder = builder.with_zero_coverage();
+            }
+
+            let counts = |roi: &ROI| {
+                let forward = if roi.name() == "covered" { &covered[..] } else { &uncovered[..] };
+                InnerNucCounts {
+                    data: roi,
+                    range: 0..4,
+                    cnts: Stranded { forward: Some(forward), reverse: Some(&uncovered[..]), unknown: None },
+                    samples: Stranded::default(),
+                    coverage: Stranded { forward: forward[0].coverage(), reverse: 0, unknown: 0 },
+                    alnstats: Default::default(),
+                    depth: Stranded::default(),
+                    qualities: Stranded::default(),
+                    fragments: Stranded::default(),
+                }
+            };
+            let nc = NucCounterResult {
+                contig: "chr1",
+                mapped: Stranded { forward: 10, reverse: 0, unknown: 0 },
+                mapq_255_dropped: 0,
+                umi_collapsed: 0,
+                coverage_capped: 0,
+                junctions: None,
+                cnts: rois.iter().map(counts).collect(),
+            };
+            let items = builder.build(nc).unwrap().items;
+            let names = |x: &ROIMismatchesVec| x.data.roi.name.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            assert_eq!(names(&items.forward), forward);
+            assert_eq!(names(&items.reverse), reverse);
+            assert!(items.unknown.is_empty());
+            if zero_coverage {
+                assert_eq!(items.reverse.data.coverage, vec![0]);
+                assert_eq!(items.reverse.data.roi.subintervals, vec![vec![0..4]]);
+            }
+        }
+    }
+
+    #[test]
+    fn allocations() {
+        // Previously each record cost 2 allocations (name & subintervals) even if the ROI was dropped right away,
+        // plus the vec growth. Now names are shared with the workload and subintervals are copied for reported ROIs only
+        let rois: Vec<ROI> = (0..200)
+            .map(|ind| ROI::new("chr1".into(), 0..4, vec![0..2, 3..4], format!("roi{}", ind), Strand::Forward))
+            .collect();
+        let cnts = [NucCounts::A(10); 4];
+        let build = |prefilter: Option<ByMismatches>, rois: &[ROI]| {
+            let mut reader = MockFastaReader::new();
+            reader.expect_fetch().returning(|_, _| Ok(()));
+            reader.expect_result().return_const(vec![Nucleotide::A; 4]);
+            let refengine = Box::new(AutoRef::new(1000, 1f32, false, Box::new(reader)));
+            let mut builder = ROIMismatchesBuilder::<RetainROIFromList, _>::new(4, refengine, None, prefilter);
+
+            let counts = |roi| InnerNucCounts {
+                data: roi,
+                range: 0..4,
+                cnts: Stranded::unknown(Some(&cnts[..])),
+                samples: Stranded::unknown(vec![]),
+                coverage: Stranded::unknown(10),
+                alnstats: Default::default(),
+                depth: Stranded::unknown(None),
+                qualities: Stranded::unknown(None),
+                fragments: Stranded::unknown(None),
+            };
+            let nc = NucCounterResult {
+                contig: "chr1",
+                mapped: Stranded::unknown(10),
+                mapq_255_dropped: 0,
+                umi_collapsed: 0,
+                coverage_capped: 0,
+                junctions: None,
+                cnts: rois.iter().map(counts).collect(),
+            };
+            let (allocations, batch) = testkit::allocations(|| builder.build(nc).unwrap());
+            (allocations, batch.items.unknown.data.len())
+        };
+
+        // Dropped ROIs don't allocate at all. Warm up first: lazily initialized thread-locals may allocate once
+        let prefilter = || Some(ByMismatches::new(5, 0.01, 20, false));
+        build(prefilter(), &rois[..1]);
+        let (dropped, reported) = build(prefilter(), &rois[..100]);
+        assert_eq!(reported, 0);
+        assert_eq!(build(prefilter(), &rois).0, dropped);
+
+        // Reported ROIs: a single allocation for the subintervals, results are allocated up front
+        let (half, reported) = build(None, &rois[..100]);
+        assert_eq!(reported, 100);
+        let (all, reported) = build(None, &rois);
+        assert_eq!(reported, 200);
+        assert_eq!(all - half, 100);
+    }
+
+    #[test]
+    fn depth() {
+        let roi = ROI::new("chr1".into(), 10..14, vec![10..12, 13..14], "roi".into(), Strand::Forward);
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(4, refengine, None, None);
+
+        // Near-max per-position depth doesn't wrap around when summed over the ROI
+        let depth = [StrandDepth::new(u32::MAX, 1); 4];
+        let total = builder.depth(&roi, 10, &depth);
+        assert_eq!(total, WideStrandDepth::new(3 * u32::MAX as u64, 3));
+    }
+
+    #[test]
+    fn alt_fragments() {
+        use Nucleotide::{A, G};
+
+        let roi = ROI::new("chr1".into(), 10..14, vec![10..12, 13..14], "roi".into(), Strand::Forward);
+        let refengine = Box::new(AutoRef::new(0, 0f32, false, Box::new(MockFastaReader::new())));
+        let builder = ROIMismatchesBuilder::<RetainROIFromList, ByMismatches>::new(4, refengine, None, None);
+
+        let reference = [A, A, A, A];
+        let mut predicted = reference.map(PredNucleotide::Homozygous);
+        let mut fragments = vec![AltFragments::default(); 4];
+        for (pos, total) in [(0, 1), (1, 2), (2, 5), (3, 3)] {
+            for fragment in 0..total {
+                fragments[pos].add(ReqNucleotide::G, fragment);
+            }
+        }
+        // Max over the subintervals, the masked position 12 is ignored
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        assert_eq!(builder.alt_fragments(&roi, 10, &refpred, &fragments), 3);
+
+        // Unknown values are ignored as well
+        predicted[3] = PredNucleotide::Homozygous(G);
+        let refpred = RefEngineResult { predicted: &predicted, reference: &reference };
+        assert_eq!(builder.alt_fragments(&roi, 10, &refpred, &fragments), 2);
     }
 }
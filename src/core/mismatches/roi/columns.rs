@@ -0,0 +1,337 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::ser::SerializeStruct;
+
+use crate::core::dna::ReqNucleotide;
+use crate::core::io::table::ColumnType;
+use crate::core::mismatches::FilterLabels;
+
+use super::vec::SerializeROIRef;
+
+const NUCLEOTIDES: [ReqNucleotide; 4] = [ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T];
+
+const HOMOZYGOUS: [&str; 4] = ["#A", "#C", "#G", "#T"];
+
+const MISMATCHES: [[&str; 4]; 4] = [
+    ["A->A", "A->C", "A->G", "A->T"],
+    ["C->A", "C->C", "C->G", "C->T"],
+    ["G->A", "G->C", "G->G", "G->T"],
+    ["T->A", "T->C", "T->G", "T->T"],
+];
+
+// Column of the main ROI table: the header name & how to extract the value for a given ROI
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ROIColumn {
+    Contig,
+    Start,
+    End,
+    Strand,
+    Name,
+    TrStrand,
+    Coverage,
+    NucMasked,
+    Heterozygous,
+    Corrected,
+    // Number of homozygous positions with the given reference
+    Homozygous(ReqNucleotide),
+    // Reference => sequenced nucleotide
+    Mismatches(ReqNucleotide, ReqNucleotide),
+    A2GFreq,
+    BackgroundFreq,
+    A2GEnrichment,
+    SoftclipFreq,
+    IndelFreq,
+    FwdDepth,
+    RevDepth,
+    StrandBias,
+    // Overlapping genes & the highest-priority feature from the user-provided annotation
+    Genes,
+    Feature,
+    // Wilson score interval of the A->G frequency at the user-provided confidence level
+    CiLow,
+    CiHigh,
+    // Max number of distinct fragments with mismatches at a single ROI position
+    AltFragments,
+    // Sequenced bases & A->G mismatches (relative to the transcription strand) in the ROI flanks
+    FlankCoverage,
+    FlankA2G,
+    FlankA2GFreq,
+    // PASS or the failed soft filters
+    Filter,
+    // Name of the run, e.g. to concatenate tables of different samples
+    Sample,
+}
+
+impl ROIColumn {
+    // Default columns, optional groups can be disabled to keep the old layout
+    pub fn layout(background: bool, corrected: bool, alnstats: bool, strand_depth: bool) -> Vec<Self> {
+        let mut columns = vec![
+            Self::Contig,
+            Self::Start,
+            Self::End,
+            Self::Strand,
+            Self::Name,
+            Self::TrStrand,
+            Self::Coverage,
+            Self::NucMasked,
+            Self::Heterozygous,
+        ];
+        if corrected {
+            columns.push(Self::Corrected);
+        }
+        for reference in NUCLEOTIDES {
+            columns.push(Self::Homozygous(reference));
+            columns.extend(NUCLEOTIDES.map(|sequenced| Self::Mismatches(reference, sequenced)));
+        }
+        if background {
+            columns.extend([Self::A2GFreq, Self::BackgroundFreq, Self::A2GEnrichment]);
+        }
+        if alnstats {
+            columns.extend([Self::SoftclipFreq, Self::IndelFreq]);
+        }
+        if strand_depth {
+            columns.extend([Self::FwdDepth, Self::RevDepth, Self::StrandBias]);
+        }
+        columns
+    }
+
+    pub fn all() -> Vec<Self> {
+        Self::layout(true, true, true, true)
+    }
+
+    // Columns reported only with the genome annotation
+    pub fn annotation() -> [Self; 2] {
+        [Self::Genes, Self::Feature]
+    }
+
+    // Columns reported only with the confidence level
+    pub fn ci() -> [Self; 2] {
+        [Self::CiLow, Self::CiHigh]
+    }
+
+    // Columns reported only with the fragments tracking
+    pub fn fragments() -> [Self; 1] {
+        [Self::AltFragments]
+    }
+
+    // Columns reported only with the ROI flanks
+    pub fn flanks() -> [Self; 3] {
+        [Self::FlankCoverage, Self::FlankA2G, Self::FlankA2GFreq]
+    }
+
+    // Columns reported only with the soft filters
+    pub fn filters() -> [Self; 1] {
+        [Self::Filter]
+    }
+
+    // Columns reported only if rows are tagged with the run name
+    pub fn tag() -> [Self; 1] {
+        [Self::Sample]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Contig => "contig",
+            Self::Start => "start",
+            Self::End => "end",
+            Self::Strand => "strand",
+            Self::Name => "name",
+            Self::TrStrand => "trstrand",
+            Self::Coverage => "coverage",
+            Self::NucMasked => "nucmasked",
+            Self::Heterozygous => "heterozygous",
+            Self::Corrected => "corrected",
+            Self::Homozygous(nuc) => HOMOZYGOUS[*nuc as usize],
+            Self::Mismatches(reference, sequenced) => MISMATCHES[*reference as usize][*sequenced as usize],
+            Self::A2GFreq => "A->G-freq",
+            Self::BackgroundFreq => "background-freq",
+            Self::A2GEnrichment => "A->G-enrichment",
+            Self::SoftclipFreq => "softclip-freq",
+            Self::IndelFreq => "indel-freq",
+            Self::FwdDepth => "fwd_depth",
+            Self::RevDepth => "rev_depth",
+            Self::StrandBias => "strand_bias",
+            Self::Genes => "genes",
+            Self::Feature => "feature",
+            Self::CiLow => "ci_low",
+            Self::CiHigh => "ci_high",
+            Self::AltFragments => "alt_fragments",
+            Self::FlankCoverage => "flank_coverage",
+            Self::FlankA2G => "flank_A->G",
+            Self::FlankA2GFreq => "flank_A->G-freq",
+            Self::Filter => "filter",
+            Self::Sample => "sample",
+        }
+    }
+
+    // Human-readable description, e.g. for the `reat schema` dumps
+    pub fn description(&self) -> String {
+        match self {
+            Self::Contig => "ROI contig".into(),
+            Self::Start => "0-based ROI start from the input file".into(),
+            Self::End => "ROI end (exclusive) from the input file".into(),
+            Self::Strand => "ROI strand from the input file".into(),
+            Self::Name => "ROI name from the input file".into(),
+            Self::TrStrand => "Transcription strand, predicted or deducted from the experiment design".into(),
+            Self::Coverage => "Number of unique reads covering the ROI".into(),
+            Self::NucMasked => "Number of ROI positions masked by the exclude list, --mask or reference Ns".into(),
+            Self::Heterozygous => "Number of heterozygous ROI positions".into(),
+            Self::Corrected => "Number of ROI positions with the reference corrected by Autoref or VCF".into(),
+            Self::Homozygous(nuc) => format!("Number of {:?} nucleotides in the ROI reference sequence", nuc),
+            Self::Mismatches(reference, sequenced) => {
+                format!("Number of sequenced {:?} nucleotides at {:?} reference positions", sequenced, reference)
+            }
+            Self::A2GFreq => "A->G mismatches frequency relative to the transcription strand".into(),
+            Self::BackgroundFreq => "Frequency of all mismatches except A->G and T->C".into(),
+            Self::A2GEnrichment => "A->G-freq / background-freq".into(),
+            Self::SoftclipFreq => "Fraction of covering reads with soft-clipped bases inside the ROI".into(),
+            Self::IndelFreq => "Fraction of covering reads with an insertion or deletion inside the ROI".into(),
+            Self::FwdDepth => "Number of sequenced bases from forward strand alignments".into(),
+            Self::RevDepth => "Number of sequenced bases from reverse strand alignments".into(),
+            Self::StrandBias => "|fwd_depth - rev_depth| / (fwd_depth + rev_depth)".into(),
+            Self::Genes => "Comma-separated names of all overlapping genes".into(),
+            Self::Feature => "Highest-priority overlapping feature: CDS, UTR, exon, intron or intergenic".into(),
+            Self::CiLow => "Lower bound of the A->G frequency Wilson score interval".into(),
+            Self::CiHigh => "Upper bound of the A->G frequency Wilson score interval".into(),
+            Self::AltFragments => "Max number of distinct fragments with mismatches at a single ROI position".into(),
+            Self::FlankCoverage => "Number of sequenced bases in the ROI flanks".into(),
+            Self::FlankA2G => "Number of A->G mismatches in the ROI flanks relative to the transcription strand".into(),
+            Self::FlankA2GFreq => {
+                "A->G mismatches frequency in the ROI flanks relative to the transcription strand".into()
+            }
+            Self::Filter => "PASS or semicolon-separated names of the failed output filters".into(),
+            Self::Sample => "Name of the run (--name)".into(),
+        }
+    }
+
+    pub fn ctype(&self) -> ColumnType {
+        match self {
+            Self::Contig
+            | Self::Strand
+            | Self::Name
+            | Self::TrStrand
+            | Self::Genes
+            | Self::Feature
+            | Self::Filter
+            | Self::Sample => ColumnType::Str,
+            Self::Mismatches(..)
+            | Self::A2GFreq
+            | Self::BackgroundFreq
+            | Self::A2GEnrichment
+            | Self::SoftclipFreq
+            | Self::IndelFreq
+            | Self::StrandBias
+            | Self::CiLow
+            | Self::CiHigh
+            | Self::FlankCoverage
+            | Self::FlankA2G
+            | Self::FlankA2GFreq => ColumnType::Float,
+            _ => ColumnType::UInt,
+        }
+    }
+
+    pub(super) fn serialize<S: SerializeStruct>(&self, state: &mut S, row: &SerializeROIRef) -> Result<(), S::Error> {
+        let (key, data) = (self.name(), &row.data);
+        match self {
+            Self::Contig => state.serialize_field(key, row.contig),
+            Self::Start => state.serialize_field(key, &data.roi.premasked.start),
+            Self::End => state.serialize_field(key, &data.roi.premasked.end),
+            Self::Strand => state.serialize_field(key, data.roi.strand.strand_symbol()),
+            Self::Name => state.serialize_field(key, &**data.roi.name),
+            Self::TrStrand => state.serialize_field(key, row.strand.strand_symbol()),
+            Self::Coverage => state.serialize_field(key, data.coverage),
+            Self::NucMasked => state.serialize_field(key, &(data.roi.nucmasked() + data.refnmasked)),
+            Self::Heterozygous => state.serialize_field(key, data.heterozygous),
+            Self::Corrected => state.serialize_field(key, data.corrected),
+            Self::Homozygous(nuc) => state.serialize_field(key, &row.homozygous()[*nuc]),
+            Self::Mismatches(reference, sequenced) => {
+                state.serialize_field(key, &row.mismatches()[*reference][*sequenced])
+            }
+            Self::A2GFreq => state.serialize_field(key, &data.a2g_freq(row.strand)),
+            Self::BackgroundFreq => state.serialize_field(key, &data.background_freq()),
+            Self::A2GEnrichment => state.serialize_field(key, &data.a2g_enrichment(row.strand)),
+            Self::SoftclipFreq => state.serialize_field(key, &data.softclip_freq()),
+            Self::IndelFreq => state.serialize_field(key, &data.indel_freq()),
+            Self::FwdDepth => state.serialize_field(key, &data.depth.forward),
+            Self::RevDepth => state.serialize_field(key, &data.depth.reverse),
+            Self::StrandBias => state.serialize_field(key, &data.depth.bias()),
+            Self::Genes => state.serialize_field(key, row.overlap.as_ref().map_or("", |x| x.genes.as_str())),
+            Self::Feature => state.serialize_field(key, row.overlap.as_ref().map_or("", |x| x.feature.name())),
+            Self::CiLow => state.serialize_field(key, &row.ci.map_or(f32::NAN, |x| x.0)),
+            Self::CiHigh => state.serialize_field(key, &row.ci.map_or(f32::NAN, |x| x.1)),
+            Self::AltFragments => state.serialize_field(key, data.alt_fragments),
+            Self::FlankCoverage => state.serialize_field(key, &data.flank.coverage()),
+            Self::FlankA2G => state.serialize_field(key, &data.flank_a2g(row.strand)),
+            Self::FlankA2GFreq => state.serialize_field(key, &data.flank_a2g_freq(row.strand)),
+            Self::Filter => state.serialize_field(key, &FilterLabels::label(row.failed)),
+            Self::Sample => state.serialize_field(key, row.sample.unwrap_or_default()),
+        }
+    }
+}
+
+impl FromStr for ROIColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let all = Self::all()
+            .into_iter()
+            .chain(Self::annotation())
+            .chain(Self::ci())
+            .chain(Self::fragments())
+            .chain(Self::flanks())
+            .chain(Self::filters())
+            .chain(Self::tag())
+            .collect_vec();
+        all.iter().find(|x| x.name() == s).copied().ok_or_else(|| {
+            format!("Unknown ROI column \"{}\", expected one of: {}", s, all.iter().map(|x| x.name()).join(","))
+        })
+    }
+}
+
+impl Display for ROIColumn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let all = ROIColumn::all();
+        assert_eq!(all.len(), 38);
+        assert!(all.iter().map(|x| x.name()).all_unique());
+        for column in all
+            .into_iter()
+            .chain(ROIColumn::annotation())
+            .chain(ROIColumn::ci())
+            .chain(ROIColumn::fragments())
+            .chain(ROIColumn::flanks())
+            .chain(ROIColumn::filters())
+            .chain(ROIColumn::tag())
+        {
+            assert_eq!(ROIColumn::from_str(column.name()), Ok(column));
+        }
+        assert!(!ROIColumn::all().contains(&ROIColumn::Genes));
+        assert!(!ROIColumn::all().contains(&ROIColumn::CiLow));
+        assert!(!ROIColumn::all().contains(&ROIColumn::AltFragments));
+        assert!(!ROIColumn::all().contains(&ROIColumn::Sample));
+        assert_eq!(ROIColumn::layout(false, false, false, false).len(), 29);
+        assert_eq!(ROIColumn::from_str("A->G"), Ok(ROIColumn::Mismatches(ReqNucleotide::A, ReqNucleotide::G)));
+        assert!(ROIColumn::from_str("pos").is_err());
+    }
+
+    #[test]
+    fn ctype() {
+        // Mismatches are weighted for heterozygous loci
+        let mismatches = ROIColumn::Mismatches(ReqNucleotide::A, ReqNucleotide::G);
+        assert_eq!(mismatches.ctype(), ColumnType::Float);
+        assert_eq!(ROIColumn::Homozygous(ReqNucleotide::A).ctype(), ColumnType::UInt);
+        assert_eq!(ROIColumn::Name.ctype(), ColumnType::Str);
+        assert_eq!(ROIColumn::CiLow.ctype(), ColumnType::Float);
+    }
+}
@@ -1,11 +1,15 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::ops::Range;
+use std::sync::Arc;
 
 use bio_types::genome::Position;
 use bio_types::strand::Strand;
 use soa_derive::StructOfArray;
 
-use crate::core::dna::NucCounts;
+use crate::core::dna::{NucCounts, Nucleotide, WideStrandDepth};
 use crate::core::mismatches::roi::ROINucCounts;
+use crate::core::statistics;
 use crate::core::workload::roi::*;
 
 #[derive(Clone, Debug, StructOfArray)]
@@ -15,8 +19,11 @@ pub struct ROIDataRecord {
     pub premasked: Range<Position>,
     pub postmasked: Range<Position>,
     pub subintervals: Vec<Range<Position>>,
-    pub name: String,
+    // Shared with the workload ROI
+    pub name: Arc<str>,
     pub strand: Strand,
+    // Total length of BED12 blocks or the whole premasked range otherwise
+    pub length: Position,
 }
 
 #[derive(Clone, Debug, StructOfArray)]
@@ -26,12 +33,89 @@ pub struct ROIData {
     pub roi: ROIDataRecord,
     // Number of unique fragments covering the ROI
     pub coverage: u32,
+    // Covering fragments with soft-clipped bases / indels inside the ROI (if requested)
+    pub softclipped: u32,
+    pub indels: u32,
+    // Forward / reverse alignments supporting ROI positions (after masking, zeros if not requested)
+    pub depth: WideStrandDepth,
+    // Max number of distinct fragments with mismatches at a single ROI position (zero if not requested)
+    pub alt_fragments: u32,
     // Predicted homozygous nucleotides in the given ROI (after masking)
     pub homozygous: NucCounts,
     // Total heterozygous loci in the ROI (after masking)
     pub heterozygous: u64,
+    // Total loci where the assembly nucleotide was corrected by the reference predictor (after masking)
+    pub corrected: u64,
+    // Total loci skipped due to unknown(N) assembly nucleotides (after masking, if requested)
+    pub refnmasked: u64,
     // Observed mismatches relative to the predicted reference
     pub mismatches: ROINucCounts,
+    // Observed mismatches for each input file separately (if requested)
+    pub persample: Vec<ROINucCounts>,
+    // Observed mismatches in the flanks outside the postmasked range (zeros without flanks)
+    pub flank: ROINucCounts,
+    // Positions with at least one mismatch (if requested)
+    pub profile: Vec<ROIProfileSite>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ROIProfileSite {
+    pub pos: Position,
+    // Predicted reference and sequenced nucleotides, forward strand
+    pub refnuc: Nucleotide,
+    pub sequenced: NucCounts,
+}
+
+impl ROIData {
+    // Add up counts of another part of the same ROI, e.g. an exon of a gene listed on a separate BED line
+    pub fn merge(&mut self, other: ROIData) {
+        debug_assert!(self.roi.name == other.roi.name);
+        let (roi, part) = (&mut self.roi, other.roi);
+        roi.premasked = roi.premasked.start.min(part.premasked.start)..roi.premasked.end.max(part.premasked.end);
+        roi.postmasked = roi.postmasked.start.min(part.postmasked.start)..roi.postmasked.end.max(part.postmasked.end);
+        roi.subintervals.extend(part.subintervals);
+        roi.subintervals.sort_by_key(|x| (x.start, x.end));
+        roi.length += part.length;
+
+        self.coverage += other.coverage;
+        self.softclipped += other.softclipped;
+        self.indels += other.indels;
+        self.depth += other.depth;
+        self.alt_fragments = self.alt_fragments.max(other.alt_fragments);
+        self.homozygous += other.homozygous;
+        self.heterozygous += other.heterozygous;
+        self.corrected += other.corrected;
+        self.refnmasked += other.refnmasked;
+        self.mismatches += other.mismatches;
+        self.flank += other.flank;
+        if self.persample.is_empty() {
+            self.persample = other.persample;
+        } else {
+            debug_assert!(other.persample.is_empty() || other.persample.len() == self.persample.len());
+            for (sample, part) in self.persample.iter_mut().zip(other.persample) {
+                *sample += part;
+            }
+        }
+        self.profile.extend(other.profile);
+        self.profile.sort_by_key(|x| x.pos);
+    }
+}
+
+// Merge records with the same name & strand into a single one, it takes the place of the first part.
+// All records must come from the same contig: same-named ROIs on different contigs are never grouped
+pub fn group_by_name(records: Vec<ROIData>) -> Vec<ROIData> {
+    let mut grouped: Vec<ROIData> = Vec::with_capacity(records.len());
+    let mut index: HashMap<(Arc<str>, &str), usize> = HashMap::with_capacity(records.len());
+    for record in records {
+        match index.entry((record.roi.name.clone(), record.roi.strand.strand_symbol())) {
+            Entry::Occupied(x) => grouped[*x.get()].merge(record),
+            Entry::Vacant(x) => {
+                x.insert(grouped.len());
+                grouped.push(record);
+            }
+        }
+    }
+    grouped
 }
 
 impl ROIDataRecordRef<'_> {
@@ -40,9 +124,104 @@ impl ROIDataRecordRef<'_> {
         for piece in self.subintervals {
             nucin += piece.end - piece.start;
         }
-        let total = self.premasked.end - self.premasked.start;
+        // Introns of BED12 records are not masked bases
+        *self.length - nucin
+    }
+}
+
+impl ROIDataRef<'_> {
+    // Frequency of all mismatches except the A->G / T->C (i.e. putative A->I editing on either strand)
+    pub fn background_freq(&self) -> f32 {
+        let coverage = self.mismatches.coverage();
+        if coverage <= 0f64 {
+            return f32::NAN;
+        }
+        ((self.mismatches.mismatches() - self.mismatches.A.G - self.mismatches.T.C) / coverage) as f32
+    }
+
+    // Mismatches relative to the given transcription strand (forward for unstranded ROIs)
+    fn oriented(&self, trstrand: Strand) -> ROINucCounts {
+        match trstrand {
+            Strand::Reverse => self.mismatches.complementary(),
+            _ => *self.mismatches,
+        }
+    }
+
+    // A->G frequency relative to the given transcription strand (forward for unstranded ROIs)
+    pub fn a2g_freq(&self, trstrand: Strand) -> f32 {
+        let mismatches = self.oriented(trstrand);
+        let coverage = mismatches.A.coverage();
+        if coverage <= 0f64 {
+            return f32::NAN;
+        }
+        (mismatches.A.G / coverage) as f32
+    }
+
+    // Wilson score interval of the A->G frequency for the given z score
+    pub fn a2g_ci(&self, trstrand: Strand, z: f32) -> (f32, f32) {
+        let mismatches = self.oriented(trstrand);
+        statistics::wilson(mismatches.A.G, mismatches.A.coverage(), z)
+    }
+
+    // Fraction of covering fragments with soft-clipped bases inside the ROI
+    pub fn softclip_freq(&self) -> f32 {
+        fraction(*self.softclipped, *self.coverage)
+    }
+
+    // Fraction of covering fragments with insertions or deletions inside the ROI
+    pub fn indel_freq(&self) -> f32 {
+        fraction(*self.indels, *self.coverage)
+    }
+
+    // A->G frequency / background frequency
+    pub fn a2g_enrichment(&self, trstrand: Strand) -> f32 {
+        let background = self.background_freq();
+        if background.is_nan() || background <= 0f32 {
+            return f32::NAN;
+        }
+        self.a2g_freq(trstrand) / background
+    }
+
+    // A->G mismatches in the flanks relative to the given transcription strand (forward for unstranded ROIs)
+    pub fn flank_a2g(&self, trstrand: Strand) -> f64 {
+        match trstrand {
+            Strand::Reverse => self.flank.T.C,
+            _ => self.flank.A.G,
+        }
+    }
+
+    // Flank A->G frequency, same as a2g_freq for the ROI itself
+    pub fn flank_a2g_freq(&self, trstrand: Strand) -> f32 {
+        let coverage = match trstrand {
+            Strand::Reverse => self.flank.T.coverage(),
+            _ => self.flank.A.coverage(),
+        };
+        if coverage <= 0f64 {
+            return f32::NAN;
+        }
+        (self.flank_a2g(trstrand) / coverage) as f32
+    }
+}
 
-        total - nucin
+#[inline]
+fn fraction(reads: u32, coverage: u32) -> f32 {
+    if coverage == 0 {
+        return f32::NAN;
+    }
+    reads as f32 / coverage as f32
+}
+
+impl ROIDataRecord {
+    // Same as the full record but without subintervals => no heap allocations until the ROI passes the prefilters
+    pub fn bare(roi: &ROI) -> Self {
+        Self {
+            premasked: roi.premasked(),
+            postmasked: roi.postmasked(),
+            subintervals: Vec::new(),
+            name: roi.shared_name(),
+            strand: roi.strand(),
+            length: roi.length(),
+        }
     }
 }
 
@@ -52,8 +231,9 @@ impl From<&'_ ROI> for ROIDataRecord {
             premasked: roi.premasked(),
             postmasked: roi.postmasked(),
             subintervals: roi.subintervals().into(),
-            name: roi.name().into(),
+            name: roi.shared_name(),
             strand: roi.strand(),
+            length: roi.length(),
         }
     }
 }
@@ -64,8 +244,9 @@ impl From<ROIDataRecordRef<'_>> for ROIDataRecord {
             premasked: x.premasked.to_owned(),
             postmasked: x.postmasked.to_owned(),
             subintervals: x.subintervals.to_owned(),
-            name: x.name.into(),
+            name: x.name.clone(),
             strand: *x.strand,
+            length: *x.length,
         }
     }
 }
@@ -75,9 +256,96 @@ impl From<ROIDataRef<'_>> for ROIData {
         Self {
             roi: x.roi.into(),
             coverage: *x.coverage,
+            softclipped: *x.softclipped,
+            indels: *x.indels,
+            depth: *x.depth,
+            alt_fragments: *x.alt_fragments,
             homozygous: *x.homozygous,
             heterozygous: *x.heterozygous,
+            corrected: *x.corrected,
+            refnmasked: *x.refnmasked,
             mismatches: *x.mismatches,
+            persample: x.persample.to_owned(),
+            flank: *x.flank,
+            profile: x.profile.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: &str, range: Range<Position>, ag: f64, coverage: u32, profile: &[Position]) -> ROIData {
+        let mut mismatches = ROINucCounts::zeros();
+        mismatches.A.A = 10f64;
+        mismatches.A.G = ag;
+        ROIData {
+            roi: ROIDataRecord {
+                premasked: range.clone(),
+                postmasked: range.clone(),
+                subintervals: vec![range.clone()],
+                name: name.into(),
+                strand: Strand::Forward,
+                length: range.end - range.start,
+            },
+            coverage,
+            softclipped: 1,
+            indels: 0,
+            depth: WideStrandDepth::new(coverage as u64, 0),
+            alt_fragments: coverage,
+            homozygous: NucCounts::A(10),
+            heterozygous: 0,
+            corrected: 1,
+            refnmasked: 0,
+            mismatches,
+            persample: vec![mismatches],
+            flank: mismatches,
+            profile: profile
+                .iter()
+                .map(|pos| ROIProfileSite { pos: *pos, refnuc: Nucleotide::A, sequenced: NucCounts::G(1) })
+                .collect(),
         }
     }
+
+    #[test]
+    fn gene_split_across_lines() {
+        // Exons of the gene are listed on separate BED lines (not in the coordinates order)
+        let records = vec![
+            part("gene", 200..210, 2f64, 3, &[205]),
+            part("other", 100..110, 5f64, 7, &[]),
+            part("gene", 0..10, 1f64, 5, &[1, 2]),
+            part("gene", 300..320, 3f64, 4, &[]),
+        ];
+        let grouped = group_by_name(records);
+        assert_eq!(grouped.len(), 2);
+        // Merged record takes the place of the first part
+        let (gene, other) = (&grouped[0], &grouped[1]);
+        assert_eq!(&*other.roi.name, "other");
+        assert_eq!(other.coverage, 7);
+
+        assert_eq!((gene.roi.premasked.clone(), gene.roi.postmasked.clone()), (0..320, 0..320));
+        assert_eq!(gene.roi.subintervals, [0..10, 200..210, 300..320]);
+        assert_eq!(gene.roi.length, 40);
+        assert_eq!((gene.coverage, gene.softclipped, gene.corrected), (12, 3, 3));
+        assert_eq!(gene.depth, WideStrandDepth::new(12, 0));
+        // Max over parts, not the sum
+        assert_eq!(gene.alt_fragments, 5);
+        assert_eq!(gene.homozygous, NucCounts::A(30));
+        assert_eq!((gene.mismatches.A.A, gene.mismatches.A.G), (30f64, 6f64));
+        assert_eq!(gene.persample, [gene.mismatches]);
+        let profile: Vec<Position> = gene.profile.iter().map(|x| x.pos).collect();
+        assert_eq!(profile, [1, 2, 205]);
+    }
+
+    #[test]
+    fn group_by_strand() {
+        // Same name on different strands => different ROIs
+        let mut reverse = part("gene", 20..30, 1f64, 1, &[]);
+        reverse.roi.strand = Strand::Reverse;
+        let grouped =
+            group_by_name(vec![part("gene", 0..10, 1f64, 1, &[]), reverse, part("gene", 40..50, 1f64, 1, &[])]);
+        let ranges: Vec<Range<Position>> = grouped.iter().map(|x| x.roi.premasked.clone()).collect();
+        assert_eq!(ranges, [0..50, 20..30]);
+    }
 }
@@ -1,10 +1,14 @@
 pub use builder::ROIMismatchesBuilder;
-pub use data::{ROIData, ROIDataRecord, ROIDataRecordRef, ROIDataRecordVec, ROIDataRef, ROIDataVec};
+pub use columns::ROIColumn;
+pub use data::{
+    group_by_name, ROIData, ROIDataRecord, ROIDataRecordRef, ROIDataRecordVec, ROIDataRef, ROIDataVec, ROIProfileSite,
+};
 pub use msummary::ROINucCounts;
 
 pub use vec::ROIMismatchesVec;
 
 mod builder;
+mod columns;
 mod data;
 mod msummary;
 mod vec;
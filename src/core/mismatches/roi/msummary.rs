@@ -35,18 +35,25 @@ impl ROINucCounts {
     }
 
     #[inline]
-    pub fn coverage(&self) -> f32 {
+    pub fn coverage(&self) -> f64 {
         self.A.coverage() + self.C.coverage() + self.G.coverage() + self.T.coverage()
     }
 
     #[inline]
-    pub fn mismatches(&self) -> f32 {
+    pub fn mismatches(&self) -> f64 {
         self.A.mismatches(Nucleotide::A)
             + self.C.mismatches(Nucleotide::C)
             + self.G.mismatches(Nucleotide::G)
             + self.T.mismatches(Nucleotide::T)
     }
 
+    #[inline]
+    pub fn scaled(&self, factor: f64) -> Self {
+        let scale = |x: &FracNucCounts| FracNucCounts::new(x.A * factor, x.C * factor, x.G * factor, x.T * factor);
+        ROINucCounts { A: scale(&self.A), C: scale(&self.C), G: scale(&self.G), T: scale(&self.T) }
+    }
+
+    // Both the reference (rows) and sequenced (columns) nucleotides are complemented, e.g. T->C becomes A->G
     #[inline]
     pub fn complementary(&self) -> Self {
         ROINucCounts {
@@ -92,7 +99,7 @@ impl IndexMut<ReqNucleotide> for ROINucCounts {
 mod tests {
     use super::*;
 
-    fn fillall(value: f32, counts: &mut FracNucCounts) {
+    fn fillall(value: f64, counts: &mut FracNucCounts) {
         counts.A = value;
         counts.C = value;
         counts.G = value;
@@ -102,19 +109,19 @@ mod tests {
     #[test]
     fn coverage() {
         let mut dummy: ROINucCounts = Default::default();
-        for num in [0_f32, 25_f32] {
+        for num in [0_f64, 25_f64] {
             fillall(num, &mut dummy.A);
             fillall(num, &mut dummy.C);
             fillall(num, &mut dummy.G);
             fillall(num, &mut dummy.T);
-            assert_eq!(dummy.coverage(), num * 4_f32 * 4_f32);
+            assert_eq!(dummy.coverage(), num * 4_f64 * 4_f64);
         }
     }
 
     #[test]
     fn match_mismatch() {
         let mut dummy: ROINucCounts = Default::default();
-        for (mismatched, matched) in [(0_f32, 25_f32), (12_f32, 0_f32), (1_f32, 2_f32)] {
+        for (mismatched, matched) in [(0_f64, 25_f64), (12_f64, 0_f64), (1_f64, 2_f64)] {
             fillall(mismatched, &mut dummy.A);
             dummy.A.A = matched;
             fillall(mismatched, &mut dummy.C);
@@ -124,10 +131,51 @@ mod tests {
             fillall(mismatched, &mut dummy.T);
             dummy.T.T = matched;
             // assert_eq!(dummy.matches(), matched * 4);
-            assert_eq!(dummy.mismatches(), mismatched * 12_f32);
+            assert_eq!(dummy.mismatches(), mismatched * 12_f64);
         }
     }
 
+    #[test]
+    fn scaled() {
+        let mut dummy: ROINucCounts = Default::default();
+        fillall(4_f64, &mut dummy.A);
+        dummy.T.C = 8_f64;
+        let scaled = dummy.scaled(0.25);
+        assert_eq!(scaled.A, FracNucCounts::new(1_f64, 1_f64, 1_f64, 1_f64));
+        assert_eq!(scaled.T, FracNucCounts::new(0_f64, 2_f64, 0_f64, 0_f64));
+        assert_eq!(scaled.coverage(), dummy.coverage() * 0.25);
+    }
+
+    #[test]
+    fn merge() {
+        // Stats merged across threads & ROIs must not wrap around or saturate
+        let mut dummy = ROINucCounts::zeros();
+        dummy.A.G = u32::MAX as f64;
+        dummy.A.A = (u32::MAX - 1) as f64;
+        let total = dummy + dummy;
+        assert_eq!(total.A.G, 2f64 * u32::MAX as f64);
+        assert_eq!(total.coverage(), 4f64 * u32::MAX as f64 - 2f64);
+        assert_eq!((total + dummy.scaled(0.5)).mismatches() - total.mismatches(), u32::MAX as f64 / 2f64);
+    }
+
+    #[test]
+    fn complementary() {
+        let mut dummy: ROINucCounts = Default::default();
+        dummy.A = FracNucCounts::new(1_f64, 2_f64, 3_f64, 4_f64);
+        dummy.T.C = 5_f64;
+        dummy.G.A = 6_f64;
+
+        let complementary = dummy.complementary();
+        assert_eq!(complementary.T, FracNucCounts::new(4_f64, 3_f64, 2_f64, 1_f64));
+        // T->C => A->G, G->A => C->T
+        assert_eq!(complementary.A, FracNucCounts::G(5_f64));
+        assert_eq!(complementary.C, FracNucCounts::T(6_f64));
+        assert_eq!(complementary.G, FracNucCounts::zeros());
+        assert_eq!(complementary.coverage(), dummy.coverage());
+        assert_eq!(complementary.mismatches(), dummy.mismatches());
+        assert_eq!(complementary.complementary(), dummy);
+    }
+
     // #[test]
     // fn from_counts() {
     //     let mut mismatches = NucMismatches::zeros();
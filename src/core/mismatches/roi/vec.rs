@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::io;
 use std::io::Write;
 
 use bio_types::strand::Strand;
@@ -7,39 +8,91 @@ use itertools::Itertools;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
-use crate::core::mismatches::roi::{ROIDataRef, ROIDataVec};
-use crate::core::mismatches::MismatchesVec;
+use crate::core::annotation::{GenomicFeatures, Overlap};
+use crate::core::dna::NucCounts;
+use crate::core::io::table::{ColumnInfo, RowWriter, Schema};
+use crate::core::mismatches::roi::{ROIDataRef, ROIDataVec, ROINucCounts, ROIProfileSite};
+use crate::core::mismatches::{FilterLabels, MismatchesVec};
 
+use super::columns::ROIColumn;
+
+#[derive(Clone)]
 pub struct ROIMismatchesVec {
     contig: String,
     trstrand: Strand,
     pub data: ROIDataVec,
+    // Failed soft filters, None if no soft filter was applied
+    filters: Option<FilterLabels>,
 }
 
 impl ROIMismatchesVec {
     pub fn new(contig: String, trstrand: Strand, data: ROIDataVec) -> Self {
-        Self { contig, trstrand, data }
+        Self { contig, trstrand, data, filters: None }
     }
-}
 
-impl MismatchesVec for ROIMismatchesVec {
-    fn contig(&self) -> &str {
-        &self.contig
+    // Serialize the given columns to the main table + BED file & per-position profile (if any).
+    // If `orient` is set, counts of reverse strand ROIs are reported relative to the transcription strand.
+    // Annotation (if any) is required for the genes & feature columns, z score - for the confidence interval ones,
+    // run name - for the sample column
+    #[allow(clippy::too_many_arguments)]
+    pub fn ugly_in_contig_sort_and_to_csv_and_bed<R: RowWriter, B: Write, P: Write>(
+        items: Vec<Self>,
+        writer: &mut R,
+        mut bed: Option<&mut Writer<B>>,
+        mut profile: Option<&mut Writer<P>>,
+        columns: &[ROIColumn],
+        orient: bool,
+        annotation: Option<&GenomicFeatures>,
+        ci: Option<f32>,
+        sample: Option<&str>,
+    ) -> csv::Result<()> {
+        for mut item in Self::sorted(&items, columns, orient) {
+            item.sample = sample;
+            if let Some(annotation) = annotation {
+                let roi = &item.data.roi.premasked;
+                item.overlap = Some(annotation.overlap(item.contig, roi.start..roi.end));
+            }
+            if let Some(z) = ci {
+                item.ci = Some(item.data.a2g_ci(item.strand, z));
+            }
+            if let Some(bed) = bed.as_mut() {
+                if let Some(record) = item.bed() {
+                    bed.write_record(record)?;
+                }
+            }
+            if let Some(profile) = profile.as_mut() {
+                for site in item.profile() {
+                    profile.serialize(site)?;
+                }
+            }
+            writer.serialize(item)?;
+        }
+        Ok(())
     }
 
-    fn trstrand(&self) -> Strand {
-        self.trstrand
+    // Columns of the main table, see ugly_in_contig_sort_and_to_csv_and_bed
+    pub fn header(columns: &[ROIColumn]) -> Vec<String> {
+        columns.iter().map(|x| x.name().to_owned()).collect()
     }
 
-    fn len(&self) -> usize {
-        self.data.len()
+    // Same as header, but with column types
+    pub fn schema(columns: &[ROIColumn]) -> Schema {
+        columns.iter().map(|x| (x.name().to_owned(), x.ctype())).collect()
     }
 
-    fn is_empty(&self) -> bool {
-        self.data.is_empty()
+    // Same as schema, but with column descriptions
+    pub fn describe(columns: &[ROIColumn]) -> Vec<ColumnInfo> {
+        columns
+            .iter()
+            .map(|x| ColumnInfo { name: x.name().to_owned(), ctype: x.ctype(), description: x.description() })
+            .collect()
     }
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut Writer<F>) -> csv::Result<()> {
+    fn sorted<'a>(
+        items: &'a [Self],
+        columns: &'a [ROIColumn],
+        orient: bool,
+    ) -> impl Iterator<Item = SerializeROIRef<'a>> {
         fn pos_then_strand_then_name(first: &SerializeROIRef, second: &SerializeROIRef) -> Ordering {
             let mut ord = first.data.roi.premasked.start.cmp(&second.data.roi.premasked.start);
             if ord.is_eq() {
@@ -54,55 +107,169 @@ impl MismatchesVec for ROIMismatchesVec {
             ord
         }
 
-        let iter = items
+        items
             .iter()
-            .flat_map(|x| x.data.iter().map(|data| SerializeROIRef { contig: &x.contig, strand: x.trstrand, data }))
-            .sorted_by(pos_then_strand_then_name);
-        for item in iter {
-            writer.serialize(item)?;
+            .flat_map(move |x| {
+                x.data.iter().enumerate().map(move |(ind, data)| SerializeROIRef {
+                    contig: &x.contig,
+                    strand: x.trstrand,
+                    data,
+                    columns,
+                    orient,
+                    overlap: None,
+                    ci: None,
+                    sample: None,
+                    failed: x.filters.as_ref().map(|f| f.get(ind)),
+                })
+            })
+            .sorted_by(pos_then_strand_then_name)
+    }
+}
+
+impl MismatchesVec for ROIMismatchesVec {
+    fn contig(&self) -> &str {
+        &self.contig
+    }
+
+    fn trstrand(&self) -> Strand {
+        self.trstrand
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn past_end(&self, length: u64) -> usize {
+        self.data.roi.premasked.iter().filter(|x| x.end > length).count()
+    }
+
+    fn ugly_in_contig_sort_and_to_csv<R: RowWriter>(items: Vec<Self>, writer: &mut R) -> csv::Result<()> {
+        Self::ugly_in_contig_sort_and_to_csv_and_bed(
+            items,
+            writer,
+            Option::<&mut Writer<io::Sink>>::None,
+            Option::<&mut Writer<io::Sink>>::None,
+            &ROIColumn::all(),
+            false,
+            None,
+            None,
+            None,
+        )
+    }
+
+    fn mark(&mut self, filter: &'static str, verdicts: &[bool]) {
+        let records = self.data.len();
+        self.filters.get_or_insert_with(|| FilterLabels::new(records)).mark(filter, verdicts);
+    }
+}
+
+pub(super) struct SerializeROIRef<'a> {
+    pub(super) contig: &'a str,
+    pub(super) strand: Strand,
+    pub(super) data: ROIDataRef<'a>,
+    columns: &'a [ROIColumn],
+    // Complement counts of reverse strand ROIs
+    orient: bool,
+    // Annotated genes & features, if requested
+    pub(super) overlap: Option<Overlap>,
+    // Confidence interval of the A->G frequency, if requested
+    pub(super) ci: Option<(f32, f32)>,
+    // Run name, if requested
+    pub(super) sample: Option<&'a str>,
+    // Failed soft filters, if any
+    pub(super) failed: Option<&'a [&'static str]>,
+}
+
+impl SerializeROIRef<'_> {
+    // Homozygous loci & mismatches relative to the transcription strand (if requested) or the reference strand
+    pub(super) fn homozygous(&self) -> NucCounts {
+        match self.oriented() {
+            true => self.data.homozygous.complementary(),
+            false => *self.data.homozygous,
+        }
+    }
+
+    pub(super) fn mismatches(&self) -> ROINucCounts {
+        match self.oriented() {
+            true => self.data.mismatches.complementary(),
+            false => *self.data.mismatches,
         }
-        Ok(())
+    }
+
+    fn oriented(&self) -> bool {
+        self.orient && self.strand == Strand::Reverse
+    }
+
+    // BED6: contig, start, end, name, score = min(1000, round(1000 * A->G freq)), trstrand
+    fn bed(&self) -> Option<[String; 6]> {
+        if *self.data.coverage == 0 {
+            return None;
+        }
+        let mismatches = match self.strand {
+            Strand::Reverse => self.data.mismatches.complementary(),
+            _ => *self.data.mismatches,
+        };
+        let coverage = mismatches.A.coverage();
+        let freq = if coverage > 0f64 { mismatches.A.G / coverage } else { 0f64 };
+        let score = (1000f64 * freq).round().min(1000f64) as u32;
+        Some([
+            self.contig.to_owned(),
+            self.data.roi.premasked.start.to_string(),
+            self.data.roi.premasked.end.to_string(),
+            self.data.roi.name.to_string(),
+            score.to_string(),
+            self.strand.strand_symbol().to_owned(),
+        ])
+    }
+
+    fn profile(&self) -> impl Iterator<Item = SerializeProfileRef<'_>> {
+        self.data.profile.iter().map(move |site| SerializeProfileRef {
+            contig: self.contig,
+            name: self.data.roi.name,
+            strand: self.strand,
+            site,
+        })
     }
 }
 
-struct SerializeROIRef<'a> {
+struct SerializeProfileRef<'a> {
     contig: &'a str,
+    name: &'a str,
     strand: Strand,
-    data: ROIDataRef<'a>,
+    site: &'a ROIProfileSite,
 }
 
-impl Serialize for SerializeROIRef<'_> {
+impl Serialize for SerializeProfileRef<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("ROIMismatches", 29)?;
+        // Nucleotides are reported relative to the transcription strand
+        let (refnuc, sequenced) = match self.strand {
+            Strand::Reverse => (self.site.refnuc.complementary(), self.site.sequenced.complementary()),
+            _ => (self.site.refnuc, self.site.sequenced),
+        };
+        let mut state = serializer.serialize_struct("ROIProfile", 9)?;
         state.serialize_field("contig", &self.contig)?;
-        state.serialize_field("start", &self.data.roi.premasked.start)?;
-        state.serialize_field("end", &self.data.roi.premasked.end)?;
-        state.serialize_field("strand", &self.data.roi.strand.strand_symbol())?;
-        state.serialize_field("name", &self.data.roi.name)?;
+        state.serialize_field("pos", &self.site.pos)?;
+        state.serialize_field("name", &self.name)?;
         state.serialize_field("trstrand", &self.strand.strand_symbol())?;
-        state.serialize_field("coverage", &self.data.coverage)?;
-        state.serialize_field("nucmasked", &self.data.roi.nucmasked())?;
-        state.serialize_field("heterozygous", &self.data.heterozygous)?;
-        state.serialize_field("#A", &self.data.homozygous.A)?;
-        state.serialize_field("A->A", &self.data.mismatches.A.A)?;
-        state.serialize_field("A->C", &self.data.mismatches.A.C)?;
-        state.serialize_field("A->G", &self.data.mismatches.A.G)?;
-        state.serialize_field("A->T", &self.data.mismatches.A.T)?;
-        state.serialize_field("#C", &self.data.homozygous.C)?;
-        state.serialize_field("C->A", &self.data.mismatches.C.A)?;
-        state.serialize_field("C->C", &self.data.mismatches.C.C)?;
-        state.serialize_field("C->G", &self.data.mismatches.C.G)?;
-        state.serialize_field("C->T", &self.data.mismatches.C.T)?;
-        state.serialize_field("#G", &self.data.homozygous.G)?;
-        state.serialize_field("G->A", &self.data.mismatches.G.A)?;
-        state.serialize_field("G->C", &self.data.mismatches.G.C)?;
-        state.serialize_field("G->G", &self.data.mismatches.G.G)?;
-        state.serialize_field("G->T", &self.data.mismatches.G.T)?;
-        state.serialize_field("#T", &self.data.homozygous.T)?;
-        state.serialize_field("T->A", &self.data.mismatches.T.A)?;
-        state.serialize_field("T->C", &self.data.mismatches.T.C)?;
-        state.serialize_field("T->G", &self.data.mismatches.T.G)?;
-        state.serialize_field("T->T", &self.data.mismatches.T.T)?;
+        state.serialize_field("refnuc", &refnuc.symbol())?;
+        state.serialize_field("A", &sequenced.A)?;
+        state.serialize_field("C", &sequenced.C)?;
+        state.serialize_field("G", &sequenced.G)?;
+        state.serialize_field("T", &sequenced.T)?;
+        state.end()
+    }
+}
+
+impl Serialize for SerializeROIRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ROIMismatches", self.columns.len())?;
+        for column in self.columns {
+            column.serialize(&mut state, self)?;
+        }
         state.end()
     }
 }
@@ -111,38 +278,79 @@ impl Serialize for SerializeROIRef<'_> {
 mod test {
     use serde_test::{assert_ser_tokens, Token};
 
-    use crate::core::dna::{FracNucCounts, NucCounts};
+    use crate::core::annotation::FeatureType;
+    use crate::core::dna::{FracNucCounts, NucCounts, Nucleotide, ReqNucleotide, WideStrandDepth};
     use crate::core::mismatches::roi::{ROIDataRecordRef, ROINucCounts};
 
     use super::*;
 
+    #[test]
+    fn header() {
+        let layouts = [(false, false, 29), (true, false, 32), (false, true, 30), (true, true, 33)];
+        for (background, corrected, len) in layouts {
+            let header = ROIMismatchesVec::header(&ROIColumn::layout(background, corrected, false, false));
+            assert_eq!(header.len(), len);
+            assert_eq!(header[..6], ["contig", "start", "end", "strand", "name", "trstrand"]);
+            assert_eq!(header.contains(&"corrected".to_owned()), corrected);
+            assert_eq!(header.last().unwrap() == "A->G-enrichment", background);
+
+            let header = ROIMismatchesVec::header(&ROIColumn::layout(background, corrected, true, false));
+            assert_eq!(header.len(), len + 2);
+            assert_eq!(header[len..], ["softclip-freq", "indel-freq"]);
+
+            let header = ROIMismatchesVec::header(&ROIColumn::layout(background, corrected, false, true));
+            assert_eq!(header.len(), len + 3);
+            assert_eq!(header[len..], ["fwd_depth", "rev_depth", "strand_bias"]);
+        }
+    }
+
     #[test]
     fn roi() {
         let record = ROIDataRecordRef {
             premasked: &(0..123),
             postmasked: &(1..100),
             subintervals: &vec![1..10, 20..100],
-            name: &"MyRep".to_owned(),
+            name: &"MyRep".into(),
             strand: &Strand::Forward,
+            length: &123,
         };
         let mm = ROINucCounts {
-            A: FracNucCounts::new(1_f32, 2_f32, 3_f32, 4_f32),
-            C: FracNucCounts::new(5_f32, 6_f32, 7_f32, 8_f32),
-            G: FracNucCounts::new(9_f32, 10_f32, 11_f32, 12_f32),
-            T: FracNucCounts::new(13_f32, 14_f32, 15_f32, 16_f32),
+            A: FracNucCounts::new(1_f64, 2_f64, 3_f64, 4_f64),
+            C: FracNucCounts::new(5_f64, 6_f64, 7_f64, 8_f64),
+            G: FracNucCounts::new(9_f64, 10_f64, 11_f64, 12_f64),
+            T: FracNucCounts::new(13_f64, 14_f64, 15_f64, 16_f64),
         };
         let roi = ROIDataRef {
             roi: record,
             coverage: &13,
+            softclipped: &2,
+            indels: &1,
+            depth: &WideStrandDepth::new(20, 60),
+            alt_fragments: &0,
             homozygous: &NucCounts::new(1, 12, 3, 5),
             heterozygous: &13,
+            corrected: &7,
+            refnmasked: &3,
             mismatches: &mm,
+            persample: &vec![],
+            flank: &ROINucCounts::zeros(),
+            profile: &vec![],
         };
 
         assert_ser_tokens(
-            &SerializeROIRef { contig: "chr1", strand: Strand::Unknown, data: roi },
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Unknown,
+                data: roi,
+                columns: &ROIColumn::all(),
+                orient: false,
+                overlap: None,
+                ci: None,
+                sample: None,
+                failed: None,
+            },
             &[
-                Token::Struct { name: "ROIMismatches", len: 29 },
+                Token::Struct { name: "ROIMismatches", len: 38 },
                 Token::Str("contig"),
                 Token::Str("chr1"),
                 Token::Str("start"),
@@ -158,51 +366,654 @@ mod test {
                 Token::Str("coverage"),
                 Token::U32(13),
                 Token::Str("nucmasked"),
-                Token::U64(34),
+                Token::U64(37),
                 Token::Str("heterozygous"),
                 Token::U64(13),
+                Token::Str("corrected"),
+                Token::U64(7),
                 Token::Str("#A"),
                 Token::U32(1),
                 Token::Str("A->A"),
-                Token::F32(1_f32),
+                Token::F64(1_f64),
                 Token::Str("A->C"),
-                Token::F32(2_f32),
+                Token::F64(2_f64),
                 Token::Str("A->G"),
-                Token::F32(3_f32),
+                Token::F64(3_f64),
                 Token::Str("A->T"),
-                Token::F32(4_f32),
+                Token::F64(4_f64),
                 Token::Str("#C"),
                 Token::U32(12),
                 Token::Str("C->A"),
-                Token::F32(5_f32),
+                Token::F64(5_f64),
                 Token::Str("C->C"),
-                Token::F32(6_f32),
+                Token::F64(6_f64),
                 Token::Str("C->G"),
-                Token::F32(7_f32),
+                Token::F64(7_f64),
                 Token::Str("C->T"),
-                Token::F32(8_f32),
+                Token::F64(8_f64),
                 Token::Str("#G"),
                 Token::U32(3),
                 Token::Str("G->A"),
-                Token::F32(9_f32),
+                Token::F64(9_f64),
                 Token::Str("G->C"),
-                Token::F32(10_f32),
+                Token::F64(10_f64),
                 Token::Str("G->G"),
-                Token::F32(11_f32),
+                Token::F64(11_f64),
                 Token::Str("G->T"),
-                Token::F32(12_f32),
+                Token::F64(12_f64),
                 Token::Str("#T"),
                 Token::U32(5),
                 Token::Str("T->A"),
-                Token::F32(13_f32),
+                Token::F64(13_f64),
                 Token::Str("T->C"),
-                Token::F32(14_f32),
+                Token::F64(14_f64),
                 Token::Str("T->G"),
-                Token::F32(15_f32),
+                Token::F64(15_f64),
                 Token::Str("T->T"),
-                Token::F32(16_f32),
+                Token::F64(16_f64),
+                Token::Str("A->G-freq"),
+                Token::F32(3_f32 / 10_f32),
+                Token::Str("background-freq"),
+                Token::F32(85_f32 / 136_f32),
+                Token::Str("A->G-enrichment"),
+                Token::F32((3_f32 / 10_f32) / (85_f32 / 136_f32)),
+                Token::Str("softclip-freq"),
+                Token::F32(2_f32 / 13_f32),
+                Token::Str("indel-freq"),
+                Token::F32(1_f32 / 13_f32),
+                Token::Str("fwd_depth"),
+                Token::U64(20),
+                Token::Str("rev_depth"),
+                Token::U64(60),
+                Token::Str("strand_bias"),
+                Token::F32(0.5),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Unknown,
+            length: &10,
+        };
+        let mut mm = ROINucCounts::zeros();
+        mm.A = FracNucCounts::new(3_f64, 0_f64, 1_f64, 0_f64);
+        let (homozygous, persample, profile) = (NucCounts::zeros(), vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &4,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        // Selected columns are reported in the requested order
+        let columns = [ROIColumn::Name, ROIColumn::Mismatches(ReqNucleotide::A, ReqNucleotide::G), ROIColumn::Contig];
+        assert_eq!(ROIMismatchesVec::header(&columns), ["name", "A->G", "contig"]);
+        assert_ser_tokens(
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data: roi,
+                columns: &columns,
+                orient: false,
+                overlap: None,
+                ci: None,
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "ROIMismatches", len: 3 },
+                Token::Str("name"),
+                Token::Str("MyRep"),
+                Token::Str("A->G"),
+                Token::F64(1_f64),
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn orient() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Reverse,
+            length: &10,
+        };
+        let mut mm = ROINucCounts::zeros();
+        mm.T = FracNucCounts::new(0_f64, 2_f64, 0_f64, 6_f64);
+        let (homozygous, persample, profile) = (NucCounts::new(1, 0, 3, 4), vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &4,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        let columns = [
+            ROIColumn::Homozygous(ReqNucleotide::A),
+            ROIColumn::Homozygous(ReqNucleotide::C),
+            ROIColumn::Mismatches(ReqNucleotide::A, ReqNucleotide::G),
+            ROIColumn::Mismatches(ReqNucleotide::T, ReqNucleotide::C),
+            ROIColumn::A2GFreq,
+        ];
+        let serialize = |strand, orient| {
+            let row = SerializeROIRef {
+                contig: "chr1",
+                strand,
+                data: roi,
+                columns: &columns,
+                orient,
+                overlap: None,
+                ci: None,
+                sample: None,
+                failed: None,
+            };
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            writer.serialize(row).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        // Reference strand by default
+        assert_eq!(serialize(Strand::Reverse, false), "1,0,0.0,2.0,0.25\n");
+        // T->C on the reference strand is A->G on the transcription strand
+        assert_eq!(serialize(Strand::Reverse, true), "4,3,2.0,0.0,0.25\n");
+        // Nothing to orient for other strands
+        assert_eq!(serialize(Strand::Forward, true), serialize(Strand::Forward, false));
+        assert_eq!(serialize(Strand::Unknown, true), serialize(Strand::Unknown, false));
+    }
+
+    #[test]
+    fn annotation() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Forward,
+            length: &10,
+        };
+        let (mm, homozygous, persample, profile) = (ROINucCounts::zeros(), NucCounts::zeros(), vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &0,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        let columns = [ROIColumn::Name, ROIColumn::Genes, ROIColumn::Feature];
+        let overlap = Overlap { genes: "ADAR,AZIN1".to_owned(), feature: FeatureType::Utr };
+        assert_ser_tokens(
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data: roi,
+                columns: &columns,
+                orient: false,
+                overlap: Some(overlap),
+                ci: None,
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "ROIMismatches", len: 3 },
+                Token::Str("name"),
+                Token::Str("MyRep"),
+                Token::Str("genes"),
+                Token::Str("ADAR,AZIN1"),
+                Token::Str("feature"),
+                Token::Str("UTR"),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn describe() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Forward,
+            length: &10,
+        };
+        let (mm, homozygous, persample, profile) = (ROINucCounts::zeros(), NucCounts::zeros(), vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &0,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        // The described columns always match the header of the written table
+        let columns = ROIColumn::all().into_iter().chain(ROIColumn::annotation()).chain(ROIColumn::ci()).collect_vec();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data: roi,
+                columns: &columns,
+                orient: false,
+                overlap: None,
+                ci: None,
+                sample: None,
+                failed: None,
+            })
+            .unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let described = ROIMismatchesVec::describe(&columns);
+        assert_eq!(written.lines().next().unwrap(), described.iter().map(|x| x.name.as_str()).join(","));
+        assert_eq!(
+            described.iter().map(|x| (x.name.clone(), x.ctype)).collect_vec(),
+            ROIMismatchesVec::schema(&columns)
+        );
+        assert!(described.iter().all(|x| !x.description.is_empty()));
+    }
+
+    #[test]
+    fn background() {
+        let record = ROIDataRecordRef {
+            premasked: &(0..10),
+            postmasked: &(0..10),
+            subintervals: &vec![0..10],
+            name: &"MyRep".into(),
+            strand: &Strand::Unknown,
+            length: &10,
+        };
+        let mut mm = ROINucCounts::zeros();
+        mm.A = FracNucCounts::new(6_f64, 0_f64, 2_f64, 0_f64);
+        mm.T = FracNucCounts::new(0_f64, 2_f64, 0_f64, 6_f64);
+        let homozygous = NucCounts::zeros();
+        let (persample, profile) = (vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &5,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+        // Only A->G / T->C mismatches => no background
+        assert_eq!(roi.background_freq(), 0f32);
+        assert_eq!(roi.a2g_freq(Strand::Forward), 0.25);
+        assert_eq!(roi.a2g_freq(Strand::Reverse), 0.25);
+        assert!(roi.a2g_enrichment(Strand::Forward).is_nan());
+
+        mm.C = FracNucCounts::new(1_f64, 3_f64, 0_f64, 0_f64);
+        let roi = ROIDataRef { mismatches: &mm, ..roi };
+        assert_eq!(roi.background_freq(), 1f32 / 20f32);
+        assert_eq!(roi.a2g_enrichment(Strand::Forward), 0.25 / (1f32 / 20f32));
+
+        // Without coverage all values are undefined
+        let zeros = ROINucCounts::zeros();
+        let roi = ROIDataRef { mismatches: &zeros, ..roi };
+        assert!(roi.background_freq().is_nan());
+        assert!(roi.a2g_freq(Strand::Unknown).is_nan());
+        assert!(roi.a2g_enrichment(Strand::Unknown).is_nan());
+    }
+
+    #[test]
+    fn ci() {
+        let record = ROIDataRecordRef {
+            premasked: &(0..10),
+            postmasked: &(0..10),
+            subintervals: &vec![0..10],
+            name: &"MyRep".into(),
+            strand: &Strand::Unknown,
+            length: &10,
+        };
+        let mut mm = ROINucCounts::zeros();
+        mm.A = FracNucCounts::new(6_f64, 0_f64, 2_f64, 0_f64);
+        mm.T = FracNucCounts::new(0_f64, 1_f64, 0_f64, 2_f64);
+        let homozygous = NucCounts::zeros();
+        let (persample, profile) = (vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &5,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+        // 2/8 A->G on the forward strand, 1/3 T->C on the reverse one
+        let z = 1.959964;
+        for (strand, expected) in [
+            (Strand::Forward, (0.071479, 0.590725)),
+            (Strand::Unknown, (0.071479, 0.590725)),
+            (Strand::Reverse, (0.061492, 0.792340)),
+        ] {
+            let (low, high) = roi.a2g_ci(strand, z);
+            assert!((low - expected.0).abs() < 1e-5 && (high - expected.1).abs() < 1e-5);
+        }
+
+        let columns = [ROIColumn::Name, ROIColumn::CiLow, ROIColumn::CiHigh];
+        assert_ser_tokens(
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data: roi,
+                columns: &columns,
+                orient: false,
+                overlap: None,
+                ci: Some((0.25, 0.75)),
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "ROIMismatches", len: 3 },
+                Token::Str("name"),
+                Token::Str("MyRep"),
+                Token::Str("ci_low"),
+                Token::F32(0.25),
+                Token::Str("ci_high"),
+                Token::F32(0.75),
                 Token::StructEnd,
             ],
         );
     }
+
+    #[test]
+    fn flanks() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Reverse,
+            length: &10,
+        };
+        let mut flank = ROINucCounts::zeros();
+        flank.A = FracNucCounts::new(4_f64, 0_f64, 4_f64, 0_f64);
+        flank.T = FracNucCounts::new(0_f64, 1_f64, 0_f64, 3_f64);
+        let (mm, homozygous) = (ROINucCounts::zeros(), NucCounts::zeros());
+        let (persample, profile) = (vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &5,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &flank,
+            profile: &profile,
+        };
+        assert_eq!((roi.flank_a2g(Strand::Forward), roi.flank_a2g_freq(Strand::Forward)), (4f64, 0.5));
+        assert!(roi.a2g_freq(Strand::Forward).is_nan());
+
+        // T->C on the reference strand for reverse strand ROIs
+        assert_ser_tokens(
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Reverse,
+                data: roi,
+                columns: &ROIColumn::flanks(),
+                orient: false,
+                overlap: None,
+                ci: None,
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "ROIMismatches", len: 3 },
+                Token::Str("flank_coverage"),
+                Token::F64(12f64),
+                Token::Str("flank_A->G"),
+                Token::F64(1f64),
+                Token::Str("flank_A->G-freq"),
+                Token::F32(0.25),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn bed() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Unknown,
+            length: &10,
+        };
+        let mut mm = ROINucCounts::zeros();
+        mm.A = FracNucCounts::new(3_f64, 0_f64, 1_f64, 0_f64);
+        mm.T = FracNucCounts::new(0_f64, 4_f64, 0_f64, 4_f64);
+        let homozygous = NucCounts::zeros();
+        let (persample, profile) = (vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &5,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        let forward = SerializeROIRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data: roi,
+            columns: &[],
+            orient: false,
+            overlap: None,
+            ci: None,
+            sample: None,
+            failed: None,
+        };
+        assert_eq!(forward.bed().unwrap(), ["chr1", "10", "20", "MyRep", "250", "+"]);
+
+        let reverse = SerializeROIRef {
+            contig: "chr1",
+            strand: Strand::Reverse,
+            data: roi,
+            columns: &[],
+            orient: false,
+            overlap: None,
+            ci: None,
+            sample: None,
+            failed: None,
+        };
+        assert_eq!(reverse.bed().unwrap(), ["chr1", "10", "20", "MyRep", "500", "-"]);
+
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &0,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+        let empty = SerializeROIRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data: roi,
+            columns: &[],
+            orient: false,
+            overlap: None,
+            ci: None,
+            sample: None,
+            failed: None,
+        };
+        assert!(empty.bed().is_none());
+    }
+
+    #[test]
+    fn sample() {
+        let record = ROIDataRecordRef {
+            premasked: &(10..20),
+            postmasked: &(10..20),
+            subintervals: &vec![10..20],
+            name: &"MyRep".into(),
+            strand: &Strand::Unknown,
+            length: &10,
+        };
+        let (mm, homozygous) = (ROINucCounts::zeros(), NucCounts::zeros());
+        let (persample, profile) = (vec![], vec![]);
+        let roi = ROIDataRef {
+            roi: record,
+            coverage: &5,
+            softclipped: &0,
+            indels: &0,
+            depth: &WideStrandDepth::default(),
+            alt_fragments: &0,
+            homozygous: &homozygous,
+            heterozygous: &0,
+            corrected: &0,
+            refnmasked: &0,
+            mismatches: &mm,
+            persample: &persample,
+            flank: &ROINucCounts::zeros(),
+            profile: &profile,
+        };
+
+        // The run name is reported at the requested position
+        let columns = [ROIColumn::Sample, ROIColumn::Name];
+        assert_ser_tokens(
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data: roi,
+                columns: &columns,
+                orient: false,
+                overlap: None,
+                ci: None,
+                sample: Some("run1"),
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "ROIMismatches", len: 2 },
+                Token::Str("sample"),
+                Token::Str("run1"),
+                Token::Str("name"),
+                Token::Str("MyRep"),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn profile() {
+        let site = ROIProfileSite { pos: 12, refnuc: Nucleotide::T, sequenced: NucCounts::new(1, 3, 0, 10) };
+        for (strand, symbol, refnuc, counts) in
+            [(Strand::Forward, "+", "T", [1, 3, 0, 10]), (Strand::Reverse, "-", "A", [10, 0, 3, 1])]
+        {
+            let row = SerializeProfileRef { contig: "chr1", name: "MyRep", strand, site: &site };
+            assert_ser_tokens(
+                &row,
+                &[
+                    Token::Struct { name: "ROIProfile", len: 9 },
+                    Token::Str("contig"),
+                    Token::Str("chr1"),
+                    Token::Str("pos"),
+                    Token::U64(12),
+                    Token::Str("name"),
+                    Token::Str("MyRep"),
+                    Token::Str("trstrand"),
+                    Token::Str(symbol),
+                    Token::Str("refnuc"),
+                    Token::Str(refnuc),
+                    Token::Str("A"),
+                    Token::U32(counts[0]),
+                    Token::Str("C"),
+                    Token::U32(counts[1]),
+                    Token::Str("G"),
+                    Token::U32(counts[2]),
+                    Token::Str("T"),
+                    Token::U32(counts[3]),
+                    Token::StructEnd,
+                ],
+            );
+        }
+    }
 }
@@ -4,14 +4,15 @@ use bio_types::genome::Position;
 use bio_types::strand::Strand;
 use itertools::izip;
 
-use crate::core::dna::NucCounts;
+use crate::core::dna::{AltFragments, BaseQualities, NucCounts, Nucleotide, StrandDepth, SubstitutionCounts};
 use crate::core::mismatches::prefilters::retain::SitesRetainer;
-use crate::core::mismatches::prefilters::MismatchesPreFilter;
+use crate::core::mismatches::prefilters::{ByReplicates, MismatchesPreFilter};
 use crate::core::mismatches::site::{SiteData, SiteDataVec, SiteMismatchesVec};
 use crate::core::mismatches::Batch;
-use crate::core::refpred::{RefEngine, RefEngineResult};
+use crate::core::refpred::{RefEngine, RefEngineResult, RefSource};
 use crate::core::rpileup::ncounter::NucCounterResult;
 use crate::core::strandutil::Stranded;
+use crate::error::Error;
 
 use super::super::Builder;
 
@@ -21,6 +22,14 @@ pub struct SiteMismatchesBuilder<SR, MP> {
     refpred: Box<dyn RefEngine>,
     retainer: Option<SR>,
     prefilter: Option<MP>,
+    // Per-file support required for non-retained sites (counts for each source must be available)
+    replicates: Option<ByReplicates>,
+    // Keep counts for each source in the output (counts for each source must be available)
+    persample: bool,
+    // Skip positions with unknown(N) assembly nucleotides
+    skip_refn: bool,
+    // Collect the background substitutions profile
+    substitutions: bool,
 }
 
 impl<'a, SR, MP> SiteMismatchesBuilder<SR, MP>
@@ -29,14 +38,49 @@ where
     MP: MismatchesPreFilter<SiteData>,
 {
     pub fn new(maxsize: usize, refpred: Box<dyn RefEngine>, retainer: Option<SR>, prefilter: Option<MP>) -> Self {
-        Self { buffer: Vec::with_capacity(maxsize), refpred, retainer, prefilter }
+        Self {
+            buffer: Vec::with_capacity(maxsize),
+            refpred,
+            retainer,
+            prefilter,
+            replicates: None,
+            persample: false,
+            skip_refn: false,
+            substitutions: false,
+        }
+    }
+
+    pub fn with_refn_skipped(mut self) -> Self {
+        self.skip_refn = true;
+        self
+    }
+
+    pub fn with_replicates(mut self, replicates: ByReplicates) -> Self {
+        self.replicates = Some(replicates);
+        self
+    }
+
+    pub fn with_persample(mut self) -> Self {
+        self.persample = true;
+        self
+    }
+
+    pub fn with_substitutions(mut self) -> Self {
+        self.substitutions = true;
+        self
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
+        trstrand: Strand,
         retained: &[Range<Position>],
         cntrange: Range<Position>,
         cnts: &[NucCounts],
+        depth: Option<&[StrandDepth]>,
+        qualities: Option<&[BaseQualities]>,
+        fragments: Option<&[AltFragments]>,
+        samples: &[&[NucCounts]],
         refngn: &RefEngineResult,
         retbuilder: &mut SiteDataVec,
         othbuilder: &mut SiteDataVec,
@@ -44,6 +88,11 @@ where
         debug_assert_eq!(cntrange.end - cntrange.start, cnts.len() as Position);
         debug_assert_eq!(cnts.len(), refngn.reference.len());
         debug_assert_eq!(cnts.len(), refngn.predicted.len());
+        debug_assert!(depth.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(qualities.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(fragments.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(samples.iter().all(|x| x.len() == cnts.len()));
+        debug_assert!((self.replicates.is_none() && !self.persample) || !samples.is_empty());
         debug_assert!(retained.iter().all(|x| cntrange.contains(&x.start) && cntrange.contains(&x.end)));
 
         let retsize = retained.iter().map(|x| x.end - x.start).sum::<Position>() as usize;
@@ -59,12 +108,39 @@ where
             if retrange.map_or(false, |x| x.end <= pos) {
                 retrange = reiter.next();
             }
+            if self.skip_refn && refnuc == Nucleotide::Unknown {
+                continue;
+            }
 
             // Are we inside the retained region?
-            let data = SiteData { pos, refnuc, prednuc, sequenced: cnt };
+            let refsource = RefSource::new(refnuc, prednuc);
+            let idx = (pos - cntrange.start) as usize;
+            let depth = depth.map_or(StrandDepth::default(), |x| x[idx]);
+            let qualities = qualities.map_or(BaseQualities::default(), |x| x[idx]);
+            let alt_fragments = fragments.and_then(|x| x[idx].alt(refnuc, &prednuc));
+            let persample = if self.persample { samples.iter().map(|x| x[idx]).collect() } else { vec![] };
+            let data = SiteData {
+                pos,
+                refnuc,
+                prednuc,
+                refsource,
+                sequenced: cnt,
+                depth,
+                qualities,
+                alt_fragments,
+                persample,
+                pvalue: None,
+                qvalue: None,
+                splice_dist: None,
+            };
             if retrange.map_or(false, |x| x.contains(&pos)) {
                 retbuilder.push(data);
-            } else if self.prefilter.as_ref().map_or(true, |x| x.is_ok(&data)) {
+            } else if self.prefilter.as_ref().map_or(true, |x| x.is_ok(&data, trstrand))
+                && self
+                    .replicates
+                    .as_ref()
+                    .map_or(true, |x| x.enough_support_per_site(prednuc, samples.iter().map(|s| &s[idx])))
+            {
                 othbuilder.push(data);
             }
         }
@@ -90,7 +166,7 @@ where
     type Out = SiteMismatchesVec;
     type SourceCounts = NucCounterResult<'a, ()>;
 
-    fn build(&mut self, nc: Self::SourceCounts) -> Batch<Self::Out> {
+    fn build(&mut self, nc: Self::SourceCounts) -> Result<Batch<Self::Out>, Error> {
         let contig = nc.contig;
 
         // Pre-allocate results
@@ -104,11 +180,22 @@ where
             // SiteMismatchesVec::new(contig.to_owned(), strnd, SiteDataVec::with_capacity(hint[strnd] / 10))
         });
 
+        let (mut covered, mut refn_skipped) = (0, 0);
+        let mut substitutions = SubstitutionCounts::new();
         for item in nc.cnts.into_iter() {
             // Predict the reference
             let counts = item.seqnuc(&mut self.buffer).unwrap_or(&self.buffer);
-            self.refpred.run(contig, item.range.clone(), counts);
+            covered += counts.iter().filter(|x| x.coverage() > 0).count() as u64;
+            self.refpred.run(contig, item.range.clone(), counts)?;
             let reference = self.refpred.results();
+            if self.substitutions {
+                for (prednuc, cnt) in reference.predicted.iter().zip(counts) {
+                    substitutions.add(*prednuc, cnt);
+                }
+            }
+            if self.skip_refn {
+                refn_skipped += reference.reference.iter().filter(|x| **x == Nucleotide::Unknown).count() as u64;
+            }
 
             // Find loci that must be retained
             let mustloci = self.retainer.as_ref().map_or(vec![], |r| r.retained(contig, item.range.clone()));
@@ -116,9 +203,14 @@ where
                 if let Some(cnt) = item.cnts[strand] {
                     // debug_assert!(item.coverage[strand] > 0);
                     self.process(
+                        strand,
                         &mustloci,
                         item.range.clone(),
                         cnt,
+                        item.depth[strand],
+                        item.qualities[strand],
+                        item.fragments[strand],
+                        &item.samples[strand],
                         &reference,
                         &mut retained[strand].data,
                         &mut items[strand].data,
@@ -127,6 +219,18 @@ where
             }
         }
 
-        Batch { contig: contig.to_owned(), mapped: nc.mapped, retained, items }
+        Ok(Batch {
+            contig: contig.to_owned(),
+            mapped: nc.mapped,
+            mapq_255_dropped: nc.mapq_255_dropped,
+            umi_collapsed: nc.umi_collapsed,
+            coverage_capped: nc.coverage_capped,
+            refn_skipped,
+            covered,
+            substitutions,
+            junctions: nc.junctions,
+            retained,
+            items,
+        })
     }
 }
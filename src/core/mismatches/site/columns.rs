@@ -0,0 +1,261 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::ser::SerializeStruct;
+
+use crate::core::dna::ReqNucleotide;
+use crate::core::io::table::ColumnType;
+use crate::core::mismatches::FilterLabels;
+use crate::core::rpileup::ncounter::cnt::MAX_SPLICE_DIST;
+
+use super::vec::SerializeSiteRef;
+
+// Column of the site table: the header name & how to extract the value for a given site.
+// Per-sample and context columns are controlled by their own options and always follow the selected columns.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SiteColumn {
+    Contig,
+    Pos,
+    TrStrand,
+    RefNuc,
+    PredNuc,
+    RefSource,
+    // Number of sequenced nucleotides
+    Count(ReqNucleotide),
+    FwdDepth,
+    RevDepth,
+    StrandBias,
+    // Overlapping genes & the highest-priority feature from the user-provided annotation
+    Genes,
+    Feature,
+    // Significance against the background substitution rates (two-pass error model)
+    PValue,
+    QValue,
+    // Mean & min base quality of the mismatching nucleotides (empty for sites without mismatches)
+    MeanQAlt,
+    MinQAlt,
+    // Distinct fragments supporting the mismatching nucleotides (empty if unknown)
+    AltFragments,
+    // Distance to the nearest splice junction observed in the spliced reads
+    SpliceDist,
+    // PASS or the failed soft filters
+    Filter,
+    // Name of the run, e.g. to concatenate tables of different samples
+    Sample,
+}
+
+impl SiteColumn {
+    // Default columns, the ref_source & strand depth columns can be disabled to keep the old layout
+    pub fn layout(refsource: bool, strand_depth: bool) -> Vec<Self> {
+        let mut columns = vec![Self::Contig, Self::Pos, Self::TrStrand, Self::RefNuc, Self::PredNuc];
+        if refsource {
+            columns.push(Self::RefSource);
+        }
+        columns.extend([ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T].map(Self::Count));
+        if strand_depth {
+            columns.extend([Self::FwdDepth, Self::RevDepth, Self::StrandBias]);
+        }
+        columns
+    }
+
+    pub fn all() -> Vec<Self> {
+        Self::layout(true, true)
+    }
+
+    // Columns reported only with the genome annotation
+    pub fn annotation() -> [Self; 2] {
+        [Self::Genes, Self::Feature]
+    }
+
+    // Columns reported only with the error model
+    pub fn significance() -> [Self; 2] {
+        [Self::PValue, Self::QValue]
+    }
+
+    // Columns reported only if base qualities are collected
+    pub fn quality() -> [Self; 2] {
+        [Self::MeanQAlt, Self::MinQAlt]
+    }
+
+    // Columns reported only if fragments are tracked
+    pub fn fragments() -> [Self; 1] {
+        [Self::AltFragments]
+    }
+
+    // Columns reported only if splice junctions are collected
+    pub fn splicing() -> [Self; 1] {
+        [Self::SpliceDist]
+    }
+
+    // Columns reported only with the soft filters
+    pub fn filters() -> [Self; 1] {
+        [Self::Filter]
+    }
+
+    // Columns reported only if rows are tagged with the run name
+    pub fn tag() -> [Self; 1] {
+        [Self::Sample]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Contig => "contig",
+            Self::Pos => "pos",
+            Self::TrStrand => "trstrand",
+            Self::RefNuc => "refnuc",
+            Self::PredNuc => "prednuc",
+            Self::RefSource => "ref_source",
+            Self::Count(ReqNucleotide::A) => "A",
+            Self::Count(ReqNucleotide::C) => "C",
+            Self::Count(ReqNucleotide::G) => "G",
+            Self::Count(ReqNucleotide::T) => "T",
+            Self::FwdDepth => "fwd_depth",
+            Self::RevDepth => "rev_depth",
+            Self::StrandBias => "strand_bias",
+            Self::Genes => "genes",
+            Self::Feature => "feature",
+            Self::PValue => "pvalue",
+            Self::QValue => "qvalue",
+            Self::MeanQAlt => "meanq_alt",
+            Self::MinQAlt => "minq_alt",
+            Self::AltFragments => "alt_fragments",
+            Self::SpliceDist => "splice_dist",
+            Self::Filter => "filter",
+            Self::Sample => "sample",
+        }
+    }
+
+    // Human-readable description, e.g. for the `reat schema` dumps
+    pub fn description(&self) -> String {
+        match self {
+            Self::Contig => "Site contig".into(),
+            Self::Pos => "0-based site position".into(),
+            Self::TrStrand => "Transcription strand, predicted or deducted from the experiment design".into(),
+            Self::RefNuc => "Reference nucleotide from the FASTA assembly".into(),
+            Self::PredNuc => "Predicted reference nucleotide, two-letter genotype for heterozygous sites".into(),
+            Self::RefSource => "Origin of the predicted nucleotide: assembly, corrected or heterozygous".into(),
+            Self::Count(nuc) => format!("Number of sequenced {:?} nucleotides", nuc),
+            Self::FwdDepth => "Number of sequenced nucleotides from forward strand alignments".into(),
+            Self::RevDepth => "Number of sequenced nucleotides from reverse strand alignments".into(),
+            Self::StrandBias => "|fwd_depth - rev_depth| / (fwd_depth + rev_depth)".into(),
+            Self::Genes => "Comma-separated names of all overlapping genes".into(),
+            Self::Feature => "Highest-priority overlapping feature: CDS, UTR, exon, intron or intergenic".into(),
+            Self::PValue => "Significance against the background substitution rates".into(),
+            Self::QValue => "Benjamini-Hochberg adjusted p-value".into(),
+            Self::MeanQAlt => "Mean base quality of the mismatching nucleotides".into(),
+            Self::MinQAlt => "Min base quality of the mismatching nucleotides".into(),
+            Self::AltFragments => "Number of distinct fragments (read names) with the mismatching nucleotides".into(),
+            Self::SpliceDist => format!(
+                "Distance to the nearest exonic base flanking an intron of the spliced reads, capped at {}",
+                MAX_SPLICE_DIST
+            ),
+            Self::Filter => "PASS or semicolon-separated names of the failed output filters".into(),
+            Self::Sample => "Name of the run (--name)".into(),
+        }
+    }
+
+    // Nucleotide counts can be weighted => their type is given by the caller
+    pub fn ctype(&self, counts: ColumnType) -> ColumnType {
+        match self {
+            Self::Contig
+            | Self::TrStrand
+            | Self::RefNuc
+            | Self::PredNuc
+            | Self::RefSource
+            | Self::Genes
+            | Self::Feature
+            | Self::Filter
+            | Self::Sample => ColumnType::Str,
+            Self::Pos | Self::FwdDepth | Self::RevDepth | Self::MinQAlt | Self::AltFragments | Self::SpliceDist => {
+                ColumnType::UInt
+            }
+            Self::Count(_) => counts,
+            Self::StrandBias | Self::PValue | Self::QValue | Self::MeanQAlt => ColumnType::Float,
+        }
+    }
+
+    pub(super) fn serialize<S: SerializeStruct>(&self, state: &mut S, row: &SerializeSiteRef) -> Result<(), S::Error> {
+        let (key, data) = (self.name(), &row.data);
+        match self {
+            Self::Contig => state.serialize_field(key, row.contig),
+            Self::Pos => state.serialize_field(key, data.pos),
+            Self::TrStrand => state.serialize_field(key, row.strand.strand_symbol()),
+            Self::RefNuc => state.serialize_field(key, row.refnuc().symbol()),
+            Self::PredNuc => state.serialize_field(key, row.prednuc().symbol()),
+            Self::RefSource => state.serialize_field(key, data.refsource.symbol()),
+            Self::Count(nuc) => row.count(state, key, row.sequenced()[*nuc]),
+            Self::FwdDepth => state.serialize_field(key, &data.depth.forward),
+            Self::RevDepth => state.serialize_field(key, &data.depth.reverse),
+            Self::StrandBias => state.serialize_field(key, &data.depth.bias()),
+            Self::Genes => state.serialize_field(key, row.overlap.as_ref().map_or("", |x| x.genes.as_str())),
+            Self::Feature => state.serialize_field(key, row.overlap.as_ref().map_or("", |x| x.feature.name())),
+            Self::PValue => state.serialize_field(key, &data.pvalue),
+            Self::QValue => state.serialize_field(key, &data.qvalue),
+            Self::MeanQAlt => state.serialize_field(key, &data.qualities.alt(data.sequenced, data.prednuc).0),
+            Self::MinQAlt => state.serialize_field(key, &data.qualities.alt(data.sequenced, data.prednuc).1),
+            Self::AltFragments => state.serialize_field(key, data.alt_fragments),
+            Self::SpliceDist => state.serialize_field(key, data.splice_dist),
+            Self::Filter => state.serialize_field(key, &FilterLabels::label(row.failed)),
+            Self::Sample => state.serialize_field(key, row.sample.unwrap_or_default()),
+        }
+    }
+}
+
+impl FromStr for SiteColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let all = Self::all()
+            .into_iter()
+            .chain(Self::annotation())
+            .chain(Self::significance())
+            .chain(Self::quality())
+            .chain(Self::fragments())
+            .chain(Self::splicing())
+            .chain(Self::filters())
+            .chain(Self::tag())
+            .collect_vec();
+        all.iter().find(|x| x.name() == s).copied().ok_or_else(|| {
+            format!("Unknown site column \"{}\", expected one of: {}", s, all.iter().map(|x| x.name()).join(","))
+        })
+    }
+}
+
+impl Display for SiteColumn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let all = SiteColumn::all();
+        assert_eq!(all.len(), 13);
+        assert!(all.iter().map(|x| x.name()).all_unique());
+        let optional = SiteColumn::annotation()
+            .into_iter()
+            .chain(SiteColumn::significance())
+            .chain(SiteColumn::quality())
+            .chain(SiteColumn::fragments())
+            .chain(SiteColumn::splicing())
+            .chain(SiteColumn::filters())
+            .chain(SiteColumn::tag());
+        for column in all.into_iter().chain(optional) {
+            assert_eq!(SiteColumn::from_str(column.name()), Ok(column));
+        }
+        assert!(!SiteColumn::all().contains(&SiteColumn::Genes));
+        assert!(!SiteColumn::all().contains(&SiteColumn::QValue));
+        assert!(!SiteColumn::all().contains(&SiteColumn::MeanQAlt));
+        assert!(!SiteColumn::all().contains(&SiteColumn::AltFragments));
+        assert!(!SiteColumn::all().contains(&SiteColumn::SpliceDist));
+        assert!(!SiteColumn::all().contains(&SiteColumn::Sample));
+        assert_eq!(SiteColumn::from_str("G"), Ok(SiteColumn::Count(ReqNucleotide::G)));
+        assert!(SiteColumn::from_str("cov_treated").is_err());
+        assert!(SiteColumn::from_str("context").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio_types::genome::Position;
+use bio_types::strand::Strand;
+
+use crate::core::dna::Nucleotide;
+use crate::core::io::fasta::FastaReader;
+use crate::error::Error;
+
+// Sites are reported in the sorted order => the reference is fetched in large windows and reused for nearby sites
+const WINDOW: u64 = 65536;
+
+// Reference sequence around each site, pos - flank..=pos + flank
+pub struct SiteContext {
+    flank: u64,
+    reader: Box<dyn FastaReader>,
+    lengths: HashMap<String, u64>,
+    // Currently fetched reference window
+    contig: String,
+    window: Range<Position>,
+}
+
+impl SiteContext {
+    pub fn new(flank: u64, reader: Box<dyn FastaReader>, lengths: HashMap<String, u64>) -> Self {
+        Self { flank, reader, lengths, contig: String::new(), window: 0..0 }
+    }
+
+    // Reverse complemented for the reverse strand sites, positions outside the contig are reported as N
+    pub fn around(&mut self, contig: &str, pos: Position, strand: Strand) -> Result<String, Error> {
+        let length =
+            *self.lengths.get(contig).unwrap_or_else(|| panic!("Contig {} is absent in the reference", contig));
+        let (start, end) = (pos.saturating_sub(self.flank), (pos + self.flank + 1).min(length));
+        if contig != self.contig || start < self.window.start || end > self.window.end {
+            let window = start..(start + WINDOW).max(end).min(length);
+            self.reader.fetch(contig, window.clone())?;
+            self.contig = contig.to_owned();
+            self.window = window;
+        }
+
+        let reference = self.reader.result();
+        let mut context: Vec<Nucleotide> = (pos as i64 - self.flank as i64..=(pos + self.flank) as i64)
+            .map(|x| {
+                if x < 0 || x as u64 >= length {
+                    Nucleotide::Unknown
+                } else {
+                    reference[(x as u64 - self.window.start) as usize]
+                }
+            })
+            .collect();
+        if strand == Strand::Reverse {
+            context = context.into_iter().rev().map(|x| x.complementary()).collect();
+        }
+        Ok(context.iter().map(|x| x.symbol()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::io::fasta::MockFastaReader;
+
+    use super::*;
+
+    #[test]
+    fn around() {
+        use Nucleotide::*;
+        const SEQUENCE: [Nucleotide; 6] = [A, C, G, T, T, G];
+
+        let mut reader = MockFastaReader::new();
+        // Single fetch for all sites
+        reader.expect_fetch().withf(|_, range| *range == (0..6)).once().returning(|_, _| Ok(()));
+        reader.expect_result().return_const(SEQUENCE.to_vec());
+        let lengths = HashMap::from([("chr1".to_owned(), 6)]);
+
+        let mut context = SiteContext::new(1, Box::new(reader), lengths);
+        for (pos, strand, expected) in [
+            (0, Strand::Forward, "NAC"),
+            (1, Strand::Forward, "ACG"),
+            (1, Strand::Unknown, "ACG"),
+            (1, Strand::Reverse, "CGT"),
+            (3, Strand::Reverse, "AAC"),
+            (5, Strand::Forward, "TGN"),
+            (5, Strand::Reverse, "NCA"),
+        ] {
+            assert_eq!(context.around("chr1", pos, strand).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_contig() {
+        let mut context = SiteContext::new(1, Box::new(MockFastaReader::new()), HashMap::new());
+        let _ = context.around("chr1", 0, Strand::Forward);
+    }
+}
@@ -1,9 +1,11 @@
 use bio_types::genome::Position;
 use soa_derive::StructOfArray;
 
+use crate::core::dna::BaseQualities;
 use crate::core::dna::NucCounts;
 use crate::core::dna::Nucleotide;
-use crate::core::refpred::PredNucleotide;
+use crate::core::dna::StrandDepth;
+use crate::core::refpred::{PredNucleotide, RefSource};
 
 #[derive(Clone, Debug, Default, StructOfArray)]
 #[soa_derive(Clone, Debug)]
@@ -14,12 +16,40 @@ pub struct SiteData {
     pub refnuc: Nucleotide,
     // Auto corrected reference nucleotide
     pub prednuc: PredNucleotide,
+    // Whether the predicted nucleotide was taken from the assembly or corrected
+    pub refsource: RefSource,
     // Sequenced nucleotides
     pub sequenced: NucCounts,
+    // Forward / reverse alignments supporting the site (zeros if not requested)
+    pub depth: StrandDepth,
+    // Base qualities of the sequenced nucleotides (defaults if not requested)
+    pub qualities: BaseQualities,
+    // Distinct fragments supporting the mismatching nucleotides (None if not requested or unknown)
+    pub alt_fragments: Option<u32>,
+    // Sequenced nucleotides for each input file separately (if requested)
+    pub persample: Vec<NucCounts>,
+    // Significance against the background substitution rates (if requested)
+    pub pvalue: Option<f64>,
+    pub qvalue: Option<f64>,
+    // Distance to the nearest splice junction observed in the spliced reads (None if not requested)
+    pub splice_dist: Option<u32>,
 }
 
 impl From<SiteDataRef<'_>> for SiteData {
     fn from(x: SiteDataRef<'_>) -> Self {
-        Self { pos: *x.pos, refnuc: *x.refnuc, prednuc: *x.prednuc, sequenced: *x.sequenced }
+        Self {
+            pos: *x.pos,
+            refnuc: *x.refnuc,
+            prednuc: *x.prednuc,
+            refsource: *x.refsource,
+            sequenced: *x.sequenced,
+            depth: *x.depth,
+            qualities: *x.qualities,
+            alt_fragments: *x.alt_fragments,
+            persample: x.persample.to_owned(),
+            pvalue: *x.pvalue,
+            qvalue: *x.qvalue,
+            splice_dist: *x.splice_dist,
+        }
     }
 }
@@ -1,7 +1,13 @@
 pub use builder::SiteMismatchesBuilder;
+pub use columns::SiteColumn;
+pub use context::SiteContext;
 pub use data::{SiteData, SiteDataRef, SiteDataVec};
-pub use vec::SiteMismatchesVec;
+pub use ranking::{RankBy, TopK};
+pub use vec::{SampleColumns, SiteMismatchesVec};
 
 mod builder;
+mod columns;
+mod context;
 mod data;
+mod ranking;
 mod vec;
@@ -0,0 +1,252 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use bio_types::genome::Position;
+use bio_types::strand::Strand;
+
+use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::mismatches::prefilters::retain::{RetainSitesFromList, SitesRetainer};
+use crate::core::mismatches::MismatchesVec;
+use crate::core::refpred::PredNucleotide;
+use crate::core::statistics;
+
+use super::SiteMismatchesVec;
+
+// Confidence level of the Wilson score
+const SCORE_Z: f32 = 1.959964;
+
+// Key to select the strongest A-to-I editing candidates
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RankBy {
+    // Number of A->G mismatches relative to the transcription strand
+    AGCount,
+    // A->G mismatches / coverage
+    Freq,
+    // Lower bound of the 95% Wilson score interval of the A->G frequency
+    Score,
+}
+
+impl RankBy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RankBy::AGCount => "ag_count",
+            RankBy::Freq => "freq",
+            RankBy::Score => "score",
+        }
+    }
+}
+
+// Compact rank key of a record: its position in the contig batches & tie breakers
+#[derive(Copy, Clone, Debug)]
+struct Ranked {
+    score: f64,
+    pos: Position,
+    batch: u32,
+    ind: u32,
+}
+
+// Greater is better: higher score, then lower position => ties at the cutoff don't depend on the threads
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.pos.cmp(&self.pos))
+            .then_with(|| other.batch.cmp(&self.batch))
+            .then_with(|| other.ind.cmp(&self.ind))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked {}
+
+// Report only the best K sites of each contig. Applied once all batches of the contig are filtered & stranded
+#[derive(Clone)]
+pub struct TopK {
+    k: usize,
+    by: RankBy,
+    // Counts are in 1 / scale units, see Multimap
+    scale: u32,
+}
+
+impl TopK {
+    pub fn new(k: usize, by: RankBy, scale: u32) -> Self {
+        assert!(k > 0, "At least one site must be selected");
+        Self { k, by, scale }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn by(&self) -> RankBy {
+        self.by
+    }
+
+    // A->G mismatches relative to the transcription strand, both A->G & T->C are editing for the unknown strand.
+    // Heterozygous sites are never ranked as edited
+    fn score(&self, prednuc: PredNucleotide, sequenced: &NucCounts, trstrand: Strand) -> f64 {
+        let edited = match (prednuc, trstrand) {
+            (PredNucleotide::Homozygous(Nucleotide::A), Strand::Forward | Strand::Unknown) => sequenced.G,
+            (PredNucleotide::Homozygous(Nucleotide::T), Strand::Reverse | Strand::Unknown) => sequenced.C,
+            _ => 0,
+        };
+        let (edited, coverage) = (edited as f64 / self.scale as f64, sequenced.coverage() as f64 / self.scale as f64);
+        match self.by {
+            RankBy::AGCount => edited,
+            _ if coverage <= 0f64 => 0f64,
+            RankBy::Freq => edited / coverage,
+            RankBy::Score => statistics::wilson(edited, coverage, SCORE_Z).0 as f64,
+        }
+    }
+
+    // Keep (or mark with soft filters) the best K records of the contig, forced sites are never dropped.
+    // Returns the score of the weakest selected record, None if all records were selected
+    pub fn select(
+        &self,
+        contig: &str,
+        items: &mut Vec<SiteMismatchesVec>,
+        forced: Option<&RetainSitesFromList>,
+        soft: bool,
+    ) -> Option<f64> {
+        let is_forced = |pos: Position| forced.map_or(false, |x| !x.retained(contig, pos..pos + 1).is_empty());
+
+        // Min-heap of the best records so far => the weakest one is replaced in O(log K)
+        let mut heap: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(self.k + 1);
+        let mut ranked = 0;
+        for (batch, x) in items.iter().enumerate() {
+            let trstrand = x.trstrand();
+            for (ind, data) in x.data.iter().enumerate() {
+                if is_forced(*data.pos) {
+                    continue;
+                }
+                ranked += 1;
+                let score = self.score(*data.prednuc, data.sequenced, trstrand);
+                let record = Ranked { score, pos: *data.pos, batch: batch as u32, ind: ind as u32 };
+                if heap.len() < self.k {
+                    heap.push(Reverse(record));
+                } else if let Some(mut weakest) = heap.peek_mut() {
+                    if record > weakest.0 {
+                        *weakest = Reverse(record);
+                    }
+                }
+            }
+        }
+        if ranked <= self.k {
+            return None;
+        }
+        let cutoff = heap.peek().map(|x| x.0.score);
+
+        let mut selected: Vec<Vec<bool>> = items.iter().map(|x| vec![false; x.len()]).collect();
+        for Reverse(x) in heap {
+            selected[x.batch as usize][x.ind as usize] = true;
+        }
+        for (x, mut verdicts) in items.iter_mut().zip(selected) {
+            for (verdict, pos) in verdicts.iter_mut().zip(&x.data.pos) {
+                *verdict |= is_forced(*pos);
+            }
+            if soft {
+                x.mark("top-k", &verdicts);
+            } else {
+                let mut verdicts = verdicts.into_iter();
+                x.data.retain(|_| verdicts.next().unwrap());
+            }
+        }
+        if !soft {
+            items.retain(|x| !x.is_empty());
+        }
+        cutoff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use crate::core::mismatches::site::{SiteData, SiteDataVec};
+
+    use super::*;
+
+    fn site(pos: Position, refnuc: Nucleotide, sequenced: NucCounts) -> SiteData {
+        SiteData { pos, prednuc: PredNucleotide::Homozygous(refnuc), sequenced, ..Default::default() }
+    }
+
+    fn items() -> Vec<SiteMismatchesVec> {
+        // 3/10, 25/50 and 2/2 A->G
+        let mut forward = SiteDataVec::new();
+        forward.push(site(1, Nucleotide::A, NucCounts::new(7, 0, 3, 0)));
+        forward.push(site(5, Nucleotide::A, NucCounts::new(25, 0, 25, 0)));
+        forward.push(site(9, Nucleotide::A, NucCounts::new(0, 0, 2, 0)));
+        // 3/10 T->C on the reverse strand (tie with the first site), G->A is not editing
+        let mut reverse = SiteDataVec::new();
+        reverse.push(site(0, Nucleotide::T, NucCounts::new(0, 3, 0, 7)));
+        reverse.push(site(7, Nucleotide::G, NucCounts::new(10, 0, 40, 0)));
+        vec![
+            SiteMismatchesVec::new("1".into(), Strand::Forward, forward),
+            SiteMismatchesVec::new("1".into(), Strand::Reverse, reverse),
+        ]
+    }
+
+    fn positions(items: &[SiteMismatchesVec]) -> Vec<Vec<Position>> {
+        items.iter().map(|x| x.data.pos.clone()).collect()
+    }
+
+    #[test]
+    fn select() {
+        for (by, k, expected, cutoff) in [
+            // Ties are resolved by the position
+            (RankBy::AGCount, 2, vec![vec![5], vec![0]], Some(3f64)),
+            (RankBy::AGCount, 3, vec![vec![1, 5], vec![0]], Some(3f64)),
+            (RankBy::Freq, 1, vec![vec![9]], Some(1f64)),
+            (RankBy::Freq, 3, vec![vec![5, 9], vec![0]], Some(0.3)),
+            // Nothing to drop
+            (RankBy::Freq, 5, vec![vec![1, 5, 9], vec![0, 7]], None),
+        ] {
+            let mut items = items();
+            assert_eq!(TopK::new(k, by, 1).select("1", &mut items, None, false), cutoff, "{:?} {}", by, k);
+            assert_eq!(positions(&items), expected, "{:?} {}", by, k);
+        }
+
+        // Wilson score penalizes low coverage
+        let mut items = items();
+        let cutoff = TopK::new(1, RankBy::Score, 1).select("1", &mut items, None, false).unwrap();
+        assert!((cutoff - statistics::wilson(25f64, 50f64, SCORE_Z).0 as f64).abs() < 1e-6);
+        assert_eq!(positions(&items), vec![vec![5]]);
+    }
+
+    #[test]
+    fn forced() {
+        let forced = RetainSitesFromList::new(vec![Interval::new("1".into(), 7..8)]);
+        let mut items = items();
+        let cutoff = TopK::new(1, RankBy::Freq, 1).select("1", &mut items, Some(&forced), false);
+        assert_eq!(cutoff, Some(1f64));
+        assert_eq!(positions(&items), vec![vec![9], vec![7]]);
+
+        // Soft mode marks the records instead
+        let mut items = items();
+        TopK::new(1, RankBy::Freq, 1).select("1", &mut items, Some(&forced), true);
+        assert_eq!(positions(&items), vec![vec![1, 5, 9], vec![0, 7]]);
+    }
+
+    #[test]
+    fn scaled() {
+        let topk = TopK::new(1, RankBy::AGCount, 840);
+        let counts = NucCounts::new(840 * 7, 0, 840 * 3, 0);
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        assert_eq!(topk.score(prednuc, &counts, Strand::Forward), 3f64);
+        assert_eq!(topk.score(prednuc, &counts, Strand::Reverse), 0f64);
+        let score = TopK::new(1, RankBy::Score, 840).score(prednuc, &counts, Strand::Unknown);
+        let expected = statistics::wilson(3f64, 10f64, SCORE_Z).0 as f64;
+        assert!((score - expected).abs() < 1e-6);
+    }
+}
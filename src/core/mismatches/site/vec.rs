@@ -1,15 +1,22 @@
 use std::cmp::Ordering;
-use std::io::Write;
+use std::io;
+use std::iter::zip;
 
 use bio_types::strand::Strand;
-use csv::Writer;
 use itertools::Itertools;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
+use crate::core::annotation::{GenomicFeatures, Overlap};
+use crate::core::dna::{Multimap, NucCounts, Nucleotide};
+use crate::core::io::table::{ColumnInfo, ColumnType, RowWriter, Schema};
 use crate::core::mismatches::site::SiteDataRef;
-use crate::core::mismatches::MismatchesVec;
+use crate::core::mismatches::{FilterLabels, MismatchesVec};
+use crate::core::refpred::PredNucleotide;
+use crate::core::rpileup::ncounter::cnt::SpliceJunctions;
 
+use super::columns::SiteColumn;
+use super::context::SiteContext;
 use super::data::SiteDataVec;
 
 #[derive(Clone)]
@@ -17,11 +24,146 @@ pub struct SiteMismatchesVec {
     contig: String,
     trstrand: Strand,
     pub data: SiteDataVec,
+    // Failed soft filters, None if no soft filter was applied
+    filters: Option<FilterLabels>,
 }
 
 impl SiteMismatchesVec {
     pub fn new(contig: String, trstrand: Strand, data: SiteDataVec) -> Self {
-        Self { contig, trstrand, data }
+        Self { contig, trstrand, data, filters: None }
+    }
+
+    // Weighted counts are reported in natural units, optionally rounded to integers.
+    // If `orient` is set, nucleotides of reverse strand sites are reported relative to the transcription strand.
+    // Annotation (if any) is required for the genes & feature columns, run name - for the sample column
+    #[allow(clippy::too_many_arguments)]
+    pub fn ugly_in_contig_sort_and_to_tsv<R: RowWriter>(
+        items: Vec<Self>,
+        writer: &mut R,
+        columns: &[SiteColumn],
+        multimap: Multimap,
+        round: bool,
+        samples: Option<&SampleColumns>,
+        mut context: Option<&mut SiteContext>,
+        orient: bool,
+        annotation: Option<&GenomicFeatures>,
+        sample: Option<&str>,
+    ) -> csv::Result<()> {
+        for mut item in Self::sorted(&items, columns, multimap, round) {
+            item.samples = samples;
+            item.orient = orient;
+            item.sample = sample;
+            if let Some(annotation) = annotation {
+                item.overlap = Some(annotation.overlap(item.contig, *item.data.pos..*item.data.pos + 1));
+            }
+            if let Some(context) = context.as_mut() {
+                // Reference failures are reported along with the output ones
+                let around = context.around(item.contig, *item.data.pos, item.strand);
+                item.context = Some(around.map_err(io::Error::other)?);
+            }
+            writer.serialize(item)?;
+        }
+        Ok(())
+    }
+
+    // Columns of the TSV table, see ugly_in_contig_sort_and_to_tsv
+    pub fn header(columns: &[SiteColumn], samples: Option<&SampleColumns>, context: bool) -> Vec<String> {
+        let mut header: Vec<String> = columns.iter().map(|x| x.name().to_owned()).collect();
+        if let Some(samples) = samples {
+            header.extend(samples.columns.iter().flat_map(|(cov, mm)| [cov.to_string(), mm.to_string()]));
+        }
+        if context {
+            header.push("context".to_owned());
+        }
+        header
+    }
+
+    // Same as header, but with column types
+    pub fn schema(
+        columns: &[SiteColumn],
+        multimap: Multimap,
+        round: bool,
+        samples: Option<&SampleColumns>,
+        context: bool,
+    ) -> Schema {
+        let counts = match (multimap, round) {
+            (Multimap::Full, _) | (_, true) => ColumnType::UInt,
+            (_, false) => ColumnType::Float,
+        };
+        let mut schema: Schema = columns.iter().map(|x| (x.name().to_owned(), x.ctype(counts))).collect();
+        for column in Self::header(&[], samples, false) {
+            schema.push((column, counts));
+        }
+        if context {
+            schema.push(("context".to_owned(), ColumnType::Str));
+        }
+        schema
+    }
+
+    // Same as schema, but with column descriptions
+    pub fn describe(
+        columns: &[SiteColumn],
+        multimap: Multimap,
+        round: bool,
+        samples: Option<&SampleColumns>,
+        context: bool,
+    ) -> Vec<ColumnInfo> {
+        let mut descriptions: Vec<String> = columns.iter().map(|x| x.description()).collect();
+        if let Some(samples) = samples {
+            for (cov, _) in &samples.columns {
+                let sample = cov.trim_start_matches("cov_");
+                descriptions.push(format!("Coverage in the {} sample", sample));
+                descriptions.push(format!("Mismatches relative to the predicted nucleotide in the {} sample", sample));
+            }
+        }
+        if context {
+            descriptions.push("Reference sequence around the site, reverse-complemented for the reverse strand".into());
+        }
+        zip(Self::schema(columns, multimap, round, samples, context), descriptions)
+            .map(|((name, ctype), description)| ColumnInfo { name, ctype, description })
+            .collect()
+    }
+
+    pub fn ugly_in_contig_sort_and_to_mpileup<R: RowWriter>(items: Vec<Self>, writer: &mut R) -> csv::Result<()> {
+        for item in Self::sorted(&items, &[], Multimap::Full, false) {
+            writer.write_record(item.mpileup())?;
+        }
+        Ok(())
+    }
+
+    fn sorted<'a>(
+        items: &'a [Self],
+        columns: &'a [SiteColumn],
+        multimap: Multimap,
+        round: bool,
+    ) -> impl Iterator<Item = SerializeSiteRef<'a>> {
+        fn pos_then_strand(first: &SerializeSiteRef, second: &SerializeSiteRef) -> Ordering {
+            let mut ord = first.data.pos.cmp(second.data.pos);
+            if ord.is_eq() {
+                ord = first.strand.strand_symbol().cmp(second.strand.strand_symbol());
+            }
+            ord
+        }
+
+        items
+            .iter()
+            .flat_map(move |x| {
+                x.data.iter().enumerate().map(move |(ind, data)| SerializeSiteRef {
+                    contig: &x.contig,
+                    strand: x.trstrand,
+                    data,
+                    columns,
+                    multimap,
+                    round,
+                    samples: None,
+                    context: None,
+                    orient: false,
+                    overlap: None,
+                    sample: None,
+                    failed: x.filters.as_ref().map(|f| f.get(ind)),
+                })
+            })
+            .sorted_by(pos_then_strand)
     }
 }
 
@@ -42,44 +184,167 @@ impl MismatchesVec for SiteMismatchesVec {
         self.data.is_empty()
     }
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut Writer<F>) -> csv::Result<()> {
-        fn pos_then_strand(first: &SerializeSiteRef, second: &SerializeSiteRef) -> Ordering {
-            let mut ord = first.data.pos.cmp(second.data.pos);
-            if ord.is_eq() {
-                ord = first.strand.strand_symbol().cmp(second.strand.strand_symbol());
-            }
-            ord
+    fn past_end(&self, length: u64) -> usize {
+        self.data.pos.iter().filter(|x| **x >= length).count()
+    }
+
+    fn ugly_in_contig_sort_and_to_csv<R: RowWriter>(items: Vec<Self>, writer: &mut R) -> csv::Result<()> {
+        Self::ugly_in_contig_sort_and_to_tsv(
+            items,
+            writer,
+            &SiteColumn::all(),
+            Multimap::Full,
+            false,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+    }
+
+    fn mark(&mut self, filter: &'static str, verdicts: &[bool]) {
+        let records = self.data.len();
+        self.filters.get_or_insert_with(|| FilterLabels::new(records)).mark(filter, verdicts);
+    }
+
+    fn annotate_splicing(&mut self, junctions: &SpliceJunctions) {
+        for (dist, pos) in self.data.splice_dist.iter_mut().zip(&self.data.pos) {
+            *dist = Some(junctions.distance(*pos));
         }
+    }
+}
 
-        let iter = items
-            .iter()
-            .flat_map(|x| x.data.iter().map(|data| SerializeSiteRef { contig: &x.contig, strand: x.trstrand, data }))
-            .sorted_by(pos_then_strand);
-        for item in iter {
-            writer.serialize(item)?;
+// Names of per-sample columns. Serde requires static field names => they are leaked once per run
+#[derive(Clone, Debug)]
+pub struct SampleColumns {
+    columns: Vec<(&'static str, &'static str)>,
+}
+
+impl SampleColumns {
+    pub fn new(samples: &[String]) -> Self {
+        let leak = |x: String| -> &'static str { Box::leak(x.into_boxed_str()) };
+        let columns = samples.iter().map(|x| (leak(format!("cov_{}", x)), leak(format!("mm_{}", x)))).collect();
+        Self { columns }
+    }
+}
+
+pub(super) struct SerializeSiteRef<'a> {
+    pub(super) contig: &'a str,
+    pub(super) strand: Strand,
+    pub(super) data: SiteDataRef<'a>,
+    columns: &'a [SiteColumn],
+    multimap: Multimap,
+    round: bool,
+    // Coverage & mismatches for each input file, if requested
+    samples: Option<&'a SampleColumns>,
+    // Reference sequence around the site, if requested
+    context: Option<String>,
+    // Complement nucleotides of reverse strand sites
+    orient: bool,
+    // Annotated genes & features, if requested
+    pub(super) overlap: Option<Overlap>,
+    // Run name, if requested
+    pub(super) sample: Option<&'a str>,
+    // Failed soft filters, if any
+    pub(super) failed: Option<&'a [&'static str]>,
+}
+
+impl SerializeSiteRef<'_> {
+    // Nucleotides relative to the transcription strand (if requested) or the reference strand
+    pub(super) fn refnuc(&self) -> Nucleotide {
+        match self.oriented() {
+            true => self.data.refnuc.complementary(),
+            false => *self.data.refnuc,
+        }
+    }
+
+    pub(super) fn prednuc(&self) -> PredNucleotide {
+        match self.oriented() {
+            true => self.data.prednuc.complementary(),
+            false => *self.data.prednuc,
+        }
+    }
+
+    pub(super) fn sequenced(&self) -> NucCounts {
+        match self.oriented() {
+            true => self.data.sequenced.complementary(),
+            false => *self.data.sequenced,
+        }
+    }
+
+    fn oriented(&self) -> bool {
+        self.orient && self.strand == Strand::Reverse
+    }
+
+    pub(super) fn count<S: SerializeStruct>(&self, state: &mut S, key: &'static str, cnt: u32) -> Result<(), S::Error> {
+        match (self.multimap, self.round) {
+            (Multimap::Full, _) => state.serialize_field(key, &cnt),
+            (_, false) => state.serialize_field(key, &self.multimap.natural(cnt)),
+            (_, true) => state.serialize_field(key, &(self.multimap.natural(cnt).round() as u32)),
+        }
+    }
+
+    // Mismatches are relative to the predicted reference, i.e. all but both alleles for heterozygous sites
+    fn samples<S: SerializeStruct>(&self, state: &mut S, samples: &SampleColumns) -> Result<(), S::Error> {
+        debug_assert_eq!(samples.columns.len(), self.data.persample.len());
+        for ((cov, mm), counts) in zip(&samples.columns, self.data.persample) {
+            let mismatches = match *self.data.prednuc {
+                PredNucleotide::Heterozygous((n1, n2)) if n1 != n2 => {
+                    counts.mismatches(n1) - (counts.coverage() - counts.mismatches(n2))
+                }
+                PredNucleotide::Homozygous(nuc) | PredNucleotide::Heterozygous((nuc, _)) => counts.mismatches(nuc),
+            };
+            self.count(state, cov, counts.coverage())?;
+            self.count(state, mm, mismatches)?;
         }
         Ok(())
     }
-}
 
-struct SerializeSiteRef<'a> {
-    contig: &'a str,
-    strand: Strand,
-    data: SiteDataRef<'a>,
+    // contig, 1-based position, reference, depth, bases
+    fn mpileup(&self) -> [String; 5] {
+        let sequenced = self.data.sequenced;
+        let mut bases = String::with_capacity(sequenced.coverage() as usize);
+        for (nuc, cnt) in [
+            (Nucleotide::A, sequenced.A),
+            (Nucleotide::C, sequenced.C),
+            (Nucleotide::G, sequenced.G),
+            (Nucleotide::T, sequenced.T),
+        ] {
+            // Reverse strand sites are reported in lowercase, just like reverse strand reads in mpileup
+            let symbol = match (nuc == *self.data.refnuc, self.strand) {
+                (true, Strand::Reverse) => ",".to_owned(),
+                (true, _) => ".".to_owned(),
+                (false, Strand::Reverse) => nuc.symbol().to_lowercase(),
+                (false, _) => nuc.symbol().to_owned(),
+            };
+            bases.push_str(&symbol.repeat(cnt as usize));
+        }
+        [
+            self.contig.to_owned(),
+            (self.data.pos + 1).to_string(),
+            self.data.refnuc.symbol().to_owned(),
+            sequenced.coverage().to_string(),
+            bases,
+        ]
+    }
 }
 
 impl Serialize for SerializeSiteRef<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("SiteMismatches", 9)?;
-        state.serialize_field("contig", self.contig)?;
-        state.serialize_field("pos", &self.data.pos)?;
-        state.serialize_field("trstrand", self.strand.strand_symbol())?;
-        state.serialize_field("refnuc", self.data.refnuc.symbol())?;
-        state.serialize_field("prednuc", self.data.prednuc.symbol())?;
-        state.serialize_field("A", &self.data.sequenced.A)?;
-        state.serialize_field("C", &self.data.sequenced.C)?;
-        state.serialize_field("G", &self.data.sequenced.G)?;
-        state.serialize_field("T", &self.data.sequenced.T)?;
+        let len = self.columns.len()
+            + self.samples.map_or(0, |x| 2 * x.columns.len())
+            + if self.context.is_some() { 1 } else { 0 };
+        let mut state = serializer.serialize_struct("SiteMismatches", len)?;
+        for column in self.columns {
+            column.serialize(&mut state, self)?;
+        }
+        if let Some(samples) = self.samples {
+            self.samples(&mut state, samples)?;
+        }
+        if let Some(context) = &self.context {
+            state.serialize_field("context", context)?;
+        }
         state.end()
     }
 }
@@ -88,8 +353,10 @@ impl Serialize for SerializeSiteRef<'_> {
 mod test {
     use serde_test::{assert_ser_tokens, Token};
 
-    use crate::core::dna::{NucCounts, Nucleotide};
-    use crate::core::refpred::PredNucleotide;
+    use crate::core::dna::{BaseQualities, NucCounts, ReqNucleotide, StrandDepth};
+    use crate::core::mismatches::site::SiteData;
+    use crate::core::refpred::RefSource;
+    use crate::core::rpileup::ncounter::cnt::MAX_SPLICE_DIST;
 
     use super::*;
 
@@ -99,12 +366,33 @@ mod test {
             pos: &13,
             refnuc: &Nucleotide::A,
             prednuc: &PredNucleotide::Heterozygous((Nucleotide::T, Nucleotide::G)),
+            refsource: &RefSource::Heterozygous,
             sequenced: &NucCounts::new(1, 2, 3, 4),
+            depth: &StrandDepth::new(7, 3),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
         };
         assert_ser_tokens(
-            &SerializeSiteRef { contig: "MySuperContig", strand: Strand::Unknown, data },
+            &SerializeSiteRef {
+                contig: "MySuperContig",
+                strand: Strand::Unknown,
+                data,
+                columns: &SiteColumn::all(),
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: None,
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed: None,
+            },
             &[
-                Token::Struct { name: "SiteMismatches", len: 9 },
+                Token::Struct { name: "SiteMismatches", len: 13 },
                 Token::Str("contig"),
                 Token::Str("MySuperContig"),
                 Token::Str("pos"),
@@ -114,7 +402,9 @@ mod test {
                 Token::Str("refnuc"),
                 Token::Str("A"),
                 Token::Str("prednuc"),
-                Token::Str("G/T"),
+                Token::Str("GT"),
+                Token::Str("ref_source"),
+                Token::Str("heterozygous"),
                 Token::Str("A"),
                 Token::U32(1),
                 Token::Str("C"),
@@ -123,8 +413,637 @@ mod test {
                 Token::U32(3),
                 Token::Str("T"),
                 Token::U32(4),
+                Token::Str("fwd_depth"),
+                Token::U32(7),
+                Token::Str("rev_depth"),
+                Token::U32(3),
+                Token::Str("strand_bias"),
+                Token::F32(0.4),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn context() {
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &RefSource::FromAssembly,
+            sequenced: &NucCounts::new(1, 0, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data,
+                columns: &SiteColumn::layout(false, false),
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: Some("TAG".to_owned()),
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 10 },
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("pos"),
+                Token::U64(13),
+                Token::Str("trstrand"),
+                Token::Str("+"),
+                Token::Str("refnuc"),
+                Token::Str("A"),
+                Token::Str("prednuc"),
+                Token::Str("A"),
+                Token::Str("A"),
+                Token::U32(1),
+                Token::Str("C"),
+                Token::U32(0),
+                Token::Str("G"),
+                Token::U32(2),
+                Token::Str("T"),
+                Token::U32(0),
+                Token::Str("context"),
+                Token::Str("TAG"),
                 Token::StructEnd,
             ],
         );
     }
+
+    #[test]
+    fn multimap() {
+        let scale = Multimap::SCALE;
+        let sequenced = NucCounts::new(3 * scale, scale / 2, scale / 4, 0);
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let refsource = RefSource::FromAssembly;
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &refsource,
+            sequenced: &sequenced,
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+
+        let serialize = |round| {
+            let site = SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data,
+                columns: &SiteColumn::layout(false, false),
+                multimap: Multimap::Fractional,
+                round,
+                samples: None,
+                context: None,
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed: None,
+            };
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.serialize(site).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(serialize(false), "contig,pos,trstrand,refnuc,prednuc,A,C,G,T\nchr1,13,+,A,A,3.0,0.5,0.25,0.0\n");
+        // Rounding half away from zero
+        assert_eq!(serialize(true), "contig,pos,trstrand,refnuc,prednuc,A,C,G,T\nchr1,13,+,A,A,3,1,0,0\n");
+    }
+
+    #[test]
+    fn samples() {
+        let prednuc = PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G));
+        let refsource = RefSource::Heterozygous;
+        let persample = vec![NucCounts::new(3, 1, 2, 0), NucCounts::new(0, 0, 0, 0)];
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &refsource,
+            sequenced: &NucCounts::new(3, 1, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &persample,
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        let samples = SampleColumns::new(&["treated".into(), "control".into()]);
+
+        let site = SerializeSiteRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data,
+            columns: &SiteColumn::layout(false, false),
+            multimap: Multimap::Full,
+            round: false,
+            samples: Some(&samples),
+            context: None,
+            orient: false,
+            overlap: None,
+            sample: None,
+            failed: None,
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(site).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "contig,pos,trstrand,refnuc,prednuc,A,C,G,T,cov_treated,mm_treated,cov_control,mm_control\n\
+            chr1,13,+,A,AG,3,1,2,0,6,1,0,0\n"
+        );
+    }
+
+    #[test]
+    fn describe() {
+        let persample = vec![NucCounts::new(3, 1, 2, 0)];
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &PredNucleotide::Homozygous(Nucleotide::A),
+            refsource: &RefSource::FromAssembly,
+            sequenced: &NucCounts::new(3, 1, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &persample,
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        let samples = SampleColumns::new(&["treated".into()]);
+        let columns = SiteColumn::all()
+            .into_iter()
+            .chain(SiteColumn::annotation())
+            .chain(SiteColumn::significance())
+            .chain(SiteColumn::quality())
+            .chain(SiteColumn::fragments())
+            .chain(SiteColumn::splicing())
+            .collect_vec();
+
+        // The described columns always match the header of the written table
+        let site = SerializeSiteRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data,
+            columns: &columns,
+            multimap: Multimap::Fractional,
+            round: false,
+            samples: Some(&samples),
+            context: Some("AAA".into()),
+            orient: false,
+            overlap: None,
+            sample: None,
+            failed: None,
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(site).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let described = SiteMismatchesVec::describe(&columns, Multimap::Fractional, false, Some(&samples), true);
+        assert_eq!(written.lines().next().unwrap(), described.iter().map(|x| x.name.as_str()).join(","));
+        assert!(described.iter().all(|x| !x.description.is_empty()));
+        let types = described.iter().map(|x| (x.name.as_str(), x.ctype)).collect_vec();
+        assert!(types.contains(&("A", ColumnType::Float)));
+        assert!(types.contains(&("mm_treated", ColumnType::Float)));
+        assert_eq!(types.last(), Some(&("context", ColumnType::Str)));
+    }
+
+    #[test]
+    fn qualities() {
+        let mut qualities = BaseQualities::default();
+        for (nuc, qual) in [(ReqNucleotide::A, 40), (ReqNucleotide::G, 12), (ReqNucleotide::G, 20)] {
+            qualities.add(nuc, qual, 1);
+        }
+        let serialize = |sequenced: NucCounts, strand| {
+            let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+            let data = SiteDataRef {
+                pos: &13,
+                refnuc: &Nucleotide::A,
+                prednuc: &prednuc,
+                refsource: &RefSource::FromAssembly,
+                sequenced: &sequenced,
+                depth: &StrandDepth::default(),
+                qualities: &qualities,
+                alt_fragments: &None,
+                persample: &vec![],
+                pvalue: &None,
+                qvalue: &None,
+                splice_dist: &None,
+            };
+            let site = SerializeSiteRef {
+                contig: "chr1",
+                strand,
+                data,
+                columns: &[SiteColumn::Pos, SiteColumn::MeanQAlt, SiteColumn::MinQAlt],
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: None,
+                orient: true,
+                overlap: None,
+                sample: None,
+                failed: None,
+            };
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.serialize(site).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(serialize(NucCounts::new(1, 0, 2, 0), Strand::Forward), "pos,meanq_alt,minq_alt\n13,16.0,12\n");
+        // Orientation doesn't change the mismatching nucleotides
+        assert_eq!(serialize(NucCounts::new(1, 0, 2, 0), Strand::Reverse), "pos,meanq_alt,minq_alt\n13,16.0,12\n");
+        // Empty for sites without mismatches
+        assert_eq!(serialize(NucCounts::new(1, 0, 0, 0), Strand::Forward), "pos,meanq_alt,minq_alt\n13,,\n");
+    }
+
+    #[test]
+    fn alt_fragments() {
+        let serialize = |alt_fragments: Option<u32>| {
+            let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+            let data = SiteDataRef {
+                pos: &13,
+                refnuc: &Nucleotide::A,
+                prednuc: &prednuc,
+                refsource: &RefSource::FromAssembly,
+                sequenced: &NucCounts::new(1, 0, 2, 0),
+                depth: &StrandDepth::default(),
+                qualities: &BaseQualities::default(),
+                alt_fragments: &alt_fragments,
+                persample: &vec![],
+                pvalue: &None,
+                qvalue: &None,
+                splice_dist: &None,
+            };
+            let site = SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data,
+                columns: &[SiteColumn::Pos, SiteColumn::AltFragments],
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: None,
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed: None,
+            };
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.serialize(site).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(serialize(Some(2)), "pos,alt_fragments\n13,2\n");
+        // Unknown if the predicted reference differs from the assembly
+        assert_eq!(serialize(None), "pos,alt_fragments\n13,\n");
+    }
+
+    #[test]
+    fn filter() {
+        let serialize = |failed: Option<&[&'static str]>| {
+            let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+            let data = SiteDataRef {
+                pos: &13,
+                refnuc: &Nucleotide::A,
+                prednuc: &prednuc,
+                refsource: &RefSource::FromAssembly,
+                sequenced: &NucCounts::new(1, 0, 2, 0),
+                depth: &StrandDepth::default(),
+                qualities: &BaseQualities::default(),
+                alt_fragments: &None,
+                persample: &vec![],
+                pvalue: &None,
+                qvalue: &None,
+                splice_dist: &None,
+            };
+            let site = SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data,
+                columns: &[SiteColumn::Pos, SiteColumn::Filter],
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: None,
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed,
+            };
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.serialize(site).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(serialize(Some(&["ref-base", "meanq"])), "pos,filter\n13,ref-base;meanq\n");
+        assert_eq!(serialize(Some(&[])), "pos,filter\n13,PASS\n");
+        // Forced sites are never marked
+        assert_eq!(serialize(None), "pos,filter\n13,PASS\n");
+    }
+
+    #[test]
+    fn sample() {
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &RefSource::FromAssembly,
+            sequenced: &NucCounts::new(1, 0, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        let columns = [SiteColumn::Sample, SiteColumn::Pos];
+        let site = SerializeSiteRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data,
+            columns: &columns,
+            multimap: Multimap::Full,
+            round: false,
+            samples: None,
+            context: None,
+            orient: false,
+            overlap: None,
+            sample: Some("run1"),
+            failed: None,
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(site).unwrap();
+        assert_eq!(String::from_utf8(writer.into_inner().unwrap()).unwrap(), "sample,pos\nrun1,13\n");
+        assert_eq!(SiteMismatchesVec::header(&columns, None, false), ["sample", "pos"]);
+    }
+
+    #[test]
+    fn splice_dist() {
+        // Spliced reads with the intron 101..200 => junction flanks at 100 and 200
+        let junctions = SpliceJunctions::new(&[(100, 3), (200, 3)].into_iter().collect());
+        let mut data = SiteDataVec::new();
+        for pos in [90, 100, 103, 2000] {
+            data.push(SiteData { pos, ..Default::default() });
+        }
+        let mut items = SiteMismatchesVec::new("chr1".into(), Strand::Forward, data);
+        let serialize = |items: &SiteMismatchesVec| {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            let columns = [SiteColumn::Pos, SiteColumn::SpliceDist];
+            SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+                vec![items.clone()],
+                &mut writer,
+                &columns,
+                Multimap::Full,
+                false,
+                None,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        // Not annotated
+        assert_eq!(serialize(&items), "pos,splice_dist\n90,\n100,\n103,\n2000,\n");
+
+        items.annotate_splicing(&junctions);
+        assert_eq!(items.data.splice_dist, [Some(10), Some(0), Some(3), Some(MAX_SPLICE_DIST)]);
+        assert_eq!(serialize(&items), "pos,splice_dist\n90,10\n100,0\n103,3\n2000,1000\n");
+    }
+
+    #[test]
+    fn columns() {
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &RefSource::FromAssembly,
+            sequenced: &NucCounts::new(1, 0, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        // Selected columns are reported in the requested order, context is always the last one
+        let columns = [SiteColumn::Pos, SiteColumn::Count(ReqNucleotide::G), SiteColumn::Contig];
+        assert_eq!(SiteMismatchesVec::header(&columns, None, true), ["pos", "G", "contig", "context"]);
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                data,
+                columns: &columns,
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: Some("TAG".to_owned()),
+                orient: false,
+                overlap: None,
+                sample: None,
+                failed: None,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 4 },
+                Token::Str("pos"),
+                Token::U64(13),
+                Token::Str("G"),
+                Token::U32(2),
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("context"),
+                Token::Str("TAG"),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn significance() {
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &RefSource::FromAssembly,
+            sequenced: &NucCounts::new(1, 0, 2, 0),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &Some(0.001),
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        let columns = [SiteColumn::Pos, SiteColumn::PValue, SiteColumn::QValue];
+        let site = SerializeSiteRef {
+            contig: "chr1",
+            strand: Strand::Forward,
+            data,
+            columns: &columns,
+            multimap: Multimap::Full,
+            round: false,
+            samples: None,
+            context: None,
+            orient: false,
+            overlap: None,
+            sample: None,
+            failed: None,
+        };
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(site).unwrap();
+        // Untested sites have empty values
+        assert_eq!(String::from_utf8(writer.into_inner().unwrap()).unwrap(), "pos,pvalue,qvalue\n13,0.001,\n");
+    }
+
+    #[test]
+    fn header() {
+        let header = SiteMismatchesVec::header(&SiteColumn::layout(false, false), None, false);
+        assert_eq!(header, ["contig", "pos", "trstrand", "refnuc", "prednuc", "A", "C", "G", "T"]);
+        let header = SiteMismatchesVec::header(&SiteColumn::layout(true, false), None, false);
+        assert_eq!(header, ["contig", "pos", "trstrand", "refnuc", "prednuc", "ref_source", "A", "C", "G", "T"]);
+        let header = SiteMismatchesVec::header(&SiteColumn::layout(false, true), None, false);
+        assert_eq!(header[9..], ["fwd_depth", "rev_depth", "strand_bias"]);
+        let samples = SampleColumns::new(&["treated".into(), "control".into()]);
+        let header = SiteMismatchesVec::header(&SiteColumn::layout(false, true), Some(&samples), true);
+        assert_eq!(
+            header[9..],
+            [
+                "fwd_depth",
+                "rev_depth",
+                "strand_bias",
+                "cov_treated",
+                "mm_treated",
+                "cov_control",
+                "mm_control",
+                "context"
+            ]
+        );
+    }
+
+    #[test]
+    fn schema() {
+        let columns = SiteColumn::all();
+        let samples = SampleColumns::new(&["treated".into()]);
+        for (multimap, round, counts) in [
+            (Multimap::Full, false, ColumnType::UInt),
+            (Multimap::Fractional, true, ColumnType::UInt),
+            (Multimap::Fractional, false, ColumnType::Float),
+        ] {
+            let schema = SiteMismatchesVec::schema(&columns, multimap, round, Some(&samples), true);
+            let names: Vec<&str> = schema.iter().map(|x| x.0.as_str()).collect();
+            assert_eq!(names, SiteMismatchesVec::header(&columns, Some(&samples), true));
+
+            let types: Vec<ColumnType> = schema.into_iter().map(|x| x.1).collect();
+            assert_eq!(
+                types[..6],
+                [ColumnType::Str, ColumnType::UInt, ColumnType::Str, ColumnType::Str, ColumnType::Str, ColumnType::Str]
+            );
+            assert_eq!(types[6..10], [counts; 4]);
+            assert_eq!(types[10..13], [ColumnType::UInt, ColumnType::UInt, ColumnType::Float]);
+            assert_eq!(types[13..], [counts, counts, ColumnType::Str]);
+        }
+    }
+
+    #[test]
+    fn mpileup() {
+        let sequenced = NucCounts::new(3, 0, 2, 1);
+        let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+        let refsource = RefSource::FromAssembly;
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::A,
+            prednuc: &prednuc,
+            refsource: &refsource,
+            sequenced: &sequenced,
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+
+        let site = |strand| SerializeSiteRef {
+            contig: "chr1",
+            strand,
+            data,
+            columns: &[],
+            multimap: Multimap::Full,
+            round: false,
+            samples: None,
+            context: None,
+            orient: false,
+            overlap: None,
+            sample: None,
+            failed: None,
+        };
+        assert_eq!(site(Strand::Forward).mpileup(), ["chr1", "14", "A", "6", "...GGT"]);
+        assert_eq!(site(Strand::Reverse).mpileup(), ["chr1", "14", "A", "6", ",,,ggt"]);
+    }
+
+    #[test]
+    fn orient() {
+        let prednuc = PredNucleotide::Heterozygous((Nucleotide::T, Nucleotide::C));
+        let data = SiteDataRef {
+            pos: &13,
+            refnuc: &Nucleotide::T,
+            prednuc: &prednuc,
+            refsource: &RefSource::Heterozygous,
+            sequenced: &NucCounts::new(0, 2, 1, 5),
+            depth: &StrandDepth::default(),
+            qualities: &BaseQualities::default(),
+            alt_fragments: &None,
+            persample: &vec![],
+            pvalue: &None,
+            qvalue: &None,
+            splice_dist: &None,
+        };
+        let serialize = |strand, orient| {
+            let site = SerializeSiteRef {
+                contig: "chr1",
+                strand,
+                data,
+                columns: &SiteColumn::layout(false, false),
+                multimap: Multimap::Full,
+                round: false,
+                samples: None,
+                context: None,
+                orient,
+                overlap: None,
+                sample: None,
+                failed: None,
+            };
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            writer.serialize(site).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(serialize(Strand::Reverse, false), "chr1,13,-,T,CT,0,2,1,5\n");
+        // T->C on the reference strand is A->G on the transcription strand
+        assert_eq!(serialize(Strand::Reverse, true), "chr1,13,-,A,AG,5,1,2,0\n");
+        assert_eq!(serialize(Strand::Forward, true), "chr1,13,+,T,CT,0,2,1,5\n");
+    }
 }
@@ -1,11 +1,15 @@
+pub mod annotation;
 pub mod dna;
 pub mod hooks;
 pub mod io;
 pub mod mismatches;
+pub mod pipeline;
 pub mod read;
 pub mod refpred;
 pub mod rpileup;
 pub mod runner;
+pub mod statistics;
 pub mod stranding;
 pub mod strandutil;
+pub mod testkit;
 pub mod workload;
@@ -0,0 +1,67 @@
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use crate::core::mismatches::MismatchesVec;
+
+// Pipeline observer for embedders, e.g. to stream results into a database while the run is in progress.
+// Progress is reported from worker threads as soon as workload items are processed. Batches are reported
+// after stats & filters (hooks), which are applied in the workload order once all bins of the contig are processed.
+pub trait EventSink<Mismatches>: Sync {
+    // Non-empty batch of reported items, i.e. the same items are passed to the stream_* callbacks afterwards
+    fn on_batch(&self, contig: &str, items: &Mismatches);
+    // Processed & total workload items
+    fn on_progress(&self, _done: u64, _total: u64) {}
+    // Total number of processed reads
+    fn on_finish(&self, _reads: u32) {}
+}
+
+// Forwards copies of reported batches to the receiver, progress events are ignored
+pub struct ChannelSink<Mismatches> {
+    sender: Mutex<Sender<(String, Mismatches)>>,
+}
+
+impl<Mismatches> ChannelSink<Mismatches> {
+    pub fn new(sender: Sender<(String, Mismatches)>) -> Self {
+        Self { sender: Mutex::new(sender) }
+    }
+}
+
+impl<Mismatches: MismatchesVec + Clone + Send> EventSink<Mismatches> for ChannelSink<Mismatches> {
+    fn on_batch(&self, contig: &str, items: &Mismatches) {
+        // The run goes on even if the receiver is gone
+        let _ = self.sender.lock().unwrap().send((contig.to_owned(), items.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use bio_types::strand::Strand;
+
+    use crate::core::mismatches::site::{SiteDataVec, SiteMismatchesVec};
+
+    use super::*;
+
+    #[test]
+    fn channel() {
+        let (sender, receiver) = mpsc::channel();
+        let sink = ChannelSink::new(sender);
+        for contig in ["1", "2"] {
+            let items = SiteMismatchesVec::new(contig.to_owned(), Strand::Forward, SiteDataVec::new());
+            sink.on_batch(contig, &items);
+        }
+        sink.on_progress(1, 2);
+        sink.on_finish(10);
+        drop(sink);
+
+        let received: Vec<(String, Strand)> = receiver.into_iter().map(|(contig, x)| (contig, x.trstrand())).collect();
+        assert_eq!(received, vec![("1".to_owned(), Strand::Forward), ("2".to_owned(), Strand::Forward)]);
+
+        // Closed channels are ignored
+        let (sender, receiver) = mpsc::channel();
+        drop(receiver);
+        let items = SiteMismatchesVec::new("3".to_owned(), Strand::Reverse, SiteDataVec::new());
+        ChannelSink::new(sender).on_batch("3", &items);
+    }
+}
@@ -0,0 +1,343 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bio_types::genome::AbstractInterval;
+use itertools::Itertools;
+use rayon::prelude::*;
+use rust_htslib::bam::Record;
+
+pub use events::{ChannelSink, EventSink};
+pub use paired::stream_paired;
+pub use roi::{run_rois, stream_rois, ROIRunConfig};
+pub use site::{run_sites, stream_sites, SiteRunConfig};
+use thread_cache::ThreadCache;
+
+use crate::core::hooks::stats::EditingStat;
+use crate::core::hooks::HooksEngine;
+use crate::core::mismatches::{Batch, MismatchesVec};
+use crate::core::rpileup::ncounter::cnt::SpliceJunctions;
+use crate::core::rpileup::ncounter::filters;
+use crate::core::runner::Runner;
+use crate::core::strandutil::Stranded;
+use crate::error::Error;
+
+mod events;
+mod paired;
+mod roi;
+mod site;
+mod thread_cache;
+
+pub type ReadsFilter = filters::Tracked<
+    Record,
+    filters::Sequential<Record, filters::Sequential<Record, filters::ByQuality, filters::ByFlags>, filters::ByTags>,
+>;
+
+// Optional callbacks to report the processing progress (e.g. render a progress bar)
+pub trait Progress: Sync {
+    fn start(&self, total: u64);
+    // Processed workload items & reads counted for them
+    fn inc(&self, delta: u64, reads: u32);
+    fn finish(
+        &self,
+        items: usize,
+        reads: Stranded<u32>,
+        mapq_255_dropped: u32,
+        umi_collapsed: u32,
+        coverage_capped: u32,
+        refn_skipped: u64,
+    );
+    // Non-fatal issues noticed during the run
+    fn warn(&self, message: &str);
+}
+
+// Territory processed within a single contig
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContigSummary {
+    pub contig: String,
+    // Positions covered by at least one read (site mode only)
+    pub covered: u64,
+    // Items reported after filters
+    pub emitted: usize,
+    // Processed workload items & items without any counted reads
+    pub workload: usize,
+    pub empty: usize,
+}
+
+pub struct Summary<Mismatches> {
+    // Collected stats
+    pub stats: Vec<Box<dyn EditingStat<Mismatches>>>,
+    // Total number of reported items & processed reads
+    pub items: usize,
+    pub reads: Stranded<u32>,
+    // Reads rejected only due to the mapq = 255 policy
+    pub mapq_255_dropped: u32,
+    // Reads collapsed as UMI duplicates
+    pub umi_collapsed: u32,
+    // Reads skipped due to the coverage cap
+    pub coverage_capped: u32,
+    // Positions skipped due to the unknown(N) reference policy
+    pub refn_skipped: u64,
+    // Processed contigs in the output order
+    pub contigs: Vec<ContigSummary>,
+}
+
+// Workload items re-processed by the self-check in the deterministic mode
+const SELF_CHECK_SIZE: usize = 16;
+
+// Fingerprint of the serialized batches items
+fn digest<Mismatches: MismatchesVec>(batches: Vec<Batch<Mismatches>>) -> u64 {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    for batch in batches {
+        for item in [batch.items, batch.retained] {
+            let items = vec![item.forward, item.unknown, item.reverse];
+            Mismatches::ugly_in_contig_sort_and_to_csv(items, &mut writer).expect("Failed to serialize mismatches");
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    let serialized = writer.into_inner().unwrap_or_else(|x| panic!("Failed to serialize mismatches: {}", x.error()));
+    serialized.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Results must not depend on the threads scheduling => the same workload processed by a single runner
+// and by a pool of runners must produce identical outputs
+fn self_check<RunnerT, Mismatches, Workload>(runner: &RunnerT, workload: Vec<Workload>) -> Result<(), Error>
+where
+    Mismatches: Send + MismatchesVec,
+    Workload: Sized + Send + Clone,
+    RunnerT: for<'runner> Runner<'runner, Mismatches, Workload = Workload> + Clone + Send,
+{
+    let mut single = runner.clone();
+    let sequential = workload
+        .iter()
+        .cloned()
+        .map(|w| single.run(w))
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let pool = runner.clone();
+    let ctxstore = ThreadCache::new(move || RefCell::new(pool.clone()));
+    let parallel = workload
+        .into_par_iter()
+        .map(|w| ctxstore.get().borrow_mut().run(w))
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    assert_eq!(
+        digest::<Mismatches>(sequential),
+        digest::<Mismatches>(parallel),
+        "Deterministic mode self-check failed: results depend on the threads scheduling"
+    );
+    Ok(())
+}
+
+// Workload grouped by contigs in the requested order, the rest (or all) contigs are sorted by name.
+// Contigs absent in the requested order are flagged, finished ones are skipped
+fn schedule<Workload: AbstractInterval>(
+    workload: Vec<Workload>,
+    order: Option<&[String]>,
+    finished: &HashSet<String>,
+) -> Vec<(String, Vec<Workload>, bool)> {
+    let mut percontig: HashMap<String, Vec<Workload>> = HashMap::with_capacity(120);
+    for w in workload.into_iter().filter(|x| !finished.contains(x.contig())) {
+        percontig.entry(w.contig().to_owned()).or_default().push(w);
+    }
+    let rank: HashMap<&str, usize> = order.into_iter().flatten().enumerate().map(|(i, x)| (x.as_str(), i)).collect();
+    let rank_of = |contig: &String| rank.get(contig.as_str()).copied().unwrap_or(usize::MAX);
+    percontig
+        .into_iter()
+        .sorted_by(|x, y| rank_of(&x.0).cmp(&rank_of(&y.0)).then_with(|| x.0.cmp(&y.0)))
+        .map(|(contig, workload)| {
+            let unordered = order.is_some() && !rank.contains_key(contig.as_str());
+            (contig, workload, unordered)
+        })
+        .collect()
+}
+
+fn warn_unordered(progress: Option<&dyn Progress>, contig: &str) {
+    if let Some(progress) = progress {
+        progress.warn(&format!("contig {} is absent in the requested contigs order, reported last.", contig));
+    }
+}
+
+// Hooks & counters applied to the batches of each contig
+struct Collector<'a, Mismatches, Hooks> {
+    hooks: Hooks,
+    events: Option<&'a dyn EventSink<Mismatches>>,
+    reads: Stranded<u32>,
+    items: usize,
+    mapq_255_dropped: u32,
+    umi_collapsed: u32,
+    coverage_capped: u32,
+    refn_skipped: u64,
+    contigs: Vec<ContigSummary>,
+}
+
+impl<'a, Mismatches, Hooks> Collector<'a, Mismatches, Hooks>
+where
+    Mismatches: MismatchesVec,
+    Hooks: HooksEngine<Mismatches>,
+{
+    fn new(hooks: Hooks, events: Option<&'a dyn EventSink<Mismatches>>) -> Self {
+        Self {
+            hooks,
+            events,
+            reads: Default::default(),
+            items: 0,
+            mapq_255_dropped: 0,
+            umi_collapsed: 0,
+            coverage_capped: 0,
+            refn_skipped: 0,
+            contigs: Vec::new(),
+        }
+    }
+
+    // Batches must be in the workload order => collected stats don't depend on the threads
+    fn collect(&mut self, contig: &str, edits: Vec<Option<Batch<Mismatches>>>) -> Vec<Mismatches> {
+        // Items without results & items without counted reads are empty, regardless of the output filters
+        let empty = edits
+            .iter()
+            .filter(|x| x.as_ref().map_or(true, |x| x.mapped.forward + x.mapped.reverse + x.mapped.unknown == 0))
+            .count();
+        // Junctions of all windows are merged first => records near the window edges see junctions of the neighbors
+        let junctions = edits.iter().flatten().filter_map(|x| x.junctions.as_ref()).collect_vec();
+        let junctions = (!junctions.is_empty()).then(|| SpliceJunctions::merge(junctions));
+
+        let mut mismatches = Vec::with_capacity(edits.len() * 6);
+        let mut summary =
+            ContigSummary { contig: contig.to_owned(), covered: 0, emitted: 0, workload: edits.len(), empty };
+        for mut batch in edits.into_iter().flatten() {
+            // Annotated before the hooks => output filters can use the annotation
+            if let Some(junctions) = &junctions {
+                batch.items.apply_mut(|x, _| x.annotate_splicing(junctions));
+                batch.retained.apply_mut(|x, _| x.annotate_splicing(junctions));
+            }
+            self.hooks.on_finish(&mut batch);
+            self.reads = self.reads + batch.mapped;
+            self.mapq_255_dropped += batch.mapq_255_dropped;
+            self.umi_collapsed += batch.umi_collapsed;
+            self.coverage_capped += batch.coverage_capped;
+            self.refn_skipped += batch.refn_skipped;
+            summary.covered += batch.covered;
+            for item in [batch.items, batch.retained] {
+                for mm in [item.forward, item.unknown, item.reverse] {
+                    if mm.is_empty() {
+                        continue;
+                    }
+                    summary.emitted += mm.len();
+                    if let Some(events) = self.events {
+                        events.on_batch(contig, &mm);
+                    }
+                    mismatches.push(mm);
+                }
+            }
+        }
+        self.items += summary.emitted;
+        self.contigs.push(summary);
+        mismatches
+    }
+
+    fn summary(self) -> Summary<Mismatches> {
+        Summary {
+            stats: self.hooks.stats(),
+            items: self.items,
+            reads: self.reads,
+            mapq_255_dropped: self.mapq_255_dropped,
+            umi_collapsed: self.umi_collapsed,
+            coverage_capped: self.coverage_capped,
+            refn_skipped: self.refn_skipped,
+            contigs: self.contigs,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn stream<RunnerT, Mismatches, Workload>(
+    workload: Vec<Workload>,
+    runner: RunnerT,
+    hooks: impl HooksEngine<Mismatches>,
+    order: Option<&[String]>,
+    finished: &HashSet<String>,
+    progress: Option<&dyn Progress>,
+    events: Option<&dyn EventSink<Mismatches>>,
+    deterministic: bool,
+    // Process the workload one by one in the given order, e.g. when reads are streamed
+    sequential: bool,
+    // Errors (e.g. failed writes) abort the processing
+    mut oncontig: impl FnMut(&str, Vec<Mismatches>) -> Result<(), Error>,
+) -> Result<Summary<Mismatches>, Error>
+where
+    Mismatches: Send + MismatchesVec,
+    Workload: Sized + Send + Clone + AbstractInterval,
+    RunnerT: for<'runner> Runner<'runner, Mismatches, Workload = Workload> + Clone + Send,
+{
+    let percontig = schedule(workload, order, finished);
+    let (total, done) = (percontig.iter().map(|x| x.1.len() as u64).sum(), AtomicU64::new(0));
+    if let Some(progress) = progress {
+        progress.start(total);
+    }
+    if let Some(events) = events {
+        events.on_progress(0, total);
+    }
+
+    // Debug builds only, the check is too expensive for the production runs.
+    // Sequential runs don't depend on the threads & their workload can't be processed twice
+    if deterministic && !sequential && cfg!(debug_assertions) {
+        if let Some((_, workload, _)) = percontig.first() {
+            self_check(&runner, workload.iter().take(SELF_CHECK_SIZE).cloned().collect())?;
+        }
+    }
+
+    // Process contigs one by one and stream results to the caller => only a single contig is kept in memory
+    let ctxstore = ThreadCache::new(move || RefCell::new(runner.clone()));
+    let mut collector = Collector::new(hooks, events);
+    for (contig, workload, unordered) in percontig {
+        let run = |w| {
+            let result = ctxstore.get().borrow_mut().run(w);
+            if let Some(progress) = progress {
+                let reads = match &result {
+                    Ok(Some(x)) => x.mapped.forward + x.mapped.reverse + x.mapped.unknown,
+                    _ => 0,
+                };
+                progress.inc(1, reads);
+            }
+            if let Some(events) = events {
+                events.on_progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+            }
+            result
+        };
+        // Worker errors (e.g. corrupted inputs) abort the whole run
+        let edits: Vec<Option<Batch<Mismatches>>> = if sequential {
+            workload.into_iter().map(run).collect::<Result<_, Error>>()?
+        } else {
+            workload.into_par_iter().map(run).collect::<Result<_, Error>>()?
+        };
+
+        let mismatches = collector.collect(&contig, edits);
+        // Empty contigs are moved to the end as well => warn regardless of the results
+        if unordered {
+            warn_unordered(progress, &contig);
+        }
+        // Contigs without results are reported as well => the caller knows that they are finished
+        oncontig(&contig, mismatches)?;
+    }
+
+    let summary = collector.summary();
+    if let Some(progress) = progress {
+        progress.finish(
+            summary.items,
+            summary.reads,
+            summary.mapq_255_dropped,
+            summary.umi_collapsed,
+            summary.coverage_capped,
+            summary.refn_skipped,
+        );
+    }
+    if let Some(events) = events {
+        events.on_finish(summary.reads.forward + summary.reads.reverse + summary.reads.unknown);
+    }
+    Ok(summary)
+}
@@ -0,0 +1,194 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bio_types::genome::Position;
+use rayon::prelude::*;
+
+use crate::core::hooks::engine::REATHooksEngine;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::rpileup::hts::{HTSPileupEngine, HTSThreadPool};
+use crate::core::rpileup::ncounter::cnt::StrandedNucCounter;
+use crate::core::rpileup::paired::PairedCollider;
+use crate::core::runner::{PairedBatches, PairedRunner};
+use crate::core::stranding::deduce::DeduceStrandByDesign;
+use crate::core::strandutil::Stranded;
+use crate::core::workload::{PairedWorkload, ROIWorkload, SiteWorkload};
+use crate::error::Error;
+
+use super::thread_cache::ThreadCache;
+use super::{roi, site, Collector, Progress, ROIRunConfig, SiteRunConfig, Summary};
+
+type Workload = PairedWorkload<ROIWorkload, SiteWorkload>;
+type Batches = PairedBatches<ROIMismatchesVec, SiteMismatchesVec>;
+
+// ROIs & sites counted in a single pass over the reads: overlapping windows are fetched once and their reads are
+// passed to the counters of both. Results are the same as for stream_rois & stream_sites with the same configs.
+// Both configs must have the same inputs & library design, site inputs can't be streamed. The contigs order,
+// progress & multithreading options are taken from the ROIs config
+pub fn stream_paired(
+    mut rois: ROIRunConfig,
+    mut sites: SiteRunConfig,
+    onroi: impl FnMut(&str, Vec<ROIMismatchesVec>) -> Result<(), Error>,
+    onsite: impl FnMut(&str, Vec<SiteMismatchesVec>) -> Result<(), Error>,
+) -> Result<(Summary<ROIMismatchesVec>, Summary<SiteMismatchesVec>), Error> {
+    if sites.stream.is_some() {
+        return Err(Error::Input("Streamed inputs can't be shared by ROIs & sites".to_owned()));
+    }
+    if rois.bamfiles != sites.bamfiles || rois.indexes != sites.indexes || rois.reference != sites.reference {
+        return Err(Error::Input("ROIs & sites must be counted for the same inputs".to_owned()));
+    }
+    if rois.design != sites.design {
+        return Err(Error::Input("ROIs & sites must be counted for the same library design".to_owned()));
+    }
+    let (roibuilder, roicounter) = roi::prepare(&mut rois);
+    let (sitebuilder, sitecounter) = site::prepare(&mut sites);
+
+    // Windows are counted separately => the joined size only limits the reads fetched at once
+    let maxspan = 2 * rois.maxwsize.max(sites.maxwsize) as Position;
+    let workload = PairedWorkload::union(rois.workload, sites.workload, maxspan);
+
+    // Single pool for all readers, it outlives them since each pileuper holds a reference
+    let tpool = (rois.hts_threads > 0).then(|| Arc::new(HTSThreadPool::new(rois.hts_threads)));
+
+    let (contigs, finished, progress) = (rois.contigs.as_deref(), &rois.finished, rois.progress.as_deref());
+    let collectors =
+        (Collector::new(rois.hooks, rois.events.as_deref()), Collector::new(sites.hooks, sites.events.as_deref()));
+    let (mut roistrander, mut sitestrander) = (rois.stranding, sites.stranding);
+
+    // Compose stranders + pileuper & launch the processing.
+    // The deterministic self-check is not supported, each window is counted exactly as in the separate runs
+    macro_rules! launch {
+        ($roicounter:expr, $sitecounter:expr) => {{
+            let mut collider = PairedCollider::new($roicounter, $sitecounter);
+            if rois.zero_coverage {
+                collider = collider.with_empty_first();
+            }
+            if sites.empty_bins {
+                collider = collider.with_empty_second();
+            }
+            let mut pileuper =
+                HTSPileupEngine::new(rois.bamfiles, rois.indexes.as_deref(), rois.reference.as_deref(), collider)?;
+            if let Some(tpool) = tpool {
+                pileuper = pileuper.with_thread_pool(tpool);
+            }
+            if rois.prefetch {
+                pileuper = pileuper.with_prefetch();
+            }
+            let runner = PairedRunner::new((roibuilder, roistrander), (sitebuilder, sitestrander), pileuper);
+            let ctxstore = ThreadCache::new(move || RefCell::new(runner.clone()));
+            let run = |w: Workload| ctxstore.get().borrow_mut().run(w);
+            process(workload, run, collectors, contigs, finished, progress, onroi, onsite)
+        }};
+    }
+
+    match rois.design {
+        None => launch!(roicounter, sitecounter),
+        Some(design) => {
+            // Remove all stranding algorithm -> they are not required
+            roistrander.clear();
+            sitestrander.clear();
+            let deductor = DeduceStrandByDesign::new(design);
+            launch!(StrandedNucCounter::new(roicounter, deductor), StrandedNucCounter::new(sitecounter, deductor))
+        }
+    }
+}
+
+type Collectors<'a> = (
+    Collector<'a, ROIMismatchesVec, REATHooksEngine<ROIMismatchesVec>>,
+    Collector<'a, SiteMismatchesVec, REATHooksEngine<SiteMismatchesVec>>,
+);
+
+#[allow(clippy::too_many_arguments)]
+fn process(
+    workload: Vec<Workload>,
+    run: impl Fn(Workload) -> Result<Batches, Error> + Sync,
+    collectors: Collectors,
+    order: Option<&[String]>,
+    finished: &HashSet<String>,
+    progress: Option<&dyn Progress>,
+    mut onroi: impl FnMut(&str, Vec<ROIMismatchesVec>) -> Result<(), Error>,
+    mut onsite: impl FnMut(&str, Vec<SiteMismatchesVec>) -> Result<(), Error>,
+) -> Result<(Summary<ROIMismatchesVec>, Summary<SiteMismatchesVec>), Error> {
+    let (mut rois, mut sites) = collectors;
+    let (roievents, siteevents) = (rois.events, sites.events);
+    let onprogress = |done: u64, total: u64| {
+        if let Some(events) = roievents {
+            events.on_progress(done, total);
+        }
+        if let Some(events) = siteevents {
+            events.on_progress(done, total);
+        }
+    };
+
+    let percontig = super::schedule(workload, order, finished);
+    let (total, done) = (percontig.iter().map(|x| x.1.len() as u64).sum(), AtomicU64::new(0));
+    if let Some(progress) = progress {
+        progress.start(total);
+    }
+    onprogress(0, total);
+
+    // Reads counted for at least one window of any kind
+    let mut reads = 0;
+    for (contig, workload, unordered) in percontig {
+        // Windows are restored in the workload order => stats are collected as in the separate runs
+        let edits: Vec<((Vec<usize>, Vec<usize>), Batches)> = workload
+            .into_par_iter()
+            .map(|w| {
+                let indices = (w.first().iter().map(|x| x.0).collect(), w.second().iter().map(|x| x.0).collect());
+                let result = run(w)?;
+                if let Some(progress) = progress {
+                    progress.inc(1, result.mapped);
+                }
+                onprogress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+                Ok((indices, result))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let (mut roiedits, mut siteedits) = (Vec::new(), Vec::new());
+        for ((first, second), batches) in edits {
+            reads += batches.mapped;
+            roiedits.extend(first.into_iter().zip(batches.first));
+            siteedits.extend(second.into_iter().zip(batches.second));
+        }
+        roiedits.sort_by_key(|x| x.0);
+        siteedits.sort_by_key(|x| x.0);
+
+        // Contigs are reported only to the consumers with workload there
+        let roimm = (!roiedits.is_empty()).then(|| rois.collect(&contig, roiedits.into_iter().map(|x| x.1).collect()));
+        let sitemm =
+            (!siteedits.is_empty()).then(|| sites.collect(&contig, siteedits.into_iter().map(|x| x.1).collect()));
+        if unordered {
+            super::warn_unordered(progress, &contig);
+        }
+        if let Some(mismatches) = roimm {
+            onroi(&contig, mismatches)?;
+        }
+        if let Some(mismatches) = sitemm {
+            onsite(&contig, mismatches)?;
+        }
+    }
+
+    let (rois, sites) = (rois.summary(), sites.summary());
+    if let Some(progress) = progress {
+        // Reads are shared by both tables => they are reported once, without strands. Skipped positions are
+        // reported for each table, while read-level counters are (nearly) the same for both of them
+        progress.finish(
+            rois.items + sites.items,
+            Stranded::unknown(reads),
+            rois.mapq_255_dropped.max(sites.mapq_255_dropped),
+            rois.umi_collapsed.max(sites.umi_collapsed),
+            rois.coverage_capped.max(sites.coverage_capped),
+            rois.refn_skipped + sites.refn_skipped,
+        );
+    }
+    if let Some(events) = roievents {
+        events.on_finish(reads);
+    }
+    if let Some(events) = siteevents {
+        events.on_finish(reads);
+    }
+    Ok((rois, sites))
+}
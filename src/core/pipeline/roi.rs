@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rust_htslib::bam::Record;
+
+use crate::core::dna::Multimap;
+use crate::core::hooks::engine::REATHooksEngine;
+use crate::core::hooks::filters;
+use crate::core::io::fasta::FastaReader;
+use crate::core::mismatches::prefilters;
+use crate::core::mismatches::prefilters::retain::RetainROIFromList;
+use crate::core::mismatches::roi::{ROIMismatchesBuilder, ROIMismatchesVec};
+use crate::core::refpred::RefEngine;
+use crate::core::rpileup::hts::{HTSPileupEngine, HTSThreadPool};
+use crate::core::rpileup::ncounter::cnt::{
+    BaseNucCounter, ExcludedRegions, MinROIOverlap, ROINucCounter, StrandedNucCounter,
+};
+use crate::core::runner::REATRunner;
+use crate::core::stranding::deduce::{DeduceStrandByDesign, StrandSpecificExperimentDesign};
+use crate::core::stranding::predict::REATStrandingEngine;
+use crate::core::workload::ROIWorkload;
+use crate::error::Error;
+
+use super::{EventSink, Progress, ReadsFilter, Summary};
+
+pub struct ROIRunConfig {
+    pub bamfiles: Vec<PathBuf>,
+    // Explicit index of each BAM file, None => indexes are looked up next to the files
+    pub indexes: Option<Vec<PathBuf>>,
+    // Reference assembly to decode CRAM files, not required for BAM files
+    pub reference: Option<PathBuf>,
+    pub workload: Vec<ROIWorkload>,
+    pub maxwsize: usize,
+    pub refnucpred: Box<dyn RefEngine>,
+    pub readfilter: ReadsFilter,
+    // Skip positions with unknown(N) assembly nucleotides
+    pub skip_refn: bool,
+    pub trim5: u16,
+    pub trim3: u16,
+    // Count bases covered by both overlapping mates only once
+    pub overlaps_dedup: bool,
+    // Collapse reads with the same start, strand and UMI (given tag)
+    pub umi: Option<[u8; 2]>,
+    // Threads in the shared htslib pool for BGZF decompression (0 => no pool)
+    pub hts_threads: u32,
+    // Decode reads in a helper thread while the previous chunk is being counted
+    pub prefetch: bool,
+    // Track forward / reverse alignments supporting each position
+    pub strand_depth: bool,
+    // Track fragments supporting mismatches (alt_fragments), bases are compared with the given assembly
+    pub alt_fragments: Option<Box<dyn FastaReader>>,
+    // Weighting of multi-mapped reads. ROI counts are always reported in natural units, but count thresholds of
+    // the reference predictor must be multiplied by Multimap::scale
+    pub multimap: Multimap,
+    // Max mismatches (relative to the given reference) a single read may contribute to a window, None => unlimited
+    pub max_read_contribution: Option<(u32, Box<dyn FastaReader>)>,
+    // Skip reads once their first position is covered by the given number of counted reads, None => unlimited
+    pub max_coverage: Option<u32>,
+    // Count covering reads with soft-clipped bases / indels inside each ROI
+    pub alnstats: bool,
+    // Count reads only for ROIs they overlap by at least the given bases / fraction of their aligned length
+    pub min_roi_overlap: Option<MinROIOverlap>,
+    // Bases inside excluded regions are never counted, even if the workload wasn't subtracted (e.g. in flanks)
+    pub excluded: Option<Arc<ExcludedRegions>>,
+    // None => unstranded library, strand is predicted by the stranding engine
+    pub design: Option<StrandSpecificExperimentDesign>,
+    pub stranding: REATStrandingEngine<ROIMismatchesVec>,
+    pub hooks: REATHooksEngine<ROIMismatchesVec>,
+    pub prefilter: Option<prefilters::ByMismatches>,
+    // Per-file mismatches support, counts for each input file are tracked within the window
+    pub replicates: Option<prefilters::ByReplicates>,
+    pub retain: Option<RetainROIFromList>,
+    // Track counts for each input file separately
+    pub per_source: bool,
+    // Keep per-position counts for ROIs that pass the prefilter
+    pub profile: bool,
+    // Merge ROIs with the same name & strand, the workload must be grouped by ROIWorkload::group_by_name
+    pub group_by_name: bool,
+    // Report unstranded counts of ROIs with a known (BED) strand on that strand, skipping the prediction
+    pub trust_roi_strand: bool,
+    // Report each ROI without coverage once with zeroed counts (even if there are no reads in the window),
+    // zero-coverage records of other ROIs are dropped. Not supported for grouped ROIs
+    pub zero_coverage: bool,
+    // Feed the stats & discard all ROIs right away, nothing is passed to the callback
+    pub stats_only: bool,
+    // Report contigs in the given order (e.g. from the FASTA index), by name otherwise
+    pub contigs: Option<Vec<String>>,
+    // Contigs finished by a previous (interrupted) run, their workload is skipped
+    pub finished: HashSet<String>,
+    // Debug builds: verify that results don't depend on the threads scheduling before the processing
+    pub deterministic: bool,
+    pub progress: Option<Box<dyn Progress>>,
+    // Library-level observer of the reported batches & progress
+    pub events: Option<Box<dyn EventSink<ROIMismatchesVec>>>,
+}
+
+impl ROIRunConfig {
+    pub fn new(
+        bamfiles: Vec<PathBuf>,
+        workload: Vec<ROIWorkload>,
+        maxwsize: usize,
+        refnucpred: Box<dyn RefEngine>,
+        readfilter: ReadsFilter,
+    ) -> Self {
+        Self {
+            bamfiles,
+            indexes: None,
+            reference: None,
+            workload,
+            maxwsize,
+            refnucpred,
+            readfilter,
+            skip_refn: false,
+            trim5: 0,
+            trim3: 0,
+            overlaps_dedup: true,
+            umi: None,
+            hts_threads: 0,
+            prefetch: false,
+            strand_depth: true,
+            alt_fragments: None,
+            multimap: Multimap::Full,
+            max_read_contribution: None,
+            max_coverage: None,
+            alnstats: true,
+            min_roi_overlap: None,
+            excluded: None,
+            design: None,
+            stranding: REATStrandingEngine::new(),
+            hooks: REATHooksEngine::new(),
+            prefilter: None,
+            replicates: None,
+            retain: None,
+            per_source: false,
+            profile: false,
+            group_by_name: false,
+            trust_roi_strand: true,
+            zero_coverage: false,
+            stats_only: false,
+            contigs: None,
+            finished: HashSet::new(),
+            deterministic: false,
+            progress: None,
+            events: None,
+        }
+    }
+}
+
+// Mismatches builder & reads counter described by the config, their parts are taken from the config
+#[allow(clippy::type_complexity)]
+pub(super) fn prepare(
+    config: &mut ROIRunConfig,
+) -> (ROIMismatchesBuilder<RetainROIFromList, prefilters::ByMismatches>, ROINucCounter<Record, ReadsFilter>) {
+    let refnucpred = dyn_clone::clone_box(&*config.refnucpred);
+    let mut builder = ROIMismatchesBuilder::new(config.maxwsize, refnucpred, config.retain.take(), config.prefilter);
+    if config.profile {
+        builder = builder.with_profile();
+    }
+    if config.group_by_name {
+        builder = builder.with_grouping();
+    }
+    if config.skip_refn {
+        builder = builder.with_refn_skipped();
+    }
+    if config.trust_roi_strand {
+        builder = builder.with_trusted_strand();
+    }
+    if config.zero_coverage {
+        assert!(!config.group_by_name, "Zero-coverage ROIs can't be reported for ROIs grouped by name");
+        builder = builder.with_zero_coverage();
+    }
+    if config.multimap != Multimap::Full {
+        builder = builder.with_multimap(config.multimap);
+    }
+    if let Some(replicates) = config.replicates {
+        builder = builder.with_replicates(replicates);
+    }
+
+    let mut counter = BaseNucCounter::new(config.maxwsize, config.readfilter.clone(), config.trim5, config.trim3);
+    if config.overlaps_dedup {
+        counter = counter.with_overlaps_dedup();
+    }
+    if let Some(tag) = config.umi {
+        counter = counter.with_umi_dedup(tag);
+    }
+    if config.strand_depth {
+        counter = counter.with_strand_depth();
+    }
+    if let Some(assembly) = config.alt_fragments.take() {
+        counter = counter.with_alt_fragments(assembly);
+    }
+    if config.multimap != Multimap::Full {
+        counter = counter.with_multimap(config.multimap);
+    }
+    if let Some((max, reference)) = config.max_read_contribution.take() {
+        counter = counter.with_max_read_contribution(max, reference);
+    }
+    if let Some(max) = config.max_coverage {
+        counter = counter.with_max_coverage(max);
+    }
+    if config.alnstats {
+        counter = counter.with_aln_stats();
+    }
+    if let Some(excluded) = config.excluded.take() {
+        counter = counter.with_excluded(excluded);
+    }
+    if config.per_source || config.replicates.is_some() {
+        counter = counter.with_sources(config.bamfiles.len());
+    }
+    let mut counter = ROINucCounter::new(counter);
+    if let Some(min) = config.min_roi_overlap {
+        counter = counter.with_min_overlap(min);
+    }
+    if config.stats_only {
+        // Stats are collected before filters => they still see all ROIs
+        config.hooks.add_filter(Box::new(filters::Discard));
+    }
+    (builder, counter)
+}
+
+// Results are passed to the callback contig by contig, collected stats & counters are returned
+pub fn stream_rois(
+    mut config: ROIRunConfig,
+    oncontig: impl FnMut(&str, Vec<ROIMismatchesVec>) -> Result<(), Error>,
+) -> Result<Summary<ROIMismatchesVec>, Error> {
+    let (builder, counter) = prepare(&mut config);
+
+    // Single pool for all readers, it outlives them since each pileuper holds a reference
+    let tpool = (config.hts_threads > 0).then(|| Arc::new(HTSThreadPool::new(config.hts_threads)));
+
+    let (contigs, finished, progress) = (config.contigs.as_deref(), &config.finished, config.progress.as_deref());
+    let (events, deterministic) = (config.events.as_deref(), config.deterministic);
+    let (workload, hooks) = (config.workload, config.hooks);
+    let mut strander = config.stranding;
+    match config.design {
+        None => {
+            // Compose strander + pileuper
+            let mut pileuper =
+                HTSPileupEngine::new(config.bamfiles, config.indexes.as_deref(), config.reference.as_deref(), counter)?;
+            if config.zero_coverage {
+                pileuper = pileuper.with_empty_windows();
+            }
+            if let Some(tpool) = tpool {
+                pileuper = pileuper.with_thread_pool(tpool);
+            }
+            if config.prefetch {
+                pileuper = pileuper.with_prefetch();
+            }
+            // Launch the processing
+            let runner = REATRunner::new(builder, strander, pileuper);
+            super::stream(workload, runner, hooks, contigs, finished, progress, events, deterministic, false, oncontig)
+        }
+        Some(design) => {
+            // Remove all stranding algorithm -> they are not required
+            strander.clear();
+            // Compose strander + pileuper
+            let deductor = DeduceStrandByDesign::new(design);
+            let mut pileuper = HTSPileupEngine::new(
+                config.bamfiles,
+                config.indexes.as_deref(),
+                config.reference.as_deref(),
+                StrandedNucCounter::new(counter, deductor),
+            )?;
+            if config.zero_coverage {
+                pileuper = pileuper.with_empty_windows();
+            }
+            if let Some(tpool) = tpool {
+                pileuper = pileuper.with_thread_pool(tpool);
+            }
+            if config.prefetch {
+                pileuper = pileuper.with_prefetch();
+            }
+            // Launch the processing
+            let runner = REATRunner::new(builder, strander, pileuper);
+            super::stream(workload, runner, hooks, contigs, finished, progress, events, deterministic, false, oncontig)
+        }
+    }
+}
+
+pub fn run_rois(config: ROIRunConfig) -> Result<Vec<ROIMismatchesVec>, Error> {
+    let mut result = Vec::new();
+    stream_rois(config, |_, x| {
+        result.extend(x);
+        Ok(())
+    })?;
+    Ok(result)
+}
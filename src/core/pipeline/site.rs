@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bio_types::genome::AbstractInterval;
+use rust_htslib::bam::Record;
+
+use crate::core::dna::Multimap;
+use crate::core::hooks::engine::REATHooksEngine;
+use crate::core::io::fasta::FastaReader;
+use crate::core::mismatches::prefilters;
+use crate::core::mismatches::prefilters::retain::RetainSitesFromList;
+use crate::core::mismatches::site::{SiteMismatchesBuilder, SiteMismatchesVec};
+use crate::core::refpred::RefEngine;
+use crate::core::rpileup::hts::{HTSPileupEngine, HTSThreadPool};
+use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, IntervalNucCounter, StrandedNucCounter};
+use crate::core::rpileup::stream::{HTSStream, HTSStreamEngine};
+use crate::core::runner::REATRunner;
+use crate::core::stranding::deduce::{DeduceStrandByDesign, StrandSpecificExperimentDesign};
+use crate::core::stranding::predict::REATStrandingEngine;
+use crate::core::workload::SiteWorkload;
+use crate::error::Error;
+
+use super::{EventSink, Progress, ReadsFilter, Summary};
+
+pub struct SiteRunConfig {
+    pub bamfiles: Vec<PathBuf>,
+    // Explicit index of each BAM file, None => indexes are looked up next to the files
+    pub indexes: Option<Vec<PathBuf>>,
+    // Reference assembly to decode CRAM files, not required for BAM files
+    pub reference: Option<PathBuf>,
+    pub workload: Vec<SiteWorkload>,
+    pub maxwsize: usize,
+    pub refnucpred: Box<dyn RefEngine>,
+    pub readfilter: ReadsFilter,
+    // Skip positions with unknown(N) assembly nucleotides
+    pub skip_refn: bool,
+    pub trim5: u16,
+    pub trim3: u16,
+    // Count bases covered by both overlapping mates only once
+    pub overlaps_dedup: bool,
+    // Collapse reads with the same start, strand and UMI (given tag)
+    pub umi: Option<[u8; 2]>,
+    // Threads in the shared htslib pool for BGZF decompression (0 => no pool)
+    pub hts_threads: u32,
+    // Decode reads in a helper thread while the previous chunk is being counted
+    pub prefetch: bool,
+    // Read coordinate-sorted inputs (e.g. stdin) sequentially instead of fetching reads from the indexed files.
+    // Workload is processed bin by bin in the header order, which becomes the contigs order
+    pub stream: Option<HTSStream>,
+    // Track forward / reverse alignments supporting each position
+    pub strand_depth: bool,
+    // Accumulate base qualities of each sequenced nucleotide (meanq_alt / minq_alt), doubles the counting memory
+    pub base_qualities: bool,
+    // Track fragments supporting mismatches (alt_fragments), bases are compared with the given assembly
+    pub alt_fragments: Option<Box<dyn FastaReader>>,
+    // Weighting of multi-mapped reads. With fractional weights, counts are in 1 / Multimap::SCALE units =>
+    // count thresholds of the reference predictor, prefilter & stranding must be scaled accordingly
+    pub multimap: Multimap,
+    // Max mismatches (relative to the given reference) a single read may contribute to a window, None => unlimited
+    pub max_read_contribution: Option<(u32, Box<dyn FastaReader>)>,
+    // Skip reads once their first position is covered by the given number of counted reads, None => unlimited
+    pub max_coverage: Option<u32>,
+    // None => unstranded library, strand is predicted by the stranding engine
+    pub design: Option<StrandSpecificExperimentDesign>,
+    pub stranding: REATStrandingEngine<SiteMismatchesVec>,
+    pub hooks: REATHooksEngine<SiteMismatchesVec>,
+    pub prefilter: Option<prefilters::ByMismatches>,
+    // Per-file mismatches support, counts for each input file are tracked within the window
+    pub replicates: Option<prefilters::ByReplicates>,
+    // Report counts for each input file separately, prefilter is passed if any of the files passes it
+    pub per_source: bool,
+    // Accumulate the background substitution profile of non-variable positions into each batch (e.g. for stats)
+    pub substitutions: bool,
+    // Annotate sites with the distance to the nearest splice junction observed in the spliced reads (splice_dist)
+    pub splice_dist: bool,
+    pub retain: Option<RetainSitesFromList>,
+    // Process bins without any reads as well, e.g. to report retained sites with zero counts
+    pub empty_bins: bool,
+    // Report contigs in the given order (e.g. from the FASTA index), by name otherwise
+    pub contigs: Option<Vec<String>>,
+    // Contigs finished by a previous (interrupted) run, their workload is skipped
+    pub finished: HashSet<String>,
+    // Debug builds: verify that results don't depend on the threads scheduling before the processing
+    pub deterministic: bool,
+    pub progress: Option<Box<dyn Progress>>,
+    // Library-level observer of the reported batches & progress
+    pub events: Option<Box<dyn EventSink<SiteMismatchesVec>>>,
+}
+
+impl SiteRunConfig {
+    pub fn new(
+        bamfiles: Vec<PathBuf>,
+        workload: Vec<SiteWorkload>,
+        maxwsize: usize,
+        refnucpred: Box<dyn RefEngine>,
+        readfilter: ReadsFilter,
+    ) -> Self {
+        Self {
+            bamfiles,
+            indexes: None,
+            reference: None,
+            workload,
+            maxwsize,
+            refnucpred,
+            readfilter,
+            skip_refn: false,
+            trim5: 0,
+            trim3: 0,
+            overlaps_dedup: true,
+            umi: None,
+            hts_threads: 0,
+            prefetch: false,
+            stream: None,
+            strand_depth: true,
+            base_qualities: false,
+            alt_fragments: None,
+            multimap: Multimap::Full,
+            max_read_contribution: None,
+            max_coverage: None,
+            design: None,
+            stranding: REATStrandingEngine::new(),
+            hooks: REATHooksEngine::new(),
+            prefilter: None,
+            replicates: None,
+            per_source: false,
+            substitutions: false,
+            splice_dist: false,
+            retain: None,
+            empty_bins: false,
+            contigs: None,
+            finished: HashSet::new(),
+            deterministic: false,
+            progress: None,
+            events: None,
+        }
+    }
+}
+
+// Mismatches builder & reads counter described by the config, their parts are taken from the config
+#[allow(clippy::type_complexity)]
+pub(super) fn prepare(
+    config: &mut SiteRunConfig,
+) -> (SiteMismatchesBuilder<RetainSitesFromList, prefilters::ByMismatches>, IntervalNucCounter<Record, ReadsFilter>) {
+    let refnucpred = dyn_clone::clone_box(&*config.refnucpred);
+    let mut builder = SiteMismatchesBuilder::new(config.maxwsize, refnucpred, config.retain.take(), config.prefilter);
+    if config.skip_refn {
+        builder = builder.with_refn_skipped();
+    }
+    if let Some(replicates) = config.replicates {
+        builder = builder.with_replicates(replicates);
+    }
+    if config.per_source {
+        builder = builder.with_persample();
+    }
+    if config.substitutions {
+        builder = builder.with_substitutions();
+    }
+
+    let mut counter = BaseNucCounter::new(config.maxwsize, config.readfilter.clone(), config.trim5, config.trim3);
+    if config.overlaps_dedup {
+        counter = counter.with_overlaps_dedup();
+    }
+    if let Some(tag) = config.umi {
+        counter = counter.with_umi_dedup(tag);
+    }
+    if config.strand_depth {
+        counter = counter.with_strand_depth();
+    }
+    if config.base_qualities {
+        counter = counter.with_base_qualities();
+    }
+    if let Some(assembly) = config.alt_fragments.take() {
+        counter = counter.with_alt_fragments(assembly);
+    }
+    if config.multimap != Multimap::Full {
+        counter = counter.with_multimap(config.multimap);
+    }
+    if let Some((max, reference)) = config.max_read_contribution.take() {
+        counter = counter.with_max_read_contribution(max, reference);
+    }
+    if let Some(max) = config.max_coverage {
+        counter = counter.with_max_coverage(max);
+    }
+    if config.per_source || config.replicates.is_some() {
+        counter = counter.with_sources(config.bamfiles.len());
+    }
+    if config.splice_dist {
+        counter = counter.with_junctions();
+    }
+    (builder, IntervalNucCounter::new(counter))
+}
+
+// Results are passed to the callback contig by contig, collected stats & counters are returned
+pub fn stream_sites(
+    mut config: SiteRunConfig,
+    oncontig: impl FnMut(&str, Vec<SiteMismatchesVec>) -> Result<(), Error>,
+) -> Result<Summary<SiteMismatchesVec>, Error> {
+    let (builder, counter) = prepare(&mut config);
+
+    // Single pool for all readers, it outlives them since each pileuper holds a reference
+    let tpool = (config.hts_threads > 0).then(|| Arc::new(HTSThreadPool::new(config.hts_threads)));
+
+    let (mut contigs, finished, progress) = (config.contigs, &config.finished, config.progress.as_deref());
+    let (events, deterministic) = (config.events.as_deref(), config.deterministic);
+    let (mut workload, hooks) = (config.workload, config.hooks);
+    let stream = config.stream.map(|stream| {
+        // Reads are consumed in the header order => bins must follow it
+        let order = stream.order();
+        let rank: HashMap<&str, usize> = order.iter().enumerate().map(|(ind, x)| (x.as_str(), ind)).collect();
+        workload.sort_by_key(|x| (rank.get(x.contig()).copied().unwrap_or(usize::MAX), x.range().start));
+        contigs = Some(order);
+        match &tpool {
+            Some(tpool) => stream.with_thread_pool(tpool.clone()),
+            None => stream,
+        }
+    });
+    let (contigs, sequential) = (contigs.as_deref(), stream.is_some());
+
+    // Compose strander + pileuper (streamed or random access) & launch the processing
+    macro_rules! launch {
+        ($strander:expr, $counter:expr) => {
+            match stream {
+                Some(stream) => {
+                    let mut pileuper = HTSStreamEngine::new(stream, $counter);
+                    if config.empty_bins {
+                        pileuper = pileuper.with_empty_windows();
+                    }
+                    let runner = REATRunner::new(builder, $strander, pileuper);
+                    super::stream(
+                        workload,
+                        runner,
+                        hooks,
+                        contigs,
+                        finished,
+                        progress,
+                        events,
+                        deterministic,
+                        sequential,
+                        oncontig,
+                    )
+                }
+                None => {
+                    let mut pileuper = HTSPileupEngine::new(
+                        config.bamfiles,
+                        config.indexes.as_deref(),
+                        config.reference.as_deref(),
+                        $counter,
+                    )?;
+                    if config.empty_bins {
+                        pileuper = pileuper.with_empty_windows();
+                    }
+                    if let Some(tpool) = tpool {
+                        pileuper = pileuper.with_thread_pool(tpool);
+                    }
+                    if config.prefetch {
+                        pileuper = pileuper.with_prefetch();
+                    }
+                    let runner = REATRunner::new(builder, $strander, pileuper);
+                    super::stream(
+                        workload,
+                        runner,
+                        hooks,
+                        contigs,
+                        finished,
+                        progress,
+                        events,
+                        deterministic,
+                        sequential,
+                        oncontig,
+                    )
+                }
+            }
+        };
+    }
+
+    let mut strander = config.stranding;
+    match config.design {
+        None => launch!(strander, counter),
+        Some(design) => {
+            // Remove all stranding algorithm -> they are not required
+            strander.clear();
+            let deductor = DeduceStrandByDesign::new(design);
+            launch!(strander, StrandedNucCounter::new(counter, deductor))
+        }
+    }
+}
+
+pub fn run_sites(config: SiteRunConfig) -> Result<Vec<SiteMismatchesVec>, Error> {
+    let mut result = Vec::new();
+    stream_sites(config, |_, x| {
+        result.extend(x);
+        Ok(())
+    })?;
+    Ok(result)
+}
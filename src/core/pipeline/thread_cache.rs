@@ -24,8 +24,4 @@ where
     pub fn get(&self) -> &RefCell<Type> {
         self.thrlocal.get_or(|| self.mutex.lock().unwrap()())
     }
-
-    pub fn dissolve(self) -> <ThreadLocal<RefCell<Type>> as IntoIterator>::IntoIter {
-        self.thrlocal.into_iter()
-    }
 }
@@ -2,7 +2,7 @@ use bio_types::genome;
 use bio_types::strand::ReqStrand;
 #[cfg(test)]
 use mockall::{mock, predicate::*};
-use rust_htslib::bam::record::CigarStringView;
+use rust_htslib::bam::record::{Aux, CigarStringView};
 use rust_htslib::bam::Record;
 
 #[allow(clippy::len_without_is_empty)]
@@ -21,8 +21,12 @@ pub trait SequencedRead {
     }
 
     fn is_first(&self) -> bool;
+    fn is_paired(&self) -> bool;
 
     fn len(&self) -> usize;
+
+    // Value of the given aux tag, if it's present and can be represented as a string
+    fn tag(&self, tag: &[u8]) -> Option<Vec<u8>>;
 }
 
 pub trait AlignedRead: SequencedRead {
@@ -55,7 +59,9 @@ mock! {
         fn base_qual(&self, i: usize) -> u8;
 
         fn is_first(&self) -> bool;
+        fn is_paired(&self) -> bool;
         fn len(&self) -> usize;
+        fn tag(&self, tag: &[u8]) -> Option<Vec<u8>>;
     }
 }
 
@@ -89,10 +95,29 @@ impl SequencedRead for Record {
         self.is_first_in_template()
     }
 
+    #[inline]
+    fn is_paired(&self) -> bool {
+        self.is_paired()
+    }
+
     #[inline]
     fn len(&self) -> usize {
         self.seq_len()
     }
+
+    fn tag(&self, tag: &[u8]) -> Option<Vec<u8>> {
+        match self.aux(tag).ok()? {
+            Aux::String(x) | Aux::HexByteArray(x) => Some(x.as_bytes().to_vec()),
+            Aux::Char(x) => Some(vec![x]),
+            Aux::I8(x) => Some(x.to_string().into_bytes()),
+            Aux::U8(x) => Some(x.to_string().into_bytes()),
+            Aux::I16(x) => Some(x.to_string().into_bytes()),
+            Aux::U16(x) => Some(x.to_string().into_bytes()),
+            Aux::I32(x) => Some(x.to_string().into_bytes()),
+            Aux::U32(x) => Some(x.to_string().into_bytes()),
+            _ => None,
+        }
+    }
 }
 
 impl AlignedRead for Record {
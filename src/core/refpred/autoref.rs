@@ -6,8 +6,9 @@ use itertools::zip;
 use crate::core::dna::NucCounts;
 use crate::core::dna::{Nucleotide, ReqNucleotide};
 use crate::core::io::fasta::FastaReader;
-use crate::core::refpred::PredNucleotide::Homozygous;
+use crate::core::refpred::PredNucleotide::{Heterozygous, Homozygous};
 use crate::core::refpred::{PredNucleotide, RefEngineResult};
+use crate::error::Error;
 
 use super::RefEngine;
 
@@ -16,13 +17,51 @@ pub struct AutoRef {
     mincoverage: u32,
     minfreq: f32,
     skip_hyperediting: bool,
+    // Min frequency & count of the second most common nucleotide to call a heterozygous site
+    heterozygous: Option<(f32, u32)>,
     cache: Vec<PredNucleotide>,
     reader: Box<dyn FastaReader>,
 }
 
 impl AutoRef {
     pub fn new(mincoverage: u32, minfreq: f32, skip_hyperediting: bool, reader: Box<dyn FastaReader>) -> Self {
-        Self { mincoverage, minfreq, skip_hyperediting, cache: Vec::new(), reader }
+        Self { mincoverage, minfreq, skip_hyperediting, heterozygous: None, cache: Vec::new(), reader }
+    }
+
+    pub fn with_heterozygous(mut self, minfreq: f32, mincount: u32) -> Self {
+        self.heterozygous = Some((minfreq, mincount));
+        self
+    }
+
+    #[inline]
+    fn is_hyperediting(&self, assembly: Nucleotide, nuc: ReqNucleotide) -> bool {
+        self.skip_hyperediting
+            && ((assembly == Nucleotide::A && nuc == ReqNucleotide::G)
+                || (assembly == Nucleotide::T && nuc == ReqNucleotide::C))
+    }
+
+    #[inline]
+    pub fn predict(&self, assembly: Nucleotide, sequenced: &NucCounts) -> PredNucleotide {
+        let coverage = sequenced.coverage();
+        if let Some((minfreq, mincount)) = self.heterozygous {
+            if coverage >= self.mincoverage && coverage > 0 {
+                let mut counts = [
+                    (ReqNucleotide::A, sequenced.A),
+                    (ReqNucleotide::C, sequenced.C),
+                    (ReqNucleotide::G, sequenced.G),
+                    (ReqNucleotide::T, sequenced.T),
+                ];
+                counts.sort_by(|x, y| y.1.cmp(&x.1));
+                let ((major, _), (minor, cnt)) = (counts[0], counts[1]);
+
+                // Potential hyper-editing sites must not be masked as heterozygous
+                let hyperediting = self.is_hyperediting(assembly, major) || self.is_hyperediting(assembly, minor);
+                if cnt > 0 && cnt >= mincount && cnt as f32 / coverage as f32 >= minfreq && !hyperediting {
+                    return Heterozygous((major.into(), minor.into()));
+                }
+            }
+        }
+        Homozygous(self.infer(assembly, sequenced))
     }
 
     #[inline]
@@ -35,11 +74,7 @@ impl AutoRef {
             // and the most abundant nucleotide is frequent enough
             if *counts as f32 / coverage as f32 >= self.minfreq {
                 // check for a potential hyper-editing site
-                let skip_hyperediting = self.skip_hyperediting
-                    && ((assembly == Nucleotide::A && nuc == ReqNucleotide::G)
-                        || (assembly == Nucleotide::T && nuc == ReqNucleotide::C));
-
-                return if skip_hyperediting { assembly } else { nuc.into() };
+                return if self.is_hyperediting(assembly, nuc) { assembly } else { nuc.into() };
             }
         }
         assembly
@@ -47,18 +82,18 @@ impl AutoRef {
 }
 
 impl RefEngine for AutoRef {
-    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]) {
+    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]) -> Result<(), Error> {
         self.cache.clear();
         self.cache.reserve(sequenced.len());
 
-        self.reader.fetch(contig, range);
+        self.reader.fetch(contig, range)?;
         let reference = self.reader.result();
         debug_assert!(reference.len() == sequenced.len());
 
         for (r, s) in zip(sequenced, reference) {
-            let inferred = self.infer(*s, r);
-            self.cache.push(Homozygous(inferred));
+            self.cache.push(self.predict(*s, r));
         }
+        Ok(())
     }
 
     fn results(&self) -> RefEngineResult<'_> {
@@ -112,7 +147,7 @@ mod tests {
         let mut reader = MockFastaReader::new();
         let mut seq = Sequence::new();
         for ind in 0..intervals.len() {
-            reader.expect_fetch().once().return_const(()).in_sequence(&mut seq);
+            reader.expect_fetch().once().returning(|_, _| Ok(())).in_sequence(&mut seq);
             reader.expect_result().once().return_const(sequenced[ind].1.clone()).in_sequence(&mut seq);
             reader.expect_result().once().return_const(sequenced[ind].1.clone()).in_sequence(&mut seq);
         }
@@ -120,7 +155,7 @@ mod tests {
         let mut dummy = AutoRef::new(10, 1f32, false, Box::new(reader));
 
         for ind in 0..sequenced.len() {
-            dummy.run(intervals[ind].contig(), intervals[ind].range(), &sequenced[ind].0);
+            dummy.run(intervals[ind].contig(), intervals[ind].range(), &sequenced[ind].0).unwrap();
             let result = dummy.results();
             assert_eq!(result.reference, sequenced[ind].1);
 
@@ -151,4 +186,40 @@ mod tests {
         let t2c = NucCounts { A: 0, C: 3, G: 0, T: 1 };
         run([Nucleotide::T, Nucleotide::C], [true, false], &t2c, Nucleotide::T);
     }
+
+    #[test]
+    fn heterozygous() {
+        let sequenced = NucCounts { A: 6, C: 0, G: 1, T: 3 };
+        let het = |x: PredNucleotide| match x {
+            Heterozygous(x) => Some(x),
+            Homozygous(_) => None,
+        };
+
+        // Disabled by default
+        let dummy = AutoRef::new(0, 0.5, false, Box::new(MockFastaReader::new()));
+        assert_eq!(het(dummy.predict(Nucleotide::A, &sequenced)), None);
+
+        for (mincoverage, minfreq, mincount, expected) in [
+            (0, 0.3, 3, Some((Nucleotide::A, Nucleotide::T))),
+            (0, 0.0, 0, Some((Nucleotide::A, Nucleotide::T))),
+            (11, 0.3, 3, None),
+            (0, 0.31, 3, None),
+            (0, 0.3, 4, None),
+        ] {
+            let dummy = AutoRef::new(mincoverage, 0.5, false, Box::new(MockFastaReader::new()))
+                .with_heterozygous(minfreq, mincount);
+            assert_eq!(het(dummy.predict(Nucleotide::A, &sequenced)), expected);
+        }
+
+        // Homozygous positions are not affected
+        let dummy = AutoRef::new(0, 0.5, false, Box::new(MockFastaReader::new())).with_heterozygous(0.0, 0);
+        assert_eq!(het(dummy.predict(Nucleotide::A, &NucCounts::G(10))), None);
+
+        // Potential hyper-editing sites are never heterozygous
+        let a2g = NucCounts { A: 5, C: 0, G: 5, T: 0 };
+        for (skip, expected) in [(true, None), (false, Some((Nucleotide::A, Nucleotide::G)))] {
+            let dummy = AutoRef::new(0, 0.5, skip, Box::new(MockFastaReader::new())).with_heterozygous(0.1, 1);
+            assert_eq!(het(dummy.predict(Nucleotide::A, &a2g)), expected);
+        }
+    }
 }
@@ -4,12 +4,15 @@ use bio_types::genome::Position;
 use dyn_clone::DynClone;
 
 pub use autoref::AutoRef;
+pub use unknown::ResolveUnknownRef;
 pub use vcf::VCFCorrectedReference;
 
 use crate::core::dna::NucCounts;
 use crate::core::dna::Nucleotide;
+use crate::error::Error;
 
 mod autoref;
+mod unknown;
 mod vcf;
 
 #[derive(Clone, Copy, Debug)]
@@ -23,29 +26,38 @@ impl PredNucleotide {
         match self {
             PredNucleotide::Homozygous(nuc) => nuc.symbol(),
             PredNucleotide::Heterozygous((n1, n2)) => match (n1, n2) {
-                (Nucleotide::A, Nucleotide::A) => "A/A",
-                (Nucleotide::A, Nucleotide::C) => "A/C",
-                (Nucleotide::A, Nucleotide::G) => "A/G",
-                (Nucleotide::A, Nucleotide::T) => "A/T",
-
-                (Nucleotide::C, Nucleotide::A) => "A/C",
-                (Nucleotide::C, Nucleotide::C) => "C/C",
-                (Nucleotide::C, Nucleotide::G) => "C/G",
-                (Nucleotide::C, Nucleotide::T) => "C/T",
-
-                (Nucleotide::G, Nucleotide::A) => "A/G",
-                (Nucleotide::G, Nucleotide::C) => "C/G",
-                (Nucleotide::G, Nucleotide::G) => "G/G",
-                (Nucleotide::G, Nucleotide::T) => "G/T",
-
-                (Nucleotide::T, Nucleotide::A) => "A/T",
-                (Nucleotide::T, Nucleotide::C) => "C/T",
-                (Nucleotide::T, Nucleotide::G) => "G/T",
-                (Nucleotide::T, Nucleotide::T) => "T/T",
+                (Nucleotide::A, Nucleotide::A) => "AA",
+                (Nucleotide::A, Nucleotide::C) => "AC",
+                (Nucleotide::A, Nucleotide::G) => "AG",
+                (Nucleotide::A, Nucleotide::T) => "AT",
+
+                (Nucleotide::C, Nucleotide::A) => "AC",
+                (Nucleotide::C, Nucleotide::C) => "CC",
+                (Nucleotide::C, Nucleotide::G) => "CG",
+                (Nucleotide::C, Nucleotide::T) => "CT",
+
+                (Nucleotide::G, Nucleotide::A) => "AG",
+                (Nucleotide::G, Nucleotide::C) => "CG",
+                (Nucleotide::G, Nucleotide::G) => "GG",
+                (Nucleotide::G, Nucleotide::T) => "GT",
+
+                (Nucleotide::T, Nucleotide::A) => "AT",
+                (Nucleotide::T, Nucleotide::C) => "CT",
+                (Nucleotide::T, Nucleotide::G) => "GT",
+                (Nucleotide::T, Nucleotide::T) => "TT",
                 _ => "N",
             },
         }
     }
+
+    pub fn complementary(&self) -> Self {
+        match self {
+            PredNucleotide::Homozygous(nuc) => PredNucleotide::Homozygous(nuc.complementary()),
+            PredNucleotide::Heterozygous((n1, n2)) => {
+                PredNucleotide::Heterozygous((n1.complementary(), n2.complementary()))
+            }
+        }
+    }
 }
 
 impl Default for PredNucleotide {
@@ -54,13 +66,63 @@ impl Default for PredNucleotide {
     }
 }
 
+// How to handle unknown(N) nucleotides in the reference assembly
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RefNPolicy {
+    // Keep the predicted reference as is, i.e. all sequenced nucleotides are mismatches for N
+    Mismatch,
+    // Never report or count positions with N in the assembly
+    Skip,
+    // Infer N from the sequenced nucleotides (see ResolveUnknownRef)
+    AutoRef,
+}
+
+impl Default for RefNPolicy {
+    fn default() -> Self {
+        RefNPolicy::Mismatch
+    }
+}
+
+// Where the predicted reference nucleotide came from
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RefSource {
+    FromAssembly,
+    Corrected,
+    Heterozygous,
+}
+
+impl RefSource {
+    pub fn new(assembly: Nucleotide, predicted: PredNucleotide) -> Self {
+        match predicted {
+            PredNucleotide::Homozygous(nuc) if nuc == assembly => RefSource::FromAssembly,
+            PredNucleotide::Homozygous(_) => RefSource::Corrected,
+            PredNucleotide::Heterozygous(_) => RefSource::Heterozygous,
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            RefSource::FromAssembly => "assembly",
+            RefSource::Corrected => "corrected",
+            RefSource::Heterozygous => "heterozygous",
+        }
+    }
+}
+
+impl Default for RefSource {
+    fn default() -> Self {
+        RefSource::FromAssembly
+    }
+}
+
 pub struct RefEngineResult<'a> {
     pub predicted: &'a [PredNucleotide],
     pub reference: &'a [Nucleotide],
 }
 
 pub trait RefEngine: Send + DynClone {
-    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]);
+    // Errors are reported if the reference assembly can't be fetched for the given range
+    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]) -> Result<(), Error>;
     fn results(&self) -> RefEngineResult<'_>;
 }
 dyn_clone::clone_trait_object!(RefEngine);
@@ -0,0 +1,102 @@
+use std::iter::zip;
+use std::ops::Range;
+
+use bio_types::genome::Position;
+
+use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::refpred::PredNucleotide::Homozygous;
+use crate::core::refpred::{PredNucleotide, RefEngineResult};
+use crate::error::Error;
+
+use super::RefEngine;
+
+// Infer unknown(N) assembly nucleotides left by the inner engine from the sequenced ones
+#[derive(Clone)]
+pub struct ResolveUnknownRef {
+    inner: Box<dyn RefEngine>,
+    mincoverage: u32,
+    minfreq: f32,
+    cache: Vec<PredNucleotide>,
+}
+
+impl ResolveUnknownRef {
+    pub fn new(inner: Box<dyn RefEngine>, mincoverage: u32, minfreq: f32) -> Self {
+        Self { inner, mincoverage, minfreq, cache: Vec::new() }
+    }
+
+    #[inline]
+    fn infer(&self, sequenced: &NucCounts) -> Option<Nucleotide> {
+        let coverage = sequenced.coverage();
+        if coverage == 0 || coverage < self.mincoverage {
+            return None;
+        }
+        let (nuc, counts) = sequenced.mostfreq();
+        if *counts as f32 / coverage as f32 >= self.minfreq {
+            Some(nuc.into())
+        } else {
+            None
+        }
+    }
+}
+
+impl RefEngine for ResolveUnknownRef {
+    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]) -> Result<(), Error> {
+        self.inner.run(contig, range, sequenced)?;
+
+        let result = self.inner.results();
+        debug_assert!(result.predicted.len() == sequenced.len());
+        let mut cache = std::mem::take(&mut self.cache);
+        cache.clear();
+        for (predicted, (assembly, seq)) in zip(result.predicted, zip(result.reference, sequenced)) {
+            let predicted = match predicted {
+                Homozygous(Nucleotide::Unknown) if *assembly == Nucleotide::Unknown => {
+                    self.infer(seq).map_or(*predicted, Homozygous)
+                }
+                _ => *predicted,
+            };
+            cache.push(predicted);
+        }
+        self.cache = cache;
+        Ok(())
+    }
+
+    fn results(&self) -> RefEngineResult<'_> {
+        RefEngineResult { predicted: &self.cache, reference: self.inner.results().reference }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::io::fasta::MockFastaReader;
+    use crate::core::refpred::AutoRef;
+
+    use super::*;
+
+    #[test]
+    fn resolve_unknown() {
+        let assembly = vec![Nucleotide::Unknown, Nucleotide::Unknown, Nucleotide::Unknown, Nucleotide::A];
+        let mut reader = MockFastaReader::new();
+        reader.expect_fetch().returning(|_, _| Ok(()));
+        reader.expect_result().return_const(assembly.clone());
+
+        // Inner engine never corrects the assembly
+        let inner = AutoRef::new(u32::MAX, 1f32, false, Box::new(reader));
+        let mut dummy = ResolveUnknownRef::new(Box::new(inner), 10, 0.9);
+
+        let sequenced = vec![NucCounts::G(12), NucCounts::G(9), NucCounts { A: 1, C: 0, G: 9, T: 0 }, NucCounts::G(12)];
+        dummy.run("chr1", 0..4, &sequenced).unwrap();
+        let result = dummy.results();
+        assert_eq!(result.reference, assembly);
+
+        let predicted: Vec<_> = result
+            .predicted
+            .iter()
+            .map(|x| match x {
+                Homozygous(x) => *x,
+                _ => panic!("Only homozygous positions are expected"),
+            })
+            .collect();
+        // Low coverage, low frequency & known assembly nucleotides are not inferred
+        assert_eq!(predicted, vec![Nucleotide::G, Nucleotide::Unknown, Nucleotide::G, Nucleotide::A]);
+    }
+}
@@ -12,6 +12,7 @@ use crate::core::io::fasta::FastaReader;
 use crate::core::io::vcf;
 use crate::core::refpred::PredNucleotide::{Heterozygous, Homozygous};
 use crate::core::refpred::{PredNucleotide, RefEngineResult};
+use crate::error::Error;
 
 use super::RefEngine;
 
@@ -50,12 +51,12 @@ impl VCFCorrectedReference {
 }
 
 impl RefEngine for VCFCorrectedReference {
-    fn run(&mut self, contig: &str, range: Range<Position>, _: &[NucCounts]) {
+    fn run(&mut self, contig: &str, range: Range<Position>, _: &[NucCounts]) -> Result<(), Error> {
         let length = (range.end - range.start) as usize;
         self.cache.clear();
         self.cache.reserve(length);
 
-        self.reader.fetch(contig, range.clone());
+        self.reader.fetch(contig, range.clone())?;
         let reference = self.reader.result();
 
         for refn in reference.iter() {
@@ -77,6 +78,7 @@ impl RefEngine for VCFCorrectedReference {
             let (f, s) = hit.data();
             self.cache[(loc - range.start) as usize] = Heterozygous(((*f).into(), (*s).into()))
         }
+        Ok(())
     }
 
     fn results(&self) -> RefEngineResult<'_> {
@@ -1,83 +1,219 @@
-use std::path::PathBuf;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::{panic, thread};
 
-use bio_types::genome::AbstractInterval;
-use itertools::Itertools;
+use bio_types::genome::{AbstractInterval, Position};
 use rust_htslib::bam::{IndexedReader, Read, Record};
+use rust_htslib::htslib;
 
+use crate::core::io;
 use crate::core::rpileup::{ReadsCollider, ReadsCollidingEngine};
+use crate::error::Error;
+
+// Number of records decoded ahead of the counting when prefetching is enabled
+const PREFETCH_CHUNK: usize = 4096;
 
 pub struct HTSPileupEngine<Collider> {
     collider: Collider,
     htsreaders: Vec<IndexedReader>,
     htsfiles: Vec<PathBuf>,
+    // Explicit index of each file, None => indexes are looked up next to the files
+    indexes: Option<Vec<PathBuf>>,
+    // Reference assembly to decode CRAM files
+    reference: Option<PathBuf>,
+    // Run the collider even if there are no reads in the window
+    empty: bool,
+    // Decode reads in a helper thread while the collider is running
+    prefetch: bool,
     success: bool,
+    // Must be dropped after the readers
+    tpool: Option<Arc<HTSThreadPool>>,
+}
+
+fn fetch_error(file: &Path, contig: &str, range: &Range<Position>, err: impl Display) -> Error {
+    Error::hts(file, format!("failed to fetch reads for {}:{}-{}: {}", contig, range.start, range.end, err))
+}
+
+fn read_error(file: &Path, contig: &str, range: &Range<Position>, err: impl Display) -> Error {
+    let message =
+        format!("failed to read {}:{}-{} (truncated or corrupted file?): {}", contig, range.start, range.end, err);
+    Error::hts(file, message)
 }
 
 impl<Collider: for<'a> ReadsCollider<'a, Record>> HTSPileupEngine<Collider> {
-    pub fn new(htsfiles: Vec<PathBuf>, collider: Collider) -> Self {
-        let htsreaders: Vec<IndexedReader> = htsfiles
+    // Indexes => explicit index of each file, otherwise they are looked up next to the files.
+    // Reference => assembly to decode CRAM files, BAM & CRAM files can be mixed
+    pub fn new(
+        htsfiles: Vec<PathBuf>,
+        indexes: Option<&[PathBuf]>,
+        reference: Option<&Path>,
+        collider: Collider,
+    ) -> Result<Self, Error> {
+        if let Some(indexes) = indexes {
+            assert_eq!(htsfiles.len(), indexes.len(), "Each input file must have exactly one index");
+        }
+        let htsreaders = htsfiles
             .iter()
-            .map(|hts| {
-                IndexedReader::from_path(&hts).unwrap_or_else(|_| {
-                    panic!(
-                        "Failed to open file {}\n\
-                        Possible reasons: BAM file was not indexed (samtools index); you don't have read permissions",
-                        hts.display()
-                    )
-                })
-            })
-            .collect();
-
-        Self { collider, htsreaders, htsfiles, success: false }
+            .enumerate()
+            .map(|(ind, x)| io::hts::open(x, indexes.map(|indexes| indexes[ind].as_path()), reference))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (indexes, reference) = (indexes.map(|x| x.to_vec()), reference.map(|x| x.to_path_buf()));
+        Ok(Self {
+            collider,
+            htsreaders,
+            htsfiles,
+            indexes,
+            reference,
+            empty: false,
+            prefetch: false,
+            success: false,
+            tpool: None,
+        })
+    }
+
+    // Decompress BGZF blocks of all readers in the shared htslib thread pool
+    pub fn with_thread_pool(mut self, tpool: Arc<HTSThreadPool>) -> Self {
+        for reader in &self.htsreaders {
+            tpool.attach(reader);
+        }
+        self.tpool = Some(tpool);
+        self
+    }
+
+    pub fn with_empty_windows(mut self) -> Self {
+        self.empty = true;
+        self
+    }
+
+    pub fn with_prefetch(mut self) -> Self {
+        self.prefetch = true;
+        self
+    }
+
+    // Same as the regular run, but reads are decoded by a helper thread in chunks.
+    // At most one chunk is queued ahead of the collider => memory usage doesn't depend on the window size.
+    // Readers are used only by the helper thread, consumed chunks are sent back to reuse allocated records.
+    fn prefetched(&mut self, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) -> Result<bool, Error> {
+        let (contig, range) = (cwork.contig().to_owned(), cwork.range());
+        let (collider, readers, files, empty) = (&mut self.collider, &mut self.htsreaders, &self.htsfiles, self.empty);
+
+        let (sender, receiver) = mpsc::sync_channel::<(usize, Vec<Record>)>(1);
+        let (recycle, recycled) = mpsc::channel::<Vec<Record>>();
+        thread::scope(|s| {
+            let helper = s.spawn(move || -> Result<(), Error> {
+                for (ind, (reader, file)) in readers.iter_mut().zip(files).enumerate() {
+                    // No such contig in the BAM file
+                    if !reader.header().target_names().contains(&contig.as_bytes()) {
+                        continue;
+                    }
+                    reader
+                        .fetch((contig.as_str(), range.start, range.end))
+                        .map_err(|x| fetch_error(file, &contig, &range, x))?;
+
+                    loop {
+                        let mut chunk = recycled.try_recv().unwrap_or_default();
+                        let mut filled = 0;
+                        while filled < PREFETCH_CHUNK {
+                            if filled == chunk.len() {
+                                chunk.push(Record::new());
+                            }
+                            match reader.read(&mut chunk[filled]) {
+                                Some(Ok(())) => filled += 1,
+                                Some(Err(x)) => return Err(read_error(file, &contig, &range, x)),
+                                None => break,
+                            }
+                        }
+                        chunk.truncate(filled);
+
+                        let exhausted = filled < PREFETCH_CHUNK;
+                        // The receiver is gone only if the collider failed or panicked
+                        if (filled > 0 && sender.send((ind, chunk)).is_err()) || exhausted {
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            });
+
+            let mut chunks = receiver.into_iter().peekable();
+            // Nothing to do otherwise
+            let available = chunks.peek().is_some() || empty;
+            if available {
+                // Something to do, trigger the reset -> collide -> finalize
+                collider.reset(cwork)?;
+                let mut source = None;
+                for (ind, chunk) in chunks {
+                    if source != Some(ind) {
+                        collider.set_source(ind);
+                        source = Some(ind);
+                    }
+                    for record in &chunk {
+                        collider.collide(record);
+                    }
+                    // The helper might be already finished
+                    let _ = recycle.send(chunk);
+                }
+                collider.finalize();
+            }
+            // Counted reads are discarded if the helper failed midway
+            helper.join().unwrap_or_else(|x| panic::resume_unwind(x))?;
+            Ok(available)
+        })
     }
 }
 
 impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, Collider> for HTSPileupEngine<Collider> {
-    fn run(&mut self, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) {
-        let toread = self
-            .htsreaders
-            .iter_mut()
-            .filter_map(|reader| {
-                // No such contig in the BAM file
-                if !reader.header().target_names().contains(&cwork.contig().as_bytes()) {
-                    return None;
-                };
-
-                reader.fetch((cwork.contig(), cwork.range().start, cwork.range().end)).unwrap_or_else(|_| {
-                    panic!(
-                        "Failed to fetch reads for {}:{}-{} (HTS file corrupted?)",
-                        cwork.contig(),
-                        cwork.range().start,
-                        cwork.range().end
-                    )
-                });
-
-                let mut record = Record::new();
-                let isavailable = reader.read(&mut record);
-                match isavailable {
-                    Some(Ok(())) => Some((reader, record)),
-                    _ => None,
-                }
-            })
-            .collect_vec();
+    fn run(&mut self, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) -> Result<(), Error> {
+        self.success = false;
+        if self.prefetch {
+            self.success = self.prefetched(cwork)?;
+            return Ok(());
+        }
+
+        let (contig, range) = (cwork.contig().to_owned(), cwork.range());
+        let mut toread = Vec::with_capacity(self.htsreaders.len());
+        for (ind, (reader, file)) in self.htsreaders.iter_mut().zip(&self.htsfiles).enumerate() {
+            // No such contig in the BAM file
+            if !reader.header().target_names().contains(&contig.as_bytes()) {
+                continue;
+            };
+
+            reader
+                .fetch((contig.as_str(), range.start, range.end))
+                .map_err(|x| fetch_error(file, &contig, &range, x))?;
+
+            let mut record = Record::new();
+            match reader.read(&mut record) {
+                Some(Ok(())) => toread.push((ind, reader, file, record)),
+                Some(Err(x)) => return Err(read_error(file, &contig, &range, x)),
+                None => {}
+            }
+        }
 
         // Nothing to do
-        if toread.is_empty() {
-            self.success = false;
-            return;
+        if toread.is_empty() && !self.empty {
+            return Ok(());
         }
 
         // Something to do, trigger the reset -> collide -> finalize
-        self.collider.reset(cwork);
+        self.collider.reset(cwork)?;
 
-        for (reader, mut record) in toread.into_iter() {
+        for (ind, reader, file, mut record) in toread.into_iter() {
+            self.collider.set_source(ind);
             self.collider.collide(&record);
-            while let Some(Ok(())) = reader.read(&mut record) {
-                self.collider.collide(&record);
+            loop {
+                match reader.read(&mut record) {
+                    Some(Ok(())) => self.collider.collide(&record),
+                    Some(Err(x)) => return Err(read_error(file, &contig, &range, x)),
+                    None => break,
+                }
             }
         }
         self.collider.finalize();
         self.success = true;
+        Ok(())
     }
 
     fn result(&self) -> Option<<Collider as ReadsCollider<'_, Record>>::ColliderResult> {
@@ -89,7 +225,53 @@ impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, C
 }
 
 impl<Collider: for<'a> ReadsCollider<'a, Record> + Clone> Clone for HTSPileupEngine<Collider> {
+    // Inputs were already opened once => failures here are not expected
     fn clone(&self) -> Self {
-        Self::new(self.htsfiles.clone(), self.collider.clone())
+        let clone = Self {
+            empty: self.empty,
+            prefetch: self.prefetch,
+            ..Self::new(
+                self.htsfiles.clone(),
+                self.indexes.as_deref(),
+                self.reference.as_deref(),
+                self.collider.clone(),
+            )
+            .expect("Failed to reopen input files")
+        };
+        match &self.tpool {
+            Some(tpool) => clone.with_thread_pool(tpool.clone()),
+            None => clone,
+        }
+    }
+}
+
+// htslib thread pool that can be shared by readers living in different threads
+pub struct HTSThreadPool {
+    pool: *mut htslib::hts_tpool,
+}
+
+// hts_tpool is internally synchronized and designed to be shared between several files
+unsafe impl Send for HTSThreadPool {}
+unsafe impl Sync for HTSThreadPool {}
+
+impl HTSThreadPool {
+    pub fn new(threads: u32) -> Self {
+        debug_assert!(threads > 0);
+        let pool = unsafe { htslib::hts_tpool_init(threads as i32) };
+        assert!(!pool.is_null(), "Failed to create htslib thread pool with {} threads", threads);
+        Self { pool }
+    }
+
+    pub(super) fn attach(&self, reader: &impl Read) {
+        // htslib copies the pool pointer, the struct itself is not retained
+        let mut tpool = htslib::htsThreadPool { pool: self.pool, qsize: 0 };
+        let code = unsafe { htslib::hts_set_thread_pool(reader.htsfile(), &mut tpool) };
+        assert_eq!(code, 0, "Failed to attach htslib thread pool to the reader");
+    }
+}
+
+impl Drop for HTSThreadPool {
+    fn drop(&mut self) {
+        unsafe { htslib::hts_tpool_destroy(self.pool) }
     }
 }
@@ -1,9 +1,14 @@
+use std::cmp::max;
+
 use bio_types::genome::AbstractInterval;
 
 use crate::core::read::AlignedRead;
+use crate::error::Error;
 
 pub mod hts;
 pub mod ncounter;
+pub mod paired;
+pub mod stream;
 
 // Pileup engine
 pub trait ReadsCollidingEngine<R: AlignedRead, Collider>
@@ -11,8 +16,9 @@ where
     R: AlignedRead,
     Collider: for<'a> ReadsCollider<'a, R>,
 {
-    // Reset and run the engine and collider for the given interval and get results
-    fn run(&mut self, cwork: <Collider as ReadsCollider<'_, R>>::Workload);
+    // Reset and run the engine and collider for the given interval and get results.
+    // Errors are reported for unreadable inputs, e.g. truncated or corrupted files
+    fn run(&mut self, cwork: <Collider as ReadsCollider<'_, R>>::Workload) -> Result<(), Error>;
     // Get calculated result if any is available
     fn result(&self) -> Option<<Collider as ReadsCollider<'_, R>>::ColliderResult>;
 }
@@ -22,12 +28,23 @@ pub trait ReadsCollider<'a, R: AlignedRead> {
     type ColliderResult;
     type Workload: AbstractInterval;
 
-    // Reset the collider using the given Workload
-    fn reset(&mut self, info: Self::Workload);
+    // Reset the collider using the given Workload, errors are reported if the window reference can't be fetched
+    fn reset(&mut self, info: Self::Workload) -> Result<(), Error>;
+    // Following reads are coming from the given source (i.e. input file index)
+    fn set_source(&mut self, source: usize);
     // Run the collider
     fn collide(&mut self, read: &R);
     // Calculate the result
     fn finalize(&mut self);
     // Return prepared info
     fn result(&'a self) -> Self::ColliderResult;
+    // Reads counted since the last reset
+    fn mapped(&self) -> u32;
+}
+
+// Same as htslib bam_endpos: unmapped reads and reads without reference-consuming operations cover 1bp
+pub(crate) fn endpos(read: &impl AlignedRead) -> i64 {
+    let pos = read.pos();
+    let end = if read.flags() & 4 != 0 { pos } else { read.cigar().end_pos() };
+    max(end, pos + 1)
 }
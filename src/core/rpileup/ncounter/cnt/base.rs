@@ -1,14 +1,24 @@
 use std::cmp::min;
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::Range;
+use std::sync::Arc;
 
-use bio_types::genome::{AbstractInterval, Interval};
+use bio_types::genome::{AbstractInterval, Interval, Position};
 use bio_types::strand::ReqStrand;
-use rust_htslib::bam::record::Cigar;
+use rust_htslib::bam::record::{Cigar, CigarStringView};
 
-use crate::core::dna::NucCounts;
+use crate::core::dna::{AltFragments, BaseQualities, Multimap, NucCounts, Nucleotide, ReqNucleotide, StrandDepth};
+use crate::core::io::fasta::FastaReader;
 use crate::core::read::AlignedRead;
 use crate::core::rpileup::ncounter::filters::ReadsFilter;
+use crate::error::Error;
+
+use super::excluded::ExcludedRegions;
+use super::junctions::SpliceJunctions;
 
 #[derive(Clone)]
 pub struct BaseNucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
@@ -20,6 +30,49 @@ pub struct BaseNucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
     buffer: Vec<NucCounts>,
     matched: Vec<Range<u32>>,
     mapped: u32,
+    // Reads rejected only due to the mapq = 255 policy
+    mapq_255_dropped: u32,
+    // Per-source caches (empty if not requested)
+    samples: Vec<Vec<NucCounts>>,
+    // Forward / reverse alignments supporting each position (empty if not requested)
+    depth: Vec<StrandDepth>,
+    strand_depth: bool,
+    // Base qualities of each sequenced nucleotide (empty if not requested)
+    qualities: Vec<BaseQualities>,
+    base_qualities: bool,
+    // Fragments with non-assembly nucleotides at each position (empty if not requested) & the window assembly
+    fragments: Vec<AltFragments>,
+    assembly: Option<Box<dyn FastaReader>>,
+    // Hash of the currently counted fragment (only if fragments are tracked)
+    fragment: u64,
+    source: usize,
+    // Bases counted for first mates in the current window (None if overlaps are not resolved)
+    mates: Option<HashMap<Vec<u8>, Vec<CountedBase>>>,
+    // UMI tag to collapse duplicates (None if not requested)
+    umi: Option<[u8; 2]>,
+    // Reads with UMI staged in the current window, counted only on flush
+    staged: BTreeMap<UMIKey, StagedRead>,
+    umi_collapsed: u32,
+    // Soft-clipped bases & indels of the last counted read (collected only if requested)
+    alnstats: bool,
+    events: AlnEvents,
+    // Aligned blocks & counted bases of the last counted read (collected only if requested)
+    trace: bool,
+    // Window ranges where bases of the currently counted read are ignored
+    skipped: Vec<Range<u32>>,
+    // Excluded regions & their parts inside the current window (sorted), bases there are never counted
+    excluded: Option<Arc<ExcludedRegions>>,
+    masked: Vec<Range<u32>>,
+    // Flanks of N gaps observed in the current window & their counts (None if not requested)
+    junctions: Option<BTreeMap<Position, u32>>,
+    // Weight of the currently counted read
+    multimap: Multimap,
+    weight: u32,
+    // Max mismatches a single read may contribute to the current window (None => unlimited)
+    contribution: Option<ReadContribution>,
+    // Max reads covering the first counted position of a read (None => unlimited) & reads skipped due to the cap
+    max_coverage: Option<u32>,
+    coverage_capped: u32,
     // Current interval
     interval: Interval,
     phantom: PhantomData<fn() -> R>,
@@ -33,12 +86,121 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
             buffer: Vec::with_capacity(maxbuf),
             matched: Vec::with_capacity(20),
             mapped: 0,
+            mapq_255_dropped: 0,
+            samples: vec![],
+            depth: vec![],
+            strand_depth: false,
+            qualities: vec![],
+            base_qualities: false,
+            fragments: vec![],
+            assembly: None,
+            fragment: 0,
+            source: 0,
+            mates: None,
+            umi: None,
+            staged: BTreeMap::new(),
+            umi_collapsed: 0,
+            alnstats: false,
+            events: Default::default(),
+            trace: false,
+            skipped: vec![],
+            excluded: None,
+            masked: vec![],
+            junctions: None,
+            multimap: Multimap::Full,
+            weight: 1,
+            contribution: None,
+            max_coverage: None,
+            coverage_capped: 0,
             trim5: trim5 as usize,
             trim3: trim3 as usize,
             phantom: Default::default(),
         }
     }
 
+    pub fn with_sources(mut self, sources: usize) -> Self {
+        self.samples = vec![Vec::with_capacity(self.buffer.capacity()); sources];
+        self
+    }
+
+    // Track forward / reverse alignments supporting each position
+    pub fn with_strand_depth(mut self) -> Self {
+        self.strand_depth = true;
+        self.depth = Vec::with_capacity(self.buffer.capacity());
+        self
+    }
+
+    // Accumulate base qualities of each sequenced nucleotide, doubles the memory footprint of the window
+    pub fn with_base_qualities(mut self) -> Self {
+        self.base_qualities = true;
+        self.qualities = Vec::with_capacity(self.buffer.capacity());
+        self
+    }
+
+    // Track fragments (read names) sequencing non-assembly nucleotides at each position, mates are the same fragment
+    pub fn with_alt_fragments(mut self, assembly: Box<dyn FastaReader>) -> Self {
+        self.assembly = Some(assembly);
+        self.fragments = Vec::with_capacity(self.buffer.capacity());
+        self
+    }
+
+    // Count bases covered by both mates of a pair only once
+    pub fn with_overlaps_dedup(mut self) -> Self {
+        self.mates = Some(HashMap::new());
+        self
+    }
+
+    // Reads with the same start, strand and UMI are counted only once (the one with the highest sum of base qualities)
+    pub fn with_umi_dedup(mut self, tag: [u8; 2]) -> Self {
+        self.umi = Some(tag);
+        self
+    }
+
+    // Record soft-clipped bases & indels for each counted read
+    pub fn with_aln_stats(mut self) -> Self {
+        self.alnstats = true;
+        self
+    }
+
+    // Record aligned blocks & changes of the window counts for each counted read, e.g. to revert them later
+    pub fn with_read_trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
+    // Weight multi-mapped reads by the number of their alignments (NH tag)
+    pub fn with_multimap(mut self, multimap: Multimap) -> Self {
+        self.multimap = multimap;
+        self
+    }
+
+    // Stop counting a read after it contributed `max` mismatches to the window, e.g. to damp systematic errors of
+    // noisy long reads. Mismatches are called relative to the reference assembly, N's are never mismatches.
+    pub fn with_max_read_contribution(mut self, max: u32, reference: Box<dyn FastaReader>) -> Self {
+        self.contribution = Some(ReadContribution { max, reference, current: 0 });
+        self
+    }
+
+    // Skip reads once the window position where they start is covered by `max` counted reads.
+    // Reads are taken in the BAM order (and files in the given order) => results are reproducible
+    pub fn with_max_coverage(mut self, max: u32) -> Self {
+        self.max_coverage = Some(max);
+        self
+    }
+
+    // Bases inside the excluded regions are never counted, e.g. they don't contribute to ROI flanks.
+    // Unlike the workload subtraction, reads covering only excluded bases don't cover the window either
+    pub fn with_excluded(mut self, excluded: Arc<ExcludedRegions>) -> Self {
+        self.excluded = Some(excluded);
+        self
+    }
+
+    // Record splice junctions (N gaps) of the counted reads, e.g. to annotate sites with the distance to them
+    pub fn with_junctions(mut self) -> Self {
+        self.junctions = Some(BTreeMap::new());
+        self
+    }
+
     #[inline]
     pub fn interval(&self) -> &Interval {
         &self.interval
@@ -49,41 +211,260 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
         &self.buffer
     }
 
+    #[inline]
+    pub fn counted_by_source(&self) -> &[Vec<NucCounts>] {
+        &self.samples
+    }
+
+    #[inline]
+    pub fn depth(&self) -> &[StrandDepth] {
+        &self.depth
+    }
+
+    #[inline]
+    pub fn qualities(&self) -> &[BaseQualities] {
+        &self.qualities
+    }
+
+    #[inline]
+    pub fn fragments(&self) -> &[AltFragments] {
+        &self.fragments
+    }
+
+    #[inline]
+    pub fn matched(&self) -> &[Range<u32>] {
+        &self.matched
+    }
+
+    #[inline]
+    pub fn events(&self) -> &AlnEvents {
+        &self.events
+    }
+
+    // Junctions observed in the current window, None if not requested
+    pub fn junctions(&self) -> Option<SpliceJunctions> {
+        self.junctions.as_ref().map(SpliceJunctions::new)
+    }
+
     #[inline]
     pub fn mapped(&self) -> u32 {
         self.mapped
     }
 
     #[inline]
-    pub fn reset(&mut self, interval: Interval) {
+    pub fn mapq_255_dropped(&self) -> u32 {
+        self.mapq_255_dropped
+    }
+
+    #[inline]
+    pub fn umi_collapsed(&self) -> u32 {
+        self.umi_collapsed
+    }
+
+    #[inline]
+    pub fn coverage_capped(&self) -> u32 {
+        self.coverage_capped
+    }
+
+    #[inline]
+    pub fn reset(&mut self, interval: Interval) -> Result<(), Error> {
         let newlen = interval.range().end - interval.range().start;
         debug_assert!(newlen > 0);
         self.buffer.clear();
         self.buffer.resize(newlen as usize, NucCounts::zeros());
+        for sample in &mut self.samples {
+            sample.clear();
+            sample.resize(newlen as usize, NucCounts::zeros());
+        }
+        if self.strand_depth {
+            self.depth.clear();
+            self.depth.resize(newlen as usize, StrandDepth::default());
+        }
+        if self.base_qualities {
+            self.qualities.clear();
+            self.qualities.resize(newlen as usize, BaseQualities::default());
+        }
+        if let Some(assembly) = &mut self.assembly {
+            assembly.fetch(interval.contig(), interval.range())?;
+            self.fragments.clear();
+            self.fragments.resize(newlen as usize, AltFragments::default());
+        }
+        self.source = 0;
+        if let Some(mates) = &mut self.mates {
+            mates.clear();
+        }
+        self.staged.clear();
 
         self.mapped = 0;
+        self.mapq_255_dropped = 0;
+        self.umi_collapsed = 0;
+        self.coverage_capped = 0;
+        if let Some(contribution) = &mut self.contribution {
+            contribution.reference.fetch(interval.contig(), interval.range())?;
+        }
+        if let Some(excluded) = &self.excluded {
+            self.masked = excluded.window(interval.contig(), interval.range());
+        }
+        if let Some(junctions) = &mut self.junctions {
+            junctions.clear();
+        }
         self.interval = interval;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_source(&mut self, source: usize) {
+        debug_assert!(self.samples.is_empty() || source < self.samples.len());
+        self.source = source;
+        // Read names are meaningful only within a single source
+        if let Some(mates) = &mut self.mates {
+            mates.clear();
+        }
     }
 
     pub fn count(&mut self, read: &R) -> &[Range<u32>] {
         self.matched.clear();
+        self.events.clear();
 
         if self.is_record_ok(read) {
-            self.implprocess(read);
-
-            if !self.matched.is_empty() {
-                self.mapped += 1;
+            if self.is_capped(read) {
+                self.coverage_capped += 1;
+                return &self.matched;
             }
+            self.weight = self.weight(read);
+            match self.umi.and_then(|tag| read.tag(&tag)) {
+                Some(umi) => self.stage(read, umi),
+                None => {
+                    if self.assembly.is_some() {
+                        self.fragment = fragment(self.source, read.name());
+                    }
+                    self.implprocess(read);
+                    if !self.matched.is_empty() {
+                        self.mapped += 1;
+                    }
+                }
+            }
+        } else if self.rfilter.is_mapq_255_dropped(read) {
+            self.mapq_255_dropped += 1;
         }
         &self.matched
     }
 
+    // Same as count, but bases inside the given window ranges are ignored (as if they were filtered out)
+    pub fn count_skipping(&mut self, read: &R, skipped: Vec<Range<u32>>) -> &[Range<u32>] {
+        self.skipped = skipped;
+        self.count(read);
+        self.skipped.clear();
+        &self.matched
+    }
+
+    // Count staged reads, matched ranges & alignment events are reported for each counted read
+    pub fn flush(&mut self, mut oncounted: impl FnMut(&[Range<u32>], &AlnEvents)) {
+        if self.staged.is_empty() {
+            return;
+        }
+        let (source, staged) = (self.source, std::mem::take(&mut self.staged));
+        let mut current = None;
+        for mut read in staged.into_values() {
+            if current != Some(read.source) {
+                self.set_source(read.source);
+                current = Some(read.source);
+            }
+            self.weight = read.weight;
+            self.events.counted.clear();
+            if self.assembly.is_some() {
+                self.fragment = fragment(read.source, &read.name);
+            }
+
+            let mut mate =
+                if self.mates.is_some() && read.flags & 1 != 0 { self.pair(&read.name) } else { Mate::Unpaired };
+            for base in read.bases {
+                self.count_base(&mut mate, base);
+            }
+            if let Mate::First(counted) = mate {
+                self.remember(&read.name, counted);
+            }
+
+            self.mapped += 1;
+            read.events.counted = std::mem::take(&mut self.events.counted);
+            oncounted(&read.matched, &read.events);
+        }
+        self.source = source;
+    }
+
     #[inline]
     fn is_record_ok(&self, record: &R) -> bool {
         self.rfilter.is_read_ok(record) && record.contig() == self.interval.contig()
     }
 
+    // Counted coverage is checked at the first window position of the read, weights are in 1 / scale units
+    #[inline]
+    fn is_capped(&self, read: &R) -> bool {
+        let max = match self.max_coverage {
+            None => return false,
+            Some(max) => max,
+        };
+        let pos = (read.pos() - self.interval.range().start as i64).max(0) as usize;
+        self.buffer.get(pos).map_or(false, |x| x.coverage() >= max.saturating_mul(self.multimap.scale()))
+    }
+
+    #[inline]
+    fn weight(&self, read: &R) -> u32 {
+        match self.multimap {
+            Multimap::Full => 1,
+            Multimap::Fractional => {
+                let hits = read.tag(b"NH").and_then(|x| String::from_utf8(x).ok()?.parse().ok());
+                self.multimap.weight(hits.unwrap_or(1))
+            }
+        }
+    }
+
+    fn stage(&mut self, read: &R, umi: Vec<u8>) {
+        let bases = match self.walk(read, Mate::Staged(Vec::new())) {
+            Mate::Staged(bases) => bases,
+            _ => unreachable!(),
+        };
+        // Matched ranges are reported only when the read is flushed
+        let matched: Vec<Range<u32>> = self.matched.drain(..).collect();
+        if matched.is_empty() {
+            return;
+        }
+
+        let staged = StagedRead {
+            qualsum: read.qual().iter().map(|x| *x as u64).sum(),
+            source: self.source,
+            weight: self.weight,
+            name: read.name().to_vec(),
+            flags: read.flags(),
+            bases,
+            matched,
+            events: std::mem::take(&mut self.events),
+        };
+        let key = (self.source, read.pos(), *read.strand() == ReqStrand::Reverse, umi);
+        match self.staged.entry(key) {
+            Entry::Vacant(x) => {
+                x.insert(staged);
+            }
+            Entry::Occupied(mut x) => {
+                self.umi_collapsed += 1;
+                // Keep the read with the highest sum of base qualities, the first one on ties
+                if staged.qualsum > x.get().qualsum {
+                    x.insert(staged);
+                }
+            }
+        }
+    }
+
     fn implprocess(&mut self, read: &R) {
+        let mate = self.mate(read);
+        if let Mate::First(counted) = self.walk(read, mate) {
+            self.remember(read.name(), counted);
+        }
+    }
+
+    // Every aligned base inside the window is counted regardless of where the read starts or ends.
+    // Trimming & base filters work in the read coordinates => they don't depend on the window either.
+    fn walk(&mut self, read: &R, mut mate: Mate) -> Mate {
         let sequence = read.seq();
 
         let (mut roipos, mut seqpos) = (read.pos() - self.interval.range().start as i64, 0usize);
@@ -91,7 +472,7 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
 
         // Read is too short
         if read.len() <= (self.trim5 + self.trim3) {
-            return;
+            return mate;
         }
 
         let (minseqpos, maxseqpos) = match read.strand() {
@@ -99,18 +480,34 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
             ReqStrand::Reverse => (self.trim3, read.len() - self.trim5),
         };
 
-        for block in read.cigar().iter() {
+        let reverse = *read.strand() == ReqStrand::Reverse;
+        let cigar = read.cigar();
+        if self.alnstats {
+            self.record(&cigar, roipos, roisize);
+        }
+        if self.junctions.is_some() {
+            self.splice(&cigar, read.pos());
+        }
+        if self.trace {
+            (self.events.aligned, self.events.alnlen) = aligned(&cigar, roipos, roisize);
+        }
+        if let Some(contribution) = &mut self.contribution {
+            contribution.current = 0;
+        }
+
+        // Reference/query positions are tracked for all operations, including blocks outside the window
+        let mut capped = false;
+        for block in cigar.iter() {
             if roipos >= roisize || seqpos >= maxseqpos {
                 break;
             }
             match block {
                 Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => {
-                    // fast-end when possible
-                    let end = min(roisize as i64, roipos + *ops as i64);
-                    // fast-forward when possible
+                    // Clip the block at the window boundaries
+                    let end = min(roisize, roipos + *ops as i64);
                     if roipos < 0 {
-                        let skip = min(roipos.abs() as u32, *ops);
-                        roipos += skip as i64;
+                        let skip = min(-roipos, *ops as i64);
+                        roipos += skip;
                         seqpos += skip as usize;
                     }
 
@@ -118,15 +515,27 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
                     let start = roipos;
                     for _ in start..end {
                         debug_assert!(roipos < roisize);
-                        if seqpos >= minseqpos && seqpos < maxseqpos && self.rfilter.is_base_ok(read, seqpos) {
+                        if seqpos >= minseqpos
+                            && seqpos < maxseqpos
+                            && !self.is_skipped(roipos)
+                            && self.rfilter.is_base_ok(read, seqpos)
+                        {
                             debug_assert!(roipos >= 0);
-                            // From the SAM specification: No assumptions can be made on the letter cases
-                            match sequence[seqpos as usize] {
-                                b'A' | b'a' => self.buffer[roipos as usize].A += 1,
-                                b'T' | b't' => self.buffer[roipos as usize].T += 1,
-                                b'G' | b'g' => self.buffer[roipos as usize].G += 1,
-                                b'C' | b'c' => self.buffer[roipos as usize].C += 1,
-                                _ => {}
+                            let (pos, base) = (roipos as usize, sequence[seqpos as usize]);
+                            if !self.contributes(pos, base) {
+                                capped = true;
+                                break;
+                            }
+                            match mate {
+                                Mate::Unpaired => {
+                                    let qual = if self.base_qualities { read.base_qual(seqpos) } else { 0 };
+                                    self.add(pos, base, qual, reverse)
+                                }
+                                _ => {
+                                    let qual = read.base_qual(seqpos);
+                                    let base = CountedBase { pos: pos as u32, base, qual, reverse };
+                                    self.count_base(&mut mate, base);
+                                }
                             }
                             if prevmatched.is_none() {
                                 prevmatched = Some(roipos as u32);
@@ -141,6 +550,10 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
                     if let Some(m) = prevmatched {
                         self.matched.push(m..roipos as u32);
                     }
+                    // The rest of the read is ignored
+                    if capped {
+                        break;
+                    }
                 }
                 Cigar::Del(ops) | Cigar::RefSkip(ops) => {
                     roipos += *ops as i64;
@@ -151,7 +564,323 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
                 Cigar::HardClip(_) | Cigar::Pad(_) => {}
             }
         }
+
+        mate
+    }
+
+    // Soft clips (anchored to the adjacent aligned base) & indels in the window coordinates
+    fn record(&mut self, cigar: &CigarStringView, mut roipos: i64, roisize: i64) {
+        let alnstart = roipos;
+        for block in cigar.iter() {
+            let (range, events) = match block {
+                Cigar::SoftClip(_) => {
+                    let pos = if roipos == alnstart { roipos } else { roipos - 1 };
+                    (pos..pos + 1, &mut self.events.softclipped)
+                }
+                // Insertions are attributed to both flanking bases
+                Cigar::Ins(_) => (roipos - 1..roipos + 1, &mut self.events.indels),
+                Cigar::Del(ops) => {
+                    roipos += *ops as i64;
+                    (roipos - *ops as i64..roipos, &mut self.events.indels)
+                }
+                Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) | Cigar::RefSkip(ops) => {
+                    roipos += *ops as i64;
+                    continue;
+                }
+                Cigar::HardClip(_) | Cigar::Pad(_) => continue,
+            };
+            let (start, end) = (range.start.max(0), range.end.min(roisize));
+            if start < end {
+                events.push(start as u32..end as u32);
+            }
+        }
+    }
+
+    // Exonic bases flanking N gaps in the reference coordinates, including gaps outside the window
+    fn splice(&mut self, cigar: &CigarStringView, mut refpos: i64) {
+        let observed = match &mut self.junctions {
+            None => return,
+            Some(x) => x,
+        };
+        for block in cigar.iter() {
+            match block {
+                Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) | Cigar::Del(ops) => refpos += *ops as i64,
+                Cigar::RefSkip(ops) => {
+                    if refpos > 0 {
+                        *observed.entry((refpos - 1) as Position).or_default() += 1;
+                    }
+                    refpos += *ops as i64;
+                    *observed.entry(refpos as Position).or_default() += 1;
+                }
+                Cigar::SoftClip(_) | Cigar::Ins(_) | Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+    }
+
+    #[inline]
+    fn is_skipped(&self, roipos: i64) -> bool {
+        let pos = roipos as u32;
+        let ind = self.masked.partition_point(|x| x.end <= pos);
+        self.masked.get(ind).map_or(false, |x| x.start <= pos) || self.skipped.iter().any(|x| x.contains(&pos))
+    }
+
+    // False once the read has already contributed the max number of mismatches & the base is yet another one
+    #[inline]
+    fn contributes(&mut self, pos: usize, base: u8) -> bool {
+        let contribution = match &mut self.contribution {
+            None => return true,
+            Some(x) => x,
+        };
+        let (reference, sequenced) = (contribution.reference.result()[pos], Nucleotide::from(base));
+        if reference == Nucleotide::Unknown || sequenced == Nucleotide::Unknown || reference == sequenced {
+            return true;
+        }
+        if contribution.current >= contribution.max {
+            return false;
+        }
+        contribution.current += 1;
+        true
+    }
+
+    fn count_base(&mut self, mate: &mut Mate, counted: CountedBase) {
+        let (pos, base, qual, reverse) = (counted.pos as usize, counted.base, counted.qual, counted.reverse);
+        match mate {
+            Mate::Unpaired => self.add(pos, base, qual, reverse),
+            Mate::Staged(staged) => staged.push(counted),
+            Mate::First(first) => {
+                first.push(counted);
+                self.add(pos, base, qual, reverse);
+            }
+            Mate::Second(first) => match first.binary_search_by_key(&counted.pos, |x| x.pos) {
+                // Both mates cover the locus -> keep the base with the highest quality
+                Ok(ind) => {
+                    if counted.qual > first[ind].qual {
+                        self.remove(pos, first[ind].base, first[ind].qual, first[ind].reverse);
+                        self.add(pos, base, qual, reverse);
+                    }
+                }
+                Err(_) => self.add(pos, base, qual, reverse),
+            },
+        }
+    }
+
+    fn mate(&mut self, read: &R) -> Mate {
+        // Paired read
+        if self.mates.is_some() && read.flags() & 1 != 0 {
+            self.pair(read.name())
+        } else {
+            Mate::Unpaired
+        }
+    }
+
+    fn pair(&mut self, name: &[u8]) -> Mate {
+        match self.mates.as_mut().and_then(|mates| mates.remove(name)) {
+            Some(counted) => Mate::Second(counted),
+            None => Mate::First(Vec::new()),
+        }
+    }
+
+    fn remember(&mut self, name: &[u8], counted: Vec<CountedBase>) {
+        if let Some(mates) = &mut self.mates {
+            if !counted.is_empty() {
+                mates.insert(name.to_vec(), counted);
+            }
+        }
+    }
+
+    // Mates share the NH tag => overlapping bases are replaced using the weight of the current mate
+    #[inline]
+    fn add(&mut self, pos: usize, base: u8, qual: u8, reverse: bool) {
+        self.traced(pos, base, reverse, true);
+        let counted = increment(&mut self.buffer[pos], base, self.weight);
+        if let Some(sample) = self.samples.get_mut(self.source) {
+            increment(&mut sample[pos], base, self.weight);
+        }
+        if let (Some(quals), Ok(nuc)) = (self.qualities.get_mut(pos), ReqNucleotide::try_from(base)) {
+            quals.add(nuc, qual, self.weight);
+        }
+        self.track(pos, base, true);
+        if let (true, Some(depth)) = (counted, self.depth.get_mut(pos)) {
+            match reverse {
+                true => depth.reverse += 1,
+                false => depth.forward += 1,
+            }
+        }
+    }
+
+    #[inline]
+    fn remove(&mut self, pos: usize, base: u8, qual: u8, reverse: bool) {
+        self.traced(pos, base, reverse, false);
+        let counted = decrement(&mut self.buffer[pos], base, self.weight);
+        if let Some(sample) = self.samples.get_mut(self.source) {
+            decrement(&mut sample[pos], base, self.weight);
+        }
+        if let (Some(quals), Ok(nuc)) = (self.qualities.get_mut(pos), ReqNucleotide::try_from(base)) {
+            quals.remove(nuc, qual, self.weight);
+        }
+        self.track(pos, base, false);
+        if let (true, Some(depth)) = (counted, self.depth.get_mut(pos)) {
+            match reverse {
+                true => depth.reverse -= 1,
+                false => depth.forward -= 1,
+            }
+        }
+    }
+
+    // Only nucleotides other than the assembly one are tracked, mismatching bases are rare => the memory is bounded
+    #[inline]
+    fn track(&mut self, pos: usize, base: u8, added: bool) {
+        let assembly = match &self.assembly {
+            None => return,
+            Some(x) => x.result()[pos],
+        };
+        if let Ok(nuc) = ReqNucleotide::try_from(base) {
+            if assembly != nuc.into() {
+                match added {
+                    true => self.fragments[pos].add(nuc, self.fragment),
+                    false => self.fragments[pos].remove(nuc, self.fragment),
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn traced(&mut self, pos: usize, base: u8, reverse: bool, added: bool) {
+        if self.trace {
+            let (weight, source) = (self.weight, self.source);
+            self.events.counted.push(TracedBase { pos: pos as u32, base, weight, source, reverse, added });
+        }
+    }
+}
+
+// Window ranges covered by soft-clipped bases & indels of a read
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct AlnEvents {
+    pub softclipped: Vec<Range<u32>>,
+    pub indels: Vec<Range<u32>>,
+    // Aligned blocks clipped to the window & the total aligned length of the read (only if traced)
+    pub aligned: Vec<Range<u32>>,
+    pub alnlen: u32,
+    // Changes of the window counts made by the read (only if traced)
+    pub counted: Vec<TracedBase>,
+}
+
+impl AlnEvents {
+    #[inline]
+    fn clear(&mut self) {
+        self.softclipped.clear();
+        self.indels.clear();
+        self.aligned.clear();
+        self.alnlen = 0;
+        self.counted.clear();
+    }
+}
+
+// Base added to (or removed from, e.g. replaced by the overlapping mate) the window counts
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TracedBase {
+    pub pos: u32,
+    pub base: u8,
+    pub weight: u32,
+    pub source: usize,
+    // Alignment strand of the read
+    pub reverse: bool,
+    pub added: bool,
+}
+
+// Aligned (M/=/X) blocks clipped to the window & the total aligned length of the read
+pub fn aligned(cigar: &CigarStringView, mut roipos: i64, roisize: i64) -> (Vec<Range<u32>>, u32) {
+    let (mut blocks, mut alnlen) = (Vec::new(), 0);
+    for block in cigar.iter() {
+        match block {
+            Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => {
+                let (start, end) = (roipos.max(0), (roipos + *ops as i64).min(roisize));
+                if start < end {
+                    blocks.push(start as u32..end as u32);
+                }
+                alnlen += *ops;
+                roipos += *ops as i64;
+            }
+            Cigar::Del(ops) | Cigar::RefSkip(ops) => roipos += *ops as i64,
+            Cigar::SoftClip(_) | Cigar::Ins(_) | Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+    (blocks, alnlen)
+}
+
+#[derive(Clone)]
+struct ReadContribution {
+    max: u32,
+    // Reference sequence of the current window
+    reference: Box<dyn FastaReader>,
+    // Mismatches contributed by the currently counted read
+    current: u32,
+}
+
+#[derive(Copy, Clone)]
+struct CountedBase {
+    pos: u32,
+    base: u8,
+    qual: u8,
+    // Alignment strand of the read
+    reverse: bool,
+}
+
+enum Mate {
+    Unpaired,
+    First(Vec<CountedBase>),
+    Second(Vec<CountedBase>),
+    // Bases are collected to be counted later
+    Staged(Vec<CountedBase>),
+}
+
+// Source, start, is reverse, UMI
+type UMIKey = (usize, i64, bool, Vec<u8>);
+
+#[derive(Clone)]
+struct StagedRead {
+    qualsum: u64,
+    source: usize,
+    weight: u32,
+    name: Vec<u8>,
+    flags: u16,
+    bases: Vec<CountedBase>,
+    matched: Vec<Range<u32>>,
+    events: AlnEvents,
+}
+
+// Fragments are identified by the source & read name => mates of a pair are the same fragment
+#[inline]
+fn fragment(source: usize, name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (source, name).hash(&mut hasher);
+    hasher.finish()
+}
+
+// Both return false for bases that are not counted (e.g. N)
+#[inline]
+fn increment(cnts: &mut NucCounts, base: u8, weight: u32) -> bool {
+    // From the SAM specification: No assumptions can be made on the letter cases
+    match base {
+        b'A' | b'a' => cnts.A += weight,
+        b'T' | b't' => cnts.T += weight,
+        b'G' | b'g' => cnts.G += weight,
+        b'C' | b'c' => cnts.C += weight,
+        _ => return false,
     }
+    true
+}
+
+#[inline]
+fn decrement(cnts: &mut NucCounts, base: u8, weight: u32) -> bool {
+    match base {
+        b'A' | b'a' => cnts.A -= weight,
+        b'T' | b't' => cnts.T -= weight,
+        b'G' | b'g' => cnts.G -= weight,
+        b'C' | b'c' => cnts.C -= weight,
+        _ => return false,
+    }
+    true
 }
 
 #[cfg(test)]
@@ -163,8 +892,10 @@ mod tests {
     use shortcats::*;
 
     use crate::core::dna::NucCounts;
+    use crate::core::io::fasta::MockFastaReader;
     use crate::core::read::MockRead;
-    use crate::core::rpileup::ncounter::filters::MockReadsFilter;
+    use crate::core::refpred::PredNucleotide;
+    use crate::core::rpileup::ncounter::filters::{ByFlags, ByQuality, MapQ255, MockReadsFilter, Sequential};
 
     use super::*;
 
@@ -253,7 +984,7 @@ mod tests {
         }
 
         let mut counter = BaseNucCounter::new((roisize + 1) as usize, filter, trim.0, trim.1);
-        counter.reset(roi);
+        counter.reset(roi).unwrap();
 
         let mut read = MockRead::new();
         read.expect_pos().return_const(pos);
@@ -426,6 +1157,713 @@ mod tests {
         run((2, 0), 2..4, 1, "CGTA", ReqStrand::Reverse, vec![true], vec![M(4)], &[G(), Z()], &[0..1]);
     }
 
+    #[test]
+    fn base_quality() {
+        let mut counter = BaseNucCounter::new(6, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        counter.reset(Interval::new("chr1".into(), 0..6)).unwrap();
+
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(0);
+        read.expect_len().return_const(6usize);
+        read.expect_cigar().return_once(|| CigarString(vec![M(6)]).into_view(0));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(|| b"ACGTAC".to_vec());
+        let quals = [30, 10, 20, 19, 40, 0];
+        read.expect_base_qual().returning(move |i| quals[i]);
+
+        // Low quality bases are neither counted nor contribute to the coverage
+        assert_eq!(counter.count(&read), &[0..1, 2..3, 4..5]);
+        assert_eq!(counter.counted(), &[A(), Z(), G(), Z(), A(), Z()]);
+        assert_eq!(counter.mapped(), 1);
+    }
+
+    #[test]
+    fn aln_events() {
+        let mut counter = BaseNucCounter::new(10, ByQuality::new(0, MapQ255::Keep, 0), 0, 0).with_aln_stats();
+        counter.reset(Interval::new("chr1".into(), 0..10)).unwrap();
+
+        for (pos, len, cigar, softclipped, indels) in [
+            (2, 12, vec![S(2), M(3), I(1), M(2), D(2), M(1), S(3)], vec![2..3, 9..10], vec![4..6, 7..9]),
+            // Events outside the window are ignored
+            (-2, 14, vec![S(2), M(3), D(1), N(3), M(5), I(2), M(1), S(1)], vec![], vec![1..2, 9..10]),
+            (0, 4, vec![M(4)], vec![], vec![]),
+        ] {
+            let mut read = MockRead::new();
+            read.expect_mapq().return_const(60);
+            read.expect_contig().return_const("chr1".to_owned());
+            read.expect_pos().return_const(pos);
+            read.expect_len().return_const(len);
+            read.expect_cigar().return_once(move || CigarString(cigar).into_view(0));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || vec![b'A'; len]);
+            read.expect_base_qual().return_const(30);
+
+            counter.count(&read);
+            assert_eq!(counter.events(), &AlnEvents { softclipped, indels });
+        }
+    }
+
+    #[test]
+    fn flags() {
+        // Secondary, supplementary, duplicates, etc
+        let mut counter = BaseNucCounter::new(4, ByFlags::new(0, 3844), 0, 0);
+        counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+
+        for flags in [256u16, 2048, 1024, 256 + 16, 2048 + 1] {
+            let mut read = MockRead::new();
+            read.expect_flags().return_const(flags);
+            assert!(counter.count(&read).is_empty());
+        }
+        assert_eq!(counter.counted(), &[Z(), Z(), Z(), Z()]);
+        assert_eq!(counter.mapped(), 0);
+
+        let mut read = MockRead::new();
+        read.expect_flags().return_const(16u16);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(0);
+        read.expect_len().return_const(4usize);
+        read.expect_cigar().return_once(|| CigarString(vec![M(4)]).into_view(0));
+        read.expect_strand().return_const(ReqStrand::Reverse);
+        read.expect_seq().returning(|| b"ACGT".to_vec());
+
+        assert_eq!(counter.count(&read), &[0..4]);
+        assert_eq!(counter.counted(), &[A(), C(), G(), T()]);
+        assert_eq!(counter.mapped(), 1);
+    }
+
+    #[test]
+    fn sources() {
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+        let mut counter = BaseNucCounter::new(4, filter, 0, 0).with_sources(2);
+        counter.reset(Interval::new("".into(), 0..4)).unwrap();
+
+        for (source, seq) in [(0, "ACGT"), (1, "AAGG"), (0, "TTTT")] {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(0);
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(|| CigarString(vec![M(4)]).into_view(0));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+
+            counter.set_source(source);
+            counter.implprocess(&mut read);
+        }
+
+        let n = NucCounts::new;
+        assert_eq!(counter.counted(), &[n(2, 0, 0, 1), n(1, 1, 0, 1), n(0, 0, 2, 1), n(0, 0, 1, 2)]);
+        assert_eq!(
+            counter.counted_by_source(),
+            &[vec![n(1, 0, 0, 1), n(0, 1, 0, 1), n(0, 0, 1, 1), n(0, 0, 0, 2)], vec![A(), A(), G(), G()]]
+        );
+    }
+
+    fn mate(pos: i64, seq: &'static str, quals: Vec<u8>) -> MockRead {
+        stranded_mate(pos, seq, quals, ReqStrand::Forward)
+    }
+
+    fn stranded_mate(pos: i64, seq: &'static str, quals: Vec<u8>, strand: ReqStrand) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(3u16);
+        read.expect_name().return_const(b"pair".to_vec());
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(vec![M(seq.len() as u32)]).into_view(pos));
+        read.expect_strand().return_const(strand);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().returning(move |i| quals[i]);
+        read
+    }
+
+    fn aligned(pos: i64, seq: &'static str, cigar: Vec<Cigar>, strand: ReqStrand) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(0u16);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(cigar).into_view(pos));
+        read.expect_strand().return_const(strand);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().return_const(30);
+        read
+    }
+
+    #[test]
+    fn window_edges() {
+        let reads = || {
+            [
+                // Left edge
+                aligned(1, "ACGTACG", vec![S(2), M(2), D(1), M(3)], ReqStrand::Forward),
+                // Right edge
+                aligned(7, "GGTTAC", vec![M(2), N(2), M(2), I(1), M(1)], ReqStrand::Reverse),
+                // Both edges
+                aligned(0, "CCCCAAAAAA", vec![M(4), D(2), M(6)], ReqStrand::Forward),
+            ]
+        };
+        let count = |window: Range<u64>, trim: (u16, u16)| {
+            let size = (window.end - window.start) as usize;
+            let mut counter =
+                BaseNucCounter::new(size, ByQuality::new(0, MapQ255::Keep, 20), trim.0, trim.1).with_strand_depth();
+            counter.reset(Interval::new("chr1".into(), window)).unwrap();
+            for read in reads() {
+                counter.count(&read);
+            }
+            (counter.counted().to_vec(), counter.depth().to_vec())
+        };
+
+        let n = NucCounts::new;
+        let (counts, _) = count(3..9, (0, 0));
+        assert_eq!(counts, [n(0, 1, 0, 0), n(1, 0, 0, 0), n(0, 1, 0, 0), n(1, 0, 1, 0), n(1, 0, 1, 0), n(1, 0, 1, 0)]);
+
+        // Windows split at any position produce the same counts
+        for trim in [(0, 0), (1, 2)] {
+            let (whole, depth) = count(0..14, trim);
+            for split in 1..14 {
+                let (mut left, mut ldepth) = count(0..split, trim);
+                let (right, rdepth) = count(split..14, trim);
+                left.extend(right);
+                ldepth.extend(rdepth);
+                assert_eq!(left, whole);
+                assert_eq!(ldepth, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn trim_spliced() {
+        // Each cycle has a distinct base: C@2 G@3 T@4 | intron | T@9 G@10 | inserted C | A@11
+        let read = |strand| aligned(2, "ACGTTGCA", vec![S(1), M(3), N(4), M(2), I(1), M(1)], strand);
+        let count = |strand, trim: (u16, u16)| {
+            let mut counter = BaseNucCounter::new(14, ByQuality::new(0, MapQ255::Keep, 20), trim.0, trim.1);
+            counter.reset(Interval::new("chr1".into(), 0..14)).unwrap();
+            let matched = counter.count(&read(strand)).to_vec();
+            let counted =
+                counter.counted().iter().enumerate().filter(|(_, x)| x.coverage() > 0).map(|(pos, x)| (pos, *x));
+            (counted.collect::<Vec<_>>(), matched)
+        };
+
+        let all = vec![(2, C()), (3, G()), (4, T()), (9, T()), (10, G()), (11, A())];
+        for strand in [ReqStrand::Forward, ReqStrand::Reverse] {
+            assert_eq!(count(strand, (0, 0)), (all.clone(), vec![2..5, 9..11, 11..12]));
+        }
+
+        // Forward: 5` = soft-clipped A, C@2, G@3; 3` = A@11
+        assert_eq!(count(ReqStrand::Forward, (3, 1)), (vec![(4, T()), (9, T()), (10, G())], vec![4..5, 9..11]));
+        // Reverse: 5` = A@11, inserted C, G@10; 3` = soft-clipped A.
+        // Trimming in the reference coordinates would have dropped T@9 and C@2 as well.
+        assert_eq!(
+            count(ReqStrand::Reverse, (3, 1)),
+            (vec![(2, C()), (3, G()), (4, T()), (9, T())], vec![2..5, 9..10])
+        );
+
+        // Inserted & soft-clipped bases are trimmed as any other query base
+        assert_eq!(count(ReqStrand::Forward, (1, 2)), (all[..5].to_vec(), vec![2..5, 9..11]));
+        assert_eq!(count(ReqStrand::Reverse, (2, 1)), (all[..5].to_vec(), vec![2..5, 9..11]));
+        assert_eq!(count(ReqStrand::Reverse, (1, 2)), (all[1..5].to_vec(), vec![3..5, 9..11]));
+    }
+
+    #[test]
+    fn junctions() {
+        let reads = || {
+            [
+                // Intron 5..9
+                aligned(2, "ACGTTGCA", vec![S(1), M(3), N(4), M(2), I(1), M(1)], ReqStrand::Forward),
+                aligned(1, "CCCCGG", vec![M(4), N(4), M(2)], ReqStrand::Reverse),
+                // Intron 25..125 extends past the window, deletions aren't junctions
+                aligned(20, "AAAAACCCCC", vec![M(2), D(1), M(2), N(100), M(6)], ReqStrand::Forward),
+            ]
+        };
+        let mut counter = BaseNucCounter::new(30, ByQuality::new(0, MapQ255::Keep, 20), 0, 0).with_junctions();
+        counter.reset(Interval::new("chr1".into(), 0..30)).unwrap();
+        for read in reads() {
+            counter.count(&read);
+        }
+        let junctions = counter.junctions().unwrap();
+        assert_eq!(junctions.flanks(), [(4, 2), (9, 2), (24, 1), (125, 1)]);
+
+        counter.reset(Interval::new("chr1".into(), 30..60)).unwrap();
+        assert!(counter.junctions().unwrap().is_empty());
+
+        // Not requested
+        let mut counter = BaseNucCounter::new(30, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        counter.reset(Interval::new("chr1".into(), 0..30)).unwrap();
+        counter.count(&reads()[0]);
+        assert!(counter.junctions().is_none());
+    }
+
+    fn long_read(pos: i64, seq: String, cigar: Vec<Cigar>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(0u16);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(cigar).into_view(pos));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().return_const(30);
+        read
+    }
+
+    #[test]
+    fn long_spliced_reads() {
+        // Long read with a multi-kilobase intron, large clips & indels, aligned at 10000:
+        // 10000..11500 A | 11500..51500 intron | 51500..52300 C | 300 inserted T | 52300..52500 G |
+        // 52500..53700 deletion | 53700..54200 A
+        let cigar = vec![H(30), S(2000), M(1500), N(40000), M(800), I(300), M(200), D(1200), M(500), H(50)];
+        let seq =
+            ["T".repeat(2000), "A".repeat(1500), "C".repeat(800), "T".repeat(300), "G".repeat(200), "A".repeat(500)]
+                .concat();
+        assert_eq!(seq.len(), 5300);
+
+        let count = |window: Range<u64>, trim: (u16, u16)| {
+            let size = (window.end - window.start) as usize;
+            let mut counter = BaseNucCounter::new(size, ByQuality::new(0, MapQ255::Keep, 20), trim.0, trim.1);
+            counter.reset(Interval::new("chr1".into(), window)).unwrap();
+            let matched = counter.count(&long_read(10_000, seq.clone(), cigar.clone())).to_vec();
+            (counter.counted().to_vec(), matched)
+        };
+        let total =
+            |cnts: &[NucCounts]| cnts.iter().fold((0, 0, 0, 0), |x, c| (x.0 + c.A, x.1 + c.C, x.2 + c.G, x.3 + c.T));
+
+        let (whole, matched) = count(0..60_000, (0, 0));
+        assert_eq!(total(&whole), (2000, 800, 200, 0));
+        assert_eq!(matched, [10_000..11_500, 51_500..52_300, 52_300..52_500, 53_700..54_200]);
+        for (pos, expected) in [
+            (9_999, Z()),
+            (10_000, A()),
+            (11_499, A()),
+            (11_500, Z()),
+            (51_499, Z()),
+            (51_500, C()),
+            (52_299, C()),
+            (52_300, G()),
+            (52_499, G()),
+            (52_500, Z()),
+            (53_699, Z()),
+            (53_700, A()),
+            (54_199, A()),
+            (54_200, Z()),
+        ] {
+            assert_eq!(whole[pos], expected, "{}", pos);
+        }
+
+        // Windows around the intron / deletion & inside the intron
+        for (window, cnts, matched) in [
+            (11_400..51_600, (100, 100, 0, 0), vec![0..100, 40_100..40_200]),
+            (52_250..53_750, (50, 50, 200, 0), vec![0..50, 50..250, 1450..1500]),
+            (20_000..50_000, (0, 0, 0, 0), vec![]),
+        ] {
+            let (counted, exmatched) = count(window, (0, 0));
+            assert_eq!((total(&counted), exmatched), (cnts, matched));
+        }
+
+        // Trimming is applied in the read coordinates, i.e. soft-clipped bases are trimmed first
+        let (trimmed, matched) = count(0..60_000, (2100, 100));
+        assert_eq!(total(&trimmed), (1800, 800, 200, 0));
+        assert_eq!(matched, [10_100..11_500, 51_500..52_300, 52_300..52_500, 53_700..54_100]);
+
+        // Windows split at any position produce the same counts
+        for split in [10_500, 11_500, 30_000, 52_300, 52_400, 53_700] {
+            let (mut left, _) = count(0..split, (0, 0));
+            left.extend(count(split..60_000, (0, 0)).0);
+            assert_eq!(left, whole, "{}", split);
+        }
+    }
+
+    #[test]
+    fn max_read_contribution() {
+        let reference: Vec<Nucleotide> = b"AAAAAAAANA".iter().map(|x| Nucleotide::from(*x)).collect();
+        let count = |max: Option<u32>, reads: &[(i64, &'static str)]| {
+            let mut counter = BaseNucCounter::new(10, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+            if let Some(max) = max {
+                let mut fasta = MockFastaReader::new();
+                fasta.expect_fetch().withf(|_, range| *range == (0..10)).once().returning(|_, _| Ok(()));
+                fasta.expect_result().return_const(reference.clone());
+                counter = counter.with_max_read_contribution(max, Box::new(fasta));
+            }
+            counter.reset(Interval::new("chr1".into(), 0..10)).unwrap();
+
+            let mut matched = Vec::new();
+            for &(pos, seq) in reads {
+                let read = aligned(pos, seq, vec![M(seq.len() as u32)], ReqStrand::Forward);
+                matched.push(counter.count(&read).to_vec());
+            }
+            (counter.counted().to_vec(), matched)
+        };
+
+        // Mismatches at 1, 2, 4, 5, 7, 9 + N reference at 8
+        let noisy = (0, "AGGAGGAGGG");
+        let clean = (2, "AAAAAAAA");
+
+        let n = NucCounts::new;
+        let (a2, g2, a3, mixed) = (n(2, 0, 0, 0), n(0, 0, 2, 0), n(3, 0, 0, 0), n(1, 0, 2, 0));
+        let (counts, matched) = count(None, &[noisy, noisy, clean]);
+        assert_eq!(counts, [a2, g2, mixed, a3, mixed, mixed, a3, mixed, mixed, mixed]);
+        assert_eq!(matched, [vec![0..10], vec![0..10], vec![2..10]]);
+
+        // The budget is tracked for each read separately, the rest of the read is ignored once it's exceeded
+        let (counts, matched) = count(Some(2), &[noisy, noisy, clean]);
+        assert_eq!(counts, [a2, g2, mixed, a3, A(), A(), A(), A(), A(), A()]);
+        assert_eq!(matched, [vec![0..4], vec![0..4], vec![2..10]]);
+
+        // Mismatches against N's don't count
+        let (counts, matched) = count(Some(0), &[noisy, (0, "AAAAAAAAGA")]);
+        assert_eq!(counts, [a2, A(), A(), A(), A(), A(), A(), A(), G(), A()]);
+        assert_eq!(matched, [vec![0..1], vec![0..10]]);
+    }
+
+    #[test]
+    fn max_coverage() {
+        // Synthetic 10K-coverage pileup
+        let count = |max: Option<u32>| {
+            let mut counter = BaseNucCounter::new(10, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+            if let Some(max) = max {
+                counter = counter.with_max_coverage(max);
+            }
+            counter.reset(Interval::new("chr1".into(), 0..10)).unwrap();
+            for _ in 0..10_000 {
+                counter.count(&aligned(0, "AAAAAAAAAA", vec![M(10)], ReqStrand::Forward));
+            }
+            (counter.counted().to_vec(), counter.mapped(), counter.coverage_capped())
+        };
+
+        // Nothing changes if the cap is disabled or not reached
+        let (counts, mapped, capped) = count(None);
+        assert_eq!((counts[0], mapped, capped), (NucCounts::new(10_000, 0, 0, 0), 10_000, 0));
+        assert_eq!(count(Some(10_000)), count(None));
+
+        let (counts, mapped, capped) = count(Some(100));
+        assert!(counts.iter().all(|x| x.coverage() == 100));
+        assert_eq!((mapped, capped), (100, 9_900));
+    }
+
+    #[test]
+    fn strand_depth() {
+        let mut counter = BaseNucCounter::new(6, ByQuality::new(0, MapQ255::Keep, 20), 0, 0)
+            .with_overlaps_dedup()
+            .with_strand_depth();
+        counter.reset(Interval::new("chr1".into(), 0..6)).unwrap();
+
+        // Overlapping bases are moved to the strand of the winning mate, N's are not counted at all
+        counter.count(&stranded_mate(0, "AAAN", vec![30; 4], ReqStrand::Forward));
+        counter.count(&stranded_mate(2, "CCCC", vec![40; 4], ReqStrand::Reverse));
+
+        let d = StrandDepth::new;
+        assert_eq!(counter.depth(), &[d(1, 0), d(1, 0), d(0, 1), d(0, 1), d(0, 1), d(0, 1)]);
+        assert_eq!(counter.counted(), &[A(), A(), C(), C(), C(), C()]);
+
+        // Not tracked by default
+        let counter = BaseNucCounter::new(6, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        assert!(counter.depth().is_empty());
+    }
+
+    #[test]
+    fn read_trace() {
+        let mut counter =
+            BaseNucCounter::new(10, ByQuality::new(0, MapQ255::Keep, 20), 0, 0).with_overlaps_dedup().with_read_trace();
+        counter.reset(Interval::new("chr1".into(), 2..10)).unwrap();
+
+        // Aligned blocks are clipped to the window, the aligned length covers the whole read
+        counter.count(&aligned(0, "ACGTAC", vec![M(3), D(2), I(1), M(2)], ReqStrand::Reverse));
+        assert_eq!((&counter.events().aligned, counter.events().alnlen), (&vec![0..1, 3..5], 5));
+        let traced = |pos, base, added| TracedBase { pos, base, weight: 1, source: 0, reverse: true, added };
+        assert_eq!(counter.events().counted, [traced(0, b'G', true), traced(3, b'A', true), traced(4, b'C', true)]);
+
+        // Skipped bases are neither counted nor traced
+        counter.count_skipping(&aligned(3, "TTTT", vec![M(4)], ReqStrand::Reverse), vec![1..3, 4..8]);
+        assert_eq!(counter.events().counted, [traced(3, b'T', true)]);
+        assert_eq!(counter.counted(), &[G(), Z(), Z(), NucCounts::new(1, 0, 0, 1), C(), Z(), Z(), Z()]);
+
+        // Replaced bases of overlapping mates are traced as removed
+        counter.count(&mate(4, "AA", vec![30, 30]));
+        counter.count(&mate(4, "GG", vec![40, 40]));
+        let replaced: Vec<_> = counter.events().counted.iter().map(|x| (x.pos, x.base, x.added)).collect();
+        assert_eq!(replaced, [(2, b'A', false), (2, b'G', true), (3, b'A', false), (3, b'G', true)]);
+
+        // Cigar blocks are reported in the window coordinates
+        let cigar = CigarString(vec![S(2), M(3), N(5), E(2), I(1), X(4)]).into_view(0);
+        assert_eq!(super::aligned(&cigar, -2, 10), (vec![0..1, 6..8, 8..10], 9));
+        assert_eq!(super::aligned(&cigar, 20, 10), (vec![], 9));
+
+        // Not tracked by default
+        let mut counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+        counter.count(&aligned(0, "ACGT", vec![M(4)], ReqStrand::Forward));
+        assert!(counter.events().aligned.is_empty() && counter.events().counted.is_empty());
+    }
+
+    #[test]
+    fn base_qualities() {
+        let mut counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 0), 0, 0).with_base_qualities();
+        counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+
+        // Mates are counted independently without the overlaps deduplication, N's are ignored
+        counter.count(&mate(0, "ACGT", vec![10, 20, 30, 40]));
+        counter.count(&mate(0, "AAGN", vec![30, 5, 30, 2]));
+        let quals = counter.qualities();
+        assert_eq!(quals.len(), 4);
+        assert_eq!((quals[0].sum.A, quals[0].min.A), (40, 10));
+        assert_eq!((quals[1].sum.A, quals[1].min.A, quals[1].sum.C, quals[1].min.C), (5, 5, 20, 20));
+        assert_eq!((quals[2].sum.G, quals[2].min.G), (60, 30));
+        assert_eq!((quals[3].sum.T, quals[3].min.T, quals[3].sum.A), (40, 40, 0));
+
+        // Qualities are cleared for each window
+        counter.reset(Interval::new("chr1".into(), 4..7)).unwrap();
+        assert_eq!(counter.qualities(), &[BaseQualities::default(); 3]);
+        counter.reset(Interval::new("chr1".into(), 0..2)).unwrap();
+        counter.count(&mate(0, "CC", vec![25, 25]));
+        let quals: Vec<_> = counter.qualities().iter().map(|x| (x.sum.C, x.min.C, x.sum.A)).collect();
+        assert_eq!(quals, [(25, 25, 0); 2]);
+
+        // Replaced overlapping bases are subtracted
+        let mut counter = BaseNucCounter::new(2, ByQuality::new(0, MapQ255::Keep, 0), 0, 0)
+            .with_overlaps_dedup()
+            .with_base_qualities();
+        counter.reset(Interval::new("chr1".into(), 0..2)).unwrap();
+        counter.count(&mate(0, "AA", vec![10, 30]));
+        counter.count(&mate(0, "GG", vec![20, 20]));
+        assert_eq!(counter.counted(), &[G(), A()]);
+        let quals = counter.qualities();
+        assert_eq!((quals[0].sum.A, quals[0].sum.G, quals[1].sum.A, quals[1].sum.G), (0, 20, 30, 0));
+
+        // Not tracked by default
+        let counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        assert!(counter.qualities().is_empty());
+    }
+
+    #[test]
+    fn alt_fragments() {
+        let count = |dedup: bool| {
+            let reference: Vec<Nucleotide> = b"AAAN".iter().map(|x| Nucleotide::from(*x)).collect();
+            let mut fasta = MockFastaReader::new();
+            fasta.expect_fetch().withf(|_, range| *range == (0..4)).once().returning(|_, _| Ok(()));
+            fasta.expect_result().return_const(reference);
+            let mut counter =
+                BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 0), 0, 0).with_alt_fragments(Box::new(fasta));
+            if dedup {
+                counter = counter.with_overlaps_dedup();
+            }
+            counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+
+            let mut single = aligned(0, "AGAT", vec![M(4)], ReqStrand::Forward);
+            single.expect_name().return_const(b"single".to_vec());
+            for read in [mate(0, "AGGC", vec![10; 4]), mate(0, "AAGC", vec![30; 4]), single] {
+                counter.count(&read);
+            }
+            let prednuc = PredNucleotide::Homozygous(Nucleotide::A);
+            let reference = [Nucleotide::A, Nucleotide::A, Nucleotide::A, Nucleotide::Unknown];
+            let fragments: Vec<Option<u32>> =
+                counter.fragments().iter().zip(reference).map(|(x, refnuc)| x.alt(refnuc, &prednuc)).collect();
+            fragments
+        };
+        // Mates are the same fragment even if both of them are counted; all bases are tracked for N's
+        assert_eq!(count(false), [Some(0), Some(2), Some(1), Some(2)]);
+        // The replaced mismatch is not supported by the pair anymore
+        assert_eq!(count(true), [Some(0), Some(1), Some(1), Some(2)]);
+
+        // Not tracked by default
+        let counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 20), 0, 0);
+        assert!(counter.fragments().is_empty());
+    }
+
+    #[test]
+    fn overlapping_mates() {
+        let n = NucCounts::new;
+        for (dedup, first, second, expected) in [
+            // Mates agree
+            (true, mate(0, "ACGT", vec![30; 4]), mate(0, "ACGT", vec![30; 4]), vec![A(), C(), G(), T()]),
+            (
+                false,
+                mate(0, "ACGT", vec![30; 4]),
+                mate(0, "ACGT", vec![30; 4]),
+                vec![n(2, 0, 0, 0), n(0, 2, 0, 0), n(0, 0, 2, 0), n(0, 0, 0, 2)],
+            ),
+            // Mates disagree -> the base with the highest quality wins, the first mate wins on ties
+            (
+                true,
+                mate(0, "AAAA", vec![30, 40, 30, 30]),
+                mate(0, "CCCC", vec![40, 30, 30, 20]),
+                vec![C(), A(), A(), A()],
+            ),
+            (
+                false,
+                mate(0, "AAAA", vec![30, 40, 30, 30]),
+                mate(0, "CCCC", vec![40, 30, 30, 20]),
+                vec![n(1, 1, 0, 0); 4],
+            ),
+            // Partial overlap
+            (true, mate(0, "ACG", vec![30; 3]), mate(2, "TTT", vec![40; 3]), vec![A(), C(), T(), T(), T(), Z()]),
+            (
+                false,
+                mate(0, "ACG", vec![30; 3]),
+                mate(2, "TTT", vec![40; 3]),
+                vec![A(), C(), n(0, 0, 1, 1), T(), T(), Z()],
+            ),
+        ] {
+            let mut counter = BaseNucCounter::new(6, ByQuality::new(0, MapQ255::Keep, 20), 0, 0).with_sources(1);
+            if dedup {
+                counter = counter.with_overlaps_dedup();
+            }
+            counter.reset(Interval::new("chr1".into(), 0..6)).unwrap();
+
+            counter.count(&first);
+            counter.count(&second);
+            assert_eq!(counter.counted(), &expected);
+            assert_eq!(counter.counted_by_source(), &[expected]);
+            // Both mates are still reported as mapped reads
+            assert_eq!(counter.mapped(), 2);
+        }
+    }
+
+    fn with_mapq(mapq: u8, flags: u16) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(mapq);
+        read.expect_flags().return_const(flags);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(0);
+        read.expect_len().return_const(4usize);
+        read.expect_cigar().return_once(|| CigarString(vec![M(4)]).into_view(0));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(|| b"ACGT".to_vec());
+        read.expect_base_qual().return_const(30);
+        read
+    }
+
+    #[test]
+    fn mapq_255() {
+        for (policy, mapped, dropped) in [(MapQ255::Keep, 2, 0), (MapQ255::Drop, 1, 1)] {
+            let filter = Sequential::new(ByQuality::new(10, policy, 20), ByFlags::new(0, 256));
+            let mut counter = BaseNucCounter::new(4, filter, 0, 0);
+            counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+
+            // Low mapq & secondary alignments are not reported as dropped due to the mapq = 255 policy
+            for (mapq, flags) in [(255, 0), (255, 256), (5, 0), (60, 0)] {
+                counter.count(&with_mapq(mapq, flags));
+            }
+            assert_eq!(counter.mapped(), mapped);
+            assert_eq!(counter.mapq_255_dropped(), dropped);
+
+            counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+            assert_eq!(counter.mapq_255_dropped(), 0);
+        }
+    }
+
+    fn tagged(seq: &'static str, qual: u8, umi: Option<&'static [u8]>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(0u16);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(0);
+        read.expect_len().return_const(4usize);
+        read.expect_cigar().return_once(|| CigarString(vec![M(4)]).into_view(0));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().return_const(qual);
+        read.expect_name().return_const(b"read".to_vec());
+        read.expect_qual().return_const(vec![qual; seq.len()]);
+        read.expect_tag().returning(move |_| umi.map(|x| x.to_vec()));
+        read
+    }
+
+    #[test]
+    fn umi_dedup() {
+        let mut counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 20), 0, 0).with_umi_dedup(*b"UB");
+        counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+
+        // Duplicates -> the read with the highest quality wins, reads without UMI are counted right away
+        let (first, second): (&[u8], &[u8]) = (b"AAA", b"CCC");
+        for (seq, qual, umi) in [
+            ("GGGG", 30, Some(first)),
+            ("TTTT", 40, Some(first)),
+            ("CCCC", 40, Some(first)),
+            ("ACGT", 30, Some(second)),
+            ("AAAA", 30, None),
+        ] {
+            counter.count(&tagged(seq, qual, umi));
+        }
+        assert_eq!(counter.counted(), &[A(); 4]);
+        assert_eq!(counter.mapped(), 1);
+
+        let mut flushed = Vec::new();
+        counter.flush(|x, _| flushed.push(x.to_vec()));
+        assert_eq!(flushed, vec![vec![0..4], vec![0..4]]);
+
+        let n = NucCounts::new;
+        assert_eq!(counter.counted(), &[n(2, 0, 0, 1), n(1, 1, 0, 1), n(1, 0, 1, 1), n(1, 0, 0, 2)]);
+        assert_eq!(counter.mapped(), 3);
+        assert_eq!(counter.umi_collapsed(), 2);
+
+        counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+        assert_eq!(counter.umi_collapsed(), 0);
+    }
+
+    fn multimapped(seq: &'static str, flags: u16, hits: Option<&'static [u8]>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(flags);
+        read.expect_name().return_const(b"read".to_vec());
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(0);
+        read.expect_len().return_const(4usize);
+        read.expect_cigar().return_once(|| CigarString(vec![M(4)]).into_view(0));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().return_const(30);
+        read.expect_tag().withf(|x| *x == b"NH").returning(move |_| hits.map(|x| x.to_vec()));
+        read
+    }
+
+    #[test]
+    fn multimap() {
+        let count = |multimap, reads: Vec<MockRead>| {
+            let mut counter = BaseNucCounter::new(4, ByQuality::new(0, MapQ255::Keep, 20), 0, 0)
+                .with_sources(1)
+                .with_overlaps_dedup()
+                .with_strand_depth()
+                .with_multimap(multimap);
+            counter.reset(Interval::new("chr1".into(), 0..4)).unwrap();
+            for read in reads {
+                counter.count(&read);
+            }
+            (counter.counted().to_vec(), counter.counted_by_source()[0].clone(), counter.depth().to_vec())
+        };
+        let reads = || {
+            vec![
+                multimapped("AAAA", 0, Some(b"2")),
+                multimapped("ACAC", 0, Some(b"4")),
+                multimapped("AAGG", 0, None),
+                // Overlapping mates of the same multi-mapped fragment
+                multimapped("TTTT", 3, Some(b"3")),
+                multimapped("TTTT", 3, Some(b"3")),
+            ]
+        };
+
+        // Default mode -> NH tag is ignored
+        let n = NucCounts::new;
+        let (counts, persample, depth) = count(Multimap::Full, reads());
+        let expected = [n(3, 0, 0, 1), n(2, 1, 0, 1), n(2, 0, 1, 1), n(1, 1, 1, 1)];
+        assert_eq!((counts.as_slice(), persample.as_slice()), (&expected[..], &expected[..]));
+
+        let (counts, persample, fdepth) = count(Multimap::Fractional, reads());
+        assert_eq!(counts, persample);
+        // Alignments are still counted as a whole
+        assert_eq!(fdepth, depth);
+
+        let natural: Vec<[f32; 4]> =
+            counts.iter().map(|x| [x.A, x.C, x.G, x.T].map(|cnt| Multimap::Fractional.natural(cnt))).collect();
+        let third = 1f32 / 3f32;
+        assert_eq!(
+            natural,
+            [[1.75, 0.0, 0.0, third], [1.5, 0.25, 0.0, third], [0.75, 0.0, 1.0, third], [0.5, 0.25, 1.0, third]]
+        );
+    }
+
     #[test]
     fn is_record_ok() {
         let contig = "".to_string();
@@ -439,7 +1877,7 @@ mod tests {
             let mut filter = MockReadsFilter::new();
             filter.expect_is_read_ok().once().return_const(isok);
             let mut dummy = BaseNucCounter::new(1, filter, 4, 0);
-            dummy.reset(Interval::new(contig.clone(), 0..1));
+            dummy.reset(Interval::new(contig.clone(), 0..1)).unwrap();
 
             let mut read = MockRead::new();
             read.expect_contig().return_const(ctg.clone());
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio::data_structures::interval_tree::IntervalTree;
+use bio_types::genome::{AbstractInterval, Position};
+
+// Excluded regions of all contigs. Built once and shared read-only by counters in all threads
+#[derive(Clone, Default)]
+pub struct ExcludedRegions {
+    index: HashMap<String, IntervalTree<Position, ()>>,
+}
+
+impl ExcludedRegions {
+    pub fn new(regions: Vec<impl AbstractInterval>) -> Self {
+        let mut index: HashMap<String, IntervalTree<Position, ()>> = HashMap::new();
+        for record in regions {
+            let range = record.range();
+            if range.start < range.end {
+                index.entry(record.contig().into()).or_default().insert(range, ());
+            }
+        }
+        Self { index }
+    }
+
+    // Excluded parts of the window in window coordinates, sorted & merged. Regions may overlap each other
+    pub fn window(&self, contig: &str, window: Range<Position>) -> Vec<Range<u32>> {
+        let tree = match self.index.get(contig) {
+            None => return vec![],
+            Some(x) => x,
+        };
+        let mut hits: Vec<Range<u32>> = tree
+            .find(window.clone())
+            .map(|x| {
+                let (start, end) = (x.interval().start.max(window.start), x.interval().end.min(window.end));
+                (start - window.start) as u32..(end - window.start) as u32
+            })
+            .collect();
+        hits.sort_by_key(|x| x.start);
+
+        let mut merged: Vec<Range<u32>> = Vec::with_capacity(hits.len());
+        for hit in hits {
+            match merged.last_mut() {
+                Some(last) if last.end >= hit.start => last.end = last.end.max(hit.end),
+                _ => merged.push(hit),
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use super::*;
+
+    #[test]
+    fn window() {
+        let regions = vec![
+            Interval::new("1".into(), 10..20),
+            Interval::new("1".into(), 15..25),
+            Interval::new("1".into(), 25..30),
+            Interval::new("1".into(), 50..60),
+            Interval::new("2".into(), 0..100),
+        ];
+        let dummy = ExcludedRegions::new(regions);
+
+        assert_eq!(dummy.window("1", 0..100), vec![10..30, 50..60]);
+        // Clipped to the window
+        assert_eq!(dummy.window("1", 20..55), vec![0..10, 30..35]);
+        assert_eq!(dummy.window("1", 30..50), vec![]);
+        assert_eq!(dummy.window("2", 10..20), vec![0..10]);
+        assert_eq!(dummy.window("3", 0..100), vec![]);
+    }
+}
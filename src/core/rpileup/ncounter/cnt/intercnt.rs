@@ -8,6 +8,7 @@ use crate::core::rpileup::ncounter::{InnerNucCounts, NucCounterResult};
 use crate::core::rpileup::ReadsCollider;
 use crate::core::strandutil::Stranded;
 use crate::core::workload::SiteWorkload;
+use crate::error::Error;
 
 use super::base::BaseNucCounter;
 
@@ -27,35 +28,63 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for Interv
     type ColliderResult = NucCounterResult<'a, ()>;
     type Workload = SiteWorkload;
 
-    fn reset(&mut self, info: Self::Workload) {
+    fn reset(&mut self, info: Self::Workload) -> Result<(), Error> {
         let (interval, ranges) = info.dissolve();
-        self.base.reset(interval);
+        self.base.reset(interval)?;
         self.ranges = ranges;
+        Ok(())
+    }
+
+    fn set_source(&mut self, source: usize) {
+        self.base.set_source(source);
     }
 
     fn collide(&mut self, read: &R) {
         self.base.count(read);
     }
 
-    fn finalize(&mut self) {}
+    fn finalize(&mut self) {
+        self.base.flush(|_, _| {});
+    }
+
+    fn mapped(&self) -> u32 {
+        self.base.mapped()
+    }
 
     fn result(&'a self) -> Self::ColliderResult {
         let contig = self.base.interval().contig();
-        let start = self.base.interval().range().start;
+        let (start, end) = (self.base.interval().range().start, self.base.interval().range().end);
 
         let cnts = self
             .ranges
             .iter()
             .map(|range| {
+                // Results are clipped exactly at the counted interval
+                debug_assert!(range.start >= start && range.end <= end);
                 let indx = (range.start - start) as usize..(range.end - start) as usize;
                 InnerNucCounts {
                     data: (),
                     range: range.clone(),
-                    cnts: Stranded::unknown(Some(&self.base.counted()[indx])),
+                    cnts: Stranded::unknown(Some(&self.base.counted()[indx.clone()])),
+                    samples: Stranded::unknown(
+                        self.base.counted_by_source().iter().map(|x| &x[indx.clone()]).collect(),
+                    ),
                     coverage: Stranded::unknown(self.base.mapped()),
+                    alnstats: Default::default(),
+                    depth: Stranded::unknown(self.base.depth().get(indx.clone())),
+                    qualities: Stranded::unknown(self.base.qualities().get(indx.clone())),
+                    fragments: Stranded::unknown(self.base.fragments().get(indx)),
                 }
             })
             .collect();
-        Self::ColliderResult { contig, cnts, mapped: Stranded::unknown(self.base.mapped()) }
+        Self::ColliderResult {
+            contig,
+            cnts,
+            mapped: Stranded::unknown(self.base.mapped()),
+            mapq_255_dropped: self.base.mapq_255_dropped(),
+            umi_collapsed: self.base.umi_collapsed(),
+            coverage_capped: self.base.coverage_capped(),
+            junctions: self.base.junctions(),
+        }
     }
 }
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use bio_types::genome::Position;
+
+// Distances to splice junctions are reported up to this value, sites without closer junctions get the cap
+pub const MAX_SPLICE_DIST: u32 = 1000;
+
+// Splice junctions observed in spliced reads: reference positions of the exonic bases flanking each N gap &
+// the number of their observations, sorted by position. Collected per window and merged per contig
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct SpliceJunctions {
+    flanks: Vec<(Position, u32)>,
+}
+
+impl SpliceJunctions {
+    pub fn new(observed: &BTreeMap<Position, u32>) -> Self {
+        Self { flanks: observed.iter().map(|(pos, cnt)| (*pos, *cnt)).collect() }
+    }
+
+    // Observations of the same flank are summed, i.e. reads spanning several windows are counted in each of them
+    pub fn merge<'a>(windows: impl IntoIterator<Item = &'a SpliceJunctions>) -> Self {
+        let mut flanks: Vec<(Position, u32)> = windows.into_iter().flat_map(|x| x.flanks.iter().copied()).collect();
+        flanks.sort_unstable_by_key(|x| x.0);
+
+        let mut merged: Vec<(Position, u32)> = Vec::with_capacity(flanks.len());
+        for (pos, cnt) in flanks {
+            match merged.last_mut() {
+                Some(last) if last.0 == pos => last.1 += cnt,
+                _ => merged.push((pos, cnt)),
+            }
+        }
+        Self { flanks: merged }
+    }
+
+    #[inline]
+    pub fn flanks(&self) -> &[(Position, u32)] {
+        &self.flanks
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.flanks.is_empty()
+    }
+
+    // Distance to the nearest junction flank, 0 for the flanking bases themselves
+    pub fn distance(&self, pos: Position) -> u32 {
+        let ind = self.flanks.partition_point(|x| x.0 < pos);
+        let after = self.flanks.get(ind).map(|x| x.0 - pos);
+        let before = ind.checked_sub(1).map(|x| pos - self.flanks[x].0);
+        let nearest = [before, after].into_iter().flatten().min();
+        nearest.map_or(MAX_SPLICE_DIST, |x| x.min(MAX_SPLICE_DIST as Position) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn junctions(flanks: &[(Position, u32)]) -> SpliceJunctions {
+        SpliceJunctions::new(&flanks.iter().copied().collect())
+    }
+
+    #[test]
+    fn merge() {
+        let first = junctions(&[(10, 1), (20, 2), (5000, 1)]);
+        let second = junctions(&[(3, 1), (20, 1)]);
+        let merged = SpliceJunctions::merge([&first, &second, &SpliceJunctions::default()]);
+        assert_eq!(merged.flanks(), [(3, 1), (10, 1), (20, 3), (5000, 1)]);
+        assert!(SpliceJunctions::merge([]).is_empty());
+    }
+
+    #[test]
+    fn distance() {
+        let dummy = junctions(&[(100, 1), (200, 4)]);
+        for (pos, expected) in [(100, 0), (103, 3), (90, 10), (150, 50), (197, 3), (210, 10), (0, 100)] {
+            assert_eq!(dummy.distance(pos), expected, "{}", pos);
+        }
+        // Capped
+        assert_eq!(dummy.distance(5000), MAX_SPLICE_DIST);
+        assert_eq!(SpliceJunctions::default().distance(10), MAX_SPLICE_DIST);
+    }
+}
@@ -1,9 +1,13 @@
-pub use base::BaseNucCounter;
+pub use base::{AlnEvents, BaseNucCounter, TracedBase};
+pub use excluded::ExcludedRegions;
 pub use intercnt::IntervalNucCounter;
-pub use roicnt::ROINucCounter;
+pub use junctions::{SpliceJunctions, MAX_SPLICE_DIST};
+pub use roicnt::{MinROIOverlap, ROINucCounter};
 pub use strandcnt::StrandedNucCounter;
 
 mod base;
+mod excluded;
 mod intercnt;
+mod junctions;
 mod roicnt;
 mod strandcnt;
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
 use bio::data_structures::interval_tree::IntervalTree;
 use bio_types::genome::AbstractInterval;
 
@@ -5,24 +8,151 @@ use itertools::{zip, Itertools};
 
 use crate::core::read::AlignedRead;
 use crate::core::rpileup::ncounter::filters::ReadsFilter;
-use crate::core::rpileup::ncounter::{InnerNucCounts, NucCounterResult};
+use crate::core::rpileup::ncounter::{AlnStats, InnerNucCounts, NucCounterResult, NucCounts, StrandDepth};
 use crate::core::rpileup::ReadsCollider;
 use crate::core::strandutil::Stranded;
 use crate::core::workload::{ROIWorkload, ROI};
+use crate::error::Error;
+
+use super::base::{aligned, AlnEvents, BaseNucCounter, TracedBase};
+
+// Min overlap between aligned blocks of a read & (post-masking) subintervals of a ROI to count the read for the ROI
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MinROIOverlap {
+    Bases(u32),
+    // Fraction of the total aligned length of the read
+    Fraction(f32),
+}
+
+impl MinROIOverlap {
+    #[inline]
+    pub fn passes(&self, overlap: u32, alnlen: u32) -> bool {
+        match self {
+            MinROIOverlap::Bases(min) => overlap >= *min,
+            MinROIOverlap::Fraction(min) => overlap as f32 >= min * alnlen as f32,
+        }
+    }
+}
+
+// Unique ROI ranges of the current window, in window coordinates
+#[derive(Clone, Default)]
+struct Slots {
+    index: IntervalTree<u32, usize>,
+    ranges: Vec<Range<u32>>,
+    subintervals: Vec<Vec<Range<u32>>>,
+    coverage: Vec<u32>,
+    alnstats: Vec<AlnStats>,
+    // Bases of reads that are counted for an overlapping ROI, but don't overlap this one enough
+    excluded: Vec<Vec<TracedBase>>,
+}
+
+impl Slots {
+    fn overlap(&self, slot: usize, aligned: &[Range<u32>]) -> u32 {
+        let mut overlap = 0;
+        for (block, sub) in aligned.iter().cartesian_product(&self.subintervals[slot]) {
+            overlap += block.end.min(sub.end).saturating_sub(block.start.max(sub.start));
+        }
+        overlap
+    }
+
+    // Ranges of ROIs the read doesn't overlap enough, except for the ones shared with ROIs it does overlap enough
+    fn skipped(&self, aligned: &[Range<u32>], alnlen: u32, min: MinROIOverlap) -> Vec<Range<u32>> {
+        let (mut included, mut excluded) = (Vec::new(), Vec::new());
+        for slot in aligned.iter().flat_map(|x| self.index.find(x)).map(|x| *x.data()).unique() {
+            let range = self.ranges[slot].clone();
+            match min.passes(self.overlap(slot, aligned), alnlen) {
+                true => included.push(range),
+                false => excluded.push(range),
+            }
+        }
+
+        let mut skipped = Vec::with_capacity(excluded.len());
+        for range in excluded {
+            let mut pieces = vec![range];
+            for x in &included {
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|p| [p.start..p.end.min(x.start), p.start.max(x.end)..p.end])
+                    .filter(|p| p.start < p.end)
+                    .collect();
+            }
+            skipped.extend(pieces);
+        }
+        skipped
+    }
+
+    fn cover(&mut self, matched: &[Range<u32>], events: &AlnEvents, min: Option<MinROIOverlap>) {
+        let slots: Vec<usize> = matched.iter().flat_map(|x| self.index.find(x)).map(|x| *x.data()).unique().collect();
+        for ind in slots {
+            let roi = self.ranges[ind].clone();
+            if min.map_or(false, |min| !min.passes(self.overlap(ind, &events.aligned), events.alnlen)) {
+                // Positions shared with other ROIs => the read was counted & must be reverted for this one
+                self.excluded[ind].extend(events.counted.iter().filter(|x| roi.contains(&x.pos)));
+                continue;
+            }
+            self.coverage[ind] += 1;
+
+            let overlaps = |x: &Range<u32>| x.start < roi.end && roi.start < x.end;
+            if events.softclipped.iter().any(overlaps) {
+                self.alnstats[ind].softclipped += 1;
+            }
+            if events.indels.iter().any(overlaps) {
+                self.alnstats[ind].indels += 1;
+            }
+        }
+    }
+}
 
-use super::base::BaseNucCounter;
+// Window counts of a ROI without bases of the excluded reads
+#[derive(Clone)]
+struct Corrected {
+    cnts: Vec<NucCounts>,
+    samples: Vec<Vec<NucCounts>>,
+    depth: Option<Vec<StrandDepth>>,
+}
 
 #[derive(Clone)]
 pub struct ROINucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
     base: BaseNucCounter<R, Filter>,
     rois: Vec<ROI>,
-    coverage: Vec<u32>,
-    index: IntervalTree<u32, usize>,
+    // ROIs with the same range (e.g. duplicated records) share the coverage & alignment stats
+    slots: Vec<usize>,
+    unique: Slots,
+    min_overlap: Option<MinROIOverlap>,
+    // Built on finalize for slots with excluded reads. Base qualities & fragments are not corrected
+    corrected: Vec<Option<Corrected>>,
 }
 
 impl<R: AlignedRead, Filter: ReadsFilter<R>> ROINucCounter<R, Filter> {
     pub fn new(base: BaseNucCounter<R, Filter>) -> Self {
-        Self { base, rois: vec![], coverage: vec![], index: Default::default() }
+        Self { base, rois: vec![], slots: vec![], unique: Default::default(), min_overlap: None, corrected: vec![] }
+    }
+
+    // Count reads only for ROIs they overlap by at least the given number of bases / fraction of their length
+    pub fn with_min_overlap(self, min: MinROIOverlap) -> Self {
+        Self { base: self.base.with_read_trace(), min_overlap: Some(min), ..self }
+    }
+
+    fn correct(&self, slot: usize) -> Corrected {
+        let range = &self.unique.ranges[slot];
+        let (start, end) = (range.start as usize, range.end as usize);
+        let mut corrected = Corrected {
+            cnts: self.base.counted()[start..end].to_vec(),
+            samples: self.base.counted_by_source().iter().map(|x| x[start..end].to_vec()).collect(),
+            depth: self.base.depth().get(start..end).map(|x| x.to_vec()),
+        };
+        for x in &self.unique.excluded[slot] {
+            let pos = (x.pos - range.start) as usize;
+            let counted = revert(&mut corrected.cnts[pos], x);
+            if let Some(sample) = corrected.samples.get_mut(x.source) {
+                revert(&mut sample[pos], x);
+            }
+            if let (true, Some(depth)) = (counted, corrected.depth.as_mut()) {
+                let depth = if x.reverse { &mut depth[pos].reverse } else { &mut depth[pos].forward };
+                *depth = if x.added { depth.saturating_sub(1) } else { *depth + 1 };
+            }
+        }
+        corrected
     }
 }
 
@@ -30,50 +160,224 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for ROINuc
     type ColliderResult = NucCounterResult<'a, &'a ROI>;
     type Workload = ROIWorkload;
 
-    fn reset(&mut self, info: Self::Workload) {
+    fn reset(&mut self, info: Self::Workload) -> Result<(), Error> {
         let (bin, rois) = info.dissolve();
-        self.base.reset(bin);
+        self.base.reset(bin)?;
         self.rois = rois;
 
-        // Coverage for each roi
-        self.coverage.clear();
-        self.coverage.resize(self.rois.len(), 0);
-
-        // Index to accurately count ROIs coverage
-        self.index = Default::default();
+        // Index to accurately count ROIs coverage, each unique range is indexed only once
+        let unique = &mut self.unique;
+        unique.index = Default::default();
+        unique.ranges.clear();
+        unique.subintervals.clear();
+        self.slots.clear();
+        let mut slots = HashMap::with_capacity(self.rois.len());
         let binstart = self.base.interval().range().start;
-        for (ind, roi) in self.rois.iter().enumerate() {
-            let (start, end) = (roi.range().start - binstart, roi.range().end - binstart);
-            self.index.insert(start as u32..end as u32, ind)
+        for roi in &self.rois {
+            // Completely masked ROIs are not covered by definition
+            let masked = roi.subintervals().is_empty();
+            let slot = *slots.entry((roi.range(), masked)).or_insert_with(|| {
+                let (start, end) = ((roi.range().start - binstart) as u32, (roi.range().end - binstart) as u32);
+                if !masked {
+                    unique.index.insert(start..end, unique.ranges.len());
+                }
+                let subintervals =
+                    roi.subintervals().iter().map(|x| (x.start - binstart) as u32..(x.end - binstart) as u32);
+                unique.subintervals.push(subintervals.collect());
+                unique.ranges.push(start..end);
+                unique.ranges.len() - 1
+            });
+            self.slots.push(slot);
         }
+
+        // Coverage for each unique range
+        let size = unique.ranges.len();
+        unique.coverage.clear();
+        unique.coverage.resize(size, 0);
+        unique.alnstats.clear();
+        unique.alnstats.resize(size, Default::default());
+        unique.excluded.clear();
+        unique.excluded.resize(size, vec![]);
+        self.corrected.clear();
+        Ok(())
+    }
+
+    fn set_source(&mut self, source: usize) {
+        self.base.set_source(source);
     }
 
     fn collide(&mut self, read: &R) {
-        let covered_rois = self.base.count(read).iter().flat_map(|x| self.index.find(x)).map(|x| x.data()).unique();
-        for ind in covered_rois {
-            self.coverage[*ind] += 1;
+        match self.min_overlap {
+            None => {
+                self.base.count(read);
+            }
+            Some(min) => {
+                let window = self.base.interval().range();
+                let (roipos, roisize) = (read.pos() - window.start as i64, (window.end - window.start) as i64);
+                let (aligned, alnlen) = aligned(&read.cigar(), roipos, roisize);
+                self.base.count_skipping(read, self.unique.skipped(&aligned, alnlen, min));
+            }
         }
+        self.unique.cover(self.base.matched(), self.base.events(), self.min_overlap);
+    }
+
+    fn finalize(&mut self) {
+        // Staged reads (if any) are counted only now
+        let (unique, min) = (&mut self.unique, self.min_overlap);
+        self.base.flush(|matched, events| unique.cover(matched, events, min));
+
+        self.corrected = (0..self.unique.ranges.len())
+            .map(|x| (!self.unique.excluded[x].is_empty()).then(|| self.correct(x)))
+            .collect();
     }
 
-    fn finalize(&mut self) {}
+    fn mapped(&self) -> u32 {
+        self.base.mapped()
+    }
 
     fn result(&'a self) -> Self::ColliderResult {
         let contig = self.base.interval().contig();
         let instart = self.base.interval().range().start as usize;
 
         let mut cnts = Vec::with_capacity(self.rois.len());
-        for (coverage, roi) in zip(&self.coverage, &self.rois) {
+        for (slot, roi) in zip(&self.slots, &self.rois) {
             debug_assert_eq!(roi.contig(), contig);
-            let (start, end) = (roi.range().start as usize, roi.range().end as usize);
+            let (coverage, alnstats) = (self.unique.coverage[*slot], self.unique.alnstats[*slot]);
+            // Flanks (if any) are counted along with the ROI
+            let (start, end) = (roi.flanked().start as usize - instart, roi.flanked().end as usize - instart);
 
-            let roicnts = &self.base.counted()[start - instart..end - instart];
+            let (roicnts, samples, depth): (_, Vec<_>, _) = match self.corrected.get(*slot).and_then(|x| x.as_ref()) {
+                Some(x) => {
+                    // Corrected counts cover the ROI itself, flanks are not supported with the min overlap
+                    debug_assert_eq!(roi.flanked(), roi.range());
+                    (&x.cnts[..], x.samples.iter().map(|x| &x[..]).collect(), x.depth.as_deref())
+                }
+                None => (
+                    &self.base.counted()[start..end],
+                    self.base.counted_by_source().iter().map(|x| &x[start..end]).collect(),
+                    self.base.depth().get(start..end),
+                ),
+            };
             cnts.push(InnerNucCounts {
                 data: roi,
-                range: roi.range().clone(),
+                range: roi.flanked(),
                 cnts: Stranded::unknown(Some(roicnts)),
-                coverage: Stranded::unknown(*coverage),
+                samples: Stranded::unknown(samples),
+                coverage: Stranded::unknown(coverage),
+                alnstats: Stranded::unknown(alnstats),
+                depth: Stranded::unknown(depth),
+                qualities: Stranded::unknown(self.base.qualities().get(start..end)),
+                fragments: Stranded::unknown(self.base.fragments().get(start..end)),
             });
         }
-        NucCounterResult { contig, cnts, mapped: Stranded::unknown(self.base.mapped()) }
+        NucCounterResult {
+            contig,
+            cnts,
+            mapped: Stranded::unknown(self.base.mapped()),
+            mapq_255_dropped: self.base.mapq_255_dropped(),
+            umi_collapsed: self.base.umi_collapsed(),
+            coverage_capped: self.base.coverage_capped(),
+            junctions: self.base.junctions(),
+        }
+    }
+}
+
+// Undo a traced change of the window counts. Saturating => mates with different decisions can't underflow counts
+#[inline]
+fn revert(cnts: &mut NucCounts, x: &TracedBase) -> bool {
+    let cnt = match x.base {
+        b'A' | b'a' => &mut cnts.A,
+        b'T' | b't' => &mut cnts.T,
+        b'G' | b'g' => &mut cnts.G,
+        b'C' | b'c' => &mut cnts.C,
+        _ => return false,
+    };
+    *cnt = if x.added { cnt.saturating_sub(x.weight) } else { *cnt + x.weight };
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+    use bio_types::strand::{ReqStrand, Strand};
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    use crate::core::read::MockRead;
+    use crate::core::rpileup::ncounter::filters::{ByQuality, MapQ255};
+
+    use super::*;
+
+    fn read(pos: i64, seq: &'static str) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(60);
+        read.expect_flags().return_const(0u16);
+        read.expect_contig().return_const("chr1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        // Cigar is inspected both by the ROI & base counters
+        read.expect_cigar().returning(move || CigarString(vec![Cigar::Match(seq.len() as u32)]).into_view(pos));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_base_qual().return_const(30);
+        read
+    }
+
+    fn count(min: MinROIOverlap, reads: &[MockRead]) -> Vec<(u32, Vec<NucCounts>, Vec<StrandDepth>)> {
+        let base = BaseNucCounter::new(30, ByQuality::new(0, MapQ255::Keep, 20), 0, 0).with_strand_depth();
+        let mut counter = ROINucCounter::new(base).with_min_overlap(min);
+        let rois = vec![
+            ROI::new("chr1".into(), 0..10, vec![0..10], "first".into(), Strand::Unknown),
+            ROI::new("chr1".into(), 8..20, vec![8..20], "second".into(), Strand::Unknown),
+        ];
+        counter.reset(ROIWorkload::new(Interval::new("chr1".into(), 0..30), rois)).unwrap();
+        for read in reads {
+            counter.collide(read);
+        }
+        counter.finalize();
+
+        let result = counter.result();
+        result
+            .cnts
+            .iter()
+            .map(|x| (x.coverage.unknown, x.cnts.unknown.unwrap().to_vec(), x.depth.unknown.unwrap().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn passes() {
+        assert!(MinROIOverlap::Bases(5).passes(5, 100) && !MinROIOverlap::Bases(5).passes(4, 4));
+        assert!(MinROIOverlap::Fraction(0.5).passes(50, 100) && !MinROIOverlap::Fraction(0.5).passes(49, 100));
+        assert!(MinROIOverlap::Fraction(1.0).passes(10, 10) && !MinROIOverlap::Fraction(1.0).passes(9, 10));
+    }
+
+    #[test]
+    fn min_overlap() {
+        let reads = [
+            // Counted only for the first ROI, shared positions are reverted for the second one
+            read(0, "AAAAAAAAAA"),
+            // Both ROIs
+            read(5, "CCCCCCCCCC"),
+            // Exactly at the threshold for the second ROI
+            read(15, "TTTTTTTTTT"),
+            // Below the threshold => ignored
+            read(16, "GGGGGGGG"),
+        ];
+        let result = count(MinROIOverlap::Bases(5), &reads);
+
+        let (n, d) = (NucCounts::new, StrandDepth::new);
+        let (coverage, cnts, depth) = &result[0];
+        assert_eq!(*coverage, 2);
+        assert_eq!(cnts, &[[n(1, 0, 0, 0); 5], [n(1, 1, 0, 0); 5]].concat());
+        assert_eq!(depth, &[[d(1, 0); 5], [d(2, 0); 5]].concat());
+
+        let (coverage, cnts, depth) = &result[1];
+        assert_eq!(*coverage, 2);
+        assert_eq!(cnts, &[[n(0, 1, 0, 0); 7], [n(0, 0, 0, 1); 5]].concat());
+        assert_eq!(depth, &[d(1, 0); 12]);
+
+        // Reads must be completely inside ROIs
+        let result = count(MinROIOverlap::Fraction(1.0), &reads);
+        assert_eq!(result[0], (1, vec![n(1, 0, 0, 0); 10], vec![d(1, 0); 10]));
+        assert_eq!(result[1], (0, vec![n(0, 0, 0, 0); 12], vec![d(0, 0); 12]));
     }
 }
@@ -2,10 +2,12 @@ use bio_types::strand::ReqStrand;
 use itertools::zip;
 
 use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::cnt::SpliceJunctions;
 use crate::core::rpileup::ncounter::NucCounterResult;
 use crate::core::rpileup::ReadsCollider;
 use crate::core::stranding::deduce::StrandDeducer;
 use crate::core::strandutil::Stranded;
+use crate::error::Error;
 
 #[derive(Clone)]
 pub struct StrandedNucCounter<Deductor, InnerNucCounter> {
@@ -34,9 +36,14 @@ where
     type ColliderResult = InnerNucCounter::ColliderResult;
     type Workload = InnerNucCounter::Workload;
 
-    fn reset(&mut self, info: Self::Workload) {
-        self.forward.reset(info.clone());
-        self.reverse.reset(info);
+    fn reset(&mut self, info: Self::Workload) -> Result<(), Error> {
+        self.forward.reset(info.clone())?;
+        self.reverse.reset(info)
+    }
+
+    fn set_source(&mut self, source: usize) {
+        self.forward.set_source(source);
+        self.reverse.set_source(source);
     }
 
     #[inline]
@@ -52,6 +59,10 @@ where
         self.reverse.finalize();
     }
 
+    fn mapped(&self) -> u32 {
+        self.forward.mapped() + self.reverse.mapped()
+    }
+
     fn result(&'a self) -> Self::ColliderResult {
         let (mut fwd, mut rev) = (self.forward.result(), self.reverse.result());
         debug_assert_eq!(fwd.cnts.len(), rev.cnts.len());
@@ -64,163 +75,24 @@ where
             debug_assert!(f.data == r.data);
 
             f.coverage = Stranded { forward: f.coverage.unknown, reverse: r.coverage.unknown, unknown: 0 };
+            f.alnstats =
+                Stranded { forward: f.alnstats.unknown, reverse: r.alnstats.unknown, unknown: Default::default() };
             f.cnts = Stranded { forward: f.cnts.unknown, reverse: r.cnts.unknown, unknown: None };
+            f.depth = Stranded { forward: f.depth.unknown, reverse: r.depth.unknown, unknown: None };
+            f.qualities = Stranded { forward: f.qualities.unknown, reverse: r.qualities.unknown, unknown: None };
+            f.fragments = Stranded { forward: f.fragments.unknown, reverse: r.fragments.unknown, unknown: None };
+            f.samples = Stranded {
+                forward: std::mem::take(&mut f.samples.unknown),
+                reverse: std::mem::take(&mut r.samples.unknown),
+                unknown: vec![],
+            };
         }
         fwd.mapped = Stranded { forward: fwd.mapped.unknown, reverse: rev.mapped.unknown, unknown: 0 };
+        fwd.mapq_255_dropped += rev.mapq_255_dropped;
+        fwd.umi_collapsed += rev.umi_collapsed;
+        fwd.coverage_capped += rev.coverage_capped;
+        // Junctions aren't stranded
+        fwd.junctions = fwd.junctions.zip(rev.junctions).map(|(f, r)| SpliceJunctions::merge([&f, &r]));
         fwd
     }
 }
-
-// #[cfg(test)]
-// mod tests {
-//     use mockall::Sequence;
-//
-//     use crate::core::counting::buffers::RawCounts;
-//     use crate::core::counting::nuccounter::MockNucCounter;
-//     use crate::core::read::MockRead;
-//     use crate::core::stranding::deduct::MockStrandDeductor;
-//
-//     use super::*;
-//     use itertools::Itertools;
-//
-//     fn counter() -> MockNucCounter<MockRead> {
-//         let mut counter = MockNucCounter::<MockRead>::new();
-//         counter.expect_rois().return_const(vec![Interval::new("".into(), 0..1), Interval::new("".into(), 0..2)]);
-//         counter.expect_interval().return_const(Interval::new("".into(), 0..5));
-//         counter
-//     }
-//
-//     #[test]
-//     fn mapped() {
-//         let mut forward = counter();
-//         forward.expect_mapped().return_const(3u32);
-//
-//         let mut reverse = counter();
-//         reverse.expect_mapped().return_const(123u32);
-//
-//         let dummy = StrandedNucCounter::new(forward, reverse, MockStrandDeductor::new());
-//         debug_assert_eq!(dummy.mapped(), 126);
-//     }
-//
-//     #[test]
-//     fn delegation() {
-//         let dummy = StrandedNucCounter::new(counter(), counter(), MockStrandDeductor::new());
-//
-//         let reference = counter();
-//         debug_assert_eq!(dummy.rois(), reference.rois());
-//         debug_assert_eq!(dummy.interval(), reference.interval());
-//     }
-//
-//     #[test]
-//     fn count() {
-//         // Forward
-//         let mut seq = Sequence::new();
-//
-//         let mut deductor = MockStrandDeductor::new();
-//         let mut forward = counter();
-//         let mut reverse = counter();
-//
-//         deductor.expect_deduce().once().return_const(ReqStrand::Forward).in_sequence(&mut seq);
-//         forward.expect_count().once().return_const(()).in_sequence(&mut seq);
-//
-//         deductor.expect_deduce().once().return_const(ReqStrand::Reverse).in_sequence(&mut seq);
-//         reverse.expect_count().once().return_const(()).in_sequence(&mut seq);
-//
-//         let mut dummy = StrandedNucCounter::new(forward, reverse, deductor);
-//
-//         let mut read = MockRead::new();
-//         dummy.count(&mut read);
-//         dummy.count(&mut read);
-//     }
-//
-//     #[test]
-//     fn reset() {
-//         let mut forward = counter();
-//         forward.expect_reset().once().return_const(());
-//
-//         let mut reverse = counter();
-//         reverse.expect_reset().once().return_const(());
-//
-//         let mut dummy = StrandedNucCounter::new(forward, reverse, MockStrandDeductor::new());
-//         dummy.sequenced.extend(&[NucCounts::A(123), NucCounts::C(1), NucCounts::G(1233)]);
-//
-//         dummy.reset(ROIWorkload::default());
-//         assert!(dummy.sequenced.is_empty());
-//     }
-//
-//     #[test]
-//     fn counted() {
-//         let mut forward = counter();
-//         forward.expect_counted().once().return_const(vec![NucCounts::A(12), NucCounts::G(3), NucCounts::T(2)]);
-//
-//         let mut reverse = counter();
-//         reverse.expect_counted().once().return_const(vec![NucCounts::A(3), NucCounts::G(7), NucCounts::G(1)]);
-//
-//         let mut dummy = StrandedNucCounter::new(forward, reverse, MockStrandDeductor::new());
-//         let counted = dummy.counted();
-//
-//         assert_eq!(counted, &[NucCounts::A(15), NucCounts::G(10), NucCounts { A: 0, C: 0, G: 1, T: 2 }]);
-//     }
-//
-//     const NUCLEOTIDES_1_1: &[NucCounts] = &[NucCounts::A(1), NucCounts::G(3)];
-//     const NUCLEOTIDES_1_2: &[NucCounts] = &[NucCounts::G(33), NucCounts::C(31)];
-//     const NUCLEOTIDES_2_1: &[NucCounts] = &[NucCounts::A(1), NucCounts::T(3), NucCounts::T(3)];
-//     const NUCLEOTIDES_2_2: &[NucCounts] = &[NucCounts::C(1), NucCounts::T(3), NucCounts::G(3)];
-//
-//     #[test]
-//     fn results() {
-//         // Leak it intentionally
-//         let interval1 = Box::leak(Box::new(Interval::new("3".into(), 0..124)));
-//         let interval2 = Box::leak(Box::new(Interval::new("4".into(), 10..124)));
-//
-//         let mut fwd = vec![
-//             CountingResult {
-//                 name: "1",
-//                 strand: Strand::Unknown,
-//                 roi: interval1,
-//                 cnts: RawCounts { nuc: NUCLEOTIDES_1_1, coverage: 5 },
-//             },
-//             CountingResult {
-//                 name: "2",
-//                 strand: Strand::Unknown,
-//                 roi: interval2,
-//                 cnts: RawCounts { nuc: NUCLEOTIDES_2_2, coverage: 5123 },
-//             },
-//         ];
-//         let mut forward = counter();
-//         forward.expect_results().once().return_const(fwd.clone());
-//
-//         let rev = vec![
-//             CountingResult {
-//                 name: "1",
-//                 strand: Strand::Forward,
-//                 roi: interval1,
-//                 cnts: RawCounts { nuc: NUCLEOTIDES_1_2, coverage: 55 },
-//             },
-//             CountingResult {
-//                 name: "2",
-//                 strand: Strand::Reverse,
-//                 roi: interval2,
-//                 cnts: RawCounts { nuc: NUCLEOTIDES_2_1, coverage: 15 },
-//             },
-//         ];
-//         let mut reverse = counter();
-//         reverse.expect_results().once().return_const(rev.clone());
-//
-//         let dummy = StrandedNucCounter::new(forward, reverse, MockStrandDeductor::new());
-//         let mut counted = dummy.results();
-//         counted.sort_by_key(|x| x.name);
-//
-//         fwd.extend(rev);
-//         let mut expected = fwd
-//             .into_iter()
-//             .zip(&[Strand::Forward, Strand::Forward, Strand::Reverse, Strand::Reverse])
-//             .map(|(mut x, strand)| {
-//                 x.strand = *strand;
-//                 x
-//             })
-//             .collect_vec();
-//         expected.sort_by_key(|x| x.name);
-//         assert_eq!(counted, expected);
-//     }
-// }
@@ -1,7 +1,7 @@
 use derive_getters::Getters;
 use derive_more::Constructor;
 
-use super::{AlignedRead, ReadsFilter};
+use super::{AlignedRead, Criterion, ReadsFilter};
 
 #[derive(Constructor, Getters, Copy, Clone)]
 pub struct ByFlags {
@@ -14,6 +14,11 @@ impl<R: AlignedRead> ReadsFilter<R> for ByFlags {
     fn is_read_ok(&self, record: &R) -> bool {
         ((record.flags() & self.include) == self.include) && ((record.flags() & self.exclude) == 0)
     }
+
+    #[inline]
+    fn rejected_by(&self, record: &R) -> Option<Criterion> {
+        (!self.is_read_ok(record)).then_some(Criterion::Flags)
+    }
 }
 
 #[cfg(test)]
@@ -31,6 +36,7 @@ mod tests {
         for (flag, result) in [(83u16, true), (91u16, true), (1107u16, false), (1, false), (4095, false)] {
             read.expect_flags().return_const(flag);
             assert_eq!(dummy.is_read_ok(&read), result);
+            assert_eq!(dummy.rejected_by(&read), (!result).then_some(Criterion::Flags));
             read.checkpoint()
         }
     }
@@ -1,25 +1,45 @@
 use derive_getters::Getters;
 use derive_more::Constructor;
 
-use super::{AlignedRead, ReadsFilter};
+use super::{AlignedRead, Criterion, ReadsFilter};
+
+// Policy for reads with mapq = 255 (mapping quality is not available), independent of the mapq threshold
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MapQ255 {
+    Keep,
+    Drop,
+}
 
 #[derive(Constructor, Getters, Copy, Clone)]
 pub struct ByQuality {
     mapq: u8,
-    no_mapq_255: bool, // 255 = mapping quality is not available
+    mapq_255: MapQ255,
     phread: u8,
 }
 
 impl<R: AlignedRead> ReadsFilter<R> for ByQuality {
     #[inline]
     fn is_read_ok(&self, record: &R) -> bool {
-        record.mapq() >= self.mapq && !(self.no_mapq_255 && record.mapq() == 255)
+        match record.mapq() {
+            255 => self.mapq_255 == MapQ255::Keep,
+            mapq => mapq >= self.mapq,
+        }
+    }
+
+    #[inline]
+    fn is_mapq_255_dropped(&self, record: &R) -> bool {
+        self.mapq_255 == MapQ255::Drop && record.mapq() == 255
     }
 
     #[inline]
     fn is_base_ok(&self, record: &R, base: usize) -> bool {
         record.base_qual(base) >= self.phread
     }
+
+    #[inline]
+    fn rejected_by(&self, record: &R) -> Option<Criterion> {
+        (!self.is_read_ok(record)).then_some(Criterion::MapQ)
+    }
 }
 
 #[cfg(test)]
@@ -34,31 +54,47 @@ mod tests {
     fn is_read_ok() {
         let mut read = MockRead::new();
 
-        let dummy = ByQuality::new(10, true, 0);
+        let dummy = ByQuality::new(10, MapQ255::Drop, 0);
         for mapq in [0, 9, 255] {
             read.expect_mapq().return_const(mapq);
             assert!(!ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
             read.checkpoint();
         }
-        let dummy = ByQuality::new(10, false, 0);
+        let dummy = ByQuality::new(10, MapQ255::Keep, 0);
         for mapq in [10, 30, 254, 255] {
             read.expect_mapq().return_const(mapq);
             assert!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
             read.checkpoint();
         }
 
-        let dummy = ByQuality::new(254, true, 0);
+        let dummy = ByQuality::new(254, MapQ255::Drop, 0);
         read.expect_mapq().return_const(255);
         assert!(!ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
-        let dummy = ByQuality::new(255, true, 0);
+        let dummy = ByQuality::new(255, MapQ255::Drop, 0);
         assert!(!ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
-        let dummy = ByQuality::new(255, false, 0);
+        let dummy = ByQuality::new(255, MapQ255::Keep, 0);
         assert!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
     }
 
+    #[test]
+    fn is_mapq_255_dropped() {
+        let mut read = MockRead::new();
+        for (mapq, policy, dropped) in [
+            (255, MapQ255::Drop, true),
+            (255, MapQ255::Keep, false),
+            (254, MapQ255::Drop, false),
+            (0, MapQ255::Drop, false),
+        ] {
+            read.expect_mapq().return_const(mapq);
+            let dummy = ByQuality::new(10, policy, 0);
+            assert_eq!(ReadsFilter::<MockRead>::is_mapq_255_dropped(&dummy, &read), dropped);
+            read.checkpoint();
+        }
+    }
+
     #[test]
     fn is_base_ok() {
-        let dummy = ByQuality::new(10, false, 25);
+        let dummy = ByQuality::new(10, MapQ255::Keep, 25);
 
         let mut read = MockRead::new();
         for phread in [0, 24] {
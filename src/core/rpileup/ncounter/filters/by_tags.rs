@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::{AlignedRead, Criterion, ReadsFilter};
+
+pub const READ_GROUP_TAG: [u8; 2] = *b"RG";
+
+// Reads matched & rejected by a single criterion, shared by all filter copies (i.e. worker threads)
+#[derive(Default, Debug)]
+pub struct TagCounts {
+    matched: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl TagCounts {
+    pub fn matched(&self) -> u64 {
+        self.matched.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+struct Whitelist {
+    tag: [u8; 2],
+    allowed: Arc<HashSet<Vec<u8>>>,
+    counts: Arc<TagCounts>,
+}
+
+impl Whitelist {
+    fn new(tag: [u8; 2], allowed: HashSet<Vec<u8>>) -> Self {
+        Self { tag, allowed: Arc::new(allowed), counts: Default::default() }
+    }
+
+    // Reads without the tag are rejected
+    #[inline]
+    fn matches<R: AlignedRead>(&self, record: &R) -> bool {
+        record.tag(&self.tag).map_or(false, |x| self.allowed.contains(&x))
+    }
+
+    #[inline]
+    fn is_ok<R: AlignedRead>(&self, record: &R) -> bool {
+        let ok = self.matches(record);
+        let counter = if ok { &self.counts.matched } else { &self.counts.rejected };
+        counter.fetch_add(1, Ordering::Relaxed);
+        ok
+    }
+}
+
+// Keep only reads from the given read groups (RG tag) and/or cell barcodes (CB or any other tag)
+#[derive(Clone, Default)]
+pub struct ByTags {
+    readgroups: Option<Whitelist>,
+    barcodes: Option<Whitelist>,
+}
+
+impl ByTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_read_groups(mut self, allowed: HashSet<Vec<u8>>) -> Self {
+        self.readgroups = Some(Whitelist::new(READ_GROUP_TAG, allowed));
+        self
+    }
+
+    pub fn with_barcodes(mut self, tag: [u8; 2], allowed: HashSet<Vec<u8>>) -> Self {
+        self.barcodes = Some(Whitelist::new(tag, allowed));
+        self
+    }
+
+    // Counters are available only for the active criteria
+    pub fn read_groups(&self) -> Option<Arc<TagCounts>> {
+        self.readgroups.as_ref().map(|x| x.counts.clone())
+    }
+
+    pub fn barcodes(&self) -> Option<Arc<TagCounts>> {
+        self.barcodes.as_ref().map(|x| x.counts.clone())
+    }
+}
+
+impl<R: AlignedRead> ReadsFilter<R> for ByTags {
+    #[inline]
+    fn is_read_ok(&self, record: &R) -> bool {
+        // Inactive criteria are skipped without touching the read, barcodes are checked only for matched read groups
+        self.readgroups.as_ref().map_or(true, |x| x.is_ok(record))
+            && self.barcodes.as_ref().map_or(true, |x| x.is_ok(record))
+    }
+
+    #[inline]
+    fn rejected_by(&self, record: &R) -> Option<Criterion> {
+        if self.readgroups.as_ref().map_or(false, |x| !x.matches(record)) {
+            Some(Criterion::ReadGroup)
+        } else if self.barcodes.as_ref().map_or(false, |x| !x.matches(record)) {
+            Some(Criterion::CellBarcode)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    fn allowed(items: &[&str]) -> HashSet<Vec<u8>> {
+        items.iter().map(|x| x.as_bytes().to_vec()).collect()
+    }
+
+    fn tagged(read: &mut MockRead, rg: Option<&'static str>, cb: Option<&'static str>) {
+        read.expect_tag().returning(move |tag| match tag {
+            b"RG" => rg.map(|x| x.as_bytes().to_vec()),
+            b"CB" => cb.map(|x| x.as_bytes().to_vec()),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn inactive() {
+        // Tags are never requested
+        let read = MockRead::new();
+        let dummy = ByTags::new();
+        assert!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
+        assert!(dummy.read_groups().is_none() && dummy.barcodes().is_none());
+    }
+
+    #[test]
+    fn is_read_ok() {
+        let dummy = ByTags::new().with_read_groups(allowed(&["S1", "S2"])).with_barcodes(*b"CB", allowed(&["AAAC-1"]));
+
+        let mut read = MockRead::new();
+        for (rg, cb, result) in [
+            (Some("S1"), Some("AAAC-1"), true),
+            (Some("S2"), Some("AAAC-1"), true),
+            (Some("S3"), Some("AAAC-1"), false),
+            (None, Some("AAAC-1"), false),
+            (Some("S1"), Some("AAAG-1"), false),
+            (Some("S2"), None, false),
+        ] {
+            tagged(&mut read, rg, cb);
+            assert_eq!(dummy.is_read_ok(&read), result, "{:?} {:?}", rg, cb);
+            read.checkpoint();
+        }
+
+        let (readgroups, barcodes) = (dummy.read_groups().unwrap(), dummy.barcodes().unwrap());
+        assert_eq!((readgroups.matched(), readgroups.rejected()), (4, 2));
+        // Reads from the rejected read groups are not checked for barcodes
+        assert_eq!((barcodes.matched(), barcodes.rejected()), (2, 2));
+
+        // Counters are shared by copies of the filter
+        let copy = dummy.clone();
+        tagged(&mut read, Some("S1"), Some("AAAC-1"));
+        assert!(copy.is_read_ok(&read));
+        assert_eq!((readgroups.matched(), barcodes.matched()), (5, 3));
+
+        // Stats lookups don't touch the counters
+        read.checkpoint();
+        tagged(&mut read, Some("S1"), Some("AAAG-1"));
+        assert_eq!(ReadsFilter::<MockRead>::rejected_by(&dummy, &read), Some(Criterion::CellBarcode));
+        assert_eq!((readgroups.matched(), barcodes.rejected()), (5, 2));
+    }
+}
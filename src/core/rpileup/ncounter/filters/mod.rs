@@ -2,14 +2,18 @@
 use mockall::{automock, predicate::*};
 
 pub use by_flags::ByFlags;
-pub use by_quality::ByQuality;
+pub use by_quality::{ByQuality, MapQ255};
+pub use by_tags::{ByTags, TagCounts};
 pub use sequential::Sequential;
+pub use tracked::{Criterion, FilterStats, ReadsDump, Tracked};
 
 use crate::core::read::AlignedRead;
 
 mod by_flags;
 mod by_quality;
+mod by_tags;
 mod sequential;
+mod tracked;
 
 #[cfg_attr(test, automock)]
 pub trait ReadsFilter<R: AlignedRead> {
@@ -19,4 +23,12 @@ pub trait ReadsFilter<R: AlignedRead> {
     fn is_base_ok(&self, _record: &R, _base: usize) -> bool {
         true
     }
+    // The read was rejected only because of the mapq = 255 policy
+    fn is_mapq_255_dropped(&self, _record: &R) -> bool {
+        false
+    }
+    // The first criterion rejecting the read (if any). Used only for the filtering stats => must not count anything
+    fn rejected_by(&self, _record: &R) -> Option<Criterion> {
+        None
+    }
 }
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use super::{AlignedRead, ReadsFilter};
+use super::{AlignedRead, Criterion, ReadsFilter};
 
 // TODO: it should have a static variant based on a macro of some kind
 // You create it for 1-12 arguments in a compile time AND a from declaration for them using tuples
@@ -55,4 +55,15 @@ where
     fn is_base_ok(&self, record: &R, base: usize) -> bool {
         self.first.is_base_ok(record, base) & self.second.is_base_ok(record, base)
     }
+
+    #[inline]
+    fn is_mapq_255_dropped(&self, record: &R) -> bool {
+        (self.first.is_mapq_255_dropped(record) && self.second.is_read_ok(record))
+            || (self.second.is_mapq_255_dropped(record) && self.first.is_read_ok(record))
+    }
+
+    #[inline]
+    fn rejected_by(&self, record: &R) -> Option<Criterion> {
+        self.first.rejected_by(record).or_else(|| self.second.rejected_by(record))
+    }
 }
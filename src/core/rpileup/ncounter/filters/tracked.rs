@@ -0,0 +1,250 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::Error;
+
+use super::{AlignedRead, ReadsFilter};
+
+// Filtering criteria in the order they are checked
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Criterion {
+    MapQ,
+    Flags,
+    ReadGroup,
+    CellBarcode,
+    Other,
+}
+
+impl Criterion {
+    pub const ALL: [Criterion; 5] =
+        [Criterion::MapQ, Criterion::Flags, Criterion::ReadGroup, Criterion::CellBarcode, Criterion::Other];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Criterion::MapQ => "mapq",
+            Criterion::Flags => "flags",
+            Criterion::ReadGroup => "read group",
+            Criterion::CellBarcode => "cell barcode",
+            Criterion::Other => "other",
+        }
+    }
+}
+
+type FinishDump = Box<dyn FnOnce() -> Result<(), Error> + Send>;
+
+// Accepted reads & reads rejected by each criterion, shared by all filter copies (i.e. worker threads).
+// Reads are checked (and counted) once for each overlapping genome bin
+#[derive(Default)]
+pub struct FilterStats {
+    accepted: AtomicU64,
+    rejected: [AtomicU64; 5],
+    // The first failure of the reads dump, reads are not dumped after it
+    dump_failed: AtomicBool,
+    dump_failure: Mutex<Option<Error>>,
+    // Finalizes the reads dump, if any
+    finish_dump: Mutex<Option<FinishDump>>,
+}
+
+impl FilterStats {
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.iter().map(|x| x.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn rejected_by(&self, criterion: Criterion) -> u64 {
+        self.rejected[criterion as usize].load(Ordering::Relaxed)
+    }
+
+    // Filters can't fail => dump failures are reported once the run is over and all dumped reads are saved
+    pub fn dumped(&self) -> Result<(), Error> {
+        let finished = self.finish_dump.lock().unwrap().take().map_or(Ok(()), |finish| finish());
+        match self.dump_failure.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => finished,
+        }
+    }
+
+    fn dump_failed(&self, err: Error) {
+        if !self.dump_failed.swap(true, Ordering::Relaxed) {
+            *self.dump_failure.lock().unwrap() = Some(err);
+        }
+    }
+}
+
+// Receives every read checked by the filter along with the verdict, e.g. to save them for debugging
+pub trait ReadsDump<R>: Send + Sync {
+    fn dump(&self, record: &R, accepted: bool) -> Result<(), Error>;
+    // Called once all reads are checked, e.g. to wait until dumped reads are saved
+    fn finish(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Counts accepted & rejected reads of the wrapped filter, each rejected read is attributed to the first failed criterion
+pub struct Tracked<R: AlignedRead, Filter: ReadsFilter<R>> {
+    filter: Filter,
+    stats: Arc<FilterStats>,
+    dump: Option<Arc<dyn ReadsDump<R>>>,
+    phantom: PhantomData<fn() -> R>,
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> Tracked<R, Filter> {
+    pub fn new(filter: Filter) -> Self {
+        Self { filter, stats: Default::default(), dump: None, phantom: Default::default() }
+    }
+
+    pub fn with_dump(mut self, dump: Arc<dyn ReadsDump<R>>) -> Self
+    where
+        R: 'static,
+    {
+        let finish = dump.clone();
+        *self.stats.finish_dump.lock().unwrap() = Some(Box::new(move || finish.finish()));
+        self.dump = Some(dump);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<FilterStats> {
+        self.stats.clone()
+    }
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R> + Clone> Clone for Tracked<R, Filter> {
+    fn clone(&self) -> Self {
+        Self { filter: self.filter.clone(), stats: self.stats.clone(), dump: self.dump.clone(), phantom: self.phantom }
+    }
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> ReadsFilter<R> for Tracked<R, Filter> {
+    #[inline]
+    fn is_read_ok(&self, record: &R) -> bool {
+        let ok = self.filter.is_read_ok(record);
+        if ok {
+            self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let criterion = self.filter.rejected_by(record).unwrap_or(Criterion::Other);
+            self.stats.rejected[criterion as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(dump) = self.dump.as_ref().filter(|_| !self.stats.dump_failed.load(Ordering::Relaxed)) {
+            if let Err(err) = dump.dump(record, ok) {
+                self.stats.dump_failed(err);
+            }
+        }
+        ok
+    }
+
+    #[inline]
+    fn is_base_ok(&self, record: &R, base: usize) -> bool {
+        self.filter.is_base_ok(record, base)
+    }
+
+    #[inline]
+    fn is_mapq_255_dropped(&self, record: &R) -> bool {
+        self.filter.is_mapq_255_dropped(record)
+    }
+
+    #[inline]
+    fn rejected_by(&self, record: &R) -> Option<Criterion> {
+        self.filter.rejected_by(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::core::read::MockRead;
+    use crate::core::rpileup::ncounter::filters::{ByFlags, ByQuality, MapQ255, Sequential};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Dumped(Mutex<Vec<(u8, bool)>>, AtomicBool);
+
+    impl ReadsDump<MockRead> for Dumped {
+        fn dump(&self, record: &MockRead, accepted: bool) -> Result<(), Error> {
+            self.0.lock().unwrap().push((record.mapq(), accepted));
+            Ok(())
+        }
+
+        fn finish(&self) -> Result<(), Error> {
+            self.1.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    // Fails on the first read
+    #[derive(Default)]
+    struct Failing(AtomicU64);
+
+    impl ReadsDump<MockRead> for Failing {
+        fn dump(&self, _: &MockRead, _: bool) -> Result<(), Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(Error::hts("dump.accepted.bam", "failed to write the record"))
+        }
+    }
+
+    #[test]
+    fn is_read_ok() {
+        let dumped = Arc::new(Dumped::default());
+        let dummy = Tracked::new(Sequential::new(ByQuality::new(10, MapQ255::Drop, 0), ByFlags::new(0, 4)))
+            .with_dump(dumped.clone());
+        // Counters & dumps are shared by copies of the filter
+        let copy = dummy.clone();
+
+        let mut read = MockRead::new();
+        for (filter, mapq, flags, result) in [
+            (&dummy, 20, 0, true),
+            (&copy, 30, 0, true),
+            (&dummy, 5, 0, false),
+            (&copy, 255, 0, false),
+            (&dummy, 20, 4, false),
+            // Attributed to the first failed criterion
+            (&copy, 0, 4, false),
+        ] {
+            read.expect_mapq().return_const(mapq);
+            read.expect_flags().return_const(flags);
+            assert_eq!(filter.is_read_ok(&read), result, "{} {}", mapq, flags);
+            read.checkpoint();
+        }
+
+        let stats = dummy.stats();
+        assert_eq!((stats.accepted(), stats.rejected()), (2, 4));
+        let rejected: Vec<u64> = Criterion::ALL.iter().map(|x| stats.rejected_by(*x)).collect();
+        assert_eq!(rejected, [3, 1, 0, 0, 0]);
+        assert_eq!(
+            *dumped.0.lock().unwrap(),
+            [(20, true), (30, true), (5, false), (255, false), (20, false), (0, false)]
+        );
+        // Finished only once
+        assert!(!dumped.1.load(Ordering::Relaxed));
+        assert!(stats.dumped().is_ok());
+        assert!(dumped.1.swap(false, Ordering::Relaxed));
+        assert!(stats.dumped().is_ok());
+        assert!(!dumped.1.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn dump_failure() {
+        let failing = Arc::new(Failing::default());
+        let dummy = Tracked::new(ByQuality::new(10, MapQ255::Drop, 0)).with_dump(failing.clone());
+        let copy = dummy.clone();
+
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(20);
+        read.expect_flags().return_const(0u16);
+        // Verdicts are not affected, reads are not dumped after the failure
+        for filter in [&dummy, &copy, &dummy] {
+            assert!(filter.is_read_ok(&read));
+        }
+        assert_eq!(failing.0.load(Ordering::Relaxed), 1);
+
+        let stats = dummy.stats();
+        assert_eq!(stats.accepted(), 3);
+        assert_eq!(stats.dumped().err().unwrap().to_string(), "dump.accepted.bam: failed to write the record");
+        assert!(stats.dumped().is_ok());
+    }
+}
@@ -2,8 +2,9 @@ use std::ops::Range;
 
 use bio_types::genome::Position;
 
-pub use crate::core::dna::NucCounts;
+pub use crate::core::dna::{AltFragments, BaseQualities, NucCounts, StrandDepth};
 
+use crate::core::rpileup::ncounter::cnt::SpliceJunctions;
 use crate::core::strandutil::Stranded;
 
 pub mod cnt;
@@ -14,11 +15,35 @@ pub struct InnerNucCounts<'a, Data> {
     pub data: Data,
     pub range: Range<Position>,
     pub cnts: Stranded<Option<&'a [NucCounts]>>,
+    // Counts for each source separately (if requested)
+    pub samples: Stranded<Vec<&'a [NucCounts]>>,
     pub coverage: Stranded<u32>,
+    pub alnstats: Stranded<AlnStats>,
+    // Forward / reverse alignments supporting each position (if requested)
+    pub depth: Stranded<Option<&'a [StrandDepth]>>,
+    // Base qualities of each sequenced nucleotide (if requested)
+    pub qualities: Stranded<Option<&'a [BaseQualities]>>,
+    // Fragments with non-assembly nucleotides at each position (if requested)
+    pub fragments: Stranded<Option<&'a [AltFragments]>>,
+}
+
+// Number of covering reads with soft-clipped bases or indels inside the region
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct AlnStats {
+    pub softclipped: u32,
+    pub indels: u32,
 }
 
 pub struct NucCounterResult<'a, Data> {
     pub contig: &'a str,
     pub mapped: Stranded<u32>,
+    // Reads rejected only due to the mapq = 255 policy
+    pub mapq_255_dropped: u32,
+    // Reads collapsed as UMI duplicates
+    pub umi_collapsed: u32,
+    // Reads skipped due to the coverage cap
+    pub coverage_capped: u32,
+    // Splice junctions observed in the window (if requested)
+    pub junctions: Option<SpliceJunctions>,
     pub cnts: Vec<InnerNucCounts<'a, Data>>,
 }
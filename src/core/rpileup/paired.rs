@@ -0,0 +1,181 @@
+use std::ops::Range;
+
+use bio_types::genome::{AbstractInterval, Position};
+
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::{endpos, ReadsCollider};
+use crate::core::workload::PairedWorkload;
+use crate::error::Error;
+
+// Collider of a single window inside the paired one
+#[derive(Clone)]
+struct Lane<Collider> {
+    collider: Collider,
+    range: Range<Position>,
+    // Sources are passed to the collider along with their first overlapping read
+    source: Option<usize>,
+    // At least one read was passed to the collider
+    fed: bool,
+}
+
+// Colliders for windows of the same kind, allocated on demand from the prototype
+#[derive(Clone)]
+struct Lanes<Collider> {
+    prototype: Collider,
+    lanes: Vec<Lane<Collider>>,
+    active: usize,
+    // Finalize & report windows without reads as well
+    empty: bool,
+}
+
+impl<Collider: Clone> Lanes<Collider> {
+    fn new(prototype: Collider) -> Self {
+        Self { prototype, lanes: Vec::new(), active: 0, empty: false }
+    }
+
+    fn reset<Workload: AbstractInterval>(
+        &mut self,
+        windows: Vec<Workload>,
+        mut reset: impl FnMut(&mut Collider, Workload) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.active = windows.len();
+        while self.lanes.len() < self.active {
+            let collider = self.prototype.clone();
+            self.lanes.push(Lane { collider, range: 0..0, source: None, fed: false });
+        }
+        for (lane, window) in self.lanes.iter_mut().zip(windows) {
+            lane.range = window.range();
+            lane.source = None;
+            lane.fed = false;
+            reset(&mut lane.collider, window)?;
+        }
+        Ok(())
+    }
+
+    fn active(&mut self) -> &mut [Lane<Collider>] {
+        &mut self.lanes[..self.active]
+    }
+}
+
+// Reads of the joined window are passed to the colliders of all windows they overlap, exactly as if they were
+// fetched for each window separately => results are the same as for the separate runs
+#[derive(Clone)]
+pub struct PairedCollider<First, Second> {
+    first: Lanes<First>,
+    second: Lanes<Second>,
+    source: usize,
+    // Reads counted by at least one collider
+    mapped: u32,
+}
+
+pub struct PairedCounts<First, Second> {
+    // Results for each window in the workload order, None => no reads in the window
+    pub first: Vec<Option<First>>,
+    pub second: Vec<Option<Second>>,
+    pub mapped: u32,
+}
+
+impl<First: Clone, Second: Clone> PairedCollider<First, Second> {
+    pub fn new(first: First, second: Second) -> Self {
+        Self { first: Lanes::new(first), second: Lanes::new(second), source: 0, mapped: 0 }
+    }
+
+    // Report windows without reads, same as HTSPileupEngine::with_empty_windows
+    pub fn with_empty_first(mut self) -> Self {
+        self.first.empty = true;
+        self
+    }
+
+    pub fn with_empty_second(mut self) -> Self {
+        self.second.empty = true;
+        self
+    }
+}
+
+// Passes the read to the overlapping lanes => returns true if any of them counted it
+fn collide<'a, R: AlignedRead, Collider: ReadsCollider<'a, R>>(
+    lanes: &mut [Lane<Collider>],
+    read: &R,
+    span: &Range<Position>,
+    source: usize,
+) -> bool {
+    let mut counted = false;
+    for lane in lanes.iter_mut().filter(|x| x.range.start < span.end && span.start < x.range.end) {
+        if lane.source != Some(source) {
+            lane.collider.set_source(source);
+            lane.source = Some(source);
+        }
+        let before = lane.collider.mapped();
+        lane.collider.collide(read);
+        lane.fed = true;
+        counted |= lane.collider.mapped() > before;
+    }
+    counted
+}
+
+// Finalizes lanes with results => returns the max number of reads counted on the finalization (e.g. UMI groups)
+fn finalize<'a, R: AlignedRead, Collider: ReadsCollider<'a, R>>(lanes: &mut [Lane<Collider>], empty: bool) -> u32 {
+    let mut flushed = 0;
+    for lane in lanes.iter_mut().filter(|x| x.fed || empty) {
+        let before = lane.collider.mapped();
+        lane.collider.finalize();
+        flushed = flushed.max(lane.collider.mapped() - before);
+    }
+    flushed
+}
+
+impl<'a, R, First, Second> ReadsCollider<'a, R> for PairedCollider<First, Second>
+where
+    R: AlignedRead,
+    First: ReadsCollider<'a, R> + Clone,
+    Second: ReadsCollider<'a, R> + Clone,
+{
+    type ColliderResult = PairedCounts<First::ColliderResult, Second::ColliderResult>;
+    type Workload = PairedWorkload<First::Workload, Second::Workload>;
+
+    fn reset(&mut self, info: Self::Workload) -> Result<(), Error> {
+        let (_, first, second) = info.dissolve();
+        self.first.reset(first.into_iter().map(|x| x.1).collect(), |collider, window| collider.reset(window))?;
+        self.second.reset(second.into_iter().map(|x| x.1).collect(), |collider, window| collider.reset(window))?;
+        self.mapped = 0;
+        Ok(())
+    }
+
+    fn set_source(&mut self, source: usize) {
+        // Lanes see the source only if they overlap at least one of its reads, see collide
+        self.source = source;
+    }
+
+    fn collide(&mut self, read: &R) {
+        let span = read.pos().max(0) as Position..endpos(read).max(0) as Position;
+        let first = collide(self.first.active(), read, &span, self.source);
+        let second = collide(self.second.active(), read, &span, self.source);
+        if first || second {
+            self.mapped += 1;
+        }
+    }
+
+    fn finalize(&mut self) {
+        let first = finalize(self.first.active(), self.first.empty);
+        let second = finalize(self.second.active(), self.second.empty);
+        self.mapped += first.max(second);
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        PairedCounts {
+            first: self.first.lanes[..self.first.active]
+                .iter()
+                .map(|x| (x.fed || self.first.empty).then(|| x.collider.result()))
+                .collect(),
+            second: self.second.lanes[..self.second.active]
+                .iter()
+                .map(|x| (x.fed || self.second.empty).then(|| x.collider.result()))
+                .collect(),
+            mapped: self.mapped,
+        }
+    }
+
+    fn mapped(&self) -> u32 {
+        self.mapped
+    }
+}
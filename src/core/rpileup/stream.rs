@@ -0,0 +1,246 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bio_types::genome::{AbstractInterval, Interval, Position};
+use itertools::Itertools;
+use rust_htslib::bam::{Read, Reader, Record};
+
+use crate::core::io::hts;
+use crate::core::rpileup::hts::HTSThreadPool;
+use crate::core::rpileup::{endpos, ReadsCollider, ReadsCollidingEngine};
+use crate::error::Error;
+
+// Single coordinate-sorted input read sequentially, reads are buffered while they might overlap upcoming windows
+struct Streamed {
+    reader: Reader,
+    path: PathBuf,
+    tids: HashMap<String, i32>,
+    // Current contig & window start
+    tid: i32,
+    start: i64,
+    // (end, read) for reads of the current contig in the input order
+    buffer: VecDeque<(i64, Record)>,
+    // The first read past the current window
+    pending: Option<Record>,
+    // Coordinate of the last read => sorting is verified on the fly
+    last: (i32, i64),
+    exhausted: bool,
+}
+
+impl Streamed {
+    fn open(path: &Path, reference: Option<&Path>) -> Result<Self, Error> {
+        let reader = if path == Path::new(hts::STDIN) { Reader::from_stdin() } else { Reader::from_path(path) };
+        let mut reader = reader.map_err(|x| Error::hts(path, x.to_string()))?;
+        if hts::is_cram(&reader) {
+            let reference = hts::cram_reference(path, reference)?;
+            reader.set_reference(reference).map_err(|x| hts::reference_error(path, reference, x))?;
+        }
+
+        let header = reader.header();
+        let tids = (0..header.target_count())
+            .map(|tid| (String::from_utf8_lossy(header.tid2name(tid)).into_owned(), tid as i32))
+            .collect();
+        Ok(Self {
+            reader,
+            path: path.to_owned(),
+            tids,
+            tid: -1,
+            start: 0,
+            buffer: VecDeque::new(),
+            pending: None,
+            last: (-1, -1),
+            exhausted: false,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Record>, Error> {
+        if let Some(record) = self.pending.take() {
+            return Ok(Some(record));
+        }
+        if self.exhausted {
+            return Ok(None);
+        }
+        let mut record = Record::new();
+        match self.reader.read(&mut record) {
+            None => {
+                self.exhausted = true;
+                Ok(None)
+            }
+            Some(Err(err)) => {
+                Err(Error::hts(&self.path, format!("failed to read the input (truncated file?): {}", err)))
+            }
+            // Unplaced reads are always the last ones in sorted files
+            Some(Ok(())) if record.tid() < 0 => {
+                self.exhausted = true;
+                Ok(None)
+            }
+            Some(Ok(())) => {
+                let coordinate = (record.tid(), record.pos());
+                if coordinate < self.last {
+                    return Err(Error::hts(
+                        &self.path,
+                        format!(
+                            "input must be sorted by coordinates (samtools sort) to be streamed, unsorted read: {}",
+                            String::from_utf8_lossy(record.qname())
+                        ),
+                    ));
+                }
+                self.last = coordinate;
+                Ok(Some(record))
+            }
+        }
+    }
+
+    // Read the stream up to the window end. Returns false if there are no reads overlapping the window
+    fn advance(&mut self, contig: &str, range: &Range<Position>) -> Result<bool, Error> {
+        let tid = match self.tids.get(contig) {
+            Some(tid) => *tid,
+            // No such contig in the file
+            None => return Ok(false),
+        };
+        let (start, end) = (range.start as i64, range.end as i64);
+        if tid != self.tid {
+            assert!(
+                tid > self.tid,
+                "Reads for {} were already skipped in {}: contigs must be processed in the header order",
+                contig,
+                self.path.display()
+            );
+            self.buffer.clear();
+            (self.tid, self.start) = (tid, start);
+        }
+        assert!(start >= self.start, "Streamed windows must be processed in the coordinate order");
+        self.start = start;
+        self.buffer.retain(|(end, _)| *end > start);
+
+        while let Some(record) = self.next()? {
+            match record.tid().cmp(&tid) {
+                // Contig is not processed at all
+                Ordering::Less => continue,
+                Ordering::Greater => {
+                    self.pending = Some(record);
+                    break;
+                }
+                Ordering::Equal if record.pos() >= end => {
+                    self.pending = Some(record);
+                    break;
+                }
+                Ordering::Equal => {
+                    // Reads ending before the window can't overlap the following windows either
+                    let recend = endpos(&record);
+                    if recend > start {
+                        self.buffer.push_back((recend, record));
+                    }
+                }
+            }
+        }
+        Ok(self.overlapping(range).next().is_some())
+    }
+
+    fn overlapping<'a>(&'a self, range: &Range<Position>) -> impl Iterator<Item = &'a Record> {
+        let (start, end) = (range.start as i64, range.end as i64);
+        self.buffer.iter().filter(move |(recend, record)| *recend > start && record.pos() < end).map(|(_, x)| x)
+    }
+}
+
+// Sequential access to coordinate-sorted BAM/SAM files (or stdin) without an index.
+// Reads are consumed only once => windows must be requested one by one in the input order
+pub struct HTSStream {
+    inputs: Vec<Streamed>,
+    // Must be dropped after the readers
+    tpool: Option<Arc<HTSThreadPool>>,
+}
+
+impl HTSStream {
+    // Reference => assembly to decode CRAM inputs, it's ignored for other formats
+    pub fn open(htsfiles: &[PathBuf], reference: Option<&Path>) -> Result<Self, Error> {
+        if htsfiles.iter().filter(|x| x.as_path() == Path::new(hts::STDIN)).count() > 1 {
+            return Err(Error::Input("Standard input can be read only once".into()));
+        }
+        let inputs = htsfiles.iter().map(|x| Streamed::open(x, reference)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { inputs, tpool: None })
+    }
+
+    // Decompress BGZF blocks of all inputs in the shared htslib thread pool
+    pub fn with_thread_pool(mut self, tpool: Arc<HTSThreadPool>) -> Self {
+        for input in &self.inputs {
+            tpool.attach(&input.reader);
+        }
+        self.tpool = Some(tpool);
+        self
+    }
+
+    pub fn contigs(&self) -> Result<Vec<Interval>, Error> {
+        hts::header_contigs(self.inputs.iter().map(|x| (x.path.as_path(), x.reader.header())))
+    }
+
+    // Contigs in the header order of the first input, i.e. the order of reads
+    pub fn order(&self) -> Vec<String> {
+        self.inputs
+            .first()
+            .map(|x| x.tids.iter().sorted_by_key(|(_, tid)| **tid).map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+pub struct HTSStreamEngine<Collider> {
+    collider: Collider,
+    // Shared by all copies of the engine
+    stream: Arc<Mutex<HTSStream>>,
+    // Run the collider even if there are no reads in the window
+    empty: bool,
+    success: bool,
+}
+
+impl<Collider: for<'a> ReadsCollider<'a, Record>> HTSStreamEngine<Collider> {
+    pub fn new(stream: HTSStream, collider: Collider) -> Self {
+        Self { collider, stream: Arc::new(Mutex::new(stream)), empty: false, success: false }
+    }
+
+    pub fn with_empty_windows(mut self) -> Self {
+        self.empty = true;
+        self
+    }
+}
+
+impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, Collider> for HTSStreamEngine<Collider> {
+    fn run(&mut self, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) -> Result<(), Error> {
+        self.success = false;
+        let (contig, range) = (cwork.contig().to_owned(), cwork.range());
+        let mut stream = self.stream.lock().unwrap();
+        let available = stream.inputs.iter_mut().map(|x| x.advance(&contig, &range)).collect::<Result<Vec<_>, _>>()?;
+
+        // Nothing to do
+        if !available.contains(&true) && !self.empty {
+            return Ok(());
+        }
+
+        // Something to do, trigger the reset -> collide -> finalize
+        self.collider.reset(cwork)?;
+        for (ind, input) in stream.inputs.iter().enumerate().filter(|(ind, _)| available[*ind]) {
+            self.collider.set_source(ind);
+            for record in input.overlapping(&range) {
+                self.collider.collide(record);
+            }
+        }
+        self.collider.finalize();
+        self.success = true;
+        Ok(())
+    }
+
+    fn result(&self) -> Option<<Collider as ReadsCollider<'_, Record>>::ColliderResult> {
+        match self.success {
+            true => Some(self.collider.result()),
+            false => None,
+        }
+    }
+}
+
+impl<Collider: for<'a> ReadsCollider<'a, Record> + Clone> Clone for HTSStreamEngine<Collider> {
+    fn clone(&self) -> Self {
+        Self { collider: self.collider.clone(), stream: self.stream.clone(), empty: self.empty, success: false }
+    }
+}
@@ -1,75 +1,148 @@
+use std::marker::PhantomData;
+
 use rust_htslib::bam::Record;
 
-use crate::core::hooks::stats::EditingStat;
-use crate::core::hooks::HooksEngine;
 use crate::core::mismatches::{Batch, Builder, MismatchesVec};
 use crate::core::rpileup::hts::HTSPileupEngine;
-
+use crate::core::rpileup::paired::PairedCollider;
 use crate::core::rpileup::{ReadsCollider, ReadsCollidingEngine};
 use crate::core::stranding::predict::StrandingEngine;
+use crate::error::Error;
 
 pub trait Runner<'runner, T: MismatchesVec> {
     type Workload;
 
-    fn run(&'runner mut self, workload: Self::Workload) -> Option<Batch<T>>;
-    fn stats(self) -> Vec<Box<dyn EditingStat<T>>>;
+    fn run(&'runner mut self, workload: Self::Workload) -> Result<Option<Batch<T>>, Error>;
 }
 
+// Hooks are not a part of the runner: they are applied to batches in the workload order, see pipeline::stream.
+// Reads are fetched from indexed files by default, streamed inputs are handled by a different engine
 #[derive(Clone)]
-pub struct REATRunner<NCounter, MismatchesBuilder, Strander, Hook>
+pub struct REATRunner<NCounter, MismatchesBuilder, Strander, Pileuper = HTSPileupEngine<NCounter>>
 where
     for<'a> NCounter: ReadsCollider<'a, Record>,
+    Pileuper: ReadsCollidingEngine<Record, NCounter>,
 {
-    pileuper: HTSPileupEngine<NCounter>,
+    pileuper: Pileuper,
     mmbuilder: MismatchesBuilder,
     strander: Strander,
-    hook: Hook,
+    phantom: PhantomData<fn() -> NCounter>,
 }
 
-impl<NCounter, MismatchesBuilder, Strander, Hook> REATRunner<NCounter, MismatchesBuilder, Strander, Hook>
+impl<NCounter, MismatchesBuilder, Strander, Pileuper> REATRunner<NCounter, MismatchesBuilder, Strander, Pileuper>
 where
     for<'a> NCounter: ReadsCollider<'a, Record>,
+    Pileuper: ReadsCollidingEngine<Record, NCounter>,
 {
-    pub fn new(
-        mmbuilder: MismatchesBuilder,
-        strander: Strander,
-        pileuper: HTSPileupEngine<NCounter>,
-        hook: Hook,
-    ) -> Self {
-        Self { pileuper, mmbuilder, strander, hook }
+    pub fn new(mmbuilder: MismatchesBuilder, strander: Strander, pileuper: Pileuper) -> Self {
+        Self { pileuper, mmbuilder, strander, phantom: Default::default() }
     }
 }
 
-impl<'runner, NCounter, MBuilder, Strander, Hook> Runner<'runner, MBuilder::Out>
-    for REATRunner<NCounter, MBuilder, Strander, Hook>
+impl<'runner, NCounter, MBuilder, Strander, Pileuper> Runner<'runner, MBuilder::Out>
+    for REATRunner<NCounter, MBuilder, Strander, Pileuper>
 where
     for<'a> NCounter: ReadsCollider<'a, Record>,
+    Pileuper: ReadsCollidingEngine<Record, NCounter>,
     MBuilder: Builder<'runner, SourceCounts = <NCounter as ReadsCollider<'runner, Record>>::ColliderResult>,
     Strander: StrandingEngine<MBuilder::Out>,
-    Hook: HooksEngine<MBuilder::Out> + Clone,
 {
     type Workload = <NCounter as ReadsCollider<'runner, Record>>::Workload;
 
-    fn run(&'runner mut self, workload: Self::Workload) -> Option<Batch<MBuilder::Out>> {
-        self.pileuper.run(workload);
+    fn run(&'runner mut self, workload: Self::Workload) -> Result<Option<Batch<MBuilder::Out>>, Error> {
+        self.pileuper.run(workload)?;
 
         let ncounts = match self.pileuper.result() {
             Some(x) => x,
-            None => return None,
+            None => return Ok(None),
         };
 
-        let mut batch = self.mmbuilder.build(ncounts);
+        Ok(Some(process(&mut self.mmbuilder, &self.strander, ncounts)?))
+    }
+}
+
+fn process<'a, MBuilder: Builder<'a>, Strander: StrandingEngine<MBuilder::Out>>(
+    mmbuilder: &mut MBuilder,
+    strander: &Strander,
+    ncounts: MBuilder::SourceCounts,
+) -> Result<Batch<MBuilder::Out>, Error> {
+    let mut batch = mmbuilder.build(ncounts)?;
+
+    // Run stranding
+    batch.retained = strander.strand(&batch.contig, batch.retained);
+    batch.items = strander.strand(&batch.contig, batch.items);
+    Ok(batch)
+}
 
-        // Run stranding
-        batch.retained = self.strander.strand(&batch.contig, batch.retained);
-        batch.items = self.strander.strand(&batch.contig, batch.items);
+// Batches of both kinds of windows in a paired workload item, see PairedCollider
+pub struct PairedBatches<First: MismatchesVec, Second: MismatchesVec> {
+    pub first: Vec<Option<Batch<First>>>,
+    pub second: Vec<Option<Batch<Second>>>,
+    // Reads counted at least once
+    pub mapped: u32,
+}
 
-        // Final hooks
-        self.hook.on_finish(&mut batch);
-        Some(batch)
+// Reads are fetched & counted once for two kinds of windows (e.g. ROIs & sites),
+// each one has its own mismatches builder & strander
+#[derive(Clone)]
+pub struct PairedRunner<First, Second, FirstBuilder, FirstStrander, SecondBuilder, SecondStrander>
+where
+    for<'a> First: ReadsCollider<'a, Record> + Clone,
+    for<'a> Second: ReadsCollider<'a, Record> + Clone,
+{
+    pileuper: HTSPileupEngine<PairedCollider<First, Second>>,
+    first: (FirstBuilder, FirstStrander),
+    second: (SecondBuilder, SecondStrander),
+}
+
+impl<First, Second, FirstBuilder, FirstStrander, SecondBuilder, SecondStrander>
+    PairedRunner<First, Second, FirstBuilder, FirstStrander, SecondBuilder, SecondStrander>
+where
+    for<'a> First: ReadsCollider<'a, Record> + Clone,
+    for<'a> Second: ReadsCollider<'a, Record> + Clone,
+{
+    // Windows without reads are handled by the collider => the pileuper must always run it
+    pub fn new(
+        first: (FirstBuilder, FirstStrander),
+        second: (SecondBuilder, SecondStrander),
+        pileuper: HTSPileupEngine<PairedCollider<First, Second>>,
+    ) -> Self {
+        Self { pileuper: pileuper.with_empty_windows(), first, second }
     }
 
-    fn stats(self) -> Vec<Box<dyn EditingStat<MBuilder::Out>>> {
-        self.hook.stats()
+    #[allow(clippy::type_complexity)]
+    pub fn run<'runner>(
+        &'runner mut self,
+        workload: <PairedCollider<First, Second> as ReadsCollider<'runner, Record>>::Workload,
+    ) -> Result<PairedBatches<FirstBuilder::Out, SecondBuilder::Out>, Error>
+    where
+        FirstBuilder: Builder<'runner, SourceCounts = <First as ReadsCollider<'runner, Record>>::ColliderResult>,
+        FirstStrander: StrandingEngine<FirstBuilder::Out>,
+        SecondBuilder: Builder<'runner, SourceCounts = <Second as ReadsCollider<'runner, Record>>::ColliderResult>,
+        SecondStrander: StrandingEngine<SecondBuilder::Out>,
+    {
+        let (first, second) = (workload.first().len(), workload.second().len());
+        self.pileuper.run(workload)?;
+
+        let ncounts = match self.pileuper.result() {
+            Some(x) => x,
+            None => {
+                let (first, second) = ((0..first).map(|_| None).collect(), (0..second).map(|_| None).collect());
+                return Ok(PairedBatches { first, second, mapped: 0 });
+            }
+        };
+        let (mmbuilder, strander) = (&mut self.first.0, &self.first.1);
+        let first = ncounts
+            .first
+            .into_iter()
+            .map(|x| x.map(|x| process(mmbuilder, strander, x)).transpose())
+            .collect::<Result<_, _>>()?;
+        let (mmbuilder, strander) = (&mut self.second.0, &self.second.1);
+        let second = ncounts
+            .second
+            .into_iter()
+            .map(|x| x.map(|x| process(mmbuilder, strander, x)).transpose())
+            .collect::<Result<_, _>>()?;
+        Ok(PairedBatches { first, second, mapped: ncounts.mapped })
     }
 }
@@ -0,0 +1,249 @@
+// Quantile function of the standard normal distribution, p must be inside (0, 1).
+// Acklam's rational approximation, relative error < 1.15e-9
+pub fn normal_quantile(p: f64) -> f64 {
+    assert!(p > 0f64 && p < 1f64, "Probability must be inside (0, 1), got {}", p);
+
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.38357751867269e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00, 3.754408661907416e+00];
+    const PLOW: f64 = 0.02425;
+
+    // Lower tail, the upper one is symmetric
+    let tail = |p: f64| -> f64 {
+        let q = (-2f64 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1f64)
+    };
+    if p < PLOW {
+        tail(p)
+    } else if p > 1f64 - PLOW {
+        -tail(1f64 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1f64)
+    }
+}
+
+// Two-sided z score for the given confidence level, e.g. 1.96 for 0.95
+pub fn zscore(level: f32) -> f32 {
+    assert!(level > 0f32 && level < 1f32, "Confidence level must be inside (0, 1), got {}", level);
+    normal_quantile(1f64 - (1f64 - level as f64) / 2f64) as f32
+}
+
+// Wilson score interval for the binomial proportion successes / trials.
+// Counts can be fractional (e.g. weighted multimappers), NaN bounds are returned for zero trials
+pub fn wilson(successes: f64, trials: f64, z: f32) -> (f32, f32) {
+    if trials <= 0f64 {
+        return (f32::NAN, f32::NAN);
+    }
+    let (x, n, z) = (successes, trials, z as f64);
+    let p = x / n;
+    let z2 = z * z;
+
+    let denominator = 1f64 + z2 / n;
+    let center = (p + z2 / (2f64 * n)) / denominator;
+    let halfwidth = z * (p * (1f64 - p) / n + z2 / (4f64 * n * n)).sqrt() / denominator;
+    ((center - halfwidth).max(0f64) as f32, (center + halfwidth).min(1f64) as f32)
+}
+
+// Natural logarithm of the gamma function for x > 0, Lanczos approximation (g = 7, n = 9)
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7f64;
+    const COEF: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula
+        return (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1f64 - x);
+    }
+    let x = x - 1f64;
+    let t = x + G + 0.5;
+    let series = COEF[1..].iter().enumerate().fold(COEF[0], |sum, (i, c)| sum + c / (x + i as f64 + 1f64));
+    0.5 * (2f64 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+// Continued fraction for the regularized incomplete beta function (modified Lentz's method)
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 10_000;
+    const EPS: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+
+    let (qab, qap, qam) = (a + b, a + 1f64, a - 1f64);
+    let mut c = 1f64;
+    let mut d = 1f64 - qab * x / qap;
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1f64 / d;
+    let mut h = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2f64 * m;
+        for numerator in [m * (b - m) * x / ((qam + m2) * (a + m2)), -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2))]
+        {
+            d = 1f64 + numerator * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1f64 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1f64 / d;
+            h *= d * c;
+        }
+        if (d * c - 1f64).abs() < EPS {
+            break;
+        }
+    }
+    h
+}
+
+// Regularized incomplete beta function I_x(a, b)
+fn beta_reg(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0f64 {
+        return 0f64;
+    } else if x >= 1f64 {
+        return 1f64;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1f64 - x).ln()).exp();
+    // The continued fraction converges fast only on one side of the mean
+    if x < (a + 1f64) / (a + b + 2f64) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1f64 - front * beta_cf(b, a, 1f64 - x) / b
+    }
+}
+
+// Upper tail of the binomial distribution P(X >= successes) for the given number of trials & success probability.
+// Counts can be fractional (e.g. weighted multimappers)
+pub fn binomial_sf(successes: f64, trials: f64, p: f64) -> f64 {
+    if successes <= 0f64 {
+        1f64
+    } else if successes > trials {
+        0f64
+    } else {
+        beta_reg(successes, trials - successes + 1f64, p)
+    }
+}
+
+// Benjamini-Hochberg adjusted p-values (q-values) in the original order
+pub fn benjamini_hochberg(pvalues: &[f64]) -> Vec<f64> {
+    let total = pvalues.len() as f64;
+    let mut order: Vec<usize> = (0..pvalues.len()).collect();
+    order.sort_by(|x, y| pvalues[*y].total_cmp(&pvalues[*x]));
+
+    let mut qvalues = vec![0f64; pvalues.len()];
+    let mut minimum = 1f64;
+    for (i, idx) in order.into_iter().enumerate() {
+        let rank = total - i as f64;
+        minimum = minimum.min(pvalues[idx] * total / rank);
+        qvalues[idx] = minimum;
+    }
+    qvalues
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn normal_quantile() {
+        for (p, expected) in [
+            (0.5, 0f64),
+            (0.95, 1.6448536269514722),
+            (0.975, 1.959963984540054),
+            (0.995, 2.5758293035489004),
+            (0.025, -1.959963984540054),
+            (0.001, -3.090232306167813),
+            (1e-6, -4.753424308822899),
+        ] {
+            assert!((super::normal_quantile(p) - expected).abs() < 1e-8, "{}", p);
+        }
+    }
+
+    #[test]
+    fn zscore() {
+        for (level, expected) in [(0.95, 1.959964), (0.9, 1.6448536), (0.99, 2.5758293), (0.5, 0.6744898)] {
+            assert!((super::zscore(level) - expected).abs() < 1e-5, "{}", level);
+        }
+    }
+
+    #[test]
+    fn wilson() {
+        let z = 1.959964;
+        for (successes, trials, expected) in [
+            (1f64, 3f64, (0.061492, 0.792340)),
+            (81f64, 263f64, (0.255289, 0.366210)),
+            (50f64, 100f64, (0.403832, 0.596168)),
+            (0f64, 10f64, (0f32, 0.277533)),
+            (10f64, 10f64, (0.722467, 1f32)),
+        ] {
+            let (low, high) = super::wilson(successes, trials, z);
+            assert!((low - expected.0).abs() < 1e-5 && (high - expected.1).abs() < 1e-5, "{} {}", successes, trials);
+        }
+        // Narrower intervals for lower confidence levels
+        let (low, high) = super::wilson(1f64, 3f64, 1.6448536);
+        assert!((low - 0.078266).abs() < 1e-5 && (high - 0.746466).abs() < 1e-5);
+
+        let (low, high) = super::wilson(0f64, 0f64, z);
+        assert!(low.is_nan() && high.is_nan());
+    }
+
+    #[test]
+    fn binomial_sf() {
+        for (successes, trials, p, expected) in [
+            (3f64, 10f64, 0.1, 0.0701908264),
+            (10f64, 10f64, 0.5, 0.0009765625),
+            (1f64, 100f64, 0.01, 0.6339676587),
+            (5f64, 10f64, 0.5, 0.623046875),
+            (0f64, 10f64, 0.1, 1f64),
+            (11f64, 10f64, 0.1, 0f64),
+        ] {
+            let sf = super::binomial_sf(successes, trials, p);
+            assert!((sf - expected).abs() < 1e-9, "{} {} {}: {}", successes, trials, p, sf);
+        }
+        // Extreme tails are not rounded to zero
+        let sf = super::binomial_sf(30f64, 100f64, 0.001);
+        assert!(sf > 0f64 && sf < 1e-60);
+    }
+
+    #[test]
+    fn benjamini_hochberg() {
+        let qvalues = super::benjamini_hochberg(&[0.01, 0.04, 0.03, 0.005]);
+        for (q, expected) in qvalues.into_iter().zip([0.02, 0.04, 0.04, 0.02]) {
+            assert!((q - expected).abs() < 1e-12);
+        }
+        assert_eq!(super::benjamini_hochberg(&[1f64, 1f64]), vec![1f64, 1f64]);
+        assert!(super::benjamini_hochberg(&[]).is_empty());
+    }
+}
@@ -48,6 +48,11 @@ impl DeduceStrandByDesign {
             ReqStrand::Forward
         }
     }
+
+    // Unpaired reads in paired-end designs are treated as read1, i.e. as single-end reads from the same library
+    fn is_read1<R: AlignedRead>(record: &R) -> bool {
+        !record.is_paired() || record.is_first()
+    }
 }
 
 impl<R: AlignedRead> StrandDeducer<R> for DeduceStrandByDesign {
@@ -57,14 +62,14 @@ impl<R: AlignedRead> StrandDeducer<R> for DeduceStrandByDesign {
             StrandSpecificExperimentDesign::Same => strand,
             StrandSpecificExperimentDesign::Flip => DeduceStrandByDesign::flip(&strand),
             StrandSpecificExperimentDesign::Same1Flip2 => {
-                if record.is_first() {
+                if DeduceStrandByDesign::is_read1(record) {
                     strand
                 } else {
                     DeduceStrandByDesign::flip(&strand)
                 }
             }
             StrandSpecificExperimentDesign::Flip1Same2 => {
-                if record.is_first() {
+                if DeduceStrandByDesign::is_read1(record) {
                     DeduceStrandByDesign::flip(&strand)
                 } else {
                     strand
@@ -131,9 +136,29 @@ mod tests {
         ] {
             let dummy = DeduceStrandByDesign::new(design);
             read.expect_strand().return_const(strand);
+            read.expect_is_paired().return_const(true);
             read.expect_is_first().return_const(is_first);
             assert_eq!(dummy.deduce(&read), expected);
             read.checkpoint();
         }
     }
+
+    #[test]
+    fn deduce_unpaired_in_paired_end() {
+        let mut read = MockRead::new();
+        // Unpaired reads follow the read1 rule regardless of the mate flags
+        for (design, strand, expected) in [
+            (StrandSpecificExperimentDesign::Same1Flip2, ReqStrand::Forward, ReqStrand::Forward),
+            (StrandSpecificExperimentDesign::Same1Flip2, ReqStrand::Reverse, ReqStrand::Reverse),
+            (StrandSpecificExperimentDesign::Flip1Same2, ReqStrand::Forward, ReqStrand::Reverse),
+            (StrandSpecificExperimentDesign::Flip1Same2, ReqStrand::Reverse, ReqStrand::Forward),
+        ] {
+            let dummy = DeduceStrandByDesign::new(design);
+            read.expect_strand().return_const(strand);
+            read.expect_is_paired().return_const(false);
+            read.expect_is_first().return_const(false);
+            assert_eq!(dummy.deduce(&read), expected);
+            read.checkpoint();
+        }
+    }
 }
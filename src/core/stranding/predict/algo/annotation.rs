@@ -13,11 +13,13 @@ use flate2::read::MultiGzDecoder;
 use itertools::Itertools;
 
 use crate::core::io;
+use crate::core::io::gff::Record;
 use crate::core::mismatches::MismatchesVec;
 use crate::core::mismatches::roi::{ROIDataRef, ROIMismatchesVec};
 use crate::core::mismatches::site::{SiteDataVec, SiteMismatchesVec};
 use crate::core::stranding::predict::StrandingAlgo;
 use crate::core::strandutil::Stranded;
+use crate::error::Error;
 
 use super::utils;
 
@@ -26,43 +28,56 @@ pub struct StrandByGenomicAnnotation {
     exons: AnnotMap<String, ReqStrand>,
     genes: AnnotMap<String, ReqStrand>,
     extended3utr: AnnotMap<String, ReqStrand>,
+    // Number of genes/exons without a strand (".", "?") that were ignored
+    skipped: usize,
+    // Max distance to the nearest gene for items without overlapping features, 0 => disabled
+    flank: u32,
 }
 
 impl StrandByGenomicAnnotation {
-    pub fn from_gff(gff3: &Path, extended_3utr_size: u32, hook: impl Fn(usize)) -> Self {
-        io::utils::read_compressed!(gff3, Self::parse_gff, extended_3utr_size, hook)
+    pub fn from_gff(gff3: &Path, extended_3utr_size: u32, hook: impl Fn(usize)) -> Result<Self, Error> {
+        io::utils::read_compressed!(gff3, Self::parse_gff, gff3, extended_3utr_size, hook)?
     }
 
-    fn parse_gff<T: BufRead>(mut reader: T, extended_3utr_size: u32, hook: impl Fn(usize)) -> Self {
+    fn parse_gff<T: BufRead>(
+        mut reader: T,
+        path: &Path,
+        extended_3utr_size: u32,
+        hook: impl Fn(usize),
+    ) -> Result<Self, Error> {
         let extended_3utr_size = extended_3utr_size as isize;
 
         let mut exons: AnnotMap<String, ReqStrand> = AnnotMap::new();
         let mut genes: AnnotMap<String, ReqStrand> = AnnotMap::new();
         let mut extended3utr: AnnotMap<String, ReqStrand> = AnnotMap::new();
 
-        let mut parsedcnt: usize = 0;
+        let (mut parsedcnt, mut skipped, mut lineno): (usize, usize, usize) = (0, 0, 0);
         let mut buf = String::new();
-        while reader.read_line(&mut buf).expect("Failed to read annotation file") != 0 {
-            if buf.starts_with('#') || buf == "\n" {
-                buf.clear();
-                continue;
-            }
-
-            let split: Vec<&str> = buf.split('\t').take(7).collect();
-            assert_eq!(split.len(), 7);
+        while reader.read_line(&mut buf).map_err(|x| Error::io(path, x))? != 0 {
+            lineno += 1;
+            let parsed = match Record::parse(&buf, path, lineno)? {
+                Some(parsed) => parsed,
+                None => {
+                    buf.clear();
+                    continue;
+                }
+            };
 
-            let strand = match split[6] {
+            let strand = match parsed.strand {
                 "+" => ReqStrand::Forward,
                 "-" => ReqStrand::Reverse,
                 _ => {
+                    if matches!(parsed.feature, "exon" | "Exon" | "gene" | "Gene") {
+                        skipped += 1;
+                    }
                     buf.clear();
                     continue;
                 }
             };
-            let (start, end) = (split[3].parse::<isize>().unwrap() - 1, split[4].parse::<isize>().unwrap());
-            let record = Contig::new(split[0].into(), start, (end - start) as usize, Strand::Unknown);
+            let (start, end) = (parsed.start as isize, parsed.end as isize);
+            let record = Contig::new(parsed.contig.into(), start, (end - start) as usize, Strand::Unknown);
 
-            match split[2] {
+            match parsed.feature {
                 "exon" | "Exon" => exons.insert_at(strand, &record),
                 "gene" | "Gene" => {
                     // 1 - insert into genes
@@ -73,7 +88,8 @@ impl StrandByGenomicAnnotation {
                             ReqStrand::Forward => record.start() + record.length() as isize,
                             ReqStrand::Reverse => record.start() - extended_3utr_size,
                         };
-                        let record = Contig::new(split[0].into(), start, extended_3utr_size as usize, Strand::Unknown);
+                        let record =
+                            Contig::new(parsed.contig.into(), start, extended_3utr_size as usize, Strand::Unknown);
                         extended3utr.insert_at(strand, &record);
                     }
                 }
@@ -87,7 +103,16 @@ impl StrandByGenomicAnnotation {
             parsedcnt += 1;
             hook(parsedcnt);
         }
-        StrandByGenomicAnnotation { exons, genes, extended3utr }
+        Ok(StrandByGenomicAnnotation { exons, genes, extended3utr, skipped, flank: 0 })
+    }
+
+    pub fn with_flank(mut self, flank: u32) -> Self {
+        self.flank = flank;
+        self
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped
     }
 
     fn strand_in_index(&self, dummy: &Contig<String, Strand>, index: &AnnotMap<String, ReqStrand>) -> (u32, u32) {
@@ -102,7 +127,8 @@ impl StrandByGenomicAnnotation {
         (forward, reverse)
     }
 
-    fn predict(&self, contig: &str, range: Range<Position>) -> Strand {
+    // None if there are no overlapping features at all
+    fn overlapping(&self, contig: &str, range: Range<Position>) -> Option<Strand> {
         let (start, end) = (range.start, range.end);
         let dummy = Contig::new(contig.into(), start as isize, (end - start) as usize, Strand::Unknown);
 
@@ -110,13 +136,58 @@ impl StrandByGenomicAnnotation {
             let (forward, reverse) = self.strand_in_index(&dummy, index);
 
             match (forward == 0, reverse == 0) {
-                (true, true) => continue,                 // Nothing on both strands
-                (true, false) => return Strand::Reverse,  // Features only on the forward strand
-                (false, true) => return Strand::Forward,  // Features only on the reverse strand
-                (false, false) => return Strand::Unknown, // Features on both strands
+                (true, true) => continue,                       // Nothing on both strands
+                (true, false) => return Some(Strand::Reverse),  // Features only on the forward strand
+                (false, true) => return Some(Strand::Forward),  // Features only on the reverse strand
+                (false, false) => return Some(Strand::Unknown), // Features on both strands
             };
         }
-        Strand::Unknown
+        None
+    }
+
+    // Strand of the closest gene within the flank on either side of a non-overlapping item.
+    // Equidistant genes on both strands => unknown strand
+    fn nearest(&self, contig: &str, range: Range<Position>) -> Strand {
+        if self.flank == 0 {
+            return Strand::Unknown;
+        }
+        let (start, end) = (range.start as isize, range.end as isize);
+        let window = Contig::new(
+            contig.into(),
+            start - self.flank as isize,
+            (end - start) as usize + 2 * self.flank as usize,
+            Strand::Unknown,
+        );
+
+        let (mut mindist, mut forward, mut reverse) = (isize::MAX, 0, 0);
+        for gene in self.genes.find(&window) {
+            // Distance between the closest bases, i.e. adjacent gene => 1
+            let interval = gene.interval();
+            let distance = if interval.end <= start { start - interval.end + 1 } else { interval.start - end + 1 };
+            debug_assert!(distance > 0 && distance <= self.flank as isize);
+
+            if distance < mindist {
+                (mindist, forward, reverse) = (distance, 0, 0);
+            }
+            if distance == mindist {
+                match gene.data() {
+                    ReqStrand::Forward => forward += 1,
+                    ReqStrand::Reverse => reverse += 1,
+                }
+            }
+        }
+        match (forward == 0, reverse == 0) {
+            (false, true) => Strand::Forward,
+            (true, false) => Strand::Reverse,
+            _ => Strand::Unknown,
+        }
+    }
+
+    fn predict(&self, contig: &str, range: Range<Position>) -> Strand {
+        match self.overlapping(contig, range.clone()) {
+            Some(strand) => strand,
+            None => self.nearest(contig, range),
+        }
     }
 
     fn features_in(&self, contig: &str, range: Range<Position>) -> Vec<Range<Position>> {
@@ -180,19 +251,23 @@ impl StrandingAlgo<SiteMismatchesVec> for StrandByGenomicAnnotation {
 
         // Special case -> simply append all items to an existing vector
         if features.len() == 1 {
-            match self.predict(contig, range) {
-                Strand::Forward => items.forward.data.append(data),
-                Strand::Reverse => items.reverse.data.append(data),
-                Strand::Unknown => {}
+            let strand = self.overlapping(contig, range);
+            match strand {
+                Some(Strand::Forward) => items.forward.data.append(data),
+                Some(Strand::Reverse) => items.reverse.data.append(data),
+                Some(Strand::Unknown) | None => {}
+            }
+            // Otherwise, nearest genes are searched for each site below
+            if strand.is_some() || self.flank == 0 {
+                return;
             }
-            return;
         }
 
         let mut remained = SiteDataVec::with_capacity(data.len() / 10);
 
         let mut iter = features.into_iter();
         let mut feature = iter.next().unwrap();
-        let mut strand = self.predict(contig, feature.clone());
+        let mut strand = self.overlapping(contig, feature.clone());
         for ind in argsort {
             let pos = data.pos[ind];
             // While site is not inside the feature
@@ -201,12 +276,13 @@ impl StrandingAlgo<SiteMismatchesVec> for StrandByGenomicAnnotation {
 
                 // Predict strand if the next feature is useful
                 if feature.contains(&pos) {
-                    strand = self.predict(contig, feature.clone());
+                    strand = self.overlapping(contig, feature.clone());
                 }
             }
 
+            // Sites between genes may have different nearest genes
             let item = data.get(ind).unwrap();
-            match strand {
+            match strand.unwrap_or_else(|| self.nearest(contig, pos..pos + 1)) {
                 Strand::Forward => {
                     items.forward.data.push(item.into());
                 }
@@ -230,6 +306,11 @@ mod tests {
 
     use super::*;
 
+    fn parse(gff: &str, extended_3utr_size: u32) -> StrandByGenomicAnnotation {
+        let reader = BufReader::new(gff.as_bytes());
+        StrandByGenomicAnnotation::parse_gff(reader, Path::new("genes.gff3"), extended_3utr_size, |_| {}).unwrap()
+    }
+
     #[test]
     fn predict() {
         // create a dummy gff3 for the following genome (| - exons)
@@ -253,7 +334,7 @@ mod tests {
         2\t.\texon\t1\t9\t.\t-\t0\n\
         2\t.\texon\t20\t22\t.\t-\t0";
 
-        let dummy = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff3.as_bytes()), 0, |_| {});
+        let dummy = parse(gff3, 0);
         for (contig, range, strand) in [
             ("chr1", 5..25, Strand::Forward),
             ("chr1", 3..4, Strand::Forward),
@@ -280,13 +361,103 @@ mod tests {
         }
     }
 
+    #[test]
+    fn unstranded_features() {
+        let gff3 = "\
+        chr1\t.\tgene\t1\t29\t.\t+\t0\n\
+        chr1\t.\texon\t4\t7\t.\t?\t0\n\
+        chr1\t.\tgene\t31\t50\t.\t.\t0\n\
+        chr1\t.\tCDS\t31\t50\t.\t.\t0\n";
+
+        let dummy = parse(gff3, 0);
+        assert_eq!(dummy.skipped(), 2);
+        assert!(dummy.predict("chr1", 3..7).same(&Strand::Forward));
+        assert!(dummy.predict("chr1", 30..50).is_unknown());
+    }
+
+    #[test]
+    fn nearest() {
+        // chr1: ---------|-->-----|-----------------|--<-----|----------
+        //       0        10       20                40       50
+        let gff3 = "\
+        chr1\t.\tgene\t11\t20\t.\t+\t0\n\
+        chr1\t.\tgene\t41\t50\t.\t-\t0\n";
+
+        let disabled = parse(gff3, 0);
+        let dummy = disabled.clone().with_flank(10);
+        for (range, strand) in [
+            // Upstream
+            (5..8, Strand::Forward),
+            (0..1, Strand::Forward),
+            (35..38, Strand::Reverse),
+            // Downstream
+            (22..25, Strand::Forward),
+            (55..60, Strand::Reverse),
+            (59..60, Strand::Reverse),
+            (61..62, Strand::Unknown),
+            // Inside
+            (12..15, Strand::Forward),
+            (42..45, Strand::Reverse),
+            // Exactly between two genes
+            (29..31, Strand::Unknown),
+            // Both genes are nearby, but the forward one is closer
+            (25..31, Strand::Forward),
+            // Spans both genes
+            (15..45, Strand::Unknown),
+        ] {
+            let inferred = dummy.predict("chr1", range.clone());
+            assert!(inferred.same(&strand), "{:?}: {} vs {}", range, inferred.strand_symbol(), strand.strand_symbol());
+        }
+        assert!(dummy.predict("chr2", 0..10).is_unknown());
+
+        // No flank => no fallback
+        for range in [5..8, 22..25, 55..60] {
+            assert!(disabled.predict("chr1", range).is_unknown());
+        }
+    }
+
+    #[test]
+    fn nearest_sites() {
+        let gff3 = "\
+        1\t.\tgene\t11\t20\t.\t+\t0\n\
+        1\t.\tgene\t42\t50\t.\t-\t0\n";
+        let predictor = parse(gff3, 0).with_flank(20);
+
+        let sites = |positions: &[Position]| {
+            let mut data = SiteDataVec::new();
+            for pos in positions {
+                let mut d = SiteData::default();
+                d.pos = *pos;
+                data.push(d);
+            }
+            let mut workload =
+                Stranded::with_fn(|strand| SiteMismatchesVec::new("1".into(), strand, SiteDataVec::new()));
+            workload.unknown = SiteMismatchesVec::new("1".into(), Strand::Unknown, data);
+            workload
+        };
+
+        // Each site is assigned to its own nearest gene
+        let mut range = (0..72).collect_vec();
+        range.shuffle(&mut thread_rng());
+        let mut m = sites(&range);
+        StrandingAlgo::<SiteMismatchesVec>::predict(&predictor, "1", &mut m);
+        assert_eq!(m.forward.data.pos, (0..30).collect_vec());
+        assert_eq!(m.reverse.data.pos, (31..70).collect_vec());
+        assert_eq!(m.unknown.data.pos, &[30, 70, 71]);
+
+        // All sites between the genes
+        let mut m = sites(&[35, 25, 30]);
+        StrandingAlgo::<SiteMismatchesVec>::predict(&predictor, "1", &mut m);
+        assert_eq!((m.forward.data.pos, m.reverse.data.pos, m.unknown.data.pos), (vec![25], vec![35], vec![30]));
+    }
+
     #[test]
     fn intervals_in() {
         let gff = "\n\
         chr1\t.\tgene\t2\t12\t.\t+\t0\n\
         chr1\t.\texon\t4\t6\t.\t+\t0\n\
         chr1\t.\texon\t9\t11\t.\t+\t0";
-        let dummy = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 0, |_| {});
+        let dummy = parse(gff, 0);
         for (query, expected) in [
             (1..12, [1..3, 3..6, 6..8, 8..11, 11..12].to_vec()),
             (6..26, [6..8, 8..11, 11..12, 12..26].to_vec()),
@@ -308,7 +479,7 @@ mod tests {
         chr1\t.\texon\t29\t30\t.\t-\t0\n\
         #\n\
         chr1\t.\tgene\t2\t30\t.\t+\t0";
-        let dummy = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 0, |_| {});
+        let dummy = parse(gff, 0);
         for (query, expected) in [
             (0..14, [0..1, 1..10, 10..12, 12..14].to_vec()),
             (13..30, [13..16, 16..20, 20..24, 24..28, 28..30].to_vec()),
@@ -348,7 +519,7 @@ mod tests {
         workload.unknown = mm;
 
         // Case 1 -> no extension
-        let predictor = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 0, |_| {});
+        let predictor = parse(gff, 0);
         let mut m = workload.clone();
         StrandingAlgo::<SiteMismatchesVec>::predict(&predictor, "1", &mut m);
 
@@ -357,7 +528,7 @@ mod tests {
         debug_assert_eq!(m.unknown.data.pos, &[0, 1, 2, 3, 5, 6, 7, 11, 12, 14, 15, 18, 19, 20, 21]);
 
         // Case 2 -> has extension
-        let predictor = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 2, |_| {});
+        let predictor = parse(gff, 2);
         let mut m = workload.clone();
         StrandingAlgo::<SiteMismatchesVec>::predict(&predictor, "1", &mut m);
 
@@ -366,7 +537,7 @@ mod tests {
         debug_assert_eq!(m.unknown.data.pos, &[0, 3, 5, 6, 7, 11, 12, 14, 15, 19, 20, 21]);
 
         // Case 3 -> complete extension
-        let predictor = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 100, |_| {});
+        let predictor = parse(gff, 100);
         let mut m = workload.clone();
         StrandingAlgo::<SiteMismatchesVec>::predict(&predictor, "1", &mut m);
 
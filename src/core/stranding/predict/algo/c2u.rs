@@ -0,0 +1,180 @@
+use bio_types::strand::{Same, Strand};
+use derive_getters::Getters;
+use derive_more::Constructor;
+
+use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::mismatches::roi::ROIDataRef;
+use crate::core::mismatches::roi::{ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::site::{SiteDataRef, SiteMismatchesVec};
+use crate::core::refpred::PredNucleotide;
+use crate::core::stranding::predict::algo::utils;
+use crate::core::stranding::predict::StrandingAlgo;
+use crate::core::strandutil::Stranded;
+
+#[derive(Constructor, Getters, Copy, Clone)]
+pub struct StrandByC2UEditing {
+    minmismatches: u32,
+    minfreq: f32,
+}
+
+impl StrandByC2UEditing {
+    #[inline]
+    fn edited(&self, matches: f64, mismatches: f64) -> bool {
+        let coverage = mismatches + matches;
+        coverage > f64::EPSILON
+            && mismatches >= self.minmismatches as f64
+            && (mismatches / coverage) as f32 >= self.minfreq
+    }
+
+    #[inline]
+    fn sitepred(&self, sequenced: &NucCounts, refnuc: Nucleotide) -> Strand {
+        match refnuc {
+            Nucleotide::C => {
+                if self.edited(sequenced.C as f64, sequenced.T as f64) {
+                    Strand::Forward
+                } else {
+                    Strand::Unknown
+                }
+            }
+            Nucleotide::G => {
+                if self.edited(sequenced.G as f64, sequenced.A as f64) {
+                    Strand::Reverse
+                } else {
+                    Strand::Unknown
+                }
+            }
+            Nucleotide::A | Nucleotide::T | Nucleotide::Unknown => Strand::Unknown,
+        }
+    }
+
+    #[inline]
+    fn roipred(&self, mismatches: &ROINucCounts) -> Strand {
+        let c2t = self.edited(mismatches.C.C, mismatches.C.T);
+        let g2a = self.edited(mismatches.G.G, mismatches.G.A);
+
+        match (c2t, g2a) {
+            (false, false) => Strand::Unknown,
+            (false, true) => Strand::Reverse,
+            (true, false) => Strand::Forward,
+            (true, true) => {
+                let c2t_coverage = mismatches.C.C + mismatches.C.T;
+                let g2a_coverage = mismatches.G.G + mismatches.G.A;
+
+                if c2t_coverage <= f64::EPSILON && g2a_coverage <= f64::EPSILON {
+                    Strand::Unknown
+                } else {
+                    let c2t = mismatches.C.T / c2t_coverage;
+                    let g2a = mismatches.G.A / g2a_coverage;
+                    if mismatches.C.T > f64::EPSILON && c2t > g2a {
+                        Strand::Forward
+                    } else if mismatches.G.A > f64::EPSILON && g2a > c2t {
+                        Strand::Reverse
+                    } else {
+                        Strand::Unknown
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl StrandingAlgo<ROIMismatchesVec> for StrandByC2UEditing {
+    fn predict(&self, _: &str, items: &mut Stranded<ROIMismatchesVec>) {
+        utils::assort_strands!(items, |x: ROIDataRef| self.roipred(x.mismatches));
+    }
+}
+
+impl StrandingAlgo<SiteMismatchesVec> for StrandByC2UEditing {
+    fn predict(&self, _: &str, items: &mut Stranded<SiteMismatchesVec>) {
+        utils::assort_strands!(items, |x: SiteDataRef| match x.prednuc {
+            PredNucleotide::Homozygous(nuc) => self.sitepred(x.sequenced, *nuc),
+            PredNucleotide::Heterozygous((n1, n2)) => {
+                let (s1, s2) = (self.sitepred(x.sequenced, *n1), self.sitepred(x.sequenced, *n2));
+                match (s1.is_unknown(), s2.is_unknown()) {
+                    (false, true) => s1,
+                    (true, false) => s2,
+                    (false, false) if s1.same(&s2) => s1,
+                    _ => Strand::Unknown,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Neg;
+
+    use bio_types::strand::Same;
+
+    use super::*;
+
+    #[test]
+    fn roi_strand_by_editing() {
+        let dummy = StrandByC2UEditing::new(8, 0.05);
+        for (result, matches, mismatches) in
+            [(Strand::Forward, 8, 8), (Strand::Unknown, 100, 4), (Strand::Unknown, 1, 7), (Strand::Forward, 10, 10)]
+        {
+            let (matches, mismatches) = (matches as f64, mismatches as f64);
+            let mut mm = ROINucCounts::zeros();
+            mm.G.G = matches;
+            mm.G.A = mismatches;
+            assert!(result.neg().same(&dummy.roipred(&mm)));
+
+            mm = ROINucCounts::zeros();
+            mm.C.C = matches;
+            mm.C.T = mismatches;
+            assert!(result.same(&dummy.roipred(&mm)));
+        }
+
+        for (result, matches, c2t, g2a) in
+            [(Strand::Unknown, 10, 10, 10), (Strand::Reverse, 10, 10, 11), (Strand::Forward, 10, 11, 10)]
+        {
+            let (matches, c2t, g2a) = (matches as f64, c2t as f64, g2a as f64);
+
+            let mut mm = ROINucCounts::zeros();
+            mm.C.C = matches;
+            mm.C.T = c2t;
+
+            mm.G.G = matches;
+            mm.G.A = g2a;
+
+            assert!(result.same(&dummy.roipred(&mm)));
+        }
+
+        // A->I editing is ignored
+        let mut mm = ROINucCounts::zeros();
+        mm.A.A = 10f64;
+        mm.A.G = 10f64;
+        assert!(dummy.roipred(&mm).is_unknown());
+    }
+
+    #[test]
+    fn nucpred() {
+        let dummy = StrandByC2UEditing::new(10, 0.1);
+
+        // Unknown strand
+        for (sequenced, refnuc) in [
+            (NucCounts::G(123), Nucleotide::A),
+            (NucCounts::T(234), Nucleotide::Unknown),
+            (NucCounts::C(32), Nucleotide::T),
+            (NucCounts::new(0, 170, 0, 170), Nucleotide::A),
+            (NucCounts::new(0, 10, 0, 9), Nucleotide::C),
+            (NucCounts::new(200, 0, 200, 0), Nucleotide::T),
+        ] {
+            assert!(dummy.sitepred(&sequenced, refnuc).is_unknown());
+        }
+
+        let dummy = StrandByC2UEditing::new(8, 0.05);
+        // Inferred strand
+        for (matches, mismatches, strand) in
+            [(8, 8, Strand::Forward), (100, 4, Strand::Unknown), (1, 7, Strand::Unknown), (10, 10, Strand::Forward)]
+        {
+            let cnts = NucCounts::new(0, matches, 0, mismatches);
+            assert!(dummy.sitepred(&cnts, Nucleotide::C).same(&strand));
+
+            let cnts = NucCounts::new(mismatches, 0, matches, 0);
+            assert!(dummy.sitepred(&cnts, Nucleotide::G).same(&strand.neg()));
+        }
+    }
+}
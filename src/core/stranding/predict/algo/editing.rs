@@ -19,23 +19,25 @@ pub struct StrandByAtoIEditing {
 
 impl StrandByAtoIEditing {
     #[inline]
-    fn edited(&self, matches: f32, mismatches: f32) -> bool {
+    fn edited(&self, matches: f64, mismatches: f64) -> bool {
         let coverage = mismatches + matches;
-        coverage > f32::EPSILON && mismatches >= self.minmismatches as f32 && (mismatches / coverage) >= self.minfreq
+        coverage > f64::EPSILON
+            && mismatches >= self.minmismatches as f64
+            && (mismatches / coverage) as f32 >= self.minfreq
     }
 
     #[inline]
     fn sitepred(&self, sequenced: &NucCounts, refnuc: Nucleotide) -> Strand {
         match refnuc {
             Nucleotide::A => {
-                if self.edited(sequenced.A as f32, sequenced.G as f32) {
+                if self.edited(sequenced.A as f64, sequenced.G as f64) {
                     Strand::Forward
                 } else {
                     Strand::Unknown
                 }
             }
             Nucleotide::T => {
-                if self.edited(sequenced.T as f32, sequenced.C as f32) {
+                if self.edited(sequenced.T as f64, sequenced.C as f64) {
                     Strand::Reverse
                 } else {
                     Strand::Unknown
@@ -58,14 +60,14 @@ impl StrandByAtoIEditing {
                 let a2g_coverage = mismatches.A.A + mismatches.A.G;
                 let t2c_coverage = mismatches.T.T + mismatches.T.C;
 
-                if a2g_coverage <= f32::EPSILON && t2c_coverage <= f32::EPSILON {
+                if a2g_coverage <= f64::EPSILON && t2c_coverage <= f64::EPSILON {
                     Strand::Unknown
                 } else {
-                    let a2g = mismatches.A.G as f32 / a2g_coverage as f32;
-                    let t2c = mismatches.T.C as f32 / t2c_coverage as f32;
-                    if mismatches.A.G > f32::EPSILON && a2g > t2c {
+                    let a2g = mismatches.A.G / a2g_coverage;
+                    let t2c = mismatches.T.C / t2c_coverage;
+                    if mismatches.A.G > f64::EPSILON && a2g > t2c {
                         Strand::Forward
-                    } else if mismatches.T.C > f32::EPSILON && t2c > a2g {
+                    } else if mismatches.T.C > f64::EPSILON && t2c > a2g {
                         Strand::Reverse
                     } else {
                         Strand::Unknown
@@ -113,7 +115,7 @@ mod tests {
         for (result, matches, mismatches) in
             [(Strand::Forward, 8, 8), (Strand::Unknown, 100, 4), (Strand::Unknown, 1, 7), (Strand::Forward, 10, 10)]
         {
-            let (matches, mismatches) = (matches as f32, mismatches as f32);
+            let (matches, mismatches) = (matches as f64, mismatches as f64);
             let mut mm = ROINucCounts::zeros();
             mm.T.T = matches;
             mm.T.C = mismatches;
@@ -128,7 +130,7 @@ mod tests {
         for (result, matches, a2g, t2c) in
             [(Strand::Unknown, 10, 10, 10), (Strand::Reverse, 10, 10, 11), (Strand::Forward, 10, 11, 10)]
         {
-            let (matches, a2g, t2c) = (matches as f32, a2g as f32, t2c as f32);
+            let (matches, a2g, t2c) = (matches as f64, a2g as f64, t2c as f64);
 
             let mut mm = ROINucCounts::zeros();
             mm.A.A = matches;
@@ -1,6 +1,8 @@
 pub use annotation::StrandByGenomicAnnotation;
+pub use c2u::StrandByC2UEditing;
 pub use editing::StrandByAtoIEditing;
 
 mod annotation;
+mod c2u;
 mod editing;
 mod utils;
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use bio_types::genome::Position;
+use bio_types::strand::Strand;
+
+use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::mismatches::prefilters::ByMismatches;
+use crate::core::mismatches::roi::ROIMismatchesVec;
+use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::mismatches::MismatchesVec;
+
+use super::SyntheticData;
+
+// A->I editing frequency relative to the transcription strand, counts are given for the forward strand
+fn a2i(counts: &NucCounts, trstrand: Strand) -> Option<f32> {
+    let edited = match trstrand {
+        Strand::Forward => counts.G,
+        Strand::Reverse => counts.C,
+        Strand::Unknown => return None,
+    };
+    let coverage = counts.coverage();
+    (coverage > 0).then(|| edited as f32 / coverage as f32)
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SiteCheck {
+    pub spikes: usize,
+    // Spikes with enough edited reads to pass the prefilter
+    pub detectable: usize,
+    // Detectable spikes reported within the tolerance of the simulated frequency, i.e. the fraction of edited reads
+    pub recovered: usize,
+    // Max deviation from the simulated frequency over reported spikes
+    pub maxdev: f32,
+    // Mean deviation from the spiked frequency over reported spikes, mostly due to the sampling noise
+    pub sampling: f32,
+    // Reported sites at positions without spikes (both strands)
+    pub falsepos: usize,
+    pub clean: u64,
+}
+
+impl SiteCheck {
+    pub fn passed(&self, max_fp_rate: f64) -> bool {
+        self.recovered == self.detectable && self.falsepos as f64 <= max_fp_rate * self.clean as f64
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ROICheck {
+    // Reported ROIs with A->I editing in the simulated reads & all reported ROIs
+    pub edited: usize,
+    pub rois: usize,
+    // ROIs with the A->I frequency within the tolerance of the simulated one
+    pub recovered: usize,
+    pub maxdev: f32,
+}
+
+impl ROICheck {
+    pub fn passed(&self) -> bool {
+        self.rois > 0 && self.recovered == self.rois
+    }
+}
+
+impl SyntheticData {
+    fn tid(&self, contig: &str) -> usize {
+        self.contigs.iter().position(|x| x.0 == contig).unwrap_or_else(|| panic!("Unknown contig: {}", contig))
+    }
+
+    fn simulated(&self, tid: usize, trstrand: Strand, pos: Position) -> &NucCounts {
+        match trstrand {
+            Strand::Forward => &self.truth[tid].forward[pos as usize],
+            Strand::Reverse => &self.truth[tid].reverse[pos as usize],
+            Strand::Unknown => panic!("Simulated reads are always stranded"),
+        }
+    }
+
+    pub fn check_sites(&self, sites: &[SiteMismatchesVec], prefilter: &ByMismatches, tolerance: f32) -> SiteCheck {
+        let mut reported = HashMap::new();
+        for vec in sites {
+            for (pos, sequenced) in vec.data.pos.iter().zip(&vec.data.sequenced) {
+                reported.insert((vec.contig(), *pos, vec.trstrand()), sequenced);
+            }
+        }
+
+        let mut result = SiteCheck { spikes: self.spikes.len(), ..Default::default() };
+        let mut deviations = Vec::new();
+        for spike in &self.spikes {
+            let tid = self.tid(&spike.contig);
+            let simulated = self.simulated(tid, spike.trstrand, spike.pos);
+            let reference = Nucleotide::from(self.contigs[tid].1[spike.pos as usize]);
+            let detectable = prefilter.enough_mismatches_per_site(reference, simulated, spike.trstrand);
+            result.detectable += detectable as usize;
+
+            let key = (spike.contig.as_str(), spike.pos, spike.trstrand);
+            let freq = match reported.remove(&key).and_then(|x| a2i(x, spike.trstrand)) {
+                None => continue,
+                Some(freq) => freq,
+            };
+            let deviation = (freq - a2i(simulated, spike.trstrand).unwrap()).abs();
+            result.maxdev = result.maxdev.max(deviation);
+            result.recovered += (detectable && deviation <= tolerance) as usize;
+            deviations.push((freq - spike.freq).abs());
+        }
+        if !deviations.is_empty() {
+            result.sampling = deviations.iter().sum::<f32>() / deviations.len() as f32;
+        }
+        // Leftovers are sites without spikes
+        result.falsepos = reported.len();
+        result.clean = self.contigs.iter().map(|x| 2 * x.1.len() as u64).sum::<u64>() - self.spikes.len() as u64;
+        result
+    }
+
+    pub fn check_rois(&self, rois: &[ROIMismatchesVec], tolerance: f32) -> ROICheck {
+        let spiked = self.spiked();
+        let mut result = ROICheck::default();
+        for vec in rois {
+            let (tid, trstrand) = (self.tid(vec.contig()), vec.trstrand());
+            let reference = match trstrand {
+                Strand::Forward => b'A',
+                Strand::Reverse => b'T',
+                Strand::Unknown => continue,
+            };
+            for roi in vec.data.iter() {
+                let freq = roi.a2g_freq(trstrand);
+                if freq.is_nan() {
+                    continue;
+                }
+                let (mut edited, mut coverage) = (0u64, 0u64);
+                for pos in roi.roi.premasked.clone().filter(|x| self.contigs[tid].1[*x as usize] == reference) {
+                    let counts = self.simulated(tid, trstrand, pos);
+                    let hits = if trstrand == Strand::Forward { counts.G } else { counts.C };
+                    edited += hits as u64;
+                    coverage += counts.coverage() as u64;
+                }
+                let simulated = if coverage > 0 { edited as f32 / coverage as f32 } else { 0f32 };
+
+                let deviation = (freq - simulated).abs();
+                result.rois += 1;
+                result.edited +=
+                    roi.roi.premasked.clone().any(|x| spiked.contains(&(vec.contig(), x, trstrand))) as usize;
+                result.recovered += (deviation <= tolerance) as usize;
+                result.maxdev = result.maxdev.max(deviation);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a2i() {
+        let counts = NucCounts::new(6, 1, 2, 1);
+        assert_eq!(super::a2i(&counts, Strand::Forward), Some(0.2));
+        assert_eq!(super::a2i(&counts, Strand::Reverse), Some(0.1));
+        assert_eq!(super::a2i(&counts, Strand::Unknown), None);
+        assert_eq!(super::a2i(&NucCounts::zeros(), Strand::Forward), None);
+    }
+
+    #[test]
+    fn passed() {
+        let sites =
+            SiteCheck { spikes: 12, detectable: 10, recovered: 10, falsepos: 2, clean: 10_000, ..Default::default() };
+        assert!(sites.passed(1e-3) && !sites.passed(1e-4));
+        assert!(!SiteCheck { recovered: 9, ..sites }.passed(1e-3));
+
+        assert!(ROICheck { edited: 1, rois: 5, recovered: 5, maxdev: 0.0 }.passed());
+        assert!(!ROICheck { edited: 1, rois: 5, recovered: 4, maxdev: 0.1 }.passed());
+        assert!(!ROICheck::default().passed());
+    }
+}
@@ -0,0 +1,34 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+// System allocator counting heap allocations made by each thread, must be installed as the #[global_allocator].
+// Counters are thread-local => tests running in parallel don't affect each other
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The counter might be already destroyed while the thread is shutting down
+        let _ = ALLOCATIONS.try_with(|x| x.set(x.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|x| x.set(x.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+// Number of heap allocations (including reallocations) made by the current thread while running the closure
+pub fn allocations<R>(f: impl FnOnce() -> R) -> (usize, R) {
+    let before = ALLOCATIONS.with(|x| x.get());
+    let result = f();
+    (ALLOCATIONS.with(|x| x.get()) - before, result)
+}
@@ -0,0 +1,9 @@
+pub use check::{ROICheck, SiteCheck};
+pub use counting::{allocations, CountingAllocator};
+pub use rng::SplitMix64;
+pub use simulation::{Simulation, Spike, SyntheticData, SyntheticRead};
+
+mod check;
+mod counting;
+mod rng;
+mod simulation;
@@ -0,0 +1,61 @@
+// SplitMix64 generator: simulated datasets must be identical across platforms & releases for a given seed,
+// which is not guaranteed by general purpose RNG crates
+#[derive(Clone, Debug)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [0, n)
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Upper bound must be > 0");
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        // Reference values of the SplitMix64 generator
+        let mut dummy = SplitMix64::new(1234567);
+        assert_eq!(dummy.next_u64(), 6457827717110365317);
+        assert_eq!(dummy.next_u64(), 3203168211198807973);
+
+        let (mut first, mut second) = (SplitMix64::new(42), SplitMix64::new(42));
+        for _ in 0..100 {
+            assert_eq!(first.next_u64(), second.next_u64());
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let mut dummy = SplitMix64::new(42);
+        let mut seen = [0u32; 4];
+        for _ in 0..10_000 {
+            let x = dummy.uniform();
+            assert!((0f64..1f64).contains(&x));
+            seen[dummy.below(4) as usize] += 1;
+        }
+        assert!(seen.iter().all(|x| (2_200..2_800).contains(x)));
+    }
+}
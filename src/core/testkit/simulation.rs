@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bio_types::genome::Position;
+use bio_types::strand::Strand;
+use rust_htslib::bam;
+use rust_htslib::bam::header::HeaderRecord;
+use rust_htslib::bam::record::{Cigar, CigarString};
+
+use crate::core::dna::{NucCounts, ReqNucleotide};
+use crate::core::io::hts;
+use crate::core::strandutil::Stranded;
+use crate::error::Error;
+
+use super::SplitMix64;
+
+const NUCLEOTIDES: &[u8; 4] = b"ACGT";
+const FASTA_LINE_WIDTH: usize = 60;
+const BASE_QUALITY: u8 = 40;
+const MAPQ: u8 = 60;
+
+// A->I edit spiked at an A (forward strand) or T (reverse strand) assembly position
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spike {
+    pub contig: String,
+    pub pos: Position,
+    pub trstrand: Strand,
+    pub freq: f32,
+}
+
+// Single-end reads sampled uniformly along random contigs. The library is strand-specific with
+// the read strand = the transcript strand ("s" design), all reads are perfectly aligned (no indels/clipping).
+// Each base is replaced by a random nucleotide with the error rate after the editing
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    seed: u64,
+    contigs: Vec<(String, Position)>,
+    readlen: u32,
+    coverage: u32,
+    error_rate: f32,
+    // Spikes per contig, frequencies are assigned in a round-robin manner
+    spikes: u32,
+    freqs: Vec<f32>,
+}
+
+pub struct SyntheticRead {
+    pub tid: u32,
+    pub pos: Position,
+    pub reverse: bool,
+    pub seq: Vec<u8>,
+}
+
+pub struct SyntheticData {
+    pub contigs: Vec<(String, Vec<u8>)>,
+    pub spikes: Vec<Spike>,
+    // Sorted by the contig & position
+    pub reads: Vec<SyntheticRead>,
+    // Sequenced nucleotides for each contig position by the read strand, including edits & errors
+    pub truth: Vec<Stranded<Vec<NucCounts>>>,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, contigs: Vec<(String, Position)>) -> Self {
+        Self {
+            seed,
+            contigs,
+            readlen: 100,
+            coverage: 30,
+            error_rate: 0.001,
+            spikes: 20,
+            freqs: vec![0.1, 0.25, 0.5, 0.75, 0.9],
+        }
+    }
+
+    // Coverage is the mean number of reads per position on both strands
+    pub fn with_reads(mut self, readlen: u32, coverage: u32) -> Self {
+        assert!(readlen > 0, "Read length must be > 0");
+        self.readlen = readlen;
+        self.coverage = coverage;
+        self
+    }
+
+    pub fn with_error_rate(mut self, error_rate: f32) -> Self {
+        assert!((0f32..=1f32).contains(&error_rate), "Error rate must be in [0, 1]");
+        self.error_rate = error_rate;
+        self
+    }
+
+    pub fn with_spikes(mut self, spikes: u32, freqs: Vec<f32>) -> Self {
+        assert!(!freqs.is_empty() && freqs.iter().all(|x| (0f32..=1f32).contains(x)), "Invalid spike frequencies");
+        self.spikes = spikes;
+        self.freqs = freqs;
+        self
+    }
+
+    pub fn run(&self) -> SyntheticData {
+        let mut rng = SplitMix64::new(self.seed);
+        let contigs: Vec<(String, Vec<u8>)> = self
+            .contigs
+            .iter()
+            .map(|(name, length)| (name.clone(), (0..*length).map(|_| NUCLEOTIDES[rng.below(4) as usize]).collect()))
+            .collect();
+
+        let mut spikes = Vec::new();
+        let (mut reads, mut truth) = (Vec::new(), Vec::new());
+        for (tid, (contig, seq)) in contigs.iter().enumerate() {
+            let (length, readlen) = (seq.len() as Position, self.readlen as Position);
+            // Spikes are never placed at contig ends => they are always covered by reads on both sides
+            assert!(
+                length >= 2 * readlen + 8 * self.spikes as Position,
+                "Contig {} is too short for {} spikes & {}bp reads",
+                contig,
+                self.spikes,
+                readlen
+            );
+            let mut edits = HashMap::new();
+            while edits.len() < self.spikes as usize {
+                let pos = readlen + rng.below(length - 2 * readlen);
+                let trstrand = match seq[pos as usize] {
+                    b'A' => Strand::Forward,
+                    b'T' => Strand::Reverse,
+                    _ => continue,
+                };
+                if !edits.contains_key(&pos) {
+                    let freq = self.freqs[edits.len() % self.freqs.len()];
+                    edits.insert(pos, (trstrand, freq));
+                }
+            }
+            let mut spiked: Vec<Spike> = edits
+                .iter()
+                .map(|(pos, (trstrand, freq))| Spike {
+                    contig: contig.clone(),
+                    pos: *pos,
+                    trstrand: *trstrand,
+                    freq: *freq,
+                })
+                .collect();
+            spiked.sort_by_key(|x| x.pos);
+            spikes.extend(spiked);
+
+            let mut counts = Stranded::with_fn(|strand| match strand {
+                Strand::Unknown => vec![],
+                _ => vec![NucCounts::zeros(); length as usize],
+            });
+            let total = length * self.coverage as Position / readlen;
+            let mut starts: Vec<Position> = (0..total).map(|_| rng.below(length - readlen + 1)).collect();
+            starts.sort_unstable();
+            for start in starts {
+                let strand = if rng.below(2) == 0 { Strand::Forward } else { Strand::Reverse };
+                let mut read = seq[start as usize..(start + readlen) as usize].to_vec();
+                for (pos, base) in (start..).zip(read.iter_mut()) {
+                    if let Some((trstrand, freq)) = edits.get(&pos) {
+                        if *trstrand == strand && rng.uniform() < *freq as f64 {
+                            *base = if *base == b'A' { b'G' } else { b'C' };
+                        }
+                    }
+                    if rng.uniform() < self.error_rate as f64 {
+                        let others: Vec<u8> = NUCLEOTIDES.iter().copied().filter(|x| *x != *base).collect();
+                        *base = others[rng.below(3) as usize];
+                    }
+                    counts[strand][pos as usize][ReqNucleotide::try_from(*base).unwrap()] += 1;
+                }
+                reads.push(SyntheticRead {
+                    tid: tid as u32,
+                    pos: start,
+                    reverse: strand == Strand::Reverse,
+                    seq: read,
+                });
+            }
+            truth.push(counts);
+        }
+        SyntheticData { contigs, spikes, reads, truth }
+    }
+}
+
+impl SyntheticData {
+    // Saves <dir>/genome.fa (+.fai) & <dir>/reads.bam (+.bai)
+    pub fn write(&self, dir: &Path) -> Result<(PathBuf, PathBuf), Error> {
+        fs::create_dir_all(dir).map_err(|x| Error::io(dir, x))?;
+        let (fasta, bam) = (dir.join("genome.fa"), dir.join("reads.bam"));
+        self.write_fasta(&fasta)?;
+        self.write_bam(&bam)?;
+        Ok((fasta, bam))
+    }
+
+    pub fn write_fasta(&self, path: &Path) -> Result<(), Error> {
+        let mut faipath = OsString::from(path);
+        faipath.push(".fai");
+
+        let mut writer = BufWriter::new(File::create(path).map_err(|x| Error::io(path, x))?);
+        let (mut fai, mut offset) = (String::new(), 0);
+        for (name, seq) in &self.contigs {
+            let header = format!(">{}\n", name);
+            offset += header.len();
+            fai.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                name,
+                seq.len(),
+                offset,
+                FASTA_LINE_WIDTH,
+                FASTA_LINE_WIDTH + 1
+            ));
+
+            writer.write_all(header.as_bytes()).map_err(|x| Error::io(path, x))?;
+            for line in seq.chunks(FASTA_LINE_WIDTH) {
+                writer.write_all(line).and_then(|_| writer.write_all(b"\n")).map_err(|x| Error::io(path, x))?;
+                offset += line.len() + 1;
+            }
+        }
+        writer.flush().map_err(|x| Error::io(path, x))?;
+        fs::write(&faipath, fai).map_err(|x| Error::io(&faipath, x))
+    }
+
+    pub fn write_bam(&self, path: &Path) -> Result<(), Error> {
+        let mut header = bam::Header::new();
+        header.push_record(HeaderRecord::new(b"HD").push_tag(b"VN", &"1.6").push_tag(b"SO", &"coordinate"));
+        for (name, seq) in &self.contigs {
+            header.push_record(HeaderRecord::new(b"SQ").push_tag(b"SN", name).push_tag(b"LN", &seq.len()));
+        }
+
+        let mut writer =
+            bam::Writer::from_path(path, &header, bam::Format::Bam).map_err(|x| Error::hts(path, x.to_string()))?;
+        let mut record = bam::Record::new();
+        for (ind, read) in self.reads.iter().enumerate() {
+            let qname = format!("read{}", ind);
+            let cigar = CigarString(vec![Cigar::Match(read.seq.len() as u32)]);
+            let qual = vec![BASE_QUALITY; read.seq.len()];
+            record.set(qname.as_bytes(), Some(&cigar), &read.seq, &qual);
+            record.set_tid(read.tid as i32);
+            record.set_pos(read.pos as i64);
+            record.set_mapq(MAPQ);
+            record.set_flags(if read.reverse { 16 } else { 0 });
+            record.set_mtid(-1);
+            record.set_mpos(-1);
+            record.set_insert_size(0);
+            writer.write(&record).map_err(|x| Error::hts(path, x.to_string()))?;
+        }
+        // The index can be built only for the finished file
+        drop(writer);
+        hts::build_index(path, 1)
+    }
+
+    pub fn lengths(&self) -> Vec<(String, Position)> {
+        self.contigs.iter().map(|(name, seq)| (name.clone(), seq.len() as Position)).collect()
+    }
+
+    pub fn spiked(&self) -> HashSet<(&str, Position, Strand)> {
+        self.spikes.iter().map(|x| (x.contig.as_str(), x.pos, x.trstrand)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulation() -> Simulation {
+        Simulation::new(7, vec![("1".into(), 2_000), ("2".into(), 1_000)]).with_reads(50, 20).with_spikes(5, vec![1f32])
+    }
+
+    #[test]
+    fn reproducible() {
+        let (first, second) = (simulation().run(), simulation().run());
+        assert_eq!(first.contigs, second.contigs);
+        assert_eq!(first.spikes, second.spikes);
+        let truth =
+            |x: &SyntheticData| x.truth.iter().map(|x| (x.forward.clone(), x.reverse.clone())).collect::<Vec<_>>();
+        assert_eq!(truth(&first), truth(&second));
+
+        let other = Simulation::new(8, vec![("1".into(), 2_000), ("2".into(), 1_000)]).run();
+        assert_ne!(first.contigs, other.contigs);
+    }
+
+    #[test]
+    fn run() {
+        let data = simulation().run();
+        assert_eq!(data.lengths(), vec![("1".to_owned(), 2_000), ("2".to_owned(), 1_000)]);
+        assert_eq!(data.reads.len(), 2_000 * 20 / 50 + 1_000 * 20 / 50);
+        assert!(data.reads.windows(2).all(|x| (x[0].tid, x[0].pos) <= (x[1].tid, x[1].pos)));
+
+        assert_eq!(data.spikes.len(), 10);
+        for spike in &data.spikes {
+            let tid = data.contigs.iter().position(|x| x.0 == spike.contig).unwrap();
+            let reference = data.contigs[tid].1[spike.pos as usize];
+            let (counts, opposite) = match spike.trstrand {
+                Strand::Forward => {
+                    (data.truth[tid].forward[spike.pos as usize], data.truth[tid].reverse[spike.pos as usize])
+                }
+                _ => (data.truth[tid].reverse[spike.pos as usize], data.truth[tid].forward[spike.pos as usize]),
+            };
+            // All reads from the transcript strand are edited, edits are never visible on the opposite strand
+            match spike.trstrand {
+                Strand::Forward => {
+                    assert_eq!(reference, b'A');
+                    assert!(counts.G > 0 && opposite.G <= 1);
+                }
+                Strand::Reverse => {
+                    assert_eq!(reference, b'T');
+                    assert!(counts.C > 0 && opposite.C <= 1);
+                }
+                Strand::Unknown => unreachable!(),
+            }
+        }
+
+        // Sequenced bases match the truth
+        let mut counts = NucCounts::zeros();
+        for read in data.reads.iter().filter(|x| x.tid == 1 && x.pos <= 500 && x.pos + 50 > 500 && !x.reverse) {
+            counts[ReqNucleotide::try_from(read.seq[(500 - read.pos) as usize]).unwrap()] += 1;
+        }
+        assert_eq!(counts, data.truth[1].forward[500]);
+    }
+}
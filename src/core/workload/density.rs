@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio_types::genome::Position;
+
+use crate::core::io::hts::IndexedDensity;
+
+// Estimated number of reads in fixed-size windows along each contig
+#[derive(Clone, Debug, Default)]
+pub struct ReadsDensity {
+    window: u64,
+    contigs: HashMap<String, Vec<f64>>,
+}
+
+impl ReadsDensity {
+    pub fn new(window: u64, contigs: HashMap<String, Vec<f64>>) -> Self {
+        assert!(window > 0, "Density window must be > 0");
+        Self { window, contigs }
+    }
+
+    // Mapped reads of each contig are distributed proportionally to the compressed size of the windows
+    pub fn from_index(window: u64, index: Vec<IndexedDensity>) -> Self {
+        let contigs = index
+            .into_iter()
+            .map(|x| {
+                let total = x.bytes.iter().sum::<u64>();
+                let reads = if total > 0 {
+                    x.bytes.iter().map(|b| x.mapped as f64 * *b as f64 / total as f64).collect()
+                } else {
+                    let windows = x.bytes.len().max(1);
+                    vec![x.mapped as f64 / windows as f64; windows]
+                };
+                (x.contig, reads)
+            })
+            .collect();
+        Self::new(window, contigs)
+    }
+
+    pub fn total(&self) -> f64 {
+        self.contigs.values().flatten().sum()
+    }
+
+    // Reads are assumed to be uniformly distributed within each window
+    pub fn estimate(&self, contig: &str, range: &Range<Position>) -> f64 {
+        let windows = match self.contigs.get(contig) {
+            Some(x) => x,
+            None => return 0f64,
+        };
+        let mut estimate = 0f64;
+        let mut pos = range.start;
+        while pos < range.end {
+            let ind = (pos / self.window) as usize;
+            if ind >= windows.len() {
+                break;
+            }
+            let end = ((ind as u64 + 1) * self.window).min(range.end);
+            estimate += windows[ind] * (end - pos) as f64 / self.window as f64;
+            pos = end;
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let index = vec![
+            IndexedDensity { contig: "chr1".into(), mapped: 100, bytes: vec![0, 30, 10] },
+            // Unknown distribution => uniform
+            IndexedDensity { contig: "chrM".into(), mapped: 10, bytes: vec![0, 0] },
+        ];
+        let density = ReadsDensity::from_index(10, index);
+        assert_eq!(density.total(), 110f64);
+
+        assert_eq!(density.estimate("chr1", &(0..30)), 100f64);
+        assert_eq!(density.estimate("chr1", &(5..15)), 37.5f64);
+        assert_eq!(density.estimate("chr1", &(25..100)), 12.5f64);
+        assert_eq!(density.estimate("chrM", &(0..5)), 2.5f64);
+        assert_eq!(density.estimate("chr2", &(0..100)), 0f64);
+    }
+}
@@ -1,6 +1,10 @@
+pub use density::ReadsDensity;
+pub use paired::PairedWorkload;
 pub use roi::{ROIWorkload, ROI};
 pub use site::SiteWorkload;
 
+mod density;
+mod paired;
 pub mod roi;
 mod site;
 mod utils;
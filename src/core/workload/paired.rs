@@ -0,0 +1,128 @@
+use std::ops::Range;
+
+use bio_types::genome::{AbstractInterval, Interval, Position};
+use itertools::Itertools;
+
+// Overlapping windows of two workloads (e.g. ROIs & sites) joined into a single window => reads are fetched once.
+// Windows are never split or modified, each one is tagged with its index in the original workload
+#[derive(Clone, PartialEq, Debug)]
+pub struct PairedWorkload<First, Second> {
+    interval: Interval,
+    first: Vec<(usize, First)>,
+    second: Vec<(usize, Second)>,
+}
+
+impl<First, Second> AbstractInterval for PairedWorkload<First, Second> {
+    fn contig(&self) -> &str {
+        self.interval.contig()
+    }
+
+    fn range(&self) -> Range<Position> {
+        self.interval.range()
+    }
+}
+
+enum Window<First, Second> {
+    First(usize, First),
+    Second(usize, Second),
+}
+
+impl<First: AbstractInterval, Second: AbstractInterval> PairedWorkload<First, Second> {
+    // Windows are joined while they overlap & the joined window spans at most maxspan bases
+    pub fn union(first: Vec<First>, second: Vec<Second>, maxspan: Position) -> Vec<Self> {
+        let first = first.into_iter().enumerate().map(|(ind, x)| Window::First(ind, x));
+        let second = second.into_iter().enumerate().map(|(ind, x)| Window::Second(ind, x));
+        let interval = |x: &Window<First, Second>| match x {
+            Window::First(_, x) => (x.contig().to_owned(), x.range()),
+            Window::Second(_, x) => (x.contig().to_owned(), x.range()),
+        };
+        let windows = first
+            .chain(second)
+            .map(|x| (interval(&x), x))
+            .sorted_by(|(x, _), (y, _)| x.0.cmp(&y.0).then(x.1.start.cmp(&y.1.start)).then(x.1.end.cmp(&y.1.end)));
+
+        let mut result: Vec<Self> = Vec::new();
+        for ((contig, range), window) in windows {
+            let joined = match result.last_mut() {
+                Some(last) if last.contig() == contig && range.start < last.range().end => {
+                    let (start, end) = (last.range().start, last.range().end.max(range.end));
+                    let fits = end - start <= maxspan;
+                    if fits {
+                        last.interval.range_mut().end = end;
+                    }
+                    fits
+                }
+                _ => false,
+            };
+            if !joined {
+                result.push(Self { interval: Interval::new(contig, range), first: vec![], second: vec![] });
+            }
+            let last = result.last_mut().unwrap();
+            match window {
+                Window::First(ind, x) => last.first.push((ind, x)),
+                Window::Second(ind, x) => last.second.push((ind, x)),
+            }
+        }
+        result
+    }
+}
+
+impl<First, Second> PairedWorkload<First, Second> {
+    pub fn first(&self) -> &[(usize, First)] {
+        &self.first
+    }
+
+    pub fn second(&self) -> &[(usize, Second)] {
+        &self.second
+    }
+
+    pub fn dissolve(self) -> (Interval, Vec<(usize, First)>, Vec<(usize, Second)>) {
+        (self.interval, self.first, self.second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union() {
+        let first = vec![
+            Interval::new("1".into(), 10..50),
+            Interval::new("1".into(), 500..600),
+            Interval::new("2".into(), 0..10),
+        ];
+        let second = vec![
+            Interval::new("1".into(), 0..100),
+            Interval::new("1".into(), 100..200),
+            Interval::new("1".into(), 580..700),
+            Interval::new("2".into(), 10..20),
+        ];
+        let workload = PairedWorkload::union(first, second, 200);
+        let summary: Vec<_> = workload
+            .iter()
+            .map(|x| {
+                let first: Vec<usize> = x.first().iter().map(|x| x.0).collect();
+                let second: Vec<usize> = x.second().iter().map(|x| x.0).collect();
+                (x.contig(), x.range(), first, second)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                // Adjacent windows don't overlap
+                ("1", 0..100, vec![0], vec![0]),
+                ("1", 100..200, vec![], vec![1]),
+                ("1", 500..700, vec![1], vec![2]),
+                ("2", 0..10, vec![2], vec![]),
+                ("2", 10..20, vec![], vec![3]),
+            ]
+        );
+
+        // Windows are joined only up to the max span
+        let first = vec![Interval::new("1".into(), 0..100)];
+        let second = vec![Interval::new("1".into(), 50..250)];
+        let workload = PairedWorkload::union(first, second, 200);
+        assert_eq!(workload.iter().map(|x| x.range()).collect::<Vec<_>>(), [0..100, 50..250]);
+    }
+}
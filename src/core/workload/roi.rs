@@ -1,10 +1,15 @@
+use std::collections::HashMap;
 use std::ops::Range;
+use std::sync::Arc;
 
 use bio_types::genome::{AbstractInterval, Interval, Position};
 use bio_types::strand::{Same, Strand};
 use derive_getters::{Dissolve, Getters};
+use itertools::Itertools;
+use rayon::prelude::*;
 
 use crate::core::io::bed::BedRecord;
+use crate::core::io::contigs::ContigLengths;
 
 use super::utils;
 
@@ -13,8 +18,13 @@ pub struct ROI {
     contig: String,
     premasked: Range<Position>,
     subintervals: Vec<Range<Position>>,
-    name: String,
+    // Shared with all results for the ROI => no per-record copies
+    name: Arc<str>,
     strand: Strand,
+    // Total length of BED12 blocks or the whole premasked range otherwise, masked bases are counted relative to it
+    length: Position,
+    // Postmasked range extended by the flanks (if any), the whole range is counted
+    flanked: Range<Position>,
 }
 
 impl PartialEq for ROI {
@@ -24,6 +34,8 @@ impl PartialEq for ROI {
             && self.strand.same(&other.strand)
             && self.name == other.name
             && self.subintervals == other.subintervals
+            && self.length == other.length
+            && self.flanked == other.flanked
     }
 }
 
@@ -45,17 +57,49 @@ impl ROI {
         name: String,
         strand: Strand,
     ) -> Self {
-        debug_assert!(!subintervals.is_empty());
         debug_assert!(subintervals.iter().all(|x| x.start >= premasked.start && x.end <= premasked.end));
-        ROI { contig, premasked, subintervals, name, strand }
+        let length = premasked.end - premasked.start;
+        let mut roi = ROI { contig, premasked, subintervals, name: name.into(), strand, length, flanked: 0..0 };
+        roi.flanked = roi.postmasked();
+        roi
+    }
+
+    // ROI made of several blocks (e.g. exons of a transcript), subintervals must be already clipped to them
+    pub fn with_blocks(mut self, blocks: &[Range<Position>]) -> Self {
+        debug_assert!(self.subintervals.iter().all(|x| blocks.iter().any(|b| b.start <= x.start && x.end <= b.end)));
+        if !blocks.is_empty() {
+            self.length = blocks.iter().map(|x| x.end - x.start).sum();
+        }
+        self
+    }
+
+    // Count the given number of bases on both sides of the ROI, flanks are clamped at the contig bounds
+    pub fn with_flanks(mut self, flank: Position, contiglen: Position) -> Self {
+        let range = self.postmasked();
+        self.flanked = range.start.saturating_sub(flank)..range.end.max((range.end + flank).min(contiglen));
+        self
     }
 
     pub fn premasked(&self) -> Range<Position> {
         self.premasked.clone()
     }
 
+    // Completely masked ROIs span their original coordinates
     pub fn postmasked(&self) -> Range<Position> {
-        self.subintervals.first().unwrap().start..self.subintervals.last().unwrap().end
+        match (self.subintervals.first(), self.subintervals.last()) {
+            (Some(first), Some(last)) => first.start..last.end,
+            _ => self.premasked(),
+        }
+    }
+
+    pub fn flanked(&self) -> Range<Position> {
+        self.flanked.clone()
+    }
+
+    // Flanked bases before & after the postmasked range, empty without flanks
+    pub fn flanks(&self) -> [Range<Position>; 2] {
+        let range = self.postmasked();
+        [self.flanked.start..range.start, range.end..self.flanked.end]
     }
 
     pub fn subintervals(&self) -> &[Range<Position>] {
@@ -66,9 +110,22 @@ impl ROI {
         &self.name
     }
 
+    pub fn shared_name(&self) -> Arc<str> {
+        self.name.clone()
+    }
+
     pub fn strand(&self) -> Strand {
         self.strand
     }
+
+    pub fn length(&self) -> Position {
+        self.length
+    }
+
+    // Bases removed by the exclude list or the mask
+    pub fn masked(&self) -> Position {
+        self.length - self.subintervals.iter().map(|x| x.end - x.start).sum::<Position>()
+    }
 }
 
 #[derive(Clone, PartialEq, Debug, Dissolve, Getters)]
@@ -98,30 +155,241 @@ impl ROIWorkload {
 
 #[allow(clippy::len_without_is_empty)]
 impl ROIWorkload {
-    pub fn from_bed(rois: Vec<BedRecord>, binsize: u64, exclude: Option<Vec<BedRecord>>) -> Vec<ROIWorkload> {
+    pub fn from_bed(
+        rois: Vec<BedRecord>,
+        binsize: u64,
+        include: Option<Vec<BedRecord>>,
+        exclude: Option<Vec<BedRecord>>,
+        mask: Option<Vec<BedRecord>>,
+    ) -> Vec<ROIWorkload> {
         assert!(binsize > 0, "Binsize must be > 0");
 
-        // 1. Subtract from rois all the excluded regions and create ROI objects
+        // 1. Subtract from rois all the excluded regions
         let rois = if let Some(exclude) = exclude {
             utils::subtract(rois, exclude)
-                .into_iter()
-                .map(|x| ROI::new(x.inner.contig().into(), x.inner.range(), x.retained, x.inner.name, x.inner.strand))
-                .collect()
         } else {
             rois.into_iter()
                 .map(|x| {
-                    let subintervals = vec![x.interval.range()];
-                    ROI::new(x.contig().into(), x.range(), subintervals, x.name, x.strand)
+                    let retained = vec![x.interval.range()];
+                    utils::MaskedInterval { inner: x, retained }
                 })
                 .collect()
         };
 
-        // 2. Bin these guys and create workloads
-        utils::bin(rois, binsize).into_iter().map(|x| ROIWorkload { bin: x.bin, rois: x.items }).collect()
+        // 2. Clip the rest to the included regions (if any)
+        let rois = if let Some(include) = include { utils::intersect(rois, include) } else { rois };
+
+        // 3. Restrict BED12 records to their blocks, introns are never counted.
+        // Same as for excluded regions, ROIs without any retained blocks are dropped
+        let rois = utils::blocks(rois, |x| x.blocks.as_slice());
+
+        // 4. Mask subregions (if any) and create ROI objects.
+        // Unlike excluded regions, ROIs completely swallowed by the mask are kept with no subintervals
+        let rois = if let Some(mask) = mask { utils::mask(rois, mask) } else { rois };
+        let rois = rois
+            .into_par_iter()
+            .map(|x| {
+                ROI::new(x.inner.contig().into(), x.inner.range(), x.retained, x.inner.name, x.inner.strand)
+                    .with_blocks(&x.inner.blocks)
+            })
+            .collect();
+
+        // 5. Bin these guys and create workloads
+        utils::bin(rois, binsize).into_par_iter().map(|x| ROIWorkload { bin: x.bin, rois: x.items }).collect()
+    }
+
+    // Extend ROIs by the given number of bases on both sides, bins are extended to hold the flanks.
+    // Contigs absent in the given lengths are not clamped at the end
+    pub fn with_flanks(workload: Vec<ROIWorkload>, flank: Position, lengths: &ContigLengths) -> Vec<ROIWorkload> {
+        workload
+            .into_par_iter()
+            .map(|x| {
+                let contiglen = lengths.get(x.contig()).unwrap_or(Position::MAX);
+                let rois = x.rois.into_iter().map(|roi| roi.with_flanks(flank, contiglen)).collect_vec();
+                let (mut start, mut end) = (x.bin.range().start, x.bin.range().end);
+                for roi in &rois {
+                    start = start.min(roi.flanked().start);
+                    end = end.max(roi.flanked().end);
+                }
+                ROIWorkload::new(Interval::new(x.bin.contig().to_owned(), start..end), rois)
+            })
+            .collect()
     }
 
     #[inline]
     pub fn len(&self) -> usize {
         (self.bin.range().end - self.bin.range().start) as usize
     }
+
+    // Join bins holding ROIs with the same name & strand => parts of the same ROI are counted (and merged) in a
+    // single batch. Same-named ROIs on different contigs are never joined, their names are returned sorted
+    pub fn group_by_name(workload: Vec<ROIWorkload>) -> (Vec<ROIWorkload>, Vec<String>) {
+        let mut contigs: HashMap<&str, &str> = HashMap::new();
+        let mut shared = Vec::new();
+        for roi in workload.iter().flat_map(|x| &x.rois) {
+            let contig = contigs.entry(roi.name()).or_insert_with(|| roi.contig());
+            if *contig != roi.contig() {
+                shared.push(roi.name().to_owned());
+            }
+        }
+        shared.sort();
+        shared.dedup();
+
+        // Bins of each contig are consecutive and sorted by start
+        let mut result = Vec::with_capacity(workload.len());
+        for (_, bins) in &workload.into_iter().group_by(|x| x.contig().to_owned()) {
+            let bins = bins.collect_vec();
+            // Last bin with the given ROI name & strand
+            let mut last: HashMap<(&str, &str), usize> = HashMap::new();
+            for (ind, bin) in bins.iter().enumerate() {
+                for roi in &bin.rois {
+                    last.insert((roi.name(), roi.strand().strand_symbol()), ind);
+                }
+            }
+            // Runs of bins connected by the shared ROIs
+            let mut runs = Vec::new();
+            let (mut start, mut end) = (0, 0);
+            for (ind, bin) in bins.iter().enumerate() {
+                for roi in &bin.rois {
+                    end = end.max(last[&(roi.name(), roi.strand().strand_symbol())]);
+                }
+                if ind == end {
+                    runs.push(start..end + 1);
+                    (start, end) = (ind + 1, ind + 1);
+                }
+            }
+
+            let mut bins = bins.into_iter();
+            for run in runs {
+                let mut joined = bins.next().unwrap();
+                for bin in bins.by_ref().take(run.len() - 1) {
+                    let range = joined.bin.range().start.min(bin.bin.range().start)
+                        ..joined.bin.range().end.max(bin.bin.range().end);
+                    *joined.bin.range_mut() = range;
+                    joined.rois.extend(bin.rois);
+                }
+                result.push(joined);
+            }
+        }
+        (result, shared)
+    }
+}
+
+// Unstranded windows of the given size starting every `step` bases along each contig, named as contig:start-end.
+// The last window is clipped to the contig end => windows are never contained in the previous ones.
+pub fn tiles(contigs: Vec<(String, Position)>, size: Position, step: Position) -> Vec<BedRecord> {
+    assert!(size > 0 && step > 0, "Tile size and step must be > 0");
+    let mut result = Vec::new();
+    for (contig, length) in contigs {
+        let mut start = 0;
+        while start < length {
+            let end = (start + size).min(length);
+            result.push(BedRecord {
+                name: format!("{}:{}-{}", contig, start, end),
+                strand: Strand::Unknown,
+                interval: Interval::new(contig.clone(), start..end),
+                blocks: vec![],
+            });
+            if end == length {
+                break;
+            }
+            start += step;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bed(contig: &str, range: Range<Position>, name: &str) -> BedRecord {
+        BedRecord {
+            name: name.to_owned(),
+            strand: Strand::Forward,
+            interval: Interval::new(contig.to_owned(), range),
+            blocks: vec![],
+        }
+    }
+
+    fn ranges(tiles: &[BedRecord]) -> Vec<(&str, Range<Position>)> {
+        tiles.iter().map(|x| (x.interval.contig(), x.interval.range())).collect()
+    }
+
+    #[test]
+    fn tiles() {
+        let contigs = vec![("chr1".to_owned(), 2500), ("MT".to_owned(), 1000), ("empty".to_owned(), 0)];
+        let tiled = super::tiles(contigs.clone(), 1000, 1000);
+        assert_eq!(ranges(&tiled), [("chr1", 0..1000), ("chr1", 1000..2000), ("chr1", 2000..2500), ("MT", 0..1000)]);
+        assert_eq!(tiled[2].name, "chr1:2000-2500");
+        assert!(tiled.iter().all(|x| x.strand == Strand::Unknown && x.blocks.is_empty()));
+
+        // Sliding windows
+        let tiled = super::tiles(contigs, 1000, 600);
+        assert_eq!(
+            ranges(&tiled),
+            [("chr1", 0..1000), ("chr1", 600..1600), ("chr1", 1200..2200), ("chr1", 1800..2500), ("MT", 0..1000)]
+        );
+    }
+
+    #[test]
+    fn tiled_workload() {
+        // Overlapping windows are binned together & excluded regions are subtracted as for BED ROIs
+        let tiled = super::tiles(vec![("chr1".to_owned(), 2000)], 1000, 500);
+        let exclude = vec![BedRecord {
+            name: "".to_owned(),
+            strand: Strand::Unknown,
+            interval: Interval::new("chr1".to_owned(), 0..100),
+            blocks: vec![],
+        }];
+        let workload = ROIWorkload::from_bed(tiled, 10_000, None, Some(exclude), None);
+        assert_eq!(workload.len(), 1);
+        let rois = workload[0].rois();
+        assert_eq!(rois.len(), 3);
+        assert_eq!(rois[0].name(), "chr1:0-1000");
+        assert_eq!((rois[0].range(), rois[0].masked()), (100..1000, 100));
+        assert_eq!(rois.iter().map(|x| x.range()).collect::<Vec<_>>()[1..], [500..1500, 1000..2000]);
+    }
+
+    #[test]
+    fn group_by_name() {
+        // Gene split across three BED lines, each one ends up in a separate bin
+        let rois = vec![
+            bed("chr1", 0..10, "gene"),
+            bed("chr1", 100..110, "other"),
+            bed("chr1", 200..210, "gene"),
+            bed("chr1", 300..310, "gene"),
+            bed("chr1", 400..410, "last"),
+            bed("chr2", 0..10, "gene"),
+        ];
+        let workload = ROIWorkload::from_bed(rois, 20, None, None, None);
+        assert_eq!(workload.len(), 6);
+
+        let (grouped, shared) = ROIWorkload::group_by_name(workload);
+        assert_eq!(shared, ["gene"]);
+        // Bins between the parts are joined as well
+        let bins: Vec<(&str, Range<Position>, usize)> =
+            grouped.iter().map(|x| (x.contig(), x.range(), x.rois().len())).collect();
+        assert_eq!(bins, [("chr1", 0..310, 4), ("chr1", 400..410, 1), ("chr2", 0..10, 1)]);
+        let names: Vec<&str> = grouped[0].rois().iter().map(|x| x.name()).collect();
+        assert_eq!(names, ["gene", "other", "gene", "gene"]);
+    }
+
+    #[test]
+    fn flanks() {
+        let rois = vec![bed("chr1", 5..15, "clamped"), bed("chr1", 50..60, "masked"), bed("chr2", 0..10, "unknown")];
+        let mask = vec![bed("chr1", 50..52, "")];
+        let workload = ROIWorkload::from_bed(rois, 100, None, None, Some(mask));
+        let lengths = ContigLengths::new(HashMap::from([("chr1".to_owned(), 65)]));
+        let workload = ROIWorkload::with_flanks(workload, 10, &lengths);
+
+        let bins: Vec<(&str, Range<Position>)> = workload.iter().map(|x| (x.contig(), x.range())).collect();
+        assert_eq!(bins, [("chr1", 0..65), ("chr2", 0..20)]);
+        let rois = workload.iter().flat_map(|x| x.rois()).collect_vec();
+        // Flanks are clamped at the contig bounds & surround the postmasked range
+        assert_eq!((rois[0].flanked(), rois[0].flanks()), (0..25, [0..5, 15..25]));
+        assert_eq!((rois[1].flanked(), rois[1].flanks()), (42..65, [42..52, 60..65]));
+        assert_eq!((rois[1].premasked(), rois[1].postmasked()), (50..60, 52..60));
+        assert_eq!(rois[2].flanks(), [0..0, 10..20]);
+    }
 }
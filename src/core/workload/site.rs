@@ -4,6 +4,10 @@ use bio_types::genome::{AbstractInterval, Interval, Position};
 use derive_getters::{Dissolve, Getters};
 
 use super::utils;
+use super::ReadsDensity;
+
+// Adaptive bins are at most this many times smaller / larger than the fixed ones
+pub const ADAPTIVE_SCALE: u64 = 16;
 
 #[derive(Clone, PartialEq, Debug, Getters, Dissolve)]
 pub struct SiteWorkload {
@@ -25,9 +29,87 @@ impl SiteWorkload {
     pub fn from_intervals(
         mut intervals: Vec<Interval>,
         binsize: u64,
+        include: Option<Vec<impl AbstractInterval>>,
+        exclude: Option<Vec<impl AbstractInterval + Send>>,
+    ) -> Vec<SiteWorkload> {
+        assert!(binsize > 0, "Binsize must be > 0");
+        let intervals = Self::regions(intervals, include, exclude);
+
+        // Bin and transform to the workload
+        let intervals = utils::split(intervals, binsize);
+        utils::bin(intervals, binsize)
+            .into_iter()
+            .map(|x| SiteWorkload { interval: x.bin, include: x.items.into_iter().map(|x| x.range()).collect() })
+            .collect()
+    }
+
+    // Same number of bins as for the fixed binsize, but each one holds roughly the same estimated number of reads:
+    // dense regions are split into smaller bins & sparse ones are merged (within ADAPTIVE_SCALE times the binsize)
+    pub fn adaptive(
+        intervals: Vec<Interval>,
+        binsize: u64,
+        include: Option<Vec<impl AbstractInterval>>,
         exclude: Option<Vec<impl AbstractInterval + Send>>,
+        density: &ReadsDensity,
     ) -> Vec<SiteWorkload> {
         assert!(binsize > 0, "Binsize must be > 0");
+        let intervals = Self::regions(intervals, include, exclude);
+        let fixed = utils::bin(utils::split(intervals.clone(), binsize), binsize).len();
+
+        let (minsize, maxsize) = ((binsize / ADAPTIVE_SCALE).max(1), binsize * ADAPTIVE_SCALE);
+        let mut pieces = utils::split(intervals, minsize);
+        pieces.sort_by(|a, b| a.contig().cmp(b.contig()).then(a.range().start.cmp(&b.range().start)));
+        let reads: Vec<f64> = pieces.iter().map(|x| density.estimate(x.contig(), &x.range())).collect();
+        let target = reads.iter().sum::<f64>() / fixed.max(1) as f64;
+
+        let mut result: Vec<SiteWorkload> = Vec::with_capacity(fixed);
+        let mut accumulated = 0f64;
+        for (piece, reads) in pieces.into_iter().zip(reads) {
+            if let Some(last) = result.last_mut() {
+                let (start, end) = (last.interval.range().start, piece.range().end);
+                if last.contig() == piece.contig() && end - start <= maxsize && accumulated + reads <= target {
+                    // Adjacent pieces are joined back
+                    let adjacent = last.include.last().map_or(false, |x| x.end == piece.range().start);
+                    if adjacent {
+                        last.include.last_mut().unwrap().end = end;
+                    } else {
+                        last.include.push(piece.range());
+                    }
+                    last.interval.range_mut().end = end;
+                    accumulated += reads;
+                    continue;
+                }
+            }
+            result.push(SiteWorkload { include: vec![piece.range()], interval: piece });
+            accumulated = reads;
+        }
+        result
+    }
+
+    // Clip to the included regions & subtract the excluded ones
+    fn regions(
+        mut intervals: Vec<Interval>,
+        include: Option<Vec<impl AbstractInterval>>,
+        exclude: Option<Vec<impl AbstractInterval + Send>>,
+    ) -> Vec<Interval> {
+        // Clip to the included regions if needed
+        if let Some(included) = include {
+            let masked = intervals
+                .into_iter()
+                .map(|x| {
+                    let retained = vec![x.range()];
+                    utils::MaskedInterval { inner: x, retained }
+                })
+                .collect();
+            intervals = utils::intersect(masked, included)
+                .into_iter()
+                .flat_map(|x| {
+                    let contig = x.inner.contig().to_owned();
+                    x.retained.into_iter().map(move |piece| Interval::new(contig.clone(), piece))
+                })
+                .collect();
+        }
+
         // Subtract excluded if needed
         if let Some(excluded) = exclude {
             intervals = utils::subtract(intervals, excluded)
@@ -38,12 +120,56 @@ impl SiteWorkload {
                 })
                 .collect();
         }
+        intervals
+    }
 
-        // Bin and transform to the workload
-        let intervals = utils::split(intervals, binsize);
-        utils::bin(intervals, binsize)
-            .into_iter()
-            .map(|x| SiteWorkload { interval: x.bin, include: x.items.into_iter().map(|x| x.range()).collect() })
-            .collect()
+    // Extend the counted window by `pad` bases on both sides, results are still reported only for the core ranges
+    pub fn with_padding(self, pad: u64) -> Self {
+        let (start, end) = (self.interval.range().start, self.interval.range().end);
+        let interval = Interval::new(self.interval.contig().to_owned(), start.saturating_sub(pad)..end + pad);
+        Self { interval, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn ranges(workload: &[SiteWorkload]) -> Vec<(&str, Range<Position>, Vec<Range<Position>>)> {
+        workload.iter().map(|x| (x.contig(), x.range(), x.include().clone())).collect()
+    }
+
+    #[test]
+    fn adaptive() {
+        // chr1: 0..1000 is dense, 1000..4000 is sparse, chr2 has no reads at all
+        let density = ReadsDensity::new(
+            1000,
+            HashMap::from([("chr1".to_owned(), vec![300f64, 10f64, 10f64, 10f64]), ("chr2".to_owned(), vec![])]),
+        );
+        let genome = vec![Interval::new("chr1".into(), 0..4000), Interval::new("chr2".into(), 0..500)];
+        let exclude = vec![Interval::new("chr1".into(), 2000..2500)];
+        let none = None::<Vec<Interval>>;
+
+        // 4 + 1 fixed bins => 65 estimated reads per adaptive bin
+        let fixed = SiteWorkload::from_intervals(genome.clone(), 1000, none.clone(), Some(exclude.clone()));
+        assert_eq!(fixed.len(), 5);
+        let workload = SiteWorkload::adaptive(genome, 1000, none, Some(exclude), &density);
+        assert_eq!(
+            ranges(&workload),
+            [
+                ("chr1", 0..186, vec![0..186]),
+                ("chr1", 186..372, vec![186..372]),
+                ("chr1", 372..558, vec![372..558]),
+                ("chr1", 558..744, vec![558..744]),
+                ("chr1", 744..930, vec![744..930]),
+                ("chr1", 930..4000, vec![930..2000, 2500..4000]),
+                ("chr2", 0..500, vec![0..500]),
+            ]
+        );
+        // Same bases are reported
+        let bases = |w: &[SiteWorkload]| w.iter().flat_map(|x| x.include()).map(|x| x.end - x.start).sum::<u64>();
+        assert_eq!(bases(&workload), bases(&fixed));
     }
 }
@@ -1,6 +1,9 @@
 use std::cmp::Ordering;
+use std::collections::HashMap;
 
 use bio_types::genome::{AbstractInterval, Interval};
+use itertools::Itertools;
+use rayon::prelude::*;
 
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Bin<T> {
@@ -31,7 +34,7 @@ fn infer_bin<T: AbstractInterval>(seed: &T, binsize: u64) -> Interval {
     Interval::new(seed.contig().to_string(), seed.range().start..end)
 }
 
-pub fn bin<T: AbstractInterval>(mut workloads: Vec<T>, maxbinsize: u64) -> Vec<Bin<T>> {
+fn _bin<T: AbstractInterval>(mut workloads: Vec<T>, maxbinsize: u64) -> Vec<Bin<T>> {
     if workloads.is_empty() {
         return vec![];
     }
@@ -74,6 +77,26 @@ pub fn bin<T: AbstractInterval>(mut workloads: Vec<T>, maxbinsize: u64) -> Vec<B
     result
 }
 
+pub fn bin<T: AbstractInterval + Send>(workloads: Vec<T>, maxbinsize: u64) -> Vec<Bin<T>> {
+    // Bins never span several contigs => bin each contig in parallel.
+    // The input order is preserved within contigs, hence the result is identical to the sequential binning.
+    let mut grouped: HashMap<String, Vec<T>> = HashMap::with_capacity(128);
+    for w in workloads {
+        if !grouped.contains_key(w.contig()) {
+            grouped.insert(w.contig().into(), Default::default());
+        }
+        grouped.get_mut(w.contig()).unwrap().push(w);
+    }
+    grouped
+        .into_par_iter()
+        .map(|x| (x.0, _bin(x.1, maxbinsize)))
+        .collect::<Vec<(String, Vec<Bin<T>>)>>()
+        .into_iter()
+        .sorted_by(|x1, x2| x1.0.cmp(&x2.0))
+        .flat_map(|x| x.1)
+        .collect()
+}
+
 fn split_interval(chromosome: &str, interstart: u64, len: u64, binsize: u64) -> Vec<Interval> {
     let total_bins = (len + binsize - 1) / binsize;
     let mut bins: Vec<Interval> = Vec::with_capacity(total_bins as usize);
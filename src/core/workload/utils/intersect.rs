@@ -0,0 +1,188 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio_types::genome::AbstractInterval;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use super::subtract::MaskedInterval;
+
+// Sort & merge overlapping/adjacent ranges
+pub(super) fn merge(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_by_key(|x| x.start);
+
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = max(last.end, r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+fn clip_pieces(pieces: Vec<Range<u64>>, by: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut result = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        // First include region that might overlap the piece
+        let first = by.partition_point(|x| x.end <= piece.start);
+        for include in by[first..].iter().take_while(|x| x.start < piece.end) {
+            let overlap = max(piece.start, include.start)..min(piece.end, include.end);
+            debug_assert!(overlap.end > overlap.start);
+            result.push(overlap);
+        }
+    }
+    result
+}
+
+pub fn _intersect<T: AbstractInterval>(
+    inters: Vec<MaskedInterval<T>>,
+    include: Vec<Range<u64>>,
+) -> Vec<MaskedInterval<T>> {
+    let include = merge(include);
+    inters
+        .into_iter()
+        .filter_map(|x| {
+            let retained = clip_pieces(x.retained, &include);
+            if retained.is_empty() {
+                None
+            } else {
+                Some(MaskedInterval { inner: x.inner, retained })
+            }
+        })
+        .collect()
+}
+
+pub fn intersect<T: AbstractInterval + Send, S: AbstractInterval>(
+    inters: Vec<MaskedInterval<T>>,
+    include: Vec<S>,
+) -> Vec<MaskedInterval<T>> {
+    // Group by contig
+    let mut grouped: HashMap<String, (Vec<MaskedInterval<T>>, Vec<Range<u64>>)> = HashMap::with_capacity(128);
+    for t in inters {
+        if !grouped.contains_key(t.inner.contig()) {
+            grouped.insert(t.inner.contig().into(), Default::default());
+        }
+        grouped.get_mut(t.inner.contig()).unwrap().0.push(t);
+    }
+    for s in include {
+        // Contigs without intervals are irrelevant
+        if let Some(group) = grouped.get_mut(s.contig()) {
+            group.1.push(s.range());
+        }
+    }
+    grouped
+        .into_par_iter()
+        .map(|x| (x.0, _intersect(x.1 .0, x.1 .1)))
+        .collect::<Vec<(String, Vec<MaskedInterval<T>>)>>()
+        .into_iter()
+        .sorted_by(|x1, x2| x1.0.cmp(&x2.0))
+        .flat_map(|x| x.1)
+        .collect()
+}
+
+// Clip each interval to its own sorted non-overlapping blocks (e.g. exons), intervals without blocks are kept as is
+pub fn blocks<T: AbstractInterval + Send>(
+    inters: Vec<MaskedInterval<T>>,
+    blocks: impl Fn(&T) -> &[Range<u64>] + Sync,
+) -> Vec<MaskedInterval<T>> {
+    inters
+        .into_par_iter()
+        .filter_map(|x| {
+            let by = blocks(&x.inner);
+            if by.is_empty() {
+                return Some(x);
+            }
+            let retained = clip_pieces(x.retained, by);
+            (!retained.is_empty()).then(|| MaskedInterval { inner: x.inner, retained })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use super::*;
+
+    fn masked(chrom: &str, range: Range<u64>, retained: Vec<Range<u64>>) -> MaskedInterval<Interval> {
+        MaskedInterval { inner: Interval::new(chrom.into(), range), retained }
+    }
+
+    fn mwork(chrom: &str, ranges: Vec<Range<u64>>) -> Vec<Interval> {
+        ranges.into_iter().map(|x| Interval::new(chrom.into(), x)).collect()
+    }
+
+    #[test]
+    fn simple() {
+        for (include, expected) in [
+            (vec![4..6], vec![4..6]),
+            (vec![0..5], vec![2..5]),
+            (vec![6..9], vec![6..8]),
+            (vec![0..10], vec![2..8]),
+            (vec![0..3, 5..6, 7..12], vec![2..3, 5..6, 7..8]),
+            (vec![3..5, 4..6], vec![3..6]),
+        ] {
+            let result = intersect(vec![masked("1", 2..8, vec![2..8])], mwork("1", include));
+            assert_eq!(result, vec![masked("1", 2..8, expected)]);
+        }
+    }
+
+    #[test]
+    fn premasked() {
+        let inter = vec![masked("1", 0..20, vec![0..5, 10..20])];
+        let result = intersect(inter, mwork("1", vec![3..12, 18..30]));
+        assert_eq!(result, vec![masked("1", 0..20, vec![3..5, 10..12, 18..20])]);
+    }
+
+    #[test]
+    fn blocks() {
+        fn exons(x: &Interval) -> &[Range<u64>] {
+            match x.range().start {
+                0 => &[0..5, 10..15, 18..20],
+                _ => &[],
+            }
+        }
+        let inter = vec![
+            masked("1", 0..20, vec![3..12, 14..16]),
+            masked("1", 30..40, vec![30..40]),
+            // Only the intronic part is left => dropped
+            masked("2", 0..20, vec![6..9]),
+        ];
+        let result = super::blocks(inter, exons);
+        assert_eq!(result, vec![masked("1", 0..20, vec![3..5, 10..12, 14..15]), masked("1", 30..40, vec![30..40])]);
+    }
+
+    #[test]
+    fn empty() {
+        // Empty include
+        let result = intersect(vec![masked("1", 2..8, vec![2..8])], Vec::<Interval>::new());
+        assert!(result.is_empty());
+
+        // Empty inter
+        let result = intersect(Vec::<MaskedInterval<Interval>>::new(), mwork("1", vec![0..100]));
+        assert!(result.is_empty());
+
+        // Empty result
+        for (contig, include) in [("1", vec![0..2]), ("1", vec![8..10, 12..20]), ("2", vec![0..10])] {
+            let result = intersect(vec![masked("1", 2..8, vec![2..8])], mwork(contig, include));
+            assert!(result.is_empty());
+        }
+    }
+
+    #[test]
+    fn multiple_contigs() {
+        let inter = vec![
+            masked("2", 0..10, vec![0..10]),
+            masked("1", 5..15, vec![5..15]),
+            masked("1", 20..30, vec![20..30]),
+            masked("3", 0..10, vec![0..10]),
+        ];
+        let include: Vec<Interval> =
+            [mwork("1", vec![10..25]), mwork("2", vec![8..12])].into_iter().flatten().collect();
+        let expected =
+            vec![masked("1", 5..15, vec![10..15]), masked("1", 20..30, vec![20..25]), masked("2", 0..10, vec![8..10])];
+        assert_eq!(intersect(inter, include), expected);
+    }
+}
@@ -0,0 +1,101 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio_types::genome::AbstractInterval;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use super::intersect::merge;
+use super::subtract::MaskedInterval;
+
+fn mask_pieces(pieces: Vec<Range<u64>>, by: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut result = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        let mut start = piece.start;
+        // First mask region that might overlap the piece
+        let first = by.partition_point(|x| x.end <= piece.start);
+        for mask in by[first..].iter().take_while(|x| x.start < piece.end) {
+            if mask.start > start {
+                result.push(start..mask.start);
+            }
+            start = max(start, min(mask.end, piece.end));
+        }
+        if start < piece.end {
+            result.push(start..piece.end);
+        }
+    }
+    result
+}
+
+pub fn _mask<T: AbstractInterval>(inters: Vec<MaskedInterval<T>>, mask: Vec<Range<u64>>) -> Vec<MaskedInterval<T>> {
+    let mask = merge(mask);
+    inters.into_iter().map(|x| MaskedInterval { retained: mask_pieces(x.retained, &mask), inner: x.inner }).collect()
+}
+
+// Same as subtract, but completely masked intervals are kept (with no retained pieces)
+pub fn mask<T: AbstractInterval + Send, S: AbstractInterval>(
+    inters: Vec<MaskedInterval<T>>,
+    mask: Vec<S>,
+) -> Vec<MaskedInterval<T>> {
+    // Group by contig
+    let mut grouped: HashMap<String, (Vec<MaskedInterval<T>>, Vec<Range<u64>>)> = HashMap::with_capacity(128);
+    for t in inters {
+        if !grouped.contains_key(t.inner.contig()) {
+            grouped.insert(t.inner.contig().into(), Default::default());
+        }
+        grouped.get_mut(t.inner.contig()).unwrap().0.push(t);
+    }
+    for s in mask {
+        // Contigs without intervals are irrelevant
+        if let Some(group) = grouped.get_mut(s.contig()) {
+            group.1.push(s.range());
+        }
+    }
+    grouped
+        .into_par_iter()
+        .map(|x| (x.0, _mask(x.1 .0, x.1 .1)))
+        .collect::<Vec<(String, Vec<MaskedInterval<T>>)>>()
+        .into_iter()
+        .sorted_by(|x1, x2| x1.0.cmp(&x2.0))
+        .flat_map(|x| x.1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use super::*;
+
+    fn masked(chrom: &str, range: Range<u64>, retained: Vec<Range<u64>>) -> MaskedInterval<Interval> {
+        MaskedInterval { inner: Interval::new(chrom.into(), range), retained }
+    }
+
+    fn mwork(chrom: &str, ranges: Vec<Range<u64>>) -> Vec<Interval> {
+        ranges.into_iter().map(|x| Interval::new(chrom.into(), x)).collect()
+    }
+
+    #[test]
+    fn simple() {
+        for (by, expected) in [
+            (vec![4..6], vec![2..4, 6..8]),
+            (vec![0..5], vec![5..8]),
+            (vec![6..9], vec![2..6]),
+            (vec![0..2, 8..10], vec![2..8]),
+            (vec![3..4, 5..6, 7..12], vec![2..3, 4..5, 6..7]),
+            (vec![3..5, 4..6], vec![2..3, 6..8]),
+        ] {
+            let result = mask(vec![masked("1", 2..8, vec![2..8])], mwork("1", by));
+            assert_eq!(result, vec![masked("1", 2..8, expected)]);
+        }
+    }
+
+    #[test]
+    fn swallowed() {
+        // Completely masked intervals are retained without pieces
+        let inter = vec![masked("1", 0..20, vec![0..5, 10..20]), masked("2", 0..10, vec![0..10])];
+        let result = mask(inter, mwork("1", vec![0..7, 9..30]));
+        assert_eq!(result, vec![masked("1", 0..20, vec![]), masked("2", 0..10, vec![0..10])]);
+    }
+}
@@ -1,5 +1,9 @@
 pub use bin::{bin, split};
-pub use subtract::subtract;
+pub use intersect::{blocks, intersect};
+pub use mask::mask;
+pub use subtract::{subtract, MaskedInterval};
 
 mod bin;
+mod intersect;
+mod mask;
 mod subtract;
@@ -0,0 +1,71 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+// Errors caused by the user inputs or the environment (missing files, malformed records, etc).
+// They are propagated up to the CLI and reported as a single line, panics are reserved for bugs
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    // Lines are 1-based
+    #[error("{}:{line}: {message}", .path.display())]
+    Parse { path: PathBuf, line: usize, message: String },
+    #[error("{}: {message}", .path.display())]
+    Hts { path: PathBuf, message: String },
+    #[error("{}: {message}", .path.display())]
+    Reference { path: PathBuf, message: String },
+    #[error("{what}: {source}")]
+    Output { what: &'static str, source: csv::Error },
+    #[error("{0}")]
+    Input(String),
+    // Nothing was counted for the whole workload, see --allow-empty
+    #[error("{0}")]
+    Empty(String),
+}
+
+impl Error {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        Error::Io { path: path.as_ref().to_owned(), source }
+    }
+
+    pub fn parse(path: impl AsRef<Path>, line: usize, message: impl Into<String>) -> Self {
+        Error::Parse { path: path.as_ref().to_owned(), line, message: message.into() }
+    }
+
+    pub fn hts(path: impl AsRef<Path>, message: impl Into<String>) -> Self {
+        Error::Hts { path: path.as_ref().to_owned(), message: message.into() }
+    }
+
+    pub fn reference(path: impl AsRef<Path>, message: impl Into<String>) -> Self {
+        Error::Reference { path: path.as_ref().to_owned(), message: message.into() }
+    }
+
+    pub fn output(what: &'static str, source: impl Into<csv::Error>) -> Self {
+        Error::Output { what, source: source.into() }
+    }
+
+    // Empty runs have a distinct status => pipelines can tell them apart from invalid inputs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Empty(_) => 3,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let err = Error::parse("regions.bed", 3, "invalid start coordinate \"1e3\"");
+        assert_eq!(err.to_string(), "regions.bed:3: invalid start coordinate \"1e3\"");
+        let err = Error::io("missing.bed", io::Error::new(io::ErrorKind::NotFound, "No such file or directory"));
+        assert_eq!(err.to_string(), "missing.bed: No such file or directory");
+        assert_eq!(Error::Input("no contigs left".into()).to_string(), "no contigs left");
+        assert_eq!((err.exit_code(), Error::Empty("no reads".into()).exit_code()), (1, 3));
+    }
+}
@@ -1,2 +1,8 @@
 pub mod cli;
 pub mod core;
+pub mod error;
+
+// Unit tests check the number of heap allocations on hot paths
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: crate::core::testkit::CountingAllocator = crate::core::testkit::CountingAllocator;
@@ -9,6 +9,8 @@ use rayon::ThreadPoolBuilder;
 
 use reat::cli;
 use reat::cli::shared::args::CoreArgs;
+use reat::cli::shared::progress::ProgressMode;
+use reat::error::Error;
 
 const CREATE_THREAD_POOL_ERROR: &str = "Failed to initialize thread pool";
 const RENDER_PROGRESS_ERROR: &str = "Failed to render progress bar";
@@ -26,9 +28,7 @@ impl PanicAwareProgressManager {
         let hook = std::panic::take_hook();
         let clone = obj.clone();
         std::panic::set_hook(Box::new(move |info| {
-            for x in clone.lock.lock().unwrap().iter() {
-                x.abandon();
-            }
+            clone.abandon();
             // Flush and ignore possible errors, we can't do anything anyway
             let _ = (io::stdout().flush(), io::stderr().flush());
             hook(info);
@@ -42,6 +42,20 @@ impl PanicAwareProgressManager {
         self.lock.lock().unwrap().push(pbar.clone());
         pbar
     }
+
+    // Stop all bars in their current state, e.g. to report an error
+    pub fn abandon(&self) {
+        for x in self.lock.lock().unwrap().iter() {
+            x.abandon();
+        }
+    }
+}
+
+// Errors caused by the inputs or the environment are reported as a single line, panics are left for bugs
+fn fail(err: Error) -> ! {
+    let _ = io::stdout().flush();
+    eprintln!("Error: {}", err);
+    std::process::exit(err.exit_code());
 }
 
 fn main() {
@@ -60,40 +74,100 @@ fn main() {
         .subcommand(
             Command::new("site").long_about("Estimate editing per-site for the whole genome.").args(cli::sites::args()),
         )
+        .subcommand(
+            Command::new("selftest")
+                .long_about(
+                    "Simulate a small genome & reads with known A->I edits, run the roi and site pipelines on them \
+                    and check the recovered editing frequencies. Timings are reported as a rough benchmark.",
+                )
+                .args(cli::selftest::args()),
+        )
+        .subcommand(
+            Command::new("merge")
+                .long_about(
+                    "Merge outputs of several REAT runs: ROI/site tables are concatenated & sorted by the contig \
+                    order, EI tables are combined by summing the raw counts and recomputing the ratios.",
+                )
+                .args(cli::merge::args()),
+        )
+        .subcommand(
+            Command::new("schema")
+                .long_about(
+                    "Print the columns of the ROI or site table: names, types and descriptions, in the same order \
+                    as written with the default options. The schema version is the one from the \"# reat-schema\" \
+                    line at the top of the tables.",
+                )
+                .args(cli::schema::args()),
+        )
         .get_matches();
-    // Log the exact command used to call reat
-    println!("CLI: {}", env::args().join(" "));
+    let (command, args) = app.subcommand().expect("Subcommand is not specified.");
+    // Log the exact command used to call reat, the schema is printed as is to be parsed by other tools
+    if command != "schema" {
+        println!("CLI: {}", env::args().join(" "));
+    }
+
+    // Self-test generates its own inputs, merge & schema work with REAT outputs => no core arguments & progress bars
+    let standalone = match command {
+        "selftest" => Some(cli::selftest::run as fn(&ArgMatches) -> Result<(), Error>),
+        "merge" => Some(cli::merge::run),
+        "schema" => Some(cli::schema::run),
+        _ => None,
+    };
+    if let Some(func) = standalone {
+        if let Err(err) = func(args) {
+            fail(err);
+        }
+        return;
+    }
+    let progress = ProgressMode::new(args);
 
-    // Setup progress tracking
+    // Setup progress tracking, bars are hidden if progress is reported in another way
     let masterbar = PanicAwareProgressManager::new();
-    let factory = || masterbar.attach(cli::shared::style::parse::with_progress());
+    let attach = |style| if progress.bars() { masterbar.attach(style) } else { ProgressBar::hidden() };
+    let factory = || attach(cli::shared::style::parse::with_progress());
 
-    let pbar = masterbar.attach(
+    let pbar = attach(
         ProgressStyle::default_spinner()
             .template("[{elapsed_precise}] {msg:.red.bold}")
             .on_finish(ProgressFinish::AndLeave),
     ); //.with_style(style);
     pbar.set_message("Running...");
-    // Parse core arguments and determine subcommand
+    // Determine subcommand
     #[allow(clippy::type_complexity)]
-    let (args, func): (&ArgMatches, Box<dyn FnOnce(&ArgMatches, CoreArgs) + Send>) = match app.subcommand() {
+    let func: Box<dyn FnOnce(&ArgMatches, CoreArgs) -> Result<(), Error> + Send> = match command {
         // cli::rois::run(matches, core, factory)
-        Some(("roi", matches)) => (matches, Box::new(|matches, core| cli::rois::run(matches, core, factory))),
+        "roi" => Box::new(|matches, core| cli::rois::run(matches, core, factory)),
         // cli::sites::run(matches, core, factory)
-        Some(("site", matches)) => (matches, Box::new(|matches, core| cli::sites::run(matches, core, factory))),
-        _ => panic!("Subcommand is not specified."),
+        "site" => Box::new(|matches, core| cli::sites::run(matches, core, factory)),
+        _ => panic!("Unknown subcommand: {}", command),
+    };
+    let core = match cli::shared::args::CoreArgs::new(args, factory) {
+        Ok(core) => core,
+        Err(err) => {
+            masterbar.abandon();
+            fail(err)
+        }
     };
-    let core = cli::shared::args::CoreArgs::new(args, factory);
 
     // + 1 thread to render progress bar
     let pool = ThreadPoolBuilder::new().num_threads(core.threads + 1).build().expect(CREATE_THREAD_POOL_ERROR);
-    pool.scope(|s| {
+    let result = pool.scope(|s| {
         // Render progress bar in the additional thread
-        s.spawn(|_| {
-            masterbar.mbar.join().expect(RENDER_PROGRESS_ERROR);
-        });
+        if progress.bars() {
+            s.spawn(|_| {
+                masterbar.mbar.join().expect(RENDER_PROGRESS_ERROR);
+            });
+        }
 
-        func(args, core);
-        pbar.finish_with_message("Finished!");
+        let result = func(args, core);
+        match result {
+            Ok(()) => pbar.finish_with_message("Finished!"),
+            // All bars must be finished to stop the rendering thread
+            Err(_) => masterbar.abandon(),
+        }
+        result
     });
+    if let Err(err) = result {
+        fail(err);
+    }
 }
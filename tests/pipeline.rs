@@ -0,0 +1,1158 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+
+use bio_types::genome::{AbstractInterval, Interval};
+use file_diff::diff_files;
+use itertools::Itertools;
+use rust_htslib::bam::{self, Read};
+use tempfile::{tempdir, NamedTempFile};
+
+use reat::core::dna::{Multimap, ReqNucleotide};
+use reat::core::hooks::filters::{self, ByErrorRates};
+use reat::core::hooks::stats::{EditingStat, ROIEditingIndex, SubstitutionRates};
+use reat::core::io;
+use reat::core::io::fasta::{BasicFastaReader, FastaReader};
+use reat::core::mismatches::prefilters;
+use reat::core::mismatches::roi::{ROIColumn, ROIMismatchesVec};
+use reat::core::mismatches::site::{SiteColumn, SiteMismatchesVec};
+use reat::core::mismatches::MismatchesVec;
+use reat::core::pipeline::{
+    run_rois, run_sites, stream_paired, stream_rois, stream_sites, ChannelSink, Progress, ROIRunConfig, ReadsFilter,
+    SiteRunConfig,
+};
+use reat::core::refpred::{AutoRef, RefEngine};
+use reat::core::rpileup::ncounter::filters::{ByFlags, ByQuality, ByTags, Criterion, MapQ255, Sequential, Tracked};
+use reat::core::rpileup::stream::HTSStream;
+use reat::core::stranding::deduce::StrandSpecificExperimentDesign;
+use reat::core::strandutil::Stranded;
+use reat::core::testkit::Simulation;
+use reat::core::workload::{self, ROIWorkload, ReadsDensity, SiteWorkload};
+use reat::error::Error;
+
+mod paths;
+
+const TMP_CREATE_ERROR: &str = "Failed to create temporary file";
+const TMP_DELETE_ERROR: &str = "Failed to delete temporary file";
+
+fn refnucpred() -> Box<dyn RefEngine> {
+    let reader = BasicFastaReader::new(PathBuf::from(&*paths::GRCh38::FASTA)).unwrap();
+    Box::new(AutoRef::new(20, 0.95, true, Box::new(reader)))
+}
+
+fn readfilter(include: u16) -> ReadsFilter {
+    tagsfilter(include, ByTags::new())
+}
+
+fn tagsfilter(include: u16, tags: ByTags) -> ReadsFilter {
+    Tracked::new(Sequential::new(
+        Sequential::new(ByQuality::new(1, MapQ255::Keep, 20), ByFlags::new(include, 2820)),
+        tags,
+    ))
+}
+
+#[test]
+fn rois() {
+    // Same as the deducted_strand regression test for the ROI subcommand
+    let expected = paths::expected::ROIS.join("deducted.csv");
+    assert!(expected.is_file());
+
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.overlaps_dedup = false;
+    config.alnstats = false;
+    config.strand_depth = false;
+    config.prefilter = Some(prefilters::ByMismatches::new(5, 0.01, 20, false));
+
+    let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+    let mut saveto = csv::Writer::from_path(tmp.path()).unwrap();
+    let summary = stream_rois(config, |_, items| {
+        ROIMismatchesVec::ugly_in_contig_sort_and_to_csv_and_bed(
+            items,
+            &mut saveto,
+            Option::<&mut csv::Writer<File>>::None,
+            Option::<&mut csv::Writer<File>>::None,
+            &ROIColumn::layout(false, false, false, false),
+            false,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        Ok(())
+    })
+    .unwrap();
+    assert!(summary.stats.is_empty());
+    saveto.flush().unwrap();
+
+    let (mut first, mut second) = (File::open(tmp.path()).unwrap(), File::open(&expected).unwrap());
+    assert!(diff_files(&mut first, &mut second));
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
+
+fn deterministic_rois(threads: usize) -> (Vec<u8>, Vec<u8>) {
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.hooks.add_stat(Box::new(ROIEditingIndex::new("example".into(), "alu".into())));
+    config.deterministic = true;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    let mut saveto = csv::Writer::from_writer(vec![]);
+    let summary = pool.install(|| {
+        stream_rois(config, |_, items| {
+            ROIMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut saveto).unwrap();
+            Ok(())
+        })
+        .unwrap()
+    });
+
+    let mut ei = csv::Writer::from_writer(vec![]);
+    let stats = summary.stats.into_iter().map(|x| x.into_any().1).collect();
+    ROIEditingIndex::collapse(stats).to_csv(&mut ei).unwrap();
+    (saveto.into_inner().unwrap(), ei.into_inner().unwrap())
+}
+
+#[test]
+fn rois_deterministic() {
+    // Results & stats must not depend on the number of threads or their scheduling
+    let expected = deterministic_rois(1);
+    assert!(!expected.0.is_empty() && !expected.1.is_empty());
+    assert_eq!(deterministic_rois(4), expected);
+    assert_eq!(deterministic_rois(4), expected);
+}
+
+fn duplicated_rois(copies: usize) -> Vec<String> {
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let rois = rois.into_iter().flat_map(|x| std::iter::repeat(x).take(copies)).collect();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(5, 0.01, 20, false));
+
+    let mut saveto = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+    stream_rois(config, |_, items| {
+        ROIMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut saveto).unwrap();
+        Ok(())
+    })
+    .unwrap();
+    String::from_utf8(saveto.into_inner().unwrap()).unwrap().lines().map(|x| x.to_owned()).collect()
+}
+
+#[test]
+fn rois_duplicated() {
+    // Duplicated ROIs share the counting window & summaries, but each copy is still reported
+    let expected = duplicated_rois(1);
+    assert!(!expected.is_empty());
+    let expected = expected.into_iter().flat_map(|x| std::iter::repeat(x).take(10)).collect_vec();
+    assert_eq!(duplicated_rois(10), expected);
+}
+
+fn split_rois(parts: u64) -> (Vec<u8>, usize) {
+    // Each ROI gets a unique name and is split into the given number of BED lines
+    let rois = io::bed::parse(&*paths::GRCh38::ALU)
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .flat_map(|(ind, mut roi)| {
+            roi.name = format!("{}-{}", roi.name, ind);
+            let (start, end) = (roi.interval.range().start, roi.interval.range().end);
+            let step = ((end - start) / parts).max(1);
+            (0..parts)
+                .map(|x| start + x * step)
+                .filter(|x| *x < end)
+                .map(|x| {
+                    let mut part = roi.clone();
+                    let partend = if x + 2 * step > end { end } else { x + step };
+                    *part.interval.range_mut() = x..partend;
+                    part
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    // Small bins => parts of the same ROI are often binned separately
+    let (workload, shared) = ROIWorkload::group_by_name(ROIWorkload::from_bed(rois, 100, None, None, None));
+    assert!(shared.is_empty());
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.hooks.add_stat(Box::new(ROIEditingIndex::new("example".into(), "alu".into())));
+    config.group_by_name = true;
+
+    let mut reported = 0;
+    let summary = stream_rois(config, |_, items| {
+        reported += items.iter().map(|x| x.len()).sum::<usize>();
+        Ok(())
+    })
+    .unwrap();
+    let mut ei = csv::Writer::from_writer(vec![]);
+    let stats = summary.stats.into_iter().map(|x| x.into_any().1).collect();
+    ROIEditingIndex::collapse(stats).to_csv(&mut ei).unwrap();
+    (ei.into_inner().unwrap(), reported)
+}
+
+#[test]
+fn rois_grouped_by_name() {
+    // ROIs split across three BED lines are merged back before the EI => same EI & number of records
+    let expected = split_rois(1);
+    assert!(!expected.0.is_empty() && expected.1 > 0);
+    assert_eq!(split_rois(3), expected);
+}
+
+fn stats_only_rois(stats_only: bool) -> (Vec<u8>, usize) {
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.hooks.add_stat(Box::new(ROIEditingIndex::new("example".into(), "alu".into())));
+    if stats_only {
+        config.stats_only = true;
+        config.alnstats = false;
+        config.strand_depth = false;
+    } else {
+        // Same as the CLI: stats see all ROIs, the output is filtered afterwards
+        let filter: filters::ByMismatches = prefilters::ByMismatches::new(5, 0.01, 10, false).into();
+        config.hooks.add_filter(Box::new(filter));
+    }
+
+    let mut reported = 0;
+    let summary = stream_rois(config, |_, items| {
+        reported += items.iter().map(|x| x.len()).sum::<usize>();
+        Ok(())
+    })
+    .unwrap();
+    let mut ei = csv::Writer::from_writer(vec![]);
+    let stats = summary.stats.into_iter().map(|x| x.into_any().1).collect();
+    ROIEditingIndex::collapse(stats).to_csv(&mut ei).unwrap();
+    (ei.into_inner().unwrap(), reported)
+}
+
+#[test]
+fn rois_stats_only() {
+    // Nothing is reported, but the EI is exactly the same
+    let (expected, reported) = stats_only_rois(false);
+    assert!(!expected.is_empty() && reported > 0);
+    assert_eq!(stats_only_rois(true), (expected, 0));
+}
+
+fn unfiltered_rois(zero_coverage: bool) -> (usize, usize) {
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let mut config = ROIRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    // Same as the CLI with --no-output-filters (and --emit-zero-coverage)
+    if zero_coverage {
+        config.zero_coverage = true;
+    } else {
+        config.prefilter = Some(prefilters::ByMismatches::new(0, 0f32, 1, false));
+    }
+    let rois = run_rois(config).unwrap();
+    let reported = rois.iter().map(|x| x.len()).sum();
+    let uncovered = rois.iter().flat_map(|x| &x.data.mismatches).filter(|x| x.coverage() == 0f64).count();
+    (reported, uncovered)
+}
+
+#[test]
+fn rois_zero_coverage() {
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap().len();
+
+    // Unstranded library => exactly one row for each ROI
+    let (reported, uncovered) = unfiltered_rois(true);
+    assert_eq!(reported, rois);
+    assert!(uncovered > 0);
+
+    // Same covered ROIs without zeroed rows
+    assert_eq!(unfiltered_rois(false), (reported - uncovered, 0));
+}
+
+fn paired_tables(paired: bool, design: Option<StrandSpecificExperimentDesign>) -> (Vec<u8>, Vec<u8>) {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+
+    let rois = io::bed::parse(&*paths::GRCh38::ALU).unwrap();
+    let workload = ROIWorkload::from_bed(rois, 64000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+    let mut rois = ROIRunConfig::new(bamfiles.clone(), workload, maxwsize, refnucpred(), readfilter(3));
+    rois.design = design;
+    rois.hooks.add_stat(Box::new(ROIEditingIndex::new("example".into(), "alu".into())));
+
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 20000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let mut sites = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(3));
+    sites.design = design;
+    sites.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+
+    let (mut roisto, mut sitesto) = (csv::Writer::from_writer(vec![]), csv::Writer::from_writer(vec![]));
+    let onroi = |_: &str, items| {
+        ROIMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut roisto).unwrap();
+        Ok(())
+    };
+    let onsite = |_: &str, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut sitesto).unwrap();
+        Ok(())
+    };
+    let stats = if paired {
+        stream_paired(rois, sites, onroi, onsite).unwrap().0.stats
+    } else {
+        stream_sites(sites, onsite).unwrap();
+        stream_rois(rois, onroi).unwrap().stats
+    };
+
+    let mut ei = csv::Writer::from_writer(vec![]);
+    ROIEditingIndex::collapse(stats.into_iter().map(|x| x.into_any().1).collect()).to_csv(&mut ei).unwrap();
+    let mut rois = roisto.into_inner().unwrap();
+    rois.extend(ei.into_inner().unwrap());
+    (rois, sitesto.into_inner().unwrap())
+}
+
+#[test]
+fn rois_with_sites() {
+    // A single pass over the reads must produce the same tables & stats as the separate runs
+    for design in [None, Some(StrandSpecificExperimentDesign::Flip1Same2)] {
+        let expected = paired_tables(false, design);
+        assert!(!expected.0.is_empty() && !expected.1.is_empty());
+        assert_eq!(paired_tables(true, design), expected);
+    }
+}
+
+#[test]
+fn rois_with_sites_mismatched() {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let workload = ROIWorkload::from_bed(io::bed::parse(&*paths::GRCh38::ALU).unwrap(), 64000, None, None, None);
+    let rois = ROIRunConfig::new(bamfiles.clone(), workload, 64000, refnucpred(), readfilter(3));
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 20000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let mut sites = SiteRunConfig::new(bamfiles, workload, 20000, refnucpred(), readfilter(3));
+    sites.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+
+    let err = stream_paired(rois, sites, |_, _| Ok(()), |_, _| Ok(())).err().unwrap();
+    assert!(matches!(err, Error::Input(_)));
+    assert_eq!(err.to_string(), "ROIs & sites must be counted for the same library design");
+}
+
+#[test]
+fn sites() {
+    // Same as the deducted_strand regression test for the site subcommand
+    let expected = paths::expected::LOCI.join("deducted.csv");
+    assert!(expected.is_file());
+
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.overlaps_dedup = false;
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+
+    let items: usize = run_sites(config).unwrap().iter().map(|x| x.len()).sum();
+    // Minus the header
+    let lines = BufReader::new(File::open(&expected).unwrap()).lines().count();
+    assert_eq!(items, lines - 1);
+}
+
+#[test]
+fn sites_contigs_order() {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+    // Contigs absent in the requested order go last
+    config.contigs = Some(vec!["MT".to_owned(), "19".to_owned()]);
+
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    config.progress = Some(Box::new(Warnings(Arc::clone(&warnings))));
+
+    let reported: Vec<String> = run_sites(config).unwrap().iter().map(|x| x.contig().to_owned()).dedup().collect();
+    assert_eq!(reported, vec!["MT", "19", "17"]);
+
+    // Contigs without any results (e.g. 1) are reported last too => they must be mentioned
+    let warnings = warnings.lock().unwrap();
+    let unordered =
+        |contig: &str| format!("contig {} is absent in the requested contigs order, reported last.", contig);
+    for contig in ["1", "17"] {
+        assert!(warnings.contains(&unordered(contig)), "{:?}", warnings);
+    }
+    for contig in ["MT", "19"] {
+        assert!(!warnings.contains(&unordered(contig)), "{:?}", warnings);
+    }
+}
+
+// Progress that only records the issued warnings
+struct Warnings(Arc<Mutex<Vec<String>>>);
+
+impl Progress for Warnings {
+    fn start(&self, _: u64) {}
+
+    fn inc(&self, _: u64, _: u32) {}
+
+    fn finish(&self, _: usize, _: Stranded<u32>, _: u32, _: u32, _: u32, _: u64) {}
+
+    fn warn(&self, message: &str) {
+        self.0.lock().unwrap().push(message.to_owned());
+    }
+}
+
+#[test]
+fn sites_events() {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+    config.events = Some(Box::new(ChannelSink::new(sender)));
+
+    let mut streamed = Vec::new();
+    let summary = stream_sites(config, |_, items| {
+        streamed.extend(items.iter().map(|x| (x.contig().to_owned(), x.len())));
+        Ok(())
+    })
+    .unwrap();
+    // The sink is dropped with the config => the channel is closed
+    let observed: Vec<(String, usize)> = receiver.into_iter().map(|(contig, x)| (contig, x.len())).collect();
+    assert!(!observed.is_empty());
+    assert_eq!(observed, streamed);
+    assert_eq!(observed.iter().map(|x| x.1).sum::<usize>(), summary.items);
+}
+
+#[test]
+fn sites_error_model() {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let config = || {
+        let mut config = SiteRunConfig::new(bamfiles.clone(), workload.clone(), maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+        config
+    };
+
+    // First pass: background substitution rates
+    let mut first = config();
+    first.substitutions = true;
+    first.hooks.add_stat(Box::new(SubstitutionRates::new()));
+    let summary = stream_sites(first, |_, _| Ok(())).unwrap();
+    let background = SubstitutionRates::collapse(summary.stats.into_iter().map(|x| x.into_any().1).collect());
+    for nuc in [ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T] {
+        assert!(background.counts().rate(nuc, nuc) > 0.9);
+    }
+
+    // Second pass: the same sites, each one is tested
+    let baseline: usize = run_sites(config()).unwrap().iter().map(|x| x.len()).sum();
+    let mut second = config();
+    second.hooks.add_filter(Box::new(ByErrorRates::new(*background.counts(), 1)));
+    let sites = run_sites(second).unwrap();
+    assert_eq!(sites.iter().map(|x| x.len()).sum::<usize>(), baseline);
+    let pvalues: Vec<f64> = sites.iter().flat_map(|x| x.data.pvalue.iter().flatten()).copied().collect();
+    assert!(!pvalues.is_empty());
+    assert!(pvalues.iter().all(|x| (0f64..=1f64).contains(x)));
+}
+
+#[test]
+fn sites_hts_threads() {
+    let run = |hts_threads| {
+        let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+        let contigs = io::hts::contigs(&bamfiles).unwrap();
+        let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+        let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+        let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+        config.hts_threads = hts_threads;
+
+        let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+        stream_sites(config, |_, items| {
+            SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+                items,
+                &mut saveto,
+                &SiteColumn::all(),
+                Multimap::Full,
+                false,
+                None,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        saveto.into_inner().unwrap()
+    };
+    // Decompression in the shared pool must not change the results
+    let expected = run(0);
+    assert!(!expected.is_empty());
+    assert_eq!(run(2), expected);
+}
+
+fn prefetched_sites(prefetch: bool, threads: usize) -> Vec<u8> {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+    config.prefetch = prefetch;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+    pool.install(|| {
+        stream_sites(config, |_, items| {
+            SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+                items,
+                &mut saveto,
+                &SiteColumn::all(),
+                Multimap::Full,
+                false,
+                None,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap()
+    });
+    saveto.into_inner().unwrap()
+}
+
+#[test]
+fn sites_prefetch() {
+    // Decoding reads in a helper thread must not change the results
+    let expected = prefetched_sites(false, 2);
+    assert!(!expected.is_empty());
+    assert_eq!(prefetched_sites(true, 2), expected);
+}
+
+// cargo test --release --test pipeline -- --ignored --nocapture bench_prefetch
+#[test]
+#[ignore]
+fn bench_prefetch() {
+    for threads in [1, 4] {
+        for prefetch in [false, true] {
+            let started = std::time::Instant::now();
+            for _ in 0..10 {
+                prefetched_sites(prefetch, threads);
+            }
+            println!("threads={}, prefetch={}: {:?} per run", threads, prefetch, started.elapsed() / 10);
+        }
+    }
+}
+
+fn tagged_sites(tags: ByTags) -> Vec<u8> {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), tagsfilter(0, tags));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+
+    let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+    stream_sites(config, |_, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+            items,
+            &mut saveto,
+            &SiteColumn::all(),
+            Multimap::Full,
+            false,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        Ok(())
+    })
+    .unwrap();
+    saveto.into_inner().unwrap()
+}
+
+#[test]
+fn sites_read_groups() {
+    // All reads in the example belong to the same read group
+    let expected = tagged_sites(ByTags::new());
+    assert!(!expected.is_empty());
+
+    let tags = ByTags::new().with_read_groups([b"SRX6966474".to_vec()].into_iter().collect());
+    assert_eq!(tagged_sites(tags.clone()), expected);
+    let counts = tags.read_groups().unwrap();
+    assert!(counts.matched() > 0);
+    assert_eq!(counts.rejected(), 0);
+
+    let tags = ByTags::new().with_read_groups([b"SRX0000000".to_vec()].into_iter().collect());
+    assert!(tagged_sites(tags.clone()).is_empty());
+    let counts = tags.read_groups().unwrap();
+    assert!(counts.rejected() > 0);
+    assert_eq!(counts.matched(), 0);
+
+    // The example has no cell barcodes => nothing is counted
+    let tags = ByTags::new().with_barcodes(*b"CB", [b"AAACCTG-1".to_vec()].into_iter().collect());
+    assert!(tagged_sites(tags.clone()).is_empty());
+    assert_eq!(tags.barcodes().unwrap().matched(), 0);
+}
+
+#[test]
+fn sites_dump_reads() {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let dir = tempfile::TempDir::new().expect(TMP_CREATE_ERROR);
+    let prefix = dir.path().join("dumped").to_str().unwrap().to_owned();
+    let readfilter = readfilter(0).with_dump(Arc::new(io::hts::BamDump::new(&prefix, &bamfiles).unwrap()));
+    let stats = readfilter.stats();
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter);
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+    stream_sites(config, |_, _| Ok(())).unwrap();
+
+    // Some reads are rejected by the mapq (0 for multimappers), others by flags (e.g. secondary alignments)
+    for criterion in [Criterion::MapQ, Criterion::Flags] {
+        assert!(stats.rejected_by(criterion) > 0, "{}", criterion.name());
+    }
+    assert!(stats.accepted() > 0);
+
+    // Writers are closed once the run is over => dumped reads match the counters
+    let dumped = |what: &str| {
+        let mut reader = bam::Reader::from_path(format!("{}.{}.bam", prefix, what)).unwrap();
+        reader.records().map(|x| x.unwrap()).collect_vec()
+    };
+    let (accepted, rejected) = (dumped("accepted"), dumped("rejected"));
+    assert_eq!((accepted.len() as u64, rejected.len() as u64), (stats.accepted(), stats.rejected()));
+    let isok = |x: &bam::Record| x.mapq() >= 1 && x.flags() & 2820 == 0;
+    assert!(accepted.iter().all(isok));
+    assert!(!rejected.iter().any(isok));
+}
+
+// cargo test --release --test pipeline -- --ignored --nocapture bench_tags
+#[test]
+#[ignore]
+fn bench_tags() {
+    let readgroups = || ByTags::new().with_read_groups([b"SRX6966474".to_vec()].into_iter().collect());
+    for (name, tags) in [("disabled", ByTags::new()), ("read groups", readgroups())] {
+        let started = std::time::Instant::now();
+        for _ in 0..10 {
+            tagged_sites(tags.clone());
+        }
+        println!("{}: {:?} per run", name, started.elapsed() / 10);
+    }
+}
+
+#[test]
+fn sites_territory() {
+    let run = |binsize| {
+        let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+        let contigs = io::hts::contigs(&bamfiles).unwrap();
+        let workload: Vec<SiteWorkload> =
+            SiteWorkload::from_intervals(contigs, binsize, None::<Vec<Interval>>, None::<Vec<Interval>>)
+                .into_iter()
+                .map(|x| x.with_padding(1000))
+                .collect();
+        let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+        let considered: u64 = workload.iter().flat_map(|x| x.include()).map(|x| x.end - x.start).sum();
+
+        let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+        (considered, stream_sites(config, |_, _| Ok(())).unwrap())
+    };
+
+    let (considered, expected) = run(64000);
+    assert_eq!(expected.contigs.iter().map(|x| x.emitted).sum::<usize>(), expected.items);
+    let covered: u64 = expected.contigs.iter().map(|x| x.covered).sum();
+    assert!(covered > 0 && covered <= considered);
+    // Same territory regardless of the bin size (reads spanning bins are handled by the padding)
+    let (_, summary) = run(20000);
+    assert_eq!(summary.contigs, expected.contigs);
+}
+
+#[test]
+fn sites_replicates() {
+    let run = |bamfiles: Vec<PathBuf>, prefilter, replicates| {
+        let contigs = io::hts::contigs(&bamfiles).unwrap();
+        let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+        let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+        let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        // Mates are matched by name => keep identical reads from different files apart
+        config.overlaps_dedup = false;
+        config.prefilter = Some(prefilter);
+        config.replicates = replicates;
+        run_sites(config).unwrap().iter().map(|x| x.len()).sum::<usize>()
+    };
+
+    let bam = PathBuf::from(&*paths::bam::EXAMPLE);
+    let expected = run(vec![bam.clone()], prefilters::ByMismatches::new(3, 0.01, 20, false), None);
+    assert!(expected > 0);
+    // The same file twice => pooled counts are doubled, while each file supports the same sites
+    let replicates = vec![bam.clone(), bam];
+    let pooled = prefilters::ByMismatches::new(6, 0.01, 40, false);
+    for (minmismatches, minfiles, items) in [(3, 2, expected), (0, 1, expected), (3, 3, 0)] {
+        let support = Some(prefilters::ByReplicates::new(minmismatches, minfiles));
+        assert_eq!(run(replicates.clone(), pooled, support), items);
+    }
+}
+
+#[test]
+fn sites_samples() {
+    let run = |bamfiles: Vec<PathBuf>, per_source| {
+        let contigs = io::hts::contigs(&bamfiles).unwrap();
+        let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+        let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+        let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        config.overlaps_dedup = false;
+        config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+        config.per_source = per_source;
+        run_sites(config).unwrap()
+    };
+
+    let bam = PathBuf::from(&*paths::bam::EXAMPLE);
+    let expected: usize = run(vec![bam.clone()], false).iter().map(|x| x.len()).sum();
+    // Each copy of the file passes the filter exactly where the single file does
+    let sites = run(vec![bam.clone(), bam], true);
+    assert_eq!(sites.iter().map(|x| x.len()).sum::<usize>(), expected);
+    for site in sites.iter().flat_map(|x| x.data.iter()) {
+        assert_eq!(site.persample.len(), 2);
+        assert_eq!(site.persample[0], site.persample[1]);
+        assert_eq!(site.persample[0].coverage() * 2, site.sequenced.coverage());
+    }
+}
+
+#[test]
+fn sites_qualities() {
+    let run = |qualities| {
+        let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+        let contigs = io::hts::contigs(&bamfiles).unwrap();
+        let workload = SiteWorkload::from_intervals(contigs, 64000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+        let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+        let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+        config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+        config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+        config.base_qualities = qualities;
+        run_sites(config).unwrap()
+    };
+
+    let (plain, sites) = (run(false), run(true));
+    // Counting is not affected
+    let counts = |x: &[SiteMismatchesVec]| x.iter().flat_map(|x| x.data.sequenced.clone()).collect_vec();
+    assert_eq!(counts(&plain), counts(&sites));
+    assert!(!sites.is_empty());
+    let mut tested = 0;
+    for site in sites.iter().flat_map(|x| x.data.iter()) {
+        // Bases below the quality threshold are never counted
+        if let (Some(meanq), Some(minq)) = site.qualities.alt(site.sequenced, site.prednuc) {
+            assert!(minq >= 20 && minq as f32 <= meanq);
+            tested += 1;
+        }
+    }
+    assert!(tested > 0);
+}
+
+fn padded_sites(binsize: u64, binpad: u64, adaptive: bool) -> Vec<u8> {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let (include, exclude) = (None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let workload = if adaptive {
+        let index = io::hts::indexed_density(&bamfiles[0], None).unwrap().unwrap();
+        let density = ReadsDensity::from_index(io::hts::BAI_WINDOW, index);
+        SiteWorkload::adaptive(contigs, binsize, include, exclude, &density)
+    } else {
+        SiteWorkload::from_intervals(contigs, binsize, include, exclude)
+    };
+    let workload: Vec<SiteWorkload> = workload.into_iter().map(|x| x.with_padding(binpad)).collect();
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles, workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+
+    let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+    stream_sites(config, |_, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+            items,
+            &mut saveto,
+            &SiteColumn::all(),
+            Multimap::Full,
+            false,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        Ok(())
+    })
+    .unwrap();
+    saveto.into_inner().unwrap()
+}
+
+#[test]
+fn sites_binpad() {
+    // With padding, results must not depend on the bin size
+    let expected = padded_sites(64000, 1000, false);
+    assert!(!expected.is_empty());
+    for binsize in [20000, 100000] {
+        assert_eq!(padded_sites(binsize, 1000, false), expected);
+    }
+}
+
+#[test]
+fn sites_bin_boundaries() {
+    // Reads spanning bin boundaries are counted for each overlapping bin => same per-site counts without padding
+    let expected = padded_sites(64000, 0, false);
+    assert!(!expected.is_empty());
+    for binsize in [5000, 20000, 100000] {
+        assert_eq!(padded_sites(binsize, 0, false), expected);
+    }
+}
+
+#[test]
+fn sites_adaptive_bins() {
+    // Only the scheduling changes => same results as for fixed bins
+    let expected = padded_sites(64000, 1000, false);
+    assert!(!expected.is_empty());
+    for binsize in [20000, 64000] {
+        assert_eq!(padded_sites(binsize, 1000, true), expected);
+    }
+}
+
+fn streamed_sites(stream: bool, binsize: u64, binpad: u64) -> Vec<u8> {
+    let bamfiles = vec![PathBuf::from(&*paths::bam::EXAMPLE)];
+    let contigs = io::hts::contigs(&bamfiles).unwrap();
+    let workload: Vec<SiteWorkload> =
+        SiteWorkload::from_intervals(contigs, binsize, None::<Vec<Interval>>, None::<Vec<Interval>>)
+            .into_iter()
+            .map(|x| x.with_padding(binpad))
+            .collect();
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+
+    let mut config = SiteRunConfig::new(bamfiles.clone(), workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Flip1Same2);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.01, 20, false));
+    // Streamed contigs are always reported in the header order
+    config.contigs = Some(HTSStream::open(&bamfiles, None).unwrap().order());
+    if stream {
+        config.stream = Some(HTSStream::open(&bamfiles, None).unwrap());
+    }
+
+    let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+    stream_sites(config, |_, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+            items,
+            &mut saveto,
+            &SiteColumn::all(),
+            Multimap::Full,
+            false,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        Ok(())
+    })
+    .unwrap();
+    saveto.into_inner().unwrap()
+}
+
+#[test]
+fn sites_stream() {
+    // Reading the sorted input sequentially must match the indexed access
+    for (binsize, binpad) in [(64000, 0), (5000, 0), (5000, 1000)] {
+        let expected = streamed_sites(false, binsize, binpad);
+        assert!(!expected.is_empty());
+        assert_eq!(streamed_sites(true, binsize, binpad), expected, "{} {}", binsize, binpad);
+    }
+}
+
+#[test]
+fn synthetic() {
+    // Same as the selftest subcommand on a smaller dataset
+    let contigs = vec![("chr1".to_owned(), 20_000), ("chr2".to_owned(), 10_000)];
+    let data = Simulation::new(42, contigs).with_reads(100, 50).with_spikes(20, vec![0.1, 0.5, 0.9]).run();
+    let tmp = tempdir().expect(TMP_CREATE_ERROR);
+    let (fasta, bam) = data.write(tmp.path()).unwrap();
+    let refnucpred = || {
+        let reader = BasicFastaReader::new(fasta.clone()).unwrap();
+        Box::new(AutoRef::new(20, 0.95, true, Box::new(reader))) as Box<dyn RefEngine>
+    };
+
+    let contigs = io::hts::contigs(&[&bam]).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 4000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let prefilter = prefilters::ByMismatches::new(3, 0.05, 10, false);
+    let mut config = SiteRunConfig::new(vec![bam.clone()], workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    config.prefilter = Some(prefilter);
+    let sites = data.check_sites(&run_sites(config).unwrap(), &prefilter, 0.01);
+    assert_eq!(sites.spikes, 40);
+    assert!(sites.detectable > 20 && sites.passed(1e-4), "{:?}", sites);
+    // All reads are counted => reported frequencies are exactly the simulated ones
+    assert_eq!(sites.maxdev, 0f32);
+
+    let tiles = workload::roi::tiles(data.lengths(), 1000, 1000);
+    let workload = ROIWorkload::from_bed(tiles, 4000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+    let mut config = ROIRunConfig::new(vec![bam], workload, maxwsize, refnucpred(), readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    let rois = data.check_rois(&run_rois(config).unwrap(), 0.01);
+    // 30 tiles on both strands
+    assert_eq!(rois.rois, 60);
+    assert!(rois.edited > 0 && rois.passed(), "{:?}", rois);
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
+
+fn cached_sites(fasta: &Path, bam: &Path, megabases: u32) -> (Vec<u8>, u64) {
+    let reader = BasicFastaReader::new(fasta.to_owned()).unwrap().with_cache(megabases);
+    let fetched = reader.fetched();
+    let refnucpred = Box::new(AutoRef::new(20, 0.95, true, Box::new(reader)));
+
+    // Heavily overlapping bins: each position is covered by 5 padded bins
+    let contigs = io::hts::contigs(&[bam]).unwrap();
+    let workload: Vec<SiteWorkload> =
+        SiteWorkload::from_intervals(contigs, 1000, None::<Vec<Interval>>, None::<Vec<Interval>>)
+            .into_iter()
+            .map(|x| x.with_padding(2000))
+            .collect();
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let mut config = SiteRunConfig::new(vec![bam.to_owned()], workload, maxwsize, refnucpred, readfilter(0));
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.05, 10, false));
+
+    // A single worker => a single cache
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let mut saveto = csv::WriterBuilder::new().delimiter(b'\t').from_writer(vec![]);
+    pool.install(|| {
+        stream_sites(config, |_, items| {
+            SiteMismatchesVec::ugly_in_contig_sort_and_to_tsv(
+                items,
+                &mut saveto,
+                &SiteColumn::all(),
+                Multimap::Full,
+                false,
+                None,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap()
+    });
+    (saveto.into_inner().unwrap(), fetched.load(Ordering::Relaxed))
+}
+
+#[test]
+fn sites_ref_cache() {
+    let contigs = vec![("chr1".to_owned(), 20_000), ("chr2".to_owned(), 10_000)];
+    let data = Simulation::new(42, contigs).with_reads(100, 50).with_spikes(20, vec![0.5]).run();
+    let tmp = tempdir().expect(TMP_CREATE_ERROR);
+    let (fasta, bam) = data.write(tmp.path()).unwrap();
+
+    let (expected, uncached) = cached_sites(&fasta, &bam, 0);
+    assert!(!expected.is_empty());
+    let (sites, cached) = cached_sites(&fasta, &bam, 4);
+    assert_eq!(sites, expected);
+    // Both contigs fit in a single cached block => each base is read from the FASTA exactly once
+    assert_eq!(cached, 30_000);
+    assert!(uncached > 3 * cached, "{}", uncached);
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
+
+fn indexed_sites(fasta: &Path, bam: &Path, index: Option<PathBuf>) -> Vec<u8> {
+    let reader = BasicFastaReader::new(fasta.to_owned()).unwrap();
+    let refnucpred = Box::new(AutoRef::new(20, 0.95, true, Box::new(reader)));
+
+    let contigs = io::hts::contigs(&[bam]).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs, 4000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let mut config = SiteRunConfig::new(vec![bam.to_owned()], workload, maxwsize, refnucpred, readfilter(0));
+    config.indexes = index.map(|x| vec![x]);
+    config.design = Some(StrandSpecificExperimentDesign::Same);
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.05, 10, false));
+
+    let mut saveto = csv::Writer::from_writer(vec![]);
+    stream_sites(config, |_, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut saveto).unwrap();
+        Ok(())
+    })
+    .unwrap();
+    saveto.into_inner().unwrap()
+}
+
+#[test]
+fn sites_explicit_index() {
+    let contigs = vec![("chr1".to_owned(), 20_000), ("chr2".to_owned(), 10_000)];
+    let data = Simulation::new(42, contigs).with_reads(100, 50).with_spikes(20, vec![0.5]).run();
+    let tmp = tempdir().expect(TMP_CREATE_ERROR);
+    let (fasta, bam) = data.write(tmp.path()).unwrap();
+    let expected = indexed_sites(&fasta, &bam, None);
+    assert!(!expected.is_empty());
+
+    // CSI index stored away from the BAM file, the default one is removed
+    let csi = tmp.path().join("indexes").join("reads.bam.csi");
+    std::fs::create_dir(csi.parent().unwrap()).unwrap();
+    bam::index::build(&bam, Some(&csi), bam::index::Type::Csi(14), 1).unwrap();
+    let bai = io::hts::find_index(&bam).unwrap();
+    std::fs::remove_file(&bai).unwrap();
+    assert_eq!(io::hts::find_index(&bam), None);
+    assert_eq!(indexed_sites(&fasta, &bam, Some(csi)), expected);
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
+
+fn cram_tables(
+    fasta: &Path,
+    inputs: Vec<PathBuf>,
+    design: Option<StrandSpecificExperimentDesign>,
+) -> (Vec<u8>, Vec<u8>) {
+    let refnucpred = || {
+        let reader = BasicFastaReader::new(fasta.to_owned()).unwrap();
+        Box::new(AutoRef::new(20, 0.95, true, Box::new(reader))) as Box<dyn RefEngine>
+    };
+
+    let contigs = io::hts::contigs(&inputs).unwrap();
+    let workload = SiteWorkload::from_intervals(contigs.clone(), 4000, None::<Vec<Interval>>, None::<Vec<Interval>>);
+    let maxwsize = workload.iter().map(|x| x.range().end - x.range().start).max().unwrap() as usize;
+    let mut config = SiteRunConfig::new(inputs.clone(), workload, maxwsize, refnucpred(), readfilter(0));
+    config.reference = Some(fasta.to_owned());
+    config.design = design;
+    config.prefilter = Some(prefilters::ByMismatches::new(3, 0.05, 10, false));
+    let mut sites = csv::Writer::from_writer(vec![]);
+    stream_sites(config, |_, items| {
+        SiteMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut sites).unwrap();
+        Ok(())
+    })
+    .unwrap();
+
+    let lengths = contigs.iter().map(|x| (x.contig().to_owned(), x.range().end)).collect();
+    let workload = ROIWorkload::from_bed(workload::roi::tiles(lengths, 1000, 1000), 4000, None, None, None);
+    let maxwsize = workload.iter().map(|x| x.len()).max().unwrap();
+    let mut config = ROIRunConfig::new(inputs, workload, maxwsize, refnucpred(), readfilter(0));
+    config.reference = Some(fasta.to_owned());
+    config.design = design;
+    let mut rois = csv::Writer::from_writer(vec![]);
+    stream_rois(config, |_, items| {
+        ROIMismatchesVec::ugly_in_contig_sort_and_to_csv(items, &mut rois).unwrap();
+        Ok(())
+    })
+    .unwrap();
+    (sites.into_inner().unwrap(), rois.into_inner().unwrap())
+}
+
+#[test]
+fn cram_inputs() {
+    let contigs = vec![("chr1".to_owned(), 20_000), ("chr2".to_owned(), 10_000)];
+    let data = Simulation::new(42, contigs).with_reads(100, 50).with_spikes(20, vec![0.5]).run();
+    let tmp = tempdir().expect(TMP_CREATE_ERROR);
+    let (fasta, bam) = data.write(tmp.path()).unwrap();
+
+    // Same reads compressed against the simulated reference
+    let cram = tmp.path().join("reads.cram");
+    let mut reader = bam::Reader::from_path(&bam).unwrap();
+    let header = bam::Header::from_template(reader.header());
+    let mut writer = bam::Writer::from_path(&cram, &header, bam::Format::Cram).unwrap();
+    writer.set_reference(&fasta).unwrap();
+    for record in reader.records() {
+        writer.write(&record.unwrap()).unwrap();
+    }
+    drop(writer);
+    io::hts::build_index(&cram, 1).unwrap();
+    assert!(io::hts::is_cram_file(&cram).unwrap() && !io::hts::is_cram_file(&bam).unwrap());
+
+    for design in [None, Some(StrandSpecificExperimentDesign::Same)] {
+        let expected = cram_tables(&fasta, vec![bam.clone()], design);
+        assert!(!expected.0.is_empty() && !expected.1.is_empty());
+        assert_eq!(cram_tables(&fasta, vec![cram.clone()], design), expected);
+
+        // Mixed inputs are pooled as usual
+        let expected = cram_tables(&fasta, vec![bam.clone(), bam.clone()], design);
+        assert_eq!(cram_tables(&fasta, vec![bam.clone(), cram.clone()], design), expected);
+    }
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
+
+#[test]
+fn bgzipped_reference() {
+    // The test reference is bgzipped => it must match a plain copy of the same sequence
+    let gzipped = PathBuf::from(&*paths::GRCh38::FASTA);
+    let mut compressed = BasicFastaReader::new(gzipped.clone()).unwrap();
+    compressed.fetch("MT", 0..16569).unwrap();
+    let sequence = compressed.result().to_vec();
+    assert_eq!(sequence.len(), 16569);
+
+    let tmp = tempdir().expect(TMP_CREATE_ERROR);
+    let fasta = tmp.path().join("MT.fa");
+    let mut lines = vec![">MT".to_owned()];
+    lines.extend(sequence.chunks(60).map(|x| x.iter().map(|n| n.symbol()).collect::<String>()));
+    std::fs::write(&fasta, lines.join("\n") + "\n").unwrap();
+    std::fs::write(tmp.path().join("MT.fa.fai"), "MT\t16569\t4\t60\t61\n").unwrap();
+
+    let mut plain = BasicFastaReader::new(fasta).unwrap();
+    // Clones reopen the file => per-thread readers must work for bgzipped files as well
+    let mut cloned = compressed.clone();
+    for range in [0..60, 59..61, 1000..5000, 16500..16569] {
+        plain.fetch("MT", range.clone()).unwrap();
+        compressed.fetch("MT", range.clone()).unwrap();
+        cloned.fetch("MT", range.clone()).unwrap();
+        assert_eq!(compressed.result(), plain.result());
+        assert_eq!(cloned.result(), plain.result());
+    }
+
+    // A bgzipped FASTA without the .gzi index is rejected upfront
+    let nogzi = tmp.path().join("nogzi.fa.gz");
+    std::fs::copy(&gzipped, &nogzi).unwrap();
+    std::fs::copy(gzipped.with_extension("gz.fai"), tmp.path().join("nogzi.fa.gz.fai")).unwrap();
+    let error = BasicFastaReader::new(nogzi).err().unwrap().to_string();
+    assert!(error.contains(".gzi"), "{}", error);
+    tmp.close().expect(TMP_DELETE_ERROR);
+}
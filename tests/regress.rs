@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 
@@ -5,7 +6,7 @@ use clap::App;
 use file_diff::diff_files;
 use indicatif::{MultiProgress, ProgressBar};
 use rayon::ThreadPoolBuilder;
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempDir};
 
 use reat::cli;
 
@@ -33,13 +34,20 @@ fn run(args: &[&str], launch: SubCommand) {
     let app = App::new("test").args(app);
     let args = app.get_matches_from(args);
 
-    let core = cli::shared::args::CoreArgs::new(&args, factory);
+    let core = cli::shared::args::CoreArgs::new(&args, factory).unwrap_or_else(|e| panic!("{}", e));
+    // Run info is saved next to the output by default and isn't a part of the regression fixtures
+    let runinfo = core.runinfo.clone();
     let pool = ThreadPoolBuilder::new().num_threads(core.threads).build().expect(THREAD_POOL_ERROR);
     pool.scope(|_| match launch {
         SubCommand::sites => cli::sites::run(&args, core, factory),
         SubCommand::rois => cli::rois::run(&args, core, factory),
-    });
+    })
+    .unwrap_or_else(|e| panic!("{}", e));
     masterbar.join_and_clear().expect("Failed to join pbars. Leak?");
+
+    if let Some(runinfo) = runinfo {
+        std::fs::remove_file(runinfo).expect(TMP_DELETE_ERROR);
+    }
 }
 
 fn same(first: &Path, second: &Path) -> bool {
@@ -54,6 +62,12 @@ fn same(first: &Path, second: &Path) -> bool {
     diff_files(&mut first, &mut second)
 }
 
+// CSV rows as column -> value maps, the schema line (if any) is skipped
+fn table(path: &Path) -> Vec<HashMap<String, String>> {
+    let mut reader = csv::ReaderBuilder::new().comment(Some(b'#')).from_path(path).unwrap_or_else(|e| panic!("{}", e));
+    reader.deserialize().map(|x| x.unwrap()).collect()
+}
+
 mod site {
     use super::*;
 
@@ -61,7 +75,8 @@ mod site {
     fn trimming() {
         // ../target/release/reat site --input resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "f/s" \
-        // -n Test -5 10 --trim3 2 -o resources/expected/site/trimmed.csv
+        // -n Test -5 10 --trim3 2 -o resources/expected/site/trimmed.csv --no-overlap-dedup --no-ref-source \
+        // --no-strand-depth --no-schema-line
         let expected = paths::expected::LOCI.join("trimmed.csv");
         assert!(expected.is_file());
 
@@ -70,6 +85,10 @@ mod site {
         let args = [
             "test", "--input", &paths::bam::EXAMPLE, "--reference", &paths::GRCh38::FASTA, "-s", "f/s",
             "-n", "Test", "-5", "10", "--trim3", "2", "-o", tmp.path().to_str().unwrap(),
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::sites);
 
@@ -81,7 +100,8 @@ mod site {
     fn deducted_strand() {
         // ../target/release/reat site --input resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz \
-        // -s "f/s" -n Test --out-min-cov 20 -o resources/expected/site/deducted.csv
+        // -s "f/s" -n Test --out-min-cov 20 -o resources/expected/site/deducted.csv --no-overlap-dedup --no-ref-source \
+        // --no-strand-depth --no-schema-line
         let expected = paths::expected::LOCI.join("deducted.csv");
         assert!(expected.is_file());
 
@@ -90,6 +110,10 @@ mod site {
         let args = [
             "test", "--input", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s",
             "-n", "Test", "--out-min-cov", "20", "-o", tmp.path().to_str().unwrap(),
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::sites);
 
@@ -102,7 +126,8 @@ mod site {
         // ../target/release/reat site --input resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz \
         // -s u --hyperedit --ref-min-cov 30 --annotation resources/GRCh38/Homo_sapiens.GRCh38.104.gff3.gz \
-        // --str-min-freq 0.01 --str-min-mismatches 5 -o resources/expected/site/predicted.csv
+        // --str-min-freq 0.01 --str-min-mismatches 5 -o resources/expected/site/predicted.csv --no-overlap-dedup --no-ref-source \
+        // --no-strand-depth --no-schema-line
         let expected = paths::expected::LOCI.join("predicted.csv");
         assert!(expected.is_file());
 
@@ -112,6 +137,10 @@ mod site {
             "test", "-i", &paths::bam::EXAMPLE, "--reference", &paths::GRCh38::FASTA, "--stranding", "u",
             "--hyperedit", "--ref-min-cov", "30", "--annotation", &paths::GRCh38::GFF3, "--str-min-freq", "0.01",
             "--str-min-mismatches", "5", "-o", tmp.path().to_str().unwrap(),
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::sites);
 
@@ -123,7 +152,8 @@ mod site {
     fn multiple_files() {
         // ../target/release/reat site --input resources/bam/SRX6966474.bam resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s f -t 12 --in-flags 67 \
-        // --out-min-freq 0.1 -o resources/expected/site/doubled.csv
+        // --out-min-freq 0.1 -o resources/expected/site/doubled.csv --no-overlap-dedup --no-ref-source \
+        // --no-strand-depth --no-schema-line
         let expected = paths::expected::LOCI.join("doubled.csv");
         assert!(expected.is_file());
 
@@ -133,16 +163,211 @@ mod site {
             "test", "-i", &paths::bam::EXAMPLE, &paths::bam::EXAMPLE, "--reference", &paths::GRCh38::FASTA,
             "--stranding", "f", "-t", "2", "--in-flags", "67", "--out-min-freq", "0.1",
             "-o", tmp.path().to_str().unwrap(),
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
+        ];
+        run(&args, SubCommand::sites);
+
+        assert!(same(tmp.path(), expected.as_path()));
+        tmp.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn checkpoint() {
+        // Same as deducted_strand, but interrupted after the first contig and resumed
+        let expected = paths::expected::LOCI.join("deducted.csv");
+        assert!(expected.is_file());
+
+        let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        let checkpoint = TempDir::new().expect(TMP_CREATE_ERROR);
+        #[rustfmt::skip]
+        let args = [
+            "test", "--input", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s",
+            "-n", "Test", "--out-min-cov", "20", "-o", tmp.path().to_str().unwrap(),
+            "--checkpoint", checkpoint.path().to_str().unwrap(),
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
+        ];
+        run(&args, SubCommand::sites);
+        assert!(same(tmp.path(), expected.as_path()));
+
+        // Drop all but the first finished contig
+        let mut markers: Vec<_> = std::fs::read_dir(checkpoint.path())
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().map_or(false, |ext| ext == "json"))
+            .collect();
+        markers.sort();
+        assert!(markers.len() > 1);
+        for marker in &markers[1..] {
+            std::fs::remove_file(marker).expect(TMP_DELETE_ERROR);
+        }
+        std::fs::write(tmp.path(), b"").expect(TMP_CREATE_ERROR);
+
+        run(&args, SubCommand::sites);
+        assert!(same(tmp.path(), expected.as_path()));
+        tmp.close().expect(TMP_DELETE_ERROR);
+        checkpoint.close().expect(TMP_DELETE_ERROR);
+    }
+}
+
+mod index {
+    use super::*;
+
+    fn unindexed() -> (TempDir, String) {
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let bam = dir.path().join("unindexed.bam");
+        std::fs::copy(&*paths::bam::EXAMPLE, &bam).expect(TMP_CREATE_ERROR);
+        (dir, bam.to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    #[should_panic(expected = "samtools index")]
+    fn missing() {
+        let (_dir, bam) = unindexed();
+        let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        #[rustfmt::skip]
+        let args = [
+            "test", "--input", &bam, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "-o", tmp.path().to_str().unwrap(),
         ];
         run(&args, SubCommand::sites);
+    }
+
+    #[test]
+    fn build() {
+        // Same as site::deducted_strand, but the index is built on the fly
+        let expected = paths::expected::LOCI.join("deducted.csv");
+        assert!(expected.is_file());
 
+        let (dir, bam) = unindexed();
+        let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        #[rustfmt::skip]
+        let args = [
+            "test", "--input", &bam, "-r", &paths::GRCh38::FASTA, "-s", "f/s",
+            "-n", "Test", "--out-min-cov", "20", "-o", tmp.path().to_str().unwrap(),
+            "--build-index",
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-strand-depth",
+            "--no-schema-line",
+        ];
+        run(&args, SubCommand::sites);
+
+        assert!(dir.path().join("unindexed.bam.bai").is_file());
         assert!(same(tmp.path(), expected.as_path()));
         tmp.close().expect(TMP_DELETE_ERROR);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+}
+
+mod errors {
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn malformed_bed() {
+        // Invalid inputs are reported as a single line and the binary exits with a non-zero code
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let bed = dir.path().join("exclude.bed");
+        std::fs::write(&bed, "17\t100\t200\n17\t1e3\t2000\n").expect(TMP_CREATE_ERROR);
+        let saveto = dir.path().join("sites.csv");
+
+        let output = Command::new(env!("CARGO_BIN_EXE_reat"))
+            .args(["site", "--input", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s"])
+            .args(["--progress", "none", "--exclude", bed.to_str().unwrap(), "-o", saveto.to_str().unwrap()])
+            .output()
+            .expect("Failed to launch reat");
+        assert_eq!(output.status.code(), Some(1));
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let expected = format!("Error: {}:2: invalid start coordinate \"1e3\"", bed.display());
+        assert_eq!(stderr.lines().last(), Some(expected.as_str()), "{}", stderr);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn incompatible_site_options() {
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let saveto = dir.path().join("sites.csv");
+        for (extra, expected) in [
+            (
+                &["--samples"][..],
+                "Error: Sample names must be unique, use NAME=PATH to name input files explicitly: \
+                SRX6966474 SRX6966474",
+            ),
+            (
+                &["--format", "mpileup", "--with-quality"],
+                "Error: Base qualities are not reported in the mpileup output format",
+            ),
+        ] {
+            let output = Command::new(env!("CARGO_BIN_EXE_reat"))
+                .args(["site", "--input", &paths::bam::EXAMPLE, &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA])
+                .args(["-s", "f/s", "--progress", "none", "-o", saveto.to_str().unwrap()])
+                .args(extra)
+                .output()
+                .expect("Failed to launch reat");
+            assert_eq!(output.status.code(), Some(1));
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert_eq!(stderr.lines().last(), Some(expected), "{}", stderr);
+        }
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn empty() {
+        // Contig 1 is present in the BAM header, but there are no reads aligned to it
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let saveto = dir.path().join("rois.csv");
+        let roi = |name: &str, content: &str| {
+            let bed = dir.path().join(name);
+            std::fs::write(&bed, content).expect(TMP_CREATE_ERROR);
+            bed.to_str().unwrap().to_owned()
+        };
+        let (offtarget, uncovered) = (roi("offtarget.bed", "1\t1000\t2000\n"), roi("uncovered.bed", "17\t100\t200\n"));
+        #[rustfmt::skip]
+        let args = |bed: &str| [
+            "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--rois", bed,
+            "-o", saveto.to_str().unwrap(),
+        ].map(|x| x.to_owned());
+
+        for (bed, reason) in [
+            (&offtarget, "processed contigs have no aligned reads according to the BAM indexes (1)"),
+            (&uncovered, "no reads overlap the processed regions"),
+        ] {
+            let output = Command::new(env!("CARGO_BIN_EXE_reat"))
+                .args(["roi", "--progress", "none"])
+                .args(args(bed))
+                .output()
+                .expect("Failed to launch reat");
+            assert_eq!(output.status.code(), Some(3));
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let last = stderr.lines().last().unwrap();
+            assert!(last.starts_with("Error: no reads were counted for any of 1 genome bins/ROIs"), "{}", stderr);
+            assert!(last.contains(reason) && last.ends_with("Use --allow-empty to accept empty results"), "{}", last);
+        }
+
+        // Only a warning with --allow-empty, the table is written as usual
+        let mut allowed = vec!["test".to_owned()];
+        allowed.extend(args(&offtarget));
+        allowed.push("--allow-empty".to_owned());
+        run(&allowed.iter().map(|x| x.as_str()).collect::<Vec<_>>(), SubCommand::rois);
+        assert!(table(&saveto).is_empty());
+        dir.close().expect(TMP_DELETE_ERROR);
     }
 }
 
 mod rois {
     use std::fs;
+    use std::io::Read;
+    use std::process::Command;
+
+    use flate2::read::MultiGzDecoder;
+    use reat::core::hooks::stats::ROIEditingIndex;
 
     use super::*;
 
@@ -151,7 +376,8 @@ mod rois {
         // ../target/release/reat roi -i resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "f/s" --out-min-cov 30 -n Test \
         // --in-flags=3 --trim5=2 -3 10 --rois resources/GRCh38/Alu.bed.gz --hyperedit \
-        // -o resources/expected/rois/trimmed.csv
+        // -o resources/expected/rois/trimmed.csv --no-background --no-overlap-dedup --no-ref-source \
+        // --no-aln-stats --no-strand-depth --no-schema-line
         let expected = paths::expected::ROIS.join("trimmed.csv");
         assert!(expected.is_file());
 
@@ -160,7 +386,12 @@ mod rois {
         let args = [
             "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--out-min-cov", "30",
             "-n", "Test", "--in-flags", "3", "--trim5", "2", "-3", "10", "--rois", &paths::GRCh38::ALU,
-            "--hyperedit", "-o", tmp.path().to_str().unwrap(),
+            "--hyperedit", "-o", tmp.path().to_str().unwrap(), "--no-background",
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-aln-stats",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::rois);
 
@@ -172,7 +403,8 @@ mod rois {
     fn deducted_strand() {
         // ../target/release/reat roi -i resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "f/s" --out-min-cov 20 -n Test \
-        // --in-flags=3 --rois resources/GRCh38/Alu.bed.gz --hyperedit -o resources/expected/rois/deducted.csv
+        // --in-flags=3 --rois resources/GRCh38/Alu.bed.gz --hyperedit -o resources/expected/rois/deducted.csv \
+        // --no-background --no-overlap-dedup --no-ref-source --no-aln-stats --no-strand-depth --no-schema-line
         let expected = paths::expected::ROIS.join("deducted.csv");
         assert!(expected.is_file());
 
@@ -181,7 +413,12 @@ mod rois {
         let args = [
             "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--out-min-cov", "20",
             "-n", "Test", "--in-flags", "3", "--rois", &paths::GRCh38::ALU, "--hyperedit",
-            "-o", tmp.path().to_str().unwrap(),
+            "-o", tmp.path().to_str().unwrap(), "--no-background",
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-aln-stats",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::rois);
 
@@ -195,7 +432,8 @@ mod rois {
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "u" \
         // --rois resources/GRCh38/Alu.bed.gz --ref-min-cov=30 \
         // --annotation resources/GRCh38/Homo_sapiens.GRCh38.104.gff3.gz --str-min-freq 0.01 \
-        // --str-min-mismatches 5 -o resources/expected/rois/predicted.csv
+        // --str-min-mismatches 5 -o resources/expected/rois/predicted.csv --no-background --no-overlap-dedup --no-ref-source \
+        // --no-aln-stats --no-strand-depth --no-schema-line --ignore-roi-strand
         let expected = paths::expected::ROIS.join("predicted.csv");
         assert!(expected.is_file());
 
@@ -205,7 +443,13 @@ mod rois {
             "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "--stranding", "u",
             "--rois", &paths::GRCh38::ALU, "--ref-min-cov", "30",
             "--annotation", &paths::GRCh38::GFF3, "--str-min-freq", "0.01",
-            "--str-min-mismatches", "5", "-o", tmp.path().to_str().unwrap(),
+            "--str-min-mismatches", "5", "-o", tmp.path().to_str().unwrap(), "--no-background",
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-aln-stats",
+            "--no-strand-depth",
+            "--no-schema-line",
+            "--ignore-roi-strand",
         ];
         run(&args, SubCommand::rois);
 
@@ -217,7 +461,8 @@ mod rois {
     fn multiple_files() {
         // ../target/release/reat roi -i resources/bam/SRX6966474.bam resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "s/f" --ref-min-freq 0.8 \
-        // --rois resources/GRCh38/Alu.bed.gz -o resources/expected/rois/doubled.csv -t 2
+        // --rois resources/GRCh38/Alu.bed.gz -o resources/expected/rois/doubled.csv --no-background -t 2 \
+        // --no-overlap-dedup --no-ref-source --no-aln-stats --no-strand-depth --no-schema-line
         let expected = paths::expected::ROIS.join("doubled.csv");
         assert!(expected.is_file());
 
@@ -226,7 +471,12 @@ mod rois {
         let args = [
             "test", "-i", &paths::bam::EXAMPLE, &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA,
             "--stranding", "s/f", "--rois", &paths::GRCh38::ALU, "--ref-min-freq", "0.8", "-t", "2",
-            "-o", tmp.path().to_str().unwrap(),
+            "-o", tmp.path().to_str().unwrap(), "--no-background",
+            "--no-overlap-dedup",
+            "--no-ref-source",
+            "--no-aln-stats",
+            "--no-strand-depth",
+            "--no-schema-line",
         ];
         run(&args, SubCommand::rois);
 
@@ -240,7 +490,7 @@ mod rois {
         // do
         //  ../target/release/reat roi -i resources/bam/SRX6966474.bam \
         // -r resources/GRCh38/Homo_sapiens.GRCh38.dna.primary_assembly.fa.gz -s "f/s" -n "$name" --in-flags=3 --rois \
-        // resources/GRCh38/Alu.bed.gz -o /dev/null --ei resources/expected/rois/ei.csv; \
+        // resources/GRCh38/Alu.bed.gz -o /dev/null --ei resources/expected/rois/ei.csv --no-overlap-dedup; \
         // done
 
         let expected = paths::expected::ROIS.join("ei.csv");
@@ -257,7 +507,8 @@ mod rois {
             let args = [
                 "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s",
                 "-n", name, "--in-flags", "3", "--rois", &paths::GRCh38::ALU,
-                "-o", "/dev/null", "--ei", ei.to_str().unwrap()
+                "-o", "/dev/null", "--ei", ei.to_str().unwrap(),
+                "--no-overlap-dedup",
             ];
             run(&args, SubCommand::rois);
         }
@@ -265,4 +516,184 @@ mod rois {
         assert!(same(ei.as_path(), expected.as_path()), "{} vs {}", ei.display(), expected.display());
         fs::remove_file(ei).expect(TMP_DELETE_ERROR);
     }
+
+    #[test]
+    fn ei_compressed() {
+        // Each run appends a new gzip member, the header is written only once
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let ei = dir.path().join("rois.ei.csv.gz");
+        for name in ["first", "second"] {
+            #[rustfmt::skip]
+            let args = [
+                "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "-n", name,
+                "--rois", &paths::GRCh38::ALU, "-o", "/dev/null", "--ei", ei.to_str().unwrap(),
+            ];
+            run(&args, SubCommand::rois);
+        }
+
+        let mut written = String::new();
+        MultiGzDecoder::new(File::open(&ei).unwrap()).read_to_string(&mut written).unwrap();
+        let mut reader = csv::Reader::from_reader(written.as_bytes());
+        assert_eq!(reader.headers().unwrap().iter().collect::<Vec<_>>().join(","), ROIEditingIndex::header());
+        let experiments: Vec<String> = reader.records().map(|x| x.unwrap()[0].to_owned()).collect();
+        assert_eq!(experiments, ["first", "second"]);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn dump_reads() {
+        use rust_htslib::bam::{self, Read};
+
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let bed = dir.path().join("rois.bed");
+        fs::write(&bed, "MT\t100\t2000\tfirst\t0\t+\n").expect(TMP_CREATE_ERROR);
+        let prefix = dir.path().join("dump");
+        #[rustfmt::skip]
+        let args = [
+            "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--rois", bed.to_str().unwrap(),
+            "-o", "/dev/null", "--dump-reads", prefix.to_str().unwrap(), "-t", "4",
+        ];
+        run(&args, SubCommand::rois);
+
+        // All checked reads are saved once the run is over
+        let count = |what: &str| {
+            let mut reader = bam::Reader::from_path(dir.path().join(format!("dump.{}.bam", what))).unwrap();
+            reader.records().map(|x| x.unwrap()).count()
+        };
+        assert!(count("accepted") + count("rejected") > 0);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn clip_rois() {
+        // MT is 16569bp long, the second ROI extends 1kb past its end
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let bed = dir.path().join("rois.bed");
+        fs::write(&bed, "MT\t100\t200\tinside\t0\t+\nMT\t16000\t17569\tpast\t0\t+\n").expect(TMP_CREATE_ERROR);
+        let saveto = dir.path().join("rois.csv");
+        #[rustfmt::skip]
+        let args = [
+            "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--rois", bed.to_str().unwrap(),
+            "--out-min-cov", "0", "--out-min-mismatches", "0", "--out-min-freq", "0", "-o", saveto.to_str().unwrap(),
+        ];
+
+        // Rejected by default
+        let output = Command::new(env!("CARGO_BIN_EXE_reat"))
+            .args(["roi", "--progress", "none"])
+            .args(args)
+            .output()
+            .expect("Failed to launch reat");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let expected = "Error: 1 ROIs extend past the end of their contigs (e.g. past MT:16000-17569, \
+            contig length 16569), wrong assembly? Use --clip-rois to truncate them at the contig ends";
+        assert_eq!(stderr.lines().last(), Some(expected), "{}", stderr);
+
+        // Truncated at the contig end with --clip-rois
+        let args = ["test"].into_iter().chain(args).chain(["--clip-rois"]).collect::<Vec<_>>();
+        run(&args, SubCommand::rois);
+        let rows = table(&saveto);
+        let ranges =
+            rows.iter().map(|x| (x["name"].as_str(), x["start"].as_str(), x["end"].as_str())).collect::<Vec<_>>();
+        assert_eq!(ranges, [("inside", "100", "200"), ("past", "16000", "16569")]);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn merge() {
+        // Two runs of the same experiment & ROIs, e.g. shards of a bigger dataset
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        fs::write(path("rois.bed"), "MT\t5000\t5100\tlate\t0\t+\nMT\t100\t200\tearly\t0\t+\n").expect(TMP_CREATE_ERROR);
+        for shard in ["first", "second"] {
+            #[rustfmt::skip]
+            let args = [
+                "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "-n", "exp",
+                "--rois", &path("rois.bed"), "--out-min-cov", "0", "--out-min-mismatches", "0", "--out-min-freq", "0",
+                "-o", &path(&format!("{}.csv", shard)), "--ei", &path(&format!("{}.ei.csv", shard)),
+            ];
+            run(&args, SubCommand::rois);
+        }
+        let merge = |inputs: [&str; 2], saveto: &str| {
+            Command::new(env!("CARGO_BIN_EXE_reat"))
+                .args(["merge", "-r", &paths::GRCh38::FASTA, "-i", &path(inputs[0]), &path(inputs[1]), "-o"])
+                .arg(path(saveto))
+                .output()
+                .expect("Failed to launch reat")
+        };
+
+        // ROI tables are concatenated & sorted
+        assert!(merge(["first.csv", "second.csv"], "rois.csv").status.success());
+        let rows = table(&dir.path().join("rois.csv"));
+        assert_eq!(rows.iter().map(|x| x["name"].as_str()).collect::<Vec<_>>(), ["early", "early", "late", "late"]);
+        assert_eq!(rows[2], table(&dir.path().join("first.csv"))[1]);
+
+        // EI counts are summed & ratios are recomputed
+        assert!(merge(["first.ei.csv", "second.ei.csv"], "ei.csv").status.success());
+        let (first, second) = (table(&dir.path().join("first.ei.csv")), table(&dir.path().join("second.ei.csv")));
+        let (first, second) = (&first[0], &second[0]);
+        let merged = table(&dir.path().join("ei.csv"));
+        assert_eq!(merged.len(), 1);
+        let sum = |column: &str| first[column].parse::<f64>().unwrap() + second[column].parse::<f64>().unwrap();
+        for column in ["#covered", "A->G(sum)", "A(coverage)", "T->C(sum)", "T(coverage)"] {
+            assert_eq!(merged[0][column].parse::<f64>().unwrap(), sum(column), "{}", column);
+        }
+        let ag = merged[0]["A->G"].parse::<f64>().unwrap();
+        assert!((ag - sum("A->G(sum)") / sum("A(coverage)")).abs() < 1e-6);
+
+        // Different kinds of outputs can't be merged
+        let output = merge(["first.csv", "first.ei.csv"], "mixed.csv");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("have different columns"), "{}", stderr);
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
+}
+
+mod schema {
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn default_header() {
+        // Printed schema matches the tables written with the default options
+        let dir = TempDir::new().expect(TMP_CREATE_ERROR);
+        let saveto = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        #[rustfmt::skip]
+        let rois = [
+            "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--rois", &paths::GRCh38::ALU,
+            "--out-min-cov", "20", "-o", &saveto("rois.csv"), "--ei", &saveto("ei.csv"),
+        ];
+        run(&rois, SubCommand::rois);
+        #[rustfmt::skip]
+        let sites = [
+            "test", "-i", &paths::bam::EXAMPLE, "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--out-min-cov", "20",
+            "-o", &saveto("sites.csv"), "--coverage-summary", &saveto("coverage.tsv"),
+        ];
+        run(&sites, SubCommand::sites);
+
+        for (mode, table) in [("rois", "rois.csv"), ("loci", "sites.csv")] {
+            let output = Command::new(env!("CARGO_BIN_EXE_reat"))
+                .args(["schema", "--mode", mode])
+                .output()
+                .expect("Failed to launch reat");
+            assert!(output.status.success());
+            let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+            let columns = schema["columns"].as_array().unwrap().iter().map(|x| x["name"].as_str().unwrap());
+
+            let written = std::fs::read_to_string(saveto(table)).unwrap();
+            let mut lines = written.lines();
+            assert_eq!(lines.next().unwrap(), format!("# reat-schema: {}", schema["version"]));
+            assert_eq!(lines.next().unwrap(), columns.collect::<Vec<_>>().join(","));
+        }
+
+        // Auxiliary tables aren't described by the schema & never have the schema line
+        for table in ["ei.csv", "coverage.tsv"] {
+            let written = std::fs::read_to_string(saveto(table)).unwrap();
+            assert!(!written.starts_with("# reat-schema"), "{}", table);
+            assert!(!written.is_empty(), "{}", table);
+        }
+        dir.close().expect(TMP_DELETE_ERROR);
+    }
 }